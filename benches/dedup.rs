@@ -0,0 +1,83 @@
+//! Compares the linear duplicate scan with the digest index when recording into a full
+//! history. Run with `cargo bench --bench dedup`.
+
+use std::collections::VecDeque;
+use std::hint::black_box;
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use slyboard::clipboard::dedup::{DedupIndex, DedupSettings};
+use slyboard::clipboard::state::{push_history_entry, push_indexed_history_entry};
+use slyboard::clipboard::ClipboardEntry;
+use slyboard::testing::{synthetic_image, text_history};
+
+fn dedup(c: &mut Criterion) {
+    run(c, "1000 text entries", text_history(1000));
+    let images = (0..50)
+        .map(|seed| Arc::new(synthetic_image(256, 256, seed)))
+        .collect();
+    run(c, "50 image entries", images);
+}
+
+/// Fills a history with all but the newest of `entries`, then times re-recording its oldest
+/// entry (a duplicate found at the far end) followed by recording fresh content (no duplicate
+/// at all).
+fn run(c: &mut Criterion, label: &str, mut entries: VecDeque<Arc<ClipboardEntry>>) {
+    let limit = entries.len();
+    let fresh = entries.pop_front().expect("entries");
+    let full = entries;
+    // A new capture of old content is a separate allocation, not the stored `Arc`.
+    let oldest = Arc::new((**full.back().expect("entries")).clone());
+
+    let mut group = c.benchmark_group(label);
+    group.bench_function("linear scan", |b| {
+        b.iter_batched_ref(
+            || full.clone(),
+            |history| {
+                black_box(push_history_entry(
+                    history,
+                    limit,
+                    oldest.clone(),
+                    DedupSettings::default(),
+                ));
+                black_box(push_history_entry(
+                    history,
+                    limit,
+                    fresh.clone(),
+                    DedupSettings::default(),
+                ));
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("digest index", |b| {
+        b.iter_batched_ref(
+            || (full.clone(), DedupIndex::build(&full)),
+            |(history, index)| {
+                black_box(push_indexed_history_entry(
+                    history,
+                    index,
+                    limit,
+                    oldest.clone(),
+                    DedupSettings::default(),
+                ));
+                black_box(push_indexed_history_entry(
+                    history,
+                    index,
+                    limit,
+                    fresh.clone(),
+                    DedupSettings::default(),
+                ));
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    // Paid on every load and reload.
+    group.bench_function("index rebuild", |b| {
+        b.iter(|| DedupIndex::build(black_box(&full)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, dedup);
+criterion_main!(benches);
@@ -0,0 +1,56 @@
+//! Saving and loading the history database, and taking snapshots of a loaded one. Run with
+//! `cargo bench --bench storage`; files go to a temp directory, never the real cache.
+
+use std::collections::VecDeque;
+use std::hint::black_box;
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use slyboard::clipboard::dedup::DedupSettings;
+use slyboard::clipboard::storage::{load_history, save_history};
+use slyboard::clipboard::{ClipboardEntry, PruneOptions};
+use slyboard::testing::{screenshot_history, text_history, ScratchStore};
+
+fn storage(c: &mut Criterion) {
+    round_trip(c, "500 text entries", text_history(500));
+    round_trip(c, "20 1080p images", screenshot_history(20));
+}
+
+fn round_trip(c: &mut Criterion, label: &str, history: VecDeque<Arc<ClipboardEntry>>) {
+    let store = ScratchStore::new("bench-storage");
+    let path = store.database_path();
+    let limit = history.len();
+
+    let mut group = c.benchmark_group(label);
+    group.sample_size(20);
+    group.bench_function("save_history", |b| {
+        b.iter(|| save_history(&path, black_box(&history)).expect("save"))
+    });
+    save_history(&path, &history).expect("save");
+    group.bench_function("load_history", |b| {
+        b.iter(|| {
+            load_history(
+                &path,
+                limit,
+                PruneOptions::default(),
+                DedupSettings::default(),
+            )
+            .expect("load")
+        })
+    });
+    group.finish();
+}
+
+/// What every reader of shared state (tray menu, HTTP endpoint, CLI listing) pays.
+fn snapshot(c: &mut Criterion) {
+    let store = ScratchStore::new("bench-snapshot");
+    save_history(&store.database_path(), &text_history(500)).expect("save");
+    let state = store.load(500).expect("load");
+
+    c.bench_function("history_snapshot of 500 entries", |b| {
+        b.iter(|| black_box(state.history_snapshot()))
+    });
+}
+
+criterion_group!(benches, storage, snapshot);
+criterion_main!(benches);
@@ -30,9 +30,11 @@ in {
       };
 
       Service = {
+        Type = "notify";
         ExecStart = "${lib.getExe cfg.package} --config ${generatedConfig} run";
         Restart = "on-failure";
         RestartSec = 2;
+        WatchdogSec = 60;
       };
 
       Install = {
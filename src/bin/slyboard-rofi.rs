@@ -1,15 +1,42 @@
-use std::io::Write;
-use std::process::{Command, ExitStatus, Stdio};
+use std::borrow::Borrow;
+use std::env;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::{Command, ExitCode, ExitStatus, Stdio};
+use std::sync::Arc;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::Parser;
-use gtk::prelude::*;
-use slyboard::clipboard::{ClipboardEntry, SharedClipboardState, DEFAULT_HISTORY_LIMIT};
+use slyboard::clipboard::age::{date_bucket, unix_now, DateBucket};
+use slyboard::clipboard::filters::HistoryFilter;
+use slyboard::clipboard::migrations;
+use slyboard::clipboard::ocr::{recognize_entry, record_recognized_text, CommandOcr};
+use slyboard::clipboard::preview::{preview_text, with_hint_tag};
+use slyboard::clipboard::slots::SlotStore;
+use slyboard::clipboard::thumbnails::ThumbnailCache;
+use slyboard::clipboard::writer::{set_clipboard, RestoreTarget};
+use slyboard::clipboard::{ClipboardEntry, ImageEncoding, PruneOptions, SharedClipboardState};
+use slyboard::config::effective::{EffectiveSettings, SettingOverrides};
+use slyboard::config::AppConfig;
+use slyboard::core::active_window::provider_from_config;
+use slyboard::core::events;
+use slyboard::core::provider_stats::ProviderStats;
 
 const DEFAULT_PROMPT: &str = "slyboard";
 const DEFAULT_ROFI_BIN: &str = "rofi";
 const DEFAULT_LINES: usize = 15;
 const MENU_LABEL_CHAR_LIMIT: usize = 120;
+const DEFAULT_PIN_KEY: &str = "Alt+p";
+const DEFAULT_OCR_KEY: &str = "Alt+o";
+/// rofi exits with 10 + n for `-kb-custom-{n + 1}`; the pin toggle is bound to `-kb-custom-2`
+/// and text recognition to `-kb-custom-3`.
+const PIN_KEY_EXIT_CODE: i32 = 11;
+const OCR_KEY_EXIT_CODE: i32 = 12;
+const PINNED_DIVIDER_LABEL: &str = "──────────";
+const PINNED_HEADER_LABEL: &str = "Pinned";
+const ICON_METADATA: &str = "\0icon\x1f";
+/// rofi's `ROFI_RETV` when the script is run for a selected row.
+const MODI_ENTRY_SELECTED: &str = "1";
 
 #[derive(Debug, Parser)]
 #[command(
@@ -29,32 +56,470 @@ struct Cli {
     /// rofi executable to invoke.
     #[arg(long, default_value = DEFAULT_ROFI_BIN)]
     rofi_bin: String,
+
+    /// Key that pins or unpins the highlighted entry without closing the picker.
+    #[arg(long, default_value = DEFAULT_PIN_KEY)]
+    pin_key: String,
+
+    /// Key that copies the text recognized in the highlighted image entry. Bound only when
+    /// `ocr.enabled` is set in the config.
+    #[arg(long, default_value = DEFAULT_OCR_KEY)]
+    ocr_key: String,
+
+    /// Only show entries copied on the currently focused workspace.
+    #[arg(long)]
+    current_workspace: bool,
+
+    /// With --current-workspace, also show entries that have no recorded workspace.
+    #[arg(long, requires = "current_workspace")]
+    include_unknown: bool,
+
+    /// Insert "Today", "Yesterday", "This week" and "Older" headers, by local capture day.
+    #[arg(long)]
+    group_by_date: bool,
+
+    /// Pick from named slots (see `slyboard yank`) instead of history.
+    #[arg(long, conflicts_with = "current_workspace")]
+    slots: bool,
+
+    /// Act as a rofi script mode (`rofi -show clipboard -modi "clipboard:slyboard-rofi
+    /// --modi-mode"`): print the rows when rofi asks for them, copy the entry when it passes
+    /// one back.
+    #[arg(long, conflicts_with_all = ["slots", "current_workspace"])]
+    modi_mode: bool,
+
+    /// Print the picked entry's text to stdout instead of copying it, e.g. to pipe it into
+    /// another program. Cancelling prints nothing and exits 1.
+    #[arg(long, conflicts_with = "modi_mode")]
+    print: bool,
+
+    /// With --print, write the PNG bytes of a picked image, as long as stdout is not a
+    /// terminal.
+    #[arg(long, requires = "print")]
+    allow_binary: bool,
+
+    /// Optional explicit config path, used to pick the active-window backend.
+    #[arg(short = 'c', long, value_name = "PATH")]
+    config: Option<PathBuf>,
+}
+
+/// One rofi row: a history entry by index, or a non-selectable divider or section header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MenuRow {
+    Entry(usize),
+    Divider,
+    Header(&'static str),
+}
+
+/// What rofi asks for when it runs slyboard-rofi as a script mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ModiRequest {
+    /// List the rows: the first call, or anything but a selected row (such as custom text).
+    Rows,
+    /// Copy the entry with this [`stable_id`](ClipboardEntry::stable_id), from the row's info.
+    Copy(String),
+}
+
+impl ModiRequest {
+    fn from_env(retv: Option<&str>, info: Option<&str>) -> Self {
+        match (retv, info) {
+            (Some(MODI_ENTRY_SELECTED), Some(info)) if !info.is_empty() => {
+                Self::Copy(info.to_string())
+            }
+            _ => Self::Rows,
+        }
+    }
+}
+
+enum MenuAction {
+    Restore(usize),
+    TogglePin(usize),
+    RecognizeText(usize),
 }
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     let cli = Cli::parse();
-    let shared_state = SharedClipboardState::load_default(DEFAULT_HISTORY_LIMIT)?;
+    let config = AppConfig::load_optional(cli.config.clone())?.map(|loaded| loaded.config);
+    let settings = EffectiveSettings::resolve(SettingOverrides::default(), config.as_ref())?;
+    if let Some(config) = &config {
+        migrations::set_legacy_order(config.storage.legacy_order);
+        if let Err(err) = events::init(&config.events) {
+            eprintln!("warning: {err:#}");
+        }
+    }
+    let shared_state = SharedClipboardState::load_default_with(
+        settings.history_limit,
+        PruneOptions::default(),
+        settings.dedup,
+    )?;
+    let restore_target = config
+        .as_ref()
+        .map_or_else(RestoreTarget::default, |config| {
+            config.clipboard.restore_target()
+        });
+    if cli.modi_mode {
+        let request = ModiRequest::from_env(
+            env::var("ROFI_RETV").ok().as_deref(),
+            env::var("ROFI_INFO").ok().as_deref(),
+        );
+        run_modi(&cli, &shared_state, request, restore_target)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if cli.slots {
+        return match pick_slot(&cli)? {
+            Some(entry) => deliver(&cli, &shared_state, &entry, true, restore_target),
+            None => Ok(cancelled(&cli)),
+        };
+    }
+    let ocr = config
+        .filter(|config| config.ocr.enabled)
+        .map(|config| CommandOcr::from_config(&config.ocr));
+    let filter = history_filter(&cli)?;
+    // Icons are a nicety; without a cache directory images are listed by size only.
+    let thumbnails = ThumbnailCache::open_default().ok();
+    let mut selected_row = 0;
+
+    let (entry, restored) = loop {
+        let entries = shared_state.history_snapshot();
+        if entries.is_empty() {
+            return Ok(cancelled(&cli));
+        }
+
+        let rows = menu_rows(&entries, &filter, grouping(&cli).as_ref());
+        if rows.is_empty() {
+            return Ok(cancelled(&cli));
+        }
+        let labels = history_labels(&entries, &rows, thumbnails.as_ref());
+        let ocr_key = ocr.as_ref().map(|_| cli.ocr_key.as_str());
+        let Some(action) = prompt_selection(&cli, &labels, selected_row, ocr_key)? else {
+            return Ok(cancelled(&cli));
+        };
+        match action {
+            MenuAction::Restore(row) => {
+                let index = history_index_for_row(&rows, row)?;
+                break (entries[index].clone(), true);
+            }
+            MenuAction::TogglePin(row) => {
+                let index = history_index_for_row(&rows, row)?;
+                // By id, as the daemon may have recorded copies while the menu was open.
+                let toggled = shared_state.toggle_pinned_by_id(&entries[index].stable_id())?;
+                // Keep the toggled entry highlighted when the menu re-launches.
+                let rows = menu_rows(
+                    &shared_state.history_snapshot(),
+                    &filter,
+                    grouping(&cli).as_ref(),
+                );
+                selected_row = toggled
+                    .and_then(|(index, _)| row_for_history_index(&rows, index))
+                    .unwrap_or(0);
+            }
+            MenuAction::RecognizeText(row) => {
+                let index = history_index_for_row(&rows, row)?;
+                let ocr = ocr.as_ref().context("text recognition is disabled")?;
+                // `slyboard history` ids count from the oldest entry.
+                let id = entries.len() - 1 - index;
+                let text = recognize_entry(ocr, &entries[index])
+                    .with_context(|| format!("failed to recognize text in entry {id}"))?;
+                let recognized = record_recognized_text(&shared_state, text, id, &entries[index])?;
+                break (Arc::new(recognized), false);
+            }
+        }
+    };
+
+    deliver(&cli, &shared_state, &entry, restored, restore_target)
+}
+
+/// How a run that picked nothing exits: 1 with --print, so a pipeline can stop there.
+fn cancelled(cli: &Cli) -> ExitCode {
+    if cli.print {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Copies the picked entry, counting it as restored if it came from history or a slot, or
+/// prints it with --print. Either way text cut to a preview is read back whole first.
+/// Printing never touches the clipboard, so GTK isn't initialized.
+fn deliver(
+    cli: &Cli,
+    shared_state: &SharedClipboardState,
+    entry: &ClipboardEntry,
+    restored: bool,
+    restore_target: RestoreTarget,
+) -> Result<ExitCode> {
+    let whole = shared_state.whole_entry(entry)?;
+    if cli.print {
+        let stdout = io::stdout();
+        let to_terminal = stdout.is_terminal();
+        print_entry(&mut stdout.lock(), &whole, cli.allow_binary, to_terminal)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    set_clipboard(&whole, restore_target)?;
+    if restored {
+        mark_restored(shared_state, entry);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `--print` output: the text as it was copied, without a newline added, or the PNG bytes of
+/// an image when `allow_binary` is set and `out` isn't a terminal.
+fn print_entry<W: Write>(
+    out: &mut W,
+    entry: &ClipboardEntry,
+    allow_binary: bool,
+    to_terminal: bool,
+) -> Result<()> {
+    match entry {
+        ClipboardEntry::Text { value, .. } => out.write_all(value.as_bytes())?,
+        ClipboardEntry::Image { .. } if !allow_binary => {
+            bail!("the picked entry is an image; pass --allow-binary to print its PNG bytes")
+        }
+        ClipboardEntry::Image { .. } if to_terminal => {
+            bail!("not writing image bytes to a terminal; redirect stdout")
+        }
+        ClipboardEntry::Image { .. } => match entry.clone().into_png_encoded() {
+            ClipboardEntry::Image {
+                encoding: ImageEncoding::Png,
+                pixels,
+                ..
+            } => out.write_all(&pixels)?,
+            _ => bail!("image layout cannot be encoded as PNG"),
+        },
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// One step of the script-mode protocol. Printing nothing after a copy makes rofi close.
+fn run_modi(
+    cli: &Cli,
+    shared_state: &SharedClipboardState,
+    request: ModiRequest,
+    restore_target: RestoreTarget,
+) -> Result<()> {
     let entries = shared_state.history_snapshot();
+    match request {
+        ModiRequest::Rows => {
+            let rows = menu_rows(&entries, &HistoryFilter::default(), grouping(cli).as_ref());
+            let thumbnails = ThumbnailCache::open_default().ok();
+            let stdout = io::stdout();
+            let mut out = BufWriter::new(stdout.lock());
+            for line in modi_lines(&cli.prompt, &entries, &rows, thumbnails.as_ref()) {
+                writeln!(out, "{line}")?;
+            }
+            out.flush()?;
+            Ok(())
+        }
+        ModiRequest::Copy(id) => {
+            // Gone if it was removed or evicted while rofi was open.
+            let entry = entries
+                .iter()
+                .find(|entry| entry.stable_id() == id)
+                .with_context(|| format!("no history entry with id {id}"))?;
+            set_clipboard(&*shared_state.whole_entry(entry)?, restore_target)?;
+            mark_restored(shared_state, entry);
+            Ok(())
+        }
+    }
+}
+
+/// Script-mode output: the prompt option, then the picker's rows with each entry's
+/// [`stable_id`](ClipboardEntry::stable_id) as `info`, which rofi hands back on selection.
+/// History ids would shift when something is copied while rofi is open.
+fn modi_lines(
+    prompt: &str,
+    entries: &[Arc<ClipboardEntry>],
+    rows: &[MenuRow],
+    thumbnails: Option<&ThumbnailCache>,
+) -> Vec<String> {
+    let labels = history_labels(entries, rows, thumbnails);
+    let mut lines = vec![
+        format!("\0prompt\x1f{prompt}"),
+        "\0no-custom\x1ftrue".to_string(),
+    ];
+    lines.extend(rows.iter().zip(labels).map(|(row, label)| match row {
+        MenuRow::Entry(index) => with_row_option(label, "info", &entries[*index].stable_id()),
+        MenuRow::Divider | MenuRow::Header(_) => label,
+    }));
+    lines
+}
+
+/// Appends a rofi row option, after any the row already has.
+fn with_row_option(label: String, option: &str, value: &str) -> String {
+    let separator = if label.contains('\0') { "\x1f" } else { "\0" };
+    format!("{label}{separator}{option}\x1f{value}")
+}
+
+/// The slot entry picked, or `None` when there are none or the picker was cancelled.
+fn pick_slot(cli: &Cli) -> Result<Option<Arc<ClipboardEntry>>> {
+    let slots = SlotStore::open_default()?.load()?;
+    if slots.is_empty() {
+        return Ok(None);
+    }
+    let labels: Vec<String> = slots
+        .iter()
+        .map(|(name, entry)| format!("{name}: {}", format_menu_label(entry)))
+        .collect();
+    let Some(MenuAction::Restore(row)) = prompt_selection(cli, &labels, 0, None)? else {
+        return Ok(None);
+    };
+    let entry = slots
+        .into_values()
+        .nth(row)
+        .ok_or_else(|| anyhow!("selected slot index out of range: {row}"))?;
+    Ok(Some(entry))
+}
+
+/// The entry is already on the clipboard, so failing to count the restore is only a warning.
+fn mark_restored(shared_state: &SharedClipboardState, entry: &ClipboardEntry) {
+    if let Err(err) = shared_state.mark_restored(entry) {
+        eprintln!("warning: failed to record the restore: {err}");
+    }
+}
+
+fn history_filter(cli: &Cli) -> Result<HistoryFilter> {
+    if !cli.current_workspace {
+        return Ok(HistoryFilter::default());
+    }
 
-    if entries.is_empty() {
-        return Ok(());
+    let config = AppConfig::load_layered(cli.config.clone())?.config;
+    let provider = provider_from_config(
+        &config.clipboard.active_window.backend,
+        &ProviderStats::default(),
+    );
+    let workspace_id = provider.capture().and_then(|window| window.workspace_id);
+    if workspace_id.is_none() {
+        eprintln!("warning: could not determine the current workspace; showing all entries");
     }
+    Ok(HistoryFilter {
+        workspace_id,
+        include_unknown_workspace: cli.include_unknown,
+        ..HistoryFilter::default()
+    })
+}
+
+/// Buckets entries for `--group-by-date`, as of now.
+fn grouping(cli: &Cli) -> Option<impl Fn(&ClipboardEntry) -> DateBucket> {
+    let now = unix_now();
+    cli.group_by_date
+        .then_some(move |entry: &ClipboardEntry| date_bucket(entry.captured_at(), now))
+}
+
+/// Maps menu rows to history indices: pinned entries first, then a divider when both sections
+/// are present, then everything else in history order. Entries rejected by `filter` get no
+/// row. With `group_by_date`, the unpinned entries are grouped under a header per bucket
+/// (keeping history order within each) and the pinned ones under a "Pinned" header instead of
+/// the divider.
+fn menu_rows<E: Borrow<ClipboardEntry>>(
+    entries: &[E],
+    filter: &HistoryFilter,
+    group_by_date: Option<&impl Fn(&ClipboardEntry) -> DateBucket>,
+) -> Vec<MenuRow> {
+    let visible: Vec<(usize, &ClipboardEntry)> = filter.apply(entries);
+    let pinned: Vec<usize> = visible
+        .iter()
+        .filter(|(_, entry)| entry.is_pinned())
+        .map(|(index, _)| *index)
+        .collect();
+    let mut unpinned: Vec<(usize, &ClipboardEntry)> = visible
+        .into_iter()
+        .filter(|(_, entry)| !entry.is_pinned())
+        .collect();
 
-    let selected = prompt_selection(&cli, &entries)?;
-    let Some(index) = selected else {
-        return Ok(());
+    let mut rows = Vec::new();
+    let Some(bucket) = group_by_date else {
+        rows.extend(pinned.iter().map(|index| MenuRow::Entry(*index)));
+        if !rows.is_empty() && !unpinned.is_empty() {
+            rows.push(MenuRow::Divider);
+        }
+        rows.extend(unpinned.iter().map(|(index, _)| MenuRow::Entry(*index)));
+        return rows;
     };
-    let entry = entries
-        .get(index)
-        .ok_or_else(|| anyhow!("selected entry index out of range: {index}"))?;
 
-    gtk::init().context("failed to initialize GTK for clipboard access")?;
-    let clipboard = gtk::Clipboard::get(&gtk::gdk::SELECTION_CLIPBOARD);
-    set_clipboard_value(&clipboard, entry);
-    Ok(())
+    if !pinned.is_empty() {
+        rows.push(MenuRow::Header(PINNED_HEADER_LABEL));
+        rows.extend(pinned.iter().map(|index| MenuRow::Entry(*index)));
+    }
+    // Stable, so a clock change can't split a bucket into several sections.
+    unpinned.sort_by_key(|(_, entry)| bucket(entry));
+    let mut current = None;
+    for (index, entry) in unpinned {
+        let entry_bucket = bucket(entry);
+        if current != Some(entry_bucket) {
+            rows.push(MenuRow::Header(entry_bucket.label()));
+            current = Some(entry_bucket);
+        }
+        rows.push(MenuRow::Entry(index));
+    }
+    rows
+}
+
+/// The history index behind the row rofi returned. Divider and header rows are marked
+/// non-selectable; rofi versions that ignore that can still return them, which is an error.
+fn history_index_for_row(rows: &[MenuRow], row: usize) -> Result<usize> {
+    match rows.get(row) {
+        Some(MenuRow::Entry(index)) => Ok(*index),
+        Some(MenuRow::Divider) => Err(anyhow!("selected row {row} is the pinned section divider")),
+        Some(MenuRow::Header(label)) => Err(anyhow!(
+            "selected row {row} is the {label:?} section header"
+        )),
+        None => Err(anyhow!("selected entry index out of range: {row}")),
+    }
 }
 
-fn prompt_selection(cli: &Cli, entries: &[ClipboardEntry]) -> Result<Option<usize>> {
+fn row_for_history_index(rows: &[MenuRow], index: usize) -> Option<usize> {
+    rows.iter().position(|row| *row == MenuRow::Entry(index))
+}
+
+fn history_labels(
+    entries: &[Arc<ClipboardEntry>],
+    rows: &[MenuRow],
+    thumbnails: Option<&ThumbnailCache>,
+) -> Vec<String> {
+    rows.iter()
+        .map(|row| match row {
+            MenuRow::Entry(index) => with_thumbnail(
+                format_menu_label(&entries[*index]),
+                &entries[*index],
+                thumbnails,
+            ),
+            MenuRow::Divider => non_selectable(PINNED_DIVIDER_LABEL),
+            MenuRow::Header(label) => non_selectable(&format!("── {label} ──")),
+        })
+        .collect()
+}
+
+/// Adds rofi's `icon` metadata to an image row, pointing at the entry's thumbnail.
+fn with_thumbnail(
+    label: String,
+    entry: &ClipboardEntry,
+    thumbnails: Option<&ThumbnailCache>,
+) -> String {
+    let Some(thumbnails) = thumbnails.filter(|_| matches!(entry, ClipboardEntry::Image { .. }))
+    else {
+        return label;
+    };
+    match thumbnails.get_or_create(entry) {
+        Ok(path) => format!("{label}{ICON_METADATA}{}", path.display()),
+        Err(err) => {
+            eprintln!("warning: no thumbnail for {label}: {err}");
+            label
+        }
+    }
+}
+
+/// A row with rofi's `nonselectable` metadata (rofi 1.7 and later).
+fn non_selectable(label: &str) -> String {
+    format!("{label}\0nonselectable\x1ftrue")
+}
+
+fn prompt_selection(
+    cli: &Cli,
+    labels: &[String],
+    selected_row: usize,
+    ocr_key: Option<&str>,
+) -> Result<Option<MenuAction>> {
     let mut child = Command::new(&cli.rofi_bin)
         .arg("-dmenu")
         .arg("-i")
@@ -64,16 +529,23 @@ fn prompt_selection(cli: &Cli, entries: &[ClipboardEntry]) -> Result<Option<usiz
         .arg(cli.lines.to_string())
         .arg("-format")
         .arg("i")
+        .arg("-selected-row")
+        .arg(selected_row.to_string())
+        .arg("-kb-custom-2")
+        .arg(&cli.pin_key)
+        .args(ocr_key.into_iter().flat_map(|key| ["-kb-custom-3", key]))
+        .args(
+            labels
+                .iter()
+                .any(|label| label.contains(ICON_METADATA))
+                .then_some("-show-icons"),
+        )
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .spawn()
         .with_context(|| format!("failed to launch {}", cli.rofi_bin))?;
 
-    let menu_input = entries
-        .iter()
-        .map(format_menu_label)
-        .collect::<Vec<_>>()
-        .join("\n");
+    let menu_input = labels.join("\n");
 
     {
         let stdin = child
@@ -92,7 +564,9 @@ fn prompt_selection(cli: &Cli, entries: &[ClipboardEntry]) -> Result<Option<usiz
     if is_rofi_cancel(&output.status) {
         return Ok(None);
     }
-    if !output.status.success() {
+    let pin_requested = output.status.code() == Some(PIN_KEY_EXIT_CODE);
+    let ocr_requested = ocr_key.is_some() && output.status.code() == Some(OCR_KEY_EXIT_CODE);
+    if !output.status.success() && !pin_requested && !ocr_requested {
         return Err(anyhow!(
             "rofi exited with non-zero status: {}",
             output.status
@@ -104,10 +578,16 @@ fn prompt_selection(cli: &Cli, entries: &[ClipboardEntry]) -> Result<Option<usiz
         return Ok(None);
     }
 
-    let index = trimmed
+    let row = trimmed
         .parse::<usize>()
         .with_context(|| format!("failed to parse rofi selection index: {trimmed}"))?;
-    Ok(Some(index))
+    if pin_requested {
+        Ok(Some(MenuAction::TogglePin(row)))
+    } else if ocr_requested {
+        Ok(Some(MenuAction::RecognizeText(row)))
+    } else {
+        Ok(Some(MenuAction::Restore(row)))
+    }
 }
 
 fn is_rofi_cancel(status: &ExitStatus) -> bool {
@@ -116,51 +596,262 @@ fn is_rofi_cancel(status: &ExitStatus) -> bool {
 
 fn format_menu_label(entry: &ClipboardEntry) -> String {
     match entry {
-        ClipboardEntry::Text { value, .. } => format_text_menu_label(value),
+        ClipboardEntry::Text { value, .. } => {
+            with_hint_tag(entry, preview_text(value, MENU_LABEL_CHAR_LIMIT))
+        }
         ClipboardEntry::Image { width, height, .. } => {
             format!("[image] {}x{}", width, height)
         }
     }
 }
 
-fn format_text_menu_label(value: &str) -> String {
-    let sanitized = value.replace('\n', "\\n").replace('\r', "\\r");
-    let char_count = sanitized.chars().count();
-    if char_count <= MENU_LABEL_CHAR_LIMIT {
-        return sanitized;
+#[cfg(test)]
+mod tests {
+    use super::{
+        cancelled, history_index_for_row, menu_rows, modi_lines, print_entry,
+        row_for_history_index, with_row_option, Cli, MenuRow, ModiRequest,
+    };
+    use clap::Parser;
+    use slyboard::clipboard::age::DateBucket;
+    use slyboard::clipboard::filters::HistoryFilter;
+    use slyboard::clipboard::ClipboardEntry;
+    use slyboard::core::active_window::ActiveWindowContext;
+    use std::process::ExitCode;
+    use std::sync::Arc;
+
+    use MenuRow::{Divider, Entry, Header};
+
+    fn text(value: &str, pinned: bool) -> ClipboardEntry {
+        ClipboardEntry::text(value).with_pinned(pinned)
     }
 
-    let truncated: String = sanitized.chars().take(MENU_LABEL_CHAR_LIMIT).collect();
-    format!("{truncated}...")
-}
+    /// Ungrouped rows; the bucketing closure type is only needed to name `None`.
+    fn rows(entries: &[ClipboardEntry], filter: &HistoryFilter) -> Vec<MenuRow> {
+        menu_rows(entries, filter, None::<&fn(&ClipboardEntry) -> DateBucket>)
+    }
 
-fn set_clipboard_value(clipboard: &gtk::Clipboard, entry: &ClipboardEntry) {
-    match entry {
-        ClipboardEntry::Text { value, .. } => {
-            clipboard.set_text(value);
-            clipboard.store();
-        }
-        ClipboardEntry::Image {
-            width,
-            height,
-            rowstride,
-            has_alpha,
-            bits_per_sample,
-            pixels,
-            ..
-        } => {
-            let bytes = gtk::glib::Bytes::from(pixels.as_slice());
-            let image = gtk::gdk_pixbuf::Pixbuf::from_bytes(
-                &bytes,
-                gtk::gdk_pixbuf::Colorspace::Rgb,
-                *has_alpha,
-                *bits_per_sample,
-                *width,
-                *height,
-                *rowstride,
-            );
-            clipboard.set_image(&image);
-            clipboard.store();
+    #[test]
+    fn lists_pinned_entries_first_with_a_divider() {
+        let entries = vec![
+            text("a", false),
+            text("b", true),
+            text("c", false),
+            text("d", true),
+        ];
+        let rows = rows(&entries, &HistoryFilter::default());
+        assert_eq!(rows, vec![Entry(1), Entry(3), Divider, Entry(0), Entry(2)]);
+
+        assert_eq!(history_index_for_row(&rows, 1).expect("pinned row"), 3);
+        assert_eq!(history_index_for_row(&rows, 3).expect("unpinned row"), 0);
+        assert!(
+            history_index_for_row(&rows, 2).is_err(),
+            "divider is not an entry"
+        );
+        assert!(history_index_for_row(&rows, 5).is_err());
+        assert_eq!(row_for_history_index(&rows, 2), Some(4));
+    }
+
+    #[test]
+    fn modi_rows_carry_stable_ids_that_find_the_entry_again() {
+        let entries: Vec<Arc<ClipboardEntry>> = vec![
+            Arc::new(text("line one\nline two", false)),
+            Arc::new(text("pinned", true)),
+        ];
+        let rows = menu_rows(
+            &entries,
+            &HistoryFilter::default(),
+            None::<&fn(&ClipboardEntry) -> DateBucket>,
+        );
+        let lines = modi_lines("clip", &entries, &rows, None);
+        let (pinned_id, text_id) = (entries[1].stable_id(), entries[0].stable_id());
+        assert_eq!(
+            lines,
+            vec![
+                "\0prompt\x1fclip".to_string(),
+                "\0no-custom\x1ftrue".to_string(),
+                format!("pinned\0info\x1f{pinned_id}"),
+                "──────────\0nonselectable\x1ftrue".to_string(),
+                format!("line one\\nline two\0info\x1f{text_id}"),
+            ]
+        );
+
+        // rofi passes the selected row's info back in ROFI_INFO.
+        let info = lines[4].rsplit('\x1f').next();
+        let request = ModiRequest::from_env(Some("1"), info);
+        assert_eq!(request, ModiRequest::Copy(text_id.clone()));
+        let ModiRequest::Copy(id) = request else {
+            unreachable!()
+        };
+        assert_eq!(
+            entries.iter().position(|entry| entry.stable_id() == id),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn modi_lists_rows_unless_a_row_was_selected() {
+        assert_eq!(ModiRequest::from_env(None, None), ModiRequest::Rows);
+        assert_eq!(ModiRequest::from_env(Some("0"), None), ModiRequest::Rows);
+        // Custom text typed into rofi, and a selection without info.
+        assert_eq!(
+            ModiRequest::from_env(Some("2"), Some("abc")),
+            ModiRequest::Rows
+        );
+        assert_eq!(
+            ModiRequest::from_env(Some("1"), Some("")),
+            ModiRequest::Rows
+        );
+        assert_eq!(
+            with_row_option("[image] 2x2\0icon\x1f/tmp/a.png".into(), "info", "ab"),
+            "[image] 2x2\0icon\x1f/tmp/a.png\x1finfo\x1fab"
+        );
+    }
+
+    #[test]
+    fn omits_divider_without_pinned_entries() {
+        let unpinned = rows(
+            &[text("a", false), text("b", false)],
+            &HistoryFilter::default(),
+        );
+        assert_eq!(unpinned, vec![Entry(0), Entry(1)]);
+
+        let pinned = rows(&[text("a", true)], &HistoryFilter::default());
+        assert_eq!(pinned, vec![Entry(0)]);
+    }
+
+    fn on_workspace(entry: ClipboardEntry, workspace_id: i64) -> ClipboardEntry {
+        entry.with_source_window(Some(ActiveWindowContext {
+            backend: "hyprctl".to_string(),
+            title: "Editor".to_string(),
+            app_id: None,
+            initial_app_id: None,
+            initial_title: None,
+            window_id: None,
+            pid: None,
+            workspace_id: Some(workspace_id),
+            workspace_name: None,
+            is_xwayland: None,
+            monitor: None,
+            geometry: None,
+        }))
+    }
+
+    #[test]
+    fn workspace_filter_keeps_history_indices() {
+        let entries = vec![
+            on_workspace(text("a", false), 1),
+            on_workspace(text("b", true), 3),
+            text("c", false),
+            on_workspace(text("d", false), 3),
+        ];
+        let filter = HistoryFilter {
+            workspace_id: Some(3),
+            ..HistoryFilter::default()
+        };
+        let rows = rows(&entries, &filter);
+        assert_eq!(rows, vec![Entry(1), Divider, Entry(3)]);
+        assert_eq!(history_index_for_row(&rows, 2).expect("unpinned row"), 3);
+    }
+
+    /// Buckets by the entry's text, e.g. "today-a" is from today.
+    fn bucket_by_name(entry: &ClipboardEntry) -> DateBucket {
+        let ClipboardEntry::Text { value, .. } = entry else {
+            return DateBucket::Older;
+        };
+        match value.split('-').next() {
+            Some("today") => DateBucket::Today,
+            Some("yesterday") => DateBucket::Yesterday,
+            Some("week") => DateBucket::ThisWeek,
+            _ => DateBucket::Older,
+        }
+    }
+
+    #[test]
+    fn groups_unpinned_entries_under_date_headers() {
+        let entries = vec![
+            text("today-a", false),
+            text("today-b", true),
+            text("today-c", false),
+            text("yesterday-a", false),
+            // Out of order after a clock change: joins its bucket instead of opening another.
+            text("today-d", false),
+            text("old-a", false),
+        ];
+        let rows = menu_rows(&entries, &HistoryFilter::default(), Some(&bucket_by_name));
+        assert_eq!(
+            rows,
+            vec![
+                Header("Pinned"),
+                Entry(1),
+                Header("Today"),
+                Entry(0),
+                Entry(2),
+                Entry(4),
+                Header("Yesterday"),
+                Entry(3),
+                Header("Older"),
+                Entry(5),
+            ]
+        );
+
+        assert_eq!(history_index_for_row(&rows, 1).unwrap(), 1);
+        assert_eq!(history_index_for_row(&rows, 5).unwrap(), 4);
+        assert_eq!(history_index_for_row(&rows, 7).unwrap(), 3);
+        assert_eq!(history_index_for_row(&rows, 9).unwrap(), 5);
+        for header in [0, 2, 6, 8] {
+            assert!(history_index_for_row(&rows, header).is_err());
         }
+        assert!(history_index_for_row(&rows, 10).is_err());
+        assert_eq!(row_for_history_index(&rows, 4), Some(5));
+        assert_eq!(row_for_history_index(&rows, 3), Some(7));
+    }
+
+    #[test]
+    fn date_groups_skip_empty_buckets_and_filtered_entries() {
+        let entries = vec![
+            on_workspace(text("today-a", false), 2),
+            on_workspace(text("week-a", false), 3),
+            on_workspace(text("old-a", false), 3),
+        ];
+        let filter = HistoryFilter {
+            workspace_id: Some(3),
+            ..HistoryFilter::default()
+        };
+        let rows = menu_rows(&entries, &filter, Some(&bucket_by_name));
+        assert_eq!(
+            rows,
+            vec![Header("This week"), Entry(1), Header("Older"), Entry(2)]
+        );
+        let empty: Vec<ClipboardEntry> = Vec::new();
+        assert!(menu_rows(&empty, &filter, Some(&bucket_by_name)).is_empty());
+    }
+
+    #[test]
+    fn print_writes_the_text_as_copied_and_images_only_when_allowed() {
+        let printed = |entry: &ClipboardEntry, allow_binary: bool, to_terminal: bool| {
+            let mut out = Vec::new();
+            print_entry(&mut out, entry, allow_binary, to_terminal).map(|()| out)
+        };
+        let copied = ClipboardEntry::text("https://example.com\n");
+        assert_eq!(
+            printed(&copied, false, true).unwrap(),
+            b"https://example.com\n"
+        );
+
+        let image = slyboard::testing::raw_image(1, 1, [0, 0, 0, 255]);
+        assert!(printed(&image, false, false).is_err());
+        assert!(printed(&image, true, true).is_err(), "not to a terminal");
+        assert!(printed(&image, true, false)
+            .unwrap()
+            .starts_with(b"\x89PNG\r\n"));
+    }
+
+    #[test]
+    fn cancelling_exits_1_only_when_printing() {
+        let parse = |args: &[&str]| Cli::try_parse_from([&["slyboard-rofi"], args].concat());
+        assert_eq!(cancelled(&parse(&[]).unwrap()), ExitCode::SUCCESS);
+        assert_eq!(cancelled(&parse(&["--print"]).unwrap()), ExitCode::FAILURE);
+        assert!(parse(&["--allow-binary"]).is_err(), "needs --print");
+        assert!(parse(&["--print", "--modi-mode"]).is_err());
     }
 }
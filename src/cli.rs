@@ -1,6 +1,15 @@
+use std::io::{BufRead, Write};
 use std::path::PathBuf;
 
+use anyhow::{bail, Result};
 use clap::{Args, Parser};
+use slyboard::clipboard::classify::ContentHint;
+use slyboard::clipboard::listing::ListingStyle;
+use slyboard::clipboard::template::HistoryTemplate;
+use slyboard::config::ActiveWindowBackend;
+use slyboard::core::setup::{parse_backend, SetupOverrides};
+use slyboard::core::status_bar::{DEFAULT_BAR_PREVIEW_CHARS, DEFAULT_BAR_TOOLTIP_ENTRIES};
+use slyboard::core::tail::DEFAULT_TAIL_PREVIEW_CHARS;
 
 #[derive(Debug, Parser)]
 #[command(name = "slyboard", version, about = "Slyboard daemon")]
@@ -11,17 +20,43 @@ pub struct Cli {
     /// Optional explicit config path (overrides discovery; useful for Nix store paths).
     #[arg(short = 'c', long, global = true, value_name = "PATH")]
     pub config: Option<PathBuf>,
+
+    /// Keep this many history entries for this invocation, overriding `storage.history_limit`
+    /// and `$SLYBOARD_HISTORY_LIMIT`.
+    #[arg(long, global = true, value_name = "N", value_parser = parse_history_limit)]
+    pub history_limit: Option<usize>,
 }
 
 #[derive(Debug, Clone, clap::Subcommand)]
 pub enum Commands {
     /// Run the clipboard manager daemon.
-    Run,
+    Run(RunArgs),
+    /// Stop the daemon running in this session.
+    Stop,
+    /// Read the clipboard once and record it like the daemon would, then exit: 0 when
+    /// recorded, 3 when unchanged, 4 when skipped (paused, blacklisted, ...), 1 on errors.
+    CaptureOnce,
+    /// Print whether a daemon is running in this session, its pid, and what it captured.
+    Status(StatusArgs),
+    /// Run each active-window provider once and print its latency and output.
+    Providers,
     /// Print clipboard history from the cache database.
     History(HistoryArgs),
+    /// Print one history entry in full.
+    Show(ShowArgs),
+    /// Print history entries whose text, source app, or (with --labels) label matches.
+    Search(SearchArgs),
+    /// Keep running and print a line for every new capture, like `tail -f`.
+    Tail(TailArgs),
+    /// Attach a short note to a history entry, or clear it.
+    Label(LabelArgs),
+    /// Print the text recognized in an image entry by the `ocr` command (tesseract by default).
+    Ocr(OcrArgs),
     /// Clear clipboard history from the cache database.
     #[command(name = "clear")]
-    ClearHistory,
+    ClearHistory(ClearArgs),
+    /// Bring back history moved to the trash by `clear` with `storage.trash_on_clear`.
+    Restore(RestoreArgs),
     /// Pause clipboard capture.
     #[command(name = "pause")]
     PauseCapture,
@@ -30,16 +65,756 @@ pub enum Commands {
     ResumeCapture,
     /// Print clipboard capture status.
     CaptureStatus,
-    /// Load and validate config, then exit.
-    ValidateConfig,
+    /// Collect copied text into one entry (`on`), join and copy it (`off`), or print `status`.
+    Append(AppendArgs),
+    /// Restore entries as normalized plain text (`on`/`off`), or print `status`.
+    PlainPaste(PlainPasteArgs),
+    /// Print a Waybar custom-module JSON line summarizing clipboard state.
+    Bar(BarArgs),
+    /// Remove stale entries from the cache database.
+    Prune(PruneArgs),
+    /// Save a history entry (the newest by default) in a named slot.
+    Yank(YankArgs),
+    /// Put the entry saved in a named slot back on the clipboard.
+    Put(PutArgs),
+    /// List named slots.
+    Slots(SlotsArgs),
+    /// Load and validate config, print warnings about suspicious settings, then exit.
+    ValidateConfig(ValidateConfigArgs),
+    /// Write the whole history, with full long texts and images, for `import` on another
+    /// machine; `--encrypt` seals it with a passphrase.
+    Export(ExportArgs),
+    /// Merge a slyboard export or bundle into history, or add another clipboard manager's
+    /// history, oldest first.
+    Import(ImportArgs),
+    /// Compare two `history --json --images` exports (or database copies) by content.
+    Diff(DiffArgs),
+    /// Write a systemd user unit for the daemon to ~/.config/systemd/user/slyboard.service.
+    InstallService(InstallServiceArgs),
+    /// Propose a config for this desktop, ask about each setting, and write it to
+    /// ~/.config/slyboard/config.yaml (or --config). `--history-limit` answers the history
+    /// question.
+    Setup(SetupArgs),
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct RunArgs {
+    /// Detach from the terminal and keep running in the background.
+    #[arg(long, conflicts_with = "foreground")]
+    pub daemonize: bool,
+    /// Stay attached to the terminal (the default), e.g. under systemd.
+    #[arg(long)]
+    pub foreground: bool,
+    /// Where a daemonized slyboard writes its output [default: $XDG_RUNTIME_DIR/slyboard-<session>.log]
+    #[arg(long, value_name = "PATH", requires = "daemonize")]
+    pub log_file: Option<PathBuf>,
+    /// Log why each skipped copy was skipped, like `logging.debug: true`.
+    #[arg(long)]
+    pub debug: bool,
+    /// Use a history database last written by a newer major slyboard version, losing any
+    /// fields that version added once the daemon saves it.
+    #[arg(long)]
+    pub force: bool,
 }
 
 #[derive(Debug, Clone, Args)]
 pub struct HistoryArgs {
     /// Emit clipboard history as JSON.
-    #[arg(long)]
+    #[arg(long, group = "json_output")]
     pub json: bool,
+    /// Emit clipboard history as JSON Lines, one object per entry.
+    #[arg(long, group = "json_output")]
+    pub jsonl: bool,
+    /// Render each entry with a template, e.g. '{id}\t{kind}\t{app}\t{preview:40}'.
+    /// Placeholders: id, kind, value, preview, preview:N, app, title, source, time, size,
+    /// approx_size, label.
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        value_parser = HistoryTemplate::parse,
+        conflicts_with_all = ["json", "jsonl"]
+    )]
+    pub format: Option<HistoryTemplate>,
+    /// Escape tabs, newlines, carriage returns, and backslashes inside `--format` values so
+    /// each entry stays on one line.
+    #[arg(long, requires = "format")]
+    pub escape: bool,
     /// Include full image pixel bytes in history output.
     #[arg(long)]
     pub images: bool,
+    /// Replace copied text, labels and window titles with placeholders and leave image
+    /// pixels out, e.g. to share the output in a bug report.
+    #[arg(long, requires = "json_output")]
+    pub redact: bool,
+    /// Only list text entries tagged with this content hint.
+    #[arg(long, value_name = "HINT", value_parser = parse_content_hint)]
+    pub hint: Option<ContentHint>,
+    /// Only list entries copied on this workspace id.
+    #[arg(long, value_name = "ID")]
+    pub workspace: Option<i64>,
+    /// With --workspace, also list entries that have no recorded workspace.
+    #[arg(long, requires = "workspace")]
+    pub include_unknown: bool,
+    /// Only list entries whose text or source app contains this, ignoring case.
+    #[arg(long, value_name = "TEXT")]
+    pub search: Option<String>,
+    /// Match --search against entry labels instead of text and source app.
+    #[arg(long, requires = "search")]
+    pub labels: bool,
+    /// Order entries newest first (`recent`) or largest first (`size`); ids stay the same.
+    #[arg(long, value_enum, default_value_t = HistorySort::Recent)]
+    pub sort: HistorySort,
+    /// List entries in one run instead of under "Today", "Yesterday", ... headings. Output
+    /// that isn't a terminal, or is sorted by size, is never grouped.
+    #[arg(long)]
+    pub no_group: bool,
+    /// When to color the plain listing; `auto` colors a terminal unless NO_COLOR is set.
+    #[arg(long, value_enum, default_value_t = ColorWhen::Auto)]
+    pub color: ColorWhen,
+}
+
+impl HistoryArgs {
+    /// How the plain listing is decorated when written to a terminal or not, with NO_COLOR
+    /// set (to something non-empty) or not.
+    pub fn listing_style(&self, to_terminal: bool, no_color: bool) -> ListingStyle {
+        ListingStyle {
+            group: to_terminal && !self.no_group && self.sort == HistorySort::Recent,
+            color: match self.color {
+                ColorWhen::Auto => to_terminal && !no_color,
+                ColorWhen::Always => true,
+                ColorWhen::Never => false,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct SearchArgs {
+    /// Text to look for, ignoring case.
+    pub query: String,
+    /// Search entry labels instead of text and source app.
+    #[arg(long, conflicts_with = "fuzzy")]
+    pub labels: bool,
+    /// Match the query's words fuzzily (`gthb tok` finds `github token`), best matches first.
+    #[arg(long)]
+    pub fuzzy: bool,
+    /// Emit matching entries as JSON.
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct LabelArgs {
+    /// History id, as listed by `slyboard history`.
+    pub id: usize,
+    /// The note, up to 100 characters on one line.
+    #[arg(required_unless_present = "clear")]
+    pub text: Option<String>,
+    /// Remove the entry's label instead.
+    #[arg(long, conflicts_with = "text")]
+    pub clear: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ShowArgs {
+    /// History id, as listed by `slyboard history`, or the stable id `show` prints, which
+    /// keeps naming the entry as newer copies arrive.
+    pub id: String,
+    /// Also print capture diagnostics, such as the targets offered with the copy.
+    #[arg(long, short, conflicts_with = "json")]
+    pub verbose: bool,
+    /// Print the entry as the JSON object `history --json` lists it as (image pixels left out).
+    #[arg(long)]
+    pub json: bool,
+    /// Replace the text, label and window titles with placeholders, as `history --redact` does.
+    #[arg(long)]
+    pub redact: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct OcrArgs {
+    /// History id of an image entry, as listed by `slyboard history`.
+    pub id: usize,
+    /// Also copy the text and record it as a new entry.
+    #[arg(long)]
+    pub copy: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum HistorySort {
+    Recent,
+    Size,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorWhen {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct StatusArgs {
+    /// Print JSON, including the daemon's capture and per-provider active-window counters.
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ValidateConfigArgs {
+    /// Exit non-zero when there are warnings, not just errors.
+    #[arg(long)]
+    pub strict: bool,
+    /// Print every field set by the merged config files and the file it came from.
+    #[arg(long)]
+    pub show: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+#[group(required = true, multiple = true)]
+pub struct PruneArgs {
+    /// Keep only the newest of entries with the same content.
+    #[arg(long)]
+    pub duplicates: bool,
+    /// Drop text entries that are empty or only whitespace.
+    #[arg(long)]
+    pub empty: bool,
+    /// Drop image entries whose stored bytes don't match their dimensions.
+    #[arg(long)]
+    pub broken_images: bool,
+    /// Drop entries copied from windows `clipboard.active_window.blacklist` now matches.
+    #[arg(long)]
+    pub blacklist: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ExportArgs {
+    /// Encrypt with a passphrase, asked for twice without echo unless --passphrase-file is given.
+    #[arg(long, requires = "output")]
+    pub encrypt: bool,
+    /// Write here instead of to stdout.
+    #[arg(long, short, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+    /// Read the passphrase from the first line of this file.
+    #[arg(long, value_name = "PATH", requires = "encrypt")]
+    pub passphrase_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ImportArgs {
+    /// Program that wrote the file. `slyboard` reads `export` output, encrypted or not (told
+    /// apart by its first bytes), `history --json --images` exports, and database copies.
+    #[arg(long, value_enum, default_value_t = ImportSource::Slyboard)]
+    pub from: ImportSource,
+    /// The history file, e.g. ~/.local/share/klipper/history2.lst for Klipper.
+    pub path: PathBuf,
+    /// Read an encrypted bundle's passphrase from the first line of this file instead of
+    /// asking for it.
+    #[arg(long, value_name = "PATH")]
+    pub passphrase_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct DiffArgs {
+    pub left: PathBuf,
+    pub right: PathBuf,
+    /// Print the union of both, newest capture first, as a `history --json --images` export.
+    #[arg(long)]
+    pub merge: bool,
+    /// Write the merge here instead of to stdout.
+    #[arg(long, short, value_name = "PATH", requires = "merge")]
+    pub output: Option<PathBuf>,
+    /// Maximum characters of each entry preview.
+    #[arg(long, default_value_t = DEFAULT_TAIL_PREVIEW_CHARS)]
+    pub preview_chars: usize,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct InstallServiceArgs {
+    /// Replace an existing unit file.
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct SetupArgs {
+    /// Take the proposed answer to every question the flags leave open, without asking.
+    #[arg(short = 'y', long)]
+    pub accept_defaults: bool,
+    /// Active-window backend: `auto`, `disabled`, or `sway` (asks `swaymsg` for the title).
+    #[arg(long, value_name = "BACKEND", value_parser = parse_active_window)]
+    pub active_window: Option<ActiveWindowBackend>,
+    /// App to keep out of history; repeat for more.
+    #[arg(long, value_name = "APP", conflicts_with = "no_blacklist")]
+    pub blacklist: Vec<String>,
+    /// Keep nothing out of history.
+    #[arg(long)]
+    pub no_blacklist: bool,
+    /// Show a notification for each copy.
+    #[arg(long, value_name = "BOOL")]
+    pub notifications: Option<bool>,
+    /// Write the systemd user unit, as `install-service` does.
+    #[arg(long, value_name = "BOOL")]
+    pub install_service: Option<bool>,
+    /// Replace an existing config file and unit file.
+    #[arg(long)]
+    pub force: bool,
+}
+
+impl SetupArgs {
+    /// The answers these flags give, with `history_limit` from the global `--history-limit`.
+    pub fn overrides(&self, history_limit: Option<usize>) -> SetupOverrides {
+        SetupOverrides {
+            active_window: self.active_window.clone(),
+            blacklist: if self.no_blacklist {
+                Some(Vec::new())
+            } else {
+                Some(self.blacklist.clone()).filter(|blacklist| !blacklist.is_empty())
+            },
+            history_limit,
+            notifications: self.notifications,
+            install_service: self.install_service,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ImportSource {
+    Slyboard,
+    Klipper,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct AppendArgs {
+    #[arg(value_enum)]
+    pub action: ModeAction,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct PlainPasteArgs {
+    #[arg(value_enum)]
+    pub action: ModeAction,
+}
+
+/// Switches a session-wide mode, or prints whether it is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ModeAction {
+    On,
+    Off,
+    Status,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct YankArgs {
+    /// Slot name: 1 to 32 letters and digits.
+    pub slot: String,
+    /// History id to save, as listed by `slyboard history` [default: the newest entry]
+    #[arg(long, value_name = "ID")]
+    pub id: Option<usize>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ClearArgs {
+    /// Don't ask for confirmation; required when stdin isn't a terminal.
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct RestoreArgs {
+    /// Restore from `history.json.trash`, behind the current history.
+    #[arg(long, required = true)]
+    pub trash: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct PutArgs {
+    /// Slot name.
+    pub slot: String,
+    /// Set both the clipboard and the primary selection (middle-click paste), like
+    /// `clipboard.restore_target: both`.
+    #[arg(long)]
+    pub primary: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct SlotsArgs {
+    /// Delete this slot instead of listing.
+    #[arg(long, value_name = "SLOT")]
+    pub remove: Option<String>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct TailArgs {
+    /// Color the source app name, with a stable color per app.
+    #[arg(long)]
+    pub follow_source: bool,
+    /// Maximum characters of each entry preview.
+    #[arg(long, default_value_t = DEFAULT_TAIL_PREVIEW_CHARS)]
+    pub preview_chars: usize,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct BarArgs {
+    /// Keep running and print a new line whenever history or capture state changes.
+    #[arg(long)]
+    pub follow: bool,
+    /// Maximum characters of each entry preview.
+    #[arg(long, default_value_t = DEFAULT_BAR_PREVIEW_CHARS)]
+    pub preview_chars: usize,
+    /// Number of recent entries listed in the tooltip.
+    #[arg(long, default_value_t = DEFAULT_BAR_TOOLTIP_ENTRIES)]
+    pub tooltip_entries: usize,
+}
+
+/// Whether `slyboard clear` may go ahead: always with `--yes`, after a `y`/`yes` answer to a
+/// prompt on a terminal, and never without `--yes` when nobody can answer.
+pub fn confirm_clear(
+    yes: bool,
+    interactive: bool,
+    mut input: impl BufRead,
+    mut prompt: impl Write,
+) -> Result<bool> {
+    if yes {
+        return Ok(true);
+    }
+    if !interactive {
+        bail!("refusing to clear clipboard history without --yes when stdin is not a terminal");
+    }
+    write!(prompt, "Clear clipboard history? [y/N] ")?;
+    prompt.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+fn parse_history_limit(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(0) | Err(_) => Err("expected a positive number".to_string()),
+        Ok(limit) => Ok(limit),
+    }
+}
+
+fn parse_active_window(value: &str) -> Result<ActiveWindowBackend, String> {
+    parse_backend(value).ok_or_else(|| "expected auto, disabled or sway".to_string())
+}
+
+fn parse_content_hint(value: &str) -> Result<ContentHint, String> {
+    ContentHint::parse(value).ok_or_else(|| {
+        let known: Vec<&str> = ContentHint::ALL.iter().map(|hint| hint.as_str()).collect();
+        format!(
+            "unknown content hint (expected one of: {})",
+            known.join(", ")
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{confirm_clear, Cli, Commands, HistorySort, ImportSource, ModeAction, StatusArgs};
+    use clap::Parser;
+    use slyboard::core::setup::sway_backend;
+    use std::path::PathBuf;
+
+    fn parse(args: &[&str]) -> Result<Option<Commands>, clap::Error> {
+        Cli::try_parse_from(std::iter::once("slyboard").chain(args.iter().copied()))
+            .map(|cli| cli.command)
+    }
+
+    #[test]
+    fn parses_run_daemon_flags() {
+        let Some(Commands::Run(args)) =
+            parse(&["run", "--daemonize", "--log-file", "/tmp/sly.log"]).unwrap()
+        else {
+            panic!("expected run");
+        };
+        assert!(args.daemonize && !args.foreground);
+        assert_eq!(args.log_file, Some(PathBuf::from("/tmp/sly.log")));
+
+        let Some(Commands::Run(args)) = parse(&["run", "--foreground"]).unwrap() else {
+            panic!("expected run");
+        };
+        assert!(args.foreground && !args.daemonize);
+
+        assert!(parse(&["run", "--daemonize", "--foreground"]).is_err());
+        assert!(parse(&["run", "--log-file", "/tmp/sly.log"]).is_err());
+        assert!(matches!(parse(&["stop"]).unwrap(), Some(Commands::Stop)));
+        assert!(matches!(
+            parse(&["capture-once"]).unwrap(),
+            Some(Commands::CaptureOnce)
+        ));
+
+        assert!(matches!(
+            parse(&["status"]).unwrap(),
+            Some(Commands::Status(StatusArgs { json: false }))
+        ));
+        assert!(matches!(
+            parse(&["status", "--json"]).unwrap(),
+            Some(Commands::Status(StatusArgs { json: true }))
+        ));
+        assert!(matches!(
+            parse(&["providers"]).unwrap(),
+            Some(Commands::Providers)
+        ));
+    }
+
+    #[test]
+    fn parses_history_sort() {
+        let sort = |args: &[&str]| match parse(args).unwrap() {
+            Some(Commands::History(args)) => args.sort,
+            _ => panic!("expected history"),
+        };
+        assert_eq!(sort(&["history"]), HistorySort::Recent);
+        assert_eq!(sort(&["history", "--sort", "size"]), HistorySort::Size);
+        assert!(parse(&["history", "--sort", "age"]).is_err());
+    }
+
+    #[test]
+    fn history_redacts_only_json_output() {
+        assert!(parse(&["history", "--json", "--redact"]).is_ok());
+        assert!(parse(&["history", "--jsonl", "--images", "--redact"]).is_ok());
+        assert!(parse(&["history", "--redact"]).is_err());
+        assert!(parse(&["history", "--json", "--jsonl"]).is_err());
+        assert!(parse(&["show", "3", "--redact"]).is_ok());
+    }
+
+    #[test]
+    fn history_decorates_only_terminals_unless_told_otherwise() {
+        let style = |args: &[&str], to_terminal: bool, no_color: bool| {
+            let Some(Commands::History(args)) = parse(&[&["history"], args].concat()).unwrap()
+            else {
+                panic!("expected history");
+            };
+            let style = args.listing_style(to_terminal, no_color);
+            (style.group, style.color)
+        };
+        assert_eq!(style(&[], true, false), (true, true));
+        assert_eq!(style(&[], false, false), (false, false));
+        assert_eq!(style(&[], true, true), (true, false), "NO_COLOR");
+        assert_eq!(style(&["--no-group"], true, false), (false, true));
+        assert_eq!(style(&["--sort", "size"], true, false), (false, true));
+        assert_eq!(style(&["--color", "always"], false, true), (false, true));
+        assert_eq!(style(&["--color=never"], true, false), (true, false));
+        assert!(parse(&["history", "--color", "sometimes"]).is_err());
+    }
+
+    #[test]
+    fn parses_mode_actions() {
+        let action = |args: &[&str]| match parse(args).unwrap() {
+            Some(Commands::Append(args)) => args.action,
+            _ => panic!("expected append"),
+        };
+        assert_eq!(action(&["append", "on"]), ModeAction::On);
+        assert_eq!(action(&["append", "off"]), ModeAction::Off);
+        assert_eq!(action(&["append", "status"]), ModeAction::Status);
+        assert!(parse(&["append"]).is_err());
+
+        let Some(Commands::PlainPaste(args)) = parse(&["plain-paste", "on"]).unwrap() else {
+            panic!("expected plain-paste");
+        };
+        assert_eq!(args.action, ModeAction::On);
+        assert!(parse(&["plain-paste", "maybe"]).is_err());
+    }
+
+    #[test]
+    fn parses_slot_commands() {
+        let Some(Commands::Yank(args)) = parse(&["yank", "a", "--id", "3"]).unwrap() else {
+            panic!("expected yank");
+        };
+        assert_eq!((args.slot.as_str(), args.id), ("a", Some(3)));
+        assert!(parse(&["yank"]).is_err());
+
+        let Some(Commands::Put(args)) = parse(&["put", "url"]).unwrap() else {
+            panic!("expected put");
+        };
+        assert_eq!(args.slot, "url");
+        assert!(!args.primary);
+        let Some(Commands::Put(args)) = parse(&["put", "url", "--primary"]).unwrap() else {
+            panic!("expected put");
+        };
+        assert!(args.primary);
+
+        let Some(Commands::Slots(args)) = parse(&["slots", "--remove", "a"]).unwrap() else {
+            panic!("expected slots");
+        };
+        assert_eq!(args.remove.as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn parses_label_and_search_commands() {
+        let Some(Commands::Label(args)) = parse(&["label", "3", "prod db password hint"]).unwrap()
+        else {
+            panic!("expected label");
+        };
+        assert_eq!(args.id, 3);
+        assert_eq!(args.text.as_deref(), Some("prod db password hint"));
+        assert!(!args.clear);
+
+        let Some(Commands::Label(args)) = parse(&["label", "3", "--clear"]).unwrap() else {
+            panic!("expected label");
+        };
+        assert!(args.clear && args.text.is_none());
+        assert!(parse(&["label", "3"]).is_err());
+        assert!(parse(&["label", "3", "note", "--clear"]).is_err());
+
+        let Some(Commands::Search(args)) = parse(&["search", "--labels", "prod"]).unwrap() else {
+            panic!("expected search");
+        };
+        assert_eq!(args.query, "prod");
+        assert!(args.labels && !args.json);
+        assert!(parse(&["history", "--labels"]).is_err());
+
+        let Some(Commands::Search(args)) = parse(&["search", "--fuzzy", "gthb tok"]).unwrap()
+        else {
+            panic!("expected search");
+        };
+        assert!(args.fuzzy && args.query == "gthb tok");
+        assert!(parse(&["search", "--fuzzy", "--labels", "prod"]).is_err());
+    }
+
+    #[test]
+    fn parses_import_source_and_path() {
+        let Some(Commands::Import(args)) =
+            parse(&["import", "--from", "klipper", "/tmp/history2.lst"]).unwrap()
+        else {
+            panic!("expected import");
+        };
+        assert_eq!(args.from, ImportSource::Klipper);
+        assert_eq!(args.path, PathBuf::from("/tmp/history2.lst"));
+        assert!(matches!(
+            parse(&["import", "bundle.slyb", "--passphrase-file", "pw"]).unwrap(),
+            Some(Commands::Import(args))
+                if args.from == ImportSource::Slyboard && args.passphrase_file.is_some()
+        ));
+        assert!(parse(&["import", "--from", "gpaste", "/tmp/history"]).is_err());
+        assert!(matches!(
+            parse(&["install-service", "--force"]).unwrap(),
+            Some(Commands::InstallService(args)) if args.force
+        ));
+    }
+
+    #[test]
+    fn encrypted_exports_go_to_a_file() {
+        let Some(Commands::Export(args)) = parse(&[
+            "export",
+            "--encrypt",
+            "--output",
+            "bundle.slyb",
+            "--passphrase-file",
+            "pw",
+        ])
+        .unwrap() else {
+            panic!("expected export");
+        };
+        assert!(args.encrypt);
+        assert_eq!(args.output, Some(PathBuf::from("bundle.slyb")));
+        assert_eq!(args.passphrase_file, Some(PathBuf::from("pw")));
+
+        assert!(parse(&["export"]).is_ok());
+        assert!(parse(&["export", "--encrypt"]).is_err(), "not to stdout");
+        assert!(parse(&["export", "-o", "plain.json", "--passphrase-file", "pw"]).is_err());
+    }
+
+    #[test]
+    fn parses_setup_answers() {
+        let overrides = |args: &[&str]| {
+            let cli = Cli::try_parse_from([&["slyboard", "setup"], args].concat()).unwrap();
+            let Some(Commands::Setup(setup)) = cli.command else {
+                panic!("expected setup");
+            };
+            setup.overrides(cli.history_limit)
+        };
+        let none = overrides(&[]);
+        assert!(none.active_window.is_none() && none.blacklist.is_none());
+
+        let given = overrides(&[
+            "--active-window",
+            "sway",
+            "--blacklist",
+            "KeePassXC",
+            "--blacklist",
+            "Signal",
+            "--notifications",
+            "false",
+            "--history-limit",
+            "200",
+        ]);
+        assert_eq!(given.active_window, Some(sway_backend()));
+        assert_eq!(given.blacklist.unwrap(), ["KeePassXC", "Signal"]);
+        assert_eq!(given.notifications, Some(false));
+        assert_eq!(given.history_limit, Some(200));
+        assert_eq!(given.install_service, None);
+
+        assert_eq!(overrides(&["--no-blacklist"]).blacklist, Some(Vec::new()));
+        assert!(parse(&["setup", "--no-blacklist", "--blacklist", "a"]).is_err());
+        assert!(parse(&["setup", "--active-window", "xdotool"]).is_err());
+        assert!(matches!(
+            parse(&["setup", "-y", "--force"]).unwrap(),
+            Some(Commands::Setup(args)) if args.accept_defaults && args.force
+        ));
+    }
+
+    #[test]
+    fn history_limit_is_global_and_positive() {
+        let limit = |args: &[&str]| {
+            Cli::try_parse_from(std::iter::once("slyboard").chain(args.iter().copied()))
+                .map(|cli| cli.history_limit)
+        };
+        assert_eq!(limit(&["history"]).unwrap(), None);
+        assert_eq!(
+            limit(&["--history-limit", "500", "run"]).unwrap(),
+            Some(500)
+        );
+        assert_eq!(
+            limit(&["clear", "--history-limit", "10"]).unwrap(),
+            Some(10)
+        );
+        assert!(limit(&["history", "--history-limit", "0"]).is_err());
+        assert!(limit(&["history", "--history-limit", "lots"]).is_err());
+    }
+
+    #[test]
+    fn clear_asks_on_a_terminal_and_needs_yes_otherwise() {
+        assert!(matches!(
+            parse(&["clear", "-y"]).unwrap(),
+            Some(Commands::ClearHistory(args)) if args.yes
+        ));
+        assert!(parse(&["restore"]).is_err(), "--trash is the only source");
+
+        let confirm = |yes, interactive, answer: &str| {
+            let mut prompt = Vec::new();
+            let confirmed = confirm_clear(yes, interactive, answer.as_bytes(), &mut prompt);
+            (confirmed.map_err(|err| err.to_string()), prompt)
+        };
+        assert_eq!(confirm(true, false, ""), (Ok(true), Vec::new()));
+        let (confirmed, prompt) = confirm(false, true, "Yes\n");
+        assert_eq!(confirmed, Ok(true));
+        assert_eq!(prompt, b"Clear clipboard history? [y/N] ");
+        assert_eq!(confirm(false, true, "\n").0, Ok(false));
+        assert_eq!(confirm(false, true, "").0, Ok(false), "end of input");
+        assert_eq!(confirm(false, true, "nope\n").0, Ok(false));
+        assert_eq!(
+            confirm(false, false, "y\n").0,
+            Err(
+                "refusing to clear clipboard history without --yes when stdin is not a terminal"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn diff_writes_a_merge_only_with_merge() {
+        let Some(Commands::Diff(args)) =
+            parse(&["diff", "a.json", "b.json", "--merge", "-o", "merged.json"]).unwrap()
+        else {
+            panic!("expected diff");
+        };
+        assert!(args.merge);
+        assert_eq!(args.output, Some(PathBuf::from("merged.json")));
+        assert!(parse(&["diff", "a.json", "b.json", "--output", "merged.json"]).is_err());
+        assert!(parse(&["diff", "a.json"]).is_err());
+    }
 }
@@ -0,0 +1,248 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Current Unix time in seconds, as stored in `captured_at`.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Compact age of something captured at `captured_at`: `now`, `5m`, `3h`, or `2d`.
+/// Timestamps in the future (clock changes) count as `now`.
+pub fn format_relative_age(captured_at: u64, now: u64) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+
+    match now.saturating_sub(captured_at) {
+        elapsed if elapsed < MINUTE => "now".to_string(),
+        elapsed if elapsed < HOUR => format!("{}m", elapsed / MINUTE),
+        elapsed if elapsed < DAY => format!("{}h", elapsed / HOUR),
+        elapsed => format!("{}d", elapsed / DAY),
+    }
+}
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Local wall-clock time of `unix_secs` as `HH:MM:SS`.
+pub fn format_clock_time(unix_secs: u64) -> String {
+    format_clock_time_with_offset(unix_secs, local_utc_offset)
+}
+
+fn format_clock_time_with_offset(unix_secs: u64, utc_offset: impl Fn(i64) -> i64) -> String {
+    let unix_secs = i64::try_from(unix_secs).unwrap_or(i64::MAX);
+    let seconds_of_day = unix_secs
+        .saturating_add(utc_offset(unix_secs))
+        .rem_euclid(SECONDS_PER_DAY);
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
+
+/// Section of a picker an entry is listed under, by local calendar day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DateBucket {
+    Today,
+    Yesterday,
+    /// Earlier in the current week, which starts on Monday.
+    ThisWeek,
+    /// Before this week, or captured before entries had timestamps.
+    Older,
+}
+
+impl DateBucket {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Today => "Today",
+            Self::Yesterday => "Yesterday",
+            Self::ThisWeek => "This week",
+            Self::Older => "Older",
+        }
+    }
+}
+
+/// Buckets `captured_at` relative to `now` in the local time zone.
+pub fn date_bucket(captured_at: Option<u64>, now: u64) -> DateBucket {
+    date_bucket_with_offset(captured_at, now, local_utc_offset)
+}
+
+/// [`date_bucket`] with the UTC offset (in seconds) in effect at a given Unix time supplied by
+/// `utc_offset`, so each timestamp is placed by the offset of its own day across DST changes.
+/// Timestamps after `now` (clock changes) count as today.
+pub fn date_bucket_with_offset(
+    captured_at: Option<u64>,
+    now: u64,
+    utc_offset: impl Fn(i64) -> i64,
+) -> DateBucket {
+    let Some(captured_at) = captured_at else {
+        return DateBucket::Older;
+    };
+    let local_day = |unix_secs: u64| {
+        let unix_secs = i64::try_from(unix_secs).unwrap_or(i64::MAX);
+        unix_secs
+            .saturating_add(utc_offset(unix_secs))
+            .div_euclid(SECONDS_PER_DAY)
+    };
+    let today = local_day(now);
+    // Day 0, 1970-01-01, was a Thursday; Monday is 0.
+    let weekday = (today + 3).rem_euclid(7);
+    match today - local_day(captured_at) {
+        days if days <= 0 => DateBucket::Today,
+        1 => DateBucket::Yesterday,
+        days if days <= weekday => DateBucket::ThisWeek,
+        _ => DateBucket::Older,
+    }
+}
+
+/// Seconds east of UTC in the local time zone at `unix_secs`; 0 if it can't be determined.
+#[cfg(unix)]
+pub fn local_utc_offset(unix_secs: i64) -> i64 {
+    // `time_t` and `c_long` are 64 bits on the Linux targets slyboard runs on.
+    let time = unix_secs as libc::time_t;
+    // SAFETY: `tm` is plain data that `localtime_r` fills in; both pointers are valid for the
+    // duration of the call.
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return 0;
+        }
+        tm.tm_gmtoff as i64
+    }
+}
+
+/// Windows' `tm` has no UTC offset, so it is the difference between local and UTC time.
+#[cfg(windows)]
+pub fn local_utc_offset(unix_secs: i64) -> i64 {
+    let time = unix_secs as libc::time_t;
+    // SAFETY: as above, with the argument order of the `_s` variants.
+    let (local, utc) = unsafe {
+        let mut local: libc::tm = std::mem::zeroed();
+        let mut utc: libc::tm = std::mem::zeroed();
+        if libc::localtime_s(&mut local, &time) != 0 || libc::gmtime_s(&mut utc, &time) != 0 {
+            return 0;
+        }
+        (local, utc)
+    };
+    // The two are at most a day apart, possibly across New Year.
+    let days = match local.tm_year.cmp(&utc.tm_year) {
+        std::cmp::Ordering::Equal => local.tm_yday - utc.tm_yday,
+        std::cmp::Ordering::Greater => 1,
+        std::cmp::Ordering::Less => -1,
+    };
+    let seconds = |tm: &libc::tm| i64::from(tm.tm_hour * 3600 + tm.tm_min * 60 + tm.tm_sec);
+    i64::from(days) * 86_400 + seconds(&local) - seconds(&utc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        date_bucket_with_offset, format_clock_time_with_offset, format_relative_age, DateBucket,
+    };
+
+    const HOUR: u64 = 3600;
+    /// 2024-03-31 01:00 UTC, when Central European time moves from UTC+1 to UTC+2.
+    const CET_SPRING_FORWARD: u64 = 1_711_846_800;
+    /// 2024-10-27 01:00 UTC, when it moves back to UTC+1.
+    const CET_FALL_BACK: u64 = 1_729_990_800;
+
+    fn berlin_offset(unix_secs: i64) -> i64 {
+        let summer = (CET_SPRING_FORWARD as i64..CET_FALL_BACK as i64).contains(&unix_secs);
+        if summer {
+            7200
+        } else {
+            3600
+        }
+    }
+
+    fn bucket(captured_at: u64, now: u64) -> DateBucket {
+        date_bucket_with_offset(Some(captured_at), now, berlin_offset)
+    }
+
+    #[test]
+    fn buckets_by_local_midnight() {
+        // Wednesday 2024-01-17 12:00 local (UTC+1).
+        let now = 1_705_489_200;
+        assert_eq!(bucket(now, now), DateBucket::Today);
+        // 00:30 local is still today although it is 23:30 UTC the day before.
+        assert_eq!(bucket(now - 11 * HOUR - 1800, now), DateBucket::Today);
+        assert_eq!(bucket(now - 12 * HOUR - 1, now), DateBucket::Yesterday);
+        // Monday 23:59 local is earlier this week; Sunday is not.
+        assert_eq!(bucket(now - 36 * HOUR - 60, now), DateBucket::ThisWeek);
+        assert_eq!(bucket(now - 60 * HOUR - 60, now), DateBucket::Older);
+        assert_eq!(bucket(now + HOUR, now), DateBucket::Today);
+        assert_eq!(
+            date_bucket_with_offset(None, now, berlin_offset),
+            DateBucket::Older
+        );
+    }
+
+    #[test]
+    fn uses_each_timestamps_own_offset_across_dst() {
+        // Sunday 2024-03-31 12:00 local, UTC+2 since 03:00 local.
+        let now = CET_SPRING_FORWARD + 9 * HOUR;
+        // 00:30 local (UTC+1) the same day, 23:30 UTC the day before.
+        assert_eq!(bucket(CET_SPRING_FORWARD - 90 * 60, now), DateBucket::Today);
+        // 23:30 local on Saturday (UTC+1); today's UTC+2 would put it after midnight.
+        assert_eq!(
+            bucket(CET_SPRING_FORWARD - 150 * 60, now),
+            DateBucket::Yesterday
+        );
+
+        // Monday 2024-10-28 00:30 local (UTC+1), the day after the 25-hour Sunday.
+        let now = CET_FALL_BACK + 22 * HOUR + 1800;
+        // Sunday 00:30 local (UTC+2); today's UTC+1 would put it on Saturday.
+        assert_eq!(bucket(CET_FALL_BACK - 150 * 60, now), DateBucket::Yesterday);
+        // Sunday 23:59 local (UTC+1) is yesterday too; the week started today.
+        assert_eq!(bucket(now - 31 * 60, now), DateBucket::Yesterday);
+        // Saturday 23:59 local (UTC+2).
+        assert_eq!(
+            bucket(CET_FALL_BACK - 3 * HOUR - 60, now),
+            DateBucket::Older
+        );
+    }
+
+    #[test]
+    fn labels_buckets() {
+        let labels: Vec<&str> = [
+            DateBucket::Today,
+            DateBucket::Yesterday,
+            DateBucket::ThisWeek,
+            DateBucket::Older,
+        ]
+        .map(DateBucket::label)
+        .to_vec();
+        assert_eq!(labels, ["Today", "Yesterday", "This week", "Older"]);
+    }
+
+    #[test]
+    fn formats_relative_ages() {
+        let now = 1_700_000_000;
+        assert_eq!(format_relative_age(now, now), "now");
+        assert_eq!(format_relative_age(now - 59, now), "now");
+        assert_eq!(format_relative_age(now - 60, now), "1m");
+        assert_eq!(format_relative_age(now - 5 * 60 - 30, now), "5m");
+        assert_eq!(format_relative_age(now - 3 * 3600, now), "3h");
+        assert_eq!(format_relative_age(now - 86_399, now), "23h");
+        assert_eq!(format_relative_age(now - 2 * 86_400, now), "2d");
+        assert_eq!(format_relative_age(now + 30, now), "now");
+    }
+
+    #[test]
+    fn formats_local_clock_times_across_dst() {
+        // 00:59:59 UTC is 01:59:59 in winter, one second later it is 03:00:00 in summer.
+        let before = CET_SPRING_FORWARD - 1;
+        assert_eq!(
+            format_clock_time_with_offset(before, berlin_offset),
+            "01:59:59"
+        );
+        assert_eq!(
+            format_clock_time_with_offset(CET_SPRING_FORWARD, berlin_offset),
+            "03:00:00"
+        );
+        assert_eq!(format_clock_time_with_offset(0, |_| 0), "00:00:00");
+    }
+}
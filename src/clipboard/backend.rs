@@ -1,65 +1,668 @@
-use crate::clipboard::ClipboardEntry;
-use crate::core::active_window::{ActiveWindowContext, ActiveWindowProvider};
+#[cfg(feature = "gtk")]
+use anyhow::{bail, Result};
+
+#[cfg(any(feature = "gtk", test))]
+use std::cell::{Cell, RefCell};
+#[cfg(any(feature = "gtk", test))]
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::clipboard::writer::Selection;
+#[cfg(feature = "gtk")]
+use crate::clipboard::ImageData;
+use crate::clipboard::{CaptureOrigin, ClipboardEntry};
+use crate::core::active_window::ActiveWindowContext;
+#[cfg(feature = "gtk")]
+use crate::core::active_window::ActiveWindowProvider;
+
+/// How long a GTK clipboard read may wait for the selection owner before the poll is skipped.
+/// Kept below the 750ms poll interval so a silent owner can't stack up reads.
+#[cfg(feature = "gtk")]
+const GTK_READ_TIMEOUT: Duration = Duration::from_millis(500);
+#[cfg(any(feature = "gtk", test))]
+const TEXT_TARGETS: [&str; 4] = ["UTF8_STRING", "STRING", "TEXT", "COMPOUND_TEXT"];
+
+/// A clipboard read and the window it was copied in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capture {
+    pub entry: ClipboardEntry,
+    pub window: Option<ActiveWindowContext>,
+    /// How long after `window` was looked up the clipboard contents were in hand; zero when
+    /// the window was looked up as the copy happened.
+    pub window_lag: Duration,
+}
 
 pub trait ClipboardBackend {
     fn read_entry(&self) -> Option<ClipboardEntry>;
+    /// Recorded as the [`captured_by`](ClipboardEntry::captured_by) of entries read here.
+    fn origin(&self) -> CaptureOrigin;
+    /// Recorded as the [`selection`](ClipboardEntry::selection) of entries read here.
+    fn selection(&self) -> Selection {
+        Selection::Clipboard
+    }
     fn read_active_window(&self) -> Option<ActiveWindowContext> {
         None
     }
+
+    /// Reads the clipboard together with the focused window. The window is looked up first:
+    /// reading the clipboard, an image especially, can take long enough for the user to have
+    /// switched windows by the time it is done.
+    fn read_capture(&self) -> Option<Capture> {
+        let window = self.read_active_window();
+        let looked_up = Instant::now();
+        let entry = self.read_entry()?;
+        Some(Capture {
+            entry,
+            window,
+            window_lag: looked_up.elapsed(),
+        })
+    }
+
+    /// Whether each [`read_entry`](Self::read_entry) result is a separate copy, as with
+    /// selection events, rather than whatever the clipboard holds right now. Only then can
+    /// copying the same content again be told apart from the clipboard not changing.
+    fn reports_each_copy(&self) -> bool {
+        false
+    }
 }
 
-#[cfg(target_os = "linux")]
+#[cfg(feature = "gtk")]
 pub struct GtkClipboardBackend {
+    selection: Selection,
     clipboard: gtk::Clipboard,
     active_window_provider: Box<dyn ActiveWindowProvider>,
+    last_image_stamp: Rc<RefCell<Option<SelectionStamp>>>,
+    record_targets: bool,
+    /// The outcome of the last read [`start_read`](Self::start_read) finished, until
+    /// [`read_capture`](ClipboardBackend::read_capture) takes it.
+    finished: Rc<RefCell<Option<Option<Capture>>>>,
 }
 
-#[cfg(target_os = "linux")]
+#[cfg(feature = "gtk")]
 impl GtkClipboardBackend {
+    /// Watches `selection` through its GTK clipboard handle.
     pub fn new(
-        clipboard: &gtk::Clipboard,
+        selection: Selection,
         active_window_provider: Box<dyn ActiveWindowProvider>,
     ) -> Self {
         Self {
-            clipboard: clipboard.clone(),
+            selection,
+            clipboard: selection.gtk_clipboard(),
             active_window_provider,
+            last_image_stamp: Rc::new(RefCell::new(None)),
+            record_targets: false,
+            finished: Rc::new(RefCell::new(None)),
         }
     }
+
+    /// Keeps the targets the owner offered on each entry read, for `clipboard.record_targets`.
+    pub fn with_recorded_targets(mut self, record_targets: bool) -> Self {
+        self.record_targets = record_targets;
+        self
+    }
+
+    /// Starts reading the selection without blocking. The owner's replies arrive through the
+    /// main loop; `done` runs once they are all in or `GTK_READ_TIMEOUT` has passed, so an owner
+    /// that never replies costs one skipped poll. The next
+    /// [`read_capture`](ClipboardBackend::read_capture) returns what was read.
+    pub fn start_read(&self, done: impl FnOnce() + 'static) {
+        let window = self.read_active_window();
+        let looked_up = Instant::now();
+        let finished = Rc::clone(&self.finished);
+        let read = Rc::new(PendingRead::new(Box::new(move |entry| {
+            finished.replace(Some(entry.map(|entry| Capture {
+                entry,
+                window,
+                window_lag: looked_up.elapsed(),
+            })));
+            done();
+        })));
+
+        let expired = Rc::clone(&read);
+        let deadline = gtk::glib::timeout_add_local_once(GTK_READ_TIMEOUT, move || {
+            // The source is gone once this runs, so there's nothing left to cancel.
+            expired.cancel_deadline.take();
+            expired.expire(GTK_READ_TIMEOUT);
+        });
+        read.cancel_deadline
+            .replace(Some(Box::new(move || deadline.remove())));
+
+        let reader = Rc::new(GtkSelectionReader {
+            clipboard: self.clipboard.clone(),
+            read: Rc::clone(&read),
+        });
+        read_selection(
+            reader,
+            Rc::clone(&self.last_image_stamp),
+            self.record_targets,
+            Box::new(move |entry| read.finish(entry)),
+        );
+    }
 }
 
-#[cfg(target_os = "linux")]
+/// The requests of one [`GtkClipboardBackend::start_read`], each answered from the main loop.
+/// Nothing more is asked once the read has finished, and late replies are dropped.
+#[cfg(feature = "gtk")]
+struct GtkSelectionReader {
+    clipboard: gtk::Clipboard,
+    read: Rc<PendingRead>,
+}
+
+#[cfg(feature = "gtk")]
+impl GtkSelectionReader {
+    fn request<T: 'static>(
+        &self,
+        what: &'static str,
+        reply: Reply<T>,
+        issue: impl FnOnce(&gtk::Clipboard, Reply<T>),
+    ) {
+        if self.read.is_finished() {
+            return;
+        }
+        self.read.waiting_for.set(what);
+        issue(&self.clipboard, self.read.guard(reply));
+    }
+
+    fn request_target(&self, target: &'static str, reply: Reply<gtk::SelectionData>) {
+        self.request(target, reply, |clipboard, reply| {
+            clipboard.request_contents(&gtk::gdk::Atom::intern(target), move |_, selection| {
+                reply(Some(selection.clone()));
+            });
+        })
+    }
+}
+
+#[cfg(feature = "gtk")]
+impl SelectionReader for GtkSelectionReader {
+    fn targets(&self, reply: Reply<Vec<String>>) {
+        self.request_target(
+            "TARGETS",
+            Box::new(move |selection| {
+                reply(selection.map(|selection| {
+                    selection
+                        .targets()
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|atom| atom.name().to_string())
+                        .collect()
+                }))
+            }),
+        );
+    }
+
+    fn timestamp(&self, reply: Reply<u64>) {
+        self.request_target(
+            "TIMESTAMP",
+            Box::new(move |selection| {
+                // X11 delivers 32-bit properties as native `long`s, so the value may span 4 or
+                // 8 bytes.
+                let timestamp = selection.and_then(|selection| {
+                    let data = selection.data();
+                    match data.len() {
+                        8.. => Some(u64::from_ne_bytes(data[..8].try_into().ok()?)),
+                        4..=7 => Some(u32::from_ne_bytes(data[..4].try_into().ok()?) as u64),
+                        _ => None,
+                    }
+                });
+                reply(timestamp.filter(|&timestamp| timestamp != 0))
+            }),
+        );
+    }
+
+    fn text(&self, reply: Reply<String>) {
+        self.request("text", reply, |clipboard, reply| {
+            clipboard.request_text(move |_, text| reply(text.map(|text| text.to_string())));
+        })
+    }
+
+    fn image(&self, reply: Reply<ClipboardEntry>) {
+        self.request("image", reply, |clipboard, reply| {
+            clipboard.request_image(move |_, image| {
+                reply(image.and_then(image_entry_from_pixbuf));
+            });
+        })
+    }
+}
+
+#[cfg(feature = "gtk")]
 impl ClipboardBackend for GtkClipboardBackend {
     fn read_entry(&self) -> Option<ClipboardEntry> {
-        if let Some(text) = self.clipboard.wait_for_text() {
-            let value = text.to_string();
-            if !value.is_empty() {
-                return Some(ClipboardEntry::Text {
-                    value,
-                    source_window: None,
-                });
+        self.read_capture().map(|capture| capture.entry)
+    }
+
+    /// Takes what the last [`start_read`](Self::start_read) read. Without one waiting, as for
+    /// `slyboard capture` or the first read before `gtk::main`, it starts a read and runs the
+    /// main loop until that finishes; never call it that way from inside the main loop.
+    fn read_capture(&self) -> Option<Capture> {
+        if let Some(finished) = self.finished.take() {
+            return finished;
+        }
+        let done = Rc::new(Cell::new(false));
+        let flag = Rc::clone(&done);
+        self.start_read(move || flag.set(true));
+        let context = gtk::glib::MainContext::default();
+        while !done.get() {
+            // The read's deadline timer guarantees a wakeup.
+            context.iteration(true);
+        }
+        self.finished.take().flatten()
+    }
+
+    fn origin(&self) -> CaptureOrigin {
+        CaptureOrigin::GtkPoll
+    }
+
+    fn selection(&self) -> Selection {
+        self.selection
+    }
+
+    fn read_active_window(&self) -> Option<ActiveWindowContext> {
+        self.active_window_provider.capture()
+    }
+}
+
+/// Receives the answer to one selection request; `None` when the owner had nothing to give.
+#[cfg(any(feature = "gtk", test))]
+type Reply<T> = Box<dyn FnOnce(Option<T>)>;
+
+#[cfg(any(feature = "gtk", test))]
+/// The individual selection requests a polling backend is built from. Each answers through
+/// `reply`, possibly long after the call returned.
+trait SelectionReader {
+    fn targets(&self, reply: Reply<Vec<String>>);
+    /// When the owner acquired the selection; `None` if it doesn't say.
+    fn timestamp(&self, reply: Reply<u64>);
+    fn text(&self, reply: Reply<String>);
+    fn image(&self, reply: Reply<ClipboardEntry>);
+}
+
+#[cfg(any(feature = "gtk", test))]
+/// Identifies one image selection without transferring its pixels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SelectionStamp {
+    targets: Vec<String>,
+    timestamp: u64,
+}
+
+#[cfg(any(feature = "gtk", test))]
+/// Reads the current selection and hands it to `done`, skipping the image transfer when the
+/// owner reports the same targets and acquisition timestamp as the last image fetched. Owners
+/// that don't report a timestamp are re-read every time, as before. With `record_targets` the
+/// entry keeps the offered targets.
+fn read_selection<R: SelectionReader + 'static>(
+    reader: Rc<R>,
+    last_image_stamp: Rc<RefCell<Option<SelectionStamp>>>,
+    record_targets: bool,
+    done: Reply<ClipboardEntry>,
+) {
+    let requests = Rc::clone(&reader);
+    reader.targets(Box::new(move |targets| {
+        let Some(targets) = targets else {
+            return done(None);
+        };
+        let recorded = if record_targets {
+            targets.clone()
+        } else {
+            Vec::new()
+        };
+        let done: Reply<ClipboardEntry> =
+            Box::new(move |entry| done(entry.map(|entry| entry.with_offered_targets(recorded))));
+        match offered_content(&targets) {
+            None => done(None),
+            Some(OfferedContent::Text) => {
+                last_image_stamp.replace(None);
+                requests.text(Box::new(move |value| {
+                    done(
+                        value
+                            .filter(|value| !value.is_empty())
+                            .map(ClipboardEntry::text),
+                    )
+                }));
+            }
+            Some(OfferedContent::Image) => {
+                let image_requests = Rc::clone(&requests);
+                requests.timestamp(Box::new(move |timestamp| {
+                    let stamp = timestamp.map(|timestamp| SelectionStamp { targets, timestamp });
+                    if stamp.is_some() && *last_image_stamp.borrow() == stamp {
+                        return done(None);
+                    }
+                    image_requests.image(Box::new(move |image| {
+                        if image.is_some() {
+                            last_image_stamp.replace(stamp);
+                        }
+                        done(image)
+                    }));
+                }));
             }
         }
+    }));
+}
+
+#[cfg(any(feature = "gtk", test))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OfferedContent {
+    Text,
+    Image,
+}
+
+#[cfg(any(feature = "gtk", test))]
+/// Picks what to read from the owner's advertised targets, preferring text like the
+/// selection itself usually does, and never asking for an image when only text is offered.
+fn offered_content<S: AsRef<str>>(targets: &[S]) -> Option<OfferedContent> {
+    let targets = || targets.iter().map(|target| target.as_ref());
+    if targets().any(|target| TEXT_TARGETS.contains(&target) || target.starts_with("text/plain")) {
+        return Some(OfferedContent::Text);
+    }
+    targets()
+        .any(|target| target.starts_with("image/"))
+        .then_some(OfferedContent::Image)
+}
+
+#[cfg(any(feature = "gtk", test))]
+/// One read in flight. Whichever comes first, its last reply or its deadline, finishes it;
+/// anything after that is ignored.
+struct PendingRead {
+    done: RefCell<Option<Reply<ClipboardEntry>>>,
+    /// Removes the deadline timer when the replies beat it.
+    cancel_deadline: RefCell<Option<Box<dyn FnOnce()>>>,
+    /// The request the read is waiting on, for the warning when it times out.
+    waiting_for: Cell<&'static str>,
+}
+
+#[cfg(any(feature = "gtk", test))]
+impl PendingRead {
+    fn new(done: Reply<ClipboardEntry>) -> Self {
+        Self {
+            done: RefCell::new(Some(done)),
+            cancel_deadline: RefCell::new(None),
+            waiting_for: Cell::new("TARGETS"),
+        }
+    }
 
-        let image = self.clipboard.wait_for_image()?;
-        let pixel_bytes = image.pixel_bytes()?;
-        let pixels = pixel_bytes.as_ref().to_vec();
-        if pixels.is_empty() {
-            return None;
+    fn is_finished(&self) -> bool {
+        self.done.borrow().is_none()
+    }
+
+    fn finish(&self, entry: Option<ClipboardEntry>) {
+        let Some(done) = self.done.take() else {
+            return;
+        };
+        if let Some(cancel) = self.cancel_deadline.take() {
+            cancel();
+        }
+        done(entry);
+    }
+
+    /// Gives up on the owner after `timeout`.
+    fn expire(&self, timeout: Duration) {
+        if self.is_finished() {
+            return;
         }
+        crate::log_eprintln!(
+            "warning: clipboard owner did not answer a {} request within {}ms; \
+             skipping this poll",
+            self.waiting_for.get(),
+            timeout.as_millis()
+        );
+        self.finish(None);
+    }
 
-        Some(ClipboardEntry::Image {
-            width: image.width(),
-            height: image.height(),
-            rowstride: image.rowstride(),
-            has_alpha: image.has_alpha(),
-            bits_per_sample: image.bits_per_sample(),
-            channels: image.n_channels(),
-            pixels,
-            source_window: None,
+    /// Passes `reply` its answer only while the read is still going.
+    fn guard<T: 'static>(self: &Rc<Self>, reply: Reply<T>) -> Reply<T> {
+        let read = Rc::clone(self);
+        Box::new(move |value| {
+            if !read.is_finished() {
+                reply(value);
+            }
         })
     }
+}
 
-    fn read_active_window(&self) -> Option<ActiveWindowContext> {
-        self.active_window_provider.capture()
+/// Captures a pixbuf as a PNG-encoded entry. Pixbufs whose rows don't match their reported
+/// geometry are skipped rather than stored.
+#[cfg(feature = "gtk")]
+pub fn image_entry_from_pixbuf(image: &gtk::gdk_pixbuf::Pixbuf) -> Option<ClipboardEntry> {
+    if image.bits_per_sample() != 8 {
+        return None;
+    }
+    let pixel_bytes = image.pixel_bytes()?;
+    let data = ImageData::new(
+        image.width(),
+        image.height(),
+        image.n_channels(),
+        image.rowstride(),
+        pixel_bytes.as_ref().to_vec(),
+    )
+    .ok()?;
+    Some(ClipboardEntry::image(data))
+}
+
+/// Rebuilds a pixbuf for restoring an image entry, decoding PNG data if needed.
+#[cfg(feature = "gtk")]
+pub fn pixbuf_from_image_entry(entry: &ClipboardEntry) -> Result<gtk::gdk_pixbuf::Pixbuf> {
+    let ClipboardEntry::Image {
+        width,
+        height,
+        rowstride,
+        has_alpha,
+        bits_per_sample,
+        ..
+    } = entry
+    else {
+        bail!("text entries cannot be restored as images");
+    };
+    // GDK trusts these numbers when reading the buffer, so check them before handing it over.
+    entry.validate_image_geometry()?;
+
+    let bytes = gtk::glib::Bytes::from_owned(entry.image_pixels()?.into_owned());
+    Ok(gtk::gdk_pixbuf::Pixbuf::from_bytes(
+        &bytes,
+        gtk::gdk_pixbuf::Colorspace::Rgb,
+        *has_alpha,
+        *bits_per_sample,
+        *width,
+        *height,
+        *rowstride,
+    ))
+}
+
+/// Puts `entry` on `clipboard`, normalized while plain-text paste is on, and asks the session's
+/// clipboard manager to keep it after this process exits.
+#[cfg(feature = "gtk")]
+pub fn set_clipboard_entry(clipboard: &gtk::Clipboard, entry: &ClipboardEntry) -> Result<()> {
+    let entry = crate::core::plain_paste::entry_for_paste(entry);
+    match entry.as_ref() {
+        ClipboardEntry::Text { value, .. } => clipboard.set_text(value),
+        ClipboardEntry::Image { .. } => clipboard.set_image(&pixbuf_from_image_entry(&entry)?),
+    }
+    clipboard.store();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        offered_content, read_selection, OfferedContent, PendingRead, Reply, SelectionReader,
+        SelectionStamp,
+    };
+    use crate::clipboard::{ClipboardEntry, ImageData};
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    #[test]
+    fn prefers_text_targets_and_skips_unknown_offers() {
+        assert_eq!(
+            offered_content(&["TARGETS", "image/png", "UTF8_STRING"]),
+            Some(OfferedContent::Text)
+        );
+        assert_eq!(
+            offered_content(&["text/plain;charset=utf-8"]),
+            Some(OfferedContent::Text)
+        );
+        assert_eq!(
+            offered_content(&["TARGETS", "image/png"]),
+            Some(OfferedContent::Image)
+        );
+        assert_eq!(
+            offered_content(&["TARGETS", "x-special/gnome-copied-files"]),
+            None
+        );
+        assert_eq!(offered_content::<&str>(&[]), None);
+    }
+
+    /// Selection owner stub that counts how often pixels are transferred.
+    struct CountingReader {
+        targets: RefCell<Vec<String>>,
+        timestamp: Cell<Option<u64>>,
+        image_reads: Cell<usize>,
+    }
+
+    impl CountingReader {
+        fn offering_image(timestamp: Option<u64>) -> Self {
+            Self {
+                targets: RefCell::new(vec!["TARGETS".to_string(), "image/png".to_string()]),
+                timestamp: Cell::new(timestamp),
+                image_reads: Cell::new(0),
+            }
+        }
+    }
+
+    impl SelectionReader for CountingReader {
+        fn targets(&self, reply: Reply<Vec<String>>) {
+            reply(Some(self.targets.borrow().clone()))
+        }
+
+        fn timestamp(&self, reply: Reply<u64>) {
+            reply(self.timestamp.get())
+        }
+
+        fn text(&self, reply: Reply<String>) {
+            reply(Some("copied text".to_string()))
+        }
+
+        fn image(&self, reply: Reply<ClipboardEntry>) {
+            self.image_reads.set(self.image_reads.get() + 1);
+            reply(Some(ClipboardEntry::image(
+                ImageData::new(1, 1, 4, 4, vec![0; 4]).unwrap(),
+            )))
+        }
+    }
+
+    type Stamp = Rc<RefCell<Option<SelectionStamp>>>;
+
+    /// Runs [`read_selection`] against a reader that answers straight away.
+    fn read(
+        reader: &Rc<CountingReader>,
+        stamp: &Stamp,
+        record_targets: bool,
+    ) -> Option<ClipboardEntry> {
+        let result = Rc::new(RefCell::new(None));
+        let answer = Rc::clone(&result);
+        read_selection(
+            Rc::clone(reader),
+            Rc::clone(stamp),
+            record_targets,
+            Box::new(move |entry| *answer.borrow_mut() = Some(entry)),
+        );
+        result.take().expect("the read finished")
+    }
+
+    #[test]
+    fn unchanged_image_selection_is_not_transferred_again() {
+        let reader = Rc::new(CountingReader::offering_image(Some(1000)));
+        let stamp = Stamp::default();
+
+        assert!(read(&reader, &stamp, false).is_some());
+        for _ in 0..5 {
+            assert!(read(&reader, &stamp, false).is_none());
+        }
+        assert_eq!(reader.image_reads.get(), 1);
+
+        reader.timestamp.set(Some(2000));
+        assert!(read(&reader, &stamp, false).is_some());
+        assert_eq!(reader.image_reads.get(), 2);
+
+        reader.targets.borrow_mut().push("text/plain".to_string());
+        assert!(matches!(
+            read(&reader, &stamp, false),
+            Some(ClipboardEntry::Text { .. })
+        ));
+        reader.targets.borrow_mut().pop();
+        assert!(read(&reader, &stamp, false).is_some());
+        assert_eq!(
+            reader.image_reads.get(),
+            3,
+            "text in between resets the stamp"
+        );
+    }
+
+    #[test]
+    fn owners_without_a_timestamp_are_always_read() {
+        let reader = Rc::new(CountingReader::offering_image(None));
+        let stamp = Stamp::default();
+        for _ in 0..3 {
+            assert!(read(&reader, &stamp, false).is_some());
+        }
+        assert_eq!(reader.image_reads.get(), 3);
+    }
+
+    #[test]
+    fn records_offered_targets_only_when_asked() {
+        let reader = Rc::new(CountingReader::offering_image(None));
+        reader.targets.borrow_mut().push("text/html".to_string());
+        let stamp = Stamp::default();
+
+        let entry = read(&reader, &stamp, false).expect("image");
+        assert!(entry.offered_targets().is_empty());
+
+        let entry = read(&reader, &stamp, true).expect("image");
+        assert_eq!(
+            entry.offered_targets(),
+            ["TARGETS", "image/png", "text/html"]
+        );
+        reader.targets.borrow_mut().push("UTF8_STRING".to_string());
+        let entry = read(&reader, &stamp, true).expect("text");
+        assert!(matches!(&entry, ClipboardEntry::Text { value, .. } if value == "copied text"));
+        assert_eq!(entry.offered_targets().len(), 4);
+    }
+
+    #[test]
+    fn a_read_finishes_once_and_drops_late_replies() {
+        let finished = Rc::new(Cell::new(0));
+        let count = Rc::clone(&finished);
+        let read = Rc::new(PendingRead::new(Box::new(move |entry| {
+            assert!(entry.is_none());
+            count.set(count.get() + 1);
+        })));
+        let late = Rc::new(Cell::new(false));
+        let seen = Rc::clone(&late);
+        let reply: Reply<String> = read.guard(Box::new(move |_| seen.set(true)));
+
+        read.waiting_for.set("text");
+        read.expire(Duration::from_millis(500));
+        assert!(read.is_finished());
+        read.finish(None);
+        read.expire(Duration::from_millis(500));
+        assert_eq!(finished.get(), 1);
+
+        reply(Some("too late".to_string()));
+        assert!(!late.get());
+    }
+
+    #[test]
+    fn replies_in_time_cancel_the_deadline() {
+        let read = Rc::new(PendingRead::new(Box::new(|entry| assert!(entry.is_some()))));
+        let cancelled = Rc::new(Cell::new(false));
+        let cancel = Rc::clone(&cancelled);
+        read.cancel_deadline
+            .replace(Some(Box::new(move || cancel.set(true))));
+        let reply: Reply<ClipboardEntry> = {
+            let read = Rc::clone(&read);
+            read.clone()
+                .guard(Box::new(move |entry| read.finish(entry)))
+        };
+        reply(Some(ClipboardEntry::text("copied")));
+        assert!(read.is_finished() && cancelled.get());
     }
 }
@@ -0,0 +1,125 @@
+use glob::Pattern;
+
+use crate::clipboard::filters::parse_glob;
+use crate::config::WindowMatchMode;
+use crate::core::active_window::ActiveWindowContext;
+
+/// One `clipboard.active_window` blacklist or transient value, compared case-insensitively
+/// with the focused window's app id and title according to its [`WindowMatchMode`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlacklistRule {
+    pattern: String,
+    mode: WindowMatchMode,
+    /// `pattern` compiled, in [`WindowMatchMode::Glob`].
+    glob: Option<Pattern>,
+}
+
+impl BlacklistRule {
+    pub fn parse(value: &str, mode: WindowMatchMode) -> Result<Self, String> {
+        let pattern = value.trim().to_lowercase();
+        if pattern.is_empty() {
+            return Err("cannot be empty".to_string());
+        }
+        let glob = match mode {
+            WindowMatchMode::Glob => Some(parse_glob(&pattern)?),
+            _ => None,
+        };
+        Ok(Self {
+            pattern,
+            mode,
+            glob,
+        })
+    }
+
+    /// The value as it is matched: trimmed and lower-cased.
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn matches(&self, window: &ActiveWindowContext) -> bool {
+        let app_id = window
+            .app_id
+            .as_deref()
+            .map(|value| value.trim().to_lowercase());
+        let title = window.title.trim().to_lowercase();
+        let pattern = self.pattern.as_str();
+        let any = |test: &dyn Fn(&str) -> bool| app_id.as_deref().is_some_and(test) || test(&title);
+        match self.mode {
+            WindowMatchMode::Mixed => app_id.as_deref() == Some(pattern) || title.contains(pattern),
+            WindowMatchMode::Exact => any(&|value| value == pattern),
+            WindowMatchMode::Substring => any(&|value| value.contains(pattern)),
+            WindowMatchMode::Glob => {
+                let glob = self
+                    .glob
+                    .as_ref()
+                    .expect("glob rules are compiled on parse");
+                any(&|value| glob.matches(value))
+            }
+        }
+    }
+}
+
+/// The first of `rules` matching `window`. Both capture and `slyboard prune --blacklist` decide
+/// through this, so a stored entry is pruned exactly when it would no longer be captured.
+pub fn first_matching_rule<'a>(
+    window: Option<&ActiveWindowContext>,
+    rules: &'a [BlacklistRule],
+) -> Option<&'a BlacklistRule> {
+    let window = window?;
+    rules.iter().find(|rule| rule.matches(window))
+}
+
+pub fn window_matches_any(window: Option<&ActiveWindowContext>, rules: &[BlacklistRule]) -> bool {
+    first_matching_rule(window, rules).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlacklistRule;
+    use crate::config::WindowMatchMode;
+    use crate::core::active_window::ActiveWindowContext;
+    use crate::testing::window;
+
+    fn matches(value: &str, mode: WindowMatchMode, window: &ActiveWindowContext) -> bool {
+        BlacklistRule::parse(value, mode).unwrap().matches(window)
+    }
+
+    #[test]
+    fn applies_each_mode_to_app_id_and_title() {
+        let browser = window(Some("firefox"), "Firefox Privacy Notice — Mozilla Firefox");
+        let chat = window(Some("org.gnome.Fractal"), "firefox release notes");
+
+        assert!(matches("Firefox", WindowMatchMode::Mixed, &browser));
+        assert!(matches("firefox", WindowMatchMode::Mixed, &chat));
+        assert!(!matches("gnome", WindowMatchMode::Mixed, &chat));
+
+        assert!(matches("firefox", WindowMatchMode::Exact, &browser));
+        assert!(!matches("firefox", WindowMatchMode::Exact, &chat));
+        assert!(matches(
+            "firefox release notes",
+            WindowMatchMode::Exact,
+            &chat
+        ));
+
+        assert!(matches("gnome", WindowMatchMode::Substring, &chat));
+        assert!(!matches("keepass", WindowMatchMode::Substring, &chat));
+
+        assert!(matches("org.gnome.*", WindowMatchMode::Glob, &chat));
+        assert!(matches(
+            "* — mozilla firefox",
+            WindowMatchMode::Glob,
+            &browser
+        ));
+        assert!(!matches("fire", WindowMatchMode::Glob, &browser));
+    }
+
+    #[test]
+    fn rejects_blank_values_and_invalid_globs() {
+        assert_eq!(
+            BlacklistRule::parse("  ", WindowMatchMode::Exact),
+            Err("cannot be empty".to_string())
+        );
+        assert!(BlacklistRule::parse("[keepass", WindowMatchMode::Glob).is_err());
+        assert!(BlacklistRule::parse("[keepass", WindowMatchMode::Substring).is_ok());
+    }
+}
@@ -0,0 +1,323 @@
+//! Encrypted export bundles (`slyboard export --encrypt`): a history export sealed with a
+//! passphrase, so it can travel through cloud drives and the like on its way to another
+//! machine. This is an interchange format; history on disk stays as it is.
+//!
+//! A bundle is the magic bytes `SLYB`, a format version byte, the Argon2id costs (memory in
+//! KiB, iterations, lanes; little-endian `u32`s), a 16-byte salt and a 24-byte nonce, then the
+//! export JSON encrypted with XChaCha20-Poly1305 under the key Argon2id derives from the
+//! passphrase. The header is authenticated along with the contents, so neither can be changed
+//! without the passphrase.
+
+use std::io;
+use std::path::Path;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use zeroize::Zeroizing;
+
+use crate::clipboard::transfer::parse_export;
+use crate::clipboard::ClipboardEntry;
+use crate::error::{Error, Result};
+
+pub const MAGIC: &[u8; 4] = b"SLYB";
+const FORMAT_VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + 1 + 12 + SALT_LEN + NONCE_LEN;
+const TAG_LEN: usize = 16;
+/// The Argon2id costs OWASP recommends: 19 MiB, two passes, one lane.
+const DEFAULT_COST: KdfCost = KdfCost {
+    memory_kib: 19 * 1024,
+    iterations: 2,
+    lanes: 1,
+};
+/// Bundles asking for more memory than this (1 GiB) are refused rather than obeyed.
+const MAX_MEMORY_KIB: u32 = 1024 * 1024;
+/// Likewise for passes, which the header could otherwise set high enough to hang an import
+/// before the passphrase is ever checked.
+const MAX_ITERATIONS: u32 = 16;
+const MAX_LANES: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct KdfCost {
+    memory_kib: u32,
+    iterations: u32,
+    lanes: u32,
+}
+
+/// Why a bundle didn't open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpenError {
+    /// Not a bundle this version can read.
+    Malformed(String),
+    /// Decryption failed: the passphrase is wrong or the bundle was changed.
+    Passphrase,
+}
+
+/// Whether `bytes` start like a bundle rather than a JSON export.
+pub fn is_bundle(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// The passphrase in a `--passphrase-file`: its first line, without the line ending, or
+/// `None` when that is empty.
+pub fn passphrase_line(contents: &str) -> Option<&str> {
+    contents.lines().next().filter(|line| !line.is_empty())
+}
+
+/// Encrypts `export` under `passphrase` with a fresh salt and nonce.
+pub fn seal(export: &[u8], passphrase: &str) -> io::Result<Vec<u8>> {
+    let mut salt = [0; SALT_LEN];
+    let mut nonce = [0; NONCE_LEN];
+    getrandom::getrandom(&mut salt)?;
+    getrandom::getrandom(&mut nonce)?;
+    seal_with(export, passphrase, DEFAULT_COST, salt, nonce)
+}
+
+fn seal_with(
+    export: &[u8],
+    passphrase: &str,
+    cost: KdfCost,
+    salt: [u8; SALT_LEN],
+    nonce: [u8; NONCE_LEN],
+) -> io::Result<Vec<u8>> {
+    let mut bundle = Vec::with_capacity(HEADER_LEN + export.len() + TAG_LEN);
+    bundle.extend_from_slice(MAGIC);
+    bundle.push(FORMAT_VERSION);
+    for value in [cost.memory_kib, cost.iterations, cost.lanes] {
+        bundle.extend_from_slice(&value.to_le_bytes());
+    }
+    bundle.extend_from_slice(&salt);
+    bundle.extend_from_slice(&nonce);
+
+    let key =
+        derive_key(passphrase, &salt, cost).map_err(|err| io::Error::other(err.to_string()))?;
+    let sealed = XChaCha20Poly1305::new(Key::from_slice(&*key))
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: export,
+                aad: &bundle,
+            },
+        )
+        .map_err(|_| io::Error::other("failed to encrypt the export"))?;
+    bundle.extend_from_slice(&sealed);
+    Ok(bundle)
+}
+
+/// The export JSON inside `bundle`.
+pub fn open(bundle: &[u8], passphrase: &str) -> std::result::Result<Vec<u8>, OpenError> {
+    if !is_bundle(bundle) {
+        return Err(OpenError::Malformed("not an export bundle".to_string()));
+    }
+    if bundle.len() < HEADER_LEN + TAG_LEN {
+        return Err(OpenError::Malformed("the bundle is truncated".to_string()));
+    }
+    let (header, sealed) = bundle.split_at(HEADER_LEN);
+    let version = header[MAGIC.len()];
+    if version != FORMAT_VERSION {
+        return Err(OpenError::Malformed(format!(
+            "bundle format version {version} is not supported by this slyboard"
+        )));
+    }
+    let field = |index: usize| {
+        let start = MAGIC.len() + 1 + index * 4;
+        u32::from_le_bytes(header[start..start + 4].try_into().expect("four bytes"))
+    };
+    let cost = KdfCost {
+        memory_kib: field(0),
+        iterations: field(1),
+        lanes: field(2),
+    };
+    if cost.memory_kib > MAX_MEMORY_KIB {
+        return Err(OpenError::Malformed(format!(
+            "the bundle asks for {} MiB to derive its key; at most {} MiB is allowed",
+            cost.memory_kib / 1024,
+            MAX_MEMORY_KIB / 1024
+        )));
+    }
+    if cost.iterations > MAX_ITERATIONS {
+        return Err(OpenError::Malformed(format!(
+            "the bundle asks for {} key derivation passes; at most {MAX_ITERATIONS} are allowed",
+            cost.iterations
+        )));
+    }
+    if cost.lanes > MAX_LANES {
+        return Err(OpenError::Malformed(format!(
+            "the bundle asks for {} key derivation lanes; at most {MAX_LANES} are allowed",
+            cost.lanes
+        )));
+    }
+    let salt = &header[MAGIC.len() + 13..MAGIC.len() + 13 + SALT_LEN];
+    let nonce = &header[HEADER_LEN - NONCE_LEN..];
+
+    let key = derive_key(passphrase, salt, cost)
+        .map_err(|err| OpenError::Malformed(format!("invalid key derivation costs: {err}")))?;
+    XChaCha20Poly1305::new(Key::from_slice(&*key))
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: sealed,
+                aad: header,
+            },
+        )
+        .map_err(|_| OpenError::Passphrase)
+}
+
+/// The entries of the bundle read from `path`, newest first.
+pub fn open_export(bundle: &[u8], passphrase: &str, path: &Path) -> Result<Vec<ClipboardEntry>> {
+    let corrupt = |reason: String| Error::ExportCorrupt {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let export = open(bundle, passphrase).map_err(|err| match err {
+        OpenError::Malformed(reason) => corrupt(reason),
+        OpenError::Passphrase => Error::BundlePassphrase {
+            path: path.to_path_buf(),
+        },
+    })?;
+    let export = String::from_utf8(export).map_err(|err| corrupt(err.to_string()))?;
+    parse_export(&export).map_err(corrupt)
+}
+
+/// The key, wiped from memory once dropped.
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    cost: KdfCost,
+) -> std::result::Result<Zeroizing<[u8; 32]>, argon2::Error> {
+    let params = Params::new(cost.memory_kib, cost.iterations, cost.lanes, Some(32))?;
+    let mut key = Zeroizing::new([0; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params).hash_password_into(
+        passphrase.as_bytes(),
+        salt,
+        &mut *key,
+    )?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        is_bundle, open, open_export, passphrase_line, seal, seal_with, KdfCost, OpenError,
+        HEADER_LEN, MAGIC,
+    };
+    use crate::clipboard::transfer::write_export;
+    use crate::error::Error;
+    use crate::testing::{png_image, text};
+    use std::path::Path;
+
+    /// Argon2's minimum costs, so tests don't spend seconds deriving keys.
+    const CHEAP: KdfCost = KdfCost {
+        memory_kib: 8,
+        iterations: 1,
+        lanes: 1,
+    };
+
+    fn cheap_seal(export: &[u8], passphrase: &str) -> Vec<u8> {
+        seal_with(export, passphrase, CHEAP, [7; 16], [9; 24]).unwrap()
+    }
+
+    #[test]
+    fn round_trips_an_export_with_images() {
+        let entries = [text("ssh prod"), png_image(2, 1, [255, 0, 0, 255])];
+        let mut export = Vec::new();
+        write_export(&mut export, &entries).unwrap();
+
+        let bundle = cheap_seal(&export, "correct horse");
+        assert!(is_bundle(&bundle) && !is_bundle(&export));
+        assert!(!bundle.windows(8).any(|window| window == b"ssh prod"));
+        assert_eq!(open(&bundle, "correct horse").unwrap(), export);
+
+        let opened = open_export(&bundle, "correct horse", Path::new("b.slyb")).unwrap();
+        assert_eq!(opened, entries);
+    }
+
+    #[test]
+    fn seals_with_a_fresh_salt_and_nonce() {
+        let (first, second) = (seal(b"[]", "pw").unwrap(), seal(b"[]", "pw").unwrap());
+        assert_ne!(first[..HEADER_LEN], second[..HEADER_LEN]);
+        assert_eq!(open(&first, "pw").unwrap(), b"[]");
+    }
+
+    #[test]
+    fn wrong_passphrase_or_changed_bytes_fail_to_open() {
+        let bundle = cheap_seal(b"[]", "correct horse");
+        assert_eq!(open(&bundle, "battery staple"), Err(OpenError::Passphrase));
+
+        // Every header byte after the version is authenticated, as is the ciphertext.
+        for index in [
+            MAGIC.len() + 1,
+            MAGIC.len() + 13,
+            HEADER_LEN - 1,
+            HEADER_LEN,
+        ] {
+            let mut changed = bundle.clone();
+            changed[index] ^= 1;
+            assert_eq!(
+                open(&changed, "correct horse"),
+                Err(OpenError::Passphrase),
+                "byte {index}"
+            );
+        }
+
+        let err = open_export(&bundle, "battery staple", Path::new("b.slyb")).unwrap_err();
+        assert!(matches!(err, Error::BundlePassphrase { .. }));
+        assert_eq!(
+            err.to_string(),
+            "cannot decrypt b.slyb: wrong passphrase, or the bundle is damaged"
+        );
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_read() {
+        let bundle = cheap_seal(b"[]", "pw");
+        let malformed = |bytes: &[u8]| match open(bytes, "pw") {
+            Err(OpenError::Malformed(reason)) => reason,
+            other => panic!("expected malformed, got {other:?}"),
+        };
+        assert_eq!(malformed(b"[]"), "not an export bundle");
+        assert_eq!(malformed(&bundle[..HEADER_LEN]), "the bundle is truncated");
+
+        let mut newer = bundle.clone();
+        newer[MAGIC.len()] = 2;
+        assert_eq!(
+            malformed(&newer),
+            "bundle format version 2 is not supported by this slyboard"
+        );
+
+        let mut greedy = bundle.clone();
+        greedy[MAGIC.len() + 1..MAGIC.len() + 5].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(malformed(&greedy).starts_with("the bundle asks for"));
+
+        // Passes and lanes are refused before any key is derived, so this returns at once.
+        let mut slow = bundle.clone();
+        slow[MAGIC.len() + 5..MAGIC.len() + 9].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            malformed(&slow),
+            "the bundle asks for 4294967295 key derivation passes; at most 16 are allowed"
+        );
+        let mut wide = bundle.clone();
+        wide[MAGIC.len() + 9..MAGIC.len() + 13].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            malformed(&wide),
+            "the bundle asks for 4294967295 key derivation lanes; at most 16 are allowed"
+        );
+
+        let not_json = cheap_seal(b"not json", "pw");
+        let err = open_export(&not_json, "pw", Path::new("b.slyb")).unwrap_err();
+        assert!(matches!(err, Error::ExportCorrupt { .. }), "{err}");
+    }
+
+    #[test]
+    fn reads_the_first_line_of_a_passphrase_file() {
+        assert_eq!(passphrase_line("hunter2\n"), Some("hunter2"));
+        assert_eq!(
+            passphrase_line(" two words \r\nignored"),
+            Some(" two words ")
+        );
+        assert_eq!(passphrase_line("\nhunter2"), None);
+        assert_eq!(passphrase_line(""), None);
+    }
+}
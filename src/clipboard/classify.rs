@@ -0,0 +1,234 @@
+use std::fmt;
+
+/// Coarse content type of a text entry, detected heuristically at capture time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentHint {
+    Url,
+    Json,
+    Email,
+    Code,
+    HexColor,
+    Path,
+    Plain,
+}
+
+impl ContentHint {
+    pub const ALL: [ContentHint; 7] = [
+        ContentHint::Url,
+        ContentHint::Json,
+        ContentHint::Email,
+        ContentHint::Code,
+        ContentHint::HexColor,
+        ContentHint::Path,
+        ContentHint::Plain,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ContentHint::Url => "url",
+            ContentHint::Json => "json",
+            ContentHint::Email => "email",
+            ContentHint::Code => "code",
+            ContentHint::HexColor => "hex-color",
+            ContentHint::Path => "path",
+            ContentHint::Plain => "plain",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|hint| hint.as_str().eq_ignore_ascii_case(value.trim()))
+    }
+}
+
+impl fmt::Display for ContentHint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+const URL_SCHEMES: [&str; 6] = [
+    "http://", "https://", "ftp://", "file://", "mailto:", "ssh://",
+];
+const CODE_KEYWORDS: [&str; 14] = [
+    "fn ",
+    "def ",
+    "function ",
+    "class ",
+    "import ",
+    "#include",
+    "let ",
+    "const ",
+    "var ",
+    "pub ",
+    "return ",
+    "impl ",
+    "struct ",
+    "package ",
+];
+
+/// Classifies text by sniffing its shape; checks run from most to least specific.
+pub fn classify_text(value: &str) -> ContentHint {
+    let trimmed = value.trim();
+    if trimmed.is_empty() {
+        return ContentHint::Plain;
+    }
+    if is_hex_color(trimmed) {
+        ContentHint::HexColor
+    } else if is_url(trimmed) {
+        ContentHint::Url
+    } else if is_email(trimmed) {
+        ContentHint::Email
+    } else if is_json(trimmed) {
+        ContentHint::Json
+    } else if is_path(trimmed) {
+        ContentHint::Path
+    } else if looks_like_code(trimmed) {
+        ContentHint::Code
+    } else {
+        ContentHint::Plain
+    }
+}
+
+fn is_hex_color(value: &str) -> bool {
+    let Some(digits) = value.strip_prefix('#') else {
+        return false;
+    };
+    matches!(digits.len(), 3 | 4 | 6 | 8) && digits.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn is_url(value: &str) -> bool {
+    if value.contains(char::is_whitespace) {
+        return false;
+    }
+    let lower = value.to_ascii_lowercase();
+    URL_SCHEMES
+        .iter()
+        .any(|scheme| lower.starts_with(scheme) && lower.len() > scheme.len())
+        || (lower.starts_with("www.") && lower[4..].contains('.'))
+}
+
+fn is_email(value: &str) -> bool {
+    if value.contains(char::is_whitespace) {
+        return false;
+    }
+    let Some((local, domain)) = value.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !domain.contains('@')
+        && domain
+            .split_once('.')
+            .is_some_and(|(host, tld)| !host.is_empty() && !tld.is_empty())
+        && !domain.ends_with('.')
+}
+
+fn is_json(value: &str) -> bool {
+    let structured = (value.starts_with('{') && value.ends_with('}'))
+        || (value.starts_with('[') && value.ends_with(']'));
+    structured && serde_json::from_str::<serde_json::Value>(value).is_ok()
+}
+
+fn is_path(value: &str) -> bool {
+    if value.contains('\n') || value.contains("://") {
+        return false;
+    }
+    let rooted = value.starts_with('/') || value.starts_with("~/") || value.starts_with("./");
+    let windows_drive = value.len() > 2
+        && value.as_bytes()[0].is_ascii_alphabetic()
+        && value[1..].starts_with(":\\");
+    (rooted && value.len() > 1 && !value.contains("  ")) || windows_drive
+}
+
+fn looks_like_code(value: &str) -> bool {
+    let lines: Vec<&str> = value.lines().map(str::trim).collect();
+    let keyword_lines = lines
+        .iter()
+        .filter(|line| {
+            CODE_KEYWORDS
+                .iter()
+                .any(|keyword| line.starts_with(keyword))
+        })
+        .count();
+    let punctuated_lines = lines
+        .iter()
+        .filter(|line| line.ends_with(';') || line.ends_with('{') || line.ends_with('}'))
+        .count();
+
+    if lines.len() == 1 {
+        return keyword_lines == 1 && punctuated_lines == 1;
+    }
+    keyword_lines + punctuated_lines >= lines.len().div_ceil(2).max(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{classify_text, ContentHint};
+
+    #[test]
+    fn detects_urls() {
+        assert_eq!(classify_text("https://example.com/a?b=c"), ContentHint::Url);
+        assert_eq!(classify_text("  www.example.org\n"), ContentHint::Url);
+        assert_eq!(classify_text("https://"), ContentHint::Plain);
+        assert_eq!(classify_text("see https://example.com"), ContentHint::Plain);
+    }
+
+    #[test]
+    fn detects_json_only_when_it_parses() {
+        assert_eq!(classify_text(r#"{"a": [1, 2]}"#), ContentHint::Json);
+        assert_eq!(classify_text("[1, 2, 3]"), ContentHint::Json);
+        assert_eq!(classify_text("{not json}"), ContentHint::Plain);
+        assert_eq!(classify_text("use {braces} in text"), ContentHint::Plain);
+        assert_eq!(classify_text("\"just a string\""), ContentHint::Plain);
+    }
+
+    #[test]
+    fn detects_emails() {
+        assert_eq!(classify_text("someone@example.com"), ContentHint::Email);
+        assert_eq!(classify_text("a@b@example.com"), ContentHint::Plain);
+        assert_eq!(classify_text("someone@localhost"), ContentHint::Plain);
+        assert_eq!(
+            classify_text("mail someone@example.com"),
+            ContentHint::Plain
+        );
+    }
+
+    #[test]
+    fn detects_hex_colors() {
+        assert_eq!(classify_text("#fff"), ContentHint::HexColor);
+        assert_eq!(classify_text("#1e1e2eff"), ContentHint::HexColor);
+        assert_eq!(classify_text("#12345"), ContentHint::Plain);
+        assert_eq!(classify_text("#ggg"), ContentHint::Plain);
+    }
+
+    #[test]
+    fn detects_paths() {
+        assert_eq!(classify_text("/etc/hosts"), ContentHint::Path);
+        assert_eq!(classify_text("~/.config/slyboard"), ContentHint::Path);
+        assert_eq!(classify_text("C:\\Users\\me"), ContentHint::Path);
+        assert_eq!(classify_text("/"), ContentHint::Plain);
+        assert_eq!(classify_text("/etc/hosts\n/etc/passwd"), ContentHint::Plain);
+    }
+
+    #[test]
+    fn detects_code() {
+        assert_eq!(
+            classify_text("fn main() {\n    println!(\"hi\");\n}"),
+            ContentHint::Code
+        );
+        assert_eq!(classify_text("let x = 1;"), ContentHint::Code);
+        assert_eq!(classify_text("let me know"), ContentHint::Plain);
+        assert_eq!(
+            classify_text("Shopping list:\nmilk\neggs\nbread"),
+            ContentHint::Plain
+        );
+    }
+
+    #[test]
+    fn parses_hint_names() {
+        assert_eq!(ContentHint::parse("URL"), Some(ContentHint::Url));
+        assert_eq!(ContentHint::parse("hex-color"), Some(ContentHint::HexColor));
+        assert_eq!(ContentHint::parse("binary"), None);
+    }
+}
@@ -0,0 +1,370 @@
+//! `backend: wayland` without `wl-paste`: a client of the compositor's `zwlr_data_control_v1`
+//! protocol, which announces every new selection as it is taken, whichever window has focus.
+//! Built with the `wayland` feature.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::wl_registry::{self, WlRegistry};
+use wayland_client::protocol::wl_seat::{self, WlSeat};
+use wayland_client::{event_created_child, Connection, Dispatch, EventQueue, Proxy, QueueHandle};
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_device_v1::{
+    self, ZwlrDataControlDeviceV1,
+};
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_manager_v1::{
+    self, ZwlrDataControlManagerV1,
+};
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_offer_v1::{
+    self, ZwlrDataControlOfferV1,
+};
+
+use crate::clipboard::wayland::{entry_from_offer, SelectionEventSource};
+use crate::clipboard::writer::Selection;
+use crate::clipboard::ClipboardEntry;
+use crate::config::DEFAULT_MAX_ENTRY_BYTES;
+
+/// How long the selection owner gets to send what it offered.
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The MIME types an offer announced, kept as its user data.
+type OfferedTypes = Mutex<Vec<String>>;
+
+/// Selection events from a data-control device on the compositor's first seat.
+pub struct DataControlSource {
+    connection: Connection,
+    queue: EventQueue<DeviceState>,
+    state: DeviceState,
+    record_targets: bool,
+    max_entry_bytes: u64,
+    _device: ZwlrDataControlDeviceV1,
+}
+
+struct DeviceState {
+    selection: Selection,
+    /// The offer for the watched selection, `None` once it was cleared.
+    offer: Option<ZwlrDataControlOfferV1>,
+    changed: bool,
+    finished: bool,
+}
+
+impl DataControlSource {
+    /// Connects to the compositor in `$WAYLAND_DISPLAY` and watches `selection`. The primary
+    /// selection needs version 2 of the protocol.
+    pub fn connect(selection: Selection) -> Result<Self> {
+        let connection =
+            Connection::connect_to_env().context("failed to connect to the Wayland compositor")?;
+        let (globals, mut queue) = registry_queue_init::<DeviceState>(&connection)
+            .context("failed to list the Wayland compositor's globals")?;
+        let handle = queue.handle();
+        let versions = match selection {
+            Selection::Clipboard => 1..=2,
+            Selection::Primary => 2..=2,
+        };
+        let manager: ZwlrDataControlManagerV1 =
+            globals.bind(&handle, versions, ()).with_context(|| {
+                format!(
+                    "the compositor does not support the wlr data-control protocol for the {} \
+                     selection",
+                    selection.as_str()
+                )
+            })?;
+        let seat: WlSeat = globals
+            .bind(&handle, 1..=1, ())
+            .context("the Wayland compositor has no seat")?;
+        let device = manager.get_data_device(&seat, &handle, ());
+
+        let mut state = DeviceState {
+            selection,
+            offer: None,
+            changed: false,
+            finished: false,
+        };
+        // The compositor answers the new device with the current selection.
+        queue
+            .roundtrip(&mut state)
+            .context("failed to read the current selection")?;
+        Ok(Self {
+            connection,
+            queue,
+            state,
+            record_targets: false,
+            max_entry_bytes: DEFAULT_MAX_ENTRY_BYTES,
+            _device: device,
+        })
+    }
+
+    /// See [`WlPasteSelectionSource::with_recorded_targets`](super::wayland::WlPasteSelectionSource::with_recorded_targets).
+    pub fn with_recorded_targets(mut self, record_targets: bool) -> Self {
+        self.record_targets = record_targets;
+        self
+    }
+
+    /// Gives up on copies larger than `max_entry_bytes` (`clipboard.max_entry_bytes`), so a
+    /// selection owner can't make the daemon buffer without end.
+    pub fn with_max_entry_bytes(mut self, max_entry_bytes: u64) -> Self {
+        self.max_entry_bytes = max_entry_bytes;
+        self
+    }
+
+    /// Asks the selection owner for `offer` as `mime_type` and reads what it writes.
+    fn receive(&self, offer: &ZwlrDataControlOfferV1, mime_type: &str) -> Option<Vec<u8>> {
+        let (read, write) = pipe().ok()?;
+        offer.receive(mime_type.to_string(), write.as_fd());
+        self.connection.flush().ok()?;
+        // Our end has to close for the reader to see the owner's end close.
+        drop(write);
+        match read_until_closed(read, TRANSFER_TIMEOUT, self.max_entry_bytes) {
+            Ok(contents) => Some(contents),
+            Err(err) => {
+                crate::log_eprintln!("warning: failed to read the selection as {mime_type}: {err}");
+                None
+            }
+        }
+    }
+}
+
+impl SelectionEventSource for DataControlSource {
+    fn wait_for_change(&mut self) -> bool {
+        loop {
+            if self.state.finished {
+                return false;
+            }
+            if std::mem::take(&mut self.state.changed) {
+                return true;
+            }
+            if let Err(err) = self.queue.blocking_dispatch(&mut self.state) {
+                crate::log_eprintln!("warning: lost the Wayland connection: {err}");
+                return false;
+            }
+        }
+    }
+
+    fn read_selection(&mut self) -> Option<ClipboardEntry> {
+        let offer = self.state.offer.clone()?;
+        let offered = offer.data::<OfferedTypes>()?.lock().ok()?.clone();
+        entry_from_offer(
+            &offered,
+            |mime_type| self.receive(&offer, mime_type),
+            self.record_targets,
+        )
+    }
+}
+
+impl DeviceState {
+    /// Takes `offer` as the new selection when it is for the watched one.
+    fn offered(&mut self, selection: Selection, offer: Option<ZwlrDataControlOfferV1>) {
+        if selection != self.selection {
+            if let Some(offer) = offer {
+                offer.destroy();
+            }
+            return;
+        }
+        if let Some(previous) = std::mem::replace(&mut self.offer, offer) {
+            previous.destroy();
+        }
+        self.changed = true;
+    }
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for DeviceState {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WlSeat, ()> for DeviceState {
+    fn event(
+        _: &mut Self,
+        _: &WlSeat,
+        _: wl_seat::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrDataControlManagerV1, ()> for DeviceState {
+    fn event(
+        _: &mut Self,
+        _: &ZwlrDataControlManagerV1,
+        _: zwlr_data_control_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrDataControlDeviceV1, ()> for DeviceState {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrDataControlDeviceV1,
+        event: zwlr_data_control_device_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_data_control_device_v1::Event::Selection { id } => {
+                state.offered(Selection::Clipboard, id);
+            }
+            zwlr_data_control_device_v1::Event::PrimarySelection { id } => {
+                state.offered(Selection::Primary, id);
+            }
+            zwlr_data_control_device_v1::Event::Finished => state.finished = true,
+            _ => {}
+        }
+    }
+
+    event_created_child!(DeviceState, ZwlrDataControlDeviceV1, [
+        zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ZwlrDataControlOfferV1, OfferedTypes::default()),
+    ]);
+}
+
+impl Dispatch<ZwlrDataControlOfferV1, OfferedTypes> for DeviceState {
+    fn event(
+        _: &mut Self,
+        _: &ZwlrDataControlOfferV1,
+        event: zwlr_data_control_offer_v1::Event,
+        offered: &OfferedTypes,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let zwlr_data_control_offer_v1::Event::Offer { mime_type } = event {
+            if let Ok(mut offered) = offered.lock() {
+                offered.push(mime_type);
+            }
+        }
+    }
+}
+
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: pipe2 just opened both descriptors, and nothing else owns them.
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Everything written to `pipe` until its last writer closes it, or an error once `timeout`
+/// has passed without that happening, since a selection owner can keep the pipe open forever,
+/// or once it wrote more than `limit` bytes.
+fn read_until_closed(pipe: OwnedFd, timeout: Duration, limit: u64) -> io::Result<Vec<u8>> {
+    let deadline = Instant::now() + timeout;
+    let mut pipe = File::from(pipe);
+    let mut contents = Vec::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let mut poll = libc::pollfd {
+            fd: pipe.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = remaining.as_millis().min(i32::MAX as u128) as i32;
+        // SAFETY: `poll` points at one initialized pollfd for a descriptor `pipe` keeps open.
+        match unsafe { libc::poll(&mut poll, 1, millis) } {
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+                continue;
+            }
+            0 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "the selection owner didn't finish sending within {}s",
+                        timeout.as_secs_f32()
+                    ),
+                ))
+            }
+            _ => {}
+        }
+        match pipe.read(&mut buffer) {
+            Ok(0) => return Ok(contents),
+            Ok(read) => {
+                if (contents.len() + read) as u64 > limit {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "the selection owner sent more than {limit} bytes \
+                             (clipboard.max_entry_bytes)"
+                        ),
+                    ));
+                }
+                contents.extend_from_slice(&buffer[..read]);
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pipe, read_until_closed};
+    use std::fs::File;
+    use std::io::{ErrorKind, Write};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn reads_the_pipe_until_the_owner_closes_it() {
+        let (read, write) = pipe().unwrap();
+        let writer = thread::spawn(move || {
+            let mut write = File::from(write);
+            write.write_all(&[7; 100_000]).unwrap();
+            write.write_all(b"end").unwrap();
+        });
+        let contents = read_until_closed(read, Duration::from_secs(5), 100_003).unwrap();
+        writer.join().unwrap();
+        assert_eq!(contents.len(), 100_003);
+        assert!(contents.ends_with(b"end"));
+    }
+
+    #[test]
+    fn gives_up_on_an_owner_that_keeps_the_pipe_open() {
+        let (read, write) = pipe().unwrap();
+        File::from(write.try_clone().unwrap())
+            .write_all(b"partial")
+            .unwrap();
+        let err = read_until_closed(read, Duration::from_millis(50), 1024).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert_eq!(
+            err.to_string(),
+            "the selection owner didn't finish sending within 0.05s"
+        );
+        drop(write);
+    }
+
+    #[test]
+    fn gives_up_on_an_owner_that_sends_too_much() {
+        let (read, write) = pipe().unwrap();
+        let writer = thread::spawn(move || {
+            // Fails with a broken pipe once the reader gives up.
+            let _ = File::from(write).write_all(&[7; 100_000]);
+        });
+        let err = read_until_closed(read, Duration::from_secs(5), 99_999).unwrap_err();
+        writer.join().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "the selection owner sent more than 99999 bytes (clipboard.max_entry_bytes)"
+        );
+    }
+}
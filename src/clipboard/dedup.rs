@@ -0,0 +1,472 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::clipboard::ClipboardEntry;
+
+/// `clipboard.dedup`, as the history that records and replays copies applies it. Journal
+/// replay deduplicates again, so a history is loaded with the settings it records with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DedupSettings {
+    /// When off, every copy is a new entry even if history already has the same content, so
+    /// history keeps the true sequence of copies.
+    pub enabled: bool,
+    /// Which entries a copy can move.
+    pub scope: DedupScope,
+    /// Whether copies differing only in trailing whitespace are the same content.
+    pub ignore_trailing_whitespace: bool,
+}
+
+impl Default for DedupSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            scope: DedupScope::All,
+            ignore_trailing_whitespace: true,
+        }
+    }
+}
+
+impl DedupSettings {
+    /// Whether a copy of `value` is the same content as `existing`: equal by
+    /// [`content_eq`](ClipboardEntry::content_eq), and byte for byte unless trailing
+    /// whitespace is ignored.
+    pub fn same_content(self, existing: &ClipboardEntry, value: &ClipboardEntry) -> bool {
+        existing.content_eq(value) && (self.ignore_trailing_whitespace || existing.same_form(value))
+    }
+}
+
+/// How far back a copy looks for an entry with the same content to move instead of adding
+/// a new one: `all`, `recent:<n>` or `window:<duration>`, e.g. `recent:10` or `window:1h`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum DedupScope {
+    #[default]
+    All,
+    /// The `n` newest entries.
+    Recent(usize),
+    /// Entries captured at most this long before the copy. Entries without a capture time
+    /// are outside it.
+    Window(Duration),
+}
+
+impl DedupScope {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        if value == "all" {
+            return Ok(Self::All);
+        }
+        if let Some(count) = value.strip_prefix("recent:") {
+            return match count.trim().parse::<usize>() {
+                Ok(count) if count > 0 => Ok(Self::Recent(count)),
+                _ => Err(format!(
+                    "invalid dedup scope {value:?}: recent needs a number of entries above zero"
+                )),
+            };
+        }
+        if let Some(duration) = value.strip_prefix("window:") {
+            return match parse_window(duration.trim()) {
+                Some(window) if !window.is_zero() => Ok(Self::Window(window)),
+                _ => Err(format!(
+                    "invalid dedup scope {value:?}: window needs a duration like 90s, 30m, 1h or 7d"
+                )),
+            };
+        }
+        Err(format!(
+            "invalid dedup scope {value:?} (expected all, recent:<n> or window:<duration>)"
+        ))
+    }
+
+    /// Whether a copy captured at `copied_at` can move the history entry at `position`.
+    pub fn covers(self, position: usize, entry: &ClipboardEntry, copied_at: u64) -> bool {
+        match self {
+            Self::All => true,
+            Self::Recent(count) => position < count,
+            Self::Window(window) => entry.captured_at().is_some_and(|captured_at| {
+                captured_at.saturating_add(window.as_secs()) >= copied_at
+            }),
+        }
+    }
+}
+
+impl TryFrom<String> for DedupScope {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+/// `90s`, `30m`, `1h` or `7d`.
+fn parse_window(value: &str) -> Option<Duration> {
+    let unit_secs = match value.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86_400,
+        _ => return None,
+    };
+    let count: u64 = value[..value.len() - 1].parse().ok()?;
+    Some(Duration::from_secs(count.checked_mul(unit_secs)?))
+}
+
+/// The part of a text entry's value that deduplication compares: all but trailing
+/// whitespace, which [`DedupSettings::same_content`] checks separately when it counts.
+pub fn dedup_text(value: &str) -> &str {
+    value.trim_end()
+}
+
+/// Finds a history entry by content without comparing against every entry.
+///
+/// History only grows at the front, so each entry gets a sequence number one higher than
+/// the current newest and `slots` stays sorted newest-first. A content digest maps to
+/// sequence numbers, and binary search over `slots` turns one into a position. The owner
+/// mirrors every insertion and removal on the history into the index.
+///
+/// Since it sees every change anyway, the index also keeps the running total of
+/// [`approx_size_bytes`](ClipboardEntry::approx_size_bytes) over the history.
+#[derive(Debug, Clone, Default)]
+pub struct DedupIndex {
+    slots: VecDeque<Slot>,
+    /// Usually one sequence number per digest; more on a hash collision, or for copies kept
+    /// as separate entries because deduplication is off or they were outside its scope.
+    by_digest: HashMap<u64, Vec<u64>>,
+    next_seq: u64,
+    total_size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Slot {
+    seq: u64,
+    digest: u64,
+    size: u64,
+}
+
+impl DedupIndex {
+    pub fn build(history: &VecDeque<Arc<ClipboardEntry>>) -> Self {
+        let mut index = Self::default();
+        for entry in history.iter().rev() {
+            index.push_front(entry);
+        }
+        index
+    }
+
+    /// Position of the entry whose content equals `value` under the default settings, like a
+    /// linear [`content_eq`](ClipboardEntry::content_eq) scan would find.
+    pub fn find(
+        &self,
+        history: &VecDeque<Arc<ClipboardEntry>>,
+        value: &ClipboardEntry,
+    ) -> Option<usize> {
+        self.find_in_scope(history, value, DedupSettings::default(), 0)
+    }
+
+    /// [`find`](Self::find) with `settings`: among the entries their scope covers for a copy
+    /// captured at `copied_at` and `value` [may replace](ClipboardEntry::may_replace), and
+    /// comparing as they say.
+    pub fn find_in_scope(
+        &self,
+        history: &VecDeque<Arc<ClipboardEntry>>,
+        value: &ClipboardEntry,
+        settings: DedupSettings,
+        copied_at: u64,
+    ) -> Option<usize> {
+        let candidates = self.by_digest.get(&value.content_id())?;
+        candidates
+            .iter()
+            .filter_map(|&seq| self.position(seq))
+            .filter(|&position| {
+                settings
+                    .scope
+                    .covers(position, &history[position], copied_at)
+                    && value.may_replace(&history[position])
+                    && settings.same_content(&history[position], value)
+            })
+            .min()
+    }
+
+    pub fn push_front(&mut self, entry: &ClipboardEntry) {
+        let slot = Slot {
+            seq: self.next_seq,
+            digest: entry.content_id(),
+            size: entry.approx_size_bytes(),
+        };
+        self.next_seq += 1;
+        self.total_size += slot.size;
+        self.slots.push_front(slot);
+        self.by_digest
+            .entry(slot.digest)
+            .or_default()
+            .push(slot.seq);
+    }
+
+    pub fn remove(&mut self, position: usize) {
+        let Some(slot) = self.slots.remove(position) else {
+            return;
+        };
+        self.total_size -= slot.size;
+        if let Some(seqs) = self.by_digest.get_mut(&slot.digest) {
+            seqs.retain(|&seq| seq != slot.seq);
+            if seqs.is_empty() {
+                self.by_digest.remove(&slot.digest);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.by_digest.clear();
+        self.total_size = 0;
+    }
+
+    /// Sum of [`approx_size_bytes`](ClipboardEntry::approx_size_bytes) over the history.
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Whether the index describes exactly `history`: same length, same digests and sizes in
+    /// order, every digest lookup resolving to the right position, and the right total size.
+    pub fn matches(&self, history: &VecDeque<Arc<ClipboardEntry>>) -> bool {
+        self.slots.len() == history.len()
+            && self.slots.iter().zip(history).all(|(slot, entry)| {
+                slot.digest == entry.content_id() && slot.size == entry.approx_size_bytes()
+            })
+            && self.total_size
+                == history
+                    .iter()
+                    .map(|entry| entry.approx_size_bytes())
+                    .sum::<u64>()
+            && self.slots.iter().enumerate().all(|(position, slot)| {
+                self.by_digest
+                    .get(&slot.digest)
+                    .is_some_and(|seqs| seqs.contains(&slot.seq))
+                    && self.position(slot.seq) == Some(position)
+            })
+            && self.by_digest.values().map(Vec::len).sum::<usize>() == self.slots.len()
+    }
+
+    fn position(&self, seq: u64) -> Option<usize> {
+        // Newest first, so sequence numbers descend.
+        self.slots.binary_search_by(|slot| seq.cmp(&slot.seq)).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dedup_text, DedupIndex, DedupScope, DedupSettings};
+    use crate::clipboard::state::{push_history_entry, push_indexed_history_entry};
+    use crate::clipboard::{ClipboardEntry, ImageData};
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// xorshift64, so the random walks below are reproducible without extra crates.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+    }
+
+    fn entry(rng: &mut Rng) -> ClipboardEntry {
+        let value = rng.below(24);
+        if value.is_multiple_of(6) {
+            let pixels = vec![value as u8; 16];
+            ClipboardEntry::image(ImageData::new(2, 2, 4, 8, pixels).unwrap())
+        } else {
+            // Sometimes as a terminal copies it, a different form of the same content.
+            let newline = if rng.below(3) == 0 { "\n" } else { "" };
+            ClipboardEntry::text(format!("value {value}{newline}"))
+        }
+    }
+
+    fn linear_find(
+        history: &VecDeque<Arc<ClipboardEntry>>,
+        value: &ClipboardEntry,
+    ) -> Option<usize> {
+        history.iter().position(|entry| entry.content_eq(value))
+    }
+
+    #[test]
+    fn index_tracks_a_linear_scan_through_random_edits() {
+        for seed in 1..=20u64 {
+            let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+            let limit = 4 + rng.below(12);
+            let mut indexed: VecDeque<Arc<ClipboardEntry>> = VecDeque::new();
+            let mut index = DedupIndex::default();
+            let mut linear = indexed.clone();
+
+            for _ in 0..400 {
+                match rng.below(10) {
+                    0 if !indexed.is_empty() => {
+                        let position = rng.below(indexed.len());
+                        indexed.remove(position);
+                        index.remove(position);
+                        linear.remove(position);
+                    }
+                    1 if !indexed.is_empty() => {
+                        let position = rng.below(indexed.len());
+                        let pinned = !indexed[position].is_pinned();
+                        Arc::make_mut(&mut indexed[position]).set_pinned(pinned);
+                        Arc::make_mut(&mut linear[position]).set_pinned(pinned);
+                    }
+                    2 if rng.below(8) == 0 => {
+                        indexed.clear();
+                        index.clear();
+                        linear.clear();
+                    }
+                    _ => {
+                        let value = Arc::new(entry(&mut rng));
+                        assert_eq!(index.find(&indexed, &value), linear_find(&linear, &value));
+                        let indexed_outcome = push_indexed_history_entry(
+                            &mut indexed,
+                            &mut index,
+                            limit,
+                            value.clone(),
+                            DedupSettings::default(),
+                        );
+                        let linear_outcome =
+                            push_history_entry(&mut linear, limit, value, DedupSettings::default());
+                        assert_eq!(indexed_outcome, linear_outcome);
+                    }
+                }
+                assert_eq!(indexed, linear);
+                assert!(index.matches(&indexed), "index drifted (seed {seed})");
+            }
+            assert!(DedupIndex::build(&indexed).matches(&indexed));
+        }
+    }
+
+    #[test]
+    fn finds_the_newest_match_after_rebuild() {
+        let history: VecDeque<Arc<ClipboardEntry>> = ["c", "b", "a"]
+            .into_iter()
+            .map(|value| Arc::new(ClipboardEntry::text(value)))
+            .collect();
+        let index = DedupIndex::build(&history);
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.find(&history, &ClipboardEntry::text("a")), Some(2));
+        assert_eq!(index.find(&history, &ClipboardEntry::text("c")), Some(0));
+        assert_eq!(index.find(&history, &ClipboardEntry::text("d")), None);
+        assert!(DedupIndex::default().is_empty());
+    }
+
+    #[test]
+    fn trailing_whitespace_is_ignored_only_when_configured() {
+        assert_eq!(dedup_text("git status\n"), "git status");
+        assert_eq!(dedup_text("  indented \t\r\n"), "  indented");
+
+        let history: VecDeque<Arc<ClipboardEntry>> =
+            VecDeque::from([Arc::new(ClipboardEntry::text("git status\n"))]);
+        let index = DedupIndex::build(&history);
+        let copy = ClipboardEntry::text("git status");
+        assert_eq!(index.find(&history, &copy), Some(0));
+        let exact = DedupSettings {
+            ignore_trailing_whitespace: false,
+            ..DedupSettings::default()
+        };
+        assert_eq!(index.find_in_scope(&history, &copy, exact, 0), None);
+        assert_eq!(
+            index.find_in_scope(&history, &ClipboardEntry::text("git status\n"), exact, 0),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn parses_scopes() {
+        assert_eq!(DedupScope::parse("all"), Ok(DedupScope::All));
+        assert_eq!(DedupScope::parse("recent:10"), Ok(DedupScope::Recent(10)));
+        assert_eq!(
+            DedupScope::parse("window:1h"),
+            Ok(DedupScope::Window(Duration::from_secs(3600)))
+        );
+        assert_eq!(
+            DedupScope::parse(" window: 90s "),
+            Ok(DedupScope::Window(Duration::from_secs(90)))
+        );
+        assert_eq!(
+            DedupScope::parse("window:7d"),
+            Ok(DedupScope::Window(Duration::from_secs(7 * 86_400)))
+        );
+        for invalid in [
+            "",
+            "some",
+            "recent:0",
+            "recent:",
+            "window:0m",
+            "window:1w",
+            "window:h",
+        ] {
+            assert!(DedupScope::parse(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn scopes_limit_which_entries_a_copy_moves() {
+        const NOW: u64 = 1_700_000_000;
+        // Newest first: "d" at 0 ... "a" at 3, each captured an hour before the next.
+        let history: VecDeque<Arc<ClipboardEntry>> = ["d", "c", "b", "a"]
+            .into_iter()
+            .zip(0..)
+            .map(|(value, hours)| {
+                Arc::new(ClipboardEntry::text(value).with_captured_at(Some(NOW - hours * 3600)))
+            })
+            .collect();
+        let scoped = |scope| DedupSettings {
+            scope,
+            ..DedupSettings::default()
+        };
+        let copy = |value: &str, scope: DedupScope| {
+            let mut history = history.clone();
+            let mut index = DedupIndex::build(&history);
+            let value = ClipboardEntry::text(value).with_captured_at(Some(NOW));
+            let outcome = push_indexed_history_entry(
+                &mut history,
+                &mut index,
+                10,
+                Arc::new(value),
+                scoped(scope),
+            );
+            assert!(index.matches(&history), "index drifted");
+            (outcome.deduplicated_from_index, history.len())
+        };
+
+        assert_eq!(copy("a", DedupScope::All), (Some(3), 4));
+        // The boundary entry is the last one in scope.
+        assert_eq!(copy("c", DedupScope::Recent(2)), (Some(1), 4));
+        assert_eq!(copy("b", DedupScope::Recent(2)), (None, 5));
+        let two_hours = DedupScope::Window(Duration::from_secs(2 * 3600));
+        assert_eq!(copy("b", two_hours), (Some(2), 4));
+        assert_eq!(copy("a", two_hours), (None, 5));
+
+        // Entries from before timestamps are outside any window.
+        let old = Arc::new(ClipboardEntry::text("old"));
+        let mut history = VecDeque::from([old.clone()]);
+        let mut index = DedupIndex::build(&history);
+        let outcome =
+            push_indexed_history_entry(&mut history, &mut index, 10, old, scoped(two_hours));
+        assert_eq!((outcome.deduplicated_from_index, history.len()), (None, 2));
+        assert!(index.matches(&history));
+        let newer_copy = index.find_in_scope(
+            &history,
+            &ClipboardEntry::text("old"),
+            scoped(DedupScope::Recent(1)),
+            NOW,
+        );
+        assert_eq!(newer_copy, Some(0));
+    }
+}
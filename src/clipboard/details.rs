@@ -0,0 +1,253 @@
+//! The untruncated view of one history entry printed by `slyboard show`.
+
+use std::io::{self, Write};
+
+use crate::clipboard::age::format_relative_age;
+use crate::clipboard::preview::format_byte_size;
+use crate::clipboard::redact::{redacted_text, redacted_window};
+use crate::clipboard::{ClipboardEntry, ImageEncoding};
+use crate::core::active_window::ActiveWindowContext;
+
+/// Writes entry `id` in full: what is known about its capture, source window, label and
+/// restores, then the whole text. Images get their metadata only; pixels would flood the
+/// terminal. `verbose` adds capture diagnostics such as the targets the clipboard owner
+/// offered. `redact` replaces the text, label and window titles as `--redact` does. Ages are
+/// relative to the Unix time `now`.
+pub fn write_entry_details<W: Write>(
+    writer: &mut W,
+    id: usize,
+    entry: &ClipboardEntry,
+    verbose: bool,
+    redact: bool,
+    now: u64,
+) -> io::Result<()> {
+    let redacted = |text: &str| {
+        if redact {
+            redacted_text(text)
+        } else {
+            text.to_string()
+        }
+    };
+    let size = format_byte_size(entry.approx_size_bytes());
+    match entry {
+        ClipboardEntry::Text { .. } => writeln!(writer, "Entry {id}: text, {size}")?,
+        ClipboardEntry::Image { width, height, .. } => {
+            writeln!(writer, "Entry {id}: {width}x{height} image, {size}")?
+        }
+    }
+    writeln!(writer, "Stable id: {}", entry.stable_id())?;
+    let at = |unix_secs: u64| format!("{unix_secs} ({})", format_relative_age(unix_secs, now));
+    match (entry.captured_at(), entry.captured_by()) {
+        (Some(captured_at), Some(origin)) => writeln!(
+            writer,
+            "Captured: {} by {}",
+            at(captured_at),
+            origin.as_str()
+        )?,
+        (Some(captured_at), None) => writeln!(writer, "Captured: {}", at(captured_at))?,
+        (None, Some(origin)) => writeln!(writer, "Captured: by {}", origin.as_str())?,
+        (None, None) => writeln!(writer, "Captured: unknown")?,
+    }
+    if let Some(selection) = entry.selection() {
+        writeln!(writer, "Selection: {}", selection.as_str())?;
+    }
+    match entry.source_window() {
+        Some(window) if redact => write_source_window(writer, &redacted_window(window))?,
+        Some(window) => write_source_window(writer, window)?,
+        None => writeln!(writer, "Source window: unknown")?,
+    }
+    if let Some(label) = entry.label() {
+        writeln!(writer, "Label: {}", redacted(label))?;
+    }
+    if entry.is_pinned() {
+        writeln!(writer, "Pinned: yes")?;
+    }
+    if let Some(hint) = entry.content_hint() {
+        writeln!(writer, "Content hint: {hint}")?;
+    }
+    match entry.restores() {
+        (0, _) => writeln!(writer, "Restored: never")?,
+        (count, Some(last)) => writeln!(writer, "Restored: {count} times, last {}", at(last))?,
+        (count, None) => writeln!(writer, "Restored: {count} times")?,
+    }
+    if let Some(expires_at) = entry.expires_at() {
+        writeln!(writer, "Expires: {expires_at}")?;
+    }
+    if verbose {
+        match entry.offered_targets() {
+            [] => writeln!(
+                writer,
+                "Offered targets: not recorded (see clipboard.record_targets)"
+            )?,
+            targets => writeln!(writer, "Offered targets: {}", targets.join(", "))?,
+        }
+    }
+    writeln!(writer)?;
+    match entry {
+        ClipboardEntry::Text { value, .. } => writeln!(writer, "{}", redacted(value))?,
+        ClipboardEntry::Image {
+            encoding,
+            channels,
+            bits_per_sample,
+            has_alpha,
+            pixels,
+            ..
+        } => {
+            let encoding = match encoding {
+                ImageEncoding::Png => "PNG",
+                ImageEncoding::Raw => "raw rows",
+            };
+            writeln!(
+                writer,
+                "Stored as {encoding} ({}), {channels} channels of {bits_per_sample} bits{}.",
+                format_byte_size(pixels.len() as u64),
+                if *has_alpha { " with alpha" } else { "" }
+            )?;
+            writeln!(
+                writer,
+                "Pixels are not printed; `slyboard history --json --images` exports them."
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn write_source_window<W: Write>(writer: &mut W, window: &ActiveWindowContext) -> io::Result<()> {
+    let ActiveWindowContext {
+        backend,
+        title,
+        app_id,
+        initial_app_id,
+        initial_title,
+        window_id,
+        pid,
+        workspace_id,
+        workspace_name,
+        is_xwayland,
+        monitor,
+        geometry,
+    } = window;
+    writeln!(
+        writer,
+        "Source window: {}",
+        app_id.as_deref().unwrap_or("unknown app")
+    )?;
+    writeln!(writer, "  title: {title}")?;
+    let optional = [
+        ("initial app id", initial_app_id.clone()),
+        ("initial title", initial_title.clone()),
+        ("window id", window_id.clone()),
+        ("pid", pid.map(|pid| pid.to_string())),
+        ("workspace id", workspace_id.map(|id| id.to_string())),
+        ("workspace name", workspace_name.clone()),
+        ("xwayland", is_xwayland.map(|xwayland| xwayland.to_string())),
+        ("monitor", monitor.clone()),
+        (
+            "geometry",
+            geometry.map(|(x, y, width, height)| format!("{width}x{height} at {x},{y}")),
+        ),
+    ];
+    for (name, value) in optional {
+        if let Some(value) = value {
+            writeln!(writer, "  {name}: {value}")?;
+        }
+    }
+    writeln!(writer, "  backend: {backend}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_entry_details;
+    use crate::clipboard::writer::Selection;
+    use crate::clipboard::{CaptureOrigin, ClipboardEntry};
+    use crate::core::active_window::ActiveWindowContext;
+    use crate::testing::{png_image, text, text_from, window};
+
+    const NOW: u64 = 1_700_007_200;
+
+    fn details(entry: &ClipboardEntry, verbose: bool) -> String {
+        let mut out = Vec::new();
+        write_entry_details(&mut out, 3, entry, verbose, false, NOW).expect("write");
+        String::from_utf8(out).expect("utf-8")
+    }
+
+    #[test]
+    fn prints_everything_known_about_a_text_entry() {
+        let source = ActiveWindowContext {
+            window_id: Some("0x55d1".to_string()),
+            pid: Some(4242),
+            workspace_id: Some(2),
+            workspace_name: Some("code".to_string()),
+            is_xwayland: Some(false),
+            monitor: Some("DP-1".to_string()),
+            geometry: Some((10, 20, 800, 600)),
+            ..window(Some("kitty"), "~/src")
+        };
+        let mut entry = text_from("line one\nline two", source)
+            .with_captured_at(Some(1_700_000_000))
+            .with_captured_by(CaptureOrigin::GtkPoll)
+            .with_selection(Some(Selection::Primary))
+            .with_label(Some("deploy".to_string()))
+            .with_pinned(true)
+            .with_offered_targets(vec!["TARGETS".into(), "UTF8_STRING".into()]);
+        entry.mark_restored(1_700_003_600);
+        entry.mark_restored(1_700_003_600);
+        let stable_id = entry.stable_id();
+
+        assert_eq!(
+            details(&entry, true),
+            format!(
+                "Entry 3: text, 17 B
+Stable id: {stable_id}
+Captured: 1700000000 (2h) by gtk-poll
+Selection: primary
+Source window: kitty
+  title: ~/src
+  window id: 0x55d1
+  pid: 4242
+  workspace id: 2
+  workspace name: code
+  xwayland: false
+  monitor: DP-1
+  geometry: 800x600 at 10,20
+  backend: hyprctl
+Label: deploy
+Pinned: yes
+Restored: 2 times, last 1700003600 (1h)
+Offered targets: TARGETS, UTF8_STRING
+
+line one
+line two
+"
+            )
+        );
+    }
+
+    #[test]
+    fn prints_image_metadata_without_pixels() {
+        let image = png_image(2, 2, [0; 4]);
+        let printed = details(&image, false);
+        let stable_id = image.stable_id();
+        assert!(
+            printed.starts_with(&format!(
+                "Entry 3: 2x2 image, 16 B
+Stable id: {stable_id}
+Captured: unknown
+Source window: unknown
+Restored: never
+
+Stored as PNG ("
+            )),
+            "{printed}"
+        );
+        assert!(printed.ends_with(
+            "), 4 channels of 8 bits with alpha.
+Pixels are not printed; `slyboard history --json --images` exports them.
+"
+        ));
+        assert_eq!(
+            details(&text("a"), true).lines().nth(5),
+            Some("Offered targets: not recorded (see clipboard.record_targets)")
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! JSON views of history entries, shared by `slyboard history --json` and the HTTP endpoint.
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::clipboard::long_text::FullText;
+use crate::clipboard::writer::Selection;
+use crate::clipboard::{CaptureOrigin, ClipboardEntry, ImageEncoding};
+use crate::core::active_window::ActiveWindowContext;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SerializableHistoryEntry<'a> {
+    pub id: usize,
+    /// [`ClipboardEntry::approx_size_bytes`].
+    pub size_bytes: u64,
+    #[serde(flatten)]
+    pub entry: SerializableClipboardEntry<'a>,
+}
+
+impl<'a> SerializableHistoryEntry<'a> {
+    pub fn new(id: usize, entry: &'a ClipboardEntry, include_images: bool) -> Self {
+        Self {
+            id,
+            size_bytes: entry.approx_size_bytes(),
+            entry: SerializableClipboardEntry::from_entry(entry, include_images),
+        }
+    }
+
+    /// See [`SerializableClipboardEntry::redacted`].
+    pub fn redacted(self) -> Self {
+        Self {
+            entry: self.entry.redacted(),
+            ..self
+        }
+    }
+}
+
+/// Borrowed view of an entry for JSON output, so image bytes are serialized in place.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SerializableClipboardEntry<'a> {
+    Text {
+        value: Cow<'a, str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        source_window: Option<Cow<'a, ActiveWindowContext>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        content_hint: Option<&'a str>,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        pinned: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        label: Option<Cow<'a, str>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        captured_at: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        captured_by: Option<CaptureOrigin>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        selection: Option<Selection>,
+        restore_count: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        last_restored_at: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        expires_at: Option<u64>,
+        #[serde(skip_serializing_if = "<[String]>::is_empty")]
+        offered_targets: &'a [String],
+        /// Set when `value` is only a preview; see [`ClipboardEntry::full_text`].
+        #[serde(skip_serializing_if = "Option::is_none")]
+        full_text: Option<&'a FullText>,
+    },
+    Image {
+        width: i32,
+        height: i32,
+        rowstride: i32,
+        has_alpha: bool,
+        bits_per_sample: i32,
+        channels: i32,
+        encoding: ImageEncoding,
+        pixel_bytes: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pixels: Option<&'a [u8]>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        source_window: Option<Cow<'a, ActiveWindowContext>>,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        pinned: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        label: Option<Cow<'a, str>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        captured_at: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        captured_by: Option<CaptureOrigin>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        selection: Option<Selection>,
+        restore_count: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        last_restored_at: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        expires_at: Option<u64>,
+        #[serde(skip_serializing_if = "<[String]>::is_empty")]
+        offered_targets: &'a [String],
+    },
+}
+
+impl<'a> SerializableClipboardEntry<'a> {
+    pub fn from_entry(entry: &'a ClipboardEntry, include_images: bool) -> Self {
+        match entry {
+            ClipboardEntry::Text {
+                value,
+                source_window,
+                content_hint,
+                pinned,
+                label,
+                captured_at,
+                captured_by,
+                selection,
+                restore_count,
+                last_restored_at,
+                expires_at,
+                offered_targets,
+                full_text,
+                ..
+            } => Self::Text {
+                value: Cow::Borrowed(value),
+                source_window: source_window.as_ref().map(Cow::Borrowed),
+                content_hint: content_hint.as_deref(),
+                pinned: *pinned,
+                label: label.as_deref().map(Cow::Borrowed),
+                captured_at: *captured_at,
+                captured_by: *captured_by,
+                selection: *selection,
+                restore_count: *restore_count,
+                last_restored_at: *last_restored_at,
+                expires_at: *expires_at,
+                offered_targets,
+                full_text: full_text.as_ref(),
+            },
+            ClipboardEntry::Image {
+                width,
+                height,
+                rowstride,
+                has_alpha,
+                bits_per_sample,
+                channels,
+                encoding,
+                pixels,
+                source_window,
+                pinned,
+                label,
+                captured_at,
+                captured_by,
+                selection,
+                restore_count,
+                last_restored_at,
+                expires_at,
+                offered_targets,
+                ..
+            } => Self::Image {
+                width: *width,
+                height: *height,
+                rowstride: *rowstride,
+                has_alpha: *has_alpha,
+                bits_per_sample: *bits_per_sample,
+                channels: *channels,
+                encoding: *encoding,
+                pixel_bytes: pixels.len(),
+                pixels: include_images.then_some(pixels.as_slice()),
+                source_window: source_window.as_ref().map(Cow::Borrowed),
+                pinned: *pinned,
+                label: label.as_deref().map(Cow::Borrowed),
+                captured_at: *captured_at,
+                captured_by: *captured_by,
+                selection: *selection,
+                restore_count: *restore_count,
+                last_restored_at: *last_restored_at,
+                expires_at: *expires_at,
+                offered_targets,
+            },
+        }
+    }
+}
+
+/// Writes history as one JSON array, serializing entry by entry so image bytes are never
+/// buffered as a whole document. `redact` replaces content as
+/// [`SerializableHistoryEntry::redacted`] does.
+pub fn write_json_history<W: Write>(
+    writer: &mut W,
+    entries: &[(usize, &ClipboardEntry)],
+    include_images: bool,
+    redact: bool,
+) -> io::Result<()> {
+    writer.write_all(b"[")?;
+    for (position, (id, entry)) in entries.iter().enumerate() {
+        if position > 0 {
+            writer.write_all(b",")?;
+        }
+        serde_json::to_writer(
+            &mut *writer,
+            &serializable_entry(*id, entry, include_images, redact),
+        )?;
+    }
+    writer.write_all(b"]\n")?;
+    Ok(())
+}
+
+pub fn write_jsonl_history<W: Write>(
+    writer: &mut W,
+    entries: &[(usize, &ClipboardEntry)],
+    include_images: bool,
+    redact: bool,
+) -> io::Result<()> {
+    for (id, entry) in entries {
+        serde_json::to_writer(
+            &mut *writer,
+            &serializable_entry(*id, entry, include_images, redact),
+        )?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn serializable_entry(
+    id: usize,
+    entry: &ClipboardEntry,
+    include_images: bool,
+    redact: bool,
+) -> SerializableHistoryEntry<'_> {
+    let serializable = SerializableHistoryEntry::new(id, entry, include_images);
+    if redact {
+        serializable.redacted()
+    } else {
+        serializable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_json_history, write_jsonl_history, SerializableHistoryEntry};
+    use crate::clipboard::{ClipboardEntry, ImageEncoding};
+
+    fn synthetic_history() -> Vec<ClipboardEntry> {
+        (0..200)
+            .map(|index| {
+                if index % 10 == 0 {
+                    ClipboardEntry::Image {
+                        width: 64,
+                        height: 64,
+                        rowstride: 256,
+                        has_alpha: true,
+                        bits_per_sample: 8,
+                        channels: 4,
+                        encoding: ImageEncoding::Png,
+                        pixels: vec![index as u8; 64 * 256],
+                        source_window: None,
+                        pinned: index == 0,
+                        label: None,
+                        transient_until: None,
+                        captured_at: None,
+                        captured_by: None,
+                        selection: None,
+                        restore_count: 0,
+                        last_restored_at: None,
+                        expires_at: None,
+                        offered_targets: Vec::new(),
+                    }
+                } else {
+                    ClipboardEntry::Text {
+                        value: format!("entry \"{index}\"\n"),
+                        source_window: None,
+                        pinned: false,
+                        label: None,
+                        content_hint: (index % 3 == 0).then(|| "plain".to_string()),
+                        transient_until: None,
+                        captured_at: None,
+                        captured_by: None,
+                        selection: None,
+                        restore_count: 0,
+                        last_restored_at: None,
+                        expires_at: None,
+                        offered_targets: Vec::new(),
+                        full_text: None,
+                    }
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn streamed_array_matches_whole_document_serialization() {
+        let history = synthetic_history();
+        let entries: Vec<(usize, &ClipboardEntry)> = history.iter().enumerate().collect();
+        for include_images in [false, true] {
+            let whole: Vec<SerializableHistoryEntry> = entries
+                .iter()
+                .map(|(id, entry)| SerializableHistoryEntry::new(*id, entry, include_images))
+                .collect();
+            let expected = format!("{}\n", serde_json::to_string(&whole).expect("serialize"));
+
+            let mut streamed = Vec::new();
+            write_json_history(&mut streamed, &entries, include_images, false).expect("stream");
+            assert_eq!(String::from_utf8(streamed).expect("utf-8"), expected);
+        }
+
+        let mut empty = Vec::new();
+        write_json_history(&mut empty, &[], false, false).expect("stream");
+        assert_eq!(empty, b"[]\n");
+    }
+
+    #[test]
+    fn jsonl_emits_one_object_per_line() {
+        let history = synthetic_history();
+        let entries: Vec<(usize, &ClipboardEntry)> = history.iter().enumerate().collect();
+        let mut output = Vec::new();
+        write_jsonl_history(&mut output, &entries, false, false).expect("stream");
+
+        let output = String::from_utf8(output).expect("utf-8");
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), history.len());
+        let first: serde_json::Value = serde_json::from_str(lines[0]).expect("json line");
+        assert_eq!(first["kind"], "image");
+        assert_eq!(first["pixel_bytes"], 64 * 256);
+        assert_eq!(first["size_bytes"], 64 * 64 * 4);
+        assert!(first.get("pixels").is_none());
+        let second: serde_json::Value = serde_json::from_str(lines[1]).expect("json line");
+        assert_eq!(second["value"], "entry \"1\"\n");
+        assert_eq!(second["size_bytes"], 10);
+    }
+}
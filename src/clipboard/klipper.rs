@@ -0,0 +1,386 @@
+//! Reads KDE Klipper's `history2.lst`, for `slyboard import --from klipper`.
+//!
+//! The file is a big-endian `QDataStream`: a CRC-32 and a byte array holding the Klipper version
+//! (a NUL-terminated `char*`) followed by the items, newest first. Each item is a `QString` type
+//! tag and its payload: a `QString` for `string`, a `QList<QUrl>`, a metadata `QMap` and a cut
+//! flag for `url`, and a `QImage` (a flag and a PNG) for `image`. Strings are UTF-16 with a
+//! 32-bit byte length prefix; `0xFFFFFFFF` marks a null string.
+
+use std::fmt;
+use std::path::Path;
+
+use crate::error::{Error, Result};
+
+/// Length prefix of a null `QString` or `QByteArray`.
+const NULL_LENGTH: u32 = u32::MAX;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+/// Far longer than any Klipper version or item type; a longer length prefix means the bytes
+/// are not a Klipper history rather than a cut-off one.
+const MAX_VERSION_BYTES: usize = 64;
+const MAX_TAG_BYTES: usize = 32;
+
+/// What could be recovered from a Klipper history file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KlipperHistory {
+    /// The Klipper version that wrote the file, if it recorded one.
+    pub version: Option<String>,
+    /// Text and URL items, newest first as Klipper stores them. URL items become their URLs,
+    /// one per line.
+    pub entries: Vec<String>,
+    /// Image items, which are not imported.
+    pub skipped_images: usize,
+    /// The file ended in the middle of an item; `entries` holds everything before it.
+    pub truncated: bool,
+}
+
+/// Why a Klipper history can't be read, and where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub offset: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte offset {}", self.reason, self.offset)
+    }
+}
+
+/// Reads and parses the Klipper history at `path`.
+pub fn read_history(path: &Path) -> Result<KlipperHistory> {
+    let bytes = std::fs::read(path).map_err(|source| Error::StorageIo {
+        action: "read Klipper history",
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_history(&bytes).map_err(|err| Error::ImportCorrupt {
+        path: path.to_path_buf(),
+        reason: err.to_string(),
+    })
+}
+
+/// Parses a `history2.lst` file. A file cut short keeps the items before the cut and sets
+/// [`KlipperHistory::truncated`]; anything that isn't a Klipper history is an error.
+pub fn parse_history(bytes: &[u8]) -> std::result::Result<KlipperHistory, ParseError> {
+    let mut file = Reader::new(bytes, 0);
+    let header = file.u32().and_then(|_crc| file.length());
+    let (data, truncated) = match header {
+        Ok(Some(len)) if len <= file.remaining() => (file.take(len)?, false),
+        // The byte array claims more than is left: read what there is.
+        Ok(Some(_)) => (file.take(file.remaining())?, true),
+        Ok(None) => (&[][..], false),
+        Err(err) => {
+            return Err(ParseError {
+                offset: err.offset,
+                reason: format!("not a Klipper history: {}", err.reason),
+            })
+        }
+    };
+
+    let mut stream = Reader::new(data, 8);
+    let mut history = KlipperHistory {
+        truncated,
+        ..KlipperHistory::default()
+    };
+    if stream.remaining() == 0 {
+        return Ok(history);
+    }
+    history.version = match stream.c_string() {
+        Ok(version) => version,
+        Err(err) if err.is_truncation() => {
+            history.truncated = true;
+            return Ok(history);
+        }
+        Err(err) => {
+            return Err(ParseError {
+                offset: err.offset,
+                reason: format!("not a Klipper history: {}", err.reason),
+            })
+        }
+    };
+    for number in 1.. {
+        if stream.remaining() == 0 {
+            break;
+        }
+        match read_item(&mut stream) {
+            Ok(Item::Text(text)) => history.entries.push(text),
+            Ok(Item::Image) => history.skipped_images += 1,
+            Err(err) if err.is_truncation() => {
+                history.truncated = true;
+                break;
+            }
+            Err(err) => {
+                return Err(ParseError {
+                    offset: err.offset,
+                    reason: format!("item {number}: {}", err.reason),
+                })
+            }
+        }
+    }
+    Ok(history)
+}
+
+enum Item {
+    Text(String),
+    Image,
+}
+
+fn read_item(stream: &mut Reader<'_>) -> std::result::Result<Item, ReadError> {
+    let tag_offset = stream.offset();
+    let tag = stream.qstring(MAX_TAG_BYTES)?.unwrap_or_default();
+    match tag.as_str() {
+        "string" => Ok(Item::Text(stream.qstring(usize::MAX)?.unwrap_or_default())),
+        "url" => {
+            let count = stream.u32()?;
+            let mut urls = Vec::new();
+            for _ in 0..count {
+                let url = stream.byte_array()?.unwrap_or_default();
+                urls.push(String::from_utf8_lossy(url).into_owned());
+            }
+            let metadata = stream.u32()?;
+            for _ in 0..metadata {
+                stream.qstring(usize::MAX)?;
+                stream.qstring(usize::MAX)?;
+            }
+            let _cut = stream.u32()?;
+            Ok(Item::Text(urls.join("\n")))
+        }
+        "image" => {
+            if stream.u32()? != 0 {
+                stream.skip_png()?;
+            }
+            Ok(Item::Image)
+        }
+        other => Err(ReadError::invalid(
+            tag_offset,
+            format!("unknown item type {other:?}"),
+        )),
+    }
+}
+
+#[derive(Debug)]
+struct ReadError {
+    offset: usize,
+    reason: String,
+    truncated: bool,
+}
+
+impl ReadError {
+    fn invalid(offset: usize, reason: impl Into<String>) -> Self {
+        Self {
+            offset,
+            reason: reason.into(),
+            truncated: false,
+        }
+    }
+
+    fn is_truncation(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl From<ReadError> for ParseError {
+    fn from(err: ReadError) -> Self {
+        Self {
+            offset: err.offset,
+            reason: err.reason,
+        }
+    }
+}
+
+/// Big-endian cursor over a byte slice; offsets are reported relative to the whole file.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    base: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], base: usize) -> Self {
+        Self {
+            bytes,
+            position: 0,
+            base,
+        }
+    }
+
+    fn offset(&self) -> usize {
+        self.base + self.position
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    fn take(&mut self, len: usize) -> std::result::Result<&'a [u8], ReadError> {
+        if len > self.remaining() {
+            return Err(ReadError {
+                offset: self.offset(),
+                reason: format!("{len} bytes expected, {} left", self.remaining()),
+                truncated: true,
+            });
+        }
+        let bytes = &self.bytes[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> std::result::Result<u32, ReadError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes(bytes.try_into().expect("took 4 bytes")))
+    }
+
+    /// A length prefix; `None` for a null value.
+    fn length(&mut self) -> std::result::Result<Option<usize>, ReadError> {
+        match self.u32()? {
+            NULL_LENGTH => Ok(None),
+            len => Ok(Some(len as usize)),
+        }
+    }
+
+    fn byte_array(&mut self) -> std::result::Result<Option<&'a [u8]>, ReadError> {
+        match self.length()? {
+            Some(len) => self.take(len).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// A `char*`: a byte array whose length includes the terminating NUL.
+    fn c_string(&mut self) -> std::result::Result<Option<String>, ReadError> {
+        let offset = self.offset();
+        let Some(len) = self.length()? else {
+            return Ok(None);
+        };
+        if len > MAX_VERSION_BYTES {
+            return Err(ReadError::invalid(
+                offset,
+                format!("version string of {len} bytes"),
+            ));
+        }
+        match self.take(len)?.split_last() {
+            None => Ok(None),
+            Some((0, text)) => Ok(Some(String::from_utf8_lossy(text).into_owned())),
+            Some(_) => Err(ReadError::invalid(
+                offset,
+                "version string is not NUL-terminated",
+            )),
+        }
+    }
+
+    /// A `QString` of at most `max_len` bytes.
+    fn qstring(&mut self, max_len: usize) -> std::result::Result<Option<String>, ReadError> {
+        let offset = self.offset();
+        let Some(len) = self.length()? else {
+            return Ok(None);
+        };
+        if len > max_len {
+            return Err(ReadError::invalid(
+                offset,
+                format!("string of {len} bytes where at most {max_len} are expected"),
+            ));
+        }
+        if len % 2 != 0 {
+            return Err(ReadError::invalid(
+                offset,
+                format!("string length {len} is not a whole number of UTF-16 units"),
+            ));
+        }
+        let units: Vec<u16> = self
+            .take(len)?
+            .chunks_exact(2)
+            .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+            .collect();
+        Ok(Some(String::from_utf16_lossy(&units)))
+    }
+
+    /// Skips a PNG by walking its chunks up to and including `IEND`.
+    fn skip_png(&mut self) -> std::result::Result<(), ReadError> {
+        let offset = self.offset();
+        if self.take(PNG_SIGNATURE.len())? != PNG_SIGNATURE {
+            return Err(ReadError::invalid(offset, "image item is not a PNG"));
+        }
+        loop {
+            let len = self.u32()? as usize;
+            let kind = self.take(4)?;
+            // Chunk data and its CRC.
+            self.take(len.saturating_add(4))?;
+            if kind == b"IEND" {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_history, read_history, KlipperHistory};
+    use crate::error::Error;
+
+    const FIXTURE: &[u8] = include_bytes!("fixtures/klipper-history2.lst");
+
+    #[test]
+    fn parses_text_url_and_image_items() {
+        let history = parse_history(FIXTURE).expect("parse fixture");
+        assert_eq!(
+            history,
+            KlipperHistory {
+                version: Some("5.27.10".to_string()),
+                entries: vec![
+                    "ssh deploy@example.org".to_string(),
+                    "https://kde.org/\nhttps://invent.kde.org/".to_string(),
+                    "Grüße 👋\nsecond line".to_string(),
+                ],
+                skipped_images: 1,
+                truncated: false,
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_the_items_before_a_truncation() {
+        let history = parse_history(&FIXTURE[..FIXTURE.len() - 10]).expect("truncated");
+        assert!(history.truncated);
+        assert_eq!(history.entries.len(), 2);
+        assert_eq!(history.skipped_images, 1);
+
+        let header_only = parse_history(&FIXTURE[..12]).expect("header only");
+        assert!(header_only.truncated && header_only.entries.is_empty());
+        assert_eq!(parse_history(&[]).unwrap_err().offset, 0);
+        assert_eq!(
+            parse_history(b"garbage, not a history")
+                .unwrap_err()
+                .to_string(),
+            "not a Klipper history: version string of 544108404 bytes at byte offset 8"
+        );
+    }
+
+    #[test]
+    fn names_the_offset_of_unparseable_items() {
+        let mut corrupt = FIXTURE.to_vec();
+        // The first item's type tag, "string", becomes "strinG".
+        let tag = corrupt
+            .windows(12)
+            .position(|window| window == b"\0s\0t\0r\0i\0n\0g")
+            .unwrap();
+        corrupt[tag + 11] = b'G';
+        let err = parse_history(&corrupt).unwrap_err();
+        assert_eq!(err.offset, tag - 4);
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "item 1: unknown item type \"strinG\" at byte offset {}",
+                tag - 4
+            )
+        );
+
+        let dir =
+            std::env::temp_dir().join(format!("slyboard-test-klipper-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history2.lst");
+        std::fs::write(&path, &corrupt).unwrap();
+        let err = read_history(&path).unwrap_err();
+        assert!(matches!(err, Error::ImportCorrupt { .. }));
+        assert!(err.to_string().starts_with("cannot import "));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
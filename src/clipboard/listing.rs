@@ -0,0 +1,161 @@
+//! Plain `slyboard history` output: each entry's line followed by its age, on a terminal
+//! grouped under the "Today", "Yesterday", "This week" and "Older" headings
+//! `slyboard-rofi --group-by-date` uses.
+
+use std::io::{self, Write};
+
+use crate::clipboard::age::{date_bucket, format_relative_age, DateBucket};
+use crate::clipboard::template::render_default_history_line;
+use crate::clipboard::ClipboardEntry;
+
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// How plain history output is decorated beyond the entry lines themselves.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ListingStyle {
+    /// List entries under a heading per [`DateBucket`], in history order within each.
+    pub group: bool,
+    /// Bold headings and dim ages, as ANSI escapes.
+    pub color: bool,
+}
+
+/// Writes `entries` as plain `slyboard history` lists them, ages relative to `now`.
+pub fn write_history_listing<W: Write>(
+    out: &mut W,
+    entries: &[(usize, &ClipboardEntry)],
+    style: ListingStyle,
+    now: u64,
+) -> io::Result<()> {
+    write_listing(out, entries, style, now, |entry| {
+        date_bucket(entry.captured_at(), now)
+    })
+}
+
+fn write_listing<W: Write>(
+    out: &mut W,
+    entries: &[(usize, &ClipboardEntry)],
+    style: ListingStyle,
+    now: u64,
+    bucket: impl Fn(&ClipboardEntry) -> DateBucket,
+) -> io::Result<()> {
+    let mut entries = entries.to_vec();
+    if style.group {
+        // Stable, so a clock change can't split a bucket into several sections.
+        entries.sort_by_key(|(_, entry)| bucket(entry));
+    }
+    let mut current = None;
+    for (id, entry) in entries {
+        if style.group {
+            let entry_bucket = bucket(entry);
+            if current != Some(entry_bucket) {
+                if current.is_some() {
+                    writeln!(out)?;
+                }
+                writeln!(out, "{}", paint(entry_bucket.label(), BOLD, style.color))?;
+                current = Some(entry_bucket);
+            }
+        }
+        let line = render_default_history_line(id, entry);
+        match entry.captured_at() {
+            Some(captured_at) => {
+                let age = paint(&age_suffix(captured_at, now), DIM, style.color);
+                writeln!(out, "{line} {age}")?;
+            }
+            None => writeln!(out, "{line}")?,
+        }
+    }
+    Ok(())
+}
+
+/// "· 4h ago", or "· now" within the last minute.
+fn age_suffix(captured_at: u64, now: u64) -> String {
+    match format_relative_age(captured_at, now) {
+        age if age == "now" => "· now".to_string(),
+        age => format!("· {age} ago"),
+    }
+}
+
+fn paint(text: &str, escape: &str, color: bool) -> String {
+    if color {
+        format!("{escape}{text}{RESET}")
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_listing, ListingStyle};
+    use crate::clipboard::age::date_bucket_with_offset;
+    use crate::clipboard::ClipboardEntry;
+    use crate::testing::text;
+
+    const NOW: u64 = 1_700_000_000;
+    const HOUR: u64 = 3600;
+
+    fn listing(style: ListingStyle) -> String {
+        let history = [
+            text("newest").with_captured_at(Some(NOW - 30)),
+            text("this morning").with_captured_at(Some(NOW - 4 * HOUR)),
+            text("last night").with_captured_at(Some(NOW - 23 * HOUR)),
+            text("before timestamps"),
+            text("last month").with_captured_at(Some(NOW - 30 * 24 * HOUR)),
+        ];
+        let entries: Vec<(usize, &ClipboardEntry)> = history
+            .iter()
+            .enumerate()
+            .map(|(position, entry)| (4 - position, entry))
+            .collect();
+        let mut out = Vec::new();
+        // 2023-11-14 22:13 UTC, so 23 hours earlier is the day before.
+        let bucket =
+            |entry: &ClipboardEntry| date_bucket_with_offset(entry.captured_at(), NOW, |_| 0);
+        write_listing(&mut out, &entries, style, NOW, bucket).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn appends_ages_and_groups_only_when_asked() {
+        assert_eq!(
+            listing(ListingStyle::default()),
+            "4: newest (6 chars) · now\n\
+             3: this morning (12 chars) · 4h ago\n\
+             2: last night (10 chars) · 23h ago\n\
+             1: before timestamps (17 chars)\n\
+             0: last month (10 chars) · 30d ago\n"
+        );
+        assert_eq!(
+            listing(ListingStyle {
+                group: true,
+                color: false
+            }),
+            "Today\n\
+             4: newest (6 chars) · now\n\
+             3: this morning (12 chars) · 4h ago\n\
+             \n\
+             Yesterday\n\
+             2: last night (10 chars) · 23h ago\n\
+             \n\
+             Older\n\
+             1: before timestamps (17 chars)\n\
+             0: last month (10 chars) · 30d ago\n"
+        );
+    }
+
+    #[test]
+    fn colors_headings_and_ages() {
+        let colored = listing(ListingStyle {
+            group: true,
+            color: true,
+        });
+        assert!(
+            colored.starts_with("\x1b[1mToday\x1b[0m\n4: newest (6 chars) \x1b[2m· now\x1b[0m\n")
+        );
+        assert!(
+            colored.contains("\n1: before timestamps (17 chars)\n"),
+            "no age to dim"
+        );
+    }
+}
@@ -0,0 +1,299 @@
+//! Text entries longer than `clipboard.text.preview_limit_bytes`. History keeps only their
+//! first bytes as a preview, so pickers, the database and its journal stay small; the whole
+//! text goes to `texts/` next to the database, named by a digest of its exact bytes, and is
+//! read back when the entry is put on the clipboard again.
+//!
+//! A file lives as long as an entry in history or the trash refers to it. Slots keep the
+//! whole text themselves, as they outlive both.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::clipboard::dedup::dedup_text;
+use crate::clipboard::digest::StableHasher;
+use crate::clipboard::storage::{io_error, long_texts_dir};
+use crate::clipboard::ClipboardEntry;
+use crate::error::Result;
+
+const TEXT_EXTENSION: &str = "txt";
+
+/// What a text entry cut to a preview says about the whole text.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FullText {
+    /// [`text_digest`] of the whole text. Deduplication compares it instead of the preview.
+    pub digest: String,
+    /// [`file_digest`] of the whole text, naming its file. Texts saved before it existed have
+    /// none and are named by `digest`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// UTF-8 length of the whole text.
+    pub bytes: u64,
+}
+
+impl FullText {
+    /// The name of the whole text's file, without extension.
+    pub fn file_stem(&self) -> &str {
+        self.file.as_deref().unwrap_or(&self.digest)
+    }
+}
+
+/// Sixteen hex digits identifying `value` as deduplication sees it, the same in every process.
+pub fn text_digest(value: &str) -> String {
+    let mut hasher = StableHasher::default();
+    hasher.write(dedup_text(value).as_bytes());
+    format!("{:016x}", hasher.finish())
+}
+
+/// Sixteen hex digits identifying `value` byte for byte, so copies that deduplication takes
+/// for the same text still get a file each.
+pub fn file_digest(value: &str) -> String {
+    let mut hasher = StableHasher::default();
+    hasher.write(value.as_bytes());
+    format!("{:016x}", hasher.finish())
+}
+
+/// The longest start of `value` within `limit` bytes that doesn't split a character.
+pub fn preview_of(value: &str, limit: usize) -> &str {
+    let mut end = limit.min(value.len());
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+/// The `texts/` directory.
+#[derive(Debug, Clone)]
+pub struct LongTextStore {
+    dir: PathBuf,
+}
+
+impl LongTextStore {
+    pub fn for_database(database_path: &Path) -> Self {
+        Self::new(long_texts_dir(database_path))
+    }
+
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn path_for(&self, full_text: &FullText) -> PathBuf {
+        self.dir
+            .join(format!("{}.{TEXT_EXTENSION}", full_text.file_stem()))
+    }
+
+    /// Cuts `entry` to a preview of `limit` bytes if it is text longer than that, after saving
+    /// the whole text.
+    pub fn spill(&self, mut entry: ClipboardEntry, limit: usize) -> Result<ClipboardEntry> {
+        let ClipboardEntry::Text {
+            value, full_text, ..
+        } = &mut entry
+        else {
+            return Ok(entry);
+        };
+        if full_text.is_some() || value.len() <= limit {
+            return Ok(entry);
+        }
+        let full = FullText {
+            digest: text_digest(value),
+            file: Some(file_digest(value)),
+            bytes: value.len() as u64,
+        };
+        let path = self.path_for(&full);
+        fs::create_dir_all(&self.dir).map_err(io_error("create long text directory", &self.dir))?;
+        // Through a temporary file, so a restore running meanwhile never reads half a text.
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, value.as_bytes()).map_err(io_error("write long text", &temp))?;
+        fs::rename(&temp, &path).map_err(io_error("write long text", &path))?;
+        let preview_len = preview_of(value, limit).len();
+        value.truncate(preview_len);
+        *full_text = Some(full);
+        Ok(entry)
+    }
+
+    /// `entry` with its whole text back in place of the preview; entries kept whole are
+    /// borrowed as they are.
+    pub fn restore<'a>(&self, entry: &'a ClipboardEntry) -> Result<Cow<'a, ClipboardEntry>> {
+        let Some(full) = entry.full_text() else {
+            return Ok(Cow::Borrowed(entry));
+        };
+        let path = self.path_for(full);
+        let whole = fs::read_to_string(&path).map_err(io_error("read long text", &path))?;
+        let mut entry = entry.clone();
+        if let ClipboardEntry::Text {
+            value, full_text, ..
+        } = &mut entry
+        {
+            *value = whole;
+            *full_text = None;
+        }
+        Ok(Cow::Owned(entry))
+    }
+
+    /// Deletes the saved texts of `entries` that no entry in `referenced` still needs, e.g.
+    /// the ones a record evicted. Returns how many files were deleted.
+    pub fn remove_for<'a>(
+        &self,
+        entries: impl IntoIterator<Item = &'a ClipboardEntry>,
+        referenced: &HashSet<String>,
+    ) -> Result<usize> {
+        let mut removed = 0;
+        for full in entries.into_iter().filter_map(ClipboardEntry::full_text) {
+            if referenced.contains(full.file_stem()) {
+                continue;
+            }
+            let path = self.path_for(full);
+            match fs::remove_file(&path) {
+                Ok(()) => removed += 1,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(source) => return Err(io_error("remove long text", &path)(source)),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Deletes every saved text whose file stem isn't in `referenced`, along with stray temporary
+    /// files of an interrupted write. Returns how many files were deleted.
+    pub fn cleanup(&self, referenced: &HashSet<String>) -> Result<usize> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(source) => return Err(io_error("read long text directory", &self.dir)(source)),
+        };
+        let mut removed = 0;
+        for entry in entries {
+            let path = entry
+                .map_err(io_error("read long text directory", &self.dir))?
+                .path();
+            let kept = path.extension().is_some_and(|ext| ext == TEXT_EXTENSION)
+                && path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .is_some_and(|stem| referenced.contains(stem));
+            if kept || !path.is_file() {
+                continue;
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => removed += 1,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(source) => return Err(io_error("remove long text", &path)(source)),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Whether any text is saved, so callers can skip gathering references when none is.
+    pub fn is_empty(&self) -> bool {
+        fs::read_dir(&self.dir).map_or(true, |mut entries| entries.next().is_none())
+    }
+}
+
+/// File stems of the saved texts `entries` refer to.
+pub fn referenced_files<'a>(
+    entries: impl IntoIterator<Item = &'a ClipboardEntry>,
+) -> HashSet<String> {
+    entries
+        .into_iter()
+        .filter_map(|entry| entry.full_text().map(|full| full.file_stem().to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{file_digest, preview_of, referenced_files, text_digest, FullText, LongTextStore};
+    use crate::clipboard::ClipboardEntry;
+    use crate::testing::{raw_image, text, ScratchStore};
+
+    #[test]
+    fn previews_end_on_a_character_boundary() {
+        assert_eq!(preview_of("héllo", 2), "h");
+        assert_eq!(preview_of("héllo", 3), "hé");
+        assert_eq!(preview_of("hi", 10), "hi");
+    }
+
+    #[test]
+    fn spills_long_text_and_restores_it_whole() {
+        let scratch = ScratchStore::new("long-text-spill");
+        let store = LongTextStore::for_database(&scratch.database_path());
+        let whole = "ünïcode ".repeat(100);
+
+        assert_eq!(store.spill(text("short"), 16).unwrap(), text("short"));
+        let image = raw_image(1, 1, [0; 4]);
+        assert_eq!(store.spill(image.clone(), 1).unwrap(), image);
+
+        let cut = store.spill(text(&whole), 14).unwrap();
+        let full = cut.full_text().expect("cut to a preview").clone();
+        assert_eq!(full.bytes, whole.len() as u64);
+        assert_eq!(full.digest, text_digest(&whole));
+        assert_eq!(full.file, Some(file_digest(&whole)));
+        assert!(matches!(&cut, ClipboardEntry::Text { value, .. } if value == "ünïcode ün"));
+        assert_eq!(store.spill(cut.clone(), 14).unwrap(), cut, "already cut");
+
+        assert_eq!(store.restore(&cut).unwrap().into_owned(), text(&whole));
+        assert!(cut.content_eq(&text(&whole)));
+        assert_eq!(cut.content_id(), text(&whole).content_id());
+    }
+
+    #[test]
+    fn removes_texts_no_longer_referenced() {
+        let scratch = ScratchStore::new("long-text-cleanup");
+        let store = LongTextStore::for_database(&scratch.database_path());
+        assert!(store.is_empty());
+        let kept = store.spill(text(&"a".repeat(64)), 8).unwrap();
+        let evicted = store.spill(text(&"b".repeat(64)), 8).unwrap();
+        std::fs::write(store.dir().join("stray.tmp"), "partial").unwrap();
+
+        let referenced = referenced_files([&kept]);
+        assert_eq!(store.remove_for([&kept, &evicted], &referenced).unwrap(), 1);
+        assert!(store.restore(&evicted).is_err());
+        assert_eq!(store.cleanup(&referenced).unwrap(), 1, "the stray file");
+        assert!(store.restore(&kept).is_ok());
+
+        assert_eq!(store.cleanup(&HashSet::new()).unwrap(), 1);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn keeps_each_exact_copy_in_its_own_file() {
+        let scratch = ScratchStore::new("long-text-exact");
+        let store = LongTextStore::for_database(&scratch.database_path());
+        let plain = "x".repeat(64);
+        let padded = format!("{plain}\n");
+
+        let first = store.spill(text(&plain), 8).unwrap();
+        let second = store.spill(text(&padded), 8).unwrap();
+        assert!(
+            first.content_eq(&second),
+            "deduplication still sees one text"
+        );
+        assert_eq!(store.restore(&first).unwrap().into_owned(), text(&plain));
+        assert_eq!(store.restore(&second).unwrap().into_owned(), text(&padded));
+
+        // Saved before files were named by their exact bytes.
+        let legacy = FullText {
+            digest: text_digest(&plain),
+            file: None,
+            bytes: plain.len() as u64,
+        };
+        std::fs::write(store.path_for(&legacy), &plain).unwrap();
+        let mut legacy_entry = first.clone();
+        if let ClipboardEntry::Text { full_text, .. } = &mut legacy_entry {
+            *full_text = Some(legacy);
+        }
+        assert_eq!(
+            store.restore(&legacy_entry).unwrap().into_owned(),
+            text(&plain)
+        );
+    }
+}
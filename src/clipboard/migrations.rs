@@ -0,0 +1,241 @@
+//! Ordered upgrades of the history snapshot (`history.json`). Each migration takes a parsed
+//! document one version forward, so a database from any older slyboard reaches
+//! [`CURRENT_VERSION`] step by step before it is deserialized.
+//!
+//! - 0: `{"history": ["text", ...]}`, plain strings from the first releases. A file synced
+//!   between machines on different releases may mix them with tagged entries.
+//! - 1: tagged entries (`{"kind": "text", ...}`) without a `version` field; images may hold
+//!   raw pixbuf rows.
+//! - 2: adds `version`; images are PNG-encoded.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, OnceLock};
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::clipboard::dedup::DedupSettings;
+use crate::clipboard::state::push_history_entry;
+use crate::clipboard::{ClipboardEntry, ImageEncoding};
+
+pub const CURRENT_VERSION: u32 = 2;
+
+/// Which end of a version 0 string list is newest, set by `storage.legacy_order`. Plain
+/// strings carry nothing to tell it from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LegacyOrder {
+    #[default]
+    NewestFirst,
+    NewestLast,
+}
+
+static LEGACY_ORDER: OnceLock<LegacyOrder> = OnceLock::new();
+
+/// Sets the order [`migrate`] reads version 0 histories in, for the rest of the process. Only
+/// the first call has an effect.
+pub fn set_legacy_order(order: LegacyOrder) {
+    let _ = LEGACY_ORDER.set(order);
+}
+
+type Migration = fn(&mut Vec<Value>, LegacyOrder) -> Result<(), String>;
+
+/// `MIGRATIONS[n]` takes the entries of a version `n` document to version `n + 1`.
+const MIGRATIONS: [Migration; CURRENT_VERSION as usize] = [strings_to_entries, encode_raw_images];
+
+/// The format version of a parsed snapshot. Documents without a `version` field predate it:
+/// any string entry means version 0, anything else version 1.
+pub fn detect_version(document: &Value) -> Result<u32, String> {
+    let object = document
+        .as_object()
+        .ok_or_else(|| "expected a JSON object".to_string())?;
+    match object.get("version") {
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| format!("invalid format version {version}")),
+        None => {
+            let legacy = history(object)?.iter().any(Value::is_string);
+            Ok(if legacy { 0 } else { 1 })
+        }
+    }
+}
+
+/// Upgrades `document` from `version` to [`CURRENT_VERSION`] in place. Versions newer than
+/// this build are the caller's to reject; they are returned unchanged.
+pub fn migrate(document: &mut Value, version: u32) -> Result<(), String> {
+    migrate_in_order(
+        document,
+        version,
+        LEGACY_ORDER.get().copied().unwrap_or_default(),
+    )
+}
+
+fn migrate_in_order(document: &mut Value, version: u32, order: LegacyOrder) -> Result<(), String> {
+    if version >= CURRENT_VERSION {
+        return Ok(());
+    }
+    let Value::Object(object) = document else {
+        return Err("expected a JSON object".to_string());
+    };
+    let Some(Value::Array(entries)) = object.get_mut("history") else {
+        return Err("missing history list".to_string());
+    };
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(entries, order)?;
+    }
+    object.insert("version".to_string(), Value::from(CURRENT_VERSION));
+    Ok(())
+}
+
+fn history(object: &Map<String, Value>) -> Result<&Vec<Value>, String> {
+    object
+        .get("history")
+        .and_then(Value::as_array)
+        .ok_or_else(|| "missing history list".to_string())
+}
+
+/// 0 → 1: plain strings become text entries, newest first as `order` says. Tagged entries
+/// found among them were written by a newer release, so they go before all strings, in their
+/// own order. The result is deduplicated like recorded history, keeping the newest copy, but
+/// never cut to the history limit.
+fn strings_to_entries(entries: &mut Vec<Value>, order: LegacyOrder) -> Result<(), String> {
+    let mut tagged = Vec::new();
+    let mut strings = Vec::new();
+    for entry in entries.drain(..) {
+        match entry {
+            Value::String(value) => strings.push(ClipboardEntry::text(value)),
+            Value::Object(_) => tagged.push(
+                serde_json::from_value::<ClipboardEntry>(entry).map_err(|err| err.to_string())?,
+            ),
+            other => return Err(format!("expected a string or an entry, found {other}")),
+        }
+    }
+    if order == LegacyOrder::NewestLast {
+        strings.reverse();
+    }
+    // Dropped up front: deduplicating would give the newer tagged copy the string's pin state.
+    strings.retain(|string| !tagged.iter().any(|entry| entry.content_eq(string)));
+
+    let mut history = VecDeque::new();
+    for entry in tagged.into_iter().chain(strings).rev() {
+        push_history_entry(
+            &mut history,
+            usize::MAX,
+            Arc::new(entry),
+            DedupSettings::default(),
+        );
+    }
+    for entry in history {
+        entries.push(serde_json::to_value(entry.as_ref()).map_err(|err| err.to_string())?);
+    }
+    Ok(())
+}
+
+/// 1 → 2: raw pixbuf images are PNG-encoded. Layouts PNG can't hold stay raw.
+#[allow(clippy::ptr_arg)] // A `Migration`, whose 0 → 1 step changes the entry count.
+fn encode_raw_images(entries: &mut Vec<Value>, _order: LegacyOrder) -> Result<(), String> {
+    for entry in entries.iter_mut() {
+        if entry.get("kind").and_then(Value::as_str) != Some("image") {
+            continue;
+        }
+        let parsed: ClipboardEntry =
+            serde_json::from_value(entry.clone()).map_err(|err| err.to_string())?;
+        if !matches!(
+            parsed,
+            ClipboardEntry::Image {
+                encoding: ImageEncoding::Raw,
+                ..
+            }
+        ) {
+            continue;
+        }
+        *entry = serde_json::to_value(parsed.into_png_encoded()).map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_version, migrate, migrate_in_order, LegacyOrder, CURRENT_VERSION};
+    use crate::clipboard::ClipboardEntry;
+    use serde_json::json;
+
+    #[test]
+    fn detects_versions_with_and_without_the_field() {
+        assert_eq!(detect_version(&json!({"history": ["a"]})), Ok(0));
+        assert_eq!(
+            detect_version(&json!({"history": [{"kind": "text", "value": "a"}]})),
+            Ok(1)
+        );
+        assert_eq!(detect_version(&json!({"history": []})), Ok(1));
+        assert_eq!(
+            detect_version(&json!({"history": [{"kind": "text", "value": "a"}, "b"]})),
+            Ok(0)
+        );
+        assert_eq!(detect_version(&json!({"version": 7, "history": []})), Ok(7));
+        assert!(detect_version(&json!({"version": "2", "history": []})).is_err());
+        assert!(detect_version(&json!({"entries": []})).is_err());
+        assert!(detect_version(&json!([])).is_err());
+    }
+
+    #[test]
+    fn migrates_step_by_step_to_the_current_version() {
+        let mut document = json!({"history": ["a", "b"]});
+        migrate(&mut document, 0).unwrap();
+        let text = |value| serde_json::to_value(ClipboardEntry::text(value)).unwrap();
+        assert_eq!(
+            document,
+            json!({"version": CURRENT_VERSION, "history": [text("a"), text("b")]})
+        );
+
+        let mut numbers = json!({"history": ["a", 1]});
+        assert!(migrate(&mut numbers, 0).is_err());
+
+        let mut newer = json!({"version": CURRENT_VERSION + 1, "history": [1]});
+        let unchanged = newer.clone();
+        migrate(&mut newer, CURRENT_VERSION + 1).unwrap();
+        assert_eq!(newer, unchanged);
+    }
+
+    #[test]
+    fn legacy_strings_follow_the_configured_order_and_are_deduplicated() {
+        let text = |value| serde_json::to_value(ClipboardEntry::text(value)).unwrap();
+        let legacy = json!({"history": ["old", "dup", "new", "dup"]});
+
+        let mut newest_first = legacy.clone();
+        migrate_in_order(&mut newest_first, 0, LegacyOrder::NewestFirst).unwrap();
+        assert_eq!(
+            newest_first["history"],
+            json!([text("old"), text("dup"), text("new")])
+        );
+
+        let mut newest_last = legacy;
+        migrate_in_order(&mut newest_last, 0, LegacyOrder::NewestLast).unwrap();
+        assert_eq!(
+            newest_last["history"],
+            json!([text("dup"), text("new"), text("old")])
+        );
+    }
+
+    #[test]
+    fn tagged_entries_in_a_legacy_list_are_newer_and_keep_their_metadata() {
+        let pinned = ClipboardEntry::text("shared").with_pinned(true);
+        let mut mixed = json!({"history": [
+            "legacy only",
+            "shared",
+            serde_json::to_value(&pinned).unwrap(),
+            {"kind": "text", "value": "rich only"},
+        ]});
+        migrate_in_order(&mut mixed, 0, LegacyOrder::NewestFirst).unwrap();
+        let text = |value| serde_json::to_value(ClipboardEntry::text(value)).unwrap();
+        assert_eq!(
+            mixed["history"],
+            json!([
+                serde_json::to_value(&pinned).unwrap(),
+                text("rich only"),
+                text("legacy only")
+            ])
+        );
+    }
+}
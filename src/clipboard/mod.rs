@@ -1,6 +1,40 @@
+pub mod age;
 pub mod backend;
+pub mod blacklist;
+pub mod bundle;
+pub mod classify;
+#[cfg(all(target_os = "linux", feature = "wayland"))]
+pub mod data_control;
+pub mod dedup;
+pub mod details;
+pub mod digest;
+pub mod filters;
+pub mod fuzzy;
+pub mod json;
+pub mod klipper;
+pub mod listing;
+pub mod long_text;
+pub mod migrations;
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+pub mod native;
+pub mod ocr;
+pub mod plain;
+pub mod png;
 pub mod poller;
+pub mod preview;
+pub mod redact;
+pub mod retention;
+pub mod slots;
 pub mod state;
 pub mod storage;
+pub mod template;
+pub mod thumbnails;
+pub mod transfer;
+#[cfg(target_os = "linux")]
+pub mod wayland;
+pub mod writer;
 
-pub use state::{ClipboardEntry, SharedClipboardState, DEFAULT_HISTORY_LIMIT};
+pub use state::{
+    CaptureOrigin, ClipboardEntry, HistoryEvent, ImageData, ImageEncoding, PruneOptions,
+    RecordOutcome, SharedClipboardState, DEFAULT_HISTORY_LIMIT,
+};
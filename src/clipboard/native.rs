@@ -0,0 +1,122 @@
+//! The clipboard on Windows and macOS, through `arboard`: text and RGBA images. Neither
+//! announces changes the way Wayland does, so the daemon polls it from
+//! [`spawn_capture_thread`](crate::core::daemon::spawn_capture_thread). Active windows aren't
+//! looked up there yet, so blacklist rules never match.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use anyhow::{anyhow, bail, Context, Result};
+use arboard::Clipboard;
+
+use crate::clipboard::backend::ClipboardBackend;
+use crate::clipboard::{CaptureOrigin, ClipboardEntry, ImageData};
+
+pub struct NativeClipboardBackend {
+    clipboard: RefCell<Clipboard>,
+}
+
+impl NativeClipboardBackend {
+    pub fn new() -> Result<Self> {
+        let clipboard = Clipboard::new().context("failed to open the clipboard")?;
+        Ok(Self {
+            clipboard: RefCell::new(clipboard),
+        })
+    }
+}
+
+impl ClipboardBackend for NativeClipboardBackend {
+    /// Text wins when the clipboard offers both, as it does with the GTK backend.
+    fn read_entry(&self) -> Option<ClipboardEntry> {
+        let mut clipboard = self.clipboard.borrow_mut();
+        if let Ok(text) = clipboard.get_text() {
+            if !text.is_empty() {
+                return Some(ClipboardEntry::text(text));
+            }
+        }
+        let image = clipboard.get_image().ok()?;
+        entry_from_rgba(image.width, image.height, image.bytes.into_owned())
+    }
+
+    fn origin(&self) -> CaptureOrigin {
+        CaptureOrigin::NativePoll
+    }
+}
+
+/// Puts `entry` on the clipboard, normalized while plain-text paste is on. Both platforms keep
+/// what was set after this process exits.
+pub fn set_native_clipboard(entry: &ClipboardEntry) -> Result<()> {
+    let entry = crate::core::plain_paste::entry_for_paste(entry);
+    let mut clipboard = Clipboard::new().context("failed to open the clipboard")?;
+    match entry.as_ref() {
+        ClipboardEntry::Text { value, .. } => clipboard.set_text(value.as_str()),
+        ClipboardEntry::Image { width, height, .. } => clipboard.set_image(arboard::ImageData {
+            width: *width as usize,
+            height: *height as usize,
+            bytes: Cow::Owned(rgba_pixels(&entry)?),
+        }),
+    }
+    .map_err(|err| anyhow!("failed to set the clipboard: {err}"))
+}
+
+fn entry_from_rgba(width: usize, height: usize, pixels: Vec<u8>) -> Option<ClipboardEntry> {
+    let width = i32::try_from(width).ok()?;
+    let height = i32::try_from(height).ok()?;
+    let data = ImageData::new(width, height, 4, width.checked_mul(4)?, pixels).ok()?;
+    Some(ClipboardEntry::image(data))
+}
+
+/// `entry`'s pixels as the tightly packed RGBA rows `arboard` takes: row padding dropped and
+/// RGB made opaque.
+fn rgba_pixels(entry: &ClipboardEntry) -> Result<Vec<u8>> {
+    let ClipboardEntry::Image {
+        width,
+        height,
+        rowstride,
+        channels,
+        ..
+    } = entry
+    else {
+        bail!("text entries cannot be restored as images");
+    };
+    entry.validate_image_geometry()?;
+    let pixels = entry.image_pixels()?;
+    let (width, channels) = (*width as usize, *channels as usize);
+    let mut rgba = Vec::with_capacity(width * *height as usize * 4);
+    for row in 0..*height as usize {
+        let start = row * *rowstride as usize;
+        for pixel in pixels[start..start + width * channels].chunks_exact(channels) {
+            rgba.extend_from_slice(&pixel[..3]);
+            rgba.push(if channels == 4 { pixel[3] } else { u8::MAX });
+        }
+    }
+    Ok(rgba)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{entry_from_rgba, rgba_pixels};
+    use crate::clipboard::{ClipboardEntry, ImageData};
+
+    #[test]
+    fn round_trips_rgba_and_makes_rgb_opaque() {
+        let rgba = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        let entry = entry_from_rgba(2, 1, rgba.clone()).unwrap();
+        assert_eq!(rgba_pixels(&entry).unwrap(), rgba);
+
+        // Two RGB pixels per row, padded to 8 bytes.
+        let rgb = ImageData::new(
+            2,
+            2,
+            3,
+            8,
+            vec![1, 2, 3, 4, 5, 6, 0, 0, 7, 8, 9, 10, 11, 12],
+        )
+        .unwrap();
+        assert_eq!(
+            rgba_pixels(&ClipboardEntry::image(rgb)).unwrap(),
+            vec![1, 2, 3, 255, 4, 5, 6, 255, 7, 8, 9, 255, 10, 11, 12, 255]
+        );
+        assert!(rgba_pixels(&ClipboardEntry::text("x".to_string())).is_err());
+    }
+}
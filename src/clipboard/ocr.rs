@@ -0,0 +1,195 @@
+//! Text recognition for image entries (`slyboard ocr`, and the tray and rofi actions when
+//! `ocr.enabled` is set). The image is piped as PNG into an external command, `tesseract stdin
+//! stdout` by default, and whatever it prints is the recognized text.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+
+use crate::clipboard::age::unix_now;
+use crate::clipboard::{CaptureOrigin, ClipboardEntry, ImageEncoding, SharedClipboardState};
+use crate::config::OcrConfig;
+use crate::core::active_window::ActiveWindowContext;
+
+/// `backend` of the source recorded on recognized text entries.
+pub const OCR_SOURCE_BACKEND: &str = "ocr";
+
+/// Turns PNG bytes into text; a trait so recognition can be tested without an OCR install.
+pub trait OcrEngine {
+    fn recognize(&self, png: &[u8]) -> Result<String>;
+}
+
+/// Runs a program with the PNG on stdin and reads the text from its stdout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOcr {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl CommandOcr {
+    pub fn from_config(config: &OcrConfig) -> Self {
+        Self {
+            program: config.program.clone(),
+            args: config.args.clone(),
+        }
+    }
+}
+
+impl OcrEngine for CommandOcr {
+    fn recognize(&self, png: &[u8]) -> Result<String> {
+        let program = &self.program;
+        let mut child = Command::new(program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to run {program}"))?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .context("OCR command stdin unavailable")?;
+        // Written from another thread, so a command printing before it has read everything
+        // can't fill its stdout pipe and stall both sides.
+        let png = png.to_vec();
+        let writer = thread::spawn(move || stdin.write_all(&png));
+        let output = child
+            .wait_with_output()
+            .with_context(|| format!("failed to wait for {program}"))?;
+        let written = writer.join().expect("OCR stdin writer panicked");
+        if !output.status.success() {
+            bail!("{program} exited with {}", output.status);
+        }
+        written.with_context(|| format!("failed to write the image to {program}"))?;
+        String::from_utf8(output.stdout)
+            .with_context(|| format!("{program} printed text that is not UTF-8"))
+    }
+}
+
+/// The text in an image entry, with the trailing whitespace and page breaks OCR tools print
+/// after it removed. Empty when the command found no text.
+pub fn recognize_entry(engine: &dyn OcrEngine, entry: &ClipboardEntry) -> Result<String> {
+    let png = png_bytes(entry)?;
+    let text = engine.recognize(&png)?;
+    Ok(text.trim_end().to_string())
+}
+
+/// A new text entry for `text` recognized in `entry`, the history entry `id`. Its source keeps
+/// the image's app and names the entry it was derived from in the title.
+pub fn recognized_text_entry(text: String, id: usize, entry: &ClipboardEntry) -> ClipboardEntry {
+    let image_source = entry.source_window();
+    let source = ActiveWindowContext {
+        backend: OCR_SOURCE_BACKEND.to_string(),
+        title: format!("Text recognized in history entry {id}"),
+        app_id: image_source.and_then(|window| window.app_id.clone()),
+        initial_app_id: None,
+        initial_title: image_source.map(|window| window.title.clone()),
+        window_id: None,
+        pid: None,
+        workspace_id: image_source.and_then(|window| window.workspace_id),
+        workspace_name: image_source.and_then(|window| window.workspace_name.clone()),
+        is_xwayland: None,
+        monitor: None,
+        geometry: None,
+    };
+    ClipboardEntry::text(text)
+        .with_source(source)
+        .with_captured_at(Some(unix_now()))
+        .with_captured_by(CaptureOrigin::CliSet)
+}
+
+/// Records `text` recognized in the history entry `id` as a new entry and returns it, to be put
+/// on the clipboard. Fails when no text was recognized, rather than recording an empty entry.
+pub fn record_recognized_text(
+    state: &SharedClipboardState,
+    text: String,
+    id: usize,
+    entry: &ClipboardEntry,
+) -> Result<ClipboardEntry> {
+    if text.is_empty() {
+        bail!("no text was recognized in entry {id}");
+    }
+    let recognized = recognized_text_entry(text, id, entry);
+    state.record(recognized.clone())?;
+    Ok(recognized)
+}
+
+fn png_bytes(entry: &ClipboardEntry) -> Result<Vec<u8>> {
+    if !matches!(entry, ClipboardEntry::Image { .. }) {
+        bail!("only image entries can be recognized");
+    }
+    match entry.clone().into_png_encoded() {
+        ClipboardEntry::Image {
+            encoding: ImageEncoding::Png,
+            pixels,
+            ..
+        } => Ok(pixels),
+        _ => bail!("image layout cannot be encoded as PNG"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{recognize_entry, recognized_text_entry, CommandOcr, OcrEngine};
+    use crate::clipboard::ClipboardEntry;
+    use crate::testing::raw_image;
+    use anyhow::Result;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct FakeOcr {
+        inputs: RefCell<Vec<Vec<u8>>>,
+    }
+
+    impl OcrEngine for FakeOcr {
+        fn recognize(&self, png: &[u8]) -> Result<String> {
+            self.inputs.borrow_mut().push(png.to_vec());
+            Ok("error: connection refused\n\n\x0c".to_string())
+        }
+    }
+
+    #[test]
+    fn pipes_images_as_png_and_trims_the_output() {
+        let ocr = FakeOcr::default();
+        let text = recognize_entry(&ocr, &raw_image(1, 1, [255, 0, 0, 255])).unwrap();
+        assert_eq!(text, "error: connection refused");
+        assert!(ocr.inputs.borrow()[0].starts_with(b"\x89PNG"));
+
+        let err = recognize_entry(&ocr, &ClipboardEntry::text("x")).unwrap_err();
+        assert!(err.to_string().contains("only image entries"), "{err}");
+        assert_eq!(ocr.inputs.borrow().len(), 1);
+    }
+
+    #[test]
+    fn runs_the_configured_command() {
+        // Reads the whole image and prints its PNG signature's name back.
+        let ocr = CommandOcr {
+            program: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "head -c 4 | tail -c 3; cat >/dev/null".to_string(),
+            ],
+        };
+        assert_eq!(
+            recognize_entry(&ocr, &raw_image(1, 1, [255, 0, 0, 255])).unwrap(),
+            "PNG"
+        );
+
+        let failing = CommandOcr {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), "exit 3".to_string()],
+        };
+        assert!(recognize_entry(&failing, &raw_image(1, 1, [255, 0, 0, 255])).is_err());
+    }
+
+    #[test]
+    fn recognized_text_names_the_image_it_came_from() {
+        let entry =
+            recognized_text_entry("hello".to_string(), 4, &raw_image(1, 1, [255, 0, 0, 255]));
+        let source = entry.source_window().unwrap();
+        assert_eq!(source.backend, "ocr");
+        assert_eq!(source.title, "Text recognized in history entry 4");
+        assert!(entry.captured_at().is_some());
+    }
+}
@@ -0,0 +1,75 @@
+//! The text written back to the clipboard in plain-text paste mode (`slyboard plain-paste on`).
+//! Entries are only ever stored as plain text, so this normalizes whitespace: line endings
+//! become `\n` and trailing whitespace is removed from every line and from the end.
+
+use std::borrow::Cow;
+
+use crate::clipboard::ClipboardEntry;
+
+pub fn plain_text(value: &str) -> Cow<'_, str> {
+    let normalized = value.replace("\r\n", "\n").replace('\r', "\n");
+    let stripped = normalized
+        .split('\n')
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n");
+    let stripped = stripped.trim_end();
+    if stripped == value {
+        Cow::Borrowed(value)
+    } else {
+        Cow::Owned(stripped.to_string())
+    }
+}
+
+/// `entry` with its text passed through [`plain_text`]; images are returned unchanged.
+pub fn plain_entry(entry: &ClipboardEntry) -> Cow<'_, ClipboardEntry> {
+    let ClipboardEntry::Text { value, .. } = entry else {
+        return Cow::Borrowed(entry);
+    };
+    match plain_text(value) {
+        Cow::Borrowed(_) => Cow::Borrowed(entry),
+        Cow::Owned(plain) => {
+            let mut entry = entry.clone();
+            if let ClipboardEntry::Text { value, .. } = &mut entry {
+                *value = plain;
+            }
+            Cow::Owned(entry)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{plain_entry, plain_text};
+    use crate::clipboard::{ClipboardEntry, ImageData};
+    use std::borrow::Cow;
+
+    #[test]
+    fn normalizes_line_endings_and_trailing_whitespace() {
+        assert_eq!(
+            plain_text("first  \r\nsecond\t\rthird \n\n \n"),
+            "first\nsecond\nthird"
+        );
+        assert_eq!(plain_text("  indented\n\tkept"), "  indented\n\tkept");
+        assert_eq!(plain_text(" \n "), "");
+    }
+
+    #[test]
+    fn borrows_text_that_is_already_plain() {
+        assert!(matches!(plain_text("a\n  b"), Cow::Borrowed(_)));
+        let entry = ClipboardEntry::text("clean");
+        assert!(matches!(plain_entry(&entry), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn rewrites_only_text_entries() {
+        let mut entry = ClipboardEntry::text("copied \r\n");
+        entry.set_pinned(true);
+        let plain = plain_entry(&entry);
+        assert!(plain.content_eq(&ClipboardEntry::text("copied")));
+        assert!(plain.is_pinned());
+
+        let image = ClipboardEntry::image(ImageData::new(1, 1, 4, 4, vec![0; 4]).unwrap());
+        assert!(matches!(plain_entry(&image), Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,238 @@
+//! PNG encoding and decoding for stored clipboard images, on top of the `png` crate.
+//!
+//! Stored images are 8-bit RGB or RGBA. Decoding also takes the other layouts a selection
+//! owner may offer (palette, grayscale, 16-bit) and converts them to one of those two.
+
+use anyhow::{bail, ensure, Context, Result};
+use png::{AdaptiveFilterType, BitDepth, ColorType, Compression, Decoder, Transformations};
+
+/// Upper bound on decoded pixel data, so a corrupt header can't request absurd allocations.
+const MAX_DECODED_BYTES: usize = 512 * 1024 * 1024;
+
+/// Pixels decoded from a PNG, tightly packed (`rowstride == width * channels`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u8,
+    pub pixels: Vec<u8>,
+}
+
+impl DecodedImage {
+    pub fn rowstride(&self) -> usize {
+        self.width as usize * self.channels as usize
+    }
+}
+
+/// Encodes 8-bit pixels with 3 (RGB) or 4 (RGBA) channels. Each row starts `rowstride` bytes
+/// after the previous one, so GDK's row padding is dropped here.
+pub fn encode(
+    width: u32,
+    height: u32,
+    channels: u8,
+    rowstride: usize,
+    pixels: &[u8],
+) -> Result<Vec<u8>> {
+    let color_type = match channels {
+        3 => ColorType::Rgb,
+        4 => ColorType::Rgba,
+        other => bail!("unsupported channel count for PNG encoding: {other}"),
+    };
+    ensure!(width > 0 && height > 0, "cannot encode an empty image");
+    let row_len = width as usize * channels as usize;
+    ensure!(rowstride >= row_len, "rowstride is shorter than a row");
+    let needed = rowstride * (height as usize - 1) + row_len;
+    ensure!(
+        pixels.len() >= needed,
+        "pixel buffer holds {} bytes, expected at least {needed}",
+        pixels.len()
+    );
+
+    let packed: Vec<u8> = pixels
+        .chunks(rowstride)
+        .take(height as usize)
+        .flat_map(|row| &row[..row_len])
+        .copied()
+        .collect();
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+    encoder.set_color(color_type);
+    encoder.set_depth(BitDepth::Eight);
+    encoder.set_compression(Compression::Default);
+    encoder.set_adaptive_filter(AdaptiveFilterType::Adaptive);
+    let mut writer = encoder
+        .write_header()
+        .context("failed to write the PNG header")?;
+    writer
+        .write_image_data(&packed)
+        .context("failed to encode PNG data")?;
+    writer.finish().context("failed to finish the PNG")?;
+    Ok(png)
+}
+
+/// Reads `(width, height, channels)` from the header without inflating any pixel data.
+/// `channels` is what [`decode`] returns for this PNG.
+pub fn read_header(png: &[u8]) -> Result<(u32, u32, u8)> {
+    let reader = decoder(png).read_info().context("not a readable PNG")?;
+    let info = reader.info();
+    Ok((info.width, info.height, output_channels(&reader)?))
+}
+
+pub fn decode(png: &[u8]) -> Result<DecodedImage> {
+    let mut reader = decoder(png).read_info().context("not a readable PNG")?;
+    let channels = output_channels(&reader)?;
+    let size = reader.output_buffer_size();
+    ensure!(size <= MAX_DECODED_BYTES, "PNG dimensions are too large");
+
+    let mut buffer = vec![0; size];
+    let frame = reader
+        .next_frame(&mut buffer)
+        .context("failed to decode PNG data")?;
+    ensure!(frame.width > 0 && frame.height > 0, "PNG has no pixels");
+    buffer.truncate(frame.buffer_size());
+    let pixels = match frame.color_type {
+        ColorType::Grayscale => buffer.iter().flat_map(|&gray| [gray; 3]).collect(),
+        ColorType::GrayscaleAlpha => buffer
+            .chunks_exact(2)
+            .flat_map(|pixel| [pixel[0], pixel[0], pixel[0], pixel[1]])
+            .collect(),
+        _ => buffer,
+    };
+    Ok(DecodedImage {
+        width: frame.width,
+        height: frame.height,
+        channels,
+        pixels,
+    })
+}
+
+/// A decoder that expands palettes and low bit depths, strips 16-bit samples to 8, and
+/// verifies every checksum, zlib's Adler-32 included.
+fn decoder(png: &[u8]) -> Decoder<&[u8]> {
+    let mut decoder = Decoder::new(png);
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    decoder.ignore_checksums(false);
+    decoder
+}
+
+/// 3 or 4: grayscale comes out as RGB, grayscale with alpha as RGBA.
+fn output_channels(reader: &png::Reader<&[u8]>) -> Result<u8> {
+    match reader.output_color_type() {
+        (ColorType::Rgb | ColorType::Grayscale, BitDepth::Eight) => Ok(3),
+        (ColorType::Rgba | ColorType::GrayscaleAlpha, BitDepth::Eight) => Ok(4),
+        (color, depth) => bail!("unsupported PNG format: {color:?} at {depth:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, read_header};
+
+    fn gradient(width: u32, height: u32, channels: u8, rowstride: usize) -> Vec<u8> {
+        let mut pixels = vec![0xee; rowstride * height as usize];
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                for c in 0..channels as usize {
+                    pixels[y * rowstride + x * channels as usize + c] = (x * 7 + y * 3 + c) as u8;
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn round_trips_padded_rgba_and_rgb_pixels() {
+        for (channels, padding) in [(4u8, 0usize), (4, 3), (3, 1)] {
+            let (width, height) = (37u32, 11u32);
+            let rowstride = width as usize * channels as usize + padding;
+            let pixels = gradient(width, height, channels, rowstride);
+
+            let png = encode(width, height, channels, rowstride, &pixels).expect("encode");
+            assert_eq!(
+                read_header(&png).expect("header"),
+                (width, height, channels)
+            );
+            let decoded = decode(&png).expect("decode");
+            assert_eq!(
+                (decoded.width, decoded.height, decoded.channels),
+                (width, height, channels)
+            );
+            for y in 0..height as usize {
+                let row_len = decoded.rowstride();
+                assert_eq!(
+                    &decoded.pixels[y * row_len..(y + 1) * row_len],
+                    &pixels[y * rowstride..y * rowstride + row_len]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_grayscale_as_rgb() {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, 2, 1);
+        encoder.set_color(png::ColorType::GrayscaleAlpha);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[10, 255, 20, 128]).unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(read_header(&png).unwrap(), (2, 1, 4));
+        let decoded = decode(&png).unwrap();
+        assert_eq!(decoded.pixels, [10, 10, 10, 255, 20, 20, 20, 128]);
+    }
+
+    #[test]
+    fn compresses_flat_screenshots() {
+        let (width, height) = (200u32, 100u32);
+        let pixels = vec![0x40; width as usize * height as usize * 4];
+        let png = encode(width, height, 4, width as usize * 4, &pixels).expect("encode");
+        assert!(png.len() * 20 < pixels.len(), "PNG was {} bytes", png.len());
+    }
+
+    #[test]
+    fn rejects_corrupt_input() {
+        let pixels = gradient(4, 4, 4, 16);
+        let mut png = encode(4, 4, 4, 16, &pixels).expect("encode");
+        assert!(decode(b"not a png").is_err());
+        assert!(read_header(&png[..20]).is_err());
+        let last = png.len() - 20;
+        png[last] ^= 0xff;
+        assert!(decode(&png).is_err());
+        assert!(encode(4, 4, 2, 8, &pixels).is_err());
+        assert!(encode(4, 4, 4, 16, &pixels[..20]).is_err());
+    }
+
+    #[test]
+    fn rejects_a_wrong_zlib_checksum() {
+        let pixels = gradient(4, 4, 4, 16);
+        let png = encode(4, 4, 4, 16, &pixels).expect("encode");
+        // Flip a bit of the Adler-32 at the end of IDAT and patch the chunk's CRC to match,
+        // so only the zlib checksum is wrong.
+        let idat = png.windows(4).position(|kind| kind == b"IDAT").unwrap();
+        let len = u32::from_be_bytes(png[idat - 4..idat].try_into().unwrap()) as usize;
+        let mut tampered = png.clone();
+        tampered[idat + 4 + len - 1] ^= 1;
+        let crc = crc32fast::hash(&tampered[idat..idat + 4 + len]);
+        tampered[idat + 4 + len..idat + 8 + len].copy_from_slice(&crc.to_be_bytes());
+        assert!(decode(&png).is_ok());
+        assert!(decode(&tampered).is_err());
+    }
+
+    #[test]
+    fn refuses_oversized_images_before_allocating() {
+        // A header claiming 100000x100000 RGBA with an empty IDAT behind it.
+        let mut png = encode(1, 1, 4, 4, &[0; 4]).unwrap();
+        png.truncate(8 + 25);
+        png[16..24]
+            .copy_from_slice(&[&100_000u32.to_be_bytes()[..], &100_000u32.to_be_bytes()].concat());
+        let crc = crc32fast::hash(&png[12..29]);
+        png[29..33].copy_from_slice(&crc.to_be_bytes());
+        for kind in [b"IDAT", b"IEND"] {
+            png.extend_from_slice(&[0; 4]);
+            png.extend_from_slice(kind);
+            png.extend_from_slice(&crc32fast::hash(kind).to_be_bytes());
+        }
+        let err = decode(&png).unwrap_err();
+        assert!(format!("{err:#}").contains("too large"), "{err:#}");
+    }
+}
@@ -1,47 +1,346 @@
-use std::cell::RefCell;
+#[cfg(feature = "gtk")]
+use std::cell::{Cell, RefCell};
+use std::ops::ControlFlow;
+#[cfg(feature = "gtk")]
 use std::rc::Rc;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
 use std::time::Duration;
 
-use crate::clipboard::backend::ClipboardBackend;
-use crate::clipboard::ClipboardEntry;
+use crate::clipboard::age::unix_now;
+#[cfg(feature = "gtk")]
+use crate::clipboard::backend::GtkClipboardBackend;
+use crate::clipboard::backend::{Capture, ClipboardBackend};
+use crate::clipboard::blacklist::{window_matches_any, BlacklistRule};
+use crate::clipboard::dedup::DedupSettings;
+use crate::clipboard::filters::ContentFilter;
+use crate::clipboard::retention::{expiry_for, ExpireRule};
+use crate::clipboard::writer::Selection;
+use crate::clipboard::{CaptureOrigin, ClipboardEntry};
+use crate::config::IncognitoPolicy;
 use crate::core::active_window::ActiveWindowContext;
+use crate::core::capture_stats::{CaptureStats, SkipReason};
+use crate::core::self_write::ExpectedWrites;
 
+/// Polls a [`ClipboardBackend`] and decides which clipboard changes become history entries.
+/// It is [`Send`] whenever the backend is, so it can be driven from any thread with
+/// [`run_polling_loop`].
+///
+/// Content equal to the last value seen is not a new copy, with one exception: a value withheld
+/// because of the window it was copied in (blacklist, or incognito with `skip`) is looked at
+/// again when a backend that [reports each copy](ClipboardBackend::reports_each_copy) delivers
+/// it again, so copying the same text from an allowed window afterwards records it. Backends
+/// that read the current clipboard can't see that second copy, and merely focusing another
+/// window must never record the withheld value.
+///
+/// The focused window is looked up before the clipboard is read (see
+/// [`ClipboardBackend::read_capture`]). With [`with_max_window_lag`](Self::with_max_window_lag),
+/// a window looked up longer before the read finished is not recorded as the source.
 pub struct ClipboardPoller<B: ClipboardBackend> {
     backend: B,
     last_seen_value: Option<ClipboardEntry>,
-    active_window_blacklist: Vec<String>,
+    /// `last_seen_value` was withheld because of its source window.
+    last_seen_suppressed: bool,
+    active_window_blacklist: Vec<BlacklistRule>,
+    paused_workspaces: Vec<String>,
+    /// The workspace the last new value was withheld for, if that was the reason.
+    suppressed_workspace: Option<String>,
+    transient_sources: Vec<BlacklistRule>,
+    transient_ttl: Duration,
+    expire_rules: Vec<ExpireRule>,
+    incognito_policy: IncognitoPolicy,
+    incognito_markers: Vec<String>,
+    content_filter: ContentFilter,
+    expected_writes: ExpectedWrites,
+    max_window_lag: Option<Duration>,
+    capture_stats: CaptureStats,
+    /// Why the last poll didn't return what it read, if a rule withheld it.
+    last_skip: Option<SkipReason>,
+    shared_last_seen: Option<SharedLastSeen>,
+    /// Decides whether a value read equals the last one seen.
+    dedup: DedupSettings,
+}
+
+/// The last clipboard value seen by any of several pollers watching the same clipboard
+/// through different backends, or different selections, so each change is handled once, by
+/// whichever poller sees it first: text copied and also left selected is one entry. Clones
+/// share it.
+#[derive(Debug, Clone, Default)]
+pub struct SharedLastSeen {
+    last: Arc<Mutex<Option<Sighting>>>,
+}
+
+#[derive(Debug)]
+struct Sighting {
+    value: ClipboardEntry,
+    /// The backends, and the selection each watches, that have seen `value` so far.
+    seen_by: Vec<(CaptureOrigin, Selection)>,
+}
+
+impl SharedLastSeen {
+    /// Notes that the backend `watcher` saw `value` as new, and tells whether that is a new
+    /// change rather than one another backend saw first. A backend seeing the same value
+    /// again reports another copy.
+    fn first_sighting(
+        &self,
+        value: &ClipboardEntry,
+        watcher: (CaptureOrigin, Selection),
+        dedup: DedupSettings,
+    ) -> bool {
+        let mut last = self.last.lock().unwrap_or_else(PoisonError::into_inner);
+        match &mut *last {
+            Some(sighting)
+                if dedup.same_content(&sighting.value, value)
+                    && !sighting.seen_by.contains(&watcher) =>
+            {
+                sighting.seen_by.push(watcher);
+                false
+            }
+            _ => {
+                *last = Some(Sighting {
+                    value: value.clone(),
+                    seen_by: vec![watcher],
+                });
+                true
+            }
+        }
+    }
 }
 
 impl<B: ClipboardBackend> ClipboardPoller<B> {
-    pub fn new(backend: B, active_window_blacklist: Vec<String>) -> Self {
+    pub fn new(backend: B, active_window_blacklist: Vec<BlacklistRule>) -> Self {
         Self {
             backend,
             last_seen_value: None,
-            active_window_blacklist: normalized_blacklist(active_window_blacklist),
+            last_seen_suppressed: false,
+            active_window_blacklist,
+            paused_workspaces: Vec::new(),
+            suppressed_workspace: None,
+            transient_sources: Vec::new(),
+            transient_ttl: Duration::ZERO,
+            expire_rules: Vec::new(),
+            incognito_policy: IncognitoPolicy::Capture,
+            incognito_markers: Vec::new(),
+            content_filter: ContentFilter::default(),
+            expected_writes: ExpectedWrites::default(),
+            max_window_lag: None,
+            capture_stats: CaptureStats::default(),
+            last_skip: None,
+            shared_last_seen: None,
+            dedup: DedupSettings::default(),
         }
     }
 
-    pub fn poll_once(&mut self) -> Option<ClipboardEntry> {
+    /// Leaves changes another poller sharing `shared_last_seen` saw first to that poller.
+    pub fn with_shared_last_seen(mut self, shared_last_seen: SharedLastSeen) -> Self {
+        self.shared_last_seen = Some(shared_last_seen);
+        self
+    }
+
+    /// Compares clipboard values as `clipboard.dedup` does, so a copy differing only in
+    /// trailing whitespace is new when that whitespace counts.
+    pub fn with_dedup(mut self, dedup: DedupSettings) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    pub fn with_content_filter(mut self, content_filter: ContentFilter) -> Self {
+        self.content_filter = content_filter;
+        self
+    }
+
+    /// Skips clipboard changes announced in `expected_writes`: slyboard's own restores.
+    pub fn with_expected_writes(mut self, expected_writes: ExpectedWrites) -> Self {
+        self.expected_writes = expected_writes;
+        self
+    }
+
+    /// Counts the changes skipped by the poller's own rules in `capture_stats`.
+    pub fn with_capture_stats(mut self, capture_stats: CaptureStats) -> Self {
+        self.capture_stats = capture_stats;
+        self
+    }
+
+    /// Leaves out the source window of entries whose read took longer than `max_lag` after the
+    /// window was looked up, as the user may have switched windows in between. Blacklist,
+    /// incognito and transient rules still apply to that window.
+    pub fn with_max_window_lag(mut self, max_lag: Duration) -> Self {
+        self.max_window_lag = Some(max_lag);
+        self
+    }
+
+    /// Withholds entries copied while a window on one of `workspaces` is focused. Each matches a
+    /// workspace name ignoring case, or a numeric workspace id.
+    pub fn with_paused_workspaces(mut self, workspaces: Vec<String>) -> Self {
+        self.paused_workspaces = normalized_patterns(workspaces);
+        self
+    }
+
+    /// The workspace (name, or id without one) the most recent new clipboard value was
+    /// withheld for, while capture is suspended there.
+    pub fn suppressed_workspace(&self) -> Option<&str> {
+        self.suppressed_workspace.as_deref()
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Why the last [`poll_once`](Self::poll_once) or [`seed`](Self::seed) withheld what it
+    /// read; `None` if it returned an entry or found nothing new.
+    pub fn last_skip(&self) -> Option<SkipReason> {
+        self.last_skip
+    }
+
+    /// Applies `policy` to entries copied from windows whose title contains one of `markers`.
+    pub fn with_incognito_policy(mut self, policy: IncognitoPolicy, markers: Vec<String>) -> Self {
+        self.incognito_policy = policy;
+        self.incognito_markers = normalized_patterns(markers);
+        self
+    }
+
+    /// Marks entries captured from matching windows as transient for `ttl`.
+    pub fn with_transient_sources(mut self, sources: Vec<BlacklistRule>, ttl: Duration) -> Self {
+        self.transient_sources = sources;
+        self.transient_ttl = ttl;
+        self
+    }
+
+    /// Stamps entries captured from windows matching one of `rules` with an expiry.
+    pub fn with_expire_rules(mut self, rules: Vec<ExpireRule>) -> Self {
+        self.expire_rules = rules;
+        self
+    }
+
+    /// Handles whatever is already on the clipboard at startup. It is always remembered as
+    /// seen, so only real changes are polled afterwards; with `capture` it is also returned for
+    /// recording, without a source window since it predates the currently focused one.
+    pub fn seed(&mut self, capture: bool) -> Option<ClipboardEntry> {
+        self.last_skip = None;
         let value = self.backend.read_entry()?;
         if value.is_empty() {
             return None;
         }
 
-        if self.last_seen_value.as_ref() == Some(&value) {
+        self.last_seen_value = Some(value.clone());
+        self.last_seen_suppressed = false;
+        if !self.first_sighting(&value) || !capture {
             return None;
         }
+        if let Some(rule) = self.content_filter.matching_rule(&value) {
+            crate::log_debug!("skipped clipboard entry matching {rule}");
+            self.skip(SkipReason::ContentFilter);
+            return None;
+        }
+        Some(
+            value
+                .with_classified_content()
+                .with_captured_at(Some(unix_now()))
+                .with_captured_by(self.backend.origin())
+                .with_selection(Some(self.backend.selection())),
+        )
+    }
 
-        self.last_seen_value = Some(value.clone());
-        let active_window = self.backend.read_active_window();
-        if should_skip_for_blacklisted_window(active_window.as_ref(), &self.active_window_blacklist)
+    pub fn poll_once(&mut self) -> Option<ClipboardEntry> {
+        self.last_skip = None;
+        let Capture {
+            entry: value,
+            window: active_window,
+            window_lag,
+        } = self.backend.read_capture()?;
+        if value.is_empty() {
+            return None;
+        }
+
+        let copied_again = self.last_seen_suppressed && self.backend.reports_each_copy();
+        if !copied_again
+            && self
+                .last_seen_value
+                .as_ref()
+                .is_some_and(|last_seen| self.dedup.same_content(last_seen, &value))
         {
             return None;
         }
-        Some(value.with_source_window(active_window))
+
+        self.last_seen_value = Some(value.clone());
+        self.last_seen_suppressed = false;
+        if !self.first_sighting(&value) {
+            return None;
+        }
+        self.suppressed_workspace = None;
+        if self.expected_writes.take(&value) {
+            crate::log_debug!("skipped clipboard entry restored by slyboard");
+            self.skip(SkipReason::OwnRestore);
+            return None;
+        }
+        if let Some(rule) = self.content_filter.matching_rule(&value) {
+            crate::log_debug!("skipped clipboard entry matching {rule}");
+            self.skip(SkipReason::ContentFilter);
+            return None;
+        }
+        if window_matches_any(active_window.as_ref(), &self.active_window_blacklist) {
+            self.last_seen_suppressed = true;
+            self.skip(SkipReason::Blacklist);
+            return None;
+        }
+        if let Some(workspace) = paused_workspace(active_window.as_ref(), &self.paused_workspaces) {
+            crate::log_debug!("skipped clipboard entry copied on workspace {workspace}");
+            self.suppressed_workspace = Some(workspace);
+            self.last_seen_suppressed = true;
+            self.skip(SkipReason::PausedWorkspace);
+            return None;
+        }
+        let incognito = title_matches_any(active_window.as_ref(), &self.incognito_markers);
+        if incognito && self.incognito_policy == IncognitoPolicy::Skip {
+            self.last_seen_suppressed = true;
+            self.skip(SkipReason::Incognito);
+            return None;
+        }
+        let transient = window_matches_any(active_window.as_ref(), &self.transient_sources)
+            || (incognito && self.incognito_policy == IncognitoPolicy::Transient);
+        let captured_at = unix_now();
+        let expires_at = expiry_for(&self.expire_rules, active_window.as_ref(), captured_at);
+        let active_window = match self.max_window_lag {
+            Some(max_lag) if window_lag > max_lag => {
+                crate::log_debug!(
+                    "not attributing a clipboard entry read {}ms after the focused window",
+                    window_lag.as_millis()
+                );
+                None
+            }
+            _ => active_window,
+        };
+        let value = value
+            .with_source_window(active_window)
+            .with_classified_content()
+            .with_captured_at(Some(captured_at))
+            .with_expires_at(expires_at)
+            .with_captured_by(self.backend.origin())
+            .with_selection(Some(self.backend.selection()));
+        if transient {
+            return Some(value.with_transient_ttl(self.transient_ttl));
+        }
+        Some(value)
+    }
+
+    fn skip(&mut self, reason: SkipReason) {
+        self.last_skip = Some(reason);
+        self.capture_stats.skipped(reason);
+    }
+
+    /// See [`SharedLastSeen`]; without one every change is this poller's.
+    fn first_sighting(&self, value: &ClipboardEntry) -> bool {
+        self.shared_last_seen.as_ref().is_none_or(|shared| {
+            shared.first_sighting(
+                value,
+                (self.backend.origin(), self.backend.selection()),
+                self.dedup,
+            )
+        })
     }
 }
 
-fn normalized_blacklist(values: Vec<String>) -> Vec<String> {
+fn normalized_patterns(values: Vec<String>) -> Vec<String> {
     values
         .into_iter()
         .map(|value| value.trim().to_lowercase())
@@ -49,40 +348,163 @@ fn normalized_blacklist(values: Vec<String>) -> Vec<String> {
         .collect()
 }
 
-fn should_skip_for_blacklisted_window(
-    active_window: Option<&ActiveWindowContext>,
-    blacklist: &[String],
-) -> bool {
-    if blacklist.is_empty() {
-        return false;
-    }
-
+fn title_matches_any(active_window: Option<&ActiveWindowContext>, patterns: &[String]) -> bool {
     let Some(active_window) = active_window else {
         return false;
     };
+    let title = active_window.title.to_lowercase();
+    patterns.iter().any(|pattern| title.contains(pattern))
+}
 
-    let app_id = active_window
-        .app_id
-        .as_ref()
-        .map(|value| value.trim().to_lowercase());
-    let title = active_window.title.trim().to_lowercase();
-    blacklist.iter().any(|blocked| {
-        app_id.as_ref().is_some_and(|value| value == blocked) || title.contains(blocked)
+/// The workspace of `active_window` if it is one of `patterns`; no window or no workspace
+/// never matches.
+fn paused_workspace(
+    active_window: Option<&ActiveWindowContext>,
+    patterns: &[String],
+) -> Option<String> {
+    let active_window = active_window?;
+    let name = active_window
+        .workspace_name
+        .as_deref()
+        .map(str::to_lowercase)
+        .filter(|name| !name.is_empty());
+    let matches = patterns.iter().any(|pattern| {
+        name.as_deref() == Some(pattern.as_str())
+            || active_window
+                .workspace_id
+                .is_some_and(|id| pattern.parse::<i64>() == Ok(id))
+    });
+    matches.then(|| {
+        active_window
+            .workspace_name
+            .clone()
+            .filter(|name| !name.is_empty())
+            .or_else(|| active_window.workspace_id.map(|id| id.to_string()))
+            .unwrap_or_default()
     })
 }
 
-#[cfg(target_os = "linux")]
-pub fn start_gtk_polling<B, F>(
-    poller: Rc<RefCell<ClipboardPoller<B>>>,
+/// Polls `poller` every `interval` on the calling thread and passes each new entry to `sink`,
+/// until `sink` returns [`ControlFlow::Break`]. What gets through is decided by the poller
+/// alone, exactly as in the tray's GTK loop.
+pub fn run_polling_loop<B: ClipboardBackend>(
+    mut poller: ClipboardPoller<B>,
+    interval: Duration,
+    mut sink: impl FnMut(ClipboardEntry) -> ControlFlow<()>,
+) {
+    loop {
+        if let Some(value) = poller.poll_once() {
+            if sink(value).is_break() {
+                return;
+            }
+        }
+        thread::sleep(interval);
+    }
+}
+
+/// Entries captured by a poller on its own thread, from [`poll_stream`].
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct PollStream {
+    receiver: tokio::sync::mpsc::UnboundedReceiver<ClipboardEntry>,
+}
+
+#[cfg(feature = "tokio")]
+impl futures_core::Stream for PollStream {
+    type Item = ClipboardEntry;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<ClipboardEntry>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Runs [`run_polling_loop`] on a new thread and streams what it captures. Works with any
+/// executor; the thread exits at the first capture after the stream is dropped.
+#[cfg(feature = "tokio")]
+pub fn poll_stream<B>(poller: ClipboardPoller<B>, interval: Duration) -> PollStream
+where
+    B: ClipboardBackend + Send + 'static,
+{
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    thread::spawn(move || {
+        run_polling_loop(poller, interval, |value| match sender.send(value) {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(_) => ControlFlow::Break(()),
+        })
+    });
+    PollStream { receiver }
+}
+
+/// Reads the GTK clipboard every `interval` without blocking the main loop: each read is
+/// answered through the loop (see [`GtkClipboardBackend::start_read`]), and a new one only
+/// starts once the last has finished.
+#[cfg(feature = "gtk")]
+pub fn start_gtk_polling<F>(
+    poller: Rc<RefCell<ClipboardPoller<GtkClipboardBackend>>>,
     interval: Duration,
+    on_change: F,
+) where
+    F: FnMut(ClipboardEntry) + 'static,
+{
+    let on_change = Rc::new(RefCell::new(on_change));
+    let reading = Rc::new(Cell::new(false));
+    gtk::glib::timeout_add_local(interval, move || {
+        if reading.replace(true) {
+            return gtk::glib::ControlFlow::Continue;
+        }
+        let (finished, reading, on_change) = (
+            Rc::clone(&poller),
+            Rc::clone(&reading),
+            Rc::clone(&on_change),
+        );
+        poller.borrow().backend().start_read(move || {
+            // An owner in this process can answer before `start_read` returns, while the
+            // poller is still borrowed, so the result is picked up on the next turn.
+            gtk::glib::idle_add_local_once(move || {
+                let value = finished.borrow_mut().poll_once();
+                reading.set(false);
+                if let Some(value) = value {
+                    (on_change.borrow_mut())(value);
+                }
+            });
+        });
+        gtk::glib::ControlFlow::Continue
+    });
+}
+
+/// Drives `poller` from a wake socket instead of a timer: every byte read means one entry
+/// is waiting in the backend's channel (see `clipboard::wayland::spawn_selection_listener`).
+#[cfg(feature = "gtk")]
+pub fn start_wake_polling<B, F>(
+    poller: Rc<RefCell<ClipboardPoller<B>>>,
+    wake: std::os::unix::net::UnixStream,
     mut on_change: F,
 ) where
     B: ClipboardBackend + 'static,
     F: FnMut(ClipboardEntry) + 'static,
 {
-    gtk::glib::timeout_add_local(interval, move || {
-        if let Some(value) = poller.borrow_mut().poll_once() {
-            on_change(value);
+    use std::io::Read;
+    use std::os::fd::AsRawFd;
+
+    let fd = wake.as_raw_fd();
+    let mut wake = wake;
+    gtk::glib::source::unix_fd_add_local(fd, gtk::glib::IOCondition::IN, move |_, condition| {
+        if condition.intersects(gtk::glib::IOCondition::HUP | gtk::glib::IOCondition::ERR) {
+            return gtk::glib::ControlFlow::Break;
+        }
+
+        let mut pending = [0u8; 64];
+        let count = match wake.read(&mut pending) {
+            Ok(0) | Err(_) => return gtk::glib::ControlFlow::Break,
+            Ok(count) => count,
+        };
+        for _ in 0..count {
+            if let Some(value) = poller.borrow_mut().poll_once() {
+                on_change(value);
+            }
         }
         gtk::glib::ControlFlow::Continue
     });
@@ -90,112 +512,596 @@ pub fn start_gtk_polling<B, F>(
 
 #[cfg(test)]
 mod tests {
-    use super::ClipboardPoller;
-    use crate::clipboard::backend::ClipboardBackend;
-    use crate::clipboard::ClipboardEntry;
+    use super::{run_polling_loop, ClipboardPoller};
+    use crate::clipboard::blacklist::BlacklistRule;
+    use crate::clipboard::filters::ContentFilter;
+    use crate::clipboard::retention::ExpireRule;
+    use crate::clipboard::{CaptureOrigin, ClipboardEntry};
+    use crate::config::{ClipboardConfig, IncognitoPolicy, WindowMatchMode};
     use crate::core::active_window::ActiveWindowContext;
-    use std::cell::RefCell;
+    use crate::core::capture_stats::{CaptureStats, SkipReason};
+    use crate::core::self_write::ExpectedWrites;
+    use crate::testing::{text, window, BackendCall, FakeClipboardBackend};
+    use std::collections::BTreeMap;
+    use std::ops::ControlFlow;
+    use std::time::Duration;
 
-    struct MockBackend {
-        entries: RefCell<Vec<Option<ClipboardEntry>>>,
-        active_windows: RefCell<Vec<Option<ActiveWindowContext>>>,
+    fn rules(values: &[&str], mode: WindowMatchMode) -> Vec<BlacklistRule> {
+        values
+            .iter()
+            .map(|value| BlacklistRule::parse(value, mode).unwrap())
+            .collect()
     }
 
-    impl MockBackend {
-        fn new(
-            entries: Vec<Option<ClipboardEntry>>,
-            active_windows: Vec<Option<ActiveWindowContext>>,
-        ) -> Self {
-            Self {
-                entries: RefCell::new(entries),
-                active_windows: RefCell::new(active_windows),
-            }
+    /// Whether copying from `window` is captured with `blacklist` matched in `mode`.
+    fn captured(window: &ActiveWindowContext, blacklist: &str, mode: WindowMatchMode) -> bool {
+        let backend = FakeClipboardBackend::new()
+            .with_entries([text("secret")])
+            .with_active_windows([Some(window.clone())]);
+        ClipboardPoller::new(backend, rules(&[blacklist], mode))
+            .poll_once()
+            .is_some()
+    }
+
+    #[test]
+    fn skips_entry_when_app_id_is_blacklisted() {
+        let window = window(
+            Some("org.keepassxc.KeePassXC"),
+            "Passwords.kdbx - KeePassXC",
+        );
+
+        for (blacklist, mode) in [
+            ("org.KeePassXC.KeePassXC", WindowMatchMode::Mixed),
+            ("org.keepassxc.keepassxc", WindowMatchMode::Exact),
+            ("keepassxc", WindowMatchMode::Substring),
+            ("org.keepassxc.*", WindowMatchMode::Glob),
+        ] {
+            assert!(
+                !captured(&window, blacklist, mode),
+                "{blacklist:?} should skip in {mode:?} mode"
+            );
         }
+        assert!(captured(&window, "org.keepassxc", WindowMatchMode::Exact));
+        assert!(captured(&window, "org.keepassxc", WindowMatchMode::Glob));
     }
 
-    impl ClipboardBackend for MockBackend {
-        fn read_entry(&self) -> Option<ClipboardEntry> {
-            self.entries.borrow_mut().remove(0)
+    #[test]
+    fn skips_entry_when_title_contains_blacklisted_value() {
+        let window = window(None, "Slack | direct messages");
+
+        for (blacklist, mode) in [
+            ("slack", WindowMatchMode::Mixed),
+            ("Slack | Direct Messages", WindowMatchMode::Exact),
+            ("direct", WindowMatchMode::Substring),
+            ("slack |*", WindowMatchMode::Glob),
+        ] {
+            assert!(
+                !captured(&window, blacklist, mode),
+                "{blacklist:?} should skip in {mode:?} mode"
+            );
         }
+        assert!(captured(&window, "slack", WindowMatchMode::Exact));
+        assert!(captured(&window, "slack", WindowMatchMode::Glob));
+    }
+
+    #[test]
+    fn accepts_entry_when_window_not_blacklisted() {
+        let window = window(Some("kitty"), "Terminal");
 
-        fn read_active_window(&self) -> Option<ActiveWindowContext> {
-            self.active_windows.borrow_mut().remove(0)
+        for mode in [
+            WindowMatchMode::Mixed,
+            WindowMatchMode::Exact,
+            WindowMatchMode::Substring,
+            WindowMatchMode::Glob,
+        ] {
+            assert!(
+                captured(&window, "slack", mode),
+                "non-blacklisted window should be captured in {mode:?} mode"
+            );
         }
+        // Mixed mode only compares app ids in full.
+        assert!(captured(&window, "kit", WindowMatchMode::Mixed));
+        assert!(!captured(&window, "kit", WindowMatchMode::Substring));
+    }
+
+    #[test]
+    fn marks_entry_transient_when_source_matches() {
+        let backend = FakeClipboardBackend::new()
+            .with_entries([text("password")])
+            .with_active_windows([Some(window(Some("keepassxc"), "KeePassXC"))]);
+        let mut poller = ClipboardPoller::new(backend, Vec::new()).with_transient_sources(
+            rules(&["keepassxc"], WindowMatchMode::Mixed),
+            Duration::from_secs(30),
+        );
+
+        let entry = poller
+            .poll_once()
+            .expect("transient entry should be captured");
+        assert!(entry.is_transient(), "entry should be marked transient");
     }
 
-    fn text(value: &str) -> ClipboardEntry {
-        ClipboardEntry::Text {
-            value: value.to_string(),
-            source_window: None,
+    #[test]
+    fn stamps_expiry_on_entries_from_matching_windows() {
+        let backend = FakeClipboardBackend::new()
+            .with_entries([text("iban"), text("notes")])
+            .with_active_windows([
+                Some(window(Some("firefox"), "Online Banking - Firefox")),
+                Some(window(Some("kitty"), "Terminal")),
+            ]);
+        let mut poller =
+            ClipboardPoller::new(backend, Vec::new()).with_expire_rules(vec![ExpireRule {
+                rule: BlacklistRule::parse("banking", WindowMatchMode::Substring).unwrap(),
+                ttl: Duration::from_secs(300),
+            }]);
+
+        let banking = poller.poll_once().expect("banking entry is captured");
+        assert!(!banking.is_transient(), "expiring entries are saved");
+        assert_eq!(
+            banking.expires_at(),
+            banking.captured_at().map(|captured_at| captured_at + 300)
+        );
+        let terminal = poller.poll_once().expect("terminal entry is captured");
+        assert_eq!(terminal.expires_at(), None);
+    }
+
+    #[test]
+    fn tags_captured_text_with_content_hint() {
+        let backend = FakeClipboardBackend::new()
+            .with_entries([text("https://example.com")])
+            .with_active_windows([None]);
+        let mut poller = ClipboardPoller::new(backend, Vec::new());
+
+        let entry = poller.poll_once().expect("entry should be captured");
+        assert_eq!(entry.content_hint(), Some("url"));
+    }
+
+    fn titled_window(title: &str) -> ActiveWindowContext {
+        window(Some("browser"), title)
+    }
+
+    fn poll_with_incognito_policy(title: &str, policy: IncognitoPolicy) -> Option<ClipboardEntry> {
+        let backend = FakeClipboardBackend::new()
+            .with_entries([text("copied")])
+            .with_active_windows([Some(titled_window(title))]);
+        let markers = ClipboardConfig::default().incognito_title_markers();
+        let mut poller =
+            ClipboardPoller::new(backend, Vec::new()).with_incognito_policy(policy, markers);
+        poller.poll_once()
+    }
+
+    #[test]
+    fn skips_each_default_incognito_marker() {
+        for title in [
+            "Example — Mozilla Firefox Private Browsing",
+            "New Tab - Google Chrome (Incognito)",
+            "InPrivate - Microsoft Edge",
+            "new tab - google chrome (incognito)",
+        ] {
+            assert!(
+                poll_with_incognito_policy(title, IncognitoPolicy::Skip).is_none(),
+                "{title} should be treated as a private window"
+            );
         }
     }
 
     #[test]
-    fn skips_entry_when_app_id_is_blacklisted() {
-        let backend = MockBackend::new(
-            vec![Some(text("secret"))],
-            vec![Some(ActiveWindowContext {
-                backend: "hyprctl".to_string(),
-                title: "KeePassXC".to_string(),
-                app_id: Some("keepassxc".to_string()),
-                initial_app_id: None,
-                initial_title: None,
-                window_id: None,
-                pid: None,
-                workspace_id: None,
-                workspace_name: None,
-                is_xwayland: None,
-            })],
+    fn incognito_transient_policy_marks_entry_transient() {
+        let entry = poll_with_incognito_policy("Private Browsing", IncognitoPolicy::Transient)
+            .expect("entry should be captured");
+        assert!(entry.is_transient());
+    }
+
+    #[test]
+    fn incognito_capture_policy_keeps_entry() {
+        let entry = poll_with_incognito_policy("(Incognito)", IncognitoPolicy::Capture)
+            .expect("entry should be captured");
+        assert!(!entry.is_transient());
+    }
+
+    #[test]
+    fn regular_windows_are_not_incognito() {
+        let entry = poll_with_incognito_policy("Inbox - Mozilla Firefox", IncognitoPolicy::Skip)
+            .expect("entry should be captured");
+        assert!(!entry.is_transient());
+    }
+
+    #[test]
+    fn ignore_rules_skip_once_and_do_not_recheck_same_value() {
+        let backend =
+            FakeClipboardBackend::new().with_entries([text("ghp_secret"), text("ghp_secret")]);
+        let mut poller = ClipboardPoller::new(backend, Vec::new())
+            .with_content_filter(ContentFilter::new(vec!["ghp_".into()], Vec::new()));
+
+        assert!(poller.poll_once().is_none(), "prefix rule should skip");
+        assert!(
+            poller.poll_once().is_none(),
+            "same value should not be re-read"
+        );
+        assert_eq!(poller.last_seen_value, Some(text("ghp_secret")));
+    }
+
+    #[test]
+    fn ignore_rules_apply_before_blacklist_and_allow_other_values() {
+        let backend = FakeClipboardBackend::new()
+            .with_entries([text("-----BEGIN KEY"), text("hello"), text("world")])
+            .with_active_windows([
+                Some(titled_window("Slack")),
+                Some(titled_window("Slack")),
+                Some(titled_window("Notes")),
+            ]);
+        let mut poller = ClipboardPoller::new(backend, rules(&["slack"], WindowMatchMode::Mixed))
+            .with_content_filter(ContentFilter::new(Vec::new(), vec!["-----BEGIN*".into()]));
+
+        assert!(poller.poll_once().is_none(), "glob rule should skip");
+        assert_eq!(poller.last_skip(), Some(SkipReason::ContentFilter));
+        assert!(
+            poller.poll_once().is_none(),
+            "blacklist still applies to values passing the content filter"
         );
-        let mut poller = ClipboardPoller::new(backend, vec!["KeePassXC".to_string()]);
+        assert_eq!(poller.last_skip(), Some(SkipReason::Blacklist));
+        let entry = poller.poll_once().expect("other values are captured");
+        assert!(entry.content_eq(&text("world")));
+    }
 
-        let entry = poller.poll_once();
-        assert!(entry.is_none(), "blacklisted app id should be skipped");
+    #[test]
+    fn same_content_copied_again_from_an_allowed_window_is_recorded_by_event_backends() {
+        let slack = window(Some("slack"), "general");
+        let editor = window(Some("code"), "main.rs");
+        let backend = FakeClipboardBackend::new()
+            .with_entries([text("token"), text("token"), text("token")])
+            .with_active_windows([Some(slack), Some(editor.clone())])
+            .reporting_each_copy();
+        let mut poller = ClipboardPoller::new(backend, rules(&["slack"], WindowMatchMode::Mixed));
+
+        assert!(poller.poll_once().is_none(), "blacklisted copy is withheld");
+        let entry = poller
+            .poll_once()
+            .expect("the copy from the editor is recorded");
+        assert_eq!(entry.source_window(), Some(&editor));
+        assert_eq!(entry.captured_by(), Some(CaptureOrigin::WaylandEvent));
+        assert!(
+            poller.poll_once().is_none(),
+            "a recorded value copied again is not recorded twice"
+        );
     }
 
     #[test]
-    fn skips_entry_when_title_contains_blacklisted_value() {
-        let backend = MockBackend::new(
-            vec![Some(text("token"))],
-            vec![Some(ActiveWindowContext {
-                backend: "xdotool".to_string(),
-                title: "Slack | direct messages".to_string(),
-                app_id: None,
-                initial_app_id: None,
-                initial_title: None,
-                window_id: None,
-                pid: None,
-                workspace_id: None,
-                workspace_name: None,
-                is_xwayland: None,
-            })],
+    fn polled_clipboards_never_record_a_withheld_value_on_focus_change() {
+        let backend = FakeClipboardBackend::new()
+            .with_entries([text("token"), text("token")])
+            .with_active_windows([Some(window(Some("slack"), "general"))]);
+        backend
+            .handle()
+            .push_active_window(Some(window(Some("code"), "main.rs")));
+        let mut poller = ClipboardPoller::new(backend, rules(&["slack"], WindowMatchMode::Mixed));
+
+        assert!(poller.poll_once().is_none());
+        assert!(
+            poller.poll_once().is_none(),
+            "the editor is focused now, but the value wasn't copied again"
         );
-        let mut poller = ClipboardPoller::new(backend, vec!["slack".to_string()]);
+    }
+
+    #[test]
+    fn different_content_after_a_blacklisted_copy_is_recorded() {
+        for each_copy in [false, true] {
+            let backend = FakeClipboardBackend::new()
+                .with_entries([text("token"), text("notes")])
+                .with_active_windows([
+                    Some(window(Some("slack"), "general")),
+                    Some(window(Some("code"), "main.rs")),
+                ]);
+            let backend = if each_copy {
+                backend.reporting_each_copy()
+            } else {
+                backend
+            };
+            let mut poller =
+                ClipboardPoller::new(backend, rules(&["slack"], WindowMatchMode::Mixed));
 
-        let entry = poller.poll_once();
-        assert!(entry.is_none(), "blacklisted title should be skipped");
+            assert!(poller.poll_once().is_none());
+            let entry = poller.poll_once().expect("notes are recorded");
+            assert!(entry.content_eq(&text("notes")));
+            let origin = if each_copy {
+                CaptureOrigin::WaylandEvent
+            } else {
+                CaptureOrigin::GtkPoll
+            };
+            assert_eq!(entry.captured_by(), Some(origin));
+        }
     }
 
     #[test]
-    fn accepts_entry_when_window_not_blacklisted() {
-        let backend = MockBackend::new(
-            vec![Some(text("hello"))],
-            vec![Some(ActiveWindowContext {
-                backend: "hyprctl".to_string(),
-                title: "Terminal".to_string(),
-                app_id: Some("kitty".to_string()),
-                initial_app_id: None,
-                initial_title: None,
-                window_id: None,
-                pid: None,
-                workspace_id: None,
-                workspace_name: None,
-                is_xwayland: None,
-            })],
-        );
-        let mut poller = ClipboardPoller::new(backend, vec!["slack".to_string()]);
-
-        let entry = poller.poll_once();
-        assert!(entry.is_some(), "non-blacklisted window should be captured");
+    fn forms_differing_in_trailing_newline_are_not_new_copies() {
+        // A clipboard alternating between the terminal's and the browser's form of a copy.
+        let backend = FakeClipboardBackend::new()
+            .with_entries([
+                text("ls -la\n"),
+                text("ls -la"),
+                text("ls -la\n"),
+                text("ls -la"),
+                text("pwd"),
+            ])
+            .with_active_windows([
+                Some(titled_window("Terminal")),
+                Some(titled_window("Terminal")),
+            ]);
+        let mut poller = ClipboardPoller::new(backend, Vec::new());
+
+        let captured: Vec<_> = (0..5).filter_map(|_| poller.poll_once()).collect();
+        assert_eq!(captured.len(), 2);
+        assert!(matches!(&captured[0], ClipboardEntry::Text { value, .. } if value == "ls -la\n"));
+        assert!(captured[1].content_eq(&text("pwd")));
+    }
+
+    #[test]
+    fn own_restores_are_not_captured_but_the_next_copy_is() {
+        let backend = FakeClipboardBackend::new()
+            .with_entries([
+                text("restored"),
+                text("restored"),
+                text("copied right after"),
+                text("restored"),
+            ])
+            .with_active_windows([Some(titled_window("Editor")), Some(titled_window("Editor"))]);
+        let expected_writes = ExpectedWrites::default();
+        let mut poller =
+            ClipboardPoller::new(backend, Vec::new()).with_expected_writes(expected_writes.clone());
+
+        expected_writes.expect(&text("restored"));
+        assert!(
+            poller.poll_once().is_none(),
+            "the restore is not a new copy"
+        );
+        assert!(poller.poll_once().is_none(), "and stays seen");
+        assert_eq!(poller.last_seen_value, Some(text("restored")));
+        assert!(poller
+            .poll_once()
+            .is_some_and(|entry| entry.content_eq(&text("copied right after"))));
+        assert!(
+            poller.poll_once().is_some(),
+            "copying the restored value again is captured"
+        );
+    }
+
+    #[test]
+    fn seed_marks_existing_content_seen_without_recording_it() {
+        let backend = FakeClipboardBackend::new()
+            .with_entries([text("stale"), text("stale"), text("fresh")])
+            .with_active_windows([None, Some(titled_window("Terminal"))]);
+        let mut poller = ClipboardPoller::new(backend, Vec::new());
+
+        assert!(poller.seed(false).is_none());
+        assert!(
+            poller.poll_once().is_none(),
+            "existing content is not a change"
+        );
+        let entry = poller.poll_once().expect("first real change is captured");
+        assert_eq!(entry.source_window(), Some(&titled_window("Terminal")));
+    }
+
+    #[test]
+    fn seed_can_capture_existing_content_without_a_source() {
+        let backend = FakeClipboardBackend::new().with_entries([text("stale"), text("stale")]);
+        let mut poller = ClipboardPoller::new(backend, Vec::new());
+
+        let entry = poller.seed(true).expect("existing content is captured");
+        assert_eq!(entry.source_window(), None);
+        assert_eq!(entry.content_hint(), Some("plain"));
+        assert!(poller.poll_once().is_none());
+    }
+
+    fn on_workspace(id: Option<i64>, name: Option<&str>) -> ActiveWindowContext {
+        ActiveWindowContext {
+            workspace_id: id,
+            workspace_name: name.map(str::to_string),
+            ..window(Some("kitty"), "~")
+        }
+    }
+
+    #[test]
+    fn skips_copies_made_on_paused_workspaces() {
+        let windows = [
+            Some(on_workspace(Some(9), Some("9"))),
+            Some(on_workspace(Some(4), Some("Private"))),
+            Some(on_workspace(Some(2), Some("web"))),
+            Some(on_workspace(Some(-98), Some("special:private"))),
+            Some(on_workspace(None, None)),
+            None,
+            Some(on_workspace(Some(9), None)),
+        ];
+        let backend = FakeClipboardBackend::new()
+            .with_entries((0..windows.len()).map(|index| text(&index.to_string())))
+            .with_active_windows(windows);
+        let mut poller = ClipboardPoller::new(backend, Vec::new())
+            .with_paused_workspaces(vec!["9".into(), " private ".into()]);
+
+        let mut suppressed = Vec::new();
+        let captured: Vec<bool> = (0..7)
+            .map(|_| {
+                let captured = poller.poll_once().is_some();
+                suppressed.push(poller.suppressed_workspace().map(str::to_string));
+                captured
+            })
+            .collect();
+        assert_eq!(captured, [false, false, true, true, true, true, false]);
+        assert_eq!(
+            suppressed,
+            [
+                Some("9".to_string()),
+                Some("Private".to_string()),
+                None,
+                None,
+                None,
+                None,
+                Some("9".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_each_reason_a_change_was_skipped() {
+        let expected_writes = ExpectedWrites::default();
+        expected_writes.expect(&text("restored"));
+        let backend = FakeClipboardBackend::new()
+            .with_entries(["restored", "secret", "password: hunter2", "ok"].map(text))
+            .with_active_windows([None, Some(window(Some("keepassxc"), "Vault")), None, None]);
+        let stats = CaptureStats::default();
+        let mut poller =
+            ClipboardPoller::new(backend, rules(&["keepassxc"], WindowMatchMode::Exact))
+                .with_expected_writes(expected_writes)
+                .with_content_filter(ContentFilter::new(vec!["password:".into()], Vec::new()))
+                .with_capture_stats(stats.clone());
+
+        let captured = (0..4).filter_map(|_| poller.poll_once()).count();
+        assert_eq!(captured, 1);
+        assert_eq!(
+            stats.snapshot().skipped,
+            BTreeMap::from([
+                (SkipReason::OwnRestore, 1),
+                (SkipReason::ContentFilter, 1),
+                (SkipReason::Blacklist, 1),
+            ])
+        );
+    }
+
+    #[test]
+    fn reads_the_focused_window_before_the_clipboard() {
+        let backend = FakeClipboardBackend::new()
+            .with_entries([text("a"), text("a")])
+            .with_active_windows([Some(titled_window("Editor"))]);
+        let handle = backend.handle();
+        let mut poller = ClipboardPoller::new(backend, Vec::new());
+
+        assert!(poller.poll_once().is_some());
+        assert!(poller.poll_once().is_none());
+        assert!(poller.poll_once().is_none());
+        use BackendCall::{ReadActiveWindow, ReadEntry};
+        assert_eq!(
+            handle.calls(),
+            [
+                ReadActiveWindow,
+                ReadEntry,
+                ReadActiveWindow,
+                ReadEntry,
+                ReadActiveWindow,
+                ReadEntry
+            ]
+        );
+    }
+
+    #[test]
+    fn windows_looked_up_too_long_before_the_read_are_not_the_source() {
+        let slow_read = |entries: [ClipboardEntry; 2]| {
+            FakeClipboardBackend::new()
+                .with_entries(entries)
+                .with_active_windows([
+                    Some(window(Some("kitty"), "~")),
+                    Some(window(Some("keepassxc"), "Passwords")),
+                ])
+                .with_read_delay(Duration::from_millis(20))
+        };
+
+        let mut poller = ClipboardPoller::new(slow_read([text("a"), text("b")]), Vec::new());
+        let entry = poller.poll_once().expect("captured");
+        assert!(entry.source_window().is_some(), "no limit by default");
+
+        let mut poller = ClipboardPoller::new(
+            slow_read([text("a"), text("b")]),
+            rules(&["keepassxc"], WindowMatchMode::Exact),
+        )
+        .with_max_window_lag(Duration::from_millis(5));
+        let entry = poller.poll_once().expect("still captured");
+        assert_eq!(entry.source_window(), None);
+        assert!(
+            poller.poll_once().is_none(),
+            "a stale window is still checked against the blacklist"
+        );
+
+        let mut poller = ClipboardPoller::new(slow_read([text("a"), text("b")]), Vec::new())
+            .with_max_window_lag(Duration::from_secs(60));
+        let entry = poller.poll_once().expect("captured");
+        assert_eq!(entry.source_window(), Some(&window(Some("kitty"), "~")));
+    }
+
+    /// Copies of repeated, blacklisted and new content, ending with "stop".
+    fn scripted_copies() -> FakeClipboardBackend {
+        let backend = FakeClipboardBackend::new();
+        let clipboard = backend.handle();
+        let kitty = || Some(window(Some("kitty"), "~"));
+        clipboard.copy(text("a"), kitty());
+        clipboard.copy(text("a"), kitty());
+        clipboard.copy(text("secret"), Some(window(Some("keepassxc"), "Passwords")));
+        clipboard.copy(text("b"), kitty());
+        clipboard.copy(text("a"), kitty());
+        clipboard.copy(text("stop"), kitty());
+        backend
+    }
+
+    fn summary(entry: &ClipboardEntry) -> (String, Option<String>) {
+        let ClipboardEntry::Text { value, .. } = entry else {
+            panic!("expected text");
+        };
+        let app_id = entry.source_window().and_then(|w| w.app_id.clone());
+        (value.clone(), app_id)
+    }
+
+    #[test]
+    fn polling_loop_captures_what_per_tick_polling_does() {
+        fn assert_send<T: Send>() {}
+        assert_send::<ClipboardPoller<FakeClipboardBackend>>();
+
+        let blacklist = || rules(&["keepassxc"], WindowMatchMode::Exact);
+        let mut ticked = ClipboardPoller::new(scripted_copies(), blacklist());
+        let expected: Vec<_> = (0..6)
+            .filter_map(|_| ticked.poll_once())
+            .map(|entry| summary(&entry))
+            .collect();
+        let kitty = Some("kitty".to_string());
+        assert_eq!(
+            expected,
+            [
+                ("a".to_string(), kitty.clone()),
+                ("b".to_string(), kitty.clone()),
+                ("a".to_string(), kitty.clone()),
+                ("stop".to_string(), kitty),
+            ]
+        );
+
+        let poller = ClipboardPoller::new(scripted_copies(), blacklist());
+        let looped = std::thread::spawn(move || {
+            let mut captured = Vec::new();
+            run_polling_loop(poller, Duration::ZERO, |entry| {
+                captured.push(summary(&entry));
+                if captured.last().is_some_and(|(value, _)| value == "stop") {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            });
+            captured
+        })
+        .join()
+        .expect("polling thread");
+        assert_eq!(looped, expected);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn stream_yields_captured_entries() {
+        use futures_core::Stream;
+        use std::task::{Context, Poll, Waker};
+
+        let poller = ClipboardPoller::new(scripted_copies(), Vec::new());
+        let mut stream = Box::pin(super::poll_stream(poller, Duration::ZERO));
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut captured = Vec::new();
+        while captured.len() < 5 {
+            match stream.as_mut().poll_next(&mut cx) {
+                Poll::Ready(entry) => captured.push(summary(&entry.expect("stream is open")).0),
+                Poll::Pending => std::thread::sleep(Duration::from_millis(1)),
+            }
+        }
+        assert_eq!(captured, ["a", "secret", "b", "a", "stop"]);
     }
 }
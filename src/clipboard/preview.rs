@@ -0,0 +1,102 @@
+use crate::clipboard::classify::ContentHint;
+use crate::clipboard::ClipboardEntry;
+
+/// Builds a single-line preview of `value`: line breaks are escaped, other control characters
+/// become spaces, and the result is cut to `char_limit` characters with a trailing `...`.
+pub fn preview_text(value: &str, char_limit: usize) -> String {
+    let mut sanitized = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '\n' => sanitized.push_str("\\n"),
+            '\r' => sanitized.push_str("\\r"),
+            ch if ch.is_control() => sanitized.push(' '),
+            ch => sanitized.push(ch),
+        }
+    }
+
+    if sanitized.chars().count() <= char_limit {
+        return sanitized;
+    }
+
+    let truncated: String = sanitized.chars().take(char_limit).collect();
+    format!("{truncated}...")
+}
+
+/// Formats a byte count with binary units and one decimal, e.g. `512 B`, `1.5 KiB`, `7.9 MiB`.
+pub fn format_byte_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    // Round first, so 1023.96 KiB becomes 1.0 MiB rather than 1024.0 KiB.
+    while (size * 10.0).round() / 10.0 >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// Prefixes `label` with the entry's content hint, e.g. `[url] https://...`. Plain text and
+/// entries captured before hints existed are left untagged.
+pub fn with_hint_tag(entry: &ClipboardEntry, label: String) -> String {
+    match entry.content_hint() {
+        Some(hint) if hint != ContentHint::Plain.as_str() => format!("[{hint}] {label}"),
+        _ => label,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_byte_size, preview_text, with_hint_tag};
+    use crate::clipboard::ClipboardEntry;
+
+    #[test]
+    fn formats_byte_sizes_with_binary_units() {
+        assert_eq!(format_byte_size(0), "0 B");
+        assert_eq!(format_byte_size(1023), "1023 B");
+        assert_eq!(format_byte_size(1024), "1.0 KiB");
+        assert_eq!(format_byte_size(1536), "1.5 KiB");
+        assert_eq!(format_byte_size(1920 * 1080 * 4), "7.9 MiB");
+        assert_eq!(format_byte_size(1024 * 1024 - 1), "1.0 MiB");
+        assert_eq!(format_byte_size(3 << 30), "3.0 GiB");
+        assert_eq!(format_byte_size(u64::MAX), "16777216.0 TiB");
+    }
+
+    #[test]
+    fn keeps_short_values_intact() {
+        assert_eq!(preview_text("hello", 10), "hello");
+    }
+
+    #[test]
+    fn escapes_line_breaks_and_replaces_control_characters() {
+        assert_eq!(preview_text("a\nb\r\tc\u{7}", 20), "a\\nb\\r c ");
+    }
+
+    #[test]
+    fn truncates_multi_byte_text_on_char_boundaries() {
+        assert_eq!(preview_text("héllo wörld", 7), "héllo w...");
+        assert_eq!(preview_text("日本語のテキスト", 3), "日本語...");
+        assert_eq!(preview_text("🦀🦀🦀", 3), "🦀🦀🦀");
+    }
+
+    #[test]
+    fn tags_labels_with_non_plain_hints() {
+        let tagged = |value: &str| {
+            let entry = ClipboardEntry::text(value);
+            with_hint_tag(&entry, value.to_string())
+        };
+        let classified = |value: &str| {
+            let entry = ClipboardEntry::text(value).with_classified_content();
+            with_hint_tag(&entry, value.to_string())
+        };
+
+        assert_eq!(tagged("https://example.com"), "https://example.com");
+        assert_eq!(
+            classified("https://example.com"),
+            "[url] https://example.com"
+        );
+        assert_eq!(classified("hello there"), "hello there");
+    }
+}
@@ -0,0 +1,143 @@
+//! `--redact` output, for sharing history in bug reports: every entry keeps its structure,
+//! timestamps and source app, while copied text, labels, window titles and image pixels are
+//! replaced by placeholders that only tell equal contents apart.
+
+use std::borrow::Cow;
+use std::hash::Hasher;
+
+use crate::clipboard::digest::StableHasher;
+use crate::clipboard::json::SerializableClipboardEntry;
+use crate::core::active_window::ActiveWindowContext;
+
+/// What redacted output shows in place of `value`, e.g. "⟨redacted 7f3a… 42 chars⟩". The
+/// four hex digits fold a [`StableHasher`] digest of the text, so equal texts share them.
+pub fn redacted_text(value: &str) -> String {
+    let mut hasher = StableHasher::default();
+    hasher.write(value.as_bytes());
+    let digest = hasher.finish();
+    let folded = (digest ^ digest >> 16 ^ digest >> 32 ^ digest >> 48) as u16;
+    format!("⟨redacted {folded:04x}… {} chars⟩", value.chars().count())
+}
+
+/// `window` with its titles redacted; app ids and geometry stay.
+pub fn redacted_window(window: &ActiveWindowContext) -> ActiveWindowContext {
+    ActiveWindowContext {
+        title: redacted_text(&window.title),
+        initial_title: window.initial_title.as_deref().map(redacted_text),
+        ..window.clone()
+    }
+}
+
+impl SerializableClipboardEntry<'_> {
+    /// This entry as `--redact` prints it. Image entries keep their dimensions and
+    /// `pixel_bytes` but never their pixels.
+    pub fn redacted(mut self) -> Self {
+        let (source_window, label) = match &mut self {
+            Self::Text {
+                value,
+                source_window,
+                label,
+                ..
+            } => {
+                *value = Cow::Owned(redacted_text(value));
+                (source_window, label)
+            }
+            Self::Image {
+                pixels,
+                source_window,
+                label,
+                ..
+            } => {
+                *pixels = None;
+                (source_window, label)
+            }
+        };
+        if let Some(window) = source_window {
+            *window = Cow::Owned(redacted_window(window));
+        }
+        if let Some(text) = label {
+            *text = Cow::Owned(redacted_text(text));
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::redacted_text;
+    use crate::clipboard::details::write_entry_details;
+    use crate::clipboard::json::write_json_history;
+    use crate::clipboard::ClipboardEntry;
+    use crate::core::active_window::ActiveWindowContext;
+    use crate::testing::{raw_image, text_from, window};
+
+    const SECRETS: [&str; 4] = ["hunter2", "prod password", "alice@example.com", "draft.md"];
+
+    fn history() -> Vec<ClipboardEntry> {
+        let source = ActiveWindowContext {
+            initial_title: Some("draft.md".to_string()),
+            ..window(Some("firefox"), "Inbox - alice@example.com")
+        };
+        vec![
+            text_from("token: hunter2", source.clone())
+                .with_captured_at(Some(1_700_000_000))
+                .with_label(Some("prod password".to_string())),
+            raw_image(2, 1, [0xab; 4]).with_source(source),
+        ]
+    }
+
+    #[test]
+    fn placeholders_tell_equal_texts_apart_without_their_content() {
+        let placeholder = redacted_text("token: hunter2");
+        assert!(placeholder.starts_with("⟨redacted "), "{placeholder}");
+        assert!(placeholder.ends_with("… 14 chars⟩"), "{placeholder}");
+        assert_eq!(placeholder, redacted_text("token: hunter2"));
+        assert_ne!(placeholder, redacted_text("token: hunter3"));
+        assert_eq!(
+            redacted_text("é").chars().count(),
+            placeholder.chars().count() - 1
+        );
+    }
+
+    #[test]
+    fn redacted_json_keeps_structure_but_no_content() {
+        let history = history();
+        let entries: Vec<(usize, &ClipboardEntry)> = history.iter().enumerate().collect();
+        let mut out = Vec::new();
+        write_json_history(&mut out, &entries, true, true).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        for secret in SECRETS {
+            assert!(!json.contains(secret), "{secret} leaked: {json}");
+        }
+
+        let parsed: Value = serde_json::from_str(&json).unwrap();
+        let (text, image) = (&parsed[0], &parsed[1]);
+        assert_eq!(text["value"], redacted_text("token: hunter2"));
+        assert_eq!(text["label"], redacted_text("prod password"));
+        assert_eq!(text["captured_at"], 1_700_000_000);
+        assert_eq!(text["size_bytes"], 14);
+        assert_eq!(text["source_window"]["app_id"], "firefox");
+        assert_eq!(
+            text["source_window"]["title"],
+            redacted_text("Inbox - alice@example.com")
+        );
+        assert_eq!(image["width"], 2);
+        assert_eq!(image["pixel_bytes"], 8);
+        assert!(image.get("pixels").is_none(), "even with --images");
+        assert_eq!(image["source_window"], text["source_window"]);
+    }
+
+    #[test]
+    fn redacted_details_print_no_content() {
+        let mut out = Vec::new();
+        write_entry_details(&mut out, 0, &history()[0], true, true, 1_700_000_060).unwrap();
+        let details = String::from_utf8(out).unwrap();
+        for secret in SECRETS {
+            assert!(!details.contains(secret), "{secret} leaked: {details}");
+        }
+        assert!(details.contains("Source window: firefox"), "{details}");
+        assert!(details.ends_with(&format!("\n{}\n", redacted_text("token: hunter2"))));
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clipboard::blacklist::BlacklistRule;
+use crate::clipboard::state::ClipboardEntry;
+use crate::core::active_window::ActiveWindowContext;
+
+/// One `clipboard.retention` rule: entries copied from a matching window are kept at most
+/// `max_entries` deep and at most `ttl` long, on top of the global history limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionRule {
+    pub rule: BlacklistRule,
+    pub max_entries: Option<usize>,
+    pub ttl: Option<Duration>,
+}
+
+/// The `clipboard.retention` rules in config order; an entry follows the first rule matching
+/// its source window, and entries no rule matches follow only the global policy.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    rules: Vec<RetentionRule>,
+}
+
+impl RetentionPolicy {
+    pub fn new(rules: Vec<RetentionRule>) -> Self {
+        Self { rules }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Positions (newest first) of the entries the policy drops at Unix time `now`, oldest
+    /// first so they can be removed one after another. Pinned entries count towards
+    /// `max_entries` but are never dropped, like with the global limit. Entries captured
+    /// before timestamps were recorded never expire.
+    pub fn expired_positions(
+        &self,
+        history: &VecDeque<Arc<ClipboardEntry>>,
+        now: u64,
+    ) -> Vec<usize> {
+        if self.rules.is_empty() {
+            return Vec::new();
+        }
+        let mut kept = vec![0usize; self.rules.len()];
+        let mut expired = Vec::new();
+        for (position, entry) in history.iter().enumerate() {
+            let Some(window) = entry.source_window() else {
+                continue;
+            };
+            let Some(rule_index) = self.rules.iter().position(|rule| rule.rule.matches(window))
+            else {
+                continue;
+            };
+            let rule = &self.rules[rule_index];
+            let too_old = rule.ttl.is_some_and(|ttl| {
+                entry
+                    .captured_at()
+                    .is_some_and(|captured_at| now.saturating_sub(captured_at) > ttl.as_secs())
+            });
+            let too_many = rule
+                .max_entries
+                .is_some_and(|max_entries| kept[rule_index] >= max_entries);
+            if !entry.is_pinned() && (too_old || too_many) {
+                expired.push(position);
+            } else {
+                kept[rule_index] += 1;
+            }
+        }
+        expired.reverse();
+        expired
+    }
+}
+
+/// One `clipboard.active_window.expire_rules` rule: entries copied from a matching window
+/// leave history `ttl` after capture. Unlike a retention TTL, the expiry is stamped on the
+/// entry when it is captured, so it holds even if the rule changes later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpireRule {
+    pub rule: BlacklistRule,
+    pub ttl: Duration,
+}
+
+/// When an entry captured from `window` at Unix time `captured_at` expires, following the
+/// first matching rule; `None` when no rule matches.
+pub fn expiry_for(
+    rules: &[ExpireRule],
+    window: Option<&ActiveWindowContext>,
+    captured_at: u64,
+) -> Option<u64> {
+    let window = window?;
+    let rule = rules.iter().find(|rule| rule.rule.matches(window))?;
+    Some(captured_at.saturating_add(rule.ttl.as_secs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expiry_for, ExpireRule, RetentionPolicy, RetentionRule};
+    use crate::clipboard::blacklist::BlacklistRule;
+    use crate::clipboard::state::ClipboardEntry;
+    use crate::config::WindowMatchMode;
+    use crate::testing::window;
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn rule(app: &str, max_entries: Option<usize>, ttl_secs: Option<u64>) -> RetentionRule {
+        RetentionRule {
+            rule: BlacklistRule::parse(app, WindowMatchMode::Exact).unwrap(),
+            max_entries,
+            ttl: ttl_secs.map(Duration::from_secs),
+        }
+    }
+
+    fn from_app(value: &str, app_id: Option<&str>, age_secs: u64) -> Arc<ClipboardEntry> {
+        let entry = ClipboardEntry::text(value).with_captured_at(Some(NOW - age_secs));
+        Arc::new(match app_id {
+            Some(app_id) => entry.with_source(window(Some(app_id), &format!("{app_id} window"))),
+            None => entry,
+        })
+    }
+
+    /// Newest first: terminal copies interleaved with browser ones and a few without a source.
+    fn multi_app_history() -> VecDeque<Arc<ClipboardEntry>> {
+        (0..20)
+            .map(|index| {
+                let app = match index % 4 {
+                    0 | 1 => Some("kitty"),
+                    2 => Some("firefox"),
+                    _ => None,
+                };
+                from_app(&format!("entry {index}"), app, index * 60)
+            })
+            .collect()
+    }
+
+    fn drop_positions(
+        history: &mut VecDeque<Arc<ClipboardEntry>>,
+        positions: &[usize],
+    ) -> Vec<String> {
+        for &position in positions {
+            history.remove(position);
+        }
+        history
+            .iter()
+            .map(|entry| match &**entry {
+                ClipboardEntry::Text { value, .. } => value.clone(),
+                ClipboardEntry::Image { .. } => unreachable!(),
+            })
+            .collect()
+    }
+
+    fn kitty_entries(remaining: &[String]) -> Vec<&str> {
+        remaining
+            .iter()
+            .map(String::as_str)
+            .filter(|value| {
+                let index: usize = value["entry ".len()..].parse().unwrap();
+                index % 4 < 2
+            })
+            .collect()
+    }
+
+    #[test]
+    fn caps_entries_per_app_and_leaves_other_apps_alone() {
+        let mut history = multi_app_history();
+        let policy = RetentionPolicy::new(vec![rule("kitty", Some(5), None)]);
+        let expired = policy.expired_positions(&history, NOW);
+        assert_eq!(expired.len(), 10 - 5);
+        assert!(expired.windows(2).all(|pair| pair[0] > pair[1]));
+
+        let remaining = drop_positions(&mut history, &expired);
+        assert_eq!(
+            kitty_entries(&remaining),
+            ["entry 0", "entry 1", "entry 4", "entry 5", "entry 8"]
+        );
+        assert_eq!(remaining.len(), 15, "firefox and sourceless entries stay");
+    }
+
+    #[test]
+    fn expires_by_ttl_and_follows_the_first_matching_rule() {
+        let mut history = multi_app_history();
+        let policy = RetentionPolicy::new(vec![
+            rule("firefox", None, Some(5 * 60)),
+            rule("firefox", Some(1), None),
+            rule("kitty", Some(2), Some(3600)),
+        ]);
+        let expired = policy.expired_positions(&history, NOW);
+        let remaining = drop_positions(&mut history, &expired);
+        assert_eq!(
+            remaining,
+            [
+                "entry 0", "entry 1", "entry 2", "entry 3", "entry 7", "entry 11", "entry 15",
+                "entry 19"
+            ]
+        );
+    }
+
+    #[test]
+    fn pinned_entries_count_but_are_never_dropped() {
+        let mut history = multi_app_history();
+        for position in [4, 16] {
+            history[position] = Arc::new((*history[position]).clone().with_pinned(true));
+        }
+        // An entry without a timestamp never expires by age.
+        history[1] = Arc::new((*history[1]).clone().with_captured_at(None));
+        let policy = RetentionPolicy::new(vec![rule("kitty", Some(2), Some(1))]);
+        let expired = policy.expired_positions(&history, NOW);
+        let remaining = drop_positions(&mut history, &expired);
+        assert_eq!(
+            kitty_entries(&remaining),
+            ["entry 0", "entry 1", "entry 4", "entry 16"]
+        );
+
+        assert!(RetentionPolicy::default()
+            .expired_positions(&multi_app_history(), NOW)
+            .is_empty());
+    }
+
+    #[test]
+    fn stamps_the_ttl_of_the_first_matching_expire_rule() {
+        let rules = vec![
+            ExpireRule {
+                rule: BlacklistRule::parse("bank", WindowMatchMode::Substring).unwrap(),
+                ttl: Duration::from_secs(300),
+            },
+            ExpireRule {
+                rule: BlacklistRule::parse("firefox", WindowMatchMode::Substring).unwrap(),
+                ttl: Duration::from_secs(3600),
+            },
+        ];
+        let banking = window(Some("firefox"), "My Bank - Online Banking");
+        assert_eq!(expiry_for(&rules, Some(&banking), NOW), Some(NOW + 300));
+        let browsing = window(Some("firefox"), "News");
+        assert_eq!(expiry_for(&rules, Some(&browsing), NOW), Some(NOW + 3600));
+        let terminal = window(Some("kitty"), "~");
+        assert_eq!(expiry_for(&rules, Some(&terminal), NOW), None);
+        assert_eq!(expiry_for(&rules, None, NOW), None);
+    }
+}
@@ -0,0 +1,177 @@
+//! Named slots, like Vim registers: entries saved under a name with `slyboard yank` and put
+//! back with `slyboard put`. They live in `slots.json` next to the history database, so
+//! eviction and `slyboard clear` leave them alone.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::clipboard::storage::{default_database_path, load_slots, save_slots, slots_path};
+use crate::clipboard::ClipboardEntry;
+use crate::error::{Error, Result};
+
+pub const MAX_SLOT_NAME_CHARS: usize = 32;
+
+/// Slot names are 1 to [`MAX_SLOT_NAME_CHARS`] ASCII letters and digits.
+pub fn validate_slot_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_SLOT_NAME_CHARS
+        && name.chars().all(|ch| ch.is_ascii_alphanumeric());
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidSlotName {
+            name: name.to_string(),
+        })
+    }
+}
+
+/// The slots file. Every call reads it afresh, since the CLI and the daemon both change it.
+#[derive(Debug, Clone)]
+pub struct SlotStore {
+    path: PathBuf,
+}
+
+impl SlotStore {
+    pub fn open_default() -> Result<Self> {
+        Ok(Self::new(slots_path(&default_database_path()?)))
+    }
+
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// All slots, ordered by name.
+    pub fn load(&self) -> Result<BTreeMap<String, Arc<ClipboardEntry>>> {
+        load_slots(&self.path)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<Arc<ClipboardEntry>>> {
+        validate_slot_name(name)?;
+        Ok(self.load()?.remove(name))
+    }
+
+    /// Saves `entry` under `name` and returns the entry it replaced, if any. Pinned and
+    /// transient flags belong to history and are not kept.
+    pub fn set(&self, name: &str, entry: &ClipboardEntry) -> Result<Option<Arc<ClipboardEntry>>> {
+        validate_slot_name(name)?;
+        let mut entry = entry.clone();
+        entry.set_pinned(false);
+        let mut slots = self.load()?;
+        let previous = slots.insert(name.to_string(), Arc::new(entry));
+        save_slots(&self.path, &slots)?;
+        Ok(previous)
+    }
+
+    pub fn remove(&self, name: &str) -> Result<Option<Arc<ClipboardEntry>>> {
+        validate_slot_name(name)?;
+        let mut slots = self.load()?;
+        let previous = slots.remove(name);
+        if previous.is_some() {
+            save_slots(&self.path, &slots)?;
+        }
+        Ok(previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_slot_name, SlotStore};
+    use crate::clipboard::state::ClipboardState;
+    use crate::clipboard::storage::slots_path;
+    use crate::clipboard::{ClipboardEntry, ImageData};
+    use crate::error::Error;
+    use std::path::PathBuf;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("slyboard-test-slots-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn accepts_only_short_alphanumeric_names() {
+        for name in ["a", "url", "Reg2", &"x".repeat(32)] {
+            assert!(validate_slot_name(name).is_ok(), "{name:?} should be valid");
+        }
+        for name in ["", "a b", "a-b", "ä", "../a", &"x".repeat(33)] {
+            assert!(
+                matches!(validate_slot_name(name), Err(Error::InvalidSlotName { .. })),
+                "{name:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn round_trips_slots_through_the_file() {
+        let dir = test_dir("round-trip");
+        let store = SlotStore::new(dir.join("slots.json"));
+        assert!(store.load().unwrap().is_empty());
+
+        let image = ClipboardEntry::image(ImageData::new(1, 1, 4, 4, vec![1, 2, 3, 4]).unwrap());
+        let mut pinned = ClipboardEntry::text("hello");
+        pinned.set_pinned(true);
+        assert_eq!(store.set("a", &pinned).unwrap(), None);
+        assert_eq!(store.set("img", &image).unwrap(), None);
+
+        let reopened = SlotStore::new(store.path().to_path_buf());
+        let a = reopened.get("a").unwrap().expect("slot a");
+        assert!(a.content_eq(&pinned) && !a.is_pinned());
+        assert!(reopened.get("img").unwrap().unwrap().content_eq(&image));
+        assert_eq!(reopened.get("b").unwrap(), None);
+        assert!(reopened.get("no way").is_err());
+
+        let replaced = reopened.set("a", &ClipboardEntry::text("bye")).unwrap();
+        assert!(replaced.is_some_and(|entry| entry.content_eq(&pinned)));
+        assert_eq!(
+            store.load().unwrap().keys().collect::<Vec<_>>(),
+            ["a", "img"]
+        );
+        assert!(store.remove("img").unwrap().is_some());
+        assert_eq!(store.remove("img").unwrap(), None);
+        assert_eq!(store.load().unwrap().len(), 1);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn slots_survive_clearing_history() {
+        let dir = test_dir("clear");
+        let database = dir.join("history.json");
+        let mut state = ClipboardState::load(database.clone(), 2).unwrap();
+        let store = SlotStore::new(slots_path(&database));
+        assert_eq!(store.path(), dir.join("slots.json"));
+
+        state.record_entry(ClipboardEntry::text("keep me")).unwrap();
+        store.set("k", &state.history_snapshot()[0]).unwrap();
+        for value in ["b", "c"] {
+            state.record_entry(ClipboardEntry::text(value)).unwrap();
+        }
+        state.clear_history().unwrap();
+
+        assert!(state.history_snapshot().is_empty());
+        let kept = store
+            .get("k")
+            .unwrap()
+            .expect("slot survives clear and eviction");
+        assert!(kept.content_eq(&ClipboardEntry::text("keep me")));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn reports_a_corrupt_slots_file() {
+        let dir = test_dir("corrupt");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("slots.json");
+        std::fs::write(&path, "{not json").unwrap();
+        assert!(matches!(
+            SlotStore::new(path).load(),
+            Err(Error::StorageCorrupt { .. })
+        ));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
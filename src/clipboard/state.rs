@@ -1,14 +1,39 @@
-use std::collections::VecDeque;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
-use crate::clipboard::storage;
+use crate::clipboard::age::unix_now;
+use crate::clipboard::blacklist::{first_matching_rule, BlacklistRule};
+use crate::clipboard::classify::classify_text;
+use crate::clipboard::dedup::{dedup_text, DedupIndex, DedupSettings};
+use crate::clipboard::digest::StableHasher;
+use crate::clipboard::long_text::{referenced_files, text_digest, FullText, LongTextStore};
+use crate::clipboard::png;
+use crate::clipboard::retention::RetentionPolicy;
+use crate::clipboard::storage::{self, DatabaseHeader, DatabaseStamp, JournalRecord};
+use crate::clipboard::transfer;
+use crate::clipboard::writer::Selection;
 use crate::core::active_window::ActiveWindowContext;
+use crate::core::events::{self, Event};
+use crate::error::{Error, Result};
 
 pub const DEFAULT_HISTORY_LIMIT: usize = 50;
+/// Largest image side accepted; far beyond any screen, and small enough that row arithmetic
+/// can't overflow.
+pub const MAX_IMAGE_DIMENSION: i32 = 1 << 15;
+/// Most padding a row may carry past its pixels. GDK aligns rows to 4 bytes; anything much
+/// larger comes from a corrupted or hand-edited history.
+const MAX_ROW_PADDING: i64 = 64;
+/// Longest label `slyboard label` accepts.
+pub const MAX_LABEL_CHARS: usize = 100;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "kind", rename_all = "snake_case")]
@@ -17,6 +42,46 @@ pub enum ClipboardEntry {
         value: String,
         #[serde(default)]
         source_window: Option<ActiveWindowContext>,
+        /// Pinned entries are listed first by pickers and never evicted by the history limit.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        pinned: bool,
+        /// Short note set with `slyboard label`; survives re-copying the same content.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        /// Heuristic content type (`url`, `json`, ...) detected at capture time.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        content_hint: Option<String>,
+        /// Set for entries from transient sources: kept in memory until this instant, never saved.
+        #[serde(skip)]
+        transient_until: Option<Instant>,
+        /// Unix time (seconds) of capture; unknown for entries saved before it was recorded.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        captured_at: Option<u64>,
+        /// How the entry got into history; unknown for entries saved before it was recorded.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        captured_by: Option<CaptureOrigin>,
+        /// The selection it was copied to; unknown for entries saved before it was recorded,
+        /// and for entries slyboard made itself.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        selection: Option<Selection>,
+        /// Times the entry was put back on the clipboard from a picker, the tray or the CLI.
+        #[serde(default, skip_serializing_if = "is_zero")]
+        restore_count: u32,
+        /// Unix time (seconds) of the latest restore.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        last_restored_at: Option<u64>,
+        /// Unix time (seconds) after which the entry leaves history, set at capture by a
+        /// `clipboard.active_window.expire_rules` rule.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_at: Option<u64>,
+        /// Targets (MIME types) the clipboard owner offered at capture; only recorded with
+        /// `clipboard.record_targets`.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        offered_targets: Vec<String>,
+        /// Set when `value` is only a preview of a text over `clipboard.text.preview_limit_bytes`;
+        /// the whole text is in the [`LongTextStore`].
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        full_text: Option<FullText>,
     },
     Image {
         width: i32,
@@ -25,13 +90,526 @@ pub enum ClipboardEntry {
         has_alpha: bool,
         bits_per_sample: i32,
         channels: i32,
+        /// How `pixels` is stored; entries written before PNG support hold raw pixbuf bytes.
+        #[serde(default)]
+        encoding: ImageEncoding,
+        /// PNG file bytes, or raw pixbuf rows (`rowstride` bytes apart) for `raw` entries.
         pixels: Vec<u8>,
         #[serde(default)]
         source_window: Option<ActiveWindowContext>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        pinned: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        #[serde(skip)]
+        transient_until: Option<Instant>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        captured_at: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        captured_by: Option<CaptureOrigin>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        selection: Option<Selection>,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        restore_count: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        last_restored_at: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_at: Option<u64>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        offered_targets: Vec<String>,
     },
 }
 
+/// Where a history entry came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaptureOrigin {
+    /// Read by polling the GTK clipboard.
+    GtkPoll,
+    /// Delivered by a Wayland selection event (`wl-paste --watch`).
+    WaylandEvent,
+    /// Read by polling the Windows or macOS clipboard.
+    NativePoll,
+    /// Made and put on the clipboard by slyboard itself, e.g. by `slyboard append off` or
+    /// text recognition.
+    CliSet,
+    /// Imported from another clipboard manager's history.
+    Import,
+}
+
+impl CaptureOrigin {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::GtkPoll => "gtk-poll",
+            Self::WaylandEvent => "wayland-event",
+            Self::NativePoll => "native-poll",
+            Self::CliSet => "cli-set",
+            Self::Import => "import",
+        }
+    }
+}
+
+fn is_zero(count: &u32) -> bool {
+    *count == 0
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageEncoding {
+    #[default]
+    Raw,
+    Png,
+}
+
+/// 8-bit RGB or RGBA pixel rows for [`ClipboardEntry::image`], checked for a consistent
+/// layout up front so mismatched buffers never reach history or a pixbuf.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageData {
+    width: i32,
+    height: i32,
+    channels: i32,
+    rowstride: i32,
+    pixels: Vec<u8>,
+}
+
+impl ImageData {
+    /// `rowstride` is the byte distance between row starts; like GDK, the last row may omit
+    /// its padding.
+    pub fn new(
+        width: i32,
+        height: i32,
+        channels: i32,
+        rowstride: i32,
+        pixels: Vec<u8>,
+    ) -> Result<Self> {
+        Self::check(width, height, channels, rowstride, pixels.len())?;
+        Ok(Self {
+            width,
+            height,
+            channels,
+            rowstride,
+            pixels,
+        })
+    }
+
+    fn check(width: i32, height: i32, channels: i32, rowstride: i32, len: usize) -> Result<()> {
+        check_dimensions(width, height)?;
+        if !matches!(channels, 3 | 4) {
+            return Err(invalid_image(format!(
+                "unsupported channel count {channels}, expected 3 or 4"
+            )));
+        }
+        let (width, height, rowstride) = (width as i64, height as i64, rowstride as i64);
+        let row_len = width * channels as i64;
+        if rowstride < row_len {
+            return Err(invalid_image(format!(
+                "rowstride {rowstride} is shorter than a {row_len}-byte row"
+            )));
+        }
+        if rowstride > row_len + MAX_ROW_PADDING {
+            return Err(invalid_image(format!(
+                "rowstride {rowstride} is implausible for a {row_len}-byte row"
+            )));
+        }
+        let min_len = rowstride * (height - 1) + row_len;
+        let max_len = rowstride * height;
+        let len = len as i64;
+        if len < min_len || len > max_len {
+            return Err(invalid_image(format!(
+                "{len} pixel bytes do not fit {height} rows of {rowstride} bytes"
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn check_dimensions(width: i32, height: i32) -> Result<()> {
+    if width <= 0 || height <= 0 {
+        return Err(invalid_image("image has no pixels"));
+    }
+    if width > MAX_IMAGE_DIMENSION || height > MAX_IMAGE_DIMENSION {
+        return Err(invalid_image(format!(
+            "{width}x{height} exceeds the {MAX_IMAGE_DIMENSION}px limit"
+        )));
+    }
+    Ok(())
+}
+
 impl ClipboardEntry {
+    /// A text entry with no source window, hint, or timestamp; see the `with_*` builders.
+    pub fn text(value: impl Into<String>) -> Self {
+        ClipboardEntry::Text {
+            value: value.into(),
+            source_window: None,
+            pinned: false,
+            label: None,
+            content_hint: None,
+            transient_until: None,
+            captured_at: None,
+            captured_by: None,
+            selection: None,
+            restore_count: 0,
+            last_restored_at: None,
+            expires_at: None,
+            offered_targets: Vec::new(),
+            full_text: None,
+        }
+    }
+
+    /// A PNG-encoded image entry. [`ImageData`] has already checked the pixel layout, so the
+    /// encoder only fails on allocation limits; the rows are then kept raw instead.
+    pub fn image(data: ImageData) -> Self {
+        let ImageData {
+            width,
+            height,
+            channels,
+            rowstride,
+            pixels,
+        } = data;
+        let (encoding, rowstride, pixels) = match png::encode(
+            width as u32,
+            height as u32,
+            channels as u8,
+            rowstride as usize,
+            &pixels,
+        ) {
+            Ok(encoded) => (ImageEncoding::Png, width * channels, encoded),
+            Err(_) => (ImageEncoding::Raw, rowstride, pixels),
+        };
+        ClipboardEntry::Image {
+            width,
+            height,
+            rowstride,
+            has_alpha: channels == 4,
+            bits_per_sample: 8,
+            channels,
+            encoding,
+            pixels,
+            source_window: None,
+            pinned: false,
+            label: None,
+            transient_until: None,
+            captured_at: None,
+            captured_by: None,
+            selection: None,
+            restore_count: 0,
+            last_restored_at: None,
+            expires_at: None,
+            offered_targets: Vec::new(),
+        }
+    }
+
+    /// Re-encodes a raw image entry as PNG, keeping its metadata. Text, PNG entries, and raw
+    /// layouts PNG can't hold (other than 8-bit RGB/RGBA) are returned unchanged.
+    pub fn into_png_encoded(self) -> Self {
+        let ClipboardEntry::Image {
+            width,
+            height,
+            rowstride,
+            has_alpha,
+            bits_per_sample,
+            channels,
+            encoding: ImageEncoding::Raw,
+            pixels,
+            ..
+        } = &self
+        else {
+            return self;
+        };
+        if *bits_per_sample != 8
+            || *has_alpha != (*channels == 4)
+            || ImageData::check(*width, *height, *channels, *rowstride, pixels.len()).is_err()
+        {
+            return self;
+        }
+
+        let ClipboardEntry::Image {
+            width,
+            height,
+            rowstride,
+            channels,
+            pixels,
+            source_window,
+            pinned,
+            label,
+            transient_until,
+            captured_at,
+            captured_by,
+            selection,
+            restore_count,
+            last_restored_at,
+            expires_at,
+            offered_targets,
+            ..
+        } = self
+        else {
+            unreachable!("checked to be an image above");
+        };
+        let mut png = ClipboardEntry::image(ImageData {
+            width,
+            height,
+            channels,
+            rowstride,
+            pixels,
+        })
+        .with_source_window(source_window)
+        .with_pinned(pinned)
+        .with_label(label)
+        .with_transient_until(transient_until)
+        .with_captured_at(captured_at)
+        .with_expires_at(expires_at)
+        .with_offered_targets(offered_targets)
+        .with_selection(selection);
+        if let Some(origin) = captured_by {
+            png = png.with_captured_by(origin);
+        }
+        png.set_restores(restore_count, last_restored_at);
+        png
+    }
+
+    /// Raw pixel rows for restoring an image, `rowstride` bytes apart; PNG entries are
+    /// decoded here. Errors for text entries and undecodable images.
+    pub fn image_pixels(&self) -> Result<Cow<'_, [u8]>> {
+        match self {
+            ClipboardEntry::Image {
+                encoding, pixels, ..
+            } => {
+                self.validate_image_geometry()?;
+                match encoding {
+                    ImageEncoding::Raw => Ok(Cow::Borrowed(pixels)),
+                    // The header was checked against the recorded geometry above.
+                    ImageEncoding::Png => png::decode(pixels)
+                        .map(|decoded| Cow::Owned(decoded.pixels))
+                        .map_err(|err| {
+                            invalid_image(format!("failed to decode stored image: {err:#}"))
+                        }),
+                }
+            }
+            ClipboardEntry::Text { .. } => Err(invalid_image("text entries have no image pixels")),
+        }
+    }
+
+    /// Compares only what was copied: the text, or the image geometry and stored bytes. Source
+    /// window, pin state, content hint, and transient expiry are ignored, and so is trailing
+    /// whitespace (see [`dedup_text`]); [`DedupSettings::same_content`] adds
+    /// [`same_form`](Self::same_form) where it counts. Text cut to a preview compares by the
+    /// digest of its whole text.
+    pub fn content_eq(&self, other: &ClipboardEntry) -> bool {
+        match (self, other) {
+            (
+                ClipboardEntry::Text {
+                    value, full_text, ..
+                },
+                ClipboardEntry::Text {
+                    value: other_value,
+                    full_text: other_full_text,
+                    ..
+                },
+            ) => match (full_text, other_full_text) {
+                (None, None) => dedup_text(value) == dedup_text(other_value),
+                (Some(full), Some(other_full)) => full.digest == other_full.digest,
+                (Some(full), None) => full.digest == text_digest(other_value),
+                (None, Some(other_full)) => text_digest(value) == other_full.digest,
+            },
+            (
+                ClipboardEntry::Image {
+                    width,
+                    height,
+                    has_alpha,
+                    bits_per_sample,
+                    channels,
+                    encoding,
+                    pixels,
+                    ..
+                },
+                ClipboardEntry::Image {
+                    width: other_width,
+                    height: other_height,
+                    has_alpha: other_has_alpha,
+                    bits_per_sample: other_bits_per_sample,
+                    channels: other_channels,
+                    encoding: other_encoding,
+                    pixels: other_pixels,
+                    ..
+                },
+            ) => {
+                (
+                    width,
+                    height,
+                    has_alpha,
+                    bits_per_sample,
+                    channels,
+                    encoding,
+                ) == (
+                    other_width,
+                    other_height,
+                    other_has_alpha,
+                    other_bits_per_sample,
+                    other_channels,
+                    other_encoding,
+                ) && pixels == other_pixels
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether text entries hold byte-identical text, which [`content_eq`](Self::content_eq)
+    /// doesn't check for trailing whitespace. Images, compared whole there, always are.
+    pub fn same_form(&self, other: &ClipboardEntry) -> bool {
+        match (self, other) {
+            (
+                ClipboardEntry::Text {
+                    value, full_text, ..
+                },
+                ClipboardEntry::Text {
+                    value: other_value,
+                    full_text: other_full_text,
+                    ..
+                },
+            ) => {
+                value == other_value
+                    && full_text.as_ref().map(FullText::file_stem)
+                        == other_full_text.as_ref().map(FullText::file_stem)
+            }
+            _ => true,
+        }
+    }
+
+    /// Fingerprint of the payload [`content_eq`](Self::content_eq) compares. History holds one
+    /// entry per payload, so this finds an entry again after the history shifted. Only stable
+    /// within one process.
+    pub fn content_id(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash_content(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Like [`content_id`](Self::content_id), but the same in every process, so it names an
+    /// entry across invocations even when newer copies shift its history id. Sixteen hex
+    /// digits.
+    pub fn stable_id(&self) -> String {
+        let mut hasher = StableHasher::default();
+        self.hash_content(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    fn hash_content<H: Hasher>(&self, hasher: &mut H) {
+        match self {
+            // By digest, so a preview hashes like the whole text it was cut from.
+            ClipboardEntry::Text {
+                full_text: Some(full),
+                ..
+            } => (0u8, full.digest.as_str()).hash(hasher),
+            ClipboardEntry::Text { value, .. } => (0u8, text_digest(value).as_str()).hash(hasher),
+            ClipboardEntry::Image {
+                width,
+                height,
+                has_alpha,
+                bits_per_sample,
+                channels,
+                encoding,
+                pixels,
+                ..
+            } => (
+                1u8,
+                width,
+                height,
+                has_alpha,
+                bits_per_sample,
+                channels,
+                encoding,
+                pixels,
+            )
+                .hash(hasher),
+        }
+    }
+
+    /// Text that is empty or only whitespace.
+    pub fn is_blank(&self) -> bool {
+        match self {
+            ClipboardEntry::Text { value, .. } => value.trim().is_empty(),
+            ClipboardEntry::Image { .. } => false,
+        }
+    }
+
+    /// Checks that an image's stored bytes can be handed to GDK as described: 8-bit RGB or
+    /// RGBA, dimensions within [`MAX_IMAGE_DIMENSION`], raw rows that fit the buffer with at
+    /// most a little padding, or a PNG whose header agrees with the recorded geometry. Text
+    /// entries always pass.
+    pub fn validate_image_geometry(&self) -> Result<()> {
+        let ClipboardEntry::Image {
+            width,
+            height,
+            rowstride,
+            has_alpha,
+            bits_per_sample,
+            channels,
+            encoding,
+            pixels,
+            ..
+        } = self
+        else {
+            return Ok(());
+        };
+        if *bits_per_sample != 8 {
+            return Err(invalid_image(format!(
+                "unsupported {bits_per_sample}-bit samples, expected 8"
+            )));
+        }
+        if *has_alpha != (*channels == 4) {
+            return Err(invalid_image(format!(
+                "{channels} channels disagree with has_alpha={has_alpha}"
+            )));
+        }
+
+        match encoding {
+            ImageEncoding::Raw => {
+                ImageData::check(*width, *height, *channels, *rowstride, pixels.len())
+            }
+            ImageEncoding::Png => {
+                check_dimensions(*width, *height)?;
+                if *rowstride != width * channels {
+                    return Err(invalid_image(format!(
+                        "rowstride {rowstride} does not match decoded {width}px rows"
+                    )));
+                }
+                let (png_width, png_height, png_channels) =
+                    png::read_header(pixels).map_err(|err| invalid_image(format!("{err:#}")))?;
+                if (png_width as i64, png_height as i64, png_channels as i32)
+                    != (*width as i64, *height as i64, *channels)
+                {
+                    return Err(invalid_image(format!(
+                        "PNG is {png_width}x{png_height} with {png_channels} channels, \
+                         recorded as {width}x{height} with {channels}"
+                    )));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// An image that fails [`validate_image_geometry`](Self::validate_image_geometry).
+    pub fn is_broken_image(&self) -> bool {
+        self.validate_image_geometry().is_err()
+    }
+
+    /// Bytes the entry takes once loaded: the UTF-8 length of text, or the decoded pixel
+    /// buffer of an image (`width * height * channels`, however it is stored).
+    pub fn approx_size_bytes(&self) -> u64 {
+        match self {
+            ClipboardEntry::Text { value, .. } => value.len() as u64,
+            ClipboardEntry::Image {
+                width,
+                height,
+                channels,
+                ..
+            } => [width, height, channels]
+                .into_iter()
+                .map(|value| u64::try_from(*value).unwrap_or(0))
+                .product(),
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
         match self {
             ClipboardEntry::Text { value, .. } => value.is_empty(),
@@ -39,6 +617,11 @@ impl ClipboardEntry {
         }
     }
 
+    /// Records the window the entry was copied from.
+    pub fn with_source(self, source: ActiveWindowContext) -> Self {
+        self.with_source_window(Some(source))
+    }
+
     pub fn with_source_window(mut self, source_window: Option<ActiveWindowContext>) -> Self {
         match &mut self {
             ClipboardEntry::Text {
@@ -52,92 +635,2852 @@ impl ClipboardEntry {
         }
         self
     }
-}
 
-#[derive(Clone)]
-pub struct SharedClipboardState {
-    inner: Arc<Mutex<ClipboardState>>,
-}
+    pub fn with_transient_ttl(self, ttl: Duration) -> Self {
+        self.with_transient_until(Some(Instant::now() + ttl))
+    }
 
-impl SharedClipboardState {
-    pub fn load_default(history_limit: usize) -> Result<Self> {
-        let state = ClipboardState::load_default(history_limit)?;
-        Ok(Self {
-            inner: Arc::new(Mutex::new(state)),
-        })
+    fn with_transient_until(mut self, expires_at: Option<Instant>) -> Self {
+        match &mut self {
+            ClipboardEntry::Text {
+                transient_until, ..
+            }
+            | ClipboardEntry::Image {
+                transient_until, ..
+            } => *transient_until = expires_at,
+        }
+        self
     }
 
-    pub fn record_entry(&self, value: ClipboardEntry) -> Result<bool> {
-        let mut guard = self.inner.lock().expect("clipboard state mutex poisoned");
-        guard.record_entry(value)
+    pub fn with_captured_at(mut self, unix_secs: Option<u64>) -> Self {
+        match &mut self {
+            ClipboardEntry::Text { captured_at, .. }
+            | ClipboardEntry::Image { captured_at, .. } => *captured_at = unix_secs,
+        }
+        self
     }
 
-    pub fn history_snapshot(&self) -> Vec<ClipboardEntry> {
-        let guard = self.inner.lock().expect("clipboard state mutex poisoned");
-        guard.history_snapshot()
+    /// Unix time (seconds) the entry was captured, if known.
+    pub fn captured_at(&self) -> Option<u64> {
+        match self {
+            ClipboardEntry::Text { captured_at, .. }
+            | ClipboardEntry::Image { captured_at, .. } => *captured_at,
+        }
     }
 
-    pub fn clear_history(&self) -> Result<()> {
-        let mut guard = self.inner.lock().expect("clipboard state mutex poisoned");
-        guard.clear_history()
+    pub fn with_expires_at(mut self, unix_secs: Option<u64>) -> Self {
+        self.set_expires_at(unix_secs);
+        self
     }
-}
 
-pub struct ClipboardState {
-    database_path: PathBuf,
-    history: VecDeque<ClipboardEntry>,
-    history_limit: usize,
-}
+    pub fn set_expires_at(&mut self, unix_secs: Option<u64>) {
+        match self {
+            ClipboardEntry::Text { expires_at, .. } | ClipboardEntry::Image { expires_at, .. } => {
+                *expires_at = unix_secs
+            }
+        }
+    }
 
-impl ClipboardState {
-    pub fn load_default(history_limit: usize) -> Result<Self> {
-        let database_path = storage::default_database_path()?;
-        let history = storage::load_history(&database_path, history_limit)?;
-        Ok(Self {
-            database_path,
-            history,
-            history_limit,
-        })
+    /// Unix time (seconds) after which the entry leaves history, if it has an expiry.
+    pub fn expires_at(&self) -> Option<u64> {
+        match self {
+            ClipboardEntry::Text { expires_at, .. } | ClipboardEntry::Image { expires_at, .. } => {
+                *expires_at
+            }
+        }
     }
 
-    pub fn history_snapshot(&self) -> Vec<ClipboardEntry> {
-        self.history.iter().cloned().collect()
+    /// Whether the entry's expiry has passed at Unix time `now`. Pinned entries never expire.
+    pub fn is_expired(&self, now: u64) -> bool {
+        !self.is_pinned()
+            && self
+                .expires_at()
+                .is_some_and(|expires_at| expires_at <= now)
     }
 
-    pub fn record_entry(&mut self, value: ClipboardEntry) -> Result<bool> {
-        if !push_history_entry(&mut self.history, self.history_limit, value) {
-            return Ok(false);
+    pub fn with_captured_by(mut self, origin: CaptureOrigin) -> Self {
+        match &mut self {
+            ClipboardEntry::Text { captured_by, .. }
+            | ClipboardEntry::Image { captured_by, .. } => *captured_by = Some(origin),
         }
+        self
+    }
 
-        storage::save_history(&self.database_path, &self.history)?;
-        Ok(true)
+    pub fn captured_by(&self) -> Option<CaptureOrigin> {
+        match self {
+            ClipboardEntry::Text { captured_by, .. }
+            | ClipboardEntry::Image { captured_by, .. } => *captured_by,
+        }
     }
 
-    pub fn clear_history(&mut self) -> Result<()> {
-        self.history.clear();
-        storage::save_history(&self.database_path, &self.history)
+    pub fn with_selection(mut self, from: Option<Selection>) -> Self {
+        match &mut self {
+            ClipboardEntry::Text { selection, .. } | ClipboardEntry::Image { selection, .. } => {
+                *selection = from
+            }
+        }
+        self
+    }
+
+    /// The selection the entry was captured from, if that was recorded.
+    pub fn selection(&self) -> Option<Selection> {
+        match self {
+            ClipboardEntry::Text { selection, .. } | ClipboardEntry::Image { selection, .. } => {
+                *selection
+            }
+        }
+    }
+
+    pub fn with_offered_targets(mut self, targets: Vec<String>) -> Self {
+        match &mut self {
+            ClipboardEntry::Text {
+                offered_targets, ..
+            }
+            | ClipboardEntry::Image {
+                offered_targets, ..
+            } => *offered_targets = targets,
+        }
+        self
+    }
+
+    /// What the clipboard owner offered when the entry was captured, if that was recorded.
+    pub fn offered_targets(&self) -> &[String] {
+        match self {
+            ClipboardEntry::Text {
+                offered_targets, ..
+            }
+            | ClipboardEntry::Image {
+                offered_targets, ..
+            } => offered_targets,
+        }
+    }
+
+    /// Where the whole text of an entry cut to a preview is, or `None` for entries kept whole.
+    pub fn full_text(&self) -> Option<&FullText> {
+        match self {
+            ClipboardEntry::Text { full_text, .. } => full_text.as_ref(),
+            ClipboardEntry::Image { .. } => None,
+        }
+    }
+
+    /// How often the entry was put back on the clipboard, and when it last was.
+    pub fn restores(&self) -> (u32, Option<u64>) {
+        match self {
+            ClipboardEntry::Text {
+                restore_count,
+                last_restored_at,
+                ..
+            }
+            | ClipboardEntry::Image {
+                restore_count,
+                last_restored_at,
+                ..
+            } => (*restore_count, *last_restored_at),
+        }
+    }
+
+    fn set_restores(&mut self, count: u32, at: Option<u64>) {
+        match self {
+            ClipboardEntry::Text {
+                restore_count,
+                last_restored_at,
+                ..
+            }
+            | ClipboardEntry::Image {
+                restore_count,
+                last_restored_at,
+                ..
+            } => {
+                *restore_count = count;
+                *last_restored_at = at;
+            }
+        }
+    }
+
+    /// Counts one more restore, at `unix_secs`.
+    pub fn mark_restored(&mut self, unix_secs: u64) {
+        let (count, _) = self.restores();
+        self.set_restores(count.saturating_add(1), Some(unix_secs));
+    }
+
+    pub fn source_window(&self) -> Option<&ActiveWindowContext> {
+        match self {
+            ClipboardEntry::Text { source_window, .. }
+            | ClipboardEntry::Image { source_window, .. } => source_window.as_ref(),
+        }
+    }
+
+    pub fn with_pinned(mut self, pinned: bool) -> Self {
+        self.set_pinned(pinned);
+        self
+    }
+
+    pub fn set_pinned(&mut self, pinned: bool) {
+        match self {
+            ClipboardEntry::Text {
+                pinned: existing, ..
+            }
+            | ClipboardEntry::Image {
+                pinned: existing, ..
+            } => *existing = pinned,
+        }
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        match self {
+            ClipboardEntry::Text { pinned, .. } | ClipboardEntry::Image { pinned, .. } => *pinned,
+        }
+    }
+
+    pub fn with_label(mut self, label: Option<String>) -> Self {
+        self.set_label(label);
+        self
+    }
+
+    pub fn set_label(&mut self, label: Option<String>) {
+        match self {
+            ClipboardEntry::Text {
+                label: existing, ..
+            }
+            | ClipboardEntry::Image {
+                label: existing, ..
+            } => *existing = label,
+        }
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            ClipboardEntry::Text { label, .. } | ClipboardEntry::Image { label, .. } => {
+                label.as_deref()
+            }
+        }
+    }
+
+    /// Tags text entries with their detected content type; images are left untouched.
+    pub fn with_classified_content(mut self) -> Self {
+        if let ClipboardEntry::Text {
+            value,
+            content_hint,
+            ..
+        } = &mut self
+        {
+            *content_hint = Some(classify_text(value).as_str().to_string());
+        }
+        self
+    }
+
+    pub fn content_hint(&self) -> Option<&str> {
+        match self {
+            ClipboardEntry::Text { content_hint, .. } => content_hint.as_deref(),
+            ClipboardEntry::Image { .. } => None,
+        }
+    }
+
+    pub fn transient_until(&self) -> Option<Instant> {
+        match self {
+            ClipboardEntry::Text {
+                transient_until, ..
+            }
+            | ClipboardEntry::Image {
+                transient_until, ..
+            } => *transient_until,
+        }
+    }
+
+    pub fn is_transient(&self) -> bool {
+        self.transient_until().is_some()
+    }
+
+    /// Whether recording `self` may move `existing`, an entry with the same content. A
+    /// transient copy never displaces a saved entry: nothing about it is journaled, so the
+    /// saved entry would stay where it was on disk and later positions would disagree.
+    pub fn may_replace(&self, existing: &ClipboardEntry) -> bool {
+        existing.is_transient() || !self.is_transient()
     }
 }
 
-fn push_history_entry(
-    history: &mut VecDeque<ClipboardEntry>,
-    history_limit: usize,
-    value: ClipboardEntry,
-) -> bool {
-    if value.is_empty() {
-        return false;
+/// Which kinds of stale entries `prune_history` drops.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PruneOptions {
+    /// Older entries with the same content as a newer one.
+    pub duplicates: bool,
+    /// Whitespace-only text entries.
+    pub empty: bool,
+    /// Images whose stored bytes don't match their geometry.
+    pub broken_images: bool,
+}
+
+impl PruneOptions {
+    pub fn all() -> Self {
+        Self {
+            duplicates: true,
+            empty: true,
+            broken_images: true,
+        }
     }
 
-    if let Some(index) = history.iter().position(|entry| entry == &value) {
-        if index == 0 {
-            return false;
+    pub fn is_none(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Removes entries selected by `options` and returns how many were dropped. Of content-equal
+/// entries the newest is kept; it inherits the pin if any dropped duplicate was pinned, and
+/// the label of the newest labelled duplicate if it has none.
+pub fn prune_history(history: &mut VecDeque<Arc<ClipboardEntry>>, options: PruneOptions) -> usize {
+    if options.is_none() {
+        return 0;
+    }
+    let before = history.len();
+    let mut kept: VecDeque<Arc<ClipboardEntry>> = VecDeque::with_capacity(before);
+    for entry in history.drain(..) {
+        if (options.empty && entry.is_blank()) || (options.broken_images && entry.is_broken_image())
+        {
+            continue;
         }
-        history.remove(index);
+        if options.duplicates {
+            if let Some(newer) = kept.iter_mut().find(|newer| newer.content_eq(&entry)) {
+                if entry.is_pinned() && !newer.is_pinned() {
+                    Arc::make_mut(newer).set_pinned(true);
+                }
+                if newer.label().is_none() && entry.label().is_some() {
+                    Arc::make_mut(newer).set_label(entry.label().map(str::to_string));
+                }
+                continue;
+            }
+        }
+        kept.push_back(entry);
     }
+    *history = kept;
+    before - history.len()
+}
 
-    history.push_front(value);
-    while history.len() > history_limit {
-        history.pop_back();
+/// Removes entries whose source window matches one of `rules`, pinned ones included, and
+/// counts them by the pattern of the first rule that matched.
+pub fn prune_blacklisted_history(
+    history: &mut VecDeque<Arc<ClipboardEntry>>,
+    rules: &[BlacklistRule],
+) -> BTreeMap<String, usize> {
+    let mut removed = BTreeMap::new();
+    history.retain(
+        |entry| match first_matching_rule(entry.source_window(), rules) {
+            Some(rule) => {
+                *removed.entry(rule.pattern().to_string()).or_insert(0) += 1;
+                false
+            }
+            None => true,
+        },
+    );
+    removed
+}
+
+/// What `record_entry` did with a captured entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordOutcome {
+    /// The entry is now the newest history item.
+    pub inserted: bool,
+    /// Entries dropped from the old end of history to stay within the limit, then any removed
+    /// by retention rules or expiry.
+    pub evicted: Vec<Arc<ClipboardEntry>>,
+    /// The history limit, when reaching it evicted entries.
+    pub limit_reached: Option<usize>,
+    /// Where an entry with the same content sat before this record; `Some(0)` means the
+    /// entry was already newest and nothing changed.
+    pub deduplicated_from_index: Option<usize>,
+    /// The entry with the same content this one took the place of, unless nothing changed.
+    pub replaced: Option<Arc<ClipboardEntry>>,
+    /// History was first reloaded because another process changed the database.
+    pub reloaded: bool,
+}
+
+impl RecordOutcome {
+    /// Whether the in-memory history changed, i.e. the old `record_entry` return value.
+    pub fn changed(&self) -> bool {
+        self.inserted || self.reloaded
+    }
+}
+
+/// A change to the in-memory history, delivered to `SharedClipboardState::subscribe` receivers.
+/// Indices count from the newest entry, like `history_snapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoryEvent {
+    Recorded(Arc<ClipboardEntry>),
+    Cleared,
+    Removed(usize),
+    Pinned {
+        index: usize,
+        pinned: bool,
+    },
+    Labeled {
+        index: usize,
+        label: Option<String>,
+    },
+    /// The entry at `index` was put back on the clipboard.
+    Restored {
+        index: usize,
+    },
+    /// History was replaced after another process changed the database.
+    Reloaded,
+}
+
+#[derive(Clone)]
+pub struct SharedClipboardState {
+    inner: Arc<Mutex<ClipboardState>>,
+    subscribers: Arc<Mutex<Vec<Sender<HistoryEvent>>>>,
+    /// Set by the first `shutdown`, so the tray's Quit and a signal don't both run it.
+    shut_down: Arc<AtomicBool>,
+}
+
+impl SharedClipboardState {
+    pub fn load_default(history_limit: usize) -> Result<Self> {
+        Ok(Self::new(ClipboardState::load_default(history_limit)?))
+    }
+
+    pub fn load(database_path: PathBuf, history_limit: usize) -> Result<Self> {
+        Ok(Self::new(ClipboardState::load(
+            database_path,
+            history_limit,
+        )?))
+    }
+
+    /// Loads the default database, dropping entries matching `prune_on_load` on every load and
+    /// deduplicating as `dedup` says.
+    pub fn load_default_with(
+        history_limit: usize,
+        prune_on_load: PruneOptions,
+        dedup: DedupSettings,
+    ) -> Result<Self> {
+        Self::load_pruned(
+            storage::default_database_path()?,
+            history_limit,
+            prune_on_load,
+            dedup,
+        )
+    }
+
+    /// See [`ClipboardState::load_pruned`].
+    pub fn load_pruned(
+        database_path: PathBuf,
+        history_limit: usize,
+        prune_on_load: PruneOptions,
+        dedup: DedupSettings,
+    ) -> Result<Self> {
+        Ok(Self::new(ClipboardState::load_pruned(
+            database_path,
+            history_limit,
+            prune_on_load,
+            dedup,
+        )?))
+    }
+
+    fn new(state: ClipboardState) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(state)),
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+            shut_down: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Returns a receiver for every subsequent history change made through this state.
+    /// Dropped receivers are forgotten the next time an event is sent.
+    pub fn subscribe(&self) -> Receiver<HistoryEvent> {
+        let (sender, receiver) = mpsc::channel();
+        lock_recovering(&self.subscribers).push(sender);
+        receiver
+    }
+
+    /// Records `value` and reports what happened to history, including evicted entries.
+    pub fn record(&self, value: ClipboardEntry) -> Result<RecordOutcome> {
+        self.with_state(|state| state.record_entry(value))
+    }
+
+    #[deprecated(note = "use `record`, which also reports evicted and deduplicated entries")]
+    pub fn record_entry(&self, value: ClipboardEntry) -> Result<bool> {
+        self.record(value).map(|outcome| outcome.changed())
+    }
+
+    /// Returns the current history, newest first. Entries are shared, not copied, so this stays
+    /// cheap for image-heavy histories.
+    pub fn history_snapshot(&self) -> Vec<Arc<ClipboardEntry>> {
+        lock_recovering(&self.inner).history_snapshot()
+    }
+
+    /// See [`ClipboardState::total_size`].
+    pub fn total_size(&self) -> u64 {
+        lock_recovering(&self.inner).total_size()
+    }
+
+    /// See [`ClipboardState::database_header`].
+    pub fn database_header(&self) -> Result<Option<DatabaseHeader>> {
+        lock_recovering(&self.inner).database_header()
+    }
+
+    /// See [`ClipboardState::find_entry`].
+    pub fn find_entry(&self, key: &str) -> Option<(usize, Arc<ClipboardEntry>)> {
+        lock_recovering(&self.inner).find_entry(key)
+    }
+
+    pub fn set_pinned(&self, index: usize, pinned: bool) -> Result<bool> {
+        self.with_state(|state| state.set_pinned(index, pinned))
+    }
+
+    /// See [`ClipboardState::toggle_pinned_by_id`].
+    pub fn toggle_pinned_by_id(&self, stable_id: &str) -> Result<Option<(usize, bool)>> {
+        self.with_state(|state| state.toggle_pinned_by_id(stable_id))
+    }
+
+    /// Attaches a note to the entry at `index` (newest first), replacing any previous one.
+    /// The note is trimmed; a blank one clears the label. Returns whether anything changed.
+    pub fn set_label(&self, index: usize, label: &str) -> Result<bool> {
+        let label = validate_label(label)?;
+        self.with_state(|state| state.set_label(index, label))
+    }
+
+    pub fn clear_label(&self, index: usize) -> Result<bool> {
+        self.with_state(|state| state.set_label(index, None))
+    }
+
+    /// Counts a restore of `entry` (it was put back on the clipboard) on the history entry with
+    /// the same content, without moving it. Returns `false` when no such entry is in history,
+    /// e.g. for a slot whose entry has left history.
+    pub fn mark_restored(&self, entry: &ClipboardEntry) -> Result<bool> {
+        self.with_state(|state| state.mark_restored(entry, unix_now()))
+    }
+
+    pub fn remove_entry(&self, index: usize) -> Result<()> {
+        self.with_state(|state| state.remove_entry(index))
+    }
+
+    pub fn clear_history(&self) -> Result<()> {
+        self.with_state(|state| state.clear_history())
+    }
+
+    /// See [`ClipboardState::set_trash_on_clear`].
+    pub fn set_trash_on_clear(&self, trash_on_clear: bool) {
+        lock_recovering(&self.inner).set_trash_on_clear(trash_on_clear);
+    }
+
+    /// See [`ClipboardState::set_text_preview_limit`].
+    pub fn set_text_preview_limit(&self, limit: Option<usize>) {
+        lock_recovering(&self.inner).set_text_preview_limit(limit);
+    }
+
+    /// See [`ClipboardState::long_texts`].
+    pub fn long_texts(&self) -> LongTextStore {
+        lock_recovering(&self.inner).long_texts().clone()
+    }
+
+    /// `entry` as restoring puts it on the clipboard: with its whole text read back from this
+    /// history's [`LongTextStore`] if it only holds a preview, otherwise the entry itself.
+    pub fn whole_entry<'a>(&self, entry: &'a ClipboardEntry) -> Result<Cow<'a, ClipboardEntry>> {
+        if entry.full_text().is_none() {
+            return Ok(Cow::Borrowed(entry));
+        }
+        self.long_texts().restore(entry)
+    }
+
+    /// See [`ClipboardState::dedup_settings`].
+    pub fn dedup_settings(&self) -> DedupSettings {
+        lock_recovering(&self.inner).dedup_settings()
+    }
+
+    /// See [`ClipboardState::restore_trash`].
+    pub fn restore_trash(&self) -> Result<usize> {
+        self.with_state(|state| state.restore_trash())
+    }
+
+    /// See [`ClipboardState::merge_history`].
+    pub fn merge_history(&self, entries: &[ClipboardEntry]) -> Result<usize> {
+        self.with_state(|state| state.merge_history(entries))
+    }
+
+    pub fn compact(&self) -> Result<()> {
+        self.with_state(|state| state.compact())
+    }
+
+    pub fn prune(&self, options: PruneOptions) -> Result<usize> {
+        self.with_state(|state| state.prune(options))
+    }
+
+    pub fn prune_blacklisted(&self, rules: &[BlacklistRule]) -> Result<BTreeMap<String, usize>> {
+        self.with_state(|state| state.prune_blacklisted(rules))
+    }
+
+    /// See [`ClipboardState::set_retention`].
+    pub fn set_retention(&self, retention: RetentionPolicy) -> Result<usize> {
+        self.with_state(|state| state.set_retention(retention))
+    }
+
+    /// Runs [`ClipboardState::shutdown`] once across all clones; later calls return
+    /// `Ok(false)` without touching storage.
+    pub fn shutdown(&self) -> Result<bool> {
+        if self.shut_down.swap(true, Ordering::SeqCst) {
+            return Ok(false);
+        }
+        self.with_state(|state| state.shutdown())?;
+        Ok(true)
+    }
+
+    pub fn reload_if_changed(&self) -> Result<bool> {
+        self.with_state(|state| state.reload_if_changed())
+    }
+
+    pub fn expire_transient_entries(&self) -> bool {
+        self.with_state(|state| state.expire_transient_entries(Instant::now()))
+    }
+
+    /// Removes entries whose expiry has passed; see [`ClipboardState::expire_entries`].
+    pub fn expire_entries(&self) -> Result<usize> {
+        self.with_state(|state| state.expire_entries(unix_now()))
+    }
+
+    /// Runs `f` under the state lock, then notifies subscribers once the lock is released.
+    fn with_state<T>(&self, f: impl FnOnce(&mut ClipboardState) -> T) -> T {
+        let (result, events) = {
+            let mut guard = lock_recovering(&self.inner);
+            let result = f(&mut guard);
+            (result, std::mem::take(&mut guard.pending_events))
+        };
+        if !events.is_empty() {
+            let mut subscribers = lock_recovering(&self.subscribers);
+            subscribers.retain(|subscriber| {
+                events
+                    .iter()
+                    .all(|event| subscriber.send(event.clone()).is_ok())
+            });
+        }
+        result
+    }
+}
+
+fn invalid_image(reason: impl Into<String>) -> Error {
+    Error::InvalidImage {
+        reason: reason.into(),
+    }
+}
+
+/// Locks `mutex` even if a previous holder panicked. Mutations change the in-memory history
+/// before appending their journal record, so a holder that failed or panicked in between
+/// leaves memory ahead of disk until the next compaction saves it. That history is still
+/// usable, whereas refusing it would make every later tray callback panic while the daemon
+/// keeps the instance lock.
+fn lock_recovering<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub struct ClipboardState {
+    database_path: PathBuf,
+    database_stamp: DatabaseStamp,
+    history: VecDeque<Arc<ClipboardEntry>>,
+    /// Mirrors `history`; rebuilt whenever history is replaced wholesale.
+    dedup: DedupIndex,
+    history_limit: usize,
+    prune_on_load: PruneOptions,
+    /// Per-application caps applied on every record and load, on top of `history_limit`.
+    retention: RetentionPolicy,
+    /// Clearing saves the history to [`storage::trash_path`] first.
+    trash_on_clear: bool,
+    /// Recorded text longer than this keeps only a preview in history; see [`LongTextStore`].
+    text_preview_limit: Option<usize>,
+    long_texts: LongTextStore,
+    /// How recording and journal replay move entries with the same content.
+    dedup_settings: DedupSettings,
+    /// Changes not yet handed to `SharedClipboardState` subscribers.
+    pending_events: Vec<HistoryEvent>,
+}
+
+impl ClipboardState {
+    pub fn load_default(history_limit: usize) -> Result<Self> {
+        Self::load(storage::default_database_path()?, history_limit)
+    }
+
+    pub fn load(database_path: PathBuf, history_limit: usize) -> Result<Self> {
+        Self::load_pruned(
+            database_path,
+            history_limit,
+            PruneOptions::default(),
+            DedupSettings::default(),
+        )
+    }
+
+    /// Like `load`, but drops entries matching `prune_on_load` now and on every later reload,
+    /// and deduplicates records and journal replay as `dedup_settings` says.
+    pub fn load_pruned(
+        database_path: PathBuf,
+        history_limit: usize,
+        prune_on_load: PruneOptions,
+        dedup_settings: DedupSettings,
+    ) -> Result<Self> {
+        let database_stamp = storage::database_stamp(&database_path);
+        let history =
+            storage::load_history(&database_path, history_limit, prune_on_load, dedup_settings)?;
+        Ok(Self {
+            long_texts: LongTextStore::for_database(&database_path),
+            database_path,
+            database_stamp,
+            dedup: DedupIndex::build(&history),
+            history,
+            history_limit,
+            prune_on_load,
+            retention: RetentionPolicy::default(),
+            trash_on_clear: false,
+            text_preview_limit: None,
+            dedup_settings,
+            pending_events: Vec::new(),
+        })
+    }
+
+    /// The history, newest first, without entries whose expiry has passed but which the next
+    /// sweep ([`expire_entries`](Self::expire_entries)) hasn't removed yet.
+    pub fn history_snapshot(&self) -> Vec<Arc<ClipboardEntry>> {
+        let now = unix_now();
+        self.history
+            .iter()
+            .filter(|entry| !entry.is_expired(now))
+            .cloned()
+            .collect()
+    }
+
+    /// The entry `key` names, with its `slyboard history` id (the oldest entry is 0). `key` is
+    /// a [`ClipboardEntry::stable_id`], which keeps naming the entry while newer copies shift
+    /// it, or else a history id.
+    pub fn find_entry(&self, key: &str) -> Option<(usize, Arc<ClipboardEntry>)> {
+        let history = self.history_snapshot();
+        let key = key.trim();
+        let by_stable_id = (key.len() == 16 && key.bytes().all(|byte| byte.is_ascii_hexdigit()))
+            .then(|| {
+                history
+                    .iter()
+                    .position(|entry| entry.stable_id().eq_ignore_ascii_case(key))
+            })
+            .flatten();
+        let index = by_stable_id.or_else(|| {
+            let id: usize = key.parse().ok()?;
+            history.len().checked_sub(id.checked_add(1)?)
+        })?;
+        Some((history.len() - 1 - index, history[index].clone()))
+    }
+
+    /// Sum of [`approx_size_bytes`](ClipboardEntry::approx_size_bytes) over the loaded history,
+    /// kept up to date on every change rather than recomputed.
+    pub fn total_size(&self) -> u64 {
+        self.dedup.total_size()
+    }
+
+    /// The format and writer versions of the snapshot on disk, or `None` before the first save.
+    pub fn database_header(&self) -> Result<Option<DatabaseHeader>> {
+        storage::read_database_header(&self.database_path)
+    }
+
+    pub fn record_entry(&mut self, value: ClipboardEntry) -> Result<RecordOutcome> {
+        let reloaded = self.reload_if_changed()?;
+        let transient = value.is_transient();
+        // Transient entries are never saved, so neither is their text.
+        let value = match self.text_preview_limit {
+            Some(limit) if !transient => self.long_texts.spill(value, limit)?,
+            _ => value,
+        };
+        let value = Arc::new(value);
+        let mut outcome = RecordOutcome {
+            reloaded,
+            ..push_indexed_history_entry(
+                &mut self.history,
+                &mut self.dedup,
+                self.history_limit,
+                value.clone(),
+                self.dedup_settings,
+            )
+        };
+        if !outcome.inserted {
+            return Ok(outcome);
+        }
+        self.pending_events
+            .push(HistoryEvent::Recorded(self.history[0].clone()));
+        events::record(Event::Capture(&self.history[0]));
+        let mut journal_size = None;
+        if !transient {
+            journal_size = Some(storage::append_journal_record(
+                &self.database_path,
+                &JournalRecord::Add { entry: value },
+            )?);
+        }
+        // Journaled one by one, so replaying the journal doesn't depend on the policy.
+        let retention = self.apply_retention();
+        for (index, entry) in retention.into_iter().chain(self.sweep_expired(unix_now())) {
+            if !entry.is_transient() {
+                journal_size = Some(storage::append_journal_record(
+                    &self.database_path,
+                    &JournalRecord::Remove { index },
+                )?);
+            }
+            outcome.evicted.push(entry);
+        }
+        // A text copied again in another form has a file of its own, leaving the old one unused.
+        let unused: Vec<_> = outcome
+            .evicted
+            .iter()
+            .chain(&outcome.replaced)
+            .cloned()
+            .collect();
+        self.remove_long_texts(&unused);
+        let Some(journal_size) = journal_size else {
+            return Ok(outcome);
+        };
+        self.database_stamp = storage::database_stamp(&self.database_path);
+        if journal_size > storage::JOURNAL_COMPACTION_THRESHOLD_BYTES {
+            self.compact()?;
+        }
+        Ok(outcome)
+    }
+
+    /// Pins or unpins the entry at `index` (newest first). Returns whether anything changed.
+    pub fn set_pinned(&mut self, index: usize, pinned: bool) -> Result<bool> {
+        self.reload_if_changed()?;
+        // Indices count over the snapshot, which leaves expired entries out.
+        self.expire_entries(unix_now())?;
+        let Some(entry) = self.history.get_mut(index) else {
+            return Err(Error::HistoryIndex { index });
+        };
+        if entry.is_pinned() == pinned {
+            return Ok(false);
+        }
+        Arc::make_mut(entry).set_pinned(pinned);
+        self.pending_events
+            .push(HistoryEvent::Pinned { index, pinned });
+        if entry.is_transient() {
+            return Ok(true);
+        }
+
+        let index = saved_index(&self.history, index);
+        storage::append_journal_record(&self.database_path, &JournalRecord::Pin { index, pinned })?;
+        self.database_stamp = storage::database_stamp(&self.database_path);
+        Ok(true)
+    }
+
+    /// Flips the pin of the entry with this [`stable_id`](ClipboardEntry::stable_id), looked up
+    /// after reloading, so copies recorded by another process since the caller's snapshot
+    /// don't shift it onto a different entry. Returns the entry's position (newest first) and
+    /// new pin state, or `None` if it has left history.
+    pub fn toggle_pinned_by_id(&mut self, stable_id: &str) -> Result<Option<(usize, bool)>> {
+        self.reload_if_changed()?;
+        self.expire_entries(unix_now())?;
+        let Some(index) = self
+            .history
+            .iter()
+            .position(|entry| entry.stable_id() == stable_id)
+        else {
+            return Ok(None);
+        };
+        let pinned = !self.history[index].is_pinned();
+        self.set_pinned(index, pinned)?;
+        Ok(Some((index, pinned)))
+    }
+
+    /// Sets or clears the label of the entry at `index` (newest first). Returns whether
+    /// anything changed.
+    pub fn set_label(&mut self, index: usize, label: Option<String>) -> Result<bool> {
+        self.reload_if_changed()?;
+        // Indices count over the snapshot, which leaves expired entries out.
+        self.expire_entries(unix_now())?;
+        let Some(entry) = self.history.get_mut(index) else {
+            return Err(Error::HistoryIndex { index });
+        };
+        if entry.label() == label.as_deref() {
+            return Ok(false);
+        }
+        Arc::make_mut(entry).set_label(label.clone());
+        self.pending_events.push(HistoryEvent::Labeled {
+            index,
+            label: label.clone(),
+        });
+        if entry.is_transient() {
+            return Ok(true);
+        }
+
+        let index = saved_index(&self.history, index);
+        storage::append_journal_record(
+            &self.database_path,
+            &JournalRecord::Label { index, label },
+        )?;
+        self.database_stamp = storage::database_stamp(&self.database_path);
+        Ok(true)
+    }
+
+    /// See [`SharedClipboardState::mark_restored`]; `at` is the Unix time of the restore.
+    pub fn mark_restored(&mut self, entry: &ClipboardEntry, at: u64) -> Result<bool> {
+        self.reload_if_changed()?;
+        let Some(index) = self.dedup.find(&self.history, entry) else {
+            return Ok(false);
+        };
+        let entry = &mut self.history[index];
+        Arc::make_mut(entry).mark_restored(at);
+        self.pending_events.push(HistoryEvent::Restored { index });
+        events::record(Event::Restore(entry));
+        if entry.is_transient() {
+            return Ok(true);
+        }
+
+        let index = saved_index(&self.history, index);
+        storage::append_journal_record(&self.database_path, &JournalRecord::Restore { index, at })?;
+        self.database_stamp = storage::database_stamp(&self.database_path);
+        Ok(true)
+    }
+
+    /// Removes the entry at `index` (newest first).
+    pub fn remove_entry(&mut self, index: usize) -> Result<()> {
+        self.reload_if_changed()?;
+        // Indices count over the snapshot, which leaves expired entries out.
+        self.expire_entries(unix_now())?;
+        let Some(entry) = self.history.remove(index) else {
+            return Err(Error::HistoryIndex { index });
+        };
+        self.dedup.remove(index);
+        self.pending_events.push(HistoryEvent::Removed(index));
+        if entry.is_transient() {
+            return Ok(());
+        }
+
+        // The entries before `index` are still in place.
+        let index = saved_index(&self.history, index);
+        storage::append_journal_record(&self.database_path, &JournalRecord::Remove { index })?;
+        self.database_stamp = storage::database_stamp(&self.database_path);
+        self.remove_long_texts(&[entry]);
+        Ok(())
+    }
+
+    /// Makes [`clear_history`](Self::clear_history) keep what it clears in the trash, for
+    /// [`restore_trash`](Self::restore_trash).
+    pub fn set_trash_on_clear(&mut self, trash_on_clear: bool) {
+        self.trash_on_clear = trash_on_clear;
+    }
+
+    /// Sets `clipboard.text.preview_limit_bytes` for later records, which keep texts whole
+    /// until then; entries already in history stay as they are.
+    pub fn set_text_preview_limit(&mut self, limit: Option<usize>) {
+        self.text_preview_limit = limit;
+    }
+
+    /// Where texts cut to a preview keep their whole text, next to this history's database.
+    pub fn long_texts(&self) -> &LongTextStore {
+        &self.long_texts
+    }
+
+    /// The `clipboard.dedup` settings this history was loaded with.
+    pub fn dedup_settings(&self) -> DedupSettings {
+        self.dedup_settings
+    }
+
+    pub fn clear_history(&mut self) -> Result<()> {
+        if self.trash_on_clear {
+            self.reload_if_changed()?;
+            // Clearing an already empty history keeps the previous trash.
+            if self.history.iter().any(|entry| !entry.is_transient()) {
+                storage::save_history(&storage::trash_path(&self.database_path), &self.history)?;
+            }
+        }
+        self.history.clear();
+        self.dedup.clear();
+        self.pending_events.push(HistoryEvent::Cleared);
+        events::record(Event::Clear);
+        // Journal the clear first so an interrupted compaction can't resurrect old entries.
+        storage::append_journal_record(&self.database_path, &JournalRecord::Clear)?;
+        self.write_compacted()
+    }
+
+    /// Puts the history saved by the last clear back behind the current entries, which stay
+    /// newest, and empties the trash. Returns the number of entries brought back; duplicates
+    /// of current entries and entries beyond the history limit are not.
+    pub fn restore_trash(&mut self) -> Result<usize> {
+        self.reload_if_changed()?;
+        let trash_path = storage::trash_path(&self.database_path);
+        if !trash_path.exists() {
+            return Err(Error::TrashEmpty { path: trash_path });
+        }
+        let mut history = storage::load_trash(&trash_path, self.history_limit)?;
+        for entry in self.history.iter().rev() {
+            push_history_entry(
+                &mut history,
+                self.history_limit,
+                entry.clone(),
+                self.dedup_settings,
+            );
+        }
+        let restored = history.len().saturating_sub(self.history.len());
+        self.dedup = DedupIndex::build(&history);
+        self.history = history;
+        self.pending_events.push(HistoryEvent::Reloaded);
+        self.write_compacted()?;
+        std::fs::remove_file(&trash_path).map_err(|source| Error::StorageIo {
+            action: "remove clipboard history trash",
+            path: trash_path,
+            source,
+        })?;
+        Ok(restored)
+    }
+
+    /// Adds another history's `entries` (newest first, as `slyboard export` writes them),
+    /// placed among the current ones by capture time as [`transfer::merge`] does; where both
+    /// hold the same content, the more recently captured copy stays. Returns how many contents
+    /// were new.
+    pub fn merge_history(&mut self, entries: &[ClipboardEntry]) -> Result<usize> {
+        self.reload_if_changed()?;
+        let current: Vec<ClipboardEntry> = self
+            .history
+            .iter()
+            .map(|entry| entry.as_ref().clone())
+            .collect();
+        let added = transfer::diff(entries, &current).only_left.len();
+        let mut history = VecDeque::new();
+        for entry in transfer::merge(&current, entries).into_iter().rev() {
+            push_history_entry(
+                &mut history,
+                self.history_limit,
+                Arc::new(entry),
+                self.dedup_settings,
+            );
+        }
+        self.dedup = DedupIndex::build(&history);
+        self.history = history;
+        self.pending_events.push(HistoryEvent::Reloaded);
+        self.write_compacted()?;
+        Ok(added)
+    }
+
+    /// Drops entries selected by `options` and rewrites the database if anything was removed.
+    /// Returns the number of removed entries.
+    pub fn prune(&mut self, options: PruneOptions) -> Result<usize> {
+        self.reload_if_changed()?;
+        let removed = prune_history(&mut self.history, options);
+        if removed > 0 {
+            self.dedup = DedupIndex::build(&self.history);
+            self.pending_events.push(HistoryEvent::Reloaded);
+            self.write_compacted()?;
+        }
+        Ok(removed)
+    }
+
+    /// See [`prune_blacklisted_history`].
+    pub fn prune_blacklisted(
+        &mut self,
+        rules: &[BlacklistRule],
+    ) -> Result<BTreeMap<String, usize>> {
+        self.reload_if_changed()?;
+        let removed = prune_blacklisted_history(&mut self.history, rules);
+        if !removed.is_empty() {
+            self.dedup = DedupIndex::build(&self.history);
+            self.pending_events.push(HistoryEvent::Reloaded);
+            self.write_compacted()?;
+        }
+        Ok(removed)
+    }
+
+    /// Replaces the per-application retention rules and applies them to the loaded history,
+    /// rewriting the database if anything was removed. Later records and reloads follow the
+    /// new rules. Returns the number of removed entries.
+    pub fn set_retention(&mut self, retention: RetentionPolicy) -> Result<usize> {
+        self.reload_if_changed()?;
+        self.retention = retention;
+        let removed = self.apply_retention().len();
+        if removed > 0 {
+            self.write_compacted()?;
+        }
+        Ok(removed)
+    }
+
+    /// Drops the entries `retention` no longer keeps, reporting each with the
+    /// [`saved_index`] it had when it was removed.
+    fn apply_retention(&mut self) -> Vec<(usize, Arc<ClipboardEntry>)> {
+        let expired = self.retention.expired_positions(&self.history, unix_now());
+        let mut removed = Vec::with_capacity(expired.len());
+        for index in expired {
+            let saved = saved_index(&self.history, index);
+            if let Some(entry) = self.history.remove(index) {
+                self.dedup.remove(index);
+                self.pending_events.push(HistoryEvent::Removed(index));
+                removed.push((saved, entry));
+            }
+        }
+        removed
+    }
+
+    /// Removes entries whose expiry passed at Unix time `now`, journaling each removal.
+    /// Returns the number of removed entries.
+    pub fn expire_entries(&mut self, now: u64) -> Result<usize> {
+        let removed = self.sweep_expired(now);
+        let mut journaled = false;
+        for (index, entry) in &removed {
+            if !entry.is_transient() {
+                storage::append_journal_record(
+                    &self.database_path,
+                    &JournalRecord::Remove { index: *index },
+                )?;
+                journaled = true;
+            }
+        }
+        if journaled {
+            self.database_stamp = storage::database_stamp(&self.database_path);
+        }
+        let removed: Vec<_> = removed.into_iter().map(|(_, entry)| entry).collect();
+        self.remove_long_texts(&removed);
+        Ok(removed.len())
+    }
+
+    /// Drops the entries expired at `now` from memory, reporting each with the
+    /// [`saved_index`] it had when it was removed.
+    fn sweep_expired(&mut self, now: u64) -> Vec<(usize, Arc<ClipboardEntry>)> {
+        let expired: Vec<usize> = (0..self.history.len())
+            .rev()
+            .filter(|&index| self.history[index].is_expired(now))
+            .collect();
+        let mut removed = Vec::with_capacity(expired.len());
+        for index in expired {
+            let saved = saved_index(&self.history, index);
+            if let Some(entry) = self.history.remove(index) {
+                self.dedup.remove(index);
+                self.pending_events.push(HistoryEvent::Removed(index));
+                removed.push((saved, entry));
+            }
+        }
+        removed
+    }
+
+    /// Folds the journal into the snapshot file.
+    pub fn compact(&mut self) -> Result<()> {
+        self.reload_if_changed()?;
+        self.write_compacted()
+    }
+
+    /// The final save before the process exits: picks up writes from other processes, then
+    /// writes everything to one fresh snapshot and drops the journal, so the next start reads
+    /// a single consistent file. Transient entries are not saved, as always.
+    pub fn shutdown(&mut self) -> Result<()> {
+        self.compact()
+    }
+
+    /// Reloads history from disk when another process wrote the database since this state last
+    /// read or wrote it. Returns whether the in-memory history changed.
+    pub fn reload_if_changed(&mut self) -> Result<bool> {
+        let current_stamp = storage::database_stamp(&self.database_path);
+        if current_stamp == self.database_stamp {
+            return Ok(false);
+        }
+
+        let mut history = storage::load_history(
+            &self.database_path,
+            self.history_limit,
+            self.prune_on_load,
+            self.dedup_settings,
+        )?;
+        self.database_stamp = current_stamp;
+        // Transient entries only live in memory, so carry them over on top of the reloaded history.
+        for entry in self
+            .history
+            .iter()
+            .rev()
+            .filter(|entry| entry.is_transient())
+        {
+            push_history_entry(
+                &mut history,
+                self.history_limit,
+                entry.clone(),
+                self.dedup_settings,
+            );
+        }
+        for index in self.retention.expired_positions(&history, unix_now()) {
+            history.remove(index);
+        }
+        if history == self.history {
+            return Ok(false);
+        }
+        self.dedup = DedupIndex::build(&history);
+        self.history = history;
+        self.pending_events.push(HistoryEvent::Reloaded);
+        Ok(true)
+    }
+
+    /// Drops transient entries whose time-to-live has passed. Returns whether any were removed.
+    pub fn expire_transient_entries(&mut self, now: Instant) -> bool {
+        let expired: Vec<usize> = (0..self.history.len())
+            .rev()
+            .filter(|&index| {
+                self.history[index]
+                    .transient_until()
+                    .is_some_and(|until| until <= now)
+            })
+            .collect();
+        // Remove from the oldest end so each reported index is valid when applied in order.
+        for &index in &expired {
+            self.history.remove(index);
+            self.dedup.remove(index);
+            self.pending_events.push(HistoryEvent::Removed(index));
+        }
+        !expired.is_empty()
+    }
+
+    fn write_compacted(&mut self) -> Result<()> {
+        storage::compact_history(&self.database_path, &self.history)?;
+        self.database_stamp = storage::database_stamp(&self.database_path);
+        // Also catches texts left behind by other processes and interrupted writes.
+        if !self.long_texts.is_empty() {
+            let cleaned = self
+                .referenced_long_texts()
+                .and_then(|referenced| self.long_texts.cleanup(&referenced));
+            if let Err(err) = cleaned {
+                crate::log_eprintln!("warning: failed to clean up long texts: {err}");
+            }
+        }
+        Ok(())
+    }
+
+    /// File stems of the whole texts that history and the trash still refer to.
+    fn referenced_long_texts(&self) -> Result<HashSet<String>> {
+        let mut referenced = referenced_files(self.history.iter().map(Arc::as_ref));
+        let trash_path = storage::trash_path(&self.database_path);
+        if trash_path.exists() {
+            let trash = storage::load_trash(&trash_path, self.history_limit)?;
+            referenced.extend(referenced_files(trash.iter().map(Arc::as_ref)));
+        }
+        Ok(referenced)
+    }
+
+    /// Deletes the whole texts of `removed` entries. The history change already happened, so
+    /// a failure here is only a warning.
+    fn remove_long_texts(&self, removed: &[Arc<ClipboardEntry>]) {
+        if removed.iter().all(|entry| entry.full_text().is_none()) {
+            return;
+        }
+        let result = self.referenced_long_texts().and_then(|referenced| {
+            self.long_texts
+                .remove_for(removed.iter().map(Arc::as_ref), &referenced)
+        });
+        if let Err(err) = result {
+            crate::log_eprintln!("warning: failed to remove long texts: {err}");
+        }
+    }
+}
+
+/// The position the entry at `index` has in the saved history, which the journal addresses:
+/// transient entries are never written, so they don't count.
+fn saved_index(history: &VecDeque<Arc<ClipboardEntry>>, index: usize) -> usize {
+    history
+        .iter()
+        .take(index)
+        .filter(|entry| !entry.is_transient())
+        .count()
+}
+
+/// Puts `value` at the front of history, moving an entry with the same content instead of
+/// duplicating it, then evicts the oldest unpinned entries beyond `history_limit`. Finds the
+/// duplicate with a linear scan; [`push_indexed_history_entry`] is the indexed equivalent.
+/// With `settings.enabled` off nothing is moved, and otherwise only entries within their
+/// scope are, so journal replay with the recording's settings keeps the same duplicates.
+pub fn push_history_entry(
+    history: &mut VecDeque<Arc<ClipboardEntry>>,
+    history_limit: usize,
+    value: Arc<ClipboardEntry>,
+    settings: DedupSettings,
+) -> RecordOutcome {
+    let mut outcome = RecordOutcome::default();
+    if value.is_empty() {
+        return outcome;
+    }
+
+    let mut value = value;
+    let copied_at = value.captured_at().unwrap_or_else(unix_now);
+    // `same_content` leaves `value` carrying the pin state of the entry it matched.
+    let duplicate = settings
+        .enabled
+        .then(|| {
+            history.iter().enumerate().position(|(position, entry)| {
+                settings.scope.covers(position, entry, copied_at)
+                    && value.may_replace(entry)
+                    && same_content(entry, &mut value, settings)
+            })
+        })
+        .flatten();
+    if let Some(index) = duplicate {
+        outcome.deduplicated_from_index = Some(index);
+        if index == 0 && !is_new_form(&history[0], &value) {
+            return outcome;
+        }
+        outcome.replaced = history.remove(index);
+    }
+
+    history.push_front(value);
+    outcome.inserted = true;
+    outcome.evicted = evict_over_limit(history, history_limit, |_| {});
+    outcome.limit_reached = (!outcome.evicted.is_empty()).then_some(history_limit);
+    outcome
+}
+
+/// [`push_history_entry`] with the duplicate found through `index`, which is updated for
+/// every insertion and eviction.
+pub fn push_indexed_history_entry(
+    history: &mut VecDeque<Arc<ClipboardEntry>>,
+    index: &mut DedupIndex,
+    history_limit: usize,
+    value: Arc<ClipboardEntry>,
+    settings: DedupSettings,
+) -> RecordOutcome {
+    let mut outcome = RecordOutcome::default();
+    if value.is_empty() {
+        return outcome;
+    }
+
+    let mut value = value;
+    let copied_at = value.captured_at().unwrap_or_else(unix_now);
+    let duplicate = settings
+        .enabled
+        .then(|| index.find_in_scope(history, &value, settings, copied_at))
+        .flatten();
+    if let Some(position) = duplicate {
+        same_content(&history[position], &mut value, settings);
+        outcome.deduplicated_from_index = Some(position);
+        if position == 0 && !is_new_form(&history[0], &value) {
+            return outcome;
+        }
+        outcome.replaced = history.remove(position);
+        index.remove(position);
+    }
+
+    index.push_front(&value);
+    history.push_front(value);
+    outcome.inserted = true;
+    outcome.evicted = evict_over_limit(history, history_limit, |position| index.remove(position));
+    outcome.limit_reached = (!outcome.evicted.is_empty()).then_some(history_limit);
+    outcome
+}
+
+fn evict_over_limit(
+    history: &mut VecDeque<Arc<ClipboardEntry>>,
+    history_limit: usize,
+    mut on_remove: impl FnMut(usize),
+) -> Vec<Arc<ClipboardEntry>> {
+    let mut evicted = Vec::new();
+    while history.len() > history_limit {
+        // Pinned entries are never evicted; the limit only applies once they are accounted for.
+        let Some(oldest_unpinned) = history.iter().rposition(|entry| !entry.is_pinned()) else {
+            break;
+        };
+        evicted.extend(history.remove(oldest_unpinned));
+        on_remove(oldest_unpinned);
+    }
+    evicted
+}
+
+/// Whether `value`, matched as a duplicate of `existing`, is text in a form deduplication
+/// ignores, such as with a trailing newline. It replaces `existing` even at the front, so the
+/// stored text is always the latest copy.
+fn is_new_form(existing: &ClipboardEntry, value: &ClipboardEntry) -> bool {
+    !existing.same_form(value)
+}
+
+/// Matches entries as `settings` say (see [`DedupSettings::same_content`]). On a match,
+/// `value` takes over the pin flag and restore counts of `existing` (and its label, unless
+/// `value` has one) without cloning either entry, and keeps its own (newest) source window
+/// and origin.
+fn same_content(
+    existing: &Arc<ClipboardEntry>,
+    value: &mut Arc<ClipboardEntry>,
+    settings: DedupSettings,
+) -> bool {
+    if Arc::ptr_eq(existing, value) {
+        return true;
+    }
+    if !settings.same_content(existing, value) {
+        return false;
+    }
+    if existing.is_pinned() != value.is_pinned() {
+        Arc::make_mut(value).set_pinned(existing.is_pinned());
+    }
+    if value.label().is_none() && existing.label().is_some() {
+        Arc::make_mut(value).set_label(existing.label().map(str::to_string));
+    }
+    if value.restores() != existing.restores() {
+        let (count, at) = existing.restores();
+        Arc::make_mut(value).set_restores(count, at);
+    }
+    true
+}
+
+/// Trims a label; `None` for a blank one. Labels are single-line notes of at most
+/// [`MAX_LABEL_CHARS`] characters.
+pub fn validate_label(label: &str) -> Result<Option<String>> {
+    let label = label.trim();
+    if label.is_empty() {
+        return Ok(None);
+    }
+    if label.chars().any(char::is_control) {
+        return Err(Error::InvalidLabel {
+            reason: "labels must be a single line".to_string(),
+        });
+    }
+    let chars = label.chars().count();
+    if chars > MAX_LABEL_CHARS {
+        return Err(Error::InvalidLabel {
+            reason: format!("{chars} characters exceeds the {MAX_LABEL_CHARS}-character limit"),
+        });
+    }
+    Ok(Some(label.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        prune_blacklisted_history, prune_history, CaptureOrigin, ClipboardEntry, ClipboardState,
+        DedupSettings, HistoryEvent, ImageData, ImageEncoding, PruneOptions, RecordOutcome,
+        SharedClipboardState, MAX_LABEL_CHARS,
+    };
+    use crate::clipboard::blacklist::BlacklistRule;
+    use crate::clipboard::long_text::LongTextStore;
+    use crate::clipboard::retention::{RetentionPolicy, RetentionRule};
+    use crate::config::WindowMatchMode;
+    use crate::core::active_window::ActiveWindowContext;
+    use crate::error::Error;
+    use crate::testing::text;
+    use std::collections::VecDeque;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    fn test_database_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!(
+                "slyboard-test-state-{}-{}",
+                std::process::id(),
+                name
+            ))
+            .join("history.json")
+    }
+
+    fn entries(snapshot: Vec<Arc<ClipboardEntry>>) -> Vec<ClipboardEntry> {
+        snapshot.iter().map(|entry| (**entry).clone()).collect()
+    }
+
+    fn image(fill: u8, byte_len: usize) -> ClipboardEntry {
+        ClipboardEntry::Image {
+            width: 1,
+            height: 1,
+            rowstride: byte_len as i32,
+            has_alpha: true,
+            bits_per_sample: 8,
+            channels: 4,
+            encoding: ImageEncoding::Raw,
+            pixels: vec![fill; byte_len],
+            source_window: None,
+            pinned: false,
+            label: None,
+            transient_until: None,
+            captured_at: None,
+            captured_by: None,
+            selection: None,
+            restore_count: 0,
+            last_restored_at: None,
+            expires_at: None,
+            offered_targets: Vec::new(),
+        }
+    }
+
+    fn raw_rgba(width: i32, height: i32, rowstride: i32) -> ClipboardEntry {
+        let pixels = (0..rowstride * height)
+            .map(|byte| (byte * 7) as u8)
+            .collect();
+        ClipboardEntry::Image {
+            width,
+            height,
+            rowstride,
+            has_alpha: true,
+            bits_per_sample: 8,
+            channels: 4,
+            encoding: ImageEncoding::Raw,
+            pixels,
+            source_window: None,
+            pinned: true,
+            label: None,
+            transient_until: None,
+            captured_at: None,
+            captured_by: None,
+            selection: None,
+            restore_count: 0,
+            last_restored_at: None,
+            expires_at: None,
+            offered_targets: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn png_encoding_round_trips_raw_images() {
+        let raw = raw_rgba(5, 3, 24);
+        let encoded = raw.clone().into_png_encoded();
+        let ClipboardEntry::Image {
+            width,
+            height,
+            rowstride,
+            channels,
+            encoding,
+            pixels,
+            ..
+        } = &encoded
+        else {
+            panic!("expected an image entry");
+        };
+        assert_eq!(
+            (*width, *height, *rowstride, *channels, *encoding),
+            (5, 3, 20, 4, ImageEncoding::Png)
+        );
+        assert!(pixels.starts_with(b"\x89PNG"));
+        assert!(encoded.is_pinned(), "metadata survives encoding");
+
+        let decoded = encoded.image_pixels().expect("decode");
+        let original = raw.image_pixels().expect("raw pixels");
+        for row in 0..3 {
+            assert_eq!(
+                &decoded[row * 20..row * 20 + 20],
+                &original[row * 24..row * 24 + 20]
+            );
+        }
+        assert_eq!(encoded.clone().into_png_encoded(), encoded);
+    }
+
+    #[test]
+    fn png_encoding_leaves_unsupported_layouts_raw() {
+        let mut sixteen_bit = raw_rgba(2, 2, 16);
+        if let ClipboardEntry::Image {
+            bits_per_sample, ..
+        } = &mut sixteen_bit
+        {
+            *bits_per_sample = 16;
+        }
+        assert_eq!(sixteen_bit.clone().into_png_encoded(), sixteen_bit);
+        assert!(text("a").image_pixels().is_err());
+    }
+
+    fn cleanup(path: &Path) {
+        let _ = std::fs::remove_dir_all(path.parent().expect("test path has a parent"));
+    }
+
+    #[test]
+    fn dedup_index_follows_every_history_change() {
+        let path = test_database_path("dedup-index");
+        let mut daemon = ClipboardState::load(path.clone(), 3).expect("daemon load");
+        let consistent = |state: &ClipboardState| state.dedup.matches(&state.history);
+
+        for value in ["a", "b", "c", "d", "b"] {
+            daemon.record_entry(text(value)).expect("record");
+            assert!(consistent(&daemon));
+        }
+        assert_eq!(
+            entries(daemon.history_snapshot()),
+            vec![text("b"), text("d"), text("c")]
+        );
+
+        daemon.set_pinned(2, true).expect("pin");
+        daemon.record_entry(text("e")).expect("record e");
+        assert!(consistent(&daemon));
+        daemon.remove_entry(1).expect("remove");
+        assert!(consistent(&daemon));
+
+        let mut cli = ClipboardState::load(path.clone(), 3).expect("cli load");
+        assert!(consistent(&cli));
+        cli.record_entry(text("f")).expect("cli record");
+        daemon.record_entry(text("c")).expect("record after reload");
+        assert!(consistent(&daemon));
+        assert_eq!(
+            daemon
+                .record_entry(text("f"))
+                .unwrap()
+                .deduplicated_from_index,
+            Some(1)
+        );
+
+        daemon.clear_history().expect("clear");
+        assert!(consistent(&daemon));
+        assert!(daemon.dedup.is_empty());
+        cleanup(&path);
+    }
+
+    #[test]
+    fn total_size_matches_a_recount_after_random_operations() {
+        /// xorshift64, as in the dedup index tests.
+        struct Rng(u64);
+        impl Rng {
+            fn below(&mut self, bound: usize) -> usize {
+                self.0 ^= self.0 << 13;
+                self.0 ^= self.0 >> 7;
+                self.0 ^= self.0 << 17;
+                (self.0 % bound as u64) as usize
+            }
+        }
+
+        let path = test_database_path("total-size");
+        cleanup(&path);
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let mut daemon = ClipboardState::load(path.clone(), 8).expect("daemon load");
+        let recount = |state: &ClipboardState| {
+            state
+                .history
+                .iter()
+                .map(|entry| entry.approx_size_bytes())
+                .sum::<u64>()
+        };
+        for step in 0..300 {
+            let len = daemon.history_snapshot().len();
+            match rng.below(12) {
+                0 if len > 0 => daemon.remove_entry(rng.below(len)).expect("remove"),
+                1 if len > 0 => {
+                    daemon
+                        .set_pinned(rng.below(len), rng.below(2) == 0)
+                        .expect("pin");
+                }
+                2 if rng.below(6) == 0 => daemon.clear_history().expect("clear"),
+                3 => {
+                    let mut cli = ClipboardState::load(path.clone(), 8).expect("cli load");
+                    cli.record_entry(text(&"x".repeat(rng.below(40))))
+                        .expect("cli record");
+                }
+                4 => {
+                    daemon
+                        .record_entry(text("secret").with_transient_ttl(Duration::ZERO))
+                        .expect("record transient");
+                    daemon.expire_transient_entries(Instant::now());
+                }
+                5 => {
+                    let side = 1 + rng.below(4) as i32;
+                    daemon
+                        .record_entry(crate::testing::raw_image(side, side, [side as u8; 4]))
+                        .expect("record image");
+                }
+                6 => {
+                    daemon.prune(PruneOptions::all()).expect("prune");
+                }
+                _ => {
+                    daemon
+                        .record_entry(text(&format!("value {}", rng.below(20))))
+                        .expect("record");
+                }
+            }
+            daemon.reload_if_changed().expect("reload");
+            assert_eq!(daemon.total_size(), recount(&daemon), "step {step}");
+        }
+        cleanup(&path);
+    }
+
+    #[test]
+    fn duplicates_in_another_form_keep_the_latest_form() {
+        let path = test_database_path("latest-form");
+        let mut state = ClipboardState::load(path.clone(), 10).expect("load");
+        let values = |state: &ClipboardState| -> Vec<String> {
+            state
+                .history_snapshot()
+                .iter()
+                .filter_map(|entry| match entry.as_ref() {
+                    ClipboardEntry::Text { value, .. } => Some(value.clone()),
+                    ClipboardEntry::Image { .. } => None,
+                })
+                .collect()
+        };
+
+        state.record_entry(text("ls -la\n")).expect("record");
+        state.set_pinned(0, true).expect("pin");
+        state.record_entry(text("pwd")).expect("record");
+        let moved = state.record_entry(text("ls -la")).expect("record");
+        assert_eq!(moved.deduplicated_from_index, Some(1));
+        assert_eq!(values(&state), ["ls -la", "pwd"]);
+        assert!(state.history[0].is_pinned(), "the duplicate's pin is kept");
+
+        let replaced = state.record_entry(text("ls -la\n")).expect("record");
+        assert!(replaced.inserted, "the newest entry takes the new form");
+        assert_eq!(values(&state), ["ls -la\n", "pwd"]);
+        assert!(
+            !state
+                .record_entry(text("ls -la\n"))
+                .expect("record")
+                .inserted
+        );
+        assert!(state.dedup.matches(&state.history));
+
+        let reloaded = ClipboardState::load(path.clone(), 10).expect("reload");
+        assert_eq!(values(&reloaded), ["ls -la\n", "pwd"]);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn records_and_replays_with_the_states_own_dedup_settings() {
+        let path = test_database_path("own-dedup-settings");
+        let off = DedupSettings {
+            enabled: false,
+            ..DedupSettings::default()
+        };
+        let load = |dedup| {
+            ClipboardState::load_pruned(path.clone(), 10, PruneOptions::default(), dedup)
+                .expect("load")
+        };
+        let mut state = load(off);
+        for value in ["a", "b", "a"] {
+            state.record_entry(text(value)).expect("record");
+        }
+        assert_eq!(state.dedup_settings(), off);
+        assert_eq!(state.history_snapshot().len(), 3);
+
+        // The journal still holds all three copies; each history replays it its own way.
+        assert_eq!(load(off).history_snapshot().len(), 3);
+        assert_eq!(
+            entries(load(DedupSettings::default()).history_snapshot()),
+            vec![text("a"), text("b")]
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn toggles_pins_by_id_after_another_process_recorded() {
+        let path = test_database_path("pin-by-id");
+        let picker = SharedClipboardState::load(path.clone(), 10).expect("load");
+        picker.record(text("a")).expect("record a");
+        picker.record(text("b")).expect("record b");
+        let snapshot = picker.history_snapshot();
+
+        // The daemon captures while the picker is open, shifting every position.
+        let daemon = SharedClipboardState::load(path.clone(), 10).expect("load");
+        daemon.record(text("c")).expect("record c");
+
+        let picked = &snapshot[0];
+        assert_eq!(
+            picker.toggle_pinned_by_id(&picked.stable_id()).unwrap(),
+            Some((1, true))
+        );
+        let pinned: Vec<_> = SharedClipboardState::load(path.clone(), 10)
+            .expect("reload")
+            .history_snapshot()
+            .iter()
+            .map(|entry| entry.is_pinned())
+            .collect();
+        assert_eq!(pinned, [false, true, false]);
+        assert_eq!(
+            picker.toggle_pinned_by_id(&picked.stable_id()).unwrap(),
+            Some((1, false))
+        );
+        assert_eq!(
+            picker
+                .toggle_pinned_by_id(&text("gone").stable_id())
+                .unwrap(),
+            None
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn transient_duplicates_leave_saved_entries_where_the_journal_has_them() {
+        let path = test_database_path("transient-duplicate");
+        let mut state = ClipboardState::load(path.clone(), 10).expect("load");
+        state.record_entry(text("a")).expect("record a");
+        state.record_entry(text("b")).expect("record b");
+        let outcome = state
+            .record_entry(text("a").with_transient_ttl(Duration::from_secs(60)))
+            .expect("record transient a");
+        assert!(outcome.inserted && outcome.replaced.is_none());
+        assert_eq!(outcome.deduplicated_from_index, None);
+        state.set_pinned(2, true).expect("pin the saved a");
+        state.remove_entry(1).expect("remove b");
+        assert!(state.dedup.matches(&state.history));
+
+        let saved: Vec<_> = state
+            .history_snapshot()
+            .into_iter()
+            .filter(|entry| !entry.is_transient())
+            .collect();
+        let reloaded = ClipboardState::load(path.clone(), 10).expect("reload");
+        assert_eq!(reloaded.history_snapshot(), saved);
+        assert!(saved[0].is_pinned());
+        cleanup(&path);
+    }
+
+    #[test]
+    fn respects_external_clear_between_captures() {
+        let path = test_database_path("external-clear");
+        let mut daemon = ClipboardState::load(path.clone(), 10).expect("daemon load");
+        daemon.record_entry(text("a")).expect("record a");
+        daemon.record_entry(text("b")).expect("record b");
+
+        let mut cli = ClipboardState::load(path.clone(), 10).expect("cli load");
+        assert_eq!(entries(cli.history_snapshot()), vec![text("b"), text("a")]);
+        cli.clear_history().expect("cli clear");
+
+        daemon.record_entry(text("c")).expect("record c");
+        assert_eq!(entries(daemon.history_snapshot()), vec![text("c")]);
+
+        let reloaded = ClipboardState::load(path.clone(), 10).expect("reload");
+        assert_eq!(entries(reloaded.history_snapshot()), vec![text("c")]);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn compaction_does_not_resurrect_externally_cleared_entries() {
+        let path = test_database_path("external-clear-compact");
+        let mut daemon = ClipboardState::load(path.clone(), 10).expect("daemon load");
+        daemon.record_entry(text("a")).expect("record a");
+
+        let mut cli = ClipboardState::load(path.clone(), 10).expect("cli load");
+        cli.clear_history().expect("cli clear");
+
+        daemon.compact().expect("compact");
+        let reloaded = ClipboardState::load(path.clone(), 10).expect("reload");
+        assert!(entries(reloaded.history_snapshot()).is_empty());
+        cleanup(&path);
+    }
+
+    #[test]
+    fn clear_moves_history_to_the_trash_for_restore() {
+        let path = test_database_path("trash");
+        let trash = crate::clipboard::storage::trash_path(&path);
+        assert_eq!(trash, path.with_file_name("history.json.trash"));
+        let mut daemon = ClipboardState::load(path.clone(), 3).expect("daemon load");
+        daemon.record_entry(text("a")).expect("record a");
+        daemon.clear_history().expect("clear without trash");
+        assert!(!trash.exists());
+        assert!(matches!(
+            daemon.restore_trash(),
+            Err(Error::TrashEmpty { .. })
+        ));
+
+        daemon.set_trash_on_clear(true);
+        daemon.record_entry(text("b")).expect("record b");
+        let mut cli = ClipboardState::load(path.clone(), 3).expect("cli load");
+        cli.record_entry(text("c")).expect("record c");
+        daemon.clear_history().expect("clear");
+        daemon.clear_history().expect("clear again");
+        assert!(daemon.history_snapshot().is_empty());
+
+        for value in ["d", "b"] {
+            daemon
+                .record_entry(text(value))
+                .expect("record after clear");
+        }
+        assert_eq!(daemon.restore_trash().expect("restore"), 1);
+        let expected = vec![text("b"), text("d"), text("c")];
+        assert_eq!(entries(daemon.history_snapshot()), expected);
+        assert!(daemon.dedup.matches(&daemon.history));
+        assert!(!trash.exists(), "restoring empties the trash");
+        let reloaded = ClipboardState::load(path.clone(), 3).expect("reload");
+        assert_eq!(entries(reloaded.history_snapshot()), expected);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn merges_another_history_by_capture_time() {
+        let path = test_database_path("merge");
+        let at = |value: &str, captured_at: u64| text(value).with_captured_at(Some(captured_at));
+        let mut state = ClipboardState::load(path.clone(), 4).expect("load");
+        for entry in [at("a", 100), at("b", 300)] {
+            state.record_entry(entry).expect("record");
+        }
+
+        let mut pinned = at("from laptop", 200);
+        pinned.set_pinned(true);
+        let laptop = [
+            at("a", 400),
+            pinned.clone(),
+            at("old", 50),
+            at("oldest", 10),
+        ];
+        assert_eq!(state.merge_history(&laptop).expect("merge"), 3);
+        let expected = vec![at("a", 400), at("b", 300), pinned, at("old", 50)];
+        assert_eq!(entries(state.history_snapshot()), expected, "limited to 4");
+        assert!(state.dedup.matches(&state.history));
+        let reloaded = ClipboardState::load(path.clone(), 4).expect("reload");
+        assert_eq!(entries(reloaded.history_snapshot()), expected);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn long_text_keeps_a_preview_and_its_file_follows_history() {
+        let path = test_database_path("long-text");
+        let store = LongTextStore::for_database(&path);
+        let whole = "line of a long log\n".repeat(20);
+        let mut daemon = ClipboardState::load(path.clone(), 2).expect("daemon load");
+        daemon.set_text_preview_limit(Some(32));
+        daemon.record_entry(text(&whole)).expect("record long");
+        daemon.record_entry(text("short")).expect("record short");
+
+        let cut = daemon.history_snapshot()[1].clone();
+        assert!(matches!(cut.as_ref(), ClipboardEntry::Text { value, .. } if value.len() == 32));
+        assert_eq!(cut.approx_size_bytes(), 32);
+        assert_eq!(store.restore(&cut).unwrap().into_owned(), text(&whole));
+        // The same text copied again, even without its trailing newline, is the same entry.
+        let outcome = daemon
+            .record_entry(text(whole.trim_end()))
+            .expect("record again");
+        assert_eq!(outcome.deduplicated_from_index, Some(1));
+        assert_eq!(daemon.history_snapshot().len(), 2);
+        assert!(
+            store.restore(&cut).is_err(),
+            "the replaced copy's file is gone"
+        );
+        let reloaded = ClipboardState::load(path.clone(), 2).expect("reload");
+        let latest = reloaded.history_snapshot()[0].clone();
+        assert_eq!(
+            latest.full_text().map(|full| full.bytes),
+            Some(whole.len() as u64 - 1)
+        );
+        assert_eq!(
+            store.restore(&latest).unwrap().into_owned(),
+            text(whole.trim_end())
+        );
+
+        // Evicted: the file goes with the entry.
+        daemon.record_entry(text("b")).expect("record b");
+        daemon.record_entry(text("c")).expect("record c");
+        assert!(store.is_empty());
+
+        daemon
+            .record_entry(text(&whole))
+            .expect("record long again");
+        daemon.set_trash_on_clear(true);
+        daemon.clear_history().expect("clear to trash");
+        assert!(!store.is_empty(), "the trash still needs it");
+        assert_eq!(daemon.restore_trash().expect("restore"), 2);
+        let restored = daemon.history_snapshot()[0].clone();
+        assert_eq!(store.restore(&restored).unwrap().into_owned(), text(&whole));
+
+        daemon.set_trash_on_clear(false);
+        daemon.clear_history().expect("clear");
+        assert!(store.is_empty());
+        cleanup(&path);
+    }
+
+    #[test]
+    fn whole_entries_are_read_next_to_the_states_own_database() {
+        let path = test_database_path("whole-entry");
+        let state = SharedClipboardState::load(path.clone(), 4).expect("load");
+        state.set_text_preview_limit(Some(8));
+        let whole = "z".repeat(40);
+        state.record(text(&whole)).expect("record");
+
+        let cut = state.history_snapshot()[0].clone();
+        assert!(cut.full_text().is_some());
+        assert_eq!(state.whole_entry(&cut).unwrap().into_owned(), text(&whole));
+        let short = text("short");
+        assert!(matches!(
+            state.whole_entry(&short).unwrap(),
+            std::borrow::Cow::Borrowed(_)
+        ));
+        cleanup(&path);
+    }
+
+    #[test]
+    fn long_text_entries_load_in_versions_without_previews() {
+        let saved = r#"{"kind":"text","value":"pre","full_text":{"digest":"00ff","bytes":9}}"#;
+        let entry: ClipboardEntry = serde_json::from_str(saved).expect("parse");
+        assert_eq!(entry.full_text().map(|full| full.bytes), Some(9));
+        let json = serde_json::to_string(&entry).unwrap();
+        assert!(
+            json.ends_with(r#""full_text":{"digest":"00ff","bytes":9}}"#),
+            "{json}"
+        );
+        assert!(!serde_json::to_string(&text("whole"))
+            .unwrap()
+            .contains("full_text"));
+    }
+
+    #[test]
+    fn finds_entries_by_stable_id_before_history_id() {
+        let path = test_database_path("find-entry");
+        let mut state = ClipboardState::load(path.clone(), 10).expect("load");
+        for value in ["a", "b", "c"] {
+            state.record_entry(text(value)).expect("record");
+        }
+        let found = |key: &str| {
+            state
+                .find_entry(key)
+                .map(|(id, entry)| (id, entry.as_ref().clone()))
+        };
+        assert_eq!(found("0"), Some((0, text("a"))));
+        assert_eq!(found(" 2 "), Some((2, text("c"))));
+        assert_eq!(found("3"), None);
+        assert_eq!(found("c"), None);
+
+        let stable_id = text("b").stable_id();
+        assert_eq!(found(&stable_id), Some((1, text("b"))));
+        assert_eq!(found(&stable_id.to_uppercase()), Some((1, text("b"))));
+        // Sixteen digits that name no entry are still read as a history id.
+        assert_eq!(found("0000000000000001"), Some((1, text("b"))));
+        cleanup(&path);
+    }
+
+    #[test]
+    fn reload_if_changed_reports_external_writes() {
+        let path = test_database_path("reload");
+        let mut daemon = ClipboardState::load(path.clone(), 10).expect("daemon load");
+        daemon.record_entry(text("a")).expect("record a");
+        assert!(!daemon.reload_if_changed().expect("reload without changes"));
+
+        let mut cli = ClipboardState::load(path.clone(), 10).expect("cli load");
+        cli.record_entry(text("b")).expect("cli record");
+
+        assert!(daemon
+            .reload_if_changed()
+            .expect("reload after external write"));
+        assert_eq!(
+            entries(daemon.history_snapshot()),
+            vec![text("b"), text("a")]
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn transient_entries_are_not_persisted_and_expire() {
+        let path = test_database_path("transient");
+        let mut daemon = ClipboardState::load(path.clone(), 10).expect("daemon load");
+        daemon.record_entry(text("kept")).expect("record kept");
+        let secret = text("secret").with_transient_ttl(Duration::from_secs(30));
+        assert!(
+            daemon
+                .record_entry(secret.clone())
+                .expect("record secret")
+                .inserted
+        );
+        assert_eq!(
+            entries(daemon.history_snapshot()),
+            vec![secret, text("kept")]
+        );
+
+        daemon.compact().expect("compact");
+        let reloaded = ClipboardState::load(path.clone(), 10).expect("reload");
+        assert_eq!(entries(reloaded.history_snapshot()), vec![text("kept")]);
+
+        assert!(!daemon.expire_transient_entries(Instant::now()));
+        assert!(daemon.expire_transient_entries(Instant::now() + Duration::from_secs(31)));
+        assert_eq!(entries(daemon.history_snapshot()), vec![text("kept")]);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn transient_entries_survive_external_reload() {
+        let path = test_database_path("transient-reload");
+        let mut daemon = ClipboardState::load(path.clone(), 10).expect("daemon load");
+        let secret = text("secret").with_transient_ttl(Duration::from_secs(30));
+        daemon.record_entry(secret.clone()).expect("record secret");
+
+        let mut cli = ClipboardState::load(path.clone(), 10).expect("cli load");
+        cli.record_entry(text("external")).expect("cli record");
+
+        assert!(daemon.reload_if_changed().expect("reload"));
+        assert_eq!(
+            entries(daemon.history_snapshot()),
+            vec![secret, text("external")]
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn journal_addresses_saved_entries_past_transient_ones() {
+        let path = test_database_path("transient-journal");
+        let mut daemon = ClipboardState::load(path.clone(), 10).expect("daemon load");
+        for value in ["a", "b", "c"] {
+            daemon.record_entry(text(value)).expect("record");
+        }
+        let secret = text("secret").with_transient_ttl(Duration::from_secs(30));
+        daemon.record_entry(secret.clone()).expect("record secret");
+
+        // In memory: secret, c, b, a. On disk: c, b, a.
+        assert!(daemon.set_pinned(2, true).expect("pin b"));
+        assert!(daemon
+            .set_label(3, Some("first".to_string()))
+            .expect("label a"));
+        assert!(daemon.mark_restored(&text("a"), 42).expect("restore a"));
+        daemon.remove_entry(1).expect("remove c");
+        assert_eq!(
+            entries(daemon.history_snapshot())[0],
+            secret,
+            "the transient entry stays in memory"
+        );
+
+        let replayed = ClipboardState::load(path.clone(), 10).expect("replay the journal");
+        let mut restored_a = text("a").with_label(Some("first".to_string()));
+        restored_a.mark_restored(42);
+        assert_eq!(
+            entries(replayed.history_snapshot()),
+            vec![text("b").with_pinned(true), restored_a]
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn pinned_entries_survive_history_limit_and_reload() {
+        let path = test_database_path("pinned");
+        let mut daemon = ClipboardState::load(path.clone(), 2).expect("daemon load");
+        daemon.record_entry(text("keep")).expect("record keep");
+        assert!(daemon.set_pinned(0, true).expect("pin"));
+        assert!(!daemon.set_pinned(0, true).expect("pin again"));
+        daemon.record_entry(text("b")).expect("record b");
+        daemon.record_entry(text("c")).expect("record c");
+        assert_eq!(
+            entries(daemon.history_snapshot()),
+            vec![text("c"), text("keep").with_pinned(true)]
+        );
+
+        daemon.record_entry(text("keep")).expect("recapture keep");
+        let reloaded = ClipboardState::load(path.clone(), 2).expect("reload");
+        assert_eq!(
+            entries(reloaded.history_snapshot()),
+            vec![text("keep").with_pinned(true), text("c")]
+        );
+        assert!(daemon.set_pinned(5, true).is_err());
+        cleanup(&path);
+    }
+
+    #[test]
+    fn labels_survive_dedup_eviction_and_reload() {
+        let path = test_database_path("labels");
+        let state = SharedClipboardState::load(path.clone(), 3).expect("load");
+        let events = state.subscribe();
+        state.record(text("hunter2")).expect("record hunter2");
+        assert!(state.set_label(0, "  prod db hint ").expect("label"));
+        assert!(!state.set_label(0, "prod db hint").expect("same label"));
+        let labelled = text("hunter2").with_label(Some("prod db hint".to_string()));
+
+        // Re-copying the same content bumps it to the front and keeps the label.
+        state.record(text("b")).expect("record b");
+        state.record(text("hunter2")).expect("recapture hunter2");
+        assert_eq!(
+            entries(state.history_snapshot()),
+            vec![labelled.clone(), text("b")]
+        );
+
+        // Evicting older entries shifts no labels onto other entries.
+        for value in ["c", "d"] {
+            state.record(text(value)).expect("record");
+        }
+        assert_eq!(
+            entries(state.history_snapshot()),
+            vec![text("d"), text("c"), labelled.clone()]
+        );
+        let reloaded = ClipboardState::load(path.clone(), 3).expect("reload");
+        assert_eq!(
+            entries(reloaded.history_snapshot()),
+            vec![text("d"), text("c"), labelled]
+        );
+
+        // A labelled entry is evicted like any other.
+        state.record(text("e")).expect("record e");
+        assert!(state
+            .history_snapshot()
+            .iter()
+            .all(|entry| entry.label().is_none()));
+
+        assert!(state.set_label(0, "note").expect("label e"));
+        assert!(state.clear_label(0).expect("clear"));
+        assert!(!state.clear_label(0).expect("clear again"));
+        assert!(state.set_label(7, "note").is_err());
+        assert!(matches!(
+            state.set_label(0, "two\nlines"),
+            Err(Error::InvalidLabel { .. })
+        ));
+        assert!(matches!(
+            state.set_label(0, &"x".repeat(MAX_LABEL_CHARS + 1)),
+            Err(Error::InvalidLabel { .. })
+        ));
+        assert!(events.try_iter().any(|event| event
+            == HistoryEvent::Labeled {
+                index: 0,
+                label: Some("prod db hint".to_string()),
+            }));
+        cleanup(&path);
+    }
+
+    #[test]
+    fn restores_update_provenance_without_duplicating_entries() {
+        let path = test_database_path("restores");
+        let state = SharedClipboardState::load(path.clone(), 5).expect("load");
+        let events = state.subscribe();
+        let captured = text("token").with_captured_by(CaptureOrigin::WaylandEvent);
+        state.record(captured.clone()).expect("record token");
+        state.record(text("b")).expect("record b");
+
+        assert!(state.mark_restored(&text("token")).expect("restore"));
+        assert!(state.mark_restored(&text("token")).expect("restore again"));
+        assert!(!state.mark_restored(&text("gone")).expect("not in history"));
+        let history = state.history_snapshot();
+        assert_eq!(history.len(), 2, "restores neither add nor move entries");
+        assert!(history[1].content_eq(&captured));
+        let (count, at) = history[1].restores();
+        assert_eq!(count, 2);
+        assert!(at.is_some());
+        assert_eq!(history[1].captured_by(), Some(CaptureOrigin::WaylandEvent));
+        assert!(events
+            .try_iter()
+            .any(|event| event == HistoryEvent::Restored { index: 1 }));
+
+        let reloaded = ClipboardState::load(path.clone(), 5).expect("reload");
+        assert_eq!(reloaded.history_snapshot()[1].restores(), (count, at));
+
+        // Copying it again keeps the counts and takes the new origin.
+        state
+            .record(text("token").with_captured_by(CaptureOrigin::GtkPoll))
+            .expect("recapture");
+        let history = state.history_snapshot();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].restores(), (count, at));
+        assert_eq!(history[0].captured_by(), Some(CaptureOrigin::GtkPoll));
+        cleanup(&path);
+    }
+
+    #[test]
+    fn provenance_defaults_for_entries_saved_without_it() {
+        let entry: ClipboardEntry =
+            serde_json::from_str(r#"{"kind":"text","value":"old"}"#).expect("parse");
+        assert_eq!(entry.captured_by(), None);
+        assert_eq!(entry.restores(), (0, None));
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            r#"{"kind":"text","value":"old","source_window":null}"#
+        );
+
+        let mut entry = entry.with_captured_by(CaptureOrigin::CliSet);
+        entry.mark_restored(1_700_000_000);
+        let json = serde_json::to_string(&entry).unwrap();
+        assert!(
+            json.contains(
+                r#""captured_by":"cli-set","restore_count":1,"last_restored_at":1700000000"#
+            ),
+            "{json}"
+        );
+    }
+
+    #[test]
+    fn shutdown_persists_history_into_a_single_snapshot_once() {
+        let path = test_database_path("shutdown");
+        let journal = crate::clipboard::storage::journal_path(&path);
+        let state = SharedClipboardState::load(path.clone(), 10).expect("load");
+        state.record(text("a")).expect("record a");
+        state.record(text("b")).expect("record b");
+        state.set_pinned(1, true).expect("pin a");
+        state
+            .record(text("secret").with_transient_ttl(Duration::from_secs(60)))
+            .expect("record transient");
+        assert!(journal.exists());
+
+        assert!(state.clone().shutdown().expect("shutdown"));
+        assert!(
+            !journal.exists(),
+            "shutdown folds the journal into the snapshot"
+        );
+        assert!(!state.shutdown().expect("second shutdown"));
+        let reloaded = ClipboardState::load(path.clone(), 10).expect("reload");
+        assert_eq!(
+            entries(reloaded.history_snapshot()),
+            vec![text("b"), text("a").with_pinned(true)]
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn subscribers_receive_record_and_clear_events() {
+        let path = test_database_path("subscribe");
+        let state = SharedClipboardState::load(path.clone(), 10).expect("load");
+        let events = state.subscribe();
+        let dropped = state.subscribe();
+        drop(dropped);
+
+        assert!(state.record(text("a")).expect("record a").inserted);
+        assert!(!state.record(text("a")).expect("record duplicate").inserted);
+        state.clear_history().expect("clear");
+
+        let received: Vec<HistoryEvent> = events.try_iter().collect();
+        assert_eq!(
+            received,
+            vec![
+                HistoryEvent::Recorded(Arc::new(text("a"))),
+                HistoryEvent::Cleared
+            ]
+        );
+        assert_eq!(state.subscribers.lock().expect("subscribers").len(), 1);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn recovers_from_a_poisoned_state_lock() {
+        let path = test_database_path("poisoned");
+        let state = SharedClipboardState::load(path.clone(), 10).expect("load");
+        state.record(text("before")).expect("record before");
+
+        let poisoner = state.clone();
+        let panicked = std::thread::spawn(move || {
+            poisoner.with_state(|_| panic!("hook panicked while holding the lock"))
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert!(state.inner.is_poisoned());
+
+        let events = state.subscribe();
+        assert!(state.record(text("after")).expect("record after").inserted);
+        assert_eq!(
+            entries(state.history_snapshot()),
+            vec![text("after"), text("before")]
+        );
+        assert_eq!(events.try_iter().count(), 1);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn subscribers_receive_removals_and_reloads() {
+        let path = test_database_path("subscribe-remove");
+        let state = SharedClipboardState::load(path.clone(), 10).expect("load");
+        state.record(text("a")).expect("record a");
+        state.record(text("b")).expect("record b");
+        let events = state.subscribe();
+
+        state.remove_entry(1).expect("remove a");
+        assert!(state.remove_entry(5).is_err());
+        let mut cli = ClipboardState::load(path.clone(), 10).expect("cli load");
+        cli.record_entry(text("c")).expect("cli record");
+        assert!(state.reload_if_changed().expect("reload"));
+
+        let received: Vec<HistoryEvent> = events.try_iter().collect();
+        assert_eq!(
+            received,
+            vec![HistoryEvent::Removed(1), HistoryEvent::Reloaded]
+        );
+        assert_eq!(
+            entries(state.history_snapshot()),
+            vec![text("c"), text("b")]
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn record_outcome_reports_dedup_eviction_and_empty_entries() {
+        let path = test_database_path("outcome");
+        let mut state = ClipboardState::load(path.clone(), 2).expect("load");
+
+        assert_eq!(
+            state.record_entry(text("")).expect("record empty"),
+            RecordOutcome::default()
+        );
+        state.record_entry(text("a")).expect("record a");
+        state.record_entry(text("b")).expect("record b");
+
+        let outcome = state.record_entry(text("c")).expect("record c");
+        assert!(outcome.inserted);
+        assert_eq!(outcome.evicted, vec![Arc::new(text("a"))]);
+        assert_eq!(outcome.limit_reached, Some(2));
+        assert_eq!(outcome.deduplicated_from_index, None);
+
+        let outcome = state.record_entry(text("b")).expect("record b again");
+        assert!(outcome.inserted);
+        assert!(outcome.evicted.is_empty());
+        assert_eq!(outcome.limit_reached, None);
+        assert_eq!(outcome.deduplicated_from_index, Some(1));
+
+        let outcome = state.record_entry(text("b")).expect("record newest again");
+        assert!(!outcome.changed());
+        assert_eq!(outcome.deduplicated_from_index, Some(0));
+        cleanup(&path);
+    }
+
+    fn from_window(value: &str, title: &str) -> ClipboardEntry {
+        text(value).with_source(ActiveWindowContext {
+            backend: "hyprctl".to_string(),
+            title: title.to_string(),
+            app_id: None,
+            initial_app_id: None,
+            initial_title: None,
+            window_id: None,
+            pid: None,
+            workspace_id: None,
+            workspace_name: None,
+            is_xwayland: None,
+            monitor: None,
+            geometry: None,
+        })
+    }
+
+    #[test]
+    fn same_value_from_another_window_is_a_duplicate() {
+        let path = test_database_path("dedup-source");
+        let mut state = ClipboardState::load(path.clone(), 10).expect("load");
+        state
+            .record_entry(from_window("shared", "Editor"))
+            .expect("record from editor");
+        state.record_entry(text("other")).expect("record other");
+
+        let outcome = state
+            .record_entry(from_window("shared", "Terminal"))
+            .expect("record from terminal");
+        assert_eq!(outcome.deduplicated_from_index, Some(1));
+        assert_eq!(
+            entries(state.history_snapshot()),
+            vec![from_window("shared", "Terminal"), text("other")]
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn content_eq_compares_only_the_payload() {
+        let mut hinted = from_window("shared", "Editor").with_pinned(true);
+        if let ClipboardEntry::Text { content_hint, .. } = &mut hinted {
+            *content_hint = Some("plain".to_string());
+        }
+        assert!(hinted.content_eq(&text("shared")));
+        assert!(!hinted.content_eq(&text("other")));
+        assert!(image(1, 4)
+            .content_eq(&image(1, 4).with_source_window(hinted.source_window().cloned())));
+        assert!(!image(1, 4).content_eq(&image(2, 4)));
+        assert!(!text("").content_eq(&image(0, 0)));
+    }
+
+    #[test]
+    fn content_id_follows_content_eq() {
+        let shared = from_window("shared", "Editor").with_pinned(true);
+        assert_eq!(shared.content_id(), text("shared").content_id());
+        assert_ne!(shared.content_id(), text("other").content_id());
+        assert_eq!(
+            image(1, 4).content_id(),
+            image(1, 4)
+                .with_source_window(shared.source_window().cloned())
+                .content_id()
+        );
+        assert_ne!(image(1, 4).content_id(), image(2, 4).content_id());
+
+        assert_eq!(shared.stable_id(), text("shared").stable_id());
+        assert_ne!(shared.stable_id(), text("other").stable_id());
+        assert_eq!(shared.stable_id().len(), 16);
+
+        // Trailing whitespace, which content_eq ignores, leaves the ids alone too.
+        assert!(text("a\n").content_eq(&text("a")));
+        assert_eq!(text("a\n").content_id(), text("a").content_id());
+        assert_eq!(text("a \t\n").stable_id(), text("a").stable_id());
+        assert_ne!(text(" a").stable_id(), text("a").stable_id());
+    }
+
+    #[test]
+    fn bumping_a_pinned_entry_keeps_the_pin_and_takes_the_new_source() {
+        let path = test_database_path("dedup-bump");
+        let mut state = ClipboardState::load(path.clone(), 10).expect("load");
+        state
+            .record_entry(from_window("shared", "Editor").with_pinned(true))
+            .expect("record pinned");
+        state.record_entry(text("other")).expect("record other");
+
+        state
+            .record_entry(from_window("shared", "slyboard-rofi"))
+            .expect("record restored entry");
+        let history = state.history_snapshot();
+        assert_eq!(history.len(), 2, "restoring an entry must not duplicate it");
+        assert!(history[0].is_pinned());
+        assert_eq!(
+            history[0]
+                .source_window()
+                .map(|window| window.title.as_str()),
+            Some("slyboard-rofi")
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn prune_keeps_newest_duplicates_and_drops_blank_and_broken_entries() {
+        let mut broken = image(3, 2);
+        if let ClipboardEntry::Image { rowstride, .. } = &mut broken {
+            *rowstride = 4;
+        }
+        let mut history: VecDeque<Arc<ClipboardEntry>> = [
+            from_window("url", "Terminal"),
+            text("  \n"),
+            text("other"),
+            from_window("url", "Editor").with_pinned(true),
+            broken,
+            image(1, 4),
+            text("url").with_label(Some("docs".to_string())),
+        ]
+        .into_iter()
+        .map(Arc::new)
+        .collect();
+
+        let mut only_blank = history.clone();
+        assert_eq!(
+            prune_history(
+                &mut only_blank,
+                PruneOptions {
+                    empty: true,
+                    ..PruneOptions::default()
+                }
+            ),
+            1
+        );
+        assert_eq!(prune_history(&mut only_blank, PruneOptions::default()), 0);
+
+        assert_eq!(prune_history(&mut history, PruneOptions::all()), 4);
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| (**entry).clone())
+                .collect::<Vec<_>>(),
+            vec![
+                from_window("url", "Terminal")
+                    .with_pinned(true)
+                    .with_label(Some("docs".to_string())),
+                text("other"),
+                image(1, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn image_data_rejects_inconsistent_layouts() {
+        let invalid = |result: crate::error::Result<ImageData>| {
+            matches!(result, Err(Error::InvalidImage { .. }))
+        };
+        assert!(invalid(ImageData::new(2, 2, 4, 8, vec![0; 15])));
+        assert!(invalid(ImageData::new(2, 2, 4, 8, vec![0; 17])));
+        assert!(invalid(ImageData::new(2, 2, 4, 7, vec![0; 16])));
+        assert!(invalid(ImageData::new(0, 2, 4, 8, Vec::new())));
+        assert!(invalid(ImageData::new(2, 2, 2, 4, vec![0; 8])));
+        // The last row may omit its padding, as GDK pixbufs do.
+        assert!(ImageData::new(2, 2, 3, 8, vec![0; 14]).is_ok());
+        assert!(ImageData::new(2, 2, 3, 8, vec![0; 16]).is_ok());
+    }
+
+    #[test]
+    fn constructors_build_png_images_and_plain_text() {
+        let pixels: Vec<u8> = (0..14).collect();
+        let image = ClipboardEntry::image(ImageData::new(2, 2, 3, 8, pixels).unwrap());
+        let ClipboardEntry::Image {
+            encoding,
+            rowstride,
+            has_alpha,
+            ..
+        } = &image
+        else {
+            panic!("expected an image entry");
+        };
+        assert_eq!(
+            (*encoding, *rowstride, *has_alpha),
+            (ImageEncoding::Png, 6, false)
+        );
+        assert_eq!(
+            image.image_pixels().unwrap().as_ref(),
+            [0, 1, 2, 3, 4, 5, 8, 9, 10, 11, 12, 13]
+        );
+
+        let entry = from_window("hello", "Terminal");
+        assert_eq!(
+            entry.source_window().map(|window| window.title.as_str()),
+            Some("Terminal")
+        );
+        assert!(!entry.is_pinned());
+    }
+
+    #[test]
+    fn validates_image_geometry_before_restoring() {
+        let with = |edit: fn(&mut ClipboardEntry)| {
+            let mut entry = raw_rgba(2, 2, 8);
+            edit(&mut entry);
+            entry.validate_image_geometry()
+        };
+        let rejected =
+            |result: crate::error::Result<()>| matches!(result, Err(Error::InvalidImage { .. }));
+
+        assert!(with(|_| {}).is_ok());
+        assert!(text("a").validate_image_geometry().is_ok());
+        // Truncated buffer: one byte short of the last row.
+        assert!(rejected(with(|entry| {
+            if let ClipboardEntry::Image { pixels, .. } = entry {
+                pixels.pop();
+            }
+        })));
+        // Zero and oversized dimensions.
+        assert!(rejected(with(|entry| {
+            if let ClipboardEntry::Image { width, .. } = entry {
+                *width = 0;
+            }
+        })));
+        assert!(rejected(with(|entry| {
+            if let ClipboardEntry::Image { height, .. } = entry {
+                *height = i32::MAX;
+            }
+        })));
+        // Absurd rowstrides, shorter than a row or far past any padding.
+        assert!(rejected(with(|entry| {
+            if let ClipboardEntry::Image { rowstride, .. } = entry {
+                *rowstride = 7;
+            }
+        })));
+        assert!(rejected(with(|entry| {
+            if let ClipboardEntry::Image { rowstride, .. } = entry {
+                *rowstride = i32::MAX;
+            }
+        })));
+        assert!(rejected(with(|entry| {
+            if let ClipboardEntry::Image {
+                bits_per_sample, ..
+            } = entry
+            {
+                *bits_per_sample = 16;
+            }
+        })));
+        assert!(rejected(with(|entry| {
+            if let ClipboardEntry::Image { has_alpha, .. } = entry {
+                *has_alpha = false;
+            }
+        })));
+
+        let png = ClipboardEntry::image(ImageData::new(2, 2, 4, 8, vec![1; 16]).unwrap());
+        assert!(png.validate_image_geometry().is_ok());
+        let mut wrong_stride = png;
+        if let ClipboardEntry::Image { rowstride, .. } = &mut wrong_stride {
+            *rowstride = 12;
+        }
+        assert!(rejected(wrong_stride.validate_image_geometry()));
+    }
+
+    #[test]
+    fn restoring_rejects_raw_rows_that_do_not_fit() {
+        let mut short = raw_rgba(2, 2, 8);
+        if let ClipboardEntry::Image { pixels, .. } = &mut short {
+            pixels.truncate(12);
+        }
+        assert!(matches!(
+            short.image_pixels(),
+            Err(Error::InvalidImage { .. })
+        ));
+        assert!(raw_rgba(2, 2, 8).image_pixels().is_ok());
+    }
+
+    #[test]
+    fn detects_broken_raw_and_png_images() {
+        assert!(!image(1, 4).is_broken_image());
+        assert!(image(1, 3).is_broken_image());
+        assert!(!text(" ").is_broken_image());
+        assert!(text(" \t").is_blank());
+        assert!(!text(" x ").is_blank());
+
+        let png = ClipboardEntry::image(ImageData::new(2, 2, 4, 8, vec![7; 16]).expect("layout"));
+        assert!(!png.is_broken_image());
+        let mut mismatched = png.clone();
+        if let ClipboardEntry::Image { width, .. } = &mut mismatched {
+            *width = 3;
+        }
+        assert!(mismatched.is_broken_image());
+        let mut garbage = png;
+        if let ClipboardEntry::Image { pixels, .. } = &mut garbage {
+            pixels.truncate(10);
+        }
+        assert!(garbage.is_broken_image());
+    }
+
+    #[test]
+    fn prune_persists_and_applies_on_load() {
+        let path = test_database_path("prune");
+        let mut state = ClipboardState::load(path.clone(), 10).expect("load");
+        state.record_entry(text(" ")).expect("record blank");
+        state.record_entry(text("a")).expect("record a");
+
+        let pruned = ClipboardState::load_pruned(
+            path.clone(),
+            10,
+            PruneOptions::all(),
+            DedupSettings::default(),
+        )
+        .expect("load pruned");
+        assert_eq!(entries(pruned.history_snapshot()), vec![text("a")]);
+
+        assert_eq!(state.prune(PruneOptions::all()).expect("prune"), 1);
+        let reloaded = ClipboardState::load(path.clone(), 10).expect("reload");
+        assert_eq!(entries(reloaded.history_snapshot()), vec![text("a")]);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn blacklist_prune_removes_entries_the_poller_would_skip() {
+        let from = |app_id: &str, title: &str, value: &str| {
+            text(value).with_source(ActiveWindowContext {
+                backend: "hyprctl".to_string(),
+                title: title.to_string(),
+                app_id: Some(app_id.to_string()),
+                initial_app_id: None,
+                initial_title: None,
+                window_id: None,
+                pid: None,
+                workspace_id: None,
+                workspace_name: None,
+                is_xwayland: None,
+                monitor: None,
+                geometry: None,
+            })
+        };
+        let rules: Vec<BlacklistRule> = ["Slack", "KeePassXC"]
+            .iter()
+            .map(|value| BlacklistRule::parse(value, WindowMatchMode::Mixed).unwrap())
+            .collect();
+        let mut history: VecDeque<Arc<ClipboardEntry>> = [
+            from("slack", "general", "standup notes"),
+            text("no source"),
+            from("firefox", "Slack | general", "from the slack tab"),
+            from(
+                "org.keepassxc.KeePassXC",
+                "Passwords.kdbx - KeePassXC",
+                "hunter2",
+            )
+            .with_pinned(true),
+            from("firefox", "Rust docs", "docs.rs"),
+        ]
+        .into_iter()
+        .map(Arc::new)
+        .collect();
+
+        let removed = prune_blacklisted_history(&mut history, &rules);
+        assert_eq!(
+            removed.into_iter().collect::<Vec<_>>(),
+            vec![("keepassxc".to_string(), 1), ("slack".to_string(), 2)]
+        );
+        let survivors: Vec<ClipboardEntry> =
+            history.iter().map(|entry| (**entry).clone()).collect();
+        assert_eq!(
+            survivors,
+            vec![text("no source"), from("firefox", "Rust docs", "docs.rs")]
+        );
+
+        let path = test_database_path("prune-blacklist");
+        let mut state = ClipboardState::load(path.clone(), 10).expect("load");
+        state
+            .record_entry(from("slack", "general", "a"))
+            .expect("record");
+        state.record_entry(text("b")).expect("record");
+        assert_eq!(state.prune_blacklisted(&rules).expect("prune").len(), 1);
+        assert!(state
+            .prune_blacklisted(&rules)
+            .expect("prune again")
+            .is_empty());
+        let reloaded = ClipboardState::load(path.clone(), 10).expect("reload");
+        assert_eq!(entries(reloaded.history_snapshot()), vec![text("b")]);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn retention_caps_entries_per_app_on_record_and_load() {
+        let kitty_only = || {
+            RetentionPolicy::new(vec![RetentionRule {
+                rule: BlacklistRule::parse("kitty", WindowMatchMode::Mixed).unwrap(),
+                max_entries: Some(2),
+                ttl: None,
+            }])
+        };
+        let path = test_database_path("retention");
+        let mut state = ClipboardState::load(path.clone(), 4).expect("load");
+        for value in ["k1", "k2", "k3"] {
+            state
+                .record_entry(from_window(value, "kitty"))
+                .expect("record");
+        }
+        state
+            .record_entry(from_window("b1", "Firefox"))
+            .expect("record");
+        assert_eq!(state.set_retention(kitty_only()).expect("retention"), 1);
+        assert_eq!(
+            entries(state.history_snapshot()),
+            vec![
+                from_window("b1", "Firefox"),
+                from_window("k3", "kitty"),
+                from_window("k2", "kitty")
+            ]
+        );
+
+        let outcome = state
+            .record_entry(from_window("k4", "kitty"))
+            .expect("record");
+        assert_eq!(*outcome.evicted[0], from_window("k2", "kitty"));
+        assert_eq!(
+            outcome.limit_reached, None,
+            "removed by retention, not the limit"
+        );
+        state
+            .record_entry(from_window("b2", "Firefox"))
+            .expect("record");
+        let expected = vec![
+            from_window("b2", "Firefox"),
+            from_window("k4", "kitty"),
+            from_window("b1", "Firefox"),
+            from_window("k3", "kitty"),
+        ];
+        assert_eq!(entries(state.history_snapshot()), expected);
+
+        // Evictions are journaled, so a reader without the policy sees the same history.
+        let reloaded = ClipboardState::load(path.clone(), 4).expect("reload");
+        assert_eq!(entries(reloaded.history_snapshot()), expected);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn expired_entries_leave_snapshots_before_the_sweep_removes_them() {
+        let now = crate::clipboard::age::unix_now();
+        let path = test_database_path("expiry");
+        let mut state = ClipboardState::load(path.clone(), 10).expect("load");
+        state
+            .record_entry(text("kept").with_expires_at(Some(now + 3600)))
+            .expect("record");
+        // Expired on arrival, as if the daemon was not running when its time came.
+        state
+            .record_entry(text("bank").with_expires_at(Some(now - 1)))
+            .expect("record");
+        state
+            .record_entry(
+                text("pinned")
+                    .with_expires_at(Some(now - 1))
+                    .with_pinned(true),
+            )
+            .expect("record");
+        state.record_entry(text("plain")).expect("record");
+        // The capture swept "bank". "late" expires without a sweep seeing it yet.
+        assert_eq!(state.history.len(), 3);
+        state
+            .record_entry(text("late").with_expires_at(Some(now + 3600)))
+            .expect("record");
+        Arc::make_mut(&mut state.history[0]).set_expires_at(Some(now - 1));
+
+        let visible = vec![
+            text("plain"),
+            text("pinned")
+                .with_expires_at(Some(now - 1))
+                .with_pinned(true),
+            text("kept").with_expires_at(Some(now + 3600)),
+        ];
+        assert_eq!(entries(state.history_snapshot()), visible);
+        // Indices count over the snapshot: 0 is "plain", not the unswept "late".
+        state.set_label(0, Some("note".to_string())).expect("label");
+        assert_eq!(state.history[0].label(), Some("note"));
+        assert_eq!(state.expire_entries(now).expect("sweep"), 0);
+        assert_eq!(state.expire_entries(now + 3600).expect("sweep"), 1);
+        assert_eq!(state.history.len(), 2);
+
+        let reloaded = ClipboardState::load(path.clone(), 10).expect("reload");
+        assert_eq!(
+            entries(reloaded.history_snapshot()),
+            vec![
+                text("plain").with_label(Some("note".to_string())),
+                text("pinned")
+                    .with_expires_at(Some(now - 1))
+                    .with_pinned(true),
+            ]
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn snapshots_share_entries_regardless_of_pixel_payload() {
+        let path = test_database_path("snapshot-sharing");
+        let mut state = ClipboardState::load(path.clone(), 10).expect("load");
+        for fill in 0..4 {
+            state
+                .record_entry(image(fill, 256 * 1024))
+                .expect("record image");
+        }
+
+        // Taking a snapshot only bumps reference counts; no pixel buffer is copied, so its cost
+        // depends on the number of entries, not on how large the images are.
+        let first = state.history_snapshot();
+        let second = state.history_snapshot();
+        for (a, b) in first.iter().zip(&second) {
+            assert!(Arc::ptr_eq(a, b), "snapshots should share entries");
+        }
+        cleanup(&path);
     }
-    true
 }
@@ -1,72 +1,328 @@
-use std::collections::VecDeque;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
 
-use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
-use crate::clipboard::state::ClipboardEntry;
+use crate::clipboard::dedup::DedupSettings;
+use crate::clipboard::migrations;
+use crate::clipboard::state::{
+    prune_history, push_history_entry, ClipboardEntry, ImageEncoding, PruneOptions,
+};
+use crate::error::{Error, Result};
 
 const CACHE_DIR_NAME: &str = "slyboard";
 const HISTORY_FILE_NAME: &str = "history.json";
+const SLOTS_FILE_NAME: &str = "slots.json";
+const THUMBNAILS_DIR_NAME: &str = "thumbnails";
+const LONG_TEXTS_DIR_NAME: &str = "texts";
+const JOURNAL_FILE_EXTENSION: &str = "jsonl";
+pub const JOURNAL_COMPACTION_THRESHOLD_BYTES: u64 = 8 * 1024 * 1024;
+const RUNNING_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+static REFUSE_NEWER_WRITER: AtomicBool = AtomicBool::new(false);
+static WARNED_NEWER_WRITER: AtomicBool = AtomicBool::new(false);
+
+/// Makes loading a snapshot last written by a newer major slyboard fail instead of warn. The
+/// daemon sets this unless run with `--force`, since its compactions rewrite the snapshot and
+/// would drop any fields the newer version added.
+pub fn set_refuse_newer_writer(refuse: bool) {
+    REFUSE_NEWER_WRITER.store(refuse, Ordering::Relaxed);
+}
+
+/// The snapshot file; older layouts are upgraded by [`migrations`] before parsing. The writer
+/// versions are missing from snapshots saved before they were recorded.
 #[derive(Debug, Serialize, Deserialize)]
 struct HistoryDatabase {
-    history: Vec<ClipboardEntry>,
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_by_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_written_by_version: Option<String>,
+    /// The journal generation folded into this snapshot. A journal of that generation or an
+    /// older one, left behind by a compaction that died before removing it, is not replayed.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    journal_generation: u64,
+    history: Vec<Arc<ClipboardEntry>>,
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+/// Just the part of a snapshot a save carries over, without parsing its entries.
+#[derive(Debug, Default, Deserialize)]
+struct SnapshotHeader {
+    #[serde(default)]
+    created_by_version: Option<String>,
+    #[serde(default)]
+    journal_generation: u64,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum HistoryDatabaseCompat {
-    Current(HistoryDatabase),
-    Legacy { history: Vec<String> },
+/// Which format a snapshot has and which slyboard versions wrote it, as `slyboard status`
+/// reports it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DatabaseHeader {
+    pub format_version: u32,
+    pub created_by_version: Option<String>,
+    pub last_written_by_version: Option<String>,
 }
 
+impl DatabaseHeader {
+    /// "format version 2, created by 0.2.9, last written by 0.3.0".
+    pub fn summary(&self) -> String {
+        let version =
+            |version: &Option<String>| version.as_deref().unwrap_or("unknown").to_string();
+        format!(
+            "format version {}, created by {}, last written by {}",
+            self.format_version,
+            version(&self.created_by_version),
+            version(&self.last_written_by_version)
+        )
+    }
+}
+
+/// Named slots (`slyboard yank`), stored apart from the capped history.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SlotsDatabase {
+    slots: BTreeMap<String, Arc<ClipboardEntry>>,
+}
+
+/// A single mutation appended to the history journal (`history.jsonl`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum JournalRecord {
+    /// The first record of a journal: one past the snapshot's `journal_generation` when the
+    /// journal was started. Journals written before it existed have none and always replay.
+    Generation {
+        generation: u64,
+    },
+    Add {
+        entry: Arc<ClipboardEntry>,
+    },
+    Remove {
+        index: usize,
+    },
+    Pin {
+        index: usize,
+        pinned: bool,
+    },
+    Label {
+        index: usize,
+        label: Option<String>,
+    },
+    Restore {
+        index: usize,
+        at: u64,
+    },
+    Clear,
+}
+
+/// Size and modification time of the snapshot and journal files, used to notice writes made
+/// by another process (for example `slyboard clear` while the daemon is running).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DatabaseStamp {
+    snapshot: Option<FileStamp>,
+    journal: Option<FileStamp>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+pub fn database_stamp(database_path: &Path) -> DatabaseStamp {
+    DatabaseStamp {
+        snapshot: file_stamp(database_path),
+        journal: file_stamp(&journal_path(database_path)),
+    }
+}
+
+fn file_stamp(path: &Path) -> Option<FileStamp> {
+    let metadata = fs::metadata(path).ok()?;
+    Some(FileStamp {
+        len: metadata.len(),
+        modified: metadata.modified().ok(),
+    })
+}
+
+/// `slyboard/history.json` in the user cache dir: `$XDG_CACHE_HOME` (or `~/.cache`) on Linux,
+/// `~/Library/Caches` on macOS, `%LOCALAPPDATA%` on Windows.
 pub fn default_database_path() -> Result<PathBuf> {
-    let cache_root = dirs::cache_dir()
-        .or_else(|| dirs::home_dir().map(|home| home.join(".cache")))
-        .context("unable to resolve cache directory from environment")?;
+    database_path_in(dirs::cache_dir(), dirs::home_dir())
+}
+
+/// [`default_database_path`] for the given cache and home dirs.
+pub fn database_path_in(cache_dir: Option<PathBuf>, home_dir: Option<PathBuf>) -> Result<PathBuf> {
+    let cache_root = cache_dir
+        .or_else(|| home_dir.map(|home| home.join(".cache")))
+        .ok_or(Error::MissingDirectory { kind: "cache" })?;
 
     Ok(cache_root.join(CACHE_DIR_NAME).join(HISTORY_FILE_NAME))
 }
 
-pub fn load_history(path: &PathBuf, history_limit: usize) -> Result<VecDeque<ClipboardEntry>> {
-    if !path.exists() {
-        return Ok(VecDeque::new());
-    }
+pub fn journal_path(database_path: &Path) -> PathBuf {
+    database_path.with_extension(JOURNAL_FILE_EXTENSION)
+}
 
-    let raw = std::fs::read_to_string(path).with_context(|| {
-        format!(
-            "failed to read clipboard history database: {}",
-            path.display()
-        )
-    })?;
-    let db: HistoryDatabaseCompat = serde_json::from_str(&raw).with_context(|| {
-        format!(
-            "failed to parse clipboard history database: {}",
-            path.display()
-        )
+/// Where a clear moves history with `storage.trash_on_clear`: `history.json.trash`, one level
+/// deep and replaced by the next clear.
+pub fn trash_path(database_path: &Path) -> PathBuf {
+    let mut path = database_path.as_os_str().to_owned();
+    path.push(".trash");
+    PathBuf::from(path)
+}
+
+pub fn slots_path(database_path: &Path) -> PathBuf {
+    database_path.with_file_name(SLOTS_FILE_NAME)
+}
+
+pub fn thumbnails_dir(database_path: &Path) -> PathBuf {
+    database_path.with_file_name(THUMBNAILS_DIR_NAME)
+}
+
+/// Where text entries cut to `clipboard.text.preview_limit_bytes` keep their whole text.
+pub fn long_texts_dir(database_path: &Path) -> PathBuf {
+    database_path.with_file_name(LONG_TEXTS_DIR_NAME)
+}
+
+pub fn load_slots(path: &Path) -> Result<BTreeMap<String, Arc<ClipboardEntry>>> {
+    let raw = match fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(source) => return Err(io_error("read clipboard slots", path)(source)),
+    };
+    let database: SlotsDatabase =
+        serde_json::from_str(&raw).map_err(|err| Error::StorageCorrupt {
+            path: path.to_path_buf(),
+            reason: err.to_string(),
+        })?;
+    Ok(database.slots)
+}
+
+pub fn save_slots(path: &Path, slots: &BTreeMap<String, Arc<ClipboardEntry>>) -> Result<()> {
+    ensure_parent_dir(path)?;
+    let raw = serde_json::to_string_pretty(&SlotsDatabase {
+        slots: slots.clone(),
     })?;
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, raw).map_err(io_error("write clipboard slots", &temp_path))?;
+    fs::rename(&temp_path, path).map_err(io_error("replace clipboard slots", path))?;
+    Ok(())
+}
 
-    let mut history = VecDeque::new();
-    match db {
-        HistoryDatabaseCompat::Current(current) => {
-            for item in current.history {
-                if !item.is_empty() {
-                    history.push_back(item);
-                }
-            }
+/// Loads the snapshot, replays the journal on top deduplicating as `dedup` says, and drops
+/// entries matching `prune`.
+pub fn load_history(
+    path: &Path,
+    history_limit: usize,
+    prune: PruneOptions,
+    dedup: DedupSettings,
+) -> Result<VecDeque<Arc<ClipboardEntry>>> {
+    let (mut history, folded_generation) = load_snapshot(path, history_limit)?;
+    let records = read_journal(&journal_path(path))?;
+    let already_folded = matches!(
+        records.first(),
+        Some(JournalRecord::Generation { generation }) if *generation <= folded_generation
+    );
+    if !already_folded {
+        for record in records {
+            apply_journal_record(&mut history, history_limit, record, dedup);
         }
-        HistoryDatabaseCompat::Legacy {
-            history: old_entries,
-        } => {
-            for item in old_entries {
-                if !item.is_empty() {
-                    history.push_back(ClipboardEntry::Text {
-                        value: item,
-                        source_window: None,
-                    });
-                }
+    }
+    drop_invalid_images(&mut history);
+    encode_raw_images(&mut history);
+    prune_history(&mut history, prune);
+    Ok(history)
+}
+
+/// Drops images whose stored bytes don't match their geometry (a corrupted or hand-edited
+/// database), so they never reach a pixbuf on restore.
+fn drop_invalid_images(history: &mut VecDeque<Arc<ClipboardEntry>>) {
+    history.retain(|entry| match entry.validate_image_geometry() {
+        Ok(()) => true,
+        Err(err) => {
+            crate::log_eprintln!("warning: dropping clipboard history image: {err}");
+            false
+        }
+    });
+}
+
+/// Encodes raw pixbuf images as PNG. Snapshots are migrated on load, but a journal written by
+/// an older version may still add raw images; they reach disk with the next compaction.
+fn encode_raw_images(history: &mut VecDeque<Arc<ClipboardEntry>>) {
+    for entry in history.iter_mut() {
+        if matches!(
+            **entry,
+            ClipboardEntry::Image {
+                encoding: ImageEncoding::Raw,
+                ..
             }
+        ) {
+            *entry = Arc::new((**entry).clone().into_png_encoded());
+        }
+    }
+}
+
+/// The snapshot's history and the journal generation folded into it.
+fn load_snapshot(
+    path: &Path,
+    history_limit: usize,
+) -> Result<(VecDeque<Arc<ClipboardEntry>>, u64)> {
+    if !path.exists() {
+        return Ok((VecDeque::new(), 0));
+    }
+
+    let raw =
+        fs::read_to_string(path).map_err(io_error("read clipboard history database", path))?;
+    let corrupt = |reason: String| Error::StorageCorrupt {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let mut document: serde_json::Value =
+        serde_json::from_str(&raw).map_err(|err| corrupt(err.to_string()))?;
+    let version = migrations::detect_version(&document).map_err(corrupt)?;
+    if version > migrations::CURRENT_VERSION {
+        return Err(Error::StorageNewerVersion {
+            path: path.to_path_buf(),
+            version,
+            supported: migrations::CURRENT_VERSION,
+        });
+    }
+    if let Some(written_by) = document
+        .get("last_written_by_version")
+        .and_then(serde_json::Value::as_str)
+        .filter(|written_by| is_newer_major(written_by, RUNNING_VERSION))
+    {
+        check_newer_writer(path, written_by)?;
+    }
+    migrations::migrate(&mut document, version).map_err(corrupt)?;
+    let db: HistoryDatabase =
+        serde_json::from_value(document).map_err(|err| corrupt(err.to_string()))?;
+
+    let mut history: VecDeque<Arc<ClipboardEntry>> = db
+        .history
+        .into_iter()
+        .filter(|item| !item.is_empty())
+        .collect();
+    if version < migrations::CURRENT_VERSION {
+        // Rewritten before the history limit applies, so migrating never drops entries.
+        let header = SnapshotHeader {
+            created_by_version: db.created_by_version,
+            journal_generation: db.journal_generation,
+        };
+        match write_snapshot(path, &history, header) {
+            Ok(()) => crate::log_eprintln!(
+                "Migrated clipboard history from format version {version} to {}: {}",
+                migrations::CURRENT_VERSION,
+                path.display()
+            ),
+            Err(err) => crate::log_eprintln!("warning: failed to rewrite migrated history: {err}"),
         }
     }
 
@@ -74,29 +330,840 @@ pub fn load_history(path: &PathBuf, history_limit: usize) -> Result<VecDeque<Cli
         history.pop_back();
     }
 
+    Ok((history, db.journal_generation))
+}
+
+/// Loads a trashed history. Unlike [`load_history`] there is no journal to replay: the trash
+/// is always written as one full snapshot.
+pub fn load_trash(path: &Path, history_limit: usize) -> Result<VecDeque<Arc<ClipboardEntry>>> {
+    let (mut history, _) = load_snapshot(path, history_limit)?;
+    drop_invalid_images(&mut history);
+    encode_raw_images(&mut history);
     Ok(history)
 }
 
-pub fn save_history(path: &PathBuf, history: &VecDeque<ClipboardEntry>) -> Result<()> {
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent).with_context(|| {
-            format!(
-                "failed to create clipboard history cache directory: {}",
-                parent.display()
-            )
-        })?;
+/// Reads the format and writer versions of the snapshot at `path`, or `None` if there is none.
+pub fn read_database_header(path: &Path) -> Result<Option<DatabaseHeader>> {
+    let raw = match fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(source) => return Err(io_error("read clipboard history database", path)(source)),
+    };
+    let corrupt = |reason: String| Error::StorageCorrupt {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let document: serde_json::Value =
+        serde_json::from_str(&raw).map_err(|err| corrupt(err.to_string()))?;
+    let writer = |key: &str| {
+        document
+            .get(key)
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_string)
+    };
+    Ok(Some(DatabaseHeader {
+        format_version: migrations::detect_version(&document).map_err(corrupt)?,
+        created_by_version: writer("created_by_version"),
+        last_written_by_version: writer("last_written_by_version"),
+    }))
+}
+
+/// Saves `history` as the snapshot at `path`, keeping the version that created the file and
+/// the journal generation folded into it.
+pub fn save_history(path: &Path, history: &VecDeque<Arc<ClipboardEntry>>) -> Result<()> {
+    write_snapshot(path, history, existing_header(path))
+}
+
+/// What to keep when replacing the snapshot at `path`. `created_by_version` is this version
+/// for a new file, and unknown for one saved before writer versions were recorded.
+fn existing_header(path: &Path) -> SnapshotHeader {
+    match fs::read_to_string(path) {
+        Ok(raw) => serde_json::from_str(&raw).unwrap_or_default(),
+        Err(_) => SnapshotHeader {
+            created_by_version: Some(RUNNING_VERSION.to_string()),
+            journal_generation: 0,
+        },
     }
+}
+
+fn write_snapshot(
+    path: &Path,
+    history: &VecDeque<Arc<ClipboardEntry>>,
+    header: SnapshotHeader,
+) -> Result<()> {
+    ensure_parent_dir(path)?;
 
     let db = HistoryDatabase {
-        history: history.iter().cloned().collect(),
+        version: migrations::CURRENT_VERSION,
+        created_by_version: header.created_by_version,
+        last_written_by_version: Some(RUNNING_VERSION.to_string()),
+        journal_generation: header.journal_generation,
+        history: history
+            .iter()
+            .filter(|entry| !entry.is_transient())
+            .cloned()
+            .collect(),
     };
-    let raw = serde_json::to_string_pretty(&db).context("failed to serialize clipboard history")?;
-    std::fs::write(path, raw).with_context(|| {
-        format!(
-            "failed to write clipboard history database: {}",
+    let raw = serde_json::to_string_pretty(&db)?;
+    // Write to a sibling file and rename so a crash never leaves a truncated snapshot behind.
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, raw).map_err(io_error("write clipboard history database", &temp_path))?;
+    fs::rename(&temp_path, path).map_err(io_error("replace clipboard history database", path))?;
+
+    Ok(())
+}
+
+/// Writes a full snapshot and drops the journal whose records it now contains.
+///
+/// The snapshot records the journal's generation, so if the process dies between the two
+/// steps the leftover journal is skipped on load rather than replayed: its `Remove`, `Pin`,
+/// `Label` and `Restore` records address entries by position and would hit the wrong ones.
+pub fn compact_history(path: &Path, history: &VecDeque<Arc<ClipboardEntry>>) -> Result<()> {
+    let journal = journal_path(path);
+    let mut header = existing_header(path);
+    if let Some(generation) = journal_generation(&journal) {
+        header.journal_generation = header.journal_generation.max(generation);
+    }
+    write_snapshot(path, history, header)?;
+
+    if journal.exists() {
+        fs::remove_file(&journal).map_err(io_error(
+            "remove compacted clipboard history journal",
+            &journal,
+        ))?;
+    }
+    Ok(())
+}
+
+/// The generation a journal started with, or `None` for a missing journal or one written
+/// before generations were recorded.
+fn journal_generation(journal: &Path) -> Option<u64> {
+    let file = fs::File::open(journal).ok()?;
+    let mut first_line = String::new();
+    io::BufReader::new(file).read_line(&mut first_line).ok()?;
+    match serde_json::from_str(&first_line).ok()? {
+        JournalRecord::Generation { generation } => Some(generation),
+        _ => None,
+    }
+}
+
+/// Appends one record to the journal and returns the journal size afterwards. A new journal
+/// starts with its [`JournalRecord::Generation`].
+pub fn append_journal_record(database_path: &Path, record: &JournalRecord) -> Result<u64> {
+    let journal = journal_path(database_path);
+    ensure_parent_dir(&journal)?;
+
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+
+    let mut file = OpenOptions::new()
+        .read(true)
+        .create(true)
+        .append(true)
+        .open(&journal)
+        .map_err(io_error("open clipboard history journal", &journal))?;
+    let is_new = file
+        .metadata()
+        .map_err(io_error("open clipboard history journal", &journal))?
+        .len()
+        == 0;
+    if is_new {
+        let generation = JournalRecord::Generation {
+            generation: existing_header(database_path).journal_generation + 1,
+        };
+        let mut header = serde_json::to_string(&generation)?;
+        header.push('\n');
+        line.insert_str(0, &header);
+    }
+
+    append_line(&mut file, line).map_err(io_error("append to clipboard history journal", &journal))
+}
+
+fn append_line(file: &mut fs::File, mut line: String) -> io::Result<u64> {
+    // A crash mid-append leaves a partial last line; start a fresh line so the
+    // next record isn't glued onto the torn one.
+    if ends_with_partial_line(file)? {
+        line.insert(0, '\n');
+    }
+    file.write_all(line.as_bytes())?;
+    file.flush()?;
+    Ok(file.metadata()?.len())
+}
+
+fn read_journal(path: &Path) -> Result<Vec<JournalRecord>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let raw = fs::read_to_string(path).map_err(io_error("read clipboard history journal", path))?;
+    parse_journal(&raw).map_err(|reason| Error::StorageCorrupt {
+        path: path.to_path_buf(),
+        reason,
+    })
+}
+
+fn parse_journal(raw: &str) -> std::result::Result<Vec<JournalRecord>, String> {
+    let mut records = Vec::new();
+    let complete = raw.ends_with('\n');
+    let lines: Vec<&str> = raw.lines().collect();
+    for (index, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<JournalRecord>(line) {
+            Ok(record) => records.push(record),
+            // The last line of a journal without a trailing newline was torn by a crash.
+            Err(_) if index + 1 == lines.len() && !complete => break,
+            // Earlier torn lines were followed by a fresh record on the next line.
+            Err(_) if is_torn_line(line) => continue,
+            Err(err) => {
+                return Err(format!(
+                    "invalid journal record on line {}: {err}",
+                    index + 1
+                ))
+            }
+        }
+    }
+    Ok(records)
+}
+
+fn is_torn_line(line: &str) -> bool {
+    !line.trim_end().ends_with('}')
+}
+
+fn apply_journal_record(
+    history: &mut VecDeque<Arc<ClipboardEntry>>,
+    history_limit: usize,
+    record: JournalRecord,
+    dedup: DedupSettings,
+) {
+    match record {
+        JournalRecord::Generation { .. } => {}
+        JournalRecord::Add { entry } => {
+            push_history_entry(history, history_limit, entry, dedup);
+        }
+        JournalRecord::Remove { index } => {
+            history.remove(index);
+        }
+        JournalRecord::Pin { index, pinned } => {
+            if let Some(entry) = history.get_mut(index) {
+                Arc::make_mut(entry).set_pinned(pinned);
+            }
+        }
+        JournalRecord::Label { index, label } => {
+            if let Some(entry) = history.get_mut(index) {
+                Arc::make_mut(entry).set_label(label);
+            }
+        }
+        JournalRecord::Restore { index, at } => {
+            if let Some(entry) = history.get_mut(index) {
+                Arc::make_mut(entry).mark_restored(at);
+            }
+        }
+        JournalRecord::Clear => history.clear(),
+    }
+}
+
+fn ends_with_partial_line(file: &mut fs::File) -> io::Result<bool> {
+    let len = file.metadata()?.len();
+    if len == 0 {
+        return Ok(false);
+    }
+    file.seek(SeekFrom::Start(len - 1))?;
+    let mut last = [0u8; 1];
+    file.read_exact(&mut last)?;
+    Ok(last[0] != b'\n')
+}
+
+/// Refuses or, once per process, warns about a snapshot last written by `written_by`.
+fn check_newer_writer(path: &Path, written_by: &str) -> Result<()> {
+    if REFUSE_NEWER_WRITER.load(Ordering::Relaxed) {
+        return Err(Error::StorageNewerWriter {
+            path: path.to_path_buf(),
+            written_by: written_by.to_string(),
+            running: RUNNING_VERSION,
+        });
+    }
+    if !WARNED_NEWER_WRITER.swap(true, Ordering::Relaxed) {
+        crate::log_eprintln!(
+            "warning: clipboard history was last written by slyboard {written_by}, newer than \
+             this {RUNNING_VERSION}; fields it added are lost if this version saves it: {}",
             path.display()
-        )
-    })?;
+        );
+    }
+    Ok(())
+}
+
+/// Whether `version` is a newer release line than `running`: a higher major version, or for
+/// `0.x` versions a higher minor, as Cargo treats them.
+fn is_newer_major(version: &str, running: &str) -> bool {
+    match (release_line(version), release_line(running)) {
+        (Some(version), Some(running)) => version > running,
+        _ => false,
+    }
+}
 
+fn release_line(version: &str) -> Option<(u64, u64)> {
+    let mut parts = version.split(['.', '-', '+']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().map_or(Some(0), |minor| minor.parse().ok())?;
+    Some(if major == 0 { (0, minor) } else { (major, 0) })
+}
+
+fn ensure_parent_dir(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(io_error("create clipboard history cache directory", parent))?;
+    }
     Ok(())
 }
+
+pub(crate) fn io_error<'a>(
+    action: &'static str,
+    path: &'a Path,
+) -> impl FnOnce(io::Error) -> Error + 'a {
+    move |source| Error::StorageIo {
+        action,
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        append_journal_record, compact_history, database_path_in, is_newer_major, journal_path,
+        load_history, read_database_header, save_history, set_refuse_newer_writer, slots_path,
+        JournalRecord, PruneOptions, RUNNING_VERSION,
+    };
+    use crate::clipboard::dedup::{DedupScope, DedupSettings};
+    use crate::clipboard::migrations::{self, CURRENT_VERSION};
+    use crate::clipboard::{ClipboardEntry, ImageEncoding};
+    use crate::error::Error;
+    use crate::testing::text;
+    use std::collections::VecDeque;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    fn test_database_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!(
+                "slyboard-test-storage-{}-{}",
+                std::process::id(),
+                name
+            ))
+            .join("history.json")
+    }
+
+    fn add(value: &str) -> JournalRecord {
+        JournalRecord::Add {
+            entry: Arc::new(text(value)),
+        }
+    }
+
+    fn cleanup(path: &Path) {
+        let _ = std::fs::remove_dir_all(path.parent().expect("test path has a parent"));
+    }
+
+    #[test]
+    fn resolves_the_database_under_each_platforms_cache_dir() {
+        for cache in [
+            "/home/alice/.cache",
+            "/Users/alice/Library/Caches",
+            r"C:\Users\alice\AppData\Local",
+        ] {
+            let path = database_path_in(Some(PathBuf::from(cache)), None).unwrap();
+            assert_eq!(path, Path::new(cache).join("slyboard").join("history.json"));
+            assert_eq!(
+                slots_path(&path),
+                Path::new(cache).join("slyboard").join("slots.json")
+            );
+        }
+        assert_eq!(
+            database_path_in(None, Some(PathBuf::from("/home/alice"))).unwrap(),
+            Path::new("/home/alice/.cache/slyboard/history.json")
+        );
+        assert!(matches!(
+            database_path_in(None, None),
+            Err(Error::MissingDirectory { kind: "cache" })
+        ));
+    }
+
+    #[test]
+    fn drops_images_with_invalid_geometry_on_load() {
+        let path = test_database_path("invalid-geometry");
+        let image = |pixels: usize| {
+            Arc::new(ClipboardEntry::Image {
+                width: 2,
+                height: 2,
+                rowstride: 8,
+                has_alpha: true,
+                bits_per_sample: 8,
+                channels: 4,
+                encoding: ImageEncoding::Raw,
+                pixels: vec![9; pixels],
+                source_window: None,
+                pinned: false,
+                label: None,
+                transient_until: None,
+                captured_at: None,
+                captured_by: None,
+                selection: None,
+                restore_count: 0,
+                last_restored_at: None,
+                expires_at: None,
+                offered_targets: Vec::new(),
+            })
+        };
+        compact_history(
+            &path,
+            &VecDeque::from(vec![Arc::new(text("a")), image(12), image(16)]),
+        )
+        .expect("snapshot write should succeed");
+
+        let history = load_history(&path, 10, PruneOptions::default(), DedupSettings::default())
+            .expect("load should succeed");
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0], Arc::new(text("a")));
+        assert!(history[1].validate_image_geometry().is_ok());
+        cleanup(&path);
+    }
+
+    #[test]
+    fn reports_unparseable_files_as_corrupt() {
+        let path = test_database_path("corrupt");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "{not json").unwrap();
+        match load_history(&path, 10, PruneOptions::default(), DedupSettings::default()) {
+            Err(Error::StorageCorrupt { path: corrupt, .. }) => assert_eq!(corrupt, path),
+            other => panic!("expected StorageCorrupt, got {other:?}"),
+        }
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::write(
+            journal_path(&path),
+            "{\"op\":\"bogus\"}\n{\"op\":\"clear\"}\n",
+        )
+        .unwrap();
+        match load_history(&path, 10, PruneOptions::default(), DedupSettings::default()) {
+            Err(Error::StorageCorrupt {
+                path: corrupt,
+                reason,
+            }) => {
+                assert_eq!(corrupt, journal_path(&path));
+                assert!(reason.starts_with("invalid journal record on line 1"));
+            }
+            other => panic!("expected StorageCorrupt, got {other:?}"),
+        }
+        cleanup(&path);
+    }
+
+    #[test]
+    fn replays_journal_over_snapshot() {
+        let path = test_database_path("replay");
+        compact_history(
+            &path,
+            &VecDeque::from(vec![Arc::new(text("b")), Arc::new(text("a"))]),
+        )
+        .expect("snapshot write should succeed");
+        append_journal_record(&path, &add("c")).expect("append should succeed");
+        append_journal_record(&path, &add("a")).expect("append should succeed");
+        append_journal_record(&path, &JournalRecord::Remove { index: 2 })
+            .expect("append should succeed");
+
+        let history = load_history(&path, 10, PruneOptions::default(), DedupSettings::default())
+            .expect("load should succeed");
+        assert_eq!(
+            history,
+            VecDeque::from(vec![Arc::new(text("a")), Arc::new(text("c"))])
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn replay_deduplicates_as_the_loading_history_says() {
+        let path = test_database_path("replay-dedup");
+        for value in ["a", "b", "a\n"] {
+            append_journal_record(&path, &add(value)).expect("append should succeed");
+        }
+        let load = |dedup| load_history(&path, 10, PruneOptions::default(), dedup).unwrap();
+
+        assert_eq!(
+            load(DedupSettings::default()),
+            VecDeque::from(vec![Arc::new(text("a\n")), Arc::new(text("b"))])
+        );
+        let exact = DedupSettings {
+            ignore_trailing_whitespace: false,
+            ..DedupSettings::default()
+        };
+        assert_eq!(load(exact).len(), 3);
+        let off = DedupSettings {
+            enabled: false,
+            ..DedupSettings::default()
+        };
+        assert_eq!(load(off).len(), 3);
+        let recent = DedupSettings {
+            scope: DedupScope::Recent(1),
+            ..DedupSettings::default()
+        };
+        assert_eq!(load(recent).len(), 3);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn replay_honors_clear_and_history_limit() {
+        let path = test_database_path("clear-limit");
+        append_journal_record(&path, &add("old")).expect("append should succeed");
+        append_journal_record(&path, &JournalRecord::Clear).expect("append should succeed");
+        for value in ["a", "b", "c"] {
+            append_journal_record(&path, &add(value)).expect("append should succeed");
+        }
+
+        let history = load_history(&path, 2, PruneOptions::default(), DedupSettings::default())
+            .expect("load should succeed");
+        assert_eq!(
+            history,
+            VecDeque::from(vec![Arc::new(text("c")), Arc::new(text("b"))])
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn tolerates_torn_last_line() {
+        let path = test_database_path("torn");
+        append_journal_record(&path, &add("kept")).expect("append should succeed");
+        let journal = journal_path(&path);
+        let mut raw = std::fs::read_to_string(&journal).expect("read journal");
+        raw.push_str("{\"op\":\"add\",\"entry\":{\"kind\":\"te");
+        std::fs::write(&journal, raw).expect("write torn journal");
+
+        let history = load_history(&path, 10, PruneOptions::default(), DedupSettings::default())
+            .expect("torn tail should be ignored");
+        assert_eq!(history, VecDeque::from(vec![Arc::new(text("kept"))]));
+
+        append_journal_record(&path, &add("after")).expect("append after torn line");
+        let history = load_history(&path, 10, PruneOptions::default(), DedupSettings::default())
+            .expect("load after recovery should succeed");
+        assert_eq!(
+            history,
+            VecDeque::from(vec![Arc::new(text("after")), Arc::new(text("kept"))])
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn migrates_raw_images_to_png_on_load() {
+        let path = test_database_path("raw-image");
+        std::fs::create_dir_all(path.parent().expect("test path has a parent"))
+            .expect("create test dir");
+        let pixels: Vec<u8> = (0..24).collect();
+        let raw = serde_json::json!({"history": [{
+            "kind": "image", "width": 2, "height": 2, "rowstride": 12, "has_alpha": false,
+            "bits_per_sample": 8, "channels": 3, "pixels": pixels,
+        }]});
+        std::fs::write(&path, raw.to_string()).expect("write legacy snapshot");
+
+        let history = load_history(&path, 10, PruneOptions::default(), DedupSettings::default())
+            .expect("load should succeed");
+        let ClipboardEntry::Image {
+            encoding, pixels, ..
+        } = history[0].as_ref()
+        else {
+            panic!("expected an image entry");
+        };
+        assert_eq!(*encoding, ImageEncoding::Png);
+        assert!(pixels.starts_with(b"\x89PNG"));
+        // Rows were 12 bytes apart on disk; the 6 bytes of padding per row are dropped.
+        assert_eq!(
+            history[0].image_pixels().expect("decode").as_ref(),
+            [0, 1, 2, 3, 4, 5, 12, 13, 14, 15, 16, 17]
+        );
+        cleanup(&path);
+    }
+
+    /// Writes a fixture from `src/clipboard/fixtures` as the snapshot, loads it, and returns
+    /// the history and the snapshot as rewritten on disk.
+    fn load_fixture(name: &str, raw: &str) -> (VecDeque<Arc<ClipboardEntry>>, serde_json::Value) {
+        let path = test_database_path(name);
+        std::fs::create_dir_all(path.parent().expect("test path has a parent"))
+            .expect("create test dir");
+        std::fs::write(&path, raw).expect("write fixture");
+        let history = load_history(&path, 10, PruneOptions::default(), DedupSettings::default())
+            .expect("fixture should load");
+        let rewritten = std::fs::read_to_string(&path).expect("read snapshot");
+        cleanup(&path);
+        (
+            history,
+            serde_json::from_str(&rewritten).expect("snapshot is JSON"),
+        )
+    }
+
+    #[test]
+    fn loads_version_0_string_history() {
+        let (history, rewritten) =
+            load_fixture("fixture-v0", include_str!("fixtures/history-v0.json"));
+        assert_eq!(
+            history,
+            VecDeque::from(vec![
+                Arc::new(text("newest")),
+                Arc::new(text("multi\nline"))
+            ])
+        );
+        assert_eq!(rewritten["version"], CURRENT_VERSION);
+        assert_eq!(rewritten["history"][1]["kind"], "text");
+    }
+
+    #[test]
+    fn upgrades_a_sync_conflicted_legacy_history_without_duplicates() {
+        let (history, rewritten) = load_fixture(
+            "fixture-v0-mixed",
+            include_str!("fixtures/history-v0-mixed.json"),
+        );
+        let values: Vec<&str> = history
+            .iter()
+            .map(|entry| match entry.as_ref() {
+                ClipboardEntry::Text { value, .. } => value.as_str(),
+                ClipboardEntry::Image { .. } => "image",
+            })
+            .collect();
+        assert_eq!(
+            values,
+            ["git push", "rich only", "legacy newest", "legacy oldest"]
+        );
+        assert!(history[0].is_pinned());
+        assert_eq!(
+            history[0].source_window().and_then(|w| w.app_id.as_deref()),
+            Some("kitty")
+        );
+        assert_eq!(rewritten["version"], CURRENT_VERSION);
+    }
+
+    #[test]
+    fn upgraded_legacy_history_is_not_migrated_again() {
+        let path = test_database_path("fixture-v0-once");
+        std::fs::create_dir_all(path.parent().expect("test path has a parent"))
+            .expect("create test dir");
+        std::fs::write(&path, include_str!("fixtures/history-v0.json")).expect("write fixture");
+        let upgraded = load_history(&path, 10, PruneOptions::default(), DedupSettings::default())
+            .expect("upgrade");
+        let rewritten = std::fs::read_to_string(&path).expect("read snapshot");
+        let document: serde_json::Value = serde_json::from_str(&rewritten).expect("JSON");
+        assert_eq!(migrations::detect_version(&document), Ok(CURRENT_VERSION));
+
+        let reloaded = load_history(&path, 10, PruneOptions::default(), DedupSettings::default())
+            .expect("reload");
+        assert_eq!(reloaded, upgraded);
+        assert_eq!(
+            std::fs::read_to_string(&path).expect("read snapshot"),
+            rewritten
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn loads_version_1_entries_and_encodes_raw_images() {
+        let (history, rewritten) =
+            load_fixture("fixture-v1", include_str!("fixtures/history-v1.json"));
+        assert_eq!(history.len(), 3);
+        assert!(history[0].is_pinned());
+        assert_eq!(history[0].content_hint(), Some("url"));
+        assert_eq!(
+            history[0].source_window().and_then(|w| w.app_id.as_deref()),
+            Some("firefox")
+        );
+        let ClipboardEntry::Image { encoding, .. } = history[1].as_ref() else {
+            panic!("expected an image entry");
+        };
+        assert_eq!(*encoding, ImageEncoding::Png);
+        assert_eq!(
+            history[1].image_pixels().expect("decode").as_ref(),
+            [255, 0, 0, 255, 0, 0, 255, 128]
+        );
+        assert_eq!(history[2].captured_at(), None);
+        assert_eq!(rewritten["version"], CURRENT_VERSION);
+        assert_eq!(rewritten["history"][1]["encoding"], "png");
+    }
+
+    #[test]
+    fn loads_version_2_without_rewriting() {
+        let raw = include_str!("fixtures/history-v2.json");
+        let (history, rewritten) = load_fixture("fixture-v2", raw);
+        let (migrated, _) = load_fixture(
+            "fixture-v2-from-v1",
+            include_str!("fixtures/history-v1.json"),
+        );
+        // The PNG codec has changed since the fixture was written, so compare pixels.
+        assert_eq!(history[0], migrated[0]);
+        assert_eq!(
+            history[1].image_pixels().expect("decode"),
+            migrated[1].image_pixels().expect("decode")
+        );
+        assert_eq!(history[2].label(), Some("kept note"));
+        assert_eq!(history[2].captured_at(), Some(1_700_000_000));
+        assert_eq!(
+            rewritten,
+            serde_json::from_str::<serde_json::Value>(raw).unwrap()
+        );
+    }
+
+    #[test]
+    fn refuses_databases_from_newer_versions() {
+        let path = test_database_path("newer");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let raw = format!(
+            r#"{{"version": {}, "history": [{{"kind": "hologram"}}]}}"#,
+            CURRENT_VERSION + 1
+        );
+        std::fs::write(&path, &raw).unwrap();
+        match load_history(&path, 10, PruneOptions::default(), DedupSettings::default()) {
+            Err(Error::StorageNewerVersion {
+                version, supported, ..
+            }) => assert_eq!((version, supported), (CURRENT_VERSION + 1, CURRENT_VERSION)),
+            other => panic!("expected StorageNewerVersion, got {other:?}"),
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), raw);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn compaction_folds_journal_into_snapshot() {
+        let path = test_database_path("compact");
+        append_journal_record(&path, &add("a")).expect("append should succeed");
+        append_journal_record(&path, &add("b")).expect("append should succeed");
+        let history = load_history(&path, 10, PruneOptions::default(), DedupSettings::default())
+            .expect("load should succeed");
+
+        compact_history(&path, &history).expect("compaction should succeed");
+        assert!(!journal_path(&path).exists(), "journal should be removed");
+        assert_eq!(
+            load_history(&path, 10, PruneOptions::default(), DedupSettings::default())
+                .expect("reload should succeed"),
+            history
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn skips_a_journal_left_behind_by_an_interrupted_compaction() {
+        let path = test_database_path("interrupted-compaction");
+        let load = || load_history(&path, 10, PruneOptions::default(), DedupSettings::default());
+        for value in ["a", "b", "c"] {
+            append_journal_record(&path, &add(value)).unwrap();
+        }
+        append_journal_record(&path, &JournalRecord::Remove { index: 2 }).unwrap();
+        append_journal_record(
+            &path,
+            &JournalRecord::Pin {
+                index: 0,
+                pinned: true,
+            },
+        )
+        .unwrap();
+        let history = load().unwrap();
+        assert_eq!(history.len(), 2);
+
+        // The process died after writing the snapshot but before removing the journal.
+        let leftover = std::fs::read_to_string(journal_path(&path)).unwrap();
+        compact_history(&path, &history).unwrap();
+        std::fs::write(journal_path(&path), &leftover).unwrap();
+        assert_eq!(load().unwrap(), history);
+
+        // Once a compaction finishes, the next journal is replayed again.
+        compact_history(&path, &history).unwrap();
+        append_journal_record(&path, &JournalRecord::Remove { index: 1 }).unwrap();
+        assert_eq!(load().unwrap(), VecDeque::from(vec![history[0].clone()]));
+
+        // Journals from before generations were recorded always replay.
+        std::fs::write(journal_path(&path), "{\"op\":\"remove\",\"index\":0}\n").unwrap();
+        assert_eq!(load().unwrap(), VecDeque::from(vec![history[1].clone()]));
+        cleanup(&path);
+    }
+
+    #[test]
+    fn records_which_versions_wrote_the_database() {
+        let path = test_database_path("writer-versions");
+        assert_eq!(read_database_header(&path).unwrap(), None);
+        save_history(&path, &VecDeque::from(vec![Arc::new(text("a"))])).unwrap();
+        let header = read_database_header(&path).unwrap().unwrap();
+        assert_eq!(header.format_version, CURRENT_VERSION);
+        assert_eq!(header.created_by_version.as_deref(), Some(RUNNING_VERSION));
+        assert_eq!(
+            header.last_written_by_version.as_deref(),
+            Some(RUNNING_VERSION)
+        );
+
+        std::fs::write(
+            &path,
+            r#"{"version": 2, "created_by_version": "0.1.0", "last_written_by_version": "0.1.4", "history": []}"#,
+        )
+        .unwrap();
+        compact_history(&path, &VecDeque::from(vec![Arc::new(text("b"))])).unwrap();
+        let header = read_database_header(&path).unwrap().unwrap();
+        assert_eq!(header.created_by_version.as_deref(), Some("0.1.0"));
+        assert_eq!(
+            header.last_written_by_version.as_deref(),
+            Some(RUNNING_VERSION)
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn databases_without_writer_versions_load_and_stay_of_unknown_origin() {
+        let path = test_database_path("no-header");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, include_str!("fixtures/history-v2.json")).unwrap();
+        let history =
+            load_history(&path, 10, PruneOptions::default(), DedupSettings::default()).unwrap();
+        let header = read_database_header(&path).unwrap().unwrap();
+        assert_eq!(
+            header.summary(),
+            "format version 2, created by unknown, last written by unknown"
+        );
+
+        save_history(&path, &history).unwrap();
+        let header = read_database_header(&path).unwrap().unwrap();
+        assert_eq!(header.created_by_version, None);
+        assert_eq!(
+            header.last_written_by_version.as_deref(),
+            Some(RUNNING_VERSION)
+        );
+        assert_eq!(
+            load_history(&path, 10, PruneOptions::default(), DedupSettings::default()).unwrap(),
+            history
+        );
+        cleanup(&path);
+    }
+
+    #[test]
+    fn databases_from_newer_major_writers_warn_unless_refused() {
+        let path = test_database_path("newer-writer");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let raw = r#"{"version": 2, "last_written_by_version": "99.0.0", "history": [{"kind": "text", "value": "a", "sparkle": true}]}"#;
+        std::fs::write(&path, raw).unwrap();
+        assert_eq!(
+            load_history(&path, 10, PruneOptions::default(), DedupSettings::default()).unwrap(),
+            VecDeque::from(vec![Arc::new(text("a"))])
+        );
+
+        set_refuse_newer_writer(true);
+        let refused = load_history(&path, 10, PruneOptions::default(), DedupSettings::default());
+        set_refuse_newer_writer(false);
+        match refused {
+            Err(Error::StorageNewerWriter { written_by, .. }) => assert_eq!(written_by, "99.0.0"),
+            other => panic!("expected StorageNewerWriter, got {other:?}"),
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), raw);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn compares_release_lines_like_cargo() {
+        assert!(is_newer_major("1.0.0", "0.9.3"));
+        assert!(is_newer_major("0.3.0", "0.2.9"));
+        assert!(is_newer_major("2.0.0-rc.1", "1.4.0"));
+        assert!(!is_newer_major("0.2.10", "0.2.9"));
+        assert!(!is_newer_major("1.7.0", "1.2.0"));
+        assert!(!is_newer_major("0.1.0", "0.2.9"));
+        assert!(!is_newer_major("nightly", "0.2.9"));
+    }
+}
@@ -0,0 +1,576 @@
+use anyhow::{bail, Result};
+
+use crate::clipboard::age::{format_clock_time, format_relative_age, unix_now};
+use crate::clipboard::preview::{format_byte_size, preview_text};
+use crate::clipboard::ClipboardEntry;
+use crate::core::active_window::ActiveWindowContext;
+
+/// Preview length used by a bare `{preview}` placeholder.
+pub const DEFAULT_TEMPLATE_PREVIEW_CHARS: usize = 60;
+/// `slyboard history` output for entries without a recorded source window.
+pub const DEFAULT_HISTORY_TEMPLATE: &str = "{id}: {value} ({approx_size})";
+/// `slyboard history` output for entries with a recorded source window.
+pub const DEFAULT_SOURCED_HISTORY_TEMPLATE: &str =
+    "{id}: {value} ({approx_size}) [source: {source}]";
+/// Appended to the default `slyboard history` line of labelled entries.
+pub const DEFAULT_HISTORY_LABEL_SUFFIX: &str = " [label: {label}]";
+
+/// A parsed `slyboard history --format` string, e.g. `{id}\t{kind}\t{preview:40}`.
+///
+/// Placeholders: `id`, `kind`, `value`, `preview` / `preview:N`, `app`, `title`, `source`,
+/// `time` (relative age such as `5m`), `clock` (local capture time such as `14:03:22`), `size` (stored bytes), `approx_size` (`142 chars`
+/// for text, decoded size such as `7.9 MiB` for images), and `label`. The format string understands `\t`,
+/// `\n`, `\r`, `\\`, `\{`, and `\}`.
+/// Values are rendered as they are unless [`escaped`](Self::escaped) is set, which escapes
+/// tabs, newlines, carriage returns, and backslashes so every entry stays on one line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryTemplate {
+    segments: Vec<Segment>,
+    escape: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Field(Field),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Id,
+    Kind,
+    Value,
+    Preview(usize),
+    App,
+    Title,
+    Source,
+    Time,
+    Clock,
+    Size,
+    ApproxSize,
+    Label,
+}
+
+impl HistoryTemplate {
+    pub fn parse(format: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = format.chars();
+
+        while let Some(ch) = chars.next() {
+            match ch {
+                '\\' => literal.push(match chars.next() {
+                    Some('t') => '\t',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some(escaped @ ('\\' | '{' | '}')) => escaped,
+                    Some(other) => bail!("unknown escape sequence \\{other} in format"),
+                    None => bail!("format ends with a dangling backslash"),
+                }),
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(ch) => name.push(ch),
+                            None => bail!("unterminated placeholder {{{name} in format"),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Field(parse_field(&name)?));
+                }
+                '}' => bail!("unmatched }} in format (write \\}} for a literal brace)"),
+                ch => literal.push(ch),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Self {
+            segments,
+            escape: false,
+        })
+    }
+
+    /// Escapes tabs, newlines, carriage returns, and backslashes inside rendered values.
+    pub fn escaped(mut self, escape: bool) -> Self {
+        self.escape = escape;
+        self
+    }
+
+    pub fn render(&self, id: usize, entry: &ClipboardEntry) -> String {
+        self.render_at(id, entry, unix_now())
+    }
+
+    /// Renders with `{time}` measured against `now` (Unix seconds).
+    pub fn render_at(&self, id: usize, entry: &ClipboardEntry, now: u64) -> String {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => rendered.push_str(literal),
+                Segment::Field(field) => {
+                    rendered.push_str(&render_field(*field, id, entry, now, self.escape));
+                }
+            }
+        }
+        rendered
+    }
+
+    /// Renders at most `char_limit` characters. Text previews give up their length first so
+    /// the other components stay readable; anything still too long is cut at the end.
+    pub fn render_limited(
+        &self,
+        id: usize,
+        entry: &ClipboardEntry,
+        now: u64,
+        char_limit: usize,
+    ) -> String {
+        let is_text_preview = |segment: &Segment| {
+            matches!(segment, Segment::Field(Field::Preview(_)))
+                && matches!(entry, ClipboardEntry::Text { .. })
+        };
+        let fixed: Vec<Option<String>> = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                _ if is_text_preview(segment) => None,
+                Segment::Literal(literal) => Some(literal.clone()),
+                Segment::Field(field) => Some(render_field(*field, id, entry, now, self.escape)),
+            })
+            .collect();
+        let fixed_chars: usize = fixed
+            .iter()
+            .flatten()
+            .map(|part| part.chars().count())
+            .sum();
+        let mut budget = char_limit.saturating_sub(fixed_chars);
+
+        let mut rendered = String::new();
+        for (segment, part) in self.segments.iter().zip(fixed) {
+            let part = match (segment, part) {
+                (_, Some(part)) => part,
+                (Segment::Field(Field::Preview(limit)), None) => {
+                    let ClipboardEntry::Text { value, .. } = entry else {
+                        unreachable!("only text previews are deferred");
+                    };
+                    let preview = fit_preview(value, *limit, budget, self.escape);
+                    budget -= preview.chars().count();
+                    preview
+                }
+                (_, None) => unreachable!("only previews are deferred"),
+            };
+            rendered.push_str(&part);
+        }
+
+        if rendered.chars().count() > char_limit {
+            rendered = rendered.chars().take(char_limit).collect();
+        }
+        rendered
+    }
+}
+
+/// The longest preview of `value` (at most `limit` characters plus `...`) within `budget`.
+fn fit_preview(value: &str, limit: usize, budget: usize, escape: bool) -> String {
+    let preview = text_preview(value, limit, escape);
+    if preview.chars().count() <= budget {
+        return preview;
+    }
+    match budget.checked_sub(3) {
+        Some(0) | None => String::new(),
+        Some(chars) => text_preview(value, chars.min(limit), escape),
+    }
+}
+
+/// `{preview:N}` of a text value. Previews always escape line breaks and blank out other
+/// control characters; escaping templates double backslashes too, as `{value}` does.
+fn text_preview(value: &str, limit: usize, escape: bool) -> String {
+    if escape && value.contains('\\') {
+        preview_text(&value.replace('\\', "\\\\"), limit)
+    } else {
+        preview_text(value, limit)
+    }
+}
+
+/// Renders `entry` the way plain `slyboard history` prints it, with values as they are.
+pub fn render_default_history_line(id: usize, entry: &ClipboardEntry) -> String {
+    let mut format = match entry.source_window() {
+        Some(_) => DEFAULT_SOURCED_HISTORY_TEMPLATE,
+        None => DEFAULT_HISTORY_TEMPLATE,
+    }
+    .to_string();
+    if entry.label().is_some() {
+        format.push_str(DEFAULT_HISTORY_LABEL_SUFFIX);
+    }
+    HistoryTemplate::parse(&format)
+        .expect("default history templates are valid")
+        .render(id, entry)
+}
+
+fn parse_field(name: &str) -> Result<Field> {
+    let field = match name.trim() {
+        "id" => Field::Id,
+        "kind" => Field::Kind,
+        "value" => Field::Value,
+        "preview" => Field::Preview(DEFAULT_TEMPLATE_PREVIEW_CHARS),
+        "app" => Field::App,
+        "title" => Field::Title,
+        "source" => Field::Source,
+        "time" => Field::Time,
+        "clock" => Field::Clock,
+        "size" => Field::Size,
+        "approx_size" => Field::ApproxSize,
+        "label" => Field::Label,
+        other => match other.strip_prefix("preview:") {
+            Some(limit) => match limit.trim().parse::<usize>() {
+                Ok(limit) if limit > 0 => Field::Preview(limit),
+                _ => bail!("invalid preview length in {{{other}}}: expected a positive number"),
+            },
+            None => bail!(
+                "unknown placeholder {{{other}}} (expected id, kind, value, preview, preview:N, \
+                 app, title, source, time, clock, size, approx_size, or label)"
+            ),
+        },
+    };
+    Ok(field)
+}
+
+fn render_field(field: Field, id: usize, entry: &ClipboardEntry, now: u64, escape: bool) -> String {
+    let field_value = |value: &str| {
+        if escape {
+            escape_value(value)
+        } else {
+            value.to_string()
+        }
+    };
+    match field {
+        Field::Id => id.to_string(),
+        Field::Kind => entry_kind(entry).to_string(),
+        Field::Value => match entry {
+            ClipboardEntry::Text { value, .. } => field_value(value),
+            ClipboardEntry::Image { .. } => image_summary(entry),
+        },
+        Field::Preview(limit) => match entry {
+            ClipboardEntry::Text { value, .. } => text_preview(value, limit, escape),
+            ClipboardEntry::Image { .. } => image_summary(entry),
+        },
+        Field::App => entry
+            .source_window()
+            .and_then(|window| window.app_id.as_deref())
+            .map(&field_value)
+            .unwrap_or_default(),
+        Field::Title => entry
+            .source_window()
+            .map(|window| field_value(&window.title))
+            .unwrap_or_default(),
+        Field::Source => entry
+            .source_window()
+            .map(|window| {
+                field_value(&format!(
+                    "{} via {}",
+                    format_window_source(window),
+                    window.backend
+                ))
+            })
+            .unwrap_or_default(),
+        Field::Time => entry
+            .captured_at()
+            .map(|captured_at| format_relative_age(captured_at, now))
+            .unwrap_or_default(),
+        Field::Clock => entry
+            .captured_at()
+            .map(format_clock_time)
+            .unwrap_or_default(),
+        Field::Size => match entry {
+            ClipboardEntry::Text { value, .. } => value.len().to_string(),
+            ClipboardEntry::Image { pixels, .. } => pixels.len().to_string(),
+        },
+        Field::ApproxSize => match entry {
+            ClipboardEntry::Text { value, .. } => match value.chars().count() {
+                1 => "1 char".to_string(),
+                count => format!("{count} chars"),
+            },
+            ClipboardEntry::Image { .. } => format_byte_size(entry.approx_size_bytes()),
+        },
+        Field::Label => entry.label().map(&field_value).unwrap_or_default(),
+    }
+}
+
+/// `text` or `image`, as `{kind}` renders it.
+pub fn entry_kind(entry: &ClipboardEntry) -> &'static str {
+    match entry {
+        ClipboardEntry::Text { .. } => "text",
+        ClipboardEntry::Image { .. } => "image",
+    }
+}
+
+/// `{preview:N}` of `entry`: a one-line text preview, or `[image] WxH`.
+pub fn entry_preview(entry: &ClipboardEntry, limit: usize) -> String {
+    match entry {
+        // Previews already escape line breaks and blank out other control characters.
+        ClipboardEntry::Text { value, .. } => preview_text(value, limit),
+        ClipboardEntry::Image { .. } => image_summary(entry),
+    }
+}
+
+fn image_summary(entry: &ClipboardEntry) -> String {
+    match entry {
+        ClipboardEntry::Image { width, height, .. } => format!("[image] {}x{}", width, height),
+        ClipboardEntry::Text { .. } => String::new(),
+    }
+}
+
+fn escape_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// Describes a source window as `title (key=value, ...)` for history output.
+pub fn format_window_source(context: &ActiveWindowContext) -> String {
+    let mut details = Vec::new();
+    if let Some(app_id) = &context.app_id {
+        details.push(format!("app_id={app_id}"));
+    }
+    if let Some(initial_app_id) = &context.initial_app_id {
+        details.push(format!("initial_app_id={initial_app_id}"));
+    }
+    if let Some(initial_title) = &context.initial_title {
+        details.push(format!("initial_title={initial_title}"));
+    }
+    if let Some(window_id) = &context.window_id {
+        details.push(format!("window_id={window_id}"));
+    }
+    if let Some(pid) = context.pid {
+        details.push(format!("pid={pid}"));
+    }
+    if let Some(workspace_id) = context.workspace_id {
+        details.push(format!("workspace_id={workspace_id}"));
+    }
+    if let Some(workspace_name) = &context.workspace_name {
+        details.push(format!("workspace_name={workspace_name}"));
+    }
+    if let Some(is_xwayland) = context.is_xwayland {
+        details.push(format!("xwayland={is_xwayland}"));
+    }
+    if let Some(monitor) = &context.monitor {
+        details.push(format!("monitor={monitor}"));
+    }
+    if let Some((x, y, width, height)) = context.geometry {
+        details.push(format!("geometry={width}x{height}+{x}+{y}"));
+    }
+
+    if details.is_empty() {
+        context.title.clone()
+    } else {
+        format!("{} ({})", context.title, details.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_default_history_line, HistoryTemplate};
+    use crate::clipboard::{ClipboardEntry, ImageEncoding};
+    use crate::core::active_window::ActiveWindowContext;
+    use crate::testing::text;
+
+    fn image() -> ClipboardEntry {
+        ClipboardEntry::Image {
+            width: 2,
+            height: 1,
+            rowstride: 8,
+            has_alpha: true,
+            bits_per_sample: 8,
+            channels: 4,
+            encoding: ImageEncoding::Raw,
+            pixels: vec![0; 8],
+            source_window: None,
+            pinned: false,
+            label: None,
+            transient_until: None,
+            captured_at: None,
+            captured_by: None,
+            selection: None,
+            restore_count: 0,
+            last_restored_at: None,
+            expires_at: None,
+            offered_targets: Vec::new(),
+        }
+    }
+
+    fn window() -> ActiveWindowContext {
+        ActiveWindowContext {
+            backend: "hyprctl".to_string(),
+            title: "Terminal".to_string(),
+            app_id: Some("kitty".to_string()),
+            initial_app_id: None,
+            initial_title: None,
+            window_id: None,
+            pid: None,
+            workspace_id: Some(3),
+            workspace_name: None,
+            is_xwayland: None,
+            monitor: None,
+            geometry: None,
+        }
+    }
+
+    fn render(format: &str, entry: &ClipboardEntry) -> String {
+        HistoryTemplate::parse(format)
+            .expect("format should parse")
+            .render(7, entry)
+    }
+
+    #[test]
+    fn renders_placeholders_and_format_escapes() {
+        let entry = text("hello world").with_source_window(Some(window()));
+        assert_eq!(
+            render(r"{id}\t{kind}\t{app}\t{preview:5}\t{size}", &entry),
+            "7\ttext\tkitty\thello...\t11"
+        );
+        assert_eq!(render(r"\{{title}\}\\", &entry), "{Terminal}\\");
+        assert_eq!(render("{app}|{title}|{time}", &text("x")), "||");
+        assert_eq!(render("{clock}|{kind}", &text("x")), "|text");
+    }
+
+    #[test]
+    fn renders_relative_capture_time() {
+        let now = 1_700_000_000;
+        let entry = text("x").with_captured_at(Some(now - 120));
+        let template = HistoryTemplate::parse("{time} {preview}").unwrap();
+        assert_eq!(template.render_at(0, &entry, now), "2m x");
+    }
+
+    #[test]
+    fn limited_render_truncates_preview_first() {
+        let now = 1_700_000_000;
+        let entry = text("https://example.com/some/long/path")
+            .with_source_window(Some(window()))
+            .with_captured_at(Some(now - 120));
+        let template = HistoryTemplate::parse("{time} · {app} · {preview:60}").unwrap();
+
+        let label = template.render_limited(0, &entry, now, 24);
+        assert_eq!(label, "2m · kitty · https://...");
+        assert!(label.chars().count() <= 24);
+        assert_eq!(
+            template.render_limited(0, &entry, now, 80),
+            "2m · kitty · https://example.com/some/long/path"
+        );
+        // Without room for a preview, the fixed parts are cut at the limit.
+        assert_eq!(template.render_limited(0, &entry, now, 6), "2m · k");
+        assert_eq!(template.render_limited(0, &image(), now, 11), " ·  · [imag");
+    }
+
+    #[test]
+    fn escapes_values_only_when_asked() {
+        let entry = text("a\tb\nc\\d").with_source_window(Some(ActiveWindowContext {
+            title: "x\ty".to_string(),
+            ..window()
+        }));
+        assert_eq!(render("{value}|{title}", &entry), "a\tb\nc\\d|x\ty");
+        assert_eq!(render("{preview:5}", &entry), r"a b\n...");
+
+        let escaped = |format: &str| {
+            HistoryTemplate::parse(format)
+                .unwrap()
+                .escaped(true)
+                .render(0, &entry)
+        };
+        assert_eq!(escaped("{value}|{title}"), r"a\tb\nc\\d|x\ty");
+        // Backslashes in previews are escaped the same way, so `\n` stays distinguishable.
+        assert_eq!(escaped("{preview:9}"), r"a b\nc\\d");
+        assert_eq!(
+            HistoryTemplate::parse("{preview:8}")
+                .unwrap()
+                .escaped(true)
+                .render_limited(0, &text(r"C:\dir\file"), 0, 7),
+            r"C:\\..."
+        );
+    }
+
+    #[test]
+    fn approximates_sizes_in_characters_and_decoded_bytes() {
+        assert_eq!(render("{size} {approx_size}", &text("héllo")), "6 5 chars");
+
+        // Stored bytes don't matter: a PNG's decoded size comes from its dimensions.
+        let mut screenshot = image();
+        if let ClipboardEntry::Image {
+            width,
+            height,
+            encoding,
+            ..
+        } = &mut screenshot
+        {
+            (*width, *height, *encoding) = (1920, 1080, ImageEncoding::Png);
+        }
+        assert_eq!(screenshot.approx_size_bytes(), 1920 * 1080 * 4);
+        assert_eq!(
+            render("{value} ({approx_size})", &screenshot),
+            "[image] 1920x1080 (7.9 MiB)"
+        );
+    }
+
+    #[test]
+    fn summarizes_images_for_value_and_preview() {
+        assert_eq!(
+            render("{kind} {value} {preview:2} {size}", &image()),
+            "image [image] 2x1 [image] 2x1 8"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_formats() {
+        for format in [
+            "{nope}",
+            "{preview:0}",
+            "{preview:x}",
+            "{id",
+            "id}",
+            r"\q",
+            "\\",
+        ] {
+            assert!(
+                HistoryTemplate::parse(format).is_err(),
+                "{format:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn default_output_uses_templates() {
+        assert_eq!(
+            render_default_history_line(0, &text("plain")),
+            "0: plain (5 chars)"
+        );
+        // Values print as they are, like before templates existed.
+        assert_eq!(
+            render_default_history_line(0, &text("a\tb\\c\nd")),
+            "0: a\tb\\c\nd (7 chars)"
+        );
+        assert_eq!(
+            render_default_history_line(1, &text("x").with_source_window(Some(window()))),
+            "1: x (1 char) [source: Terminal (app_id=kitty, workspace_id=3) via hyprctl]"
+        );
+        assert_eq!(
+            render_default_history_line(2, &image()),
+            "2: [image] 2x1 (8 B)"
+        );
+        let labelled = text("hunter2").with_label(Some("prod db".to_string()));
+        assert_eq!(
+            render_default_history_line(3, &labelled),
+            "3: hunter2 (7 chars) [label: prod db]"
+        );
+        let template = HistoryTemplate::parse("{id}:{label}").unwrap();
+        assert_eq!(template.render(3, &labelled), "3:prod db");
+        assert_eq!(template.render(0, &text("plain")), "0:");
+    }
+}
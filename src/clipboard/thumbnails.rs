@@ -0,0 +1,357 @@
+//! Small PNG previews of image entries, for tray menu items and rofi icons. They live in
+//! `thumbnails/` next to the history database, named by a digest of the image, so every process
+//! finds the same file and an image copied again reuses it. [`ThumbnailCache::cleanup`] deletes
+//! those whose image left history.
+
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::clipboard::digest::StableHasher;
+use crate::clipboard::png;
+use crate::clipboard::storage::{default_database_path, io_error, thumbnails_dir};
+use crate::clipboard::ClipboardEntry;
+use crate::error::{Error, Result};
+
+/// Longest side of a thumbnail, in pixels. Smaller images are kept at their size.
+pub const THUMBNAIL_SIZE: u32 = 96;
+/// The cache is trimmed to this, oldest thumbnails first, whenever it is cleaned up.
+pub const DEFAULT_MAX_CACHE_BYTES: u64 = 32 * 1024 * 1024;
+const THUMBNAIL_EXTENSION: &str = "png";
+
+#[derive(Debug, Clone)]
+pub struct ThumbnailCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl ThumbnailCache {
+    pub fn open_default() -> Result<Self> {
+        Ok(Self::new(thumbnails_dir(&default_database_path()?)))
+    }
+
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            max_bytes: DEFAULT_MAX_CACHE_BYTES,
+        }
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where the thumbnail of `entry` is stored, or `None` for text.
+    pub fn path_for(&self, entry: &ClipboardEntry) -> Option<PathBuf> {
+        thumbnail_key(entry).map(|key| self.dir.join(format!("{key}.{THUMBNAIL_EXTENSION}")))
+    }
+
+    /// The thumbnail of an image entry, rendering it on first use.
+    pub fn get_or_create(&self, entry: &ClipboardEntry) -> Result<PathBuf> {
+        let path = self.path_for(entry).ok_or_else(|| Error::InvalidImage {
+            reason: "text entries have no thumbnail".to_string(),
+        })?;
+        if path.exists() {
+            return Ok(path);
+        }
+        let png = render_thumbnail(entry)?;
+        fs::create_dir_all(&self.dir).map_err(io_error("create thumbnail directory", &self.dir))?;
+        // Through a temporary file, so a picker running meanwhile never shows half an image.
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, png).map_err(io_error("write thumbnail", &temp))?;
+        fs::rename(&temp, &path).map_err(io_error("write thumbnail", &path))?;
+        Ok(path)
+    }
+
+    /// Deletes the thumbnails of `entries`, e.g. the ones a record evicted. Images still in
+    /// history elsewhere are better handled by [`cleanup`](Self::cleanup).
+    pub fn remove_for(&self, entries: &[Arc<ClipboardEntry>]) -> Result<usize> {
+        let mut removed = 0;
+        for path in entries.iter().filter_map(|entry| self.path_for(entry)) {
+            match fs::remove_file(&path) {
+                Ok(()) => removed += 1,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(source) => return Err(io_error("remove thumbnail", &path)(source)),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Deletes thumbnails of images no longer in `history`, then the oldest remaining ones
+    /// until the cache fits in its size limit. Returns how many files were deleted.
+    pub fn cleanup(&self, history: &[Arc<ClipboardEntry>]) -> Result<usize> {
+        let keep: Vec<PathBuf> = history
+            .iter()
+            .filter_map(|entry| self.path_for(entry))
+            .collect();
+        let mut kept = Vec::new();
+        let mut removed = 0;
+        for (path, size, modified) in self.files()? {
+            if keep.contains(&path) {
+                kept.push((path, size, modified));
+                continue;
+            }
+            remove_cached(&path)?;
+            removed += 1;
+        }
+
+        let mut total: u64 = kept.iter().map(|(_, size, _)| size).sum();
+        kept.sort_by_key(|(_, _, modified)| *modified);
+        for (path, size, _) in kept {
+            if total <= self.max_bytes {
+                break;
+            }
+            remove_cached(&path)?;
+            total -= size;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    /// Cached thumbnails with their size and modification time; stray temporary files count
+    /// too, so an interrupted write is cleaned up.
+    fn files(&self) -> Result<Vec<(PathBuf, u64, SystemTime)>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(source) => return Err(io_error("read thumbnail directory", &self.dir)(source)),
+        };
+        let mut files = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(io_error("read thumbnail directory", &self.dir))?;
+            let path = entry.path();
+            let metadata = entry
+                .metadata()
+                .map_err(io_error("read thumbnail metadata", &path))?;
+            if !metadata.is_file() {
+                continue;
+            }
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((path, metadata.len(), modified));
+        }
+        Ok(files)
+    }
+}
+
+fn remove_cached(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            Err(io_error("remove thumbnail", path)(err))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Digest of what [`ClipboardEntry::content_eq`] compares for images, as 16 hex digits.
+fn thumbnail_key(entry: &ClipboardEntry) -> Option<String> {
+    let ClipboardEntry::Image {
+        width,
+        height,
+        has_alpha,
+        bits_per_sample,
+        channels,
+        encoding,
+        pixels,
+        ..
+    } = entry
+    else {
+        return None;
+    };
+    let mut hasher = StableHasher::default();
+    (
+        width,
+        height,
+        has_alpha,
+        bits_per_sample,
+        channels,
+        encoding,
+        pixels,
+    )
+        .hash(&mut hasher);
+    Some(format!("{:016x}", hasher.finish()))
+}
+
+/// Scales the image to fit [`THUMBNAIL_SIZE`], averaging the source pixels under each
+/// thumbnail pixel, and encodes it as PNG.
+fn render_thumbnail(entry: &ClipboardEntry) -> Result<Vec<u8>> {
+    entry.validate_image_geometry()?;
+    let ClipboardEntry::Image {
+        width,
+        height,
+        rowstride,
+        channels,
+        ..
+    } = entry
+    else {
+        unreachable!("path_for only accepts images");
+    };
+    let pixels = entry.image_pixels()?;
+    let (width, height, channels, rowstride) = (
+        *width as usize,
+        *height as usize,
+        *channels as usize,
+        *rowstride as usize,
+    );
+    let scale = |side: usize| {
+        let longest = width.max(height);
+        if longest <= THUMBNAIL_SIZE as usize {
+            side
+        } else {
+            (side * THUMBNAIL_SIZE as usize / longest).max(1)
+        }
+    };
+    let (thumb_width, thumb_height) = (scale(width), scale(height));
+
+    let mut thumb = Vec::with_capacity(thumb_width * thumb_height * channels);
+    for y in 0..thumb_height {
+        let (top, bottom) = (
+            y * height / thumb_height,
+            ((y + 1) * height / thumb_height).max(y * height / thumb_height + 1),
+        );
+        for x in 0..thumb_width {
+            let (left, right) = (
+                x * width / thumb_width,
+                ((x + 1) * width / thumb_width).max(x * width / thumb_width + 1),
+            );
+            let count = ((bottom - top) * (right - left)) as u64;
+            for channel in 0..channels {
+                let mut sum = 0u64;
+                for row in top..bottom {
+                    for column in left..right {
+                        sum += u64::from(pixels[row * rowstride + column * channels + channel]);
+                    }
+                }
+                thumb.push((sum / count) as u8);
+            }
+        }
+    }
+    png::encode(
+        thumb_width as u32,
+        thumb_height as u32,
+        channels as u8,
+        thumb_width * channels,
+        &thumb,
+    )
+    .map_err(|err| Error::InvalidImage {
+        reason: format!("failed to encode thumbnail: {err:#}"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ThumbnailCache, THUMBNAIL_SIZE};
+    use crate::clipboard::png;
+    use crate::clipboard::{ClipboardEntry, ImageData};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "slyboard-test-thumbnails-{}-{name}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// An opaque RGB image filled with `shade`, PNG-encoded like stored history entries.
+    fn image(width: i32, height: i32, shade: u8) -> Arc<ClipboardEntry> {
+        let pixels = vec![shade; (width * height * 3) as usize];
+        let data = ImageData::new(width, height, 3, width * 3, pixels).unwrap();
+        Arc::new(ClipboardEntry::image(data).into_png_encoded())
+    }
+
+    #[test]
+    fn creates_scaled_thumbnails_and_reuses_them() {
+        let cache = ThumbnailCache::new(test_dir("create"));
+        let wide = image(400, 100, 200);
+        let path = cache.get_or_create(&wide).unwrap();
+        assert_eq!(path.parent(), Some(cache.dir()));
+
+        let decoded = png::decode(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!((decoded.width, decoded.height), (THUMBNAIL_SIZE, 24));
+        assert!(decoded.pixels.iter().all(|&byte| byte == 200));
+
+        // The same image, recorded again, finds the file instead of rendering it anew.
+        std::fs::write(&path, b"cached").unwrap();
+        assert_eq!(cache.get_or_create(&image(400, 100, 200)).unwrap(), path);
+        assert_eq!(std::fs::read(&path).unwrap(), b"cached");
+
+        let small = cache.get_or_create(&image(10, 20, 7)).unwrap();
+        let decoded = png::decode(&std::fs::read(small).unwrap()).unwrap();
+        assert_eq!((decoded.width, decoded.height), (10, 20));
+
+        assert!(cache.path_for(&ClipboardEntry::text("hello")).is_none());
+        assert!(cache.get_or_create(&ClipboardEntry::text("hello")).is_err());
+        let _ = std::fs::remove_dir_all(cache.dir());
+    }
+
+    #[test]
+    fn cleanup_removes_thumbnails_that_left_history() {
+        let cache = ThumbnailCache::new(test_dir("cleanup"));
+        let (kept, dropped, evicted) = (image(8, 8, 1), image(8, 8, 2), image(8, 8, 3));
+        for entry in [&kept, &dropped, &evicted] {
+            cache.get_or_create(entry).unwrap();
+        }
+        std::fs::write(cache.dir().join("interrupted.tmp"), b"half").unwrap();
+
+        assert_eq!(cache.remove_for(std::slice::from_ref(&evicted)).unwrap(), 1);
+        assert_eq!(cache.remove_for(std::slice::from_ref(&evicted)).unwrap(), 0);
+        let history = vec![Arc::new(ClipboardEntry::text("text")), kept.clone()];
+        assert_eq!(cache.cleanup(&history).unwrap(), 2);
+        assert!(cache.path_for(&kept).unwrap().exists());
+        assert!(!cache.path_for(&dropped).unwrap().exists());
+        assert_eq!(std::fs::read_dir(cache.dir()).unwrap().count(), 1);
+
+        // After `slyboard clear`, nothing is kept.
+        assert_eq!(cache.cleanup(&[]).unwrap(), 1);
+        assert_eq!(
+            ThumbnailCache::new(test_dir("missing"))
+                .cleanup(&[])
+                .unwrap(),
+            0
+        );
+        let _ = std::fs::remove_dir_all(cache.dir());
+    }
+
+    #[test]
+    fn cleanup_trims_the_oldest_thumbnails_over_the_size_limit() {
+        let dir = test_dir("limit");
+        let cache = ThumbnailCache::new(dir.clone());
+        let history: Vec<_> = (0..3).map(|shade| image(8, 8, shade)).collect();
+        let paths: Vec<_> = history
+            .iter()
+            .map(|entry| cache.get_or_create(entry).unwrap())
+            .collect();
+        let total: u64 = paths
+            .iter()
+            .map(|path| std::fs::metadata(path).unwrap().len())
+            .sum();
+        let age = |path: &PathBuf, seconds: u64| {
+            let time = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+            std::fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        };
+        age(&paths[0], 3_000);
+        age(&paths[1], 1_000);
+        age(&paths[2], 2_000);
+
+        let limited = ThumbnailCache::new(dir.clone()).with_max_bytes(total - 1);
+        assert_eq!(limited.cleanup(&history).unwrap(), 1);
+        assert!(!paths[1].exists(), "the oldest thumbnail goes first");
+        assert!(paths[0].exists() && paths[2].exists());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
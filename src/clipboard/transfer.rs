@@ -0,0 +1,243 @@
+//! Compares and merges histories saved on different machines, for `slyboard diff`. Reads the
+//! array `slyboard history --json --images` writes, or a copy of a `history.json` database,
+//! and writes merges back in the export format.
+//!
+//! Entries are matched by content alone, as history deduplicates them: the same text copied in
+//! different apps or at different times is one entry, and images match by their pixels.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::clipboard::json::write_json_history;
+use crate::clipboard::migrations;
+use crate::clipboard::ClipboardEntry;
+use crate::error::{Error, Result};
+
+/// Distinct contents of two histories, each list newest first as in the files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryDiff {
+    pub only_left: Vec<ClipboardEntry>,
+    pub only_right: Vec<ClipboardEntry>,
+    /// Contents found in both.
+    pub in_both: usize,
+}
+
+/// Reads the entries of an export or database copy at `path`, newest first.
+pub fn read_export(path: &Path) -> Result<Vec<ClipboardEntry>> {
+    let raw = std::fs::read_to_string(path).map_err(|source| Error::StorageIo {
+        action: "read history export",
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_export(&raw).map_err(|reason| Error::ExportCorrupt {
+        path: path.to_path_buf(),
+        reason,
+    })
+}
+
+pub(crate) fn parse_export(raw: &str) -> std::result::Result<Vec<ClipboardEntry>, String> {
+    let mut document: Value = serde_json::from_str(raw).map_err(|err| err.to_string())?;
+    let items = if let Value::Array(items) = document {
+        items
+    } else {
+        let version = migrations::detect_version(&document)
+            .map_err(|reason| format!("expected a history export or database: {reason}"))?;
+        if version > migrations::CURRENT_VERSION {
+            return Err(format!(
+                "format version {version} is newer than this slyboard supports"
+            ));
+        }
+        migrations::migrate(&mut document, version)?;
+        match document["history"].take() {
+            Value::Array(items) => items,
+            _ => return Err("missing history list".to_string()),
+        }
+    };
+
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            if item["kind"] == "image" && item.get("pixels").is_none() {
+                return Err(format!(
+                    "image entry {index} has no pixels; export with `slyboard history --json --images`"
+                ));
+            }
+            serde_json::from_value::<ClipboardEntry>(item)
+                .map(ClipboardEntry::into_png_encoded)
+                .map_err(|err| format!("entry {index}: {err}"))
+        })
+        .collect()
+}
+
+/// Contents in only one of `left` and `right`. Repeated content within one history counts once.
+pub fn diff(left: &[ClipboardEntry], right: &[ClipboardEntry]) -> HistoryDiff {
+    let (left_set, right_set) = (ContentSet::of(left), ContentSet::of(right));
+    let only = |entries: &ContentSet, other: &ContentSet| -> Vec<ClipboardEntry> {
+        entries
+            .distinct
+            .iter()
+            .filter(|entry| !other.contains(entry))
+            .map(|entry| (*entry).clone())
+            .collect()
+    };
+    let only_left = only(&left_set, &right_set);
+    HistoryDiff {
+        in_both: left_set.distinct.len() - only_left.len(),
+        only_left,
+        only_right: only(&right_set, &left_set),
+    }
+}
+
+/// The union of `left` and `right`, newest capture first; entries without a capture time go
+/// last. Where both have the same content, the more recently captured copy is kept, or the
+/// one from `left` when that can't tell them apart.
+pub fn merge(left: &[ClipboardEntry], right: &[ClipboardEntry]) -> Vec<ClipboardEntry> {
+    let mut merged: Vec<ClipboardEntry> = Vec::new();
+    let mut positions: HashMap<u64, Vec<usize>> = HashMap::new();
+    for entry in left.iter().chain(right) {
+        let candidates = positions.entry(entry.content_id()).or_default();
+        match candidates
+            .iter()
+            .find(|&&position| merged[position].content_eq(entry))
+        {
+            Some(&position) => {
+                if entry.captured_at() > merged[position].captured_at() {
+                    merged[position] = entry.clone();
+                }
+            }
+            None => {
+                candidates.push(merged.len());
+                merged.push(entry.clone());
+            }
+        }
+    }
+    // Stable, so entries captured at the same time keep the order they were found in.
+    merged.sort_by_key(|entry| std::cmp::Reverse(entry.captured_at()));
+    merged
+}
+
+/// Writes `entries` as `slyboard history --json --images` would.
+pub fn write_export<W: Write>(writer: &mut W, entries: &[ClipboardEntry]) -> io::Result<()> {
+    let entries: Vec<(usize, &ClipboardEntry)> = entries.iter().enumerate().collect();
+    write_json_history(writer, &entries, true, false)
+}
+
+/// The first entry of each content in a history, looked up like the dedup index: by content
+/// id, confirmed with [`ClipboardEntry::content_eq`].
+struct ContentSet<'a> {
+    distinct: Vec<&'a ClipboardEntry>,
+    by_id: HashMap<u64, Vec<&'a ClipboardEntry>>,
+}
+
+impl<'a> ContentSet<'a> {
+    fn of(entries: &'a [ClipboardEntry]) -> Self {
+        let mut set = Self {
+            distinct: Vec::new(),
+            by_id: HashMap::new(),
+        };
+        for entry in entries {
+            if !set.contains(entry) {
+                set.by_id.entry(entry.content_id()).or_default().push(entry);
+                set.distinct.push(entry);
+            }
+        }
+        set
+    }
+
+    fn contains(&self, entry: &ClipboardEntry) -> bool {
+        self.by_id
+            .get(&entry.content_id())
+            .is_some_and(|candidates| candidates.iter().any(|other| other.content_eq(entry)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, merge, parse_export, write_export};
+    use crate::clipboard::ClipboardEntry;
+    use crate::testing::{png_image, raw_image, text, text_from, window};
+
+    fn at(entry: ClipboardEntry, captured_at: u64) -> ClipboardEntry {
+        entry.with_captured_at(Some(captured_at))
+    }
+
+    #[test]
+    fn repeated_content_counts_once() {
+        let left = [text("a"), text("b"), text("a")];
+        let right = [text("b"), text("b"), text("c")];
+        let diff = diff(&left, &right);
+        assert_eq!(diff.only_left, [text("a")]);
+        assert_eq!(diff.only_right, [text("c")]);
+        assert_eq!(diff.in_both, 1);
+
+        let merged = merge(&left, &right);
+        assert_eq!(merged, [text("a"), text("b"), text("c")]);
+    }
+
+    #[test]
+    fn matches_text_copied_in_different_apps_and_keeps_the_newest_copy() {
+        let laptop = at(text_from("ssh host", window(Some("kitty"), "~")), 100);
+        let desktop = at(text_from("ssh host", window(Some("foot"), "~")), 300);
+        let left = [at(text("laptop only"), 200), laptop];
+        let right = [desktop.clone(), at(text("desktop only"), 50)];
+
+        let diff = diff(&left, &right);
+        assert_eq!(diff.in_both, 1);
+        assert_eq!(diff.only_left, [at(text("laptop only"), 200)]);
+        assert_eq!(diff.only_right, [at(text("desktop only"), 50)]);
+
+        let merged = merge(&left, &right);
+        assert_eq!(
+            merged,
+            [
+                desktop,
+                at(text("laptop only"), 200),
+                at(text("desktop only"), 50)
+            ]
+        );
+        assert_eq!(
+            merge(&[text("undated")], &merged).last(),
+            Some(&text("undated"))
+        );
+    }
+
+    #[test]
+    fn compares_images_by_pixels() {
+        let red = png_image(2, 2, [255, 0, 0, 255]);
+        let left = [red.clone(), png_image(2, 2, [0, 0, 255, 255])];
+        // Same pixels from another app, and a database copy still holding raw rows.
+        let right = [
+            red.clone()
+                .with_source_window(Some(window(Some("gimp"), "red.png"))),
+            raw_image(2, 2, [0, 255, 0, 255]),
+        ];
+
+        let mut exported = Vec::new();
+        write_export(&mut exported, &right).expect("export");
+        let database = serde_json::json!({ "version": 1, "history": right }).to_string();
+        for raw in [String::from_utf8(exported).expect("utf-8"), database] {
+            let right = parse_export(&raw).expect("parse");
+            let diff = diff(&left, &right);
+            assert_eq!(diff.in_both, 1);
+            assert_eq!(diff.only_left, [png_image(2, 2, [0, 0, 255, 255])]);
+            assert_eq!(diff.only_right, [png_image(2, 2, [0, 255, 0, 255])]);
+        }
+    }
+
+    #[test]
+    fn rejects_exports_without_image_pixels() {
+        let export = r#"[{"id":0,"kind":"image","width":1,"height":1,"rowstride":4,
+            "has_alpha":true,"bits_per_sample":8,"channels":4,"encoding":"png","pixel_bytes":68}]"#;
+        assert_eq!(
+            parse_export(export).unwrap_err(),
+            "image entry 0 has no pixels; export with `slyboard history --json --images`"
+        );
+        assert!(parse_export("\"text\"").is_err());
+        let export = r#"[{"id":0,"kind":"text","value":"a","size_bytes":1,"restore_count":0}]"#;
+        assert_eq!(parse_export(export).unwrap(), [text("a")]);
+    }
+}
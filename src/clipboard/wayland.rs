@@ -0,0 +1,474 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::clipboard::backend::{Capture, ClipboardBackend};
+#[cfg(feature = "wayland")]
+use crate::clipboard::data_control::DataControlSource;
+use crate::clipboard::writer::Selection;
+use crate::clipboard::{CaptureOrigin, ClipboardEntry};
+use crate::config::DEFAULT_MAX_ENTRY_BYTES;
+use crate::core::active_window::{ActiveWindowContext, ActiveWindowProvider};
+
+const WL_PASTE_BIN: &str = "wl-paste";
+const PNG_MIME_TYPE: &str = "image/png";
+const TEXT_MIME_TYPES: [&str; 4] = [
+    "text/plain;charset=utf-8",
+    "text/plain",
+    "UTF8_STRING",
+    "STRING",
+];
+
+/// Pushes selection changes instead of being polled.
+pub trait SelectionEventSource: Send + 'static {
+    /// Blocks until the selection changes. Returns `false` once the source has shut down.
+    fn wait_for_change(&mut self) -> bool;
+    fn read_selection(&mut self) -> Option<ClipboardEntry>;
+}
+
+/// Selection events from `wl-paste --watch`, which listens on the compositor's data-control
+/// protocol (`zwlr_data_control_v1` or `ext_data_control_v1`). Without the `wayland` feature,
+/// and on compositors [`DataControlSource`] can't talk to, `backend: wayland` watches with
+/// this instead.
+pub struct WlPasteSelectionSource {
+    selection: Selection,
+    child: Child,
+    events: BufReader<ChildStdout>,
+    record_targets: bool,
+}
+
+impl WlPasteSelectionSource {
+    /// Watches `selection`, with `wl-paste --primary` for the primary selection.
+    pub fn spawn(selection: Selection) -> Result<Self> {
+        let mut child = Command::new(WL_PASTE_BIN)
+            .args(selection_args(selection))
+            .args(["--watch", "echo"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to launch {WL_PASTE_BIN} --watch"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .context("failed to capture wl-paste --watch output")?;
+        Ok(Self {
+            selection,
+            child,
+            events: BufReader::new(stdout),
+            record_targets: false,
+        })
+    }
+
+    /// Keeps the MIME types the owner offered on each entry read, for
+    /// `clipboard.record_targets`.
+    pub fn with_recorded_targets(mut self, record_targets: bool) -> Self {
+        self.record_targets = record_targets;
+        self
+    }
+}
+
+impl Drop for WlPasteSelectionSource {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl SelectionEventSource for WlPasteSelectionSource {
+    fn wait_for_change(&mut self) -> bool {
+        let mut line = String::new();
+        matches!(self.events.read_line(&mut line), Ok(read) if read > 0)
+    }
+
+    fn read_selection(&mut self) -> Option<ClipboardEntry> {
+        read_wl_selection(self.selection, self.record_targets)
+    }
+}
+
+/// Starts watching `selection` for `backend: wayland` and delivers its captures as
+/// [`spawn_selection_listener`] does: with [`DataControlSource`] when the `wayland` feature
+/// is built and the compositor supports it, otherwise with [`WlPasteSelectionSource`].
+/// `max_entry_bytes` only bounds the former.
+#[cfg_attr(not(feature = "wayland"), allow(unused_variables))]
+pub fn spawn_wayland_listener(
+    selection: Selection,
+    record_targets: bool,
+    max_entry_bytes: u64,
+    active_window_provider: Box<dyn ActiveWindowProvider>,
+) -> Result<(Receiver<Capture>, UnixStream)> {
+    #[cfg(feature = "wayland")]
+    match DataControlSource::connect(selection) {
+        Ok(source) => {
+            return spawn_selection_listener(
+                source
+                    .with_recorded_targets(record_targets)
+                    .with_max_entry_bytes(max_entry_bytes),
+                active_window_provider,
+            )
+        }
+        Err(err) => crate::log_eprintln!("warning: {err:#}; watching through {WL_PASTE_BIN}"),
+    }
+    let source = WlPasteSelectionSource::spawn(selection)?.with_recorded_targets(record_targets);
+    spawn_selection_listener(source, active_window_provider)
+}
+
+/// Reads the clipboard once, for `slyboard capture-once`: no watcher is started, and the
+/// focused window is looked up before the contents are read. Like
+/// [`spawn_wayland_listener`], it reads through `wl-paste` only without the data-control
+/// client.
+pub struct WaylandBackend {
+    selection: Selection,
+    active_window_provider: Box<dyn ActiveWindowProvider>,
+    record_targets: bool,
+    #[cfg_attr(not(feature = "wayland"), allow(dead_code))]
+    max_entry_bytes: u64,
+}
+
+impl WaylandBackend {
+    pub fn new(
+        selection: Selection,
+        active_window_provider: Box<dyn ActiveWindowProvider>,
+    ) -> Self {
+        Self {
+            selection,
+            active_window_provider,
+            record_targets: false,
+            max_entry_bytes: DEFAULT_MAX_ENTRY_BYTES,
+        }
+    }
+
+    /// See [`WlPasteSelectionSource::with_recorded_targets`].
+    pub fn with_recorded_targets(mut self, record_targets: bool) -> Self {
+        self.record_targets = record_targets;
+        self
+    }
+
+    /// See [`DataControlSource::with_max_entry_bytes`].
+    pub fn with_max_entry_bytes(mut self, max_entry_bytes: u64) -> Self {
+        self.max_entry_bytes = max_entry_bytes;
+        self
+    }
+}
+
+impl ClipboardBackend for WaylandBackend {
+    fn read_entry(&self) -> Option<ClipboardEntry> {
+        #[cfg(feature = "wayland")]
+        match DataControlSource::connect(self.selection) {
+            Ok(source) => {
+                return source
+                    .with_recorded_targets(self.record_targets)
+                    .with_max_entry_bytes(self.max_entry_bytes)
+                    .read_selection()
+            }
+            Err(err) => crate::log_eprintln!("warning: {err:#}; reading through {WL_PASTE_BIN}"),
+        }
+        read_wl_selection(self.selection, self.record_targets)
+    }
+
+    fn origin(&self) -> CaptureOrigin {
+        CaptureOrigin::WaylandEvent
+    }
+
+    fn selection(&self) -> Selection {
+        self.selection
+    }
+
+    fn read_active_window(&self) -> Option<ActiveWindowContext> {
+        self.active_window_provider.capture()
+    }
+}
+
+fn read_wl_selection(selection: Selection, record_targets: bool) -> Option<ClipboardEntry> {
+    let wl_paste = |args: &[&str]| wl_paste(&[selection_args(selection), args].concat());
+    let offered = wl_paste(&["--list-types"])?;
+    let offered: Vec<String> = String::from_utf8_lossy(&offered)
+        .lines()
+        .map(|mime| mime.trim().to_string())
+        .collect();
+    entry_from_offer(
+        &offered,
+        |mime_type| wl_paste(&["--no-newline", "--type", mime_type]),
+        record_targets,
+    )
+}
+
+/// The entry for a selection offering the `offered` MIME types, transferring one with
+/// `receive`: text when any text type is offered, else a PNG image.
+pub(crate) fn entry_from_offer(
+    offered: &[String],
+    mut receive: impl FnMut(&str) -> Option<Vec<u8>>,
+    record_targets: bool,
+) -> Option<ClipboardEntry> {
+    let is_offered = |mime: &str| offered.iter().any(|offered| offered == mime);
+    let entry = if let Some(mime) = TEXT_MIME_TYPES.into_iter().find(|mime| is_offered(mime)) {
+        let value = String::from_utf8(receive(mime)?).ok()?;
+        if value.is_empty() {
+            return None;
+        }
+        ClipboardEntry::text(value)
+    } else if is_offered(PNG_MIME_TYPE) {
+        decode_png(&receive(PNG_MIME_TYPE)?)?
+    } else {
+        return None;
+    };
+    if !record_targets {
+        return Some(entry);
+    }
+    Some(entry.with_offered_targets(offered.to_vec()))
+}
+
+fn selection_args(selection: Selection) -> &'static [&'static str] {
+    match selection {
+        Selection::Clipboard => &[],
+        Selection::Primary => &["--primary"],
+    }
+}
+
+fn wl_paste(args: &[&str]) -> Option<Vec<u8>> {
+    let output = Command::new(WL_PASTE_BIN)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    output.status.success().then_some(output.stdout)
+}
+
+fn decode_png(bytes: &[u8]) -> Option<ClipboardEntry> {
+    let image = crate::clipboard::png::decode(bytes).ok()?;
+    let data = crate::clipboard::ImageData::new(
+        i32::try_from(image.width).ok()?,
+        i32::try_from(image.height).ok()?,
+        i32::from(image.channels),
+        i32::try_from(image.rowstride()).ok()?,
+        image.pixels,
+    )
+    .ok()?;
+    Some(ClipboardEntry::image(data))
+}
+
+/// Runs `source` on a background thread. Each captured entry is sent on the returned channel,
+/// and one byte is written to the returned socket so a main loop can wait on it.
+///
+/// The focused window is looked up as soon as a change is announced, before the selection is
+/// transferred, so a slow transfer can't attribute the copy to a window focused afterwards.
+pub fn spawn_selection_listener<S: SelectionEventSource>(
+    mut source: S,
+    active_window_provider: Box<dyn ActiveWindowProvider>,
+) -> Result<(Receiver<Capture>, UnixStream)> {
+    let (entry_tx, entry_rx) = mpsc::channel();
+    let (mut wake_tx, wake_rx) = UnixStream::pair().context("failed to create wake socket")?;
+
+    thread::Builder::new()
+        .name("slyboard-selection".to_string())
+        .spawn(move || {
+            while source.wait_for_change() {
+                let window = active_window_provider.capture();
+                let Some(entry) = source.read_selection() else {
+                    continue;
+                };
+                let capture = Capture {
+                    entry,
+                    window,
+                    window_lag: Duration::ZERO,
+                };
+                if entry_tx.send(capture).is_err() || wake_tx.write_all(&[1]).is_err() {
+                    return;
+                }
+            }
+            crate::log_eprintln!("warning: clipboard selection listener stopped");
+        })
+        .context("failed to spawn clipboard selection listener")?;
+
+    Ok((entry_rx, wake_rx))
+}
+
+/// Feeds pushed entries into [`ClipboardPoller`](crate::clipboard::poller::ClipboardPoller) so
+/// blacklist, filter, and dedup logic stay shared with polling backends.
+pub struct ChannelBackend {
+    captures: Receiver<Capture>,
+    selection: Selection,
+}
+
+impl ChannelBackend {
+    /// Delivers the captures of a listener watching `selection`.
+    pub fn new(captures: Receiver<Capture>, selection: Selection) -> Self {
+        Self {
+            captures,
+            selection,
+        }
+    }
+}
+
+impl ClipboardBackend for ChannelBackend {
+    fn read_entry(&self) -> Option<ClipboardEntry> {
+        self.read_capture().map(|capture| capture.entry)
+    }
+
+    fn origin(&self) -> CaptureOrigin {
+        CaptureOrigin::WaylandEvent
+    }
+
+    fn selection(&self) -> Selection {
+        self.selection
+    }
+
+    /// The window was looked up by the listener when the change was announced.
+    fn read_capture(&self) -> Option<Capture> {
+        self.captures.try_recv().ok()
+    }
+
+    fn reports_each_copy(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        entry_from_offer, spawn_selection_listener, ChannelBackend, Selection, SelectionEventSource,
+    };
+    use crate::clipboard::poller::ClipboardPoller;
+    use crate::clipboard::ClipboardEntry;
+    use crate::core::active_window::{
+        ActiveWindowProvider, CaptureOutcome, DisabledActiveWindowProvider,
+    };
+    use crate::testing::{text, window};
+    use std::collections::VecDeque;
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    type CallLog = Arc<Mutex<Vec<&'static str>>>;
+
+    #[derive(Default)]
+    struct FakeSelectionSource {
+        selections: VecDeque<Option<ClipboardEntry>>,
+        calls: CallLog,
+    }
+
+    impl SelectionEventSource for FakeSelectionSource {
+        fn wait_for_change(&mut self) -> bool {
+            !self.selections.is_empty()
+        }
+
+        fn read_selection(&mut self) -> Option<ClipboardEntry> {
+            self.calls.lock().unwrap().push("read selection");
+            self.selections.pop_front().flatten()
+        }
+    }
+
+    struct RecordingProvider(CallLog);
+
+    impl ActiveWindowProvider for RecordingProvider {
+        fn probe(&self) -> CaptureOutcome {
+            self.0.lock().unwrap().push("active window");
+            CaptureOutcome::Captured(Box::new(window(Some("kitty"), "~")))
+        }
+    }
+
+    fn source(selections: Vec<Option<ClipboardEntry>>) -> FakeSelectionSource {
+        FakeSelectionSource {
+            selections: VecDeque::from(selections),
+            ..FakeSelectionSource::default()
+        }
+    }
+
+    #[test]
+    fn delivers_pushed_entries_with_one_wake_byte_each() {
+        let source = source(vec![Some(text("a")), None, Some(text("b"))]);
+        let (captures, mut wake) =
+            spawn_selection_listener(source, Box::new(DisabledActiveWindowProvider))
+                .expect("listener starts");
+
+        let mut wake_bytes = [0u8; 2];
+        wake.set_read_timeout(Some(Duration::from_secs(5)))
+            .expect("set timeout");
+        wake.read_exact(&mut wake_bytes).expect("two wake bytes");
+
+        let received: Vec<ClipboardEntry> = captures.iter().map(|capture| capture.entry).collect();
+        assert_eq!(received, vec![text("a"), text("b")]);
+    }
+
+    #[test]
+    fn looks_up_the_window_before_transferring_the_selection() {
+        let source = source(vec![Some(text("a")), Some(text("b"))]);
+        let calls = Arc::clone(&source.calls);
+        let (captures, mut wake) =
+            spawn_selection_listener(source, Box::new(RecordingProvider(Arc::clone(&calls))))
+                .expect("listener starts");
+        let mut wake_bytes = [0u8; 2];
+        wake.set_read_timeout(Some(Duration::from_secs(5)))
+            .expect("set timeout");
+        wake.read_exact(&mut wake_bytes).expect("two wake bytes");
+
+        let mut poller = ClipboardPoller::new(
+            ChannelBackend::new(captures, Selection::Clipboard),
+            Vec::new(),
+        );
+        let entry = poller.poll_once().expect("a is captured");
+        assert_eq!(entry.source_window(), Some(&window(Some("kitty"), "~")));
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "active window",
+                "read selection",
+                "active window",
+                "read selection"
+            ]
+        );
+    }
+
+    #[test]
+    fn channel_backend_shares_poller_dedup() {
+        let source = source(vec![Some(text("a")), Some(text("a")), Some(text("b"))]);
+        let (captures, mut wake) =
+            spawn_selection_listener(source, Box::new(DisabledActiveWindowProvider))
+                .expect("listener starts");
+        let mut wake_bytes = [0u8; 3];
+        wake.read_exact(&mut wake_bytes).expect("three wake bytes");
+
+        let mut poller = ClipboardPoller::new(
+            ChannelBackend::new(captures, Selection::Clipboard),
+            Vec::new(),
+        );
+        let captured: Vec<Option<String>> = wake_bytes
+            .iter()
+            .map(|_| match poller.poll_once() {
+                Some(ClipboardEntry::Text { value, .. }) => Some(value),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            captured,
+            vec![Some("a".to_string()), None, Some("b".to_string())]
+        );
+    }
+
+    #[test]
+    fn takes_the_preferred_text_type_from_an_offer() {
+        let offered = ["image/png", "text/plain", "text/plain;charset=utf-8"].map(String::from);
+        let mut asked = Vec::new();
+        let entry = entry_from_offer(
+            &offered,
+            |mime_type| {
+                asked.push(mime_type.to_string());
+                Some(b"hello".to_vec())
+            },
+            true,
+        )
+        .expect("text is offered");
+        assert_eq!(asked, ["text/plain;charset=utf-8"]);
+        assert!(entry.content_eq(&text("hello")));
+        assert_eq!(entry.offered_targets(), offered);
+
+        let only_files = ["text/uri-list".to_string()];
+        assert!(entry_from_offer(&only_files, |_| unreachable!(), false).is_none());
+        let empty = ["UTF8_STRING".to_string()];
+        assert!(entry_from_offer(&empty, |_| Some(Vec::new()), false).is_none());
+    }
+}
@@ -0,0 +1,294 @@
+//! Sets the clipboard, and the primary selection when restores go there too: through GTK when
+//! built with it, otherwise by handing content to `wl-copy` or `xclip`. Windows and macOS set
+//! their one clipboard through `arboard`.
+
+use std::borrow::Cow;
+use std::env;
+use std::ffi::OsStr;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::clipboard::{ClipboardEntry, ImageEncoding};
+use crate::core::self_write::expect_self_write;
+
+const PNG_MIME_TYPE: &str = "image/png";
+
+/// A selection entries are captured from (`clipboard.selections`) and restored to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Selection {
+    /// What Ctrl+V pastes.
+    Clipboard,
+    /// What middle click pastes.
+    Primary,
+}
+
+impl Selection {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Clipboard => "clipboard",
+            Self::Primary => "primary",
+        }
+    }
+}
+
+/// Where restores write by default (`clipboard.restore_target`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RestoreTarget {
+    #[default]
+    Clipboard,
+    Primary,
+    Both,
+}
+
+#[cfg(feature = "gtk")]
+impl Selection {
+    pub fn gtk_clipboard(self) -> gtk::Clipboard {
+        gtk::Clipboard::get(match self {
+            Self::Clipboard => &gtk::gdk::SELECTION_CLIPBOARD,
+            Self::Primary => &gtk::gdk::SELECTION_PRIMARY,
+        })
+    }
+}
+
+/// Where restoring `entry` to `target` writes it. Images skip primary, since middle click
+/// pastes text, so they go to the clipboard even when `target` is only primary.
+pub fn restore_selections(entry: &ClipboardEntry, target: RestoreTarget) -> Vec<Selection> {
+    match (target, entry) {
+        (RestoreTarget::Primary, ClipboardEntry::Text { .. }) => vec![Selection::Primary],
+        (RestoreTarget::Both, ClipboardEntry::Text { .. }) => {
+            vec![Selection::Clipboard, Selection::Primary]
+        }
+        _ => vec![Selection::Clipboard],
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandWriter {
+    /// `wl-copy`, for Wayland sessions.
+    WlCopy,
+    /// `xclip -selection clipboard`, for X11 sessions.
+    Xclip,
+}
+
+impl CommandWriter {
+    /// Picks the writer for the current session from `$WAYLAND_DISPLAY` and `$DISPLAY`.
+    pub fn detect() -> Result<Self> {
+        Self::detect_from(
+            env::var_os("WAYLAND_DISPLAY").as_deref(),
+            env::var_os("DISPLAY").as_deref(),
+        )
+        .ok_or_else(|| anyhow!("no graphical session found (WAYLAND_DISPLAY and DISPLAY unset)"))
+    }
+
+    pub fn detect_from(wayland_display: Option<&OsStr>, display: Option<&OsStr>) -> Option<Self> {
+        let set = |value: Option<&OsStr>| value.is_some_and(|value| !value.is_empty());
+        if set(wayland_display) {
+            Some(Self::WlCopy)
+        } else if set(display) {
+            Some(Self::Xclip)
+        } else {
+            None
+        }
+    }
+
+    fn program(self) -> &'static str {
+        match self {
+            Self::WlCopy => "wl-copy",
+            Self::Xclip => "xclip",
+        }
+    }
+
+    fn args(self, selection: Selection, entry: &ClipboardEntry) -> Vec<&'static str> {
+        let target = match entry {
+            ClipboardEntry::Image { .. } => PNG_MIME_TYPE,
+            ClipboardEntry::Text { .. } if self == Self::WlCopy => "text/plain;charset=utf-8",
+            ClipboardEntry::Text { .. } => "UTF8_STRING",
+        };
+        match (self, selection) {
+            (Self::WlCopy, Selection::Clipboard) => vec!["--type", target],
+            (Self::WlCopy, Selection::Primary) => vec!["--primary", "--type", target],
+            (Self::Xclip, Selection::Clipboard) => {
+                vec!["-selection", "clipboard", "-t", target, "-i"]
+            }
+            (Self::Xclip, Selection::Primary) => vec!["-selection", "primary", "-t", target, "-i"],
+        }
+    }
+
+    /// Pipes `entry` into the writer for `selection`. Images are written as PNG. Both tools
+    /// keep serving the selection from a background process after this returns.
+    pub fn write(self, selection: Selection, entry: &ClipboardEntry) -> Result<()> {
+        let payload = payload(entry)?;
+        let program = self.program();
+        let mut child = Command::new(program)
+            .args(self.args(selection, entry))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .with_context(|| format!("failed to run {program}"))?;
+        child
+            .stdin
+            .take()
+            .context("clipboard writer stdin unavailable")?
+            .write_all(&payload)
+            .with_context(|| format!("failed to write to {program}"))?;
+        let status = child
+            .wait()
+            .with_context(|| format!("failed to wait for {program}"))?;
+        if !status.success() {
+            bail!("{program} exited with {status}");
+        }
+        Ok(())
+    }
+}
+
+/// Puts `entry` on the selections `target` names (see [`restore_selections`]), normalized
+/// while plain-text paste is on. Text cut to a preview goes on as it is, so callers read it
+/// back whole first (see [`SharedClipboardState::whole_entry`]). With GTK the content is
+/// handed to the session's clipboard manager before returning; `wl-copy` and `xclip` keep
+/// serving it themselves. The daemon is told to expect the write, so it doesn't capture it as
+/// a new copy.
+///
+/// [`SharedClipboardState::whole_entry`]: crate::clipboard::SharedClipboardState::whole_entry
+#[cfg(all(target_os = "linux", feature = "gtk"))]
+pub fn set_clipboard(entry: &ClipboardEntry, target: RestoreTarget) -> Result<()> {
+    announce_write(entry);
+    gtk::init().context("failed to initialize GTK for clipboard access")?;
+    set_gtk_selections(entry, &restore_selections(entry, target))
+}
+
+/// There is no primary selection to restore to, so `target` makes no difference.
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+pub fn set_clipboard(entry: &ClipboardEntry, _target: RestoreTarget) -> Result<()> {
+    announce_write(entry);
+    crate::clipboard::native::set_native_clipboard(entry)
+}
+
+#[cfg(not(any(
+    all(target_os = "linux", feature = "gtk"),
+    target_os = "windows",
+    target_os = "macos"
+)))]
+pub fn set_clipboard(entry: &ClipboardEntry, target: RestoreTarget) -> Result<()> {
+    let writer = CommandWriter::detect()?;
+    announce_write(entry);
+    let entry = crate::core::plain_paste::entry_for_paste(entry);
+    for selection in restore_selections(&entry, target) {
+        writer.write(selection, &entry)?;
+    }
+    Ok(())
+}
+
+/// Writes `entry` to each of `selections` through GTK, which must be initialized. Callers
+/// announce the write themselves.
+#[cfg(feature = "gtk")]
+pub fn set_gtk_selections(entry: &ClipboardEntry, selections: &[Selection]) -> Result<()> {
+    for selection in selections {
+        crate::clipboard::backend::set_clipboard_entry(&selection.gtk_clipboard(), entry)?;
+    }
+    Ok(())
+}
+
+/// Without the announcement the write still happens; the daemon just records it again.
+fn announce_write(entry: &ClipboardEntry) {
+    if let Err(err) = expect_self_write(entry) {
+        crate::log_eprintln!("warning: {err:#}");
+    }
+}
+
+/// The bytes handed to the writer: the text itself, or the image as PNG.
+fn payload(entry: &ClipboardEntry) -> Result<Cow<'_, [u8]>> {
+    match entry {
+        ClipboardEntry::Text { value, .. } => Ok(Cow::Borrowed(value.as_bytes())),
+        ClipboardEntry::Image {
+            encoding: ImageEncoding::Png,
+            pixels,
+            ..
+        } => Ok(Cow::Borrowed(pixels)),
+        ClipboardEntry::Image { .. } => match entry.clone().into_png_encoded() {
+            ClipboardEntry::Image {
+                encoding: ImageEncoding::Png,
+                pixels,
+                ..
+            } => Ok(Cow::Owned(pixels)),
+            _ => bail!("image layout cannot be written as PNG"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{payload, restore_selections, CommandWriter, RestoreTarget, Selection};
+    use crate::testing::{raw_image, text};
+    use std::ffi::OsStr;
+
+    #[test]
+    fn prefers_wl_copy_on_wayland() {
+        let wayland = Some(OsStr::new("wayland-1"));
+        let x11 = Some(OsStr::new(":0"));
+        assert_eq!(
+            CommandWriter::detect_from(wayland, x11),
+            Some(CommandWriter::WlCopy)
+        );
+        assert_eq!(
+            CommandWriter::detect_from(Some(OsStr::new("")), x11),
+            Some(CommandWriter::Xclip)
+        );
+        assert_eq!(CommandWriter::detect_from(None, None), None);
+    }
+
+    #[test]
+    fn writes_text_as_is_and_images_as_png() {
+        let entry = text("hello");
+        assert_eq!(payload(&entry).unwrap().as_ref(), b"hello");
+        assert_eq!(
+            CommandWriter::Xclip.args(Selection::Clipboard, &entry),
+            ["-selection", "clipboard", "-t", "UTF8_STRING", "-i"]
+        );
+
+        let raw = raw_image(1, 1, [1, 2, 3, 4]);
+        let png = payload(&raw).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+        assert_eq!(
+            CommandWriter::WlCopy.args(Selection::Clipboard, &raw),
+            ["--type", "image/png"]
+        );
+    }
+
+    #[test]
+    fn restores_text_to_primary_only_when_asked() {
+        let entry = text("hello");
+        assert_eq!(
+            restore_selections(&entry, RestoreTarget::Clipboard),
+            [Selection::Clipboard]
+        );
+        assert_eq!(
+            restore_selections(&entry, RestoreTarget::Both),
+            [Selection::Clipboard, Selection::Primary]
+        );
+        assert_eq!(
+            restore_selections(&entry, RestoreTarget::Primary),
+            [Selection::Primary]
+        );
+        for target in [RestoreTarget::Both, RestoreTarget::Primary] {
+            assert_eq!(
+                restore_selections(&raw_image(1, 1, [0; 4]), target),
+                [Selection::Clipboard],
+                "images skip primary"
+            );
+        }
+
+        assert_eq!(
+            CommandWriter::WlCopy.args(Selection::Primary, &entry),
+            ["--primary", "--type", "text/plain;charset=utf-8"]
+        );
+        assert_eq!(
+            CommandWriter::Xclip.args(Selection::Primary, &entry),
+            ["-selection", "primary", "-t", "UTF8_STRING", "-i"]
+        );
+    }
+}
@@ -1,36 +1,720 @@
-use std::path::PathBuf;
+use std::ffi::OsStr;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use anyhow::{bail, Context, Result};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+use crate::clipboard::blacklist::BlacklistRule;
+use crate::clipboard::dedup::{DedupScope, DedupSettings};
+use crate::clipboard::filters::parse_glob;
+use crate::clipboard::migrations::LegacyOrder;
+use crate::clipboard::retention::{ExpireRule, RetentionPolicy, RetentionRule};
+use crate::clipboard::template::HistoryTemplate;
+use crate::clipboard::writer::{RestoreTarget, Selection};
+use crate::clipboard::DEFAULT_HISTORY_LIMIT;
+use crate::core::environment::program_on_path;
+use crate::core::tray_menu::MENU_LABEL_CHAR_LIMIT;
+use crate::error::{Error, Result};
+
+pub mod effective;
+pub mod layers;
+
+use layers::MergedConfig;
+
+pub const DEFAULT_TRANSIENT_TTL_SECS: u64 = 60;
+pub const DEFAULT_NOTIFICATION_PREVIEW_CHARS: usize = 40;
+pub const DEFAULT_LOG_MAX_SIZE_MB: u64 = 5;
+pub const DEFAULT_EVENTS_FILE: &str = "events.jsonl";
+pub const DEFAULT_EVENTS_MAX_SIZE_MB: u64 = 10;
+pub const DEFAULT_APPEND_SEPARATOR: &str = "\n";
+pub const DEFAULT_MAX_ENTRY_BYTES: u64 = 128 * 1024 * 1024;
+pub const DEFAULT_OCR_PROGRAM: &str = "tesseract";
+pub const DEFAULT_OCR_ARGS: [&str; 2] = ["stdin", "stdout"];
+pub const DEFAULT_INCOGNITO_MARKERS: [&str; 3] = ["Private Browsing", "(Incognito)", "InPrivate"];
 
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct AppConfig {
     #[serde(default)]
     pub clipboard: ClipboardConfig,
+    #[serde(default)]
+    pub tray: TrayConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub ocr: OcrConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub events: EventsConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LoggingConfig {
+    /// Copy daemon output to this file. Relative paths are resolved against
+    /// `$XDG_STATE_HOME/slyboard`; nothing is written to disk when unset.
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    /// Size at which the log file is moved to `<file>.1`, replacing the previous one.
+    #[serde(default = "default_log_max_size_mb")]
+    pub max_size_mb: u64,
+    /// Also log why each skipped copy was skipped.
+    #[serde(default)]
+    pub debug: bool,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            file: None,
+            max_size_mb: DEFAULT_LOG_MAX_SIZE_MB,
+            debug: false,
+        }
+    }
+}
+
+impl LoggingConfig {
+    pub fn resolved_file(&self) -> Result<Option<PathBuf>> {
+        let Some(file) = &self.file else {
+            return Ok(None);
+        };
+        if file.is_absolute() {
+            return Ok(Some(file.clone()));
+        }
+        state_path(file).map(Some)
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_size_mb.saturating_mul(1024 * 1024)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self
+            .file
+            .as_ref()
+            .is_some_and(|file| file.as_os_str().is_empty())
+        {
+            return Err(Error::config_invalid("logging.file cannot be empty"));
+        }
+        if self.max_size_mb == 0 {
+            return Err(Error::config_invalid(
+                "logging.max_size_mb must be greater than zero",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn default_max_entry_bytes() -> u64 {
+    DEFAULT_MAX_ENTRY_BYTES
+}
+
+fn default_log_max_size_mb() -> u64 {
+    DEFAULT_LOG_MAX_SIZE_MB
+}
+
+/// `file` resolved against `$XDG_STATE_HOME/slyboard`. macOS and Windows have no state dir,
+/// so the cache dir is used there (and when it can't be found).
+fn state_path(file: &Path) -> Result<PathBuf> {
+    state_path_in(dirs::state_dir(), dirs::cache_dir(), file)
+}
+
+fn state_path_in(
+    state_dir: Option<PathBuf>,
+    cache_dir: Option<PathBuf>,
+    file: &Path,
+) -> Result<PathBuf> {
+    let state_dir = state_dir
+        .or(cache_dir)
+        .ok_or(Error::MissingDirectory { kind: "state" })?;
+    Ok(state_dir.join("slyboard").join(file))
+}
+
+/// The activity log: one JSON line per capture, restore, clear, pause, and resume, for
+/// analytics. Unlike history it is never trimmed to the history limit.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EventsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Relative paths are resolved against `$XDG_STATE_HOME/slyboard`.
+    #[serde(default = "default_events_file")]
+    pub file: PathBuf,
+    /// Include the text of captured and restored entries, not only their hash.
+    #[serde(default)]
+    pub log_content: bool,
+    /// Size at which the file is moved to `<file>.1`, replacing the previous one.
+    #[serde(default = "default_events_max_size_mb")]
+    pub max_size_mb: u64,
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            file: default_events_file(),
+            log_content: false,
+            max_size_mb: DEFAULT_EVENTS_MAX_SIZE_MB,
+        }
+    }
+}
+
+impl EventsConfig {
+    /// Where events go, or `None` while the log is off.
+    pub fn resolved_file(&self) -> Result<Option<PathBuf>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        if self.file.is_absolute() {
+            return Ok(Some(self.file.clone()));
+        }
+        state_path(&self.file).map(Some)
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_size_mb.saturating_mul(1024 * 1024)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.file.as_os_str().is_empty() {
+            return Err(Error::config_invalid("events.file cannot be empty"));
+        }
+        if self.max_size_mb == 0 {
+            return Err(Error::config_invalid(
+                "events.max_size_mb must be greater than zero",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn default_events_file() -> PathBuf {
+    PathBuf::from(DEFAULT_EVENTS_FILE)
+}
+
+fn default_events_max_size_mb() -> u64 {
+    DEFAULT_EVENTS_MAX_SIZE_MB
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OcrConfig {
+    /// Offer text recognition for image entries in the tray and rofi. `slyboard ocr` works
+    /// either way.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Reads a PNG on stdin and prints the text in it.
+    #[serde(default = "default_ocr_program")]
+    pub program: String,
+    #[serde(default = "default_ocr_args")]
+    pub args: Vec<String>,
+}
+
+impl Default for OcrConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            program: default_ocr_program(),
+            args: default_ocr_args(),
+        }
+    }
+}
+
+impl OcrConfig {
+    fn validate(&self) -> Result<()> {
+        if self.program.trim().is_empty() {
+            return Err(Error::config_invalid("ocr.program cannot be empty"));
+        }
+        Ok(())
+    }
+}
+
+/// The read-only history endpoint; off unless `listen` is set.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HttpConfig {
+    /// Address to serve on, e.g. `127.0.0.1:7878`.
+    #[serde(default)]
+    pub listen: Option<String>,
+    /// Require `Authorization: Bearer <token>` on every request.
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Allow `listen` to be a non-loopback address. History is served to anyone who can reach
+    /// it, so set a `token` too.
+    #[serde(default)]
+    pub allow_remote: bool,
+}
+
+impl HttpConfig {
+    /// The address to serve on, or `None` when the endpoint is disabled.
+    pub fn listen_addr(&self) -> Result<Option<SocketAddr>> {
+        let Some(listen) = &self.listen else {
+            return Ok(None);
+        };
+        let addr: SocketAddr = listen.trim().parse().map_err(|_| {
+            Error::config_invalid(format!(
+                "http.listen must be an address and port like 127.0.0.1:7878, got {listen:?}"
+            ))
+        })?;
+        if !addr.ip().is_loopback() && !self.allow_remote {
+            return Err(Error::config_invalid(format!(
+                "http.listen {addr} is not a loopback address; set http.allow_remote to serve it"
+            )));
+        }
+        Ok(Some(addr))
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.listen_addr()?;
+        if self
+            .token
+            .as_ref()
+            .is_some_and(|token| token.trim().is_empty())
+        {
+            return Err(Error::config_invalid("http.token cannot be empty"));
+        }
+        Ok(())
+    }
+}
+
+fn default_ocr_program() -> String {
+    DEFAULT_OCR_PROGRAM.to_string()
+}
+
+fn default_ocr_args() -> Vec<String> {
+    DEFAULT_OCR_ARGS.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StorageConfig {
+    /// Drop duplicate, whitespace-only, and broken image entries whenever the daemon loads
+    /// history from disk.
+    #[serde(default)]
+    pub prune_on_load: bool,
+    /// Entries kept in history; the oldest unpinned ones are dropped beyond it. Overridden by
+    /// `--history-limit` and `$SLYBOARD_HISTORY_LIMIT`, see [`effective`].
+    #[serde(default = "default_history_limit")]
+    pub history_limit: usize,
+    /// Clearing history moves it to `history.json.trash` first, for `slyboard restore --trash`.
+    #[serde(default)]
+    pub trash_on_clear: bool,
+    /// Which end of a history from the first releases (plain strings) is newest; read once,
+    /// when that history is upgraded.
+    #[serde(default)]
+    pub legacy_order: LegacyOrder,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            prune_on_load: false,
+            history_limit: DEFAULT_HISTORY_LIMIT,
+            trash_on_clear: false,
+            legacy_order: LegacyOrder::default(),
+        }
+    }
+}
+
+impl StorageConfig {
+    fn validate(&self) -> Result<()> {
+        if self.history_limit == 0 {
+            return Err(Error::config_invalid(
+                "storage.history_limit must be greater than zero",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn default_history_limit() -> usize {
+    DEFAULT_HISTORY_LIMIT
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TrayConfig {
+    #[serde(default)]
+    pub backend: TrayBackend,
+    /// Freedesktop icon name, or an absolute path to an SVG/PNG file; the bundled icon when unset.
+    #[serde(default)]
+    pub icon: Option<String>,
+    /// History item labels, in `slyboard history --format` syntax; just the preview when unset.
+    #[serde(default)]
+    pub label_template: Option<String>,
+    /// Longest history label, in characters; previews are shortened first to fit.
+    #[serde(default = "default_tray_label_chars")]
+    pub label_chars: usize,
+    /// Put a fuzzy search box atop the History submenu. Off by default since some indicator
+    /// hosts render custom menu widgets poorly.
+    #[serde(default)]
+    pub search: bool,
+    /// Give each history item a Copy/Delete/Pin (and Open for URLs) submenu; clicking the item
+    /// itself still copies. When off, history items are plain copy-on-click entries.
+    #[serde(default = "default_true")]
+    pub entry_submenus: bool,
+    /// Middle-clicking the tray icon restores the newest (or scroll-selected) entry.
+    #[serde(default = "default_true")]
+    pub middle_click_restore: bool,
+    /// Scrolling over the tray icon selects which entry a middle click restores.
+    #[serde(default = "default_true")]
+    pub scroll_to_arm: bool,
+}
+
+impl Default for TrayConfig {
+    fn default() -> Self {
+        Self {
+            backend: TrayBackend::default(),
+            icon: None,
+            label_template: None,
+            label_chars: default_tray_label_chars(),
+            search: false,
+            entry_submenus: true,
+            middle_click_restore: true,
+            scroll_to_arm: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TrayBackend {
+    #[default]
+    #[serde(rename = "appindicator")]
+    AppIndicator,
+    /// A StatusNotifierItem served by `ksni`; needs a build with the `ksni` feature.
+    Ksni,
+    /// Capture clipboard history without showing a tray icon.
+    None,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ClipboardConfig {
     #[serde(default)]
-    pub backend: ClipboardBackend,
+    pub backend: ClipboardBackends,
+    /// The selections each backend watches: `clipboard` (Ctrl+C), `primary` (selected text,
+    /// pasted with middle click), or both. The same text in both is recorded once.
+    #[serde(default = "default_selections")]
+    pub selections: Vec<Selection>,
     #[serde(default)]
     pub active_window: ActiveWindowConfig,
+    #[serde(default)]
+    pub notifications: NotificationConfig,
+    /// Suspend capture while the screen is locked.
+    #[serde(default)]
+    pub pause_on_lock: bool,
+    #[serde(default)]
+    pub incognito_policy: IncognitoPolicy,
+    /// Extra private-window title markers, e.g. localized browser titles.
+    #[serde(default)]
+    pub incognito_markers: Vec<String>,
+    #[serde(default = "default_true")]
+    pub incognito_default_markers: bool,
+    /// Text entries starting with one of these (after leading whitespace) are not recorded.
+    #[serde(default)]
+    pub ignore_prefixes: Vec<String>,
+    /// Text entries matching one of these `*`/`?` patterns in full are not recorded.
+    #[serde(default)]
+    pub ignore_globs: Vec<String>,
+    /// Nothing copied while a window on one of these workspaces (name or numeric id) is
+    /// focused is recorded.
+    #[serde(default)]
+    pub paused_workspaces: Vec<String>,
+    /// Record what is already on the clipboard when slyboard starts (without a source window).
+    #[serde(default)]
+    pub capture_existing_on_start: bool,
+    /// Put between the pieces collected in append mode when `slyboard append off` joins them.
+    #[serde(default = "default_append_separator")]
+    pub append_separator: String,
+    /// Per-application overrides of how many entries are kept and for how long.
+    #[serde(default)]
+    pub retention: Vec<RetentionConfig>,
+    /// The daemon warns (log and notification) when history grows past this many bytes.
+    #[serde(default)]
+    pub size_warning_bytes: Option<u64>,
+    /// The most the Wayland data-control client reads of one copy; a larger one is skipped.
+    #[serde(default = "default_max_entry_bytes")]
+    pub max_entry_bytes: u64,
+    /// Restored text also goes to the primary selection, for middle-click paste. The older
+    /// spelling of `restore_target = "both"`, which wins when set.
+    #[serde(default)]
+    pub restore_to_primary: bool,
+    /// Where restores write: `clipboard`, `primary` or `both`. Images always go to the
+    /// clipboard.
+    #[serde(default)]
+    pub restore_target: Option<RestoreTarget>,
+    #[serde(default)]
+    pub dedup: DedupConfig,
+    #[serde(default)]
+    pub text: TextConfig,
+    /// Keep the targets (MIME types) offered with each copy, shown by `history --json` and
+    /// `show --verbose`.
+    #[serde(default)]
+    pub record_targets: bool,
 }
 
 impl Default for ClipboardConfig {
     fn default() -> Self {
         Self {
-            backend: ClipboardBackend::Gtk,
+            backend: ClipboardBackends::default(),
+            selections: default_selections(),
             active_window: ActiveWindowConfig::default(),
+            notifications: NotificationConfig::default(),
+            pause_on_lock: false,
+            incognito_policy: IncognitoPolicy::default(),
+            incognito_markers: Vec::new(),
+            incognito_default_markers: true,
+            ignore_prefixes: Vec::new(),
+            ignore_globs: Vec::new(),
+            paused_workspaces: Vec::new(),
+            capture_existing_on_start: false,
+            append_separator: DEFAULT_APPEND_SEPARATOR.to_string(),
+            retention: Vec::new(),
+            size_warning_bytes: None,
+            max_entry_bytes: DEFAULT_MAX_ENTRY_BYTES,
+            restore_to_primary: false,
+            restore_target: None,
+            dedup: DedupConfig::default(),
+            text: TextConfig::default(),
+            record_targets: false,
+        }
+    }
+}
+
+impl ClipboardConfig {
+    /// `restore_target`, or what `restore_to_primary` asks for without one.
+    pub fn restore_target(&self) -> RestoreTarget {
+        self.restore_target.unwrap_or(if self.restore_to_primary {
+            RestoreTarget::Both
+        } else {
+            RestoreTarget::Clipboard
+        })
+    }
+
+    /// Title markers identifying private browser windows, built-ins first.
+    pub fn incognito_title_markers(&self) -> Vec<String> {
+        let defaults = self
+            .incognito_default_markers
+            .then_some(
+                DEFAULT_INCOGNITO_MARKERS
+                    .iter()
+                    .map(|marker| marker.to_string()),
+            )
+            .into_iter()
+            .flatten();
+        defaults
+            .chain(self.incognito_markers.iter().cloned())
+            .collect()
+    }
+
+    /// The retention rules, matched like `active_window.blacklist`; rules
+    /// [`AppConfig::validate`] rejects are left out.
+    pub fn retention_policy(&self) -> RetentionPolicy {
+        RetentionPolicy::new(
+            self.retention
+                .iter()
+                .filter_map(|retention| {
+                    Some(RetentionRule {
+                        rule: BlacklistRule::parse(&retention.app, self.active_window.match_mode)
+                            .ok()?,
+                        max_entries: retention.max_entries,
+                        ttl: retention.ttl_secs.map(Duration::from_secs),
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+/// One `clipboard.retention` item: entries copied from windows matching `app` are kept at most
+/// `max_entries` deep and `ttl_secs` long, within the global history limit.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct RetentionConfig {
+    pub app: String,
+    #[serde(default)]
+    pub max_entries: Option<usize>,
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+}
+
+impl RetentionConfig {
+    fn validate(&self, index: usize, match_mode: WindowMatchMode) -> Result<()> {
+        let key = format!("clipboard.retention[{index}]");
+        BlacklistRule::parse(&self.app, match_mode)
+            .map_err(|reason| Error::config_invalid(format!("{key}.app {reason}")))?;
+        if self.max_entries.is_none() && self.ttl_secs.is_none() {
+            return Err(Error::config_invalid(format!(
+                "{key} needs max_entries or ttl_secs"
+            )));
+        }
+        if self.max_entries == Some(0) {
+            return Err(Error::config_invalid(format!(
+                "{key}.max_entries must be greater than zero; use the blacklist to keep nothing"
+            )));
+        }
+        if self.ttl_secs == Some(0) {
+            return Err(Error::config_invalid(format!(
+                "{key}.ttl_secs must be greater than zero"
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IncognitoPolicy {
+    Skip,
+    #[default]
+    Transient,
+    Capture,
+}
+
+/// How copies are matched against history. The stored text is always the latest copy.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DedupConfig {
+    /// Copying content already in history moves that entry to the top. When off, every copy
+    /// is a new entry; the same value seen twice in a row is still one copy.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Text differing only in trailing whitespace, like a terminal's added newline, is the
+    /// same entry.
+    #[serde(default = "default_true")]
+    pub ignore_trailing_whitespace: bool,
+    /// How far back a copy looks for the entry to move: `all`, `recent:<n>` entries or
+    /// `window:<duration>` (`90s`, `30m`, `1h`, `7d`). Older copies stay where they are and
+    /// the new one is added.
+    #[serde(default)]
+    pub scope: DedupScope,
+}
+
+impl DedupConfig {
+    pub fn settings(&self) -> DedupSettings {
+        DedupSettings {
+            enabled: self.enabled,
+            scope: self.scope,
+            ignore_trailing_whitespace: self.ignore_trailing_whitespace,
+        }
+    }
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ignore_trailing_whitespace: true,
+            scope: DedupScope::All,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TextConfig {
+    /// Text longer than this many bytes keeps only a preview that long in history; the whole
+    /// text is stored next to the database and restored from there.
+    #[serde(default)]
+    pub preview_limit_bytes: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NotificationConfig {
+    /// Send a notification for each capture.
+    #[serde(default = "default_true")]
+    pub on_capture: bool,
+    #[serde(default = "default_notification_preview_chars")]
+    pub preview_chars: usize,
+    /// Warn when captures start discarding the oldest entries at `storage.history_limit`.
+    #[serde(default = "default_true")]
+    pub history_limit: bool,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            on_capture: true,
+            preview_chars: DEFAULT_NOTIFICATION_PREVIEW_CHARS,
+            history_limit: true,
         }
     }
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Default)]
+fn default_notification_preview_chars() -> usize {
+    DEFAULT_NOTIFICATION_PREVIEW_CHARS
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum ClipboardBackend {
     #[default]
     Gtk,
+    /// Event-driven capture through `wl-paste --watch` (wlroots data-control protocol).
+    Wayland,
+}
+
+impl ClipboardBackend {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Gtk => "gtk",
+            Self::Wayland => "wayland",
+        }
+    }
+}
+
+/// `clipboard.backend`: one backend, or a list of them capturing side by side into the same
+/// history, e.g. `[gtk, wayland]` while moving to Wayland. A change both see is recorded once.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(from = "OneOrMoreBackends")]
+pub struct ClipboardBackends(Vec<ClipboardBackend>);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMoreBackends {
+    One(ClipboardBackend),
+    More(Vec<ClipboardBackend>),
+}
+
+impl From<OneOrMoreBackends> for ClipboardBackends {
+    fn from(backends: OneOrMoreBackends) -> Self {
+        match backends {
+            OneOrMoreBackends::One(backend) => Self(vec![backend]),
+            OneOrMoreBackends::More(backends) => Self(backends),
+        }
+    }
+}
+
+impl Default for ClipboardBackends {
+    fn default() -> Self {
+        Self(vec![ClipboardBackend::default()])
+    }
+}
+
+impl ClipboardBackends {
+    pub fn iter(&self) -> impl Iterator<Item = ClipboardBackend> + '_ {
+        self.0.iter().copied()
+    }
+
+    /// E.g. "gtk, wayland".
+    pub fn names(&self) -> String {
+        self.iter()
+            .map(ClipboardBackend::as_str)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.0.is_empty() {
+            return Err(Error::config_invalid(
+                "clipboard.backend must name at least one backend",
+            ));
+        }
+        for (index, backend) in self.0.iter().enumerate() {
+            if self.0[..index].contains(backend) {
+                return Err(Error::config_invalid(format!(
+                    "clipboard.backend lists {} twice",
+                    backend.as_str()
+                )));
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -39,6 +723,21 @@ pub struct ActiveWindowConfig {
     pub backend: ActiveWindowBackend,
     #[serde(default)]
     pub blacklist: Vec<String>,
+    /// Sources whose entries stay in memory only and are never written to disk.
+    #[serde(default)]
+    pub transient: Vec<String>,
+    #[serde(default = "default_transient_ttl_secs")]
+    pub transient_ttl_secs: u64,
+    /// How `blacklist` and `transient` values are compared with the focused window.
+    #[serde(default)]
+    pub match_mode: WindowMatchMode,
+    /// Remove entries the blacklist matches when the daemon starts, as
+    /// `slyboard prune --blacklist` does.
+    #[serde(default)]
+    pub prune_on_start: bool,
+    /// Sources whose entries are saved like any other but leave history after a while.
+    #[serde(default)]
+    pub expire_rules: Vec<ExpireRuleConfig>,
 }
 
 impl Default for ActiveWindowConfig {
@@ -46,11 +745,115 @@ impl Default for ActiveWindowConfig {
         Self {
             backend: ActiveWindowBackend::Auto,
             blacklist: Vec::new(),
+            transient: Vec::new(),
+            transient_ttl_secs: DEFAULT_TRANSIENT_TTL_SECS,
+            match_mode: WindowMatchMode::default(),
+            prune_on_start: false,
+            expire_rules: Vec::new(),
         }
     }
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Default)]
+impl ActiveWindowConfig {
+    /// The blacklist as rules; values [`AppConfig::validate`] rejects are left out.
+    pub fn blacklist_rules(&self) -> Vec<BlacklistRule> {
+        self.rules(&self.blacklist)
+    }
+
+    /// The transient sources as rules; values [`AppConfig::validate`] rejects are left out.
+    pub fn transient_rules(&self) -> Vec<BlacklistRule> {
+        self.rules(&self.transient)
+    }
+
+    /// The expire rules; values [`AppConfig::validate`] rejects are left out.
+    pub fn expire_rules(&self) -> Vec<ExpireRule> {
+        self.expire_rules
+            .iter()
+            .filter_map(|expire| {
+                Some(ExpireRule {
+                    rule: BlacklistRule::parse(&expire.pattern, self.match_mode).ok()?,
+                    ttl: Duration::from_secs(expire.ttl_secs),
+                })
+            })
+            .collect()
+    }
+
+    fn rules(&self, values: &[String]) -> Vec<BlacklistRule> {
+        values
+            .iter()
+            .filter_map(|value| BlacklistRule::parse(value, self.match_mode).ok())
+            .collect()
+    }
+
+    fn validate_rules(&self, key: &str, values: &[String]) -> Result<()> {
+        for (index, value) in values.iter().enumerate() {
+            BlacklistRule::parse(value, self.match_mode).map_err(|reason| {
+                Error::config_invalid(format!("clipboard.active_window.{key}[{index}] {reason}"))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// One `clipboard.active_window.expire_rules` item: entries copied from windows matching
+/// `match` leave history `ttl_secs` after capture.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct ExpireRuleConfig {
+    #[serde(rename = "match")]
+    pub pattern: String,
+    pub ttl_secs: u64,
+}
+
+impl ExpireRuleConfig {
+    fn validate(&self, index: usize, match_mode: WindowMatchMode) -> Result<()> {
+        let key = format!("clipboard.active_window.expire_rules[{index}]");
+        BlacklistRule::parse(&self.pattern, match_mode)
+            .map_err(|reason| Error::config_invalid(format!("{key}.match {reason}")))?;
+        if self.ttl_secs == 0 {
+            return Err(Error::config_invalid(format!(
+                "{key}.ttl_secs must be greater than zero; use the blacklist to keep nothing"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Case-insensitive comparison of a blacklist or transient value with the focused window.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowMatchMode {
+    /// The app id equals the value, or the title contains it.
+    #[default]
+    Mixed,
+    /// The app id or title equals the value.
+    Exact,
+    /// The app id or title contains the value.
+    Substring,
+    /// The app id or title matches the value in full, with `*` and `?` wildcards.
+    Glob,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_selections() -> Vec<Selection> {
+    vec![Selection::Clipboard]
+}
+
+fn default_append_separator() -> String {
+    DEFAULT_APPEND_SEPARATOR.to_string()
+}
+
+fn default_transient_ttl_secs() -> u64 {
+    DEFAULT_TRANSIENT_TTL_SECS
+}
+
+fn default_tray_label_chars() -> usize {
+    MENU_LABEL_CHAR_LIMIT
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Default)]
 #[serde(tag = "kind", rename_all = "snake_case")]
 pub enum ActiveWindowBackend {
     #[default]
@@ -65,8 +868,11 @@ pub enum ActiveWindowBackend {
 
 #[derive(Debug, Clone)]
 pub struct LoadedConfig {
+    /// The most specific config file read: the user config if there is one.
     pub path: PathBuf,
     pub config: AppConfig,
+    /// Every file merged into `config`, system first.
+    pub layers: Vec<PathBuf>,
 }
 
 impl AppConfig {
@@ -74,68 +880,917 @@ impl AppConfig {
         let path = if let Some(path) = config_path_override {
             path
         } else {
-            resolve_default_config_path()?
+            resolve_user_config_path()?
         };
 
-        let raw = std::fs::read_to_string(&path)
-            .with_context(|| format!("failed to read config: {}", path.display()))?;
-        let config: AppConfig = serde_yaml::from_str(&raw)
-            .with_context(|| format!("failed to parse YAML config: {}", path.display()))?;
+        let raw = match std::fs::read_to_string(&path) {
+            Ok(raw) => raw,
+            Err(source) => return Err(Error::ConfigIo { path, source }),
+        };
+        let config: AppConfig = match serde_yaml::from_str(&raw) {
+            Ok(config) => config,
+            Err(source) => return Err(Error::ConfigParse { path, source }),
+        };
 
-        Ok(LoadedConfig { path, config })
+        Ok(LoadedConfig {
+            layers: vec![path.clone()],
+            path,
+            config,
+        })
+    }
+
+    /// Loads the system config (`--config`, or `/etc/slyboard/config.yaml` if present) with
+    /// the user config merged on top; see [`layers`] for the merge rules.
+    pub fn load_layered(config_path_override: Option<PathBuf>) -> Result<LoadedConfig> {
+        let merged = MergedConfig::load(config_path_override)?;
+        let config = merged.config()?;
+        Ok(LoadedConfig {
+            path: merged.layers.last().cloned().unwrap_or_default(),
+            config,
+            layers: merged.layers,
+        })
+    }
+
+    /// [`load_layered`](Self::load_layered), or `None` when there is no config file at all.
+    pub fn load_optional(config_path_override: Option<PathBuf>) -> Result<Option<LoadedConfig>> {
+        match Self::load_layered(config_path_override) {
+            Ok(loaded) => Ok(Some(loaded)),
+            Err(Error::ConfigNotFound { .. }) => Ok(None),
+            Err(err) => Err(err),
+        }
     }
 
     pub fn validate(&self) -> Result<()> {
         self.clipboard.validate()?;
+        self.tray.validate()?;
+        self.storage.validate()?;
+        self.logging.validate()?;
+        self.events.validate()?;
+        self.ocr.validate()?;
+        self.http.validate()?;
         Ok(())
     }
+
+    /// Suspicious-but-legal settings, for `slyboard validate-config` to print as warnings.
+    pub fn lint(&self) -> Vec<ValidationDiagnostic> {
+        self.lint_with_path(std::env::var_os("PATH").as_deref())
+    }
+
+    fn lint_with_path(&self, search_path: Option<&OsStr>) -> Vec<ValidationDiagnostic> {
+        let mut diagnostics = Vec::new();
+        for (index, value) in self.clipboard.active_window.blacklist.iter().enumerate() {
+            if value.trim().chars().count() == 1 {
+                diagnostics.push(ValidationDiagnostic::new(
+                    format!("clipboard.active_window.blacklist[{index}]"),
+                    format!("{value:?} is a single character and will match most windows"),
+                ));
+            }
+        }
+        if let ActiveWindowBackend::Command { program, .. } = &self.clipboard.active_window.backend
+        {
+            if !program_on_path(program, search_path) {
+                diagnostics.push(ValidationDiagnostic::new(
+                    "clipboard.active_window.backend.program",
+                    format!("{program} was not found on PATH"),
+                ));
+            }
+        }
+        if self.clipboard.restore_to_primary && self.clipboard.restore_target.is_some() {
+            diagnostics.push(ValidationDiagnostic::new(
+                "clipboard.restore_to_primary",
+                "ignored because clipboard.restore_target is set",
+            ));
+        }
+        if self.ocr.enabled && !program_on_path(&self.ocr.program, search_path) {
+            diagnostics.push(ValidationDiagnostic::new(
+                "ocr.program",
+                format!("{} was not found on PATH", self.ocr.program),
+            ));
+        }
+        diagnostics
+    }
+}
+
+/// A warning from [`AppConfig::lint`]: the config key it concerns and what looks wrong.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationDiagnostic {
+    pub key: String,
+    pub message: String,
+}
+
+impl ValidationDiagnostic {
+    fn new(key: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ValidationDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+impl TrayConfig {
+    fn validate(&self) -> Result<()> {
+        if self.label_chars == 0 {
+            return Err(Error::config_invalid(
+                "tray.label_chars must be greater than zero",
+            ));
+        }
+        if let Some(template) = &self.label_template {
+            HistoryTemplate::parse(template).map_err(|err| {
+                Error::config_invalid(format!("invalid tray.label_template: {err:#}"))
+            })?;
+        }
+        let Some(icon) = &self.icon else {
+            return Ok(());
+        };
+        if icon.trim().is_empty() {
+            return Err(Error::config_invalid("tray.icon cannot be empty"));
+        }
+        if !icon.contains('/') {
+            return Ok(());
+        }
+        let path = Path::new(icon);
+        if !path.is_absolute() {
+            return Err(Error::config_invalid(format!(
+                "tray.icon path must be absolute: {icon}"
+            )));
+        }
+        if !path.is_file() {
+            return Err(Error::config_invalid(format!(
+                "tray.icon file does not exist: {icon}"
+            )));
+        }
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("svg" | "png") => Ok(()),
+            _ => Err(Error::config_invalid(format!(
+                "tray.icon must be an .svg or .png file: {icon}"
+            ))),
+        }
+    }
 }
 
 impl ClipboardConfig {
     fn validate(&self) -> Result<()> {
+        self.backend.validate()?;
+        if self.selections.is_empty() {
+            return Err(Error::config_invalid(
+                "clipboard.selections must name at least one selection",
+            ));
+        }
+        for (index, selection) in self.selections.iter().enumerate() {
+            if self.selections[..index].contains(selection) {
+                return Err(Error::config_invalid(format!(
+                    "clipboard.selections lists {} twice",
+                    selection.as_str()
+                )));
+            }
+        }
         match &self.active_window.backend {
             ActiveWindowBackend::Command { program, .. } if program.trim().is_empty() => {
-                bail!("clipboard.active_window.command program cannot be empty");
+                return Err(Error::config_invalid(
+                    "clipboard.active_window.command program cannot be empty",
+                ));
             }
             _ => {}
         }
-        for (index, value) in self.active_window.blacklist.iter().enumerate() {
+        self.active_window
+            .validate_rules("blacklist", &self.active_window.blacklist)?;
+        self.active_window
+            .validate_rules("transient", &self.active_window.transient)?;
+        if self.active_window.transient_ttl_secs == 0 {
+            return Err(Error::config_invalid(
+                "clipboard.active_window.transient_ttl_secs must be greater than zero",
+            ));
+        }
+        for (index, expire) in self.active_window.expire_rules.iter().enumerate() {
+            expire.validate(index, self.active_window.match_mode)?;
+        }
+        if self.size_warning_bytes == Some(0) {
+            return Err(Error::config_invalid(
+                "clipboard.size_warning_bytes must be greater than zero",
+            ));
+        }
+        if self.max_entry_bytes == 0 {
+            return Err(Error::config_invalid(
+                "clipboard.max_entry_bytes must be greater than zero",
+            ));
+        }
+        for (index, value) in self.incognito_markers.iter().enumerate() {
+            if value.trim().is_empty() {
+                return Err(Error::config_invalid(format!(
+                    "clipboard.incognito_markers[{index}] cannot be empty"
+                )));
+            }
+        }
+        for (index, value) in self.ignore_prefixes.iter().enumerate() {
+            if value.is_empty() {
+                return Err(Error::config_invalid(format!(
+                    "clipboard.ignore_prefixes[{index}] cannot be empty"
+                )));
+            }
+        }
+        for (index, value) in self.ignore_globs.iter().enumerate() {
+            if value.is_empty() {
+                return Err(Error::config_invalid(format!(
+                    "clipboard.ignore_globs[{index}] cannot be empty"
+                )));
+            }
+            parse_glob(value).map_err(|reason| {
+                Error::config_invalid(format!("clipboard.ignore_globs[{index}] {reason}"))
+            })?;
+        }
+        for (index, value) in self.paused_workspaces.iter().enumerate() {
             if value.trim().is_empty() {
-                bail!("clipboard.active_window.blacklist[{index}] cannot be empty");
+                return Err(Error::config_invalid(format!(
+                    "clipboard.paused_workspaces[{index}] cannot be empty"
+                )));
             }
         }
+        if self.notifications.preview_chars == 0 {
+            return Err(Error::config_invalid(
+                "clipboard.notifications.preview_chars must be greater than zero",
+            ));
+        }
+        if self.text.preview_limit_bytes == Some(0) {
+            return Err(Error::config_invalid(
+                "clipboard.text.preview_limit_bytes must be greater than zero",
+            ));
+        }
+        for (index, retention) in self.retention.iter().enumerate() {
+            retention.validate(index, self.active_window.match_mode)?;
+        }
         Ok(())
     }
 }
 
-fn resolve_default_config_path() -> Result<PathBuf> {
-    let cwd_file = std::env::current_dir()?.join("slyboard.yaml");
+/// `~/.config/slyboard/config.yaml` (`~/Library/Application Support/slyboard` on macOS,
+/// `%APPDATA%\slyboard` on Windows), where `slyboard setup` writes the user config.
+pub fn default_user_config_path() -> Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .ok_or(Error::MissingDirectory { kind: "config" })?
+        .join("slyboard")
+        .join("config.yaml"))
+}
+
+/// The user config: `./slyboard.yaml`, else `~/.config/slyboard/config.yaml`.
+pub(crate) fn resolve_user_config_path() -> Result<PathBuf> {
+    let cwd_file = std::env::current_dir()
+        .map_err(|source| Error::ConfigIo {
+            path: PathBuf::from("."),
+            source,
+        })?
+        .join("slyboard.yaml");
     if cwd_file.exists() {
         return Ok(cwd_file);
     }
 
-    let home_config = dirs::config_dir()
-        .context("unable to resolve config directory from environment")?
-        .join("slyboard")
-        .join("config.yaml");
+    let home_config = default_user_config_path()?;
     if home_config.exists() {
         return Ok(home_config);
     }
 
-    bail!(
-        "no config file found; expected one of:\n- {}\n- {}",
-        cwd_file.display(),
-        home_config.display()
-    );
+    Err(Error::ConfigNotFound {
+        candidates: vec![cwd_file, home_config],
+    })
 }
 
 #[cfg(test)]
 mod tests {
-    use super::AppConfig;
+    use super::{
+        state_path_in, AppConfig, ClipboardBackend, LegacyOrder, TrayBackend, WindowMatchMode,
+        DEFAULT_MAX_ENTRY_BYTES,
+    };
+    use crate::clipboard::dedup::DedupScope;
+    use crate::clipboard::writer::{RestoreTarget, Selection};
+    use crate::error::Error;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn state_files_fall_back_to_the_cache_dir_without_a_state_dir() {
+        let file = Path::new("events.jsonl");
+        assert_eq!(
+            state_path_in(
+                Some(PathBuf::from("/home/alice/.local/state")),
+                Some(PathBuf::from("/home/alice/.cache")),
+                file
+            )
+            .unwrap(),
+            Path::new("/home/alice/.local/state/slyboard/events.jsonl")
+        );
+        // macOS and Windows
+        assert_eq!(
+            state_path_in(
+                None,
+                Some(PathBuf::from("/Users/alice/Library/Caches")),
+                file
+            )
+            .unwrap(),
+            Path::new("/Users/alice/Library/Caches/slyboard/events.jsonl")
+        );
+        assert!(matches!(
+            state_path_in(None, None, file),
+            Err(Error::MissingDirectory { kind: "state" })
+        ));
+    }
 
     #[test]
     fn validate_accepts_empty_config() {
         let cfg = AppConfig::default();
         cfg.validate().expect("empty config should be valid");
     }
+
+    #[test]
+    fn lint_warns_about_suspicious_settings() {
+        let dir = std::env::temp_dir().join(format!("slyboard-test-lint-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("xdotool"), "").unwrap();
+
+        let cfg: AppConfig = serde_yaml::from_str(
+            "clipboard:\n  active_window:\n    backend:\n      kind: command\n      program: xdotool\n    blacklist:\n      - ' k '\n      - keepassxc\n",
+        )
+        .expect("config should parse");
+        cfg.validate().expect("lint warnings are not errors");
+        let diagnostics = cfg.lint_with_path(Some(dir.as_os_str()));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "clipboard.active_window.blacklist[0]: \" k \" is a single character and will match most windows"
+        );
+
+        let missing = cfg.lint_with_path(Some(std::ffi::OsStr::new("/nonexistent")));
+        assert_eq!(missing.len(), 2);
+        assert_eq!(missing[1].key, "clipboard.active_window.backend.program");
+        assert_eq!(missing[1].message, "xdotool was not found on PATH");
+        assert_eq!(cfg.lint_with_path(None).len(), 2);
+
+        assert!(AppConfig::default().lint().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ocr_defaults_to_tesseract_and_is_off() {
+        let cfg: AppConfig = serde_yaml::from_str("ocr:\n  enabled: true\n").unwrap();
+        assert!(cfg.ocr.enabled);
+        assert_eq!(cfg.ocr.program, "tesseract");
+        assert_eq!(cfg.ocr.args, ["stdin", "stdout"]);
+        assert!(!AppConfig::default().ocr.enabled);
+        assert_eq!(
+            cfg.lint_with_path(None)[0].to_string(),
+            "ocr.program: tesseract was not found on PATH"
+        );
+
+        let cfg: AppConfig = serde_yaml::from_str("ocr:\n  program: ' '\n").unwrap();
+        assert_eq!(
+            cfg.validate().unwrap_err().to_string(),
+            "ocr.program cannot be empty"
+        );
+    }
+
+    #[test]
+    fn resolves_log_file_under_the_state_dir() {
+        let cfg: AppConfig =
+            serde_yaml::from_str("logging:\n  file: slyboard.log\n").expect("config should parse");
+        assert_eq!(cfg.logging.max_bytes(), 5 * 1024 * 1024);
+        assert!(!cfg.logging.debug);
+        let resolved = cfg.logging.resolved_file().unwrap().unwrap();
+        assert!(resolved.ends_with("slyboard/slyboard.log"));
+        assert!(resolved.is_absolute());
+
+        let cfg: AppConfig =
+            serde_yaml::from_str("logging:\n  file: /var/log/slyboard.log\n  max_size_mb: 0\n")
+                .expect("config should parse");
+        assert_eq!(
+            cfg.logging.resolved_file().unwrap(),
+            Some(std::path::PathBuf::from("/var/log/slyboard.log"))
+        );
+        assert_eq!(
+            cfg.validate().unwrap_err().to_string(),
+            "logging.max_size_mb must be greater than zero"
+        );
+        assert_eq!(AppConfig::default().logging.resolved_file().unwrap(), None);
+    }
+
+    #[test]
+    fn event_log_is_off_by_default_and_lives_in_the_state_dir() {
+        assert_eq!(AppConfig::default().events.resolved_file().unwrap(), None);
+        let cfg: AppConfig =
+            serde_yaml::from_str("events:\n  enabled: true\n").expect("config should parse");
+        cfg.validate().expect("events config should be valid");
+        assert!(!cfg.events.log_content);
+        assert_eq!(cfg.events.max_bytes(), 10 * 1024 * 1024);
+        let resolved = cfg.events.resolved_file().unwrap().unwrap();
+        assert!(resolved.ends_with("slyboard/events.jsonl"));
+
+        let cfg: AppConfig = serde_yaml::from_str("events:\n  enabled: true\n  max_size_mb: 0\n")
+            .expect("config should parse");
+        assert_eq!(
+            cfg.validate().unwrap_err().to_string(),
+            "events.max_size_mb must be greater than zero"
+        );
+    }
+
+    #[test]
+    fn history_limit_defaults_and_must_be_positive() {
+        assert_eq!(
+            AppConfig::default().storage.history_limit,
+            crate::clipboard::DEFAULT_HISTORY_LIMIT
+        );
+        let cfg: AppConfig =
+            serde_yaml::from_str("storage:\n  history_limit: 200\n").expect("config should parse");
+        assert_eq!(cfg.storage.history_limit, 200);
+
+        let cfg: AppConfig =
+            serde_yaml::from_str("storage:\n  history_limit: 0\n").expect("config should parse");
+        assert_eq!(
+            cfg.validate().unwrap_err().to_string(),
+            "storage.history_limit must be greater than zero"
+        );
+    }
+
+    #[test]
+    fn parses_window_match_mode_into_rules() {
+        let cfg: AppConfig = serde_yaml::from_str(
+            "clipboard:\n  active_window:\n    match_mode: glob\n    blacklist:\n      - 'org.keepassxc.*'\n      - ' '\n",
+        )
+        .expect("config should parse");
+        assert_eq!(
+            cfg.clipboard.active_window.match_mode,
+            WindowMatchMode::Glob
+        );
+        assert_eq!(cfg.clipboard.active_window.blacklist_rules().len(), 1);
+        assert_eq!(
+            cfg.validate().unwrap_err().to_string(),
+            "clipboard.active_window.blacklist[1] cannot be empty"
+        );
+        assert_eq!(
+            AppConfig::default().clipboard.active_window.match_mode,
+            WindowMatchMode::Mixed
+        );
+        assert!(serde_yaml::from_str::<AppConfig>(
+            "clipboard:\n  active_window:\n    match_mode: regex\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parses_transient_sources_with_default_ttl() {
+        let cfg: AppConfig = serde_yaml::from_str(
+            "clipboard:\n  active_window:\n    transient:\n      - keepassxc\n",
+        )
+        .expect("config should parse");
+        assert_eq!(cfg.clipboard.active_window.transient, vec!["keepassxc"]);
+        assert_eq!(
+            cfg.clipboard.active_window.transient_ttl_secs,
+            super::DEFAULT_TRANSIENT_TTL_SECS
+        );
+        cfg.validate().expect("transient config should be valid");
+    }
+
+    #[test]
+    fn reports_read_parse_and_validation_failures() {
+        let dir = std::env::temp_dir().join(format!("slyboard-test-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yaml");
+
+        let missing = AppConfig::load(Some(path.clone())).unwrap_err();
+        assert!(matches!(missing, Error::ConfigIo { path: ref p, .. } if *p == path));
+
+        std::fs::write(&path, "tray: [").unwrap();
+        let parse = AppConfig::load(Some(path.clone())).unwrap_err();
+        assert!(matches!(parse, Error::ConfigParse { .. }));
+        assert_eq!(
+            parse.to_string(),
+            format!("failed to parse YAML config: {}", path.display())
+        );
+
+        std::fs::write(&path, "tray:\n  label_chars: 0\n").unwrap();
+        let loaded = AppConfig::load(Some(path)).unwrap();
+        match loaded.config.validate() {
+            Err(Error::ConfigInvalid { reason }) => {
+                assert_eq!(reason, "tray.label_chars must be greater than zero")
+            }
+            other => panic!("expected ConfigInvalid, got {other:?}"),
+        }
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn rejects_empty_or_invalid_ignore_patterns() {
+        let cfg: AppConfig = serde_yaml::from_str("clipboard:\n  ignore_prefixes:\n    - \"\"\n")
+            .expect("config should parse");
+        assert!(cfg.validate().is_err());
+
+        let cfg: AppConfig = serde_yaml::from_str("clipboard:\n  ignore_globs:\n    - \"\"\n")
+            .expect("config should parse");
+        assert!(cfg.validate().is_err());
+
+        let cfg: AppConfig = serde_yaml::from_str("clipboard:\n  ignore_globs: [\"sk-[a\"]\n")
+            .expect("config should parse");
+        assert_eq!(
+            cfg.validate().unwrap_err().to_string(),
+            "clipboard.ignore_globs[0] is not a valid glob (invalid range pattern)"
+        );
+    }
+
+    #[test]
+    fn reads_paused_workspaces_by_name_or_id() {
+        let cfg: AppConfig =
+            serde_yaml::from_str("clipboard:\n  paused_workspaces: [9, \"private\"]\n")
+                .expect("config should parse");
+        assert_eq!(cfg.clipboard.paused_workspaces, ["9", "private"]);
+        assert!(cfg.validate().is_ok());
+
+        let cfg: AppConfig = serde_yaml::from_str("clipboard:\n  paused_workspaces: [\" \"]\n")
+            .expect("config should parse");
+        assert!(cfg.validate().is_err());
+    }
+
+    #[test]
+    fn text_previews_are_off_unless_limited() {
+        assert_eq!(
+            AppConfig::default().clipboard.text.preview_limit_bytes,
+            None
+        );
+        let cfg: AppConfig =
+            serde_yaml::from_str("clipboard:\n  text:\n    preview_limit_bytes: 65536\n")
+                .expect("config should parse");
+        assert_eq!(cfg.clipboard.text.preview_limit_bytes, Some(65536));
+        assert!(cfg.validate().is_ok());
+
+        let cfg: AppConfig =
+            serde_yaml::from_str("clipboard:\n  text:\n    preview_limit_bytes: 0\n")
+                .expect("config should parse");
+        assert!(cfg.validate().is_err());
+    }
+
+    #[test]
+    fn incognito_markers_extend_or_replace_defaults() {
+        let cfg: AppConfig = serde_yaml::from_str(
+            "clipboard:\n  incognito_policy: skip\n  incognito_markers:\n    - Privates Surfen\n",
+        )
+        .expect("config should parse");
+        assert_eq!(cfg.clipboard.incognito_policy, super::IncognitoPolicy::Skip);
+        assert_eq!(
+            cfg.clipboard.incognito_title_markers(),
+            vec![
+                "Private Browsing",
+                "(Incognito)",
+                "InPrivate",
+                "Privates Surfen"
+            ]
+        );
+
+        let cfg: AppConfig = serde_yaml::from_str(
+            "clipboard:\n  incognito_default_markers: false\n  incognito_markers:\n    - Privat\n",
+        )
+        .expect("config should parse");
+        assert_eq!(cfg.clipboard.incognito_title_markers(), vec!["Privat"]);
+    }
+
+    #[test]
+    fn parses_and_validates_retention_rules() {
+        let cfg: AppConfig = serde_yaml::from_str(
+            "clipboard:\n  retention:\n    - app: kitty\n      max_entries: 5\n    - app: firefox\n      ttl_secs: 86400\n",
+        )
+        .expect("config should parse");
+        cfg.validate().expect("retention config should be valid");
+        assert_eq!(cfg.clipboard.retention[0].max_entries, Some(5));
+        assert_eq!(cfg.clipboard.retention[1].ttl_secs, Some(86400));
+        assert!(!cfg.clipboard.retention_policy().is_empty());
+        assert!(AppConfig::default().clipboard.retention_policy().is_empty());
+
+        for (rule, error) in [
+            (
+                "app: kitty",
+                "clipboard.retention[0] needs max_entries or ttl_secs",
+            ),
+            (
+                "app: ' '\n      ttl_secs: 60",
+                "clipboard.retention[0].app cannot be empty",
+            ),
+            (
+                "app: kitty\n      ttl_secs: 0",
+                "clipboard.retention[0].ttl_secs must be greater than zero",
+            ),
+        ] {
+            let cfg: AppConfig =
+                serde_yaml::from_str(&format!("clipboard:\n  retention:\n    - {rule}\n"))
+                    .expect("config should parse");
+            assert_eq!(cfg.validate().unwrap_err().to_string(), error);
+        }
+    }
+
+    #[test]
+    fn parses_and_validates_expire_rules() {
+        let cfg: AppConfig = serde_yaml::from_str(
+            "clipboard:\n  active_window:\n    match_mode: substring\n    expire_rules:\n      - match: Online Banking\n        ttl_secs: 300\n",
+        )
+        .expect("config should parse");
+        cfg.validate().expect("expire rules should be valid");
+        let rules = cfg.clipboard.active_window.expire_rules();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].rule.pattern(), "online banking");
+        assert_eq!(rules[0].ttl.as_secs(), 300);
+
+        for (rule, error) in [
+            (
+                "match: ' '\n        ttl_secs: 60",
+                "clipboard.active_window.expire_rules[0].match cannot be empty",
+            ),
+            (
+                "match: bank\n        ttl_secs: 0",
+                "clipboard.active_window.expire_rules[0].ttl_secs must be greater than zero; use the blacklist to keep nothing",
+            ),
+        ] {
+            let cfg: AppConfig = serde_yaml::from_str(&format!(
+                "clipboard:\n  active_window:\n    expire_rules:\n      - {rule}\n"
+            ))
+            .expect("config should parse");
+            assert_eq!(cfg.validate().unwrap_err().to_string(), error);
+        }
+    }
+
+    #[test]
+    fn parses_and_validates_http_listen_address() {
+        assert_eq!(AppConfig::default().http.listen_addr().unwrap(), None);
+        let cfg: AppConfig =
+            serde_yaml::from_str("http:\n  listen: \"127.0.0.1:7878\"\n  token: s3cret\n")
+                .expect("config should parse");
+        cfg.validate().expect("http config should be valid");
+        assert_eq!(
+            cfg.http.listen_addr().unwrap(),
+            Some("127.0.0.1:7878".parse().unwrap())
+        );
+
+        for (http, error) in [
+            (
+                "listen: localhost",
+                "http.listen must be an address and port like 127.0.0.1:7878, got \"localhost\"",
+            ),
+            (
+                "listen: \"0.0.0.0:7878\"",
+                "http.listen 0.0.0.0:7878 is not a loopback address; set http.allow_remote to serve it",
+            ),
+            ("token: ''", "http.token cannot be empty"),
+        ] {
+            let cfg: AppConfig = serde_yaml::from_str(&format!("http:\n  {http}\n"))
+                .expect("config should parse");
+            assert_eq!(cfg.validate().unwrap_err().to_string(), error);
+        }
+        let remote: AppConfig =
+            serde_yaml::from_str("http:\n  listen: \"0.0.0.0:7878\"\n  allow_remote: true\n")
+                .expect("config should parse");
+        remote
+            .validate()
+            .expect("remote listening is allowed explicitly");
+    }
+
+    #[test]
+    fn size_warning_is_off_by_default_and_positive() {
+        assert_eq!(AppConfig::default().clipboard.size_warning_bytes, None);
+        let cfg: AppConfig =
+            serde_yaml::from_str("clipboard:\n  size_warning_bytes: 104857600\n").unwrap();
+        cfg.validate().expect("size warning should be valid");
+        assert_eq!(cfg.clipboard.size_warning_bytes, Some(100 * 1024 * 1024));
+        let zero: AppConfig =
+            serde_yaml::from_str("clipboard:\n  size_warning_bytes: 0\n").unwrap();
+        assert_eq!(
+            zero.validate().unwrap_err().to_string(),
+            "clipboard.size_warning_bytes must be greater than zero"
+        );
+    }
+
+    #[test]
+    fn max_entry_size_defaults_and_must_be_positive() {
+        assert_eq!(
+            AppConfig::default().clipboard.max_entry_bytes,
+            DEFAULT_MAX_ENTRY_BYTES
+        );
+        let cfg: AppConfig =
+            serde_yaml::from_str("clipboard:\n  max_entry_bytes: 1048576\n").unwrap();
+        cfg.validate().expect("max entry size should be valid");
+        assert_eq!(cfg.clipboard.max_entry_bytes, 1024 * 1024);
+        let zero: AppConfig = serde_yaml::from_str("clipboard:\n  max_entry_bytes: 0\n").unwrap();
+        assert_eq!(
+            zero.validate().unwrap_err().to_string(),
+            "clipboard.max_entry_bytes must be greater than zero"
+        );
+    }
+
+    #[test]
+    fn storage_pruning_defaults_off() {
+        assert!(!AppConfig::default().storage.prune_on_load);
+        let cfg: AppConfig =
+            serde_yaml::from_str("storage:\n  prune_on_load: true\n").expect("config should parse");
+        assert!(cfg.storage.prune_on_load);
+    }
+
+    #[test]
+    fn dedup_ignores_trailing_whitespace_by_default() {
+        assert!(
+            AppConfig::default()
+                .clipboard
+                .dedup
+                .ignore_trailing_whitespace
+        );
+        let cfg: AppConfig =
+            serde_yaml::from_str("clipboard:\n  dedup:\n    ignore_trailing_whitespace: false\n")
+                .expect("config should parse");
+        assert!(!cfg.clipboard.dedup.ignore_trailing_whitespace);
+        assert!(cfg.clipboard.dedup.enabled);
+    }
+
+    #[test]
+    fn clipboard_backend_is_one_or_a_list() {
+        let backends = |yaml: &str| {
+            serde_yaml::from_str::<AppConfig>(yaml)
+                .map(|cfg| cfg.clipboard.backend.iter().collect::<Vec<_>>())
+        };
+        assert_eq!(
+            AppConfig::default().clipboard.backend.names(),
+            "gtk",
+            "default"
+        );
+        assert_eq!(
+            backends("clipboard:\n  backend: wayland\n").unwrap(),
+            [ClipboardBackend::Wayland]
+        );
+        assert_eq!(
+            backends("clipboard:\n  backend: [gtk, wayland]\n").unwrap(),
+            [ClipboardBackend::Gtk, ClipboardBackend::Wayland]
+        );
+        assert!(backends("clipboard:\n  backend: x11\n").is_err());
+
+        for (yaml, message) in [
+            ("clipboard:\n  backend: []\n", "at least one"),
+            ("clipboard:\n  backend: [gtk, gtk]\n", "gtk twice"),
+        ] {
+            let cfg: AppConfig = serde_yaml::from_str(yaml).expect("config should parse");
+            let err = cfg.validate().unwrap_err().to_string();
+            assert!(err.contains(message), "{err}");
+        }
+    }
+
+    #[test]
+    fn dedup_scope_defaults_to_all_history() {
+        assert_eq!(AppConfig::default().clipboard.dedup.scope, DedupScope::All);
+        let scope = |yaml: &str| {
+            serde_yaml::from_str::<AppConfig>(&format!("clipboard:\n  dedup:\n    scope: {yaml}\n"))
+                .map(|cfg| cfg.clipboard.dedup.scope)
+        };
+        assert_eq!(scope("recent:10").unwrap(), DedupScope::Recent(10));
+        assert_eq!(
+            scope("window:1h").unwrap(),
+            DedupScope::Window(std::time::Duration::from_secs(3600))
+        );
+        let err = scope("recent:0").unwrap_err().to_string();
+        assert!(err.contains("recent needs a number of entries"), "{err}");
+    }
+
+    #[test]
+    fn notification_switches_default_on() {
+        let notifications = |yaml: &str| {
+            serde_yaml::from_str::<AppConfig>(yaml)
+                .unwrap()
+                .clipboard
+                .notifications
+        };
+        let defaults = notifications("{}");
+        assert!(defaults.history_limit && defaults.on_capture);
+        assert!(
+            notifications("clipboard:\n  notifications:\n    preview_chars: 10\n").history_limit
+        );
+        assert!(
+            !notifications("clipboard:\n  notifications:\n    history_limit: false\n")
+                .history_limit
+        );
+        let quiet = notifications("clipboard:\n  notifications:\n    on_capture: false\n");
+        assert!(!quiet.on_capture && quiet.history_limit);
+    }
+
+    #[test]
+    fn selections_and_restore_target_default_to_the_clipboard() {
+        let clipboard = |yaml: &str| serde_yaml::from_str::<AppConfig>(yaml).unwrap().clipboard;
+        let defaults = clipboard("{}");
+        assert_eq!(defaults.selections, [Selection::Clipboard]);
+        assert_eq!(defaults.restore_target(), RestoreTarget::Clipboard);
+
+        let both = clipboard("clipboard:\n  selections: [clipboard, primary]\n");
+        assert_eq!(both.selections, [Selection::Clipboard, Selection::Primary]);
+        assert_eq!(
+            clipboard("clipboard:\n  restore_to_primary: true\n").restore_target(),
+            RestoreTarget::Both
+        );
+        assert_eq!(
+            clipboard("clipboard:\n  restore_to_primary: true\n  restore_target: primary\n")
+                .restore_target(),
+            RestoreTarget::Primary
+        );
+
+        for (yaml, message) in [
+            ("selections: []", "at least one selection"),
+            ("selections: [primary, primary]", "lists primary twice"),
+        ] {
+            let cfg: AppConfig = serde_yaml::from_str(&format!("clipboard:\n  {yaml}\n")).unwrap();
+            let err = cfg.validate().unwrap_err().to_string();
+            assert!(err.contains(message), "{err}");
+        }
+    }
+
+    #[test]
+    fn dedup_can_be_turned_off() {
+        assert!(AppConfig::default().clipboard.dedup.enabled);
+        let cfg: AppConfig = serde_yaml::from_str("clipboard:\n  dedup:\n    enabled: false\n")
+            .expect("config should parse");
+        assert!(!cfg.clipboard.dedup.enabled);
+        assert!(cfg.clipboard.dedup.ignore_trailing_whitespace);
+    }
+
+    #[test]
+    fn trash_on_clear_defaults_off() {
+        assert!(!AppConfig::default().storage.trash_on_clear);
+        let cfg: AppConfig = serde_yaml::from_str("storage:\n  trash_on_clear: true\n")
+            .expect("config should parse");
+        assert!(cfg.storage.trash_on_clear);
+    }
+
+    #[test]
+    fn legacy_order_defaults_to_newest_first() {
+        assert_eq!(
+            AppConfig::default().storage.legacy_order,
+            LegacyOrder::NewestFirst
+        );
+        let cfg: AppConfig = serde_yaml::from_str("storage:\n  legacy_order: newest_last\n")
+            .expect("config should parse");
+        assert_eq!(cfg.storage.legacy_order, LegacyOrder::NewestLast);
+        assert!(serde_yaml::from_str::<AppConfig>("storage:\n  legacy_order: oldest\n").is_err());
+    }
+
+    #[test]
+    fn append_separator_defaults_to_a_newline() {
+        assert_eq!(AppConfig::default().clipboard.append_separator, "\n");
+        let cfg: AppConfig =
+            serde_yaml::from_str("clipboard:\n  append_separator: \"\\n---\\n\"\n")
+                .expect("config should parse");
+        assert_eq!(cfg.clipboard.append_separator, "\n---\n");
+    }
+
+    #[test]
+    fn tray_backend_parses_and_rejects_unknown_values() {
+        assert_eq!(AppConfig::default().tray.backend, TrayBackend::AppIndicator);
+        let cfg: AppConfig =
+            serde_yaml::from_str("tray:\n  backend: none\n").expect("config should parse");
+        assert_eq!(cfg.tray.backend, TrayBackend::None);
+        let cfg: AppConfig =
+            serde_yaml::from_str("tray:\n  backend: appindicator\n").expect("config should parse");
+        assert_eq!(cfg.tray.backend, TrayBackend::AppIndicator);
+        let cfg: AppConfig =
+            serde_yaml::from_str("tray:\n  backend: ksni\n").expect("config should parse");
+        assert_eq!(cfg.tray.backend, TrayBackend::Ksni);
+        assert!(serde_yaml::from_str::<AppConfig>("tray:\n  backend: systray\n").is_err());
+    }
+
+    #[test]
+    fn validates_tray_label_template() {
+        let cfg: AppConfig =
+            serde_yaml::from_str("tray:\n  label_template: \"{time} · {app} · {preview}\"\n")
+                .unwrap();
+        cfg.validate().expect("label template should be valid");
+        assert_eq!(cfg.tray.label_chars, 70);
+
+        let cfg: AppConfig = serde_yaml::from_str("tray:\n  label_template: \"{nope}\"\n").unwrap();
+        assert!(cfg.validate().is_err());
+        let cfg: AppConfig = serde_yaml::from_str("tray:\n  label_chars: 0\n").unwrap();
+        assert!(cfg.validate().is_err());
+    }
+
+    #[test]
+    fn validates_tray_icon_paths() {
+        let cfg: AppConfig = serde_yaml::from_str("tray:\n  icon: edit-paste\n").unwrap();
+        cfg.validate()
+            .expect("icon names are not checked against the theme");
+
+        for icon in ["\"\"", "icons/slyboard.svg", "/nonexistent/slyboard.svg"] {
+            let cfg: AppConfig = serde_yaml::from_str(&format!("tray:\n  icon: {icon}\n")).unwrap();
+            assert!(cfg.validate().is_err(), "{icon} should be rejected");
+        }
+
+        let dir = std::env::temp_dir().join(format!("slyboard-test-icon-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let svg = dir.join("custom.svg");
+        let txt = dir.join("custom.txt");
+        std::fs::write(&svg, "<svg/>").unwrap();
+        std::fs::write(&txt, "not an icon").unwrap();
+        let icon_config = |path: &Path| -> AppConfig {
+            serde_yaml::from_str(&format!("tray:\n  icon: {}\n", path.display())).unwrap()
+        };
+        assert!(icon_config(&svg).validate().is_ok());
+        assert!(icon_config(&txt).validate().is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,130 @@
+//! Settings a single invocation can override. Each is resolved here once: the command line
+//! wins over the environment, which wins over the config, which falls back to the default.
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use crate::clipboard::dedup::DedupSettings;
+use crate::clipboard::DEFAULT_HISTORY_LIMIT;
+use crate::config::AppConfig;
+use crate::error::{Error, Result};
+
+/// Overrides `storage.history_limit`.
+pub const HISTORY_LIMIT_ENV: &str = "SLYBOARD_HISTORY_LIMIT";
+
+/// Values given on the command line, e.g. `--history-limit`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SettingOverrides {
+    pub history_limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EffectiveSettings {
+    /// How many entries history is loaded with and trimmed to.
+    pub history_limit: usize,
+    /// `clipboard.text.preview_limit_bytes`; recorded text longer than this keeps only a
+    /// preview in history.
+    pub text_preview_limit: Option<usize>,
+    /// `clipboard.dedup`, which history is recorded and its journal replayed with.
+    pub dedup: DedupSettings,
+}
+
+impl EffectiveSettings {
+    pub fn resolve(overrides: SettingOverrides, config: Option<&AppConfig>) -> Result<Self> {
+        resolve_with_env(overrides, config, |name| std::env::var_os(name))
+    }
+
+    /// Resolves against the layered config, or the defaults when there is no config file, for
+    /// commands that work without one.
+    pub fn load(
+        config_path_override: Option<PathBuf>,
+        overrides: SettingOverrides,
+    ) -> Result<Self> {
+        let loaded = AppConfig::load_optional(config_path_override)?;
+        Self::resolve(overrides, loaded.as_ref().map(|loaded| &loaded.config))
+    }
+}
+
+fn resolve_with_env(
+    overrides: SettingOverrides,
+    config: Option<&AppConfig>,
+    env: impl Fn(&str) -> Option<OsString>,
+) -> Result<EffectiveSettings> {
+    let from_env = env(HISTORY_LIMIT_ENV)
+        .map(|value| parse_history_limit(&value))
+        .transpose()?;
+    let history_limit = overrides
+        .history_limit
+        .or(from_env)
+        .or(config.map(|config| config.storage.history_limit))
+        .unwrap_or(DEFAULT_HISTORY_LIMIT);
+    if history_limit == 0 {
+        return Err(Error::config_invalid(
+            "the history limit must be greater than zero",
+        ));
+    }
+    Ok(EffectiveSettings {
+        history_limit,
+        text_preview_limit: config.and_then(|config| config.clipboard.text.preview_limit_bytes),
+        dedup: config.map_or_else(DedupSettings::default, |config| {
+            config.clipboard.dedup.settings()
+        }),
+    })
+}
+
+fn parse_history_limit(value: &OsString) -> Result<usize> {
+    value
+        .to_str()
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|limit| *limit > 0)
+        .ok_or_else(|| {
+            Error::config_invalid(format!(
+                "{HISTORY_LIMIT_ENV} must be a positive number, got {value:?}"
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_with_env, SettingOverrides, HISTORY_LIMIT_ENV};
+    use crate::clipboard::DEFAULT_HISTORY_LIMIT;
+    use crate::config::AppConfig;
+    use std::ffi::OsString;
+
+    fn history_limit(cli: Option<usize>, env: Option<&str>, config: Option<usize>) -> usize {
+        let config = config.map(|limit| {
+            let mut config = AppConfig::default();
+            config.storage.history_limit = limit;
+            config
+        });
+        let overrides = SettingOverrides { history_limit: cli };
+        resolve_with_env(overrides, config.as_ref(), |name| {
+            assert_eq!(name, HISTORY_LIMIT_ENV);
+            env.map(OsString::from)
+        })
+        .unwrap()
+        .history_limit
+    }
+
+    #[test]
+    fn command_line_beats_environment_beats_config_beats_default() {
+        assert_eq!(history_limit(Some(5), Some("7"), Some(9)), 5);
+        assert_eq!(history_limit(None, Some("7"), Some(9)), 7);
+        assert_eq!(history_limit(None, None, Some(9)), 9);
+        assert_eq!(history_limit(None, None, None), DEFAULT_HISTORY_LIMIT);
+    }
+
+    #[test]
+    fn rejects_unusable_environment_values() {
+        for value in ["0", "-3", "many", ""] {
+            let err = resolve_with_env(SettingOverrides::default(), None, |_| {
+                Some(OsString::from(value))
+            })
+            .unwrap_err();
+            assert!(
+                err.to_string().contains(HISTORY_LIMIT_ENV),
+                "{value:?}: {err}"
+            );
+        }
+    }
+}
@@ -0,0 +1,392 @@
+//! Layered config: a system config (`--config`, or `/etc/slyboard/config.yaml`) with the user
+//! config merged on top. Scalars from a later layer win, lists are concatenated unless the later
+//! list is tagged `!replace`, and tagged values such as `clipboard.active_window.backend`
+//! (mappings with a `kind`) are replaced whole, since their fields only make sense together.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde_yaml::{Mapping, Value};
+
+use crate::config::{resolve_user_config_path, AppConfig};
+use crate::error::{Error, Result};
+
+pub const SYSTEM_CONFIG_PATH: &str = "/etc/slyboard/config.yaml";
+const REPLACE_TAG: &str = "replace";
+
+/// One parsed config file.
+#[derive(Debug, Clone)]
+pub struct ConfigLayer {
+    pub path: PathBuf,
+    value: Value,
+}
+
+impl ConfigLayer {
+    pub fn read(path: PathBuf) -> Result<Self> {
+        let raw = match std::fs::read_to_string(&path) {
+            Ok(raw) => raw,
+            Err(source) => return Err(Error::ConfigIo { path, source }),
+        };
+        Self::parse(path, &raw)
+    }
+
+    /// Parses `raw` and checks it as a config of its own, so errors name the file they are in.
+    pub fn parse(path: PathBuf, raw: &str) -> Result<Self> {
+        let value = match serde_yaml::from_str(raw) {
+            // An empty file sets nothing.
+            Ok(Value::Null) => Value::Mapping(Mapping::new()),
+            Ok(value) => value,
+            Err(source) => return Err(Error::ConfigParse { path, source }),
+        };
+        let layer = Self { path, value };
+        if let Err(source) = serde_yaml::from_value::<AppConfig>(Node::new(&layer.value, 0).value())
+        {
+            return Err(Error::ConfigParse {
+                path: layer.path,
+                source,
+            });
+        }
+        Ok(layer)
+    }
+}
+
+/// A config value in which every leaf remembers the layer that set it.
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Map(Vec<(Value, Node)>),
+    List(Vec<Node>),
+    Leaf { value: Value, layer: usize },
+}
+
+impl Node {
+    fn new(value: &Value, layer: usize) -> Self {
+        match value {
+            Value::Tagged(tagged) if tagged.tag == REPLACE_TAG => Self::new(&tagged.value, layer),
+            Value::Mapping(mapping) if !is_tagged_enum(mapping) => Node::Map(
+                mapping
+                    .iter()
+                    .map(|(key, value)| (key.clone(), Self::new(value, layer)))
+                    .collect(),
+            ),
+            Value::Sequence(items) => {
+                Node::List(items.iter().map(|item| Self::new(item, layer)).collect())
+            }
+            value => Node::Leaf {
+                value: value.clone(),
+                layer,
+            },
+        }
+    }
+
+    /// Merges `overlay`, from `layer`, on top of `self`.
+    fn merge(&mut self, overlay: &Value, layer: usize) {
+        match (&mut *self, overlay) {
+            (_, Value::Tagged(tagged)) if tagged.tag == REPLACE_TAG => {
+                *self = Self::new(&tagged.value, layer);
+            }
+            (Node::Map(entries), Value::Mapping(mapping)) if !is_tagged_enum(mapping) => {
+                for (key, value) in mapping {
+                    match entries.iter_mut().find(|(existing, _)| existing == key) {
+                        Some((_, node)) => node.merge(value, layer),
+                        None => entries.push((key.clone(), Self::new(value, layer))),
+                    }
+                }
+            }
+            (Node::List(items), Value::Sequence(overlay_items)) => {
+                items.extend(overlay_items.iter().map(|item| Self::new(item, layer)));
+            }
+            _ => *self = Self::new(overlay, layer),
+        }
+    }
+
+    fn value(&self) -> Value {
+        match self {
+            Node::Map(entries) => Value::Mapping(
+                entries
+                    .iter()
+                    .map(|(key, node)| (key.clone(), node.value()))
+                    .collect::<Mapping>(),
+            ),
+            Node::List(items) => Value::Sequence(items.iter().map(Node::value).collect()),
+            Node::Leaf { value, .. } => value.clone(),
+        }
+    }
+
+    fn collect_fields(&self, key: String, fields: &mut Vec<FieldOrigin>) {
+        match self {
+            Node::Map(entries) => {
+                for (name, node) in entries {
+                    let name = inline(name);
+                    let key = if key.is_empty() {
+                        name
+                    } else {
+                        format!("{key}.{name}")
+                    };
+                    node.collect_fields(key, fields);
+                }
+            }
+            Node::List(items) => {
+                for (index, node) in items.iter().enumerate() {
+                    node.collect_fields(format!("{key}[{index}]"), fields);
+                }
+            }
+            Node::Leaf { value, layer } => fields.push(FieldOrigin {
+                key,
+                value: inline(value),
+                layer: *layer,
+            }),
+        }
+    }
+}
+
+/// Internally tagged enums, like `active_window.backend`, are mappings with a `kind`.
+fn is_tagged_enum(mapping: &Mapping) -> bool {
+    mapping.contains_key("kind")
+}
+
+/// The config all layers merge into, remembering where each set field came from.
+#[derive(Debug, Clone)]
+pub struct MergedConfig {
+    pub layers: Vec<PathBuf>,
+    root: Node,
+}
+
+impl MergedConfig {
+    /// Merges `layers`, later ones on top.
+    pub fn merge(layers: &[ConfigLayer]) -> Self {
+        let mut root = Node::Map(Vec::new());
+        for (index, layer) in layers.iter().enumerate() {
+            root.merge(&layer.value, index);
+        }
+        Self {
+            layers: layers.iter().map(|layer| layer.path.clone()).collect(),
+            root,
+        }
+    }
+
+    /// Reads the system and user configs; see [`config_layer_paths`].
+    pub fn load(config_path_override: Option<PathBuf>) -> Result<Self> {
+        let layers = config_layer_paths(config_path_override)?
+            .into_iter()
+            .map(ConfigLayer::read)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::merge(&layers))
+    }
+
+    pub fn config(&self) -> Result<AppConfig> {
+        serde_yaml::from_value(self.root.value()).map_err(|source| Error::ConfigParse {
+            path: self.layers.last().cloned().unwrap_or_default(),
+            source,
+        })
+    }
+
+    /// Every field set by some layer, in file order; everything else has its default.
+    pub fn fields(&self) -> Vec<FieldOrigin> {
+        let mut fields = Vec::new();
+        self.root.collect_fields(String::new(), &mut fields);
+        fields
+    }
+
+    pub fn layer_path(&self, field: &FieldOrigin) -> &Path {
+        &self.layers[field.layer]
+    }
+}
+
+/// A set config field, e.g. `clipboard.active_window.blacklist[0]`, and the layer it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldOrigin {
+    pub key: String,
+    /// The value on one line, YAML flow style.
+    pub value: String,
+    /// Index into [`MergedConfig::layers`].
+    pub layer: usize,
+}
+
+impl fmt::Display for FieldOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.value)
+    }
+}
+
+/// The config files to merge, system first: `--config` or [`SYSTEM_CONFIG_PATH`] when it
+/// exists, then the user config (`./slyboard.yaml` or `~/.config/slyboard/config.yaml`) when
+/// it exists and is a different file.
+pub fn config_layer_paths(config_path_override: Option<PathBuf>) -> Result<Vec<PathBuf>> {
+    let system = match config_path_override {
+        Some(path) => Some(path),
+        None => Some(PathBuf::from(SYSTEM_CONFIG_PATH)).filter(|path| path.exists()),
+    };
+    let user = match resolve_user_config_path() {
+        Ok(path) => Some(path),
+        Err(Error::ConfigNotFound { mut candidates }) if system.is_none() => {
+            candidates.insert(0, PathBuf::from(SYSTEM_CONFIG_PATH));
+            return Err(Error::ConfigNotFound { candidates });
+        }
+        Err(Error::ConfigNotFound { .. }) => None,
+        Err(err) => return Err(err),
+    };
+
+    let mut paths: Vec<PathBuf> = system.into_iter().collect();
+    if let Some(user) = user {
+        if !paths.iter().any(|path| same_file(path, &user)) {
+            paths.push(user);
+        }
+    }
+    Ok(paths)
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Renders `value` on one line: scalars as YAML, collections in flow style.
+fn inline(value: &Value) -> String {
+    match value {
+        Value::Mapping(mapping) => format!(
+            "{{{}}}",
+            mapping
+                .iter()
+                .map(|(key, value)| format!("{}: {}", inline(key), inline(value)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Value::Sequence(items) => format!(
+            "[{}]",
+            items.iter().map(inline).collect::<Vec<_>>().join(", ")
+        ),
+        Value::Tagged(tagged) => format!("{} {}", tagged.tag, inline(&tagged.value)),
+        scalar => serde_yaml::to_string(scalar)
+            .map(|rendered| rendered.trim_end().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfigLayer, MergedConfig};
+    use crate::config::{ActiveWindowBackend, AppConfig};
+    use crate::error::Error;
+    use std::path::PathBuf;
+
+    fn layer(name: &str, raw: &str) -> ConfigLayer {
+        ConfigLayer::parse(PathBuf::from(name), raw).expect("layer should parse")
+    }
+
+    fn merged(system: &str, user: &str) -> MergedConfig {
+        MergedConfig::merge(&[layer("system.yaml", system), layer("user.yaml", user)])
+    }
+
+    fn config(system: &str, user: &str) -> AppConfig {
+        merged(system, user)
+            .config()
+            .expect("merged config should parse")
+    }
+
+    #[test]
+    fn user_scalars_win_and_nested_maps_merge() {
+        let cfg = config(
+            "tray:\n  label_chars: 40\n  search: true\nclipboard:\n  pause_on_lock: true\n",
+            "tray:\n  label_chars: 60\n",
+        );
+        assert_eq!(cfg.tray.label_chars, 60);
+        assert!(cfg.tray.search);
+        assert!(cfg.clipboard.pause_on_lock);
+    }
+
+    #[test]
+    fn lists_concatenate_unless_replaced() {
+        let system = "clipboard:\n  active_window:\n    blacklist: [keepassxc, bitwarden]\n";
+        let cfg = config(
+            system,
+            "clipboard:\n  active_window:\n    blacklist: [1password]\n",
+        );
+        assert_eq!(
+            cfg.clipboard.active_window.blacklist,
+            ["keepassxc", "bitwarden", "1password"]
+        );
+
+        let cfg = config(
+            system,
+            "clipboard:\n  active_window:\n    blacklist: !replace [1password]\n",
+        );
+        assert_eq!(cfg.clipboard.active_window.blacklist, ["1password"]);
+        let cfg = config(
+            system,
+            "clipboard:\n  active_window:\n    blacklist: !replace []\n",
+        );
+        assert!(cfg.clipboard.active_window.blacklist.is_empty());
+    }
+
+    #[test]
+    fn tagged_backend_is_replaced_whole() {
+        let system = "clipboard:\n  active_window:\n    backend:\n      kind: command\n      program: xdotool\n      args: [getactivewindow]\n";
+        let backend = |user: &str| config(system, user).clipboard.active_window.backend;
+
+        assert_eq!(
+            backend("clipboard:\n  active_window:\n    backend:\n      kind: auto\n"),
+            ActiveWindowBackend::Auto
+        );
+        // Same kind: the user's args replace the system's instead of being appended.
+        assert_eq!(
+            backend("clipboard:\n  active_window:\n    backend:\n      kind: command\n      program: hyprctl\n      args: [activewindow]\n"),
+            ActiveWindowBackend::Command {
+                program: "hyprctl".to_string(),
+                args: vec!["activewindow".to_string()],
+            }
+        );
+        // Without a user backend the system one stays, args included.
+        assert_eq!(
+            backend("tray:\n  search: true\n"),
+            ActiveWindowBackend::Command {
+                program: "xdotool".to_string(),
+                args: vec!["getactivewindow".to_string()],
+            }
+        );
+        // A partial backend would still replace the system one, so each layer must be complete.
+        assert!(ConfigLayer::parse(
+            PathBuf::from("user.yaml"),
+            "clipboard:\n  active_window:\n    backend:\n      kind: command\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn reports_where_each_field_came_from() {
+        let merged = merged(
+            "clipboard:\n  active_window:\n    blacklist: [keepassxc]\n    backend:\n      kind: disabled\ntray:\n  label_chars: 40\n",
+            "tray:\n  label_chars: 60\nclipboard:\n  active_window:\n    blacklist: [1password]\n",
+        );
+        let lines: Vec<String> = merged
+            .fields()
+            .iter()
+            .map(|field| format!("{field} ({})", merged.layer_path(field).display()))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "clipboard.active_window.blacklist[0]: keepassxc (system.yaml)",
+                "clipboard.active_window.blacklist[1]: 1password (user.yaml)",
+                "clipboard.active_window.backend: {kind: disabled} (system.yaml)",
+                "tray.label_chars: 60 (user.yaml)",
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_and_invalid_layers() {
+        let cfg = config("", "tray:\n  search: true\n");
+        assert!(cfg.tray.search);
+        assert!(merged("", "").fields().is_empty());
+
+        let err = ConfigLayer::parse(PathBuf::from("user.yaml"), "tray:\n  label_chars: many\n")
+            .unwrap_err();
+        assert!(matches!(err, Error::ConfigParse { ref path, .. } if path.ends_with("user.yaml")));
+        assert!(matches!(
+            ConfigLayer::read(PathBuf::from("/nonexistent/slyboard.yaml")),
+            Err(Error::ConfigIo { .. })
+        ));
+    }
+}
@@ -1,8 +1,16 @@
-use std::process::Command;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use serde_json::Value;
 
 use crate::config::ActiveWindowBackend;
+use crate::core::hyprland::{
+    event_socket_path, request_socket_path, HyprlandEventProvider, HyprlandSocketProvider,
+};
+use crate::core::provider_stats::ProviderStats;
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct ActiveWindowContext {
@@ -16,24 +24,54 @@ pub struct ActiveWindowContext {
     pub workspace_id: Option<i64>,
     pub workspace_name: Option<String>,
     pub is_xwayland: Option<bool>,
+    #[serde(default)]
+    pub monitor: Option<String>,
+    /// Window position and size as `(x, y, width, height)`.
+    #[serde(default)]
+    pub geometry: Option<(i32, i32, i32, i32)>,
+}
+
+/// What one attempt to read the active window produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureOutcome {
+    Captured(Box<ActiveWindowContext>),
+    Failed(String),
+    TimedOut,
+}
+
+impl CaptureOutcome {
+    pub fn into_context(self) -> Option<ActiveWindowContext> {
+        match self {
+            CaptureOutcome::Captured(context) => Some(*context),
+            CaptureOutcome::Failed(_) | CaptureOutcome::TimedOut => None,
+        }
+    }
 }
 
 pub trait ActiveWindowProvider: Send + Sync {
-    fn capture(&self) -> Option<ActiveWindowContext>;
+    fn probe(&self) -> CaptureOutcome;
+
+    fn capture(&self) -> Option<ActiveWindowContext> {
+        self.probe().into_context()
+    }
 }
 
 pub struct DisabledActiveWindowProvider;
 
 impl ActiveWindowProvider for DisabledActiveWindowProvider {
-    fn capture(&self) -> Option<ActiveWindowContext> {
-        None
+    fn probe(&self) -> CaptureOutcome {
+        CaptureOutcome::Failed("active-window capture is disabled".to_string())
     }
 }
 
+/// How long a provider command may run before it is killed and counted as a timeout.
+pub const PROVIDER_TIMEOUT: Duration = Duration::from_secs(1);
+
 pub struct CommandActiveWindowProvider {
     program: String,
     args: Vec<String>,
     parser: fn(&str) -> Option<ActiveWindowContext>,
+    timeout: Duration,
 }
 
 impl CommandActiveWindowProvider {
@@ -46,18 +84,88 @@ impl CommandActiveWindowProvider {
             program: program.into(),
             args,
             parser,
+            timeout: PROVIDER_TIMEOUT,
         }
     }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
 }
 
 impl ActiveWindowProvider for CommandActiveWindowProvider {
-    fn capture(&self) -> Option<ActiveWindowContext> {
-        let output = Command::new(&self.program).args(&self.args).output().ok()?;
-        if !output.status.success() {
-            return None;
+    fn probe(&self) -> CaptureOutcome {
+        let program = &self.program;
+        let mut child = match Command::new(program)
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(err) => return CaptureOutcome::Failed(format!("failed to run {program}: {err}")),
+        };
+        // Output is a few lines, well below the pipe buffer, so it is read after exiting.
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) if Instant::now() >= deadline => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return CaptureOutcome::TimedOut;
+                }
+                Ok(None) => thread::sleep(Duration::from_millis(5)),
+                Err(err) => {
+                    return CaptureOutcome::Failed(format!("failed to wait for {program}: {err}"))
+                }
+            }
+        };
+        if !status.success() {
+            return CaptureOutcome::Failed(format!("{program} exited with {status}"));
+        }
+        let mut raw = String::new();
+        if let Some(mut stdout) = child.stdout.take() {
+            if let Err(err) = stdout.read_to_string(&mut raw) {
+                return CaptureOutcome::Failed(format!("failed to read {program} output: {err}"));
+            }
+        }
+        match (self.parser)(raw.trim()) {
+            Some(context) => CaptureOutcome::Captured(Box::new(context)),
+            None => CaptureOutcome::Failed(format!("no active window in {program} output")),
+        }
+    }
+}
+
+/// Times every attempt of `inner` and counts its outcome in [`ProviderStats`] under `name`.
+pub struct InstrumentedProvider {
+    name: String,
+    inner: Box<dyn ActiveWindowProvider>,
+    stats: ProviderStats,
+}
+
+impl InstrumentedProvider {
+    pub fn new(
+        name: impl Into<String>,
+        inner: Box<dyn ActiveWindowProvider>,
+        stats: ProviderStats,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            inner,
+            stats,
         }
-        let raw = String::from_utf8(output.stdout).ok()?;
-        (self.parser)(raw.trim())
+    }
+}
+
+impl ActiveWindowProvider for InstrumentedProvider {
+    fn probe(&self) -> CaptureOutcome {
+        let started = Instant::now();
+        let outcome = self.inner.probe();
+        self.stats.record(&self.name, &outcome, started.elapsed());
+        outcome
     }
 }
 
@@ -67,13 +175,60 @@ pub struct AutoActiveWindowProvider {
 
 impl AutoActiveWindowProvider {
     pub fn new() -> Self {
-        Self {
-            providers: vec![
-                Box::new(CommandActiveWindowProvider::new(
-                    "hyprctl",
-                    vec!["activewindow".into(), "-j".into()],
-                    parse_hyprctl_active_window,
-                )),
+        Self::from_providers(
+            providers_from_config(&ActiveWindowBackend::Auto)
+                .into_iter()
+                .map(|(_, provider)| provider)
+                .collect(),
+        )
+    }
+
+    /// Tries `providers` in order until one captures.
+    pub fn from_providers(providers: Vec<Box<dyn ActiveWindowProvider>>) -> Self {
+        Self { providers }
+    }
+}
+
+impl ActiveWindowProvider for AutoActiveWindowProvider {
+    fn probe(&self) -> CaptureOutcome {
+        let mut last = CaptureOutcome::Failed("no active-window provider".to_string());
+        for provider in &self.providers {
+            match provider.probe() {
+                CaptureOutcome::Captured(context) => return CaptureOutcome::Captured(context),
+                outcome => last = outcome,
+            }
+        }
+        last
+    }
+}
+
+/// The providers a backend tries, in order and by name: Hyprland (its socket when running
+/// under Hyprland, else `hyprctl`) then `xdotool` for `auto`, the program for `command`, none
+/// when disabled.
+pub fn providers_from_config(
+    config: &ActiveWindowBackend,
+) -> Vec<(String, Box<dyn ActiveWindowProvider>)> {
+    backend_providers(config, request_socket_path())
+}
+
+fn backend_providers(
+    config: &ActiveWindowBackend,
+    hyprland_socket: Option<PathBuf>,
+) -> Vec<(String, Box<dyn ActiveWindowProvider>)> {
+    match config {
+        ActiveWindowBackend::Disabled => Vec::new(),
+        ActiveWindowBackend::Command { program, args } => vec![(
+            program.clone(),
+            Box::new(CommandActiveWindowProvider::new(
+                program.clone(),
+                args.clone(),
+                parse_command_active_window,
+            )),
+        )],
+        ActiveWindowBackend::Auto => vec![
+            hyprland_provider(hyprland_socket),
+            (
+                "xdotool".to_string(),
                 Box::new(CommandActiveWindowProvider::new(
                     "sh",
                     vec![
@@ -92,38 +247,83 @@ printf 'workspace_id=%s\\n' \"$workspace_id\";"
                     ],
                     parse_xdotool_active_window,
                 )),
-            ],
-        }
+            ),
+        ],
     }
 }
 
-impl ActiveWindowProvider for AutoActiveWindowProvider {
-    fn capture(&self) -> Option<ActiveWindowContext> {
-        for provider in &self.providers {
-            if let Some(context) = provider.capture() {
-                return Some(context);
-            }
-        }
-        None
+/// Hyprland's socket, which `hyprctl` talks to anyway, when its path is known.
+fn hyprland_provider(socket: Option<PathBuf>) -> (String, Box<dyn ActiveWindowProvider>) {
+    match socket {
+        Some(path) => (
+            "hyprland".to_string(),
+            Box::new(HyprlandSocketProvider::new(path)),
+        ),
+        None => (
+            "hyprctl".to_string(),
+            Box::new(CommandActiveWindowProvider::new(
+                "hyprctl",
+                vec!["activewindow".into(), "-j".into()],
+                parse_hyprctl_active_window,
+            )),
+        ),
     }
 }
 
-pub fn provider_from_config(config: &ActiveWindowBackend) -> Box<dyn ActiveWindowProvider> {
-    match config {
-        ActiveWindowBackend::Disabled => Box::new(DisabledActiveWindowProvider),
-        ActiveWindowBackend::Command { program, args } => {
-            Box::new(CommandActiveWindowProvider::new(
-                program.clone(),
-                args.clone(),
-                parse_command_active_window,
-            ))
+/// The provider for a backend, counting each attempt of each of its providers in `stats`.
+pub fn provider_from_config(
+    config: &ActiveWindowBackend,
+    stats: &ProviderStats,
+) -> Box<dyn ActiveWindowProvider> {
+    instrumented(providers_from_config(config), stats)
+}
+
+/// Like [`provider_from_config`], for the daemon: under Hyprland, `auto` first answers from
+/// the focus changes Hyprland reports on its event socket, followed on a background thread,
+/// and only queries when it has none.
+pub fn watching_provider_from_config(
+    config: &ActiveWindowBackend,
+    stats: &ProviderStats,
+) -> Box<dyn ActiveWindowProvider> {
+    let mut providers = providers_from_config(config);
+    if *config == ActiveWindowBackend::Auto {
+        if let Some(path) = event_socket_path() {
+            let query = request_socket_path().map(HyprlandSocketProvider::new);
+            providers.insert(
+                0,
+                (
+                    "hyprland-events".to_string(),
+                    Box::new(HyprlandEventProvider::watch(path, query)),
+                ),
+            );
         }
-        ActiveWindowBackend::Auto => Box::new(AutoActiveWindowProvider::new()),
     }
+    instrumented(providers, stats)
+}
+
+fn instrumented(
+    providers: Vec<(String, Box<dyn ActiveWindowProvider>)>,
+    stats: &ProviderStats,
+) -> Box<dyn ActiveWindowProvider> {
+    if providers.is_empty() {
+        return Box::new(DisabledActiveWindowProvider);
+    }
+    let providers = providers
+        .into_iter()
+        .map(|(name, provider)| {
+            Box::new(InstrumentedProvider::new(name, provider, stats.clone()))
+                as Box<dyn ActiveWindowProvider>
+        })
+        .collect();
+    Box::new(AutoActiveWindowProvider::from_providers(providers))
 }
 
 fn parse_hyprctl_active_window(raw: &str) -> Option<ActiveWindowContext> {
-    let parsed: Value = serde_json::from_str(raw).ok()?;
+    hyprctl_active_window(&serde_json::from_str(raw).ok()?)
+}
+
+/// The window in an `activewindow` reply, from `hyprctl -j` or Hyprland's socket.
+pub(crate) fn hyprctl_active_window(parsed: &Value) -> Option<ActiveWindowContext> {
     let title = parsed.get("title")?.as_str()?.trim();
     if title.is_empty() {
         return None;
@@ -147,6 +347,18 @@ fn parse_hyprctl_active_window(raw: &str) -> Option<ActiveWindowContext> {
         .filter(|value| !value.is_empty());
 
     let is_xwayland = parsed.get("xwayland").and_then(|value| value.as_bool());
+    let monitor = parsed.get("monitor").and_then(|value| match value {
+        Value::Number(number) => Some(number.to_string()),
+        Value::String(name) => Some(name.trim().to_string()).filter(|name| !name.is_empty()),
+        _ => None,
+    });
+    let geometry = match (
+        parse_i32_pair(parsed.get("at")),
+        parse_i32_pair(parsed.get("size")),
+    ) {
+        (Some((x, y)), Some((width, height))) => Some((x, y, width, height)),
+        _ => None,
+    };
 
     Some(ActiveWindowContext {
         backend: "hyprctl".to_string(),
@@ -159,6 +371,8 @@ fn parse_hyprctl_active_window(raw: &str) -> Option<ActiveWindowContext> {
         workspace_id,
         workspace_name,
         is_xwayland,
+        monitor,
+        geometry,
     })
 }
 
@@ -212,6 +426,8 @@ fn parse_xdotool_active_window(raw: &str) -> Option<ActiveWindowContext> {
         workspace_id,
         workspace_name: None,
         is_xwayland: None,
+        monitor: None,
+        geometry: None,
     })
 }
 
@@ -235,12 +451,206 @@ fn parse_title_with_backend(backend: &str, raw: &str) -> Option<ActiveWindowCont
         workspace_id: None,
         workspace_name: None,
         is_xwayland: None,
+        monitor: None,
+        geometry: None,
     })
 }
 
+fn parse_i32_pair(value: Option<&Value>) -> Option<(i32, i32)> {
+    let pair = value?.as_array()?;
+    let [first, second] = pair.as_slice() else {
+        return None;
+    };
+    let first = i32::try_from(first.as_i64()?).ok()?;
+    let second = i32::try_from(second.as_i64()?).ok()?;
+    Some((first, second))
+}
+
 fn optional_trimmed_string(value: Option<&Value>) -> Option<String> {
     value
         .and_then(|value| value.as_str())
         .map(|value| value.trim().to_string())
         .filter(|value| !value.is_empty())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        backend_providers, parse_hyprctl_active_window, parse_title_with_backend,
+        provider_from_config, ActiveWindowProvider, AutoActiveWindowProvider, CaptureOutcome,
+        CommandActiveWindowProvider, InstrumentedProvider,
+    };
+    use crate::config::ActiveWindowBackend;
+    use crate::core::provider_stats::ProviderStats;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    struct StubProvider(CaptureOutcome);
+
+    impl ActiveWindowProvider for StubProvider {
+        fn probe(&self) -> CaptureOutcome {
+            self.0.clone()
+        }
+    }
+
+    fn instrumented(
+        name: &str,
+        outcome: CaptureOutcome,
+        stats: &ProviderStats,
+    ) -> Box<dyn ActiveWindowProvider> {
+        Box::new(InstrumentedProvider::new(
+            name,
+            Box::new(StubProvider(outcome)),
+            stats.clone(),
+        ))
+    }
+
+    const HYPRCTL_ACTIVE_WINDOW: &str = r#"{
+        "address": "0x55d4c1a2b3c0",
+        "mapped": true,
+        "hidden": false,
+        "at": [1930, 48],
+        "size": [1268, 1382],
+        "workspace": { "id": 3, "name": "3" },
+        "floating": false,
+        "monitor": 1,
+        "class": "firefox",
+        "title": "Example Domain — Mozilla Firefox",
+        "initialClass": "firefox",
+        "initialTitle": "Mozilla Firefox",
+        "pid": 4242,
+        "xwayland": false
+    }"#;
+
+    #[test]
+    fn counts_each_provider_tried_until_one_captures() {
+        let stats = ProviderStats::default();
+        let context = parse_title_with_backend("stub", "Editor").unwrap();
+        let auto = AutoActiveWindowProvider::from_providers(vec![
+            instrumented("slow", CaptureOutcome::TimedOut, &stats),
+            instrumented(
+                "broken",
+                CaptureOutcome::Failed("exit 1".to_string()),
+                &stats,
+            ),
+            instrumented(
+                "works",
+                CaptureOutcome::Captured(Box::new(context.clone())),
+                &stats,
+            ),
+            instrumented("unused", CaptureOutcome::TimedOut, &stats),
+        ]);
+        assert_eq!(auto.capture(), Some(context));
+        assert_eq!(
+            auto.capture().map(|context| context.title),
+            Some("Editor".to_string())
+        );
+
+        let counters = stats.snapshot();
+        assert_eq!(
+            counters.keys().collect::<Vec<_>>(),
+            ["broken", "slow", "works"]
+        );
+        assert_eq!(
+            (counters["slow"].timeouts, counters["slow"].failures),
+            (2, 0)
+        );
+        assert_eq!(counters["broken"].failures, 2);
+        assert_eq!(counters["broken"].last_error.as_deref(), Some("exit 1"));
+        assert_eq!(counters["works"].successes, 2);
+        assert!(counters["works"].last_latency_ms.is_some());
+
+        let all_fail = AutoActiveWindowProvider::from_providers(vec![instrumented(
+            "slow",
+            CaptureOutcome::TimedOut,
+            &stats,
+        )]);
+        assert_eq!(all_fail.probe(), CaptureOutcome::TimedOut);
+        assert_eq!(stats.snapshot()["slow"].timeouts, 3);
+    }
+
+    #[test]
+    fn command_provider_reports_failures_and_timeouts() {
+        let stats = ProviderStats::default();
+        let command = |script: &str| ActiveWindowBackend::Command {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+        };
+        let provider = provider_from_config(&command("echo Terminal"), &stats);
+        assert_eq!(
+            provider.capture().map(|context| context.title),
+            Some("Terminal".to_string())
+        );
+        let provider = provider_from_config(&command("exit 3"), &stats);
+        assert_eq!(provider.capture(), None);
+        let counters = &stats.snapshot()["sh"];
+        assert_eq!((counters.successes, counters.failures), (1, 1));
+        assert_eq!(
+            counters.last_error.as_deref(),
+            Some("sh exited with exit status: 3")
+        );
+
+        let sleepy = CommandActiveWindowProvider::new("sleep", vec!["5".to_string()], |raw| {
+            parse_title_with_backend("sleep", raw)
+        })
+        .with_timeout(Duration::from_millis(50));
+        assert_eq!(sleepy.probe(), CaptureOutcome::TimedOut);
+        let missing =
+            CommandActiveWindowProvider::new("slyboard-no-such-program", Vec::new(), |raw| {
+                parse_title_with_backend("missing", raw)
+            });
+        assert!(matches!(missing.probe(), CaptureOutcome::Failed(_)));
+        assert!(provider_from_config(&ActiveWindowBackend::Disabled, &stats)
+            .capture()
+            .is_none());
+    }
+
+    #[test]
+    fn auto_asks_hyprlands_socket_instead_of_hyprctl_when_running_under_it() {
+        let names = |socket: Option<&str>| {
+            backend_providers(&ActiveWindowBackend::Auto, socket.map(PathBuf::from))
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(None), ["hyprctl", "xdotool"]);
+        assert_eq!(
+            names(Some("/run/user/1000/hypr/abc/.socket.sock")),
+            ["hyprland", "xdotool"]
+        );
+    }
+
+    #[test]
+    fn parses_hyprctl_window_with_monitor_and_geometry() {
+        let context =
+            parse_hyprctl_active_window(HYPRCTL_ACTIVE_WINDOW).expect("fixture should parse");
+        assert_eq!(context.backend, "hyprctl");
+        assert_eq!(context.title, "Example Domain — Mozilla Firefox");
+        assert_eq!(context.app_id.as_deref(), Some("firefox"));
+        assert_eq!(context.window_id.as_deref(), Some("0x55d4c1a2b3c0"));
+        assert_eq!(context.pid, Some(4242));
+        assert_eq!(context.workspace_id, Some(3));
+        assert_eq!(context.is_xwayland, Some(false));
+        assert_eq!(context.monitor.as_deref(), Some("1"));
+        assert_eq!(context.geometry, Some((1930, 48, 1268, 1382)));
+    }
+
+    #[test]
+    fn geometry_requires_both_position_and_size() {
+        let context =
+            parse_hyprctl_active_window(r#"{"title": "t", "at": [0, 0], "monitor": "DP-1"}"#)
+                .expect("minimal window should parse");
+        assert_eq!(context.monitor.as_deref(), Some("DP-1"));
+        assert_eq!(context.geometry, None);
+    }
+
+    #[test]
+    fn older_contexts_without_new_fields_still_deserialize() {
+        let context: super::ActiveWindowContext = serde_json::from_str(
+            r#"{"backend":"hyprctl","title":"t","app_id":null,"initial_app_id":null,"initial_title":null,"window_id":null,"pid":null,"workspace_id":null,"workspace_name":null,"is_xwayland":null}"#,
+        )
+        .expect("legacy context should deserialize");
+        assert_eq!(context.monitor, None);
+        assert_eq!(context.geometry, None);
+    }
+}
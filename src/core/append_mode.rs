@@ -0,0 +1,183 @@
+//! Append mode (`slyboard append on`): text captures are collected as pieces of one buffer
+//! instead of separate history entries, until `slyboard append off` joins them. The buffer is a
+//! runtime marker next to the pause marker, so the CLI and the daemon see the same state.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::clipboard::ClipboardEntry;
+use crate::core::session::{runtime_dir, session_key, write_atomically};
+
+const APPEND_FILE_SUFFIX: &str = "append.json";
+
+/// Text collected while append mode is on, oldest piece first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppendBuffer {
+    pub pieces: Vec<String>,
+}
+
+impl AppendBuffer {
+    /// Adds a text entry as the next piece. Images are not collected and return `false`.
+    pub fn push(&mut self, entry: &ClipboardEntry) -> bool {
+        match entry {
+            ClipboardEntry::Text { value, .. } => {
+                self.pieces.push(value.clone());
+                true
+            }
+            ClipboardEntry::Image { .. } => false,
+        }
+    }
+
+    pub fn join(&self, separator: &str) -> String {
+        self.pieces.join(separator)
+    }
+}
+
+/// "1 piece", "4 pieces".
+pub fn format_piece_count(pieces: usize) -> String {
+    match pieces {
+        1 => "1 piece".to_string(),
+        count => format!("{count} pieces"),
+    }
+}
+
+/// The append marker of one session; present exactly while append mode is on.
+#[derive(Debug, Clone)]
+pub struct AppendMode {
+    path: PathBuf,
+}
+
+impl AppendMode {
+    /// Append mode applies to the current graphical session only, see [`session_key`].
+    pub fn for_session() -> Self {
+        Self::at(runtime_dir().join(format!("slyboard-{}-{APPEND_FILE_SUFFIX}", session_key())))
+    }
+
+    pub fn at(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// The collected pieces, or `None` when append mode is off.
+    pub fn buffer(&self) -> Result<Option<AppendBuffer>> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("failed to read append marker: {}", self.path.display())
+                })
+            }
+        };
+        let buffer = serde_json::from_slice(&contents)
+            .with_context(|| format!("append marker is not valid JSON: {}", self.path.display()))?;
+        Ok(Some(buffer))
+    }
+
+    /// Turns append mode on with an empty buffer. Returns `false` if it was already on, in
+    /// which case the pieces collected so far are kept.
+    pub fn start(&self) -> Result<bool> {
+        if self.buffer()?.is_some() {
+            return Ok(false);
+        }
+        self.write(&AppendBuffer::default())?;
+        Ok(true)
+    }
+
+    /// Adds `entry` to the buffer if append mode is on and it is text, returning the new number
+    /// of pieces. `None` means the entry should be recorded as usual.
+    pub fn collect(&self, entry: &ClipboardEntry) -> Result<Option<usize>> {
+        let Some(mut buffer) = self.buffer()? else {
+            return Ok(None);
+        };
+        if !buffer.push(entry) {
+            return Ok(None);
+        }
+        self.write(&buffer)?;
+        Ok(Some(buffer.pieces.len()))
+    }
+
+    /// Turns append mode off and returns what was collected, or `None` if it was not on.
+    pub fn finish(&self) -> Result<Option<AppendBuffer>> {
+        let buffer = self.buffer()?;
+        if buffer.is_some() {
+            fs::remove_file(&self.path).with_context(|| {
+                format!("failed to remove append marker: {}", self.path.display())
+            })?;
+        }
+        Ok(buffer)
+    }
+
+    /// Replaces the marker through a temporary file, so readers never see half a buffer.
+    fn write(&self, buffer: &AppendBuffer) -> Result<()> {
+        write_atomically(&self.path, &serde_json::to_vec(buffer)?)
+            .with_context(|| format!("failed to write append marker: {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_piece_count, AppendBuffer, AppendMode};
+    use crate::clipboard::{ClipboardEntry, ImageData};
+    use std::path::PathBuf;
+
+    fn test_marker(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "slyboard-test-append-{}-{name}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn image() -> ClipboardEntry {
+        ClipboardEntry::image(ImageData::new(1, 1, 4, 4, vec![0; 4]).unwrap())
+    }
+
+    #[test]
+    fn joins_text_pieces_and_skips_images() {
+        let mut buffer = AppendBuffer::default();
+        assert!(buffer.push(&ClipboardEntry::text("first quote")));
+        assert!(!buffer.push(&image()));
+        assert!(buffer.push(&ClipboardEntry::text("second\nquote")));
+        assert_eq!(buffer.join("\n\n"), "first quote\n\nsecond\nquote");
+        assert_eq!(AppendBuffer::default().join(" "), "");
+        assert_eq!(format_piece_count(1), "1 piece");
+        assert_eq!(format_piece_count(0), "0 pieces");
+    }
+
+    #[test]
+    fn collects_only_while_on() {
+        let path = test_marker("transitions");
+        let mode = AppendMode::at(path.clone());
+        assert_eq!(mode.collect(&ClipboardEntry::text("before")).unwrap(), None);
+        assert_eq!(mode.finish().unwrap(), None);
+
+        assert!(mode.start().unwrap());
+        assert_eq!(mode.collect(&ClipboardEntry::text("a")).unwrap(), Some(1));
+        assert_eq!(mode.collect(&image()).unwrap(), None);
+        // Starting again keeps the buffer, and another handle sees the same state.
+        assert!(!mode.start().unwrap());
+        let other = AppendMode::at(path.clone());
+        assert_eq!(other.collect(&ClipboardEntry::text("b")).unwrap(), Some(2));
+
+        let buffer = mode.finish().unwrap().expect("append mode was on");
+        assert_eq!(buffer.pieces, ["a", "b"]);
+        assert!(!path.exists());
+        assert_eq!(other.buffer().unwrap(), None);
+        assert_eq!(mode.collect(&ClipboardEntry::text("after")).unwrap(), None);
+    }
+
+    #[test]
+    fn reports_a_corrupt_marker() {
+        let path = test_marker("corrupt");
+        std::fs::write(&path, "{").unwrap();
+        let mode = AppendMode::at(path.clone());
+        assert!(mode.buffer().is_err());
+        assert!(mode.collect(&ClipboardEntry::text("a")).is_err());
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -1,58 +1,124 @@
+use std::collections::BTreeSet;
 use std::fs;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
 
 use anyhow::{Context, Result};
 
+use crate::core::events::{self, Event};
+#[cfg(unix)]
+use crate::core::session::current_uid;
+use crate::core::session::{private_runtime_dir, session_key};
+
 const PAUSE_FILE_SUFFIX: &str = "paused";
 
+/// Markers already warned about for having another owner. `slyboard bar --follow` checks
+/// twice a second, and one warning per marker is enough.
+static FOREIGN_MARKERS: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
 pub fn is_capture_paused() -> Result<bool> {
-    Ok(default_pause_path().exists())
+    Ok(is_capture_paused_at_path(&default_pause_path()?))
+}
+
+/// Whether the marker at `path` exists and belongs to the current user. One owned by another
+/// user is ignored, with a warning the first time, so nobody can pause someone else's capture by planting it.
+#[cfg(unix)]
+pub fn is_capture_paused_at_path(path: &Path) -> bool {
+    marker_is_ours(path, current_uid(), |path| {
+        fs::symlink_metadata(path).map(|metadata| metadata.uid())
+    })
+}
+
+/// Windows has no uids to compare, but the marker is in the user's own temp dir there.
+#[cfg(not(unix))]
+pub fn is_capture_paused_at_path(path: &Path) -> bool {
+    marker_is_ours(path, 0, |path| fs::symlink_metadata(path).map(|_| 0))
+}
+
+fn marker_is_ours(path: &Path, uid: u32, owner_of: impl Fn(&Path) -> io::Result<u32>) -> bool {
+    match owner_of(path) {
+        Ok(owner) if owner == uid => true,
+        Ok(owner) => {
+            if first_foreign_sighting(path) {
+                crate::log_eprintln!(
+                    "warning: ignoring capture pause marker {} owned by uid {owner}, not {uid}",
+                    path.display()
+                );
+            }
+            false
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => false,
+        Err(err) => {
+            crate::log_eprintln!(
+                "warning: failed to check capture pause marker {}: {err}",
+                path.display()
+            );
+            false
+        }
+    }
+}
+
+fn first_foreign_sighting(path: &Path) -> bool {
+    FOREIGN_MARKERS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(path.to_path_buf())
 }
 
 pub fn set_capture_paused(paused: bool) -> Result<()> {
-    set_capture_paused_at_path(&default_pause_path(), paused)
+    set_capture_paused_at_path(&default_pause_path()?, paused)
 }
 
-fn set_capture_paused_at_path(path: &Path, paused: bool) -> Result<()> {
-    if paused {
-        fs::write(path, b"paused\n").with_context(|| {
+pub fn set_capture_paused_at_path(path: &Path, paused: bool) -> Result<()> {
+    let exists = path.symlink_metadata().is_ok();
+    let was_paused = exists && is_capture_paused_at_path(path);
+    // A marker someone else left is replaced on pause and removed on resume alike.
+    if exists && (!was_paused || !paused) {
+        fs::remove_file(path).with_context(|| {
             format!(
-                "failed to write slyboard capture pause marker: {}",
+                "failed to remove slyboard capture pause marker: {}",
                 path.display()
             )
         })?;
-        return Ok(());
     }
-
-    if path.exists() {
-        fs::remove_file(path).with_context(|| {
+    if paused {
+        fs::write(path, b"paused\n").with_context(|| {
             format!(
-                "failed to remove slyboard capture pause marker: {}",
+                "failed to write slyboard capture pause marker: {}",
                 path.display()
             )
         })?;
+        if !was_paused {
+            events::record(Event::Pause);
+        }
+    } else if was_paused {
+        events::record(Event::Resume);
     }
     Ok(())
 }
 
-fn default_pause_path() -> PathBuf {
-    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
-        .map(PathBuf::from)
-        .unwrap_or_else(std::env::temp_dir);
-    runtime_dir.join(format!("slyboard-{}-{PAUSE_FILE_SUFFIX}", user_hint()))
+/// Pausing applies to the current graphical session only, see [`session_key`]. The marker
+/// lives in [`private_runtime_dir`].
+pub fn default_pause_path() -> Result<PathBuf> {
+    let dir = private_runtime_dir().context("failed to prepare the capture pause directory")?;
+    Ok(pause_path(&dir, &session_key()))
 }
 
-fn user_hint() -> String {
-    std::env::var("USER")
-        .ok()
-        .filter(|value| !value.trim().is_empty())
-        .unwrap_or_else(|| "user".to_string())
+fn pause_path(runtime_dir: &Path, session_key: &str) -> PathBuf {
+    runtime_dir.join(format!("slyboard-{session_key}-{PAUSE_FILE_SUFFIX}"))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{is_capture_paused, set_capture_paused_at_path};
-    use std::path::PathBuf;
+    use super::{
+        first_foreign_sighting, is_capture_paused, is_capture_paused_at_path, marker_is_ours,
+        pause_path, set_capture_paused_at_path,
+    };
+    use std::io;
+    use std::path::{Path, PathBuf};
 
     fn test_pause_path(name: &str) -> PathBuf {
         std::env::temp_dir().join(format!(
@@ -78,8 +144,48 @@ mod tests {
         assert!(!path.exists(), "pause marker should be removed");
     }
 
+    #[test]
+    fn pausing_one_session_leaves_the_other_running() {
+        let dir = std::env::temp_dir();
+        let first = pause_path(&dir, &format!("test-{}-s1-x0", std::process::id()));
+        let second = pause_path(&dir, &format!("test-{}-s2-wayland-1", std::process::id()));
+        assert_ne!(first, second);
+
+        set_capture_paused_at_path(&first, true).expect("pause first session");
+        assert!(first.exists());
+        assert!(!second.exists(), "other session must stay unpaused");
+        set_capture_paused_at_path(&first, false).expect("resume first session");
+    }
+
     #[test]
     fn reports_default_state_without_crashing() {
         let _ = is_capture_paused().expect("default paused lookup should not fail");
     }
+
+    #[test]
+    fn honors_only_markers_the_current_user_owns() {
+        let path = Path::new("/run/user/1000/slyboard-alice-paused");
+        assert!(marker_is_ours(path, 1000, |_| Ok(1000)));
+        assert!(!marker_is_ours(path, 1000, |_| Ok(1001)), "planted");
+        assert!(!marker_is_ours(path, 1000, |_| Err(
+            io::ErrorKind::NotFound.into()
+        )));
+        assert!(!marker_is_ours(path, 1000, |_| Err(
+            io::ErrorKind::PermissionDenied.into()
+        )));
+
+        let own = test_pause_path("owned");
+        set_capture_paused_at_path(&own, true).expect("pause");
+        assert!(is_capture_paused_at_path(&own));
+        set_capture_paused_at_path(&own, false).expect("resume");
+        assert!(!is_capture_paused_at_path(&own));
+    }
+
+    #[test]
+    fn warns_about_each_foreign_marker_once() {
+        let path = test_pause_path("foreign");
+        assert!(first_foreign_sighting(&path));
+        assert!(!first_foreign_sighting(&path));
+        assert!(first_foreign_sighting(&test_pause_path("other-foreign")));
+    }
 }
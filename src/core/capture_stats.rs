@@ -0,0 +1,319 @@
+//! What the daemon did with clipboard changes since it started: how many it recorded, why it
+//! skipped the others, and how many notifications it sent. Like the provider counters, they
+//! are kept in a runtime file where `slyboard status` reads them, and logged on SIGUSR2.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::clipboard::age::{format_relative_age, unix_now};
+use crate::core::session::{runtime_dir, session_key, write_atomically};
+
+const CAPTURE_STATS_FILE_SUFFIX: &str = "captures.json";
+
+/// Why a clipboard change did not become a history entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    /// Put on the clipboard by slyboard itself, e.g. restoring an entry.
+    OwnRestore,
+    /// `clipboard.ignore_prefixes` or `clipboard.ignore_globs`.
+    ContentFilter,
+    /// `clipboard.active_window.blacklist`.
+    Blacklist,
+    /// `clipboard.paused_workspaces`.
+    PausedWorkspace,
+    /// An incognito window with `incognito_policy: skip`.
+    Incognito,
+    /// `slyboard pause-capture`.
+    Paused,
+    ScreenLocked,
+    /// Collected into the append buffer instead.
+    Appended,
+}
+
+impl SkipReason {
+    const ALL: [SkipReason; 8] = [
+        SkipReason::OwnRestore,
+        SkipReason::ContentFilter,
+        SkipReason::Blacklist,
+        SkipReason::PausedWorkspace,
+        SkipReason::Incognito,
+        SkipReason::Paused,
+        SkipReason::ScreenLocked,
+        SkipReason::Appended,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SkipReason::OwnRestore => "own restore",
+            SkipReason::ContentFilter => "content filter",
+            SkipReason::Blacklist => "blacklist",
+            SkipReason::PausedWorkspace => "paused workspace",
+            SkipReason::Incognito => "incognito",
+            SkipReason::Paused => "paused",
+            SkipReason::ScreenLocked => "screen locked",
+            SkipReason::Appended => "appended",
+        }
+    }
+}
+
+/// A snapshot of the counters, as written to the runtime file and reported by `status`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureCounters {
+    /// Unix time the daemon started.
+    pub started_at: u64,
+    /// Changes recorded in history, including copies of entries already in it.
+    pub captured: u64,
+    /// Skipped changes by reason; reasons that never applied are left out.
+    pub skipped: BTreeMap<SkipReason, u64>,
+    pub notifications: u64,
+    /// Unix time of the last recorded change.
+    pub last_capture_at: Option<u64>,
+}
+
+impl CaptureCounters {
+    pub fn uptime_secs(&self, now: u64) -> u64 {
+        now.saturating_sub(self.started_at)
+    }
+
+    /// One line per counter for `slyboard status` and the SIGUSR2 log, ages relative to `now`.
+    pub fn summary_lines(&self, now: u64) -> Vec<String> {
+        let mut lines = vec![format!(
+            "up {} (since {})",
+            format_relative_age(self.started_at, now),
+            self.started_at
+        )];
+        lines.push(match self.last_capture_at {
+            Some(at) => format!(
+                "captured {}, last {at} ({})",
+                self.captured,
+                format_relative_age(at, now)
+            ),
+            None => format!("captured {}", self.captured),
+        });
+        let skipped = self.skipped.values().sum::<u64>();
+        if skipped == 0 {
+            lines.push("skipped 0".to_string());
+        } else {
+            let by_reason = self
+                .skipped
+                .iter()
+                .map(|(reason, count)| format!("{} {count}", reason.as_str()))
+                .collect::<Vec<_>>();
+            lines.push(format!("skipped {skipped}: {}", by_reason.join(", ")));
+        }
+        lines.push(format!("notifications {}", self.notifications));
+        lines
+    }
+}
+
+#[derive(Debug)]
+struct Counters {
+    started_at: u64,
+    captured: AtomicU64,
+    skipped: [AtomicU64; SkipReason::ALL.len()],
+    notifications: AtomicU64,
+    /// Zero until the first capture.
+    last_capture_at: AtomicU64,
+}
+
+/// The counters of one daemon, shared by the poller and the tray callbacks. Counting is
+/// lock-free; only keeping the file in sync takes a lock.
+#[derive(Debug, Clone)]
+pub struct CaptureStats {
+    counters: Arc<Counters>,
+    path: Option<PathBuf>,
+    persisting: Arc<Mutex<()>>,
+}
+
+impl Default for CaptureStats {
+    /// Counters that are not written anywhere, for pollers outside the daemon.
+    fn default() -> Self {
+        Self {
+            counters: Arc::new(Counters {
+                started_at: unix_now(),
+                captured: AtomicU64::new(0),
+                skipped: Default::default(),
+                notifications: AtomicU64::new(0),
+                last_capture_at: AtomicU64::new(0),
+            }),
+            path: None,
+            persisting: Arc::default(),
+        }
+    }
+}
+
+impl CaptureStats {
+    /// Stats of the daemon in the current session, see [`session_key`]. Starts from zero,
+    /// replacing whatever a previous daemon left behind.
+    pub fn for_session() -> Self {
+        Self::at(default_capture_stats_path())
+    }
+
+    pub fn at(path: PathBuf) -> Self {
+        let stats = Self {
+            path: Some(path),
+            ..Self::default()
+        };
+        stats.persist();
+        stats
+    }
+
+    pub fn captured(&self) {
+        self.counters.captured.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .last_capture_at
+            .store(unix_now(), Ordering::Relaxed);
+        self.persist();
+    }
+
+    pub fn skipped(&self, reason: SkipReason) {
+        let index = SkipReason::ALL
+            .iter()
+            .position(|&known| known == reason)
+            .expect("every reason is listed");
+        self.counters.skipped[index].fetch_add(1, Ordering::Relaxed);
+        self.persist();
+    }
+
+    pub fn notified(&self) {
+        self.counters.notifications.fetch_add(1, Ordering::Relaxed);
+        self.persist();
+    }
+
+    pub fn snapshot(&self) -> CaptureCounters {
+        let counters = &self.counters;
+        CaptureCounters {
+            started_at: counters.started_at,
+            captured: counters.captured.load(Ordering::Relaxed),
+            skipped: SkipReason::ALL
+                .into_iter()
+                .zip(&counters.skipped)
+                .map(|(reason, count)| (reason, count.load(Ordering::Relaxed)))
+                .filter(|&(_, count)| count > 0)
+                .collect(),
+            notifications: counters.notifications.load(Ordering::Relaxed),
+            last_capture_at: Some(counters.last_capture_at.load(Ordering::Relaxed))
+                .filter(|&at| at > 0),
+        }
+    }
+
+    /// Capture goes on without the file, so a failed write is only logged.
+    fn persist(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        // Snapshots are taken under the lock, so a newer one is never overwritten.
+        let _persisting = self
+            .persisting
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let written = serde_json::to_vec(&self.snapshot())
+            .map_err(io::Error::from)
+            .and_then(|contents| write_atomically(path, &contents));
+        if let Err(err) = written {
+            crate::log_eprintln!(
+                "warning: failed to write capture stats {}: {err}",
+                path.display()
+            );
+        }
+    }
+}
+
+/// The counters of the daemon in this session, or `None` if no daemon has written any.
+pub fn load_capture_stats() -> Result<Option<CaptureCounters>> {
+    let path = default_capture_stats_path();
+    let contents = match fs::read(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("failed to read capture stats: {}", path.display()))
+        }
+    };
+    let counters = serde_json::from_slice(&contents)
+        .with_context(|| format!("capture stats are not valid JSON: {}", path.display()))?;
+    Ok(Some(counters))
+}
+
+fn default_capture_stats_path() -> PathBuf {
+    runtime_dir().join(format!(
+        "slyboard-{}-{CAPTURE_STATS_FILE_SUFFIX}",
+        session_key()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{CaptureCounters, CaptureStats, SkipReason};
+    use crate::testing::ScratchStore;
+
+    #[test]
+    fn counts_and_keeps_the_file_in_sync() {
+        let store = ScratchStore::new("capture-stats");
+        let path = store.dir().join("captures.json");
+        let stats = CaptureStats::at(path.clone());
+        let read = || -> CaptureCounters {
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap()
+        };
+        assert_eq!(read().captured, 0, "written on start");
+
+        stats.captured();
+        stats.skipped(SkipReason::Blacklist);
+        stats.skipped(SkipReason::Paused);
+        stats.skipped(SkipReason::Blacklist);
+        stats.notified();
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.captured, 1);
+        assert!(snapshot.last_capture_at.is_some());
+        assert_eq!(
+            snapshot.skipped,
+            BTreeMap::from([(SkipReason::Blacklist, 2), (SkipReason::Paused, 1)])
+        );
+        assert_eq!(snapshot.notifications, 1);
+        assert_eq!(read(), snapshot);
+    }
+
+    #[test]
+    fn serializes_skip_reasons_by_name() {
+        let counters = CaptureCounters {
+            started_at: 1_700_000_000,
+            captured: 12,
+            skipped: BTreeMap::from([
+                (SkipReason::OwnRestore, 3),
+                (SkipReason::PausedWorkspace, 1),
+            ]),
+            notifications: 9,
+            last_capture_at: Some(1_700_007_000),
+        };
+        let json = serde_json::to_string(&counters).unwrap();
+        assert_eq!(
+            json,
+            r#"{"started_at":1700000000,"captured":12,"skipped":{"own-restore":3,"paused-workspace":1},"notifications":9,"last_capture_at":1700007000}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<CaptureCounters>(&json).unwrap(),
+            counters
+        );
+
+        assert_eq!(
+            counters.summary_lines(1_700_007_200),
+            [
+                "up 2h (since 1700000000)",
+                "captured 12, last 1700007000 (3m)",
+                "skipped 4: own restore 3, paused workspace 1",
+                "notifications 9",
+            ]
+        );
+    }
+}
@@ -0,0 +1,498 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::clipboard::backend::ClipboardBackend;
+use crate::clipboard::filters::ContentFilter;
+use crate::clipboard::poller::ClipboardPoller;
+use crate::clipboard::preview::format_byte_size;
+use crate::clipboard::storage::DatabaseHeader;
+use crate::clipboard::{ClipboardEntry, RecordOutcome, SharedClipboardState};
+use crate::config::ClipboardConfig;
+use crate::core::append_mode::AppendMode;
+use crate::core::capture_control::is_capture_paused;
+use crate::core::capture_stats::{CaptureCounters, CaptureStats, SkipReason};
+use crate::core::provider_stats::ProviderCounters;
+
+/// How often polling backends read the clipboard.
+pub const CLIPBOARD_POLL_INTERVAL_MS: u64 = 750;
+/// How long [`LimitWarning`] stays quiet after warning.
+const LIMIT_WARNING_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// What `slyboard status --json` and the HTTP `/status` endpoint report. Provider and capture
+/// counters are only known while the daemon runs.
+#[derive(Debug, Clone, Serialize)]
+pub struct DaemonStatus {
+    pub running: bool,
+    pub pid: Option<u32>,
+    pub paused: bool,
+    pub providers: Option<BTreeMap<String, ProviderCounters>>,
+    pub uptime_secs: Option<u64>,
+    pub captures: Option<CaptureCounters>,
+    /// [`SharedClipboardState::total_size`] of the history.
+    pub history_bytes: u64,
+    /// `None` until history is first saved.
+    pub database: Option<DatabaseHeader>,
+}
+
+/// Tells when history grows past `clipboard.size_warning_bytes`: once per crossing, so a
+/// history hovering above the threshold doesn't warn on every capture.
+#[derive(Debug, Clone, Default)]
+pub struct SizeWarning {
+    threshold: Option<u64>,
+    over: bool,
+}
+
+impl SizeWarning {
+    pub fn new(threshold: Option<u64>) -> Self {
+        Self {
+            threshold,
+            over: false,
+        }
+    }
+
+    /// The warning to show if `total` bytes is over the threshold and the last check wasn't.
+    pub fn check(&mut self, total: u64) -> Option<String> {
+        let threshold = self.threshold?;
+        let was_over = std::mem::replace(&mut self.over, total > threshold);
+        (self.over && !was_over).then(|| {
+            format!(
+                "clipboard history takes {}, over the {} warning size",
+                format_byte_size(total),
+                format_byte_size(threshold)
+            )
+        })
+    }
+}
+
+/// Tells when captures start discarding the oldest entries at the history limit: the first
+/// time in a session, then at most once an hour while it keeps happening.
+#[derive(Debug, Clone, Default)]
+pub struct LimitWarning {
+    enabled: bool,
+    last_warned: Option<Instant>,
+}
+
+impl LimitWarning {
+    /// Off with `clipboard.notifications.history_limit: false`.
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            last_warned: None,
+        }
+    }
+
+    /// The warning to show if recording `outcome` evicted entries at the limit.
+    pub fn check(&mut self, outcome: &RecordOutcome) -> Option<String> {
+        self.check_at(outcome, Instant::now())
+    }
+
+    fn check_at(&mut self, outcome: &RecordOutcome, now: Instant) -> Option<String> {
+        let limit = outcome.limit_reached.filter(|_| self.enabled)?;
+        if self
+            .last_warned
+            .is_some_and(|last| now.duration_since(last) < LIMIT_WARNING_INTERVAL)
+        {
+            return None;
+        }
+        self.last_warned = Some(now);
+        Some(format!(
+            "history limit reached ({limit}); oldest entries are being discarded — raise \
+             storage.history_limit to keep more"
+        ))
+    }
+}
+
+/// A poller over `backend` applying the capture rules of `clipboard_config`: blacklist,
+/// transient and expiring sources, incognito windows, paused workspaces and content filters.
+/// The daemon and `slyboard capture-once` both capture through one.
+pub fn configured_poller<B: ClipboardBackend>(
+    backend: B,
+    clipboard_config: &ClipboardConfig,
+    capture_stats: &CaptureStats,
+) -> ClipboardPoller<B> {
+    ClipboardPoller::new(backend, clipboard_config.active_window.blacklist_rules())
+        .with_transient_sources(
+            clipboard_config.active_window.transient_rules(),
+            Duration::from_secs(clipboard_config.active_window.transient_ttl_secs),
+        )
+        .with_expire_rules(clipboard_config.active_window.expire_rules())
+        .with_incognito_policy(
+            clipboard_config.incognito_policy,
+            clipboard_config.incognito_title_markers(),
+        )
+        .with_paused_workspaces(clipboard_config.paused_workspaces.clone())
+        .with_content_filter(ContentFilter::new(
+            clipboard_config.ignore_prefixes.clone(),
+            clipboard_config.ignore_globs.clone(),
+        ))
+        .with_max_window_lag(Duration::from_millis(CLIPBOARD_POLL_INTERVAL_MS))
+        .with_capture_stats(capture_stats.clone())
+        .with_dedup(clipboard_config.dedup.settings())
+}
+
+/// What `slyboard capture-once` did with the clipboard.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SingleCapture {
+    /// The entry as recorded, now the newest in history.
+    Recorded(Arc<ClipboardEntry>),
+    /// Empty, or what history already starts with.
+    Unchanged,
+    Skipped(SkipReason),
+}
+
+impl SingleCapture {
+    /// Exit status of `slyboard capture-once`; errors exit with 1, and 2 is left to usage
+    /// errors.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Recorded(_) => 0,
+            Self::Unchanged => 3,
+            Self::Skipped(_) => 4,
+        }
+    }
+
+    /// One line for the terminal, e.g. "recorded text (42 chars) from firefox".
+    pub fn summary(&self) -> String {
+        match self {
+            Self::Recorded(entry) => {
+                let what = match entry.as_ref() {
+                    ClipboardEntry::Text { value, .. } => {
+                        format!("text ({} chars)", value.chars().count())
+                    }
+                    ClipboardEntry::Image { width, height, .. } => {
+                        format!("{width}x{height} image")
+                    }
+                };
+                match entry
+                    .source_window()
+                    .and_then(|window| window.app_id.as_deref())
+                {
+                    Some(app) => format!("recorded {what} from {app}"),
+                    None => format!("recorded {what}"),
+                }
+            }
+            Self::Unchanged => "unchanged".to_string(),
+            Self::Skipped(reason) => format!("skipped: {}", reason.as_str()),
+        }
+    }
+}
+
+/// Reads the clipboard once through `poller` and records it like the daemon would, for
+/// capture driven by a hotkey or a compositor hook instead of a running poller. Nothing is
+/// read while capture is paused.
+pub fn capture_single<B: ClipboardBackend>(
+    poller: &mut ClipboardPoller<B>,
+    state: &SharedClipboardState,
+    paused: bool,
+    append: &AppendMode,
+) -> Result<SingleCapture> {
+    if paused {
+        return Ok(SingleCapture::Skipped(SkipReason::Paused));
+    }
+    let Some(entry) = poller.poll_once() else {
+        return Ok(poller
+            .last_skip()
+            .map_or(SingleCapture::Unchanged, SingleCapture::Skipped));
+    };
+    let newest = state.history_snapshot().into_iter().next();
+    let dedup = state.dedup_settings();
+    if newest.is_some_and(|newest| dedup.same_content(&newest, &entry)) {
+        return Ok(SingleCapture::Unchanged);
+    }
+    if append.collect(&entry)?.is_some() {
+        return Ok(SingleCapture::Skipped(SkipReason::Appended));
+    }
+    if !state.record(entry)?.inserted {
+        return Ok(SingleCapture::Unchanged);
+    }
+    let recorded = state.history_snapshot().into_iter().next();
+    Ok(recorded.map_or(SingleCapture::Unchanged, SingleCapture::Recorded))
+}
+
+/// Runs one capture step: polls the clipboard and records a new entry unless capture is
+/// paused or the text goes into the append buffer. The poller still remembers what it saw
+/// while paused, so resuming doesn't record content copied during the pause.
+pub fn capture_once<B: ClipboardBackend>(
+    poller: &mut ClipboardPoller<B>,
+    state: &SharedClipboardState,
+    paused: impl FnOnce() -> Result<bool>,
+    append: &AppendMode,
+) -> Result<Option<RecordOutcome>> {
+    let Some(entry) = poller.poll_once() else {
+        return Ok(None);
+    };
+    if paused()? || append.collect(&entry)?.is_some() {
+        return Ok(None);
+    }
+    Ok(Some(state.record(entry)?))
+}
+
+/// Polls `poller` every `interval` on a plain thread. This is the capture loop for platforms
+/// without a GTK main loop; Linux captures from the tray's main loop instead.
+pub fn spawn_capture_thread<B>(
+    mut poller: ClipboardPoller<B>,
+    state: SharedClipboardState,
+    interval: Duration,
+    mut size_warning: SizeWarning,
+    mut limit_warning: LimitWarning,
+) -> JoinHandle<()>
+where
+    B: ClipboardBackend + Send + 'static,
+{
+    let append = AppendMode::for_session();
+    thread::spawn(move || loop {
+        match capture_once(&mut poller, &state, is_capture_paused, &append) {
+            Ok(Some(outcome)) => {
+                let warnings = [
+                    size_warning.check(state.total_size()),
+                    limit_warning.check(&outcome),
+                ];
+                for warning in warnings.into_iter().flatten() {
+                    crate::log_eprintln!("warning: {warning}");
+                }
+            }
+            Ok(None) => {}
+            Err(err) => crate::log_eprintln!("failed to record clipboard history: {err:#}"),
+        }
+        if let Err(err) = state.reload_if_changed() {
+            crate::log_eprintln!("failed to reload clipboard history: {err:#}");
+        }
+        state.expire_transient_entries();
+        if let Err(err) = state.expire_entries() {
+            crate::log_eprintln!("failed to remove expired clipboard entries: {err:#}");
+        }
+        thread::sleep(interval);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        capture_once, capture_single, configured_poller, LimitWarning, SizeWarning,
+        LIMIT_WARNING_INTERVAL,
+    };
+    use crate::clipboard::backend::ClipboardBackend;
+    use crate::clipboard::dedup::DedupSettings;
+    use crate::clipboard::poller::ClipboardPoller;
+    use crate::clipboard::{ClipboardEntry, RecordOutcome, SharedClipboardState};
+    use crate::config::AppConfig;
+    use crate::core::append_mode::AppendMode;
+    use crate::core::capture_stats::CaptureStats;
+    use crate::testing::{raw_image, text, window, FakeClipboardBackend, ScratchStore};
+    use std::time::{Duration, Instant};
+
+    fn history_texts(state: &SharedClipboardState) -> Vec<String> {
+        state
+            .history_snapshot()
+            .iter()
+            .filter_map(|entry| match entry.as_ref() {
+                ClipboardEntry::Text { value, .. } => Some(value.clone()),
+                ClipboardEntry::Image { .. } => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn records_changes_and_skips_while_paused() {
+        let store = ScratchStore::new("daemon-pause");
+        let state = store.load(10).expect("load");
+        let append = AppendMode::at(store.dir().join("append.json"));
+        let backend = FakeClipboardBackend::new().with_entries([
+            text("first"),
+            text("first"),
+            text("during pause"),
+            text("second"),
+        ]);
+        let mut poller = ClipboardPoller::new(backend, Vec::new());
+
+        let outcome = capture_once(&mut poller, &state, || Ok(false), &append).unwrap();
+        assert!(outcome.is_some_and(|outcome| outcome.inserted));
+        assert!(capture_once(&mut poller, &state, || Ok(false), &append)
+            .unwrap()
+            .is_none());
+        assert!(capture_once(&mut poller, &state, || Ok(true), &append)
+            .unwrap()
+            .is_none());
+        capture_once(&mut poller, &state, || Ok(false), &append).unwrap();
+        assert!(capture_once(&mut poller, &state, || Ok(false), &append)
+            .unwrap()
+            .is_none());
+
+        assert_eq!(history_texts(&state), vec!["second", "first"]);
+    }
+
+    #[test]
+    fn records_repeated_copies_with_dedup_off_but_not_an_unchanged_clipboard() {
+        let copies = || ["a", "a", "b", "a", "a\n", "b"].map(text);
+        for (deduplicate, expected) in [(true, vec!["b", "a"]), (false, vec!["b", "a", "b", "a"])] {
+            for backend in [
+                FakeClipboardBackend::new(),
+                FakeClipboardBackend::new().reporting_each_copy(),
+            ] {
+                let each_copy = backend.reports_each_copy();
+                let store = ScratchStore::new("daemon-dedup-off");
+                let dedup = DedupSettings {
+                    enabled: deduplicate,
+                    ..DedupSettings::default()
+                };
+                let state = store.load_with_dedup(10, dedup).expect("load");
+                let append = AppendMode::at(store.dir().join("append.json"));
+                let mut poller = ClipboardPoller::new(backend.with_entries(copies()), Vec::new());
+
+                let outcomes = (0..6)
+                    .filter_map(|_| {
+                        capture_once(&mut poller, &state, || Ok(false), &append).unwrap()
+                    })
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    history_texts(&state),
+                    expected,
+                    "deduplicate: {deduplicate}, each copy: {each_copy}"
+                );
+                if !deduplicate {
+                    assert!(outcomes.iter().all(
+                        |outcome| outcome.inserted && outcome.deduplicated_from_index.is_none()
+                    ));
+                    assert_eq!(
+                        state.history_snapshot()[1].stable_id(),
+                        state.history_snapshot()[3].stable_id()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn captures_the_clipboard_once_and_says_what_happened() {
+        let store = ScratchStore::new("daemon-capture-single");
+        let state = store.load(10).expect("load");
+        let append = AppendMode::at(store.dir().join("append.json"));
+        let config: AppConfig = serde_yaml::from_str(
+            "clipboard:\n  active_window:\n    blacklist: [keepassxc]\n  ignore_prefixes: ['otp:']\n",
+        )
+        .unwrap();
+        // Every invocation is a new process, so a new poller.
+        let capture = |copied: Option<ClipboardEntry>, app: &str, paused: bool| {
+            let backend = FakeClipboardBackend::new()
+                .with_entries(copied)
+                .with_active_windows([Some(window(Some(app), "~"))]);
+            let clipboard = backend.handle();
+            let mut poller =
+                configured_poller(backend, &config.clipboard, &CaptureStats::default());
+            let captured = capture_single(&mut poller, &state, paused, &append).unwrap();
+            if paused {
+                assert!(clipboard.calls().is_empty(), "nothing is read while paused");
+            }
+            (captured.summary(), captured.exit_code())
+        };
+
+        assert_eq!(
+            capture(Some(text("cargo test")), "kitty", false),
+            ("recorded text (10 chars) from kitty".to_string(), 0)
+        );
+        assert_eq!(
+            capture(Some(text("cargo test")), "kitty", false),
+            ("unchanged".to_string(), 3)
+        );
+        assert_eq!(capture(None, "kitty", false), ("unchanged".to_string(), 3));
+        assert_eq!(
+            capture(Some(text("hunter2")), "keepassxc", false),
+            ("skipped: blacklist".to_string(), 4)
+        );
+        assert_eq!(
+            capture(Some(text("otp: 123456")), "kitty", false),
+            ("skipped: content filter".to_string(), 4)
+        );
+        assert_eq!(
+            capture(Some(text("while paused")), "kitty", true),
+            ("skipped: paused".to_string(), 4)
+        );
+        assert_eq!(
+            capture(Some(raw_image(2, 1, [0; 4])), "gimp", false),
+            ("recorded 2x1 image from gimp".to_string(), 0)
+        );
+        append.start().unwrap();
+        assert_eq!(
+            capture(Some(text("a quote")), "firefox", false),
+            ("skipped: appended".to_string(), 4)
+        );
+
+        assert_eq!(history_texts(&state), vec!["cargo test"]);
+        assert_eq!(state.history_snapshot().len(), 2, "and the image");
+    }
+
+    #[test]
+    fn collects_text_into_the_append_buffer() {
+        let store = ScratchStore::new("daemon-append");
+        let state = store.load(10).expect("load");
+        let append = AppendMode::at(store.dir().join("append.json"));
+        let backend = FakeClipboardBackend::new().with_entries([
+            text("quote one"),
+            raw_image(1, 1, [0; 4]),
+            text("quote two"),
+            text("after"),
+        ]);
+        let mut poller = ClipboardPoller::new(backend, Vec::new());
+
+        append.start().unwrap();
+        for _ in 0..3 {
+            capture_once(&mut poller, &state, || Ok(false), &append).unwrap();
+        }
+        let buffer = append.finish().unwrap().expect("append mode was on");
+        assert_eq!(buffer.pieces, ["quote one", "quote two"]);
+        assert_eq!(
+            state.history_snapshot().len(),
+            1,
+            "only the image is recorded"
+        );
+
+        capture_once(&mut poller, &state, || Ok(false), &append).unwrap();
+        assert_eq!(history_texts(&state), vec!["after"]);
+    }
+
+    #[test]
+    fn warns_once_each_time_history_crosses_the_size_threshold() {
+        assert_eq!(SizeWarning::default().check(u64::MAX), None);
+
+        let mut warning = SizeWarning::new(Some(2048));
+        assert_eq!(warning.check(2048), None);
+        assert_eq!(
+            warning.check(3072).as_deref(),
+            Some("clipboard history takes 3.0 KiB, over the 2.0 KiB warning size")
+        );
+        assert_eq!(warning.check(4096), None, "still over");
+        assert_eq!(warning.check(1024), None);
+        assert!(warning.check(2049).is_some(), "crossed again");
+    }
+
+    #[test]
+    fn warns_about_the_history_limit_at_most_hourly() {
+        let evicting = RecordOutcome {
+            inserted: true,
+            limit_reached: Some(50),
+            ..RecordOutcome::default()
+        };
+        let start = Instant::now();
+        let mut warning = LimitWarning::new(true);
+        assert_eq!(warning.check_at(&RecordOutcome::default(), start), None);
+        assert_eq!(
+            warning.check_at(&evicting, start).as_deref(),
+            Some(
+                "history limit reached (50); oldest entries are being discarded — raise \
+                 storage.history_limit to keep more"
+            )
+        );
+        let almost = start + LIMIT_WARNING_INTERVAL - Duration::from_secs(1);
+        assert_eq!(warning.check_at(&evicting, almost), None);
+        let later = start + LIMIT_WARNING_INTERVAL;
+        assert!(warning.check_at(&evicting, later).is_some(), "an hour on");
+
+        let mut silenced = LimitWarning::new(false);
+        assert_eq!(silenced.check_at(&evicting, start), None);
+    }
+}
@@ -0,0 +1,122 @@
+#[cfg(unix)]
+use std::fs::{File, OpenOptions};
+use std::io;
+#[cfg(unix)]
+use std::io::Write;
+#[cfg(unix)]
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::core::instance_lock::running_instance;
+use crate::core::session::{private_runtime_dir, session_key};
+
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Where `slyboard run --daemonize` sends stdout and stderr unless told otherwise, e.g.
+/// `$XDG_RUNTIME_DIR/slyboard-alice-x0.log`, in the private runtime dir so nobody else can
+/// plant a symlink there first.
+pub fn default_log_path() -> io::Result<PathBuf> {
+    Ok(private_runtime_dir()?.join(format!("slyboard-{}.log", session_key())))
+}
+
+/// Detaches from the terminal with the classic double fork: the calling process exits, the
+/// first child starts a new session and exits too, and only the grandchild returns, with
+/// stdin from `/dev/null`, stdout and stderr appended to `log_path`, and `/` as working
+/// directory.
+///
+/// Must be called before any thread is spawned (GTK, capture, watchers), since only the
+/// forking thread survives in the child.
+#[cfg(unix)]
+pub fn daemonize(log_path: &Path) -> io::Result<()> {
+    // Opened up front so a bad path is reported on the terminal.
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)?;
+    let null = File::open("/dev/null")?;
+    io::stdout().flush()?;
+    io::stderr().flush()?;
+
+    if fork()? {
+        std::process::exit(0);
+    }
+    // SAFETY: plain syscall without pointers; the child is not a process group leader.
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    if fork()? {
+        // SAFETY: exits the intermediate child without running the parent's atexit handlers.
+        unsafe { libc::_exit(0) };
+    }
+
+    std::env::set_current_dir("/")?;
+    for (source, target) in [(&null, 0), (&log, 1), (&log, 2)] {
+        // SAFETY: both descriptors are open for the duration of the call.
+        if unsafe { libc::dup2(source.as_raw_fd(), target) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// There is no fork to detach with elsewhere; start the daemon detached from the shell
+/// instead, e.g. with `start /b slyboard run` on Windows.
+#[cfg(not(unix))]
+pub fn daemonize(_log_path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--daemonize is only supported on Unix",
+    ))
+}
+
+/// Forks, returning `true` in the parent.
+#[cfg(unix)]
+fn fork() -> io::Result<bool> {
+    // SAFETY: the callers run before any other thread exists.
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+/// Sends SIGTERM to `pid` and waits up to `timeout` for the session's instance lock to be
+/// released. Returns whether it was.
+pub fn terminate(pid: u32, timeout: Duration) -> io::Result<bool> {
+    request_termination(pid)?;
+    let started = Instant::now();
+    while started.elapsed() < timeout {
+        if running_instance().is_none() {
+            return Ok(true);
+        }
+        thread::sleep(STOP_POLL_INTERVAL);
+    }
+    Ok(running_instance().is_none())
+}
+
+#[cfg(unix)]
+fn request_termination(pid: u32) -> io::Result<()> {
+    let pid = libc::pid_t::try_from(pid)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "pid out of range"))?;
+    // SAFETY: plain syscall without pointers.
+    if unsafe { libc::kill(pid, libc::SIGTERM) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Windows has no SIGTERM for a console process to handle, so the daemon is ended outright;
+/// every storage write is journaled, so at most the entry being captured is lost.
+#[cfg(windows)]
+fn request_termination(pid: u32) -> io::Result<()> {
+    let status = std::process::Command::new("taskkill")
+        .args(["/F", "/PID", &pid.to_string()])
+        .stdout(std::process::Stdio::null())
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("taskkill exited with {status}")));
+    }
+    Ok(())
+}
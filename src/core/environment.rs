@@ -0,0 +1,208 @@
+//! What kind of desktop slyboard runs in: the session type, the compositor, and which helper
+//! programs are on `PATH`. `slyboard setup` proposes settings from it, and `validate-config`
+//! checks configured commands with [`program_on_path`].
+
+use std::env;
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::Path;
+
+use crate::core::hyprland::INSTANCE_SIGNATURE_ENV;
+
+/// Programs slyboard can drive, looked up by [`Environment::detect`].
+pub const HELPER_PROGRAMS: &[&str] = &[
+    "wl-paste",
+    "wl-copy",
+    "xclip",
+    "xdotool",
+    "hyprctl",
+    "swaymsg",
+    "jq",
+    "rofi",
+    "notify-send",
+    "systemctl",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionType {
+    Wayland,
+    X11,
+}
+
+/// Compositors and desktops slyboard knows how to get the focused window from, or not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compositor {
+    Hyprland,
+    Sway,
+    Gnome,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Environment {
+    /// `None` outside a graphical session, e.g. over SSH.
+    pub session: Option<SessionType>,
+    pub compositor: Option<Compositor>,
+    /// The [`HELPER_PROGRAMS`] found on `PATH`.
+    pub programs: Vec<&'static str>,
+}
+
+impl Environment {
+    pub fn detect() -> Self {
+        Self::detect_from(|name| env::var(name).ok(), env::var_os("PATH").as_deref())
+    }
+
+    /// The session type from `$XDG_SESSION_TYPE`, else from whether `$WAYLAND_DISPLAY` or
+    /// `$DISPLAY` is set; the compositor from `$HYPRLAND_INSTANCE_SIGNATURE`, `$SWAYSOCK` or
+    /// `$XDG_CURRENT_DESKTOP`.
+    pub fn detect_from(var: impl Fn(&str) -> Option<String>, search_path: Option<&OsStr>) -> Self {
+        let var = |name: &str| var(name).filter(|value| !value.trim().is_empty());
+
+        let session = match var("XDG_SESSION_TYPE").as_deref() {
+            Some("wayland") => Some(SessionType::Wayland),
+            Some("x11") => Some(SessionType::X11),
+            _ if var("WAYLAND_DISPLAY").is_some() => Some(SessionType::Wayland),
+            _ if var("DISPLAY").is_some() => Some(SessionType::X11),
+            _ => None,
+        };
+        let desktop = var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+        let compositor = if var(INSTANCE_SIGNATURE_ENV).is_some() {
+            Some(Compositor::Hyprland)
+        } else if var("SWAYSOCK").is_some() {
+            Some(Compositor::Sway)
+        } else if desktop
+            .split(':')
+            .any(|name| name.eq_ignore_ascii_case("gnome"))
+        {
+            Some(Compositor::Gnome)
+        } else {
+            None
+        };
+        let programs = HELPER_PROGRAMS
+            .iter()
+            .copied()
+            .filter(|program| program_on_path(program, search_path))
+            .collect();
+
+        Self {
+            session,
+            compositor,
+            programs,
+        }
+    }
+
+    /// Whether `program`, one of the [`HELPER_PROGRAMS`], was found.
+    pub fn has(&self, program: &str) -> bool {
+        self.programs.contains(&program)
+    }
+}
+
+/// E.g. `Sway (Wayland)`, `X11`, or `no graphical session`.
+impl fmt::Display for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let session = self.session.map(|session| match session {
+            SessionType::Wayland => "Wayland",
+            SessionType::X11 => "X11",
+        });
+        let compositor = self.compositor.map(|compositor| match compositor {
+            Compositor::Hyprland => "Hyprland",
+            Compositor::Sway => "Sway",
+            Compositor::Gnome => "GNOME",
+        });
+        match (compositor, session) {
+            (Some(compositor), Some(session)) => write!(f, "{compositor} ({session})"),
+            (Some(name), None) | (None, Some(name)) => f.write_str(name),
+            (None, None) => f.write_str("no graphical session"),
+        }
+    }
+}
+
+/// Whether `program` would resolve to a file: as given when it contains a `/`, otherwise in
+/// one of the `search_path` directories.
+pub fn program_on_path(program: &str, search_path: Option<&OsStr>) -> bool {
+    if program.contains('/') {
+        return Path::new(program).is_file();
+    }
+    search_path.is_some_and(|search_path| {
+        env::split_paths(search_path).any(|dir| dir.join(program).is_file())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{program_on_path, Compositor, Environment, SessionType};
+    use crate::testing::ScratchStore;
+    use std::collections::HashMap;
+    use std::ffi::OsString;
+    use std::fs;
+
+    fn detect(vars: &[(&str, &str)], search_path: Option<&OsString>) -> Environment {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        Environment::detect_from(
+            |name| vars.get(name).map(|value| value.to_string()),
+            search_path.map(OsString::as_os_str),
+        )
+    }
+
+    #[test]
+    fn detects_session_type_and_compositor() {
+        let hyprland = detect(
+            &[
+                ("XDG_SESSION_TYPE", "wayland"),
+                ("HYPRLAND_INSTANCE_SIGNATURE", "abc_123"),
+            ],
+            None,
+        );
+        assert_eq!(hyprland.session, Some(SessionType::Wayland));
+        assert_eq!(hyprland.compositor, Some(Compositor::Hyprland));
+        assert_eq!(hyprland.to_string(), "Hyprland (Wayland)");
+
+        let sway = detect(
+            &[("WAYLAND_DISPLAY", "wayland-1"), ("SWAYSOCK", "/run/sway")],
+            None,
+        );
+        assert_eq!(sway.to_string(), "Sway (Wayland)");
+
+        let gnome = detect(
+            &[("DISPLAY", ":0"), ("XDG_CURRENT_DESKTOP", "ubuntu:GNOME")],
+            None,
+        );
+        assert_eq!(
+            (gnome.session, gnome.compositor),
+            (Some(SessionType::X11), Some(Compositor::Gnome))
+        );
+
+        let x11 = detect(
+            &[("XDG_SESSION_TYPE", "x11"), ("WAYLAND_DISPLAY", "")],
+            None,
+        );
+        assert_eq!(x11.to_string(), "X11");
+        assert_eq!(
+            detect(&[("XDG_SESSION_TYPE", "tty")], None).to_string(),
+            "no graphical session"
+        );
+    }
+
+    #[test]
+    fn finds_helper_programs_on_path() {
+        let store = ScratchStore::new("environment-path");
+        let (first, second) = (store.dir().join("bin"), store.dir().join("usr-bin"));
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        fs::write(first.join("wl-copy"), "").unwrap();
+        fs::write(second.join("rofi"), "").unwrap();
+        fs::create_dir_all(second.join("jq")).unwrap();
+        let search_path = std::env::join_paths([&first, &second]).unwrap();
+
+        let environment = detect(&[], Some(&search_path));
+        assert_eq!(environment.programs, ["wl-copy", "rofi"]);
+        assert!(
+            environment.has("rofi") && !environment.has("jq"),
+            "jq is a directory"
+        );
+        assert!(detect(&[], None).programs.is_empty());
+
+        let absolute = first.join("wl-copy");
+        assert!(program_on_path(absolute.to_str().unwrap(), None));
+        assert!(!program_on_path("wl-copy", Some(&OsString::new())));
+    }
+}
@@ -0,0 +1,197 @@
+//! The optional activity log (`events`): one JSON line per capture, restore, clear, pause, and
+//! resume, appended by whichever process did it. It is purely observational: nothing reads it
+//! back, and it is never trimmed to the history limit, only rotated by size like the log file.
+
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::clipboard::age::unix_now;
+use crate::clipboard::{CaptureOrigin, ClipboardEntry};
+use crate::config::EventsConfig;
+use crate::core::logging::RotatingFile;
+
+static EVENT_LOG: OnceLock<EventLogger> = OnceLock::new();
+
+/// Starts logging [`record`]ed events when `config` enables it. Only the first call has an
+/// effect.
+pub fn init(config: &EventsConfig) -> Result<()> {
+    let Some(path) = config.resolved_file()? else {
+        return Ok(());
+    };
+    let logger = EventLogger::open(&path, config.max_bytes(), config.log_content)
+        .with_context(|| format!("failed to open activity log {}", path.display()))?;
+    let _ = EVENT_LOG.set(logger);
+    Ok(())
+}
+
+/// Appends `event` to the activity log, if [`init`] turned it on.
+pub fn record(event: Event<'_>) {
+    if let Some(logger) = EVENT_LOG.get() {
+        if let Err(err) = logger.log(event) {
+            crate::log_eprintln!("warning: failed to write clipboard activity log: {err}");
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Event<'a> {
+    Capture(&'a ClipboardEntry),
+    Restore(&'a ClipboardEntry),
+    Clear,
+    Pause,
+    Resume,
+}
+
+impl Event<'_> {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Capture(_) => "capture",
+            Self::Restore(_) => "restore",
+            Self::Clear => "clear",
+            Self::Pause => "pause",
+            Self::Resume => "resume",
+        }
+    }
+}
+
+/// One line of the log. Entry fields are only present for captures and restores.
+#[derive(Debug, Serialize)]
+struct EventLine<'a> {
+    at: u64,
+    event: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origin: Option<CaptureOrigin>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_app: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<&'a str>,
+}
+
+pub struct EventLogger {
+    file: RotatingFile,
+    log_content: bool,
+}
+
+impl EventLogger {
+    pub fn open(path: &Path, max_bytes: u64, log_content: bool) -> io::Result<Self> {
+        Ok(Self {
+            file: RotatingFile::open(path, max_bytes)?,
+            log_content,
+        })
+    }
+
+    pub fn log(&self, event: Event<'_>) -> io::Result<()> {
+        self.file.append_line(&self.line(event, unix_now()))
+    }
+
+    /// The JSON line for `event` at Unix time `at`. Entries are identified by the hex
+    /// [`content_id`](ClipboardEntry::content_id) that also finds their duplicates; text is
+    /// only included with `log_content`.
+    pub fn line(&self, event: Event<'_>, at: u64) -> String {
+        let mut line = EventLine {
+            at,
+            event: event.name(),
+            hash: None,
+            kind: None,
+            size_bytes: None,
+            origin: None,
+            source_app: None,
+            content: None,
+        };
+        if let Event::Capture(entry) | Event::Restore(entry) = event {
+            line.hash = Some(format!("{:016x}", entry.content_id()));
+            line.size_bytes = Some(entry.approx_size_bytes());
+            line.origin = entry.captured_by();
+            line.source_app = entry
+                .source_window()
+                .and_then(|window| window.app_id.as_deref());
+            match entry {
+                ClipboardEntry::Text { value, .. } => {
+                    line.kind = Some("text");
+                    line.content = self.log_content.then_some(value.as_str());
+                }
+                ClipboardEntry::Image { .. } => line.kind = Some("image"),
+            }
+        }
+        serde_json::to_string(&line).expect("event lines always serialize")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, EventLogger};
+    use crate::clipboard::CaptureOrigin;
+    use crate::testing::{raw_image, text_from, window, ScratchStore};
+    use serde_json::{json, Value};
+
+    fn parse(line: &str) -> Value {
+        serde_json::from_str(line).expect("valid JSON")
+    }
+
+    #[test]
+    fn serializes_each_event_with_its_fields() {
+        let store = ScratchStore::new("events-fields");
+        let path = store.dir().join("events.jsonl");
+        let logger = EventLogger::open(&path, 1024 * 1024, false).unwrap();
+        let copied = text_from("hunter2", window(Some("firefox"), "Login"))
+            .with_captured_by(CaptureOrigin::GtkPoll);
+        let hash = format!("{:016x}", copied.content_id());
+
+        assert_eq!(
+            parse(&logger.line(Event::Capture(&copied), 100)),
+            json!({
+                "at": 100,
+                "event": "capture",
+                "hash": hash,
+                "kind": "text",
+                "size_bytes": 7,
+                "origin": "gtk-poll",
+                "source_app": "firefox",
+            })
+        );
+        assert_eq!(
+            parse(&logger.line(Event::Restore(&raw_image(2, 1, [0; 4])), 101)),
+            json!({
+                "at": 101,
+                "event": "restore",
+                "hash": format!("{:016x}", raw_image(2, 1, [0; 4]).content_id()),
+                "kind": "image",
+                "size_bytes": 8,
+            })
+        );
+        for (event, name) in [
+            (Event::Clear, "clear"),
+            (Event::Pause, "pause"),
+            (Event::Resume, "resume"),
+        ] {
+            assert_eq!(
+                parse(&logger.line(event, 102)),
+                json!({ "at": 102, "event": name })
+            );
+        }
+
+        let with_content = EventLogger::open(&path, 1024 * 1024, true).unwrap();
+        assert_eq!(
+            parse(&with_content.line(Event::Capture(&copied), 100))["content"],
+            "hunter2"
+        );
+
+        logger.log(Event::Pause).unwrap();
+        logger.log(Event::Capture(&copied)).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        let events: Vec<Value> = written.lines().map(parse).collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1]["event"], "capture");
+        assert!(events[1].get("content").is_none());
+    }
+}
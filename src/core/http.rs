@@ -0,0 +1,556 @@
+//! The read-only history endpoint behind `http.listen`, for scripts and status bars that would
+//! otherwise shell out to the CLI on every refresh.
+//!
+//! - `GET /history`: the same JSON as `slyboard history --json`.
+//! - `GET /history/<id>`: a text entry as `text/plain`, an image as `image/png`.
+//! - `GET /status`: the same JSON as `slyboard status --json`.
+//! - `POST /pause`, `POST /resume`: toggle capture, like `slyboard pause`/`resume`.
+//!
+//! Browsers can be made to send requests to a loopback port, so requests carrying `Origin`
+//! are refused, and so is a `Host` other than `localhost` or a loopback address unless remote
+//! clients are allowed, which keeps DNS-rebound pages out.
+
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::clipboard::age::unix_now;
+use crate::clipboard::filters::HistoryFilter;
+use crate::clipboard::json::write_json_history;
+use crate::clipboard::{ClipboardEntry, ImageEncoding, SharedClipboardState};
+use crate::core::capture_control::{
+    default_pause_path, is_capture_paused_at_path, set_capture_paused_at_path,
+};
+use crate::core::capture_stats::load_capture_stats;
+use crate::core::daemon::DaemonStatus;
+use crate::core::provider_stats::load_provider_stats;
+
+const JSON: &str = "application/json";
+const TEXT: &str = "text/plain; charset=utf-8";
+const PNG: &str = "image/png";
+
+/// Answers endpoint requests from the daemon's history; [`HttpServer`] puts it on a socket.
+#[derive(Clone)]
+pub struct HttpEndpoint {
+    state: SharedClipboardState,
+    token: Option<String>,
+    /// The session's pause marker unless set.
+    pause_path: Option<PathBuf>,
+    /// Accept any `Host`, for `http.allow_remote`.
+    remote_hosts: bool,
+}
+
+/// The request headers the endpoint looks at.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestHeaders<'a> {
+    pub host: Option<&'a str>,
+    pub origin: Option<&'a str>,
+    pub authorization: Option<&'a str>,
+}
+
+/// A response before it is written to the socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Reply {
+    fn new(status: u16, content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            content_type,
+            body: body.into(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::new(status, TEXT, format!("{message}\n"))
+    }
+}
+
+impl HttpEndpoint {
+    /// `token`, when set, must be sent as `Authorization: Bearer <token>`.
+    pub fn new(state: SharedClipboardState, token: Option<String>) -> Self {
+        Self {
+            state,
+            token,
+            pause_path: None,
+            remote_hosts: false,
+        }
+    }
+
+    /// Accepts requests addressed to any host name, not only loopback ones, when `allowed`.
+    pub fn with_remote_hosts(mut self, allowed: bool) -> Self {
+        self.remote_hosts = allowed;
+        self
+    }
+
+    /// Reads and writes the capture pause marker at `path` instead of the session's.
+    pub fn with_pause_path(mut self, path: PathBuf) -> Self {
+        self.pause_path = Some(path);
+        self
+    }
+
+    /// Routes one request.
+    pub fn handle(&self, method: &str, url: &str, headers: RequestHeaders) -> Reply {
+        if headers.origin.is_some() {
+            return Reply::error(403, "cross-origin requests are not allowed");
+        }
+        if !self.remote_hosts && !headers.host.is_none_or(is_loopback_host) {
+            return Reply::error(403, "Host must be localhost or a loopback address");
+        }
+        if !self.authorized(headers.authorization) {
+            return Reply::error(401, "missing or wrong bearer token");
+        }
+        let path = url.split('?').next().unwrap_or_default();
+        let segments: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+        let result = match (method, segments.as_slice()) {
+            ("GET", ["history"]) => self.history(),
+            ("GET", ["history", id]) => self.entry(id),
+            ("GET", ["status"]) => self.status(),
+            ("POST", ["pause"]) => self.set_paused(true),
+            ("POST", ["resume"]) => self.set_paused(false),
+            (_, ["history"] | ["history", _] | ["status"] | ["pause"] | ["resume"]) => {
+                Ok(Reply::error(405, "method not allowed"))
+            }
+            _ => Ok(Reply::error(404, "not found")),
+        };
+        result.unwrap_or_else(|err| Reply::error(500, &format!("{err:#}")))
+    }
+
+    fn authorized(&self, authorization: Option<&str>) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|sent| constant_time_eq(sent.trim().as_bytes(), token.as_bytes()))
+    }
+
+    /// Saved history oldest first, so a position is the id `slyboard history` prints.
+    /// Transient entries are never saved, so the CLI never lists them either.
+    fn history_by_id(&self) -> Vec<Arc<ClipboardEntry>> {
+        self.state
+            .history_snapshot()
+            .into_iter()
+            .rev()
+            .filter(|entry| !entry.is_transient())
+            .collect()
+    }
+
+    fn history(&self) -> Result<Reply> {
+        let history = self.history_by_id();
+        let entries = HistoryFilter::default().apply(&history);
+        let mut body = Vec::new();
+        write_json_history(&mut body, &entries, false, false)?;
+        Ok(Reply::new(200, JSON, body))
+    }
+
+    fn entry(&self, id: &str) -> Result<Reply> {
+        let history = self.history_by_id();
+        let Some(entry) = id.parse::<usize>().ok().and_then(|id| history.get(id)) else {
+            return Ok(Reply::error(404, "no such entry"));
+        };
+        // History keeps only a preview of long texts; the reply is the whole text.
+        let entry = self.state.whole_entry(entry)?;
+        match entry.as_ref() {
+            ClipboardEntry::Text { value, .. } => Ok(Reply::new(200, TEXT, value.as_bytes())),
+            ClipboardEntry::Image { .. } => match entry.into_owned().into_png_encoded() {
+                ClipboardEntry::Image {
+                    encoding: ImageEncoding::Png,
+                    pixels,
+                    ..
+                } => Ok(Reply::new(200, PNG, pixels)),
+                _ => Ok(Reply::error(500, "image layout cannot be encoded as PNG")),
+            },
+        }
+    }
+
+    fn pause_path(&self) -> Result<PathBuf> {
+        match &self.pause_path {
+            Some(path) => Ok(path.clone()),
+            None => default_pause_path(),
+        }
+    }
+
+    fn status(&self) -> Result<Reply> {
+        let captures = load_capture_stats()?;
+        let status = DaemonStatus {
+            running: true,
+            pid: Some(std::process::id()),
+            paused: is_capture_paused_at_path(&self.pause_path()?),
+            providers: load_provider_stats()?,
+            uptime_secs: captures
+                .as_ref()
+                .map(|captures| captures.uptime_secs(unix_now())),
+            captures,
+            history_bytes: self.state.total_size(),
+            database: self.state.database_header()?,
+        };
+        Ok(Reply::new(200, JSON, serde_json::to_vec(&status)?))
+    }
+
+    fn set_paused(&self, paused: bool) -> Result<Reply> {
+        set_capture_paused_at_path(&self.pause_path()?, paused)?;
+        Ok(Reply::new(
+            200,
+            JSON,
+            serde_json::to_vec(&serde_json::json!({ "paused": paused }))?,
+        ))
+    }
+}
+
+/// Serves an [`HttpEndpoint`] on a background thread until dropped.
+pub struct HttpServer {
+    server: Arc<tiny_http::Server>,
+    addr: SocketAddr,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HttpServer {
+    /// Binds `addr` (port 0 picks a free one, see [`local_addr`](Self::local_addr)) and starts
+    /// answering requests.
+    pub fn start(addr: SocketAddr, endpoint: HttpEndpoint) -> Result<Self> {
+        let server = tiny_http::Server::http(addr)
+            .map_err(|err| anyhow!("failed to listen on http://{addr}: {err}"))?;
+        let addr = server
+            .server_addr()
+            .to_ip()
+            .context("HTTP server is not listening on an IP address")?;
+        let server = Arc::new(server);
+        let thread = thread::Builder::new()
+            .name("slyboard-http".to_string())
+            .spawn({
+                let server = Arc::clone(&server);
+                move || serve(&server, &endpoint)
+            })
+            .context("failed to spawn HTTP server")?;
+        Ok(Self {
+            server,
+            addr,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Compares every byte whatever the first difference, so answer times don't tell how much
+/// of a guessed token was right.
+fn constant_time_eq(sent: &[u8], expected: &[u8]) -> bool {
+    sent.len() == expected.len()
+        && sent
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Whether a `Host` header names `localhost` or a loopback address, with or without a port.
+fn is_loopback_host(host: &str) -> bool {
+    let valid_port = |port: &str| port.parse::<u16>().is_ok();
+    let name = match host.trim().strip_prefix('[') {
+        Some(bracketed) => match bracketed.split_once(']') {
+            Some((ip, "")) => ip,
+            Some((ip, port)) if port.strip_prefix(':').is_some_and(valid_port) => ip,
+            _ => return false,
+        },
+        None => match host.trim().rsplit_once(':') {
+            Some((name, port)) if valid_port(port) => name,
+            Some(_) => return false,
+            None => host.trim(),
+        },
+    };
+    name.eq_ignore_ascii_case("localhost")
+        || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+fn serve(server: &tiny_http::Server, endpoint: &HttpEndpoint) {
+    // `recv` fails once the server is unblocked on drop.
+    while let Ok(request) = server.recv() {
+        let header = |name: &'static str| {
+            request
+                .headers()
+                .iter()
+                .find(|header| header.field.equiv(name))
+                .map(|header| header.value.as_str().to_string())
+        };
+        let (host, origin, authorization) =
+            (header("Host"), header("Origin"), header("Authorization"));
+        let reply = endpoint.handle(
+            request.method().as_str(),
+            request.url(),
+            RequestHeaders {
+                host: host.as_deref(),
+                origin: origin.as_deref(),
+                authorization: authorization.as_deref(),
+            },
+        );
+        let content_type = tiny_http::Header::from_bytes("Content-Type", reply.content_type)
+            .expect("static content type is a valid header");
+        let response = tiny_http::Response::from_data(reply.body)
+            .with_status_code(reply.status)
+            .with_header(content_type);
+        if let Err(err) = request.respond(response) {
+            crate::log_eprintln!("failed to answer HTTP request: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{constant_time_eq, is_loopback_host, HttpEndpoint, HttpServer};
+    use crate::clipboard::SharedClipboardState;
+    use crate::testing::{raw_image, text, ScratchStore};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    fn seeded_state(store: &ScratchStore) -> SharedClipboardState {
+        let state = store.load(10).expect("load");
+        state.record(text("first")).unwrap();
+        state.record(raw_image(1, 1, [255, 0, 0, 255])).unwrap();
+        state.record(text("second")).unwrap();
+        state
+    }
+
+    /// Sends one request and returns the status code and body.
+    fn request(
+        server: &HttpServer,
+        method: &str,
+        path: &str,
+        token: Option<&str>,
+    ) -> (u16, Vec<u8>) {
+        let authorization = token
+            .map(|token| format!("Authorization: Bearer {token}\r\n"))
+            .unwrap_or_default();
+        request_with_headers(
+            server,
+            method,
+            path,
+            &format!("Host: localhost\r\n{authorization}"),
+        )
+    }
+
+    /// Like [`request`], with `headers` (each ending in CRLF) instead of the default ones.
+    fn request_with_headers(
+        server: &HttpServer,
+        method: &str,
+        path: &str,
+        headers: &str,
+    ) -> (u16, Vec<u8>) {
+        let mut stream = TcpStream::connect(server.local_addr()).expect("connect");
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\n{headers}Content-Length: 0\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let split = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .expect("response has headers");
+        let head = String::from_utf8_lossy(&response[..split]);
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, response[split + 4..].to_vec())
+    }
+
+    #[test]
+    fn serves_history_entries_and_status() {
+        let store = ScratchStore::new("http-serve");
+        let endpoint = HttpEndpoint::new(seeded_state(&store), None)
+            .with_pause_path(store.dir().join("paused"));
+        let server = HttpServer::start("127.0.0.1:0".parse().unwrap(), endpoint).unwrap();
+
+        let (status, body) = request(&server, "GET", "/history", None);
+        assert_eq!(status, 200);
+        let history: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let history = history.as_array().unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0]["id"], 0);
+        assert_eq!(history[0]["value"], "first");
+        assert_eq!(history[1]["kind"], "image");
+
+        assert_eq!(
+            request(&server, "GET", "/history/2", None),
+            (200, b"second".to_vec())
+        );
+        let (status, png) = request(&server, "GET", "/history/1", None);
+        assert_eq!(status, 200);
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert_eq!(request(&server, "GET", "/history/9", None).0, 404);
+        assert_eq!(request(&server, "GET", "/history/nope", None).0, 404);
+
+        let (status, body) = request(&server, "GET", "/status", None);
+        assert_eq!(status, 200);
+        let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status["running"], true);
+        assert_eq!(status["paused"], false);
+        assert_eq!(status["history_bytes"], 5 + 4 + 6);
+
+        drop(server);
+    }
+
+    #[test]
+    fn pauses_and_resumes_capture() {
+        let store = ScratchStore::new("http-pause");
+        let pause_path = store.dir().join("paused");
+        let endpoint =
+            HttpEndpoint::new(seeded_state(&store), None).with_pause_path(pause_path.clone());
+        let server = HttpServer::start("127.0.0.1:0".parse().unwrap(), endpoint).unwrap();
+
+        assert_eq!(request(&server, "GET", "/pause", None).0, 405);
+        let (status, body) = request(&server, "POST", "/pause", None);
+        assert_eq!((status, body), (200, br#"{"paused":true}"#.to_vec()));
+        assert!(pause_path.exists());
+        let (_, body) = request(&server, "GET", "/status", None);
+        assert!(String::from_utf8(body)
+            .unwrap()
+            .contains(r#""paused":true"#));
+
+        assert_eq!(request(&server, "POST", "/resume", None).0, 200);
+        assert!(!pause_path.exists());
+        assert_eq!(request(&server, "POST", "/history", None).0, 405);
+        assert_eq!(request(&server, "GET", "/elsewhere", None).0, 404);
+
+        drop(server);
+    }
+
+    #[test]
+    fn requires_the_bearer_token_when_configured() {
+        let store = ScratchStore::new("http-token");
+        let endpoint = HttpEndpoint::new(seeded_state(&store), Some("s3cret".to_string()))
+            .with_pause_path(store.dir().join("paused"));
+        let server = HttpServer::start("127.0.0.1:0".parse().unwrap(), endpoint).unwrap();
+
+        assert_eq!(request(&server, "GET", "/history", None).0, 401);
+        assert_eq!(request(&server, "GET", "/history", Some("wrong")).0, 401);
+        assert_eq!(request(&server, "POST", "/pause", Some("wrong")).0, 401);
+        assert!(!store.dir().join("paused").exists());
+        assert_eq!(request(&server, "GET", "/history", Some("s3cret")).0, 200);
+
+        drop(server);
+    }
+
+    #[test]
+    fn refuses_foreign_hosts_and_cross_origin_requests() {
+        let store = ScratchStore::new("http-host");
+        let endpoint = HttpEndpoint::new(seeded_state(&store), None)
+            .with_pause_path(store.dir().join("paused"));
+        let server = HttpServer::start("127.0.0.1:0".parse().unwrap(), endpoint.clone()).unwrap();
+        let get = |headers: &str| request_with_headers(&server, "GET", "/history", headers).0;
+
+        let port = server.local_addr().port();
+        assert_eq!(get(&format!("Host: 127.0.0.1:{port}\r\n")), 200);
+        assert_eq!(get("Host: LOCALHOST\r\n"), 200);
+        assert_eq!(get("Host: [::1]:80\r\n"), 200);
+        assert_eq!(get("Host: rebound.example:80\r\n"), 403);
+        assert_eq!(
+            get("Host: localhost\r\nOrigin: https://page.example\r\n"),
+            403
+        );
+        assert_eq!(request(&server, "POST", "/pause", None).0, 200);
+        assert_eq!(
+            request_with_headers(&server, "POST", "/resume", "Host: 10.0.0.2\r\n").0,
+            403
+        );
+        assert!(store.dir().join("paused").exists());
+
+        let remote = HttpServer::start(
+            "127.0.0.1:0".parse().unwrap(),
+            endpoint.with_remote_hosts(true),
+        )
+        .unwrap();
+        let get = |headers: &str| request_with_headers(&remote, "GET", "/history", headers).0;
+        assert_eq!(get("Host: workstation.lan:7878\r\n"), 200);
+        assert_eq!(get("Host: workstation.lan\r\nOrigin: null\r\n"), 403);
+
+        drop((server, remote));
+    }
+
+    #[test]
+    fn leaves_transient_entries_out_of_history() {
+        let store = ScratchStore::new("http-transient");
+        let state = seeded_state(&store);
+        state
+            .record(text("one-time code").with_transient_ttl(Duration::from_secs(30)))
+            .unwrap();
+        let endpoint = HttpEndpoint::new(state, None).with_pause_path(store.dir().join("paused"));
+        let server = HttpServer::start("127.0.0.1:0".parse().unwrap(), endpoint).unwrap();
+
+        let (_, body) = request(&server, "GET", "/history", None);
+        let history: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(history.as_array().unwrap().len(), 3);
+        assert!(!String::from_utf8(body).unwrap().contains("one-time code"));
+        assert_eq!(
+            request(&server, "GET", "/history/2", None),
+            (200, b"second".to_vec())
+        );
+        assert_eq!(request(&server, "GET", "/history/3", None).0, 404);
+
+        drop(server);
+    }
+
+    #[test]
+    fn serves_the_whole_text_of_entries_cut_to_a_preview() {
+        let store = ScratchStore::new("http-long-text");
+        let state = store.load(10).expect("load");
+        state.set_text_preview_limit(Some(8));
+        let whole = "a text much longer than its preview";
+        state.record(text(whole)).unwrap();
+        assert!(state.history_snapshot()[0].full_text().is_some());
+        let endpoint = HttpEndpoint::new(state, None).with_pause_path(store.dir().join("paused"));
+        let server = HttpServer::start("127.0.0.1:0".parse().unwrap(), endpoint).unwrap();
+
+        assert_eq!(
+            request(&server, "GET", "/history/0", None),
+            (200, whole.as_bytes().to_vec())
+        );
+
+        drop(server);
+    }
+
+    #[test]
+    fn recognizes_loopback_hosts_and_compares_tokens_fully() {
+        for host in [
+            "localhost",
+            "localhost:7878",
+            "127.0.0.1",
+            "127.8.0.1:1",
+            "[::1]",
+        ] {
+            assert!(is_loopback_host(host), "{host}");
+        }
+        for host in [
+            "",
+            "example.com",
+            "localhost.example",
+            "::1",
+            "[::1]x",
+            "127.0.0.1:x",
+        ] {
+            assert!(!is_loopback_host(host), "{host}");
+        }
+
+        assert!(constant_time_eq(b"s3cret", b"s3cret"));
+        assert!(!constant_time_eq(b"s3creT", b"s3cret"));
+        assert!(!constant_time_eq(b"s3cre", b"s3cret"));
+    }
+}
@@ -0,0 +1,602 @@
+//! Hyprland's IPC socket, which `hyprctl` itself talks to. Asking it directly saves spawning
+//! `hyprctl` for every capture, which adds tens of milliseconds and fails when Hyprland is
+//! busy enough to throttle its CLI.
+//!
+//! The daemon also follows Hyprland's event socket, which reports every focus change, so a
+//! copy is credited to the window focused at that moment without asking anything.
+
+use std::env;
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::core::active_window::{
+    hyprctl_active_window, ActiveWindowContext, ActiveWindowProvider, CaptureOutcome,
+    PROVIDER_TIMEOUT,
+};
+
+pub const INSTANCE_SIGNATURE_ENV: &str = "HYPRLAND_INSTANCE_SIGNATURE";
+const ACTIVE_WINDOW_REQUEST: &[u8] = b"j/activewindow";
+const EVENTS_BACKEND: &str = "hyprland-events";
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// The request socket of the Hyprland instance this process runs under, or `None` outside
+/// Hyprland.
+pub fn request_socket_path() -> Option<PathBuf> {
+    socket_path(".socket.sock")
+}
+
+/// The socket Hyprland writes its events to, one per line.
+pub fn event_socket_path() -> Option<PathBuf> {
+    socket_path(".socket2.sock")
+}
+
+/// Hyprland 0.40 moved its sockets from `/tmp/hypr` to the runtime directory; the old place
+/// is used when the new one doesn't exist.
+fn socket_path(file_name: &str) -> Option<PathBuf> {
+    let signature = env::var_os(INSTANCE_SIGNATURE_ENV).filter(|value| !value.is_empty())?;
+    let under = |root: &Path| root.join("hypr").join(&signature).join(file_name);
+    let current = env::var_os("XDG_RUNTIME_DIR").map(|dir| under(Path::new(&dir)));
+    match current {
+        Some(path) if path.exists() => Some(path),
+        _ => Some(under(Path::new("/tmp")))
+            .filter(|path| path.exists())
+            .or(current),
+    }
+}
+
+/// Reads the focused window as `hyprctl activewindow -j` does, over a connection kept open
+/// between captures. A connection the compositor closed is replaced once per capture, so a
+/// Hyprland that answers one request per connection still works, just without the saving.
+pub struct HyprlandSocketProvider {
+    path: PathBuf,
+    connection: Mutex<Option<UnixStream>>,
+    timeout: Duration,
+}
+
+impl HyprlandSocketProvider {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            connection: Mutex::new(None),
+            timeout: PROVIDER_TIMEOUT,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn connect(&self) -> io::Result<UnixStream> {
+        let stream = UnixStream::connect(&self.path)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        Ok(stream)
+    }
+
+    /// Sends the request and reads one JSON reply. A closed connection reads as
+    /// `UnexpectedEof`.
+    fn exchange(stream: &mut UnixStream) -> io::Result<Value> {
+        stream.write_all(ACTIVE_WINDOW_REQUEST)?;
+        let reply = serde_json::Deserializer::from_reader(&*stream)
+            .into_iter::<Value>()
+            .next();
+        match reply {
+            Some(Ok(value)) => Ok(value),
+            Some(Err(err)) if err.is_io() => Err(err.into()),
+            Some(Err(err)) => Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+            None => Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
+
+impl ActiveWindowProvider for HyprlandSocketProvider {
+    fn probe(&self) -> CaptureOutcome {
+        let mut connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let reused = connection.is_some();
+        let mut reply = match connection.as_mut() {
+            Some(stream) => Self::exchange(stream),
+            None => Err(io::ErrorKind::NotConnected.into()),
+        };
+        if reply.as_ref().is_err_and(|err| !is_timeout(err)) {
+            *connection = None;
+            reply = self.connect().and_then(|mut stream| {
+                let reply = Self::exchange(&mut stream);
+                *connection = Some(stream);
+                reply
+            });
+        }
+        let path = self.path.display();
+        let value = match reply {
+            Ok(value) => value,
+            Err(err) => {
+                *connection = None;
+                return if is_timeout(&err) {
+                    CaptureOutcome::TimedOut
+                } else if reused && err.kind() == io::ErrorKind::UnexpectedEof {
+                    CaptureOutcome::Failed(format!("{path} closed the connection"))
+                } else {
+                    CaptureOutcome::Failed(format!("failed to query {path}: {err}"))
+                };
+            }
+        };
+        match hyprctl_active_window(&value) {
+            Some(mut context) => {
+                context.backend = "hyprland".to_string();
+                CaptureOutcome::Captured(Box::new(context))
+            }
+            None => CaptureOutcome::Failed("Hyprland reports no active window".to_string()),
+        }
+    }
+}
+
+/// A line of Hyprland's event socket that changes what is focused.
+#[derive(Debug, Clone, PartialEq)]
+enum FocusEvent {
+    /// `activewindow>>CLASS,TITLE`; both are empty when focus left all windows.
+    Window { app_id: String, title: String },
+    /// `activewindowv2>>ADDRESS`, right after `activewindow`.
+    Address(String),
+    /// `windowtitlev2>>ADDRESS,TITLE`, for any window.
+    Title { window_id: String, title: String },
+    /// `movewindowv2>>ADDRESS,WORKSPACEID,WORKSPACENAME`, for any window.
+    Moved {
+        window_id: String,
+        workspace_id: Option<i64>,
+        workspace_name: String,
+    },
+}
+
+/// Events carry addresses without the `0x` that `activewindow` replies have.
+fn window_address(raw: &str) -> String {
+    format!("0x{}", raw.trim().trim_start_matches("0x"))
+}
+
+fn parse_event(line: &str) -> Option<FocusEvent> {
+    let (name, data) = line.split_once(">>")?;
+    match name {
+        "activewindow" => {
+            let (app_id, title) = data.split_once(',').unwrap_or((data, ""));
+            Some(FocusEvent::Window {
+                app_id: app_id.trim().to_string(),
+                title: title.trim().to_string(),
+            })
+        }
+        "activewindowv2" => Some(FocusEvent::Address(data.trim().to_string())),
+        "windowtitlev2" => {
+            let (address, title) = data.split_once(',')?;
+            Some(FocusEvent::Title {
+                window_id: window_address(address),
+                title: title.trim().to_string(),
+            })
+        }
+        "movewindowv2" => {
+            let mut fields = data.splitn(3, ',');
+            let window_id = window_address(fields.next()?);
+            let workspace_id = fields.next()?.trim().parse().ok();
+            Some(FocusEvent::Moved {
+                window_id,
+                workspace_id,
+                workspace_name: fields.next()?.trim().to_string(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// What the event socket last said about focus.
+#[derive(Debug, Default)]
+enum Focus {
+    /// Nothing since the socket was connected; Hyprland only reports changes.
+    #[default]
+    Unknown,
+    NoWindow,
+    Window(Box<ActiveWindowContext>),
+}
+
+/// The focused window as Hyprland's events last described it, shared by the thread reading
+/// them and the provider answering captures from it.
+#[derive(Debug, Clone, Default)]
+pub struct CurrentWindow {
+    focus: Arc<Mutex<Focus>>,
+}
+
+impl CurrentWindow {
+    fn lock(&self) -> MutexGuard<'_, Focus> {
+        self.focus.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Applies one line of the event socket; lines about anything but focus are ignored.
+    /// Returns whether it named a newly focused window, whose details are worth asking for.
+    pub fn apply(&self, line: &str) -> bool {
+        let Some(event) = parse_event(line) else {
+            return false;
+        };
+        let mut focus = self.lock();
+        match (event, &mut *focus) {
+            (FocusEvent::Window { title, .. }, focus) if title.is_empty() => {
+                *focus = Focus::NoWindow;
+            }
+            (FocusEvent::Window { app_id, title }, focus) => {
+                *focus = Focus::Window(Box::new(ActiveWindowContext {
+                    backend: EVENTS_BACKEND.to_string(),
+                    title,
+                    app_id: Some(app_id).filter(|app_id| !app_id.is_empty()),
+                    initial_app_id: None,
+                    initial_title: None,
+                    window_id: None,
+                    pid: None,
+                    workspace_id: None,
+                    workspace_name: None,
+                    is_xwayland: None,
+                    monitor: None,
+                    geometry: None,
+                }));
+            }
+            (FocusEvent::Address(address), Focus::Window(window)) if !address.is_empty() => {
+                window.window_id = Some(window_address(&address));
+                return true;
+            }
+            (FocusEvent::Title { window_id, title }, Focus::Window(window))
+                if window.window_id.as_ref() == Some(&window_id) && !title.is_empty() =>
+            {
+                window.title = title;
+            }
+            (
+                FocusEvent::Moved {
+                    window_id,
+                    workspace_id,
+                    workspace_name,
+                },
+                Focus::Window(window),
+            ) if window.window_id.as_ref() == Some(&window_id) => {
+                window.workspace_id = workspace_id;
+                window.workspace_name = Some(workspace_name).filter(|name| !name.is_empty());
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Takes what an `activewindow` reply tells beyond events (pid, workspace, geometry, ...)
+    /// if `details` is about the window still focused.
+    pub fn enrich(&self, details: ActiveWindowContext) {
+        if let Focus::Window(window) = &mut *self.lock() {
+            if details.window_id.is_some() && details.window_id == window.window_id {
+                **window = ActiveWindowContext {
+                    backend: EVENTS_BACKEND.to_string(),
+                    ..details
+                };
+            }
+        }
+    }
+
+    /// Forgets the focus, as changes go unseen while the socket is closed.
+    pub fn forget(&self) {
+        *self.lock() = Focus::Unknown;
+    }
+
+    fn outcome(&self) -> CaptureOutcome {
+        match &*self.lock() {
+            Focus::Unknown => CaptureOutcome::Failed(
+                "no focus change seen on Hyprland's event socket".to_string(),
+            ),
+            Focus::NoWindow => {
+                CaptureOutcome::Failed("Hyprland reports no active window".to_string())
+            }
+            Focus::Window(window) => CaptureOutcome::Captured(window.clone()),
+        }
+    }
+}
+
+/// Answers captures from the focus Hyprland's event socket last reported, followed on a
+/// background thread. A capture then costs nothing and names the window focused when the copy
+/// happened, even when a query would come after the user switched windows. Fails while the
+/// socket is closed or before the first focus change, leaving the capture to a query.
+pub struct HyprlandEventProvider {
+    current: CurrentWindow,
+}
+
+impl HyprlandEventProvider {
+    /// Follows the events at `path`, asking `query` for the details of each newly focused
+    /// window, and reconnects whenever the socket closes, e.g. as Hyprland restarts.
+    pub fn watch(path: PathBuf, query: Option<HyprlandSocketProvider>) -> Self {
+        Self::watch_retrying(path, query, RECONNECT_DELAY)
+    }
+
+    fn watch_retrying(
+        path: PathBuf,
+        query: Option<HyprlandSocketProvider>,
+        retry_delay: Duration,
+    ) -> Self {
+        let current = CurrentWindow::default();
+        let watched = Arc::downgrade(&current.focus);
+        let spawned = thread::Builder::new()
+            .name("slyboard-hyprland-events".to_string())
+            .spawn(move || follow_events(&path, query.as_ref(), &watched, retry_delay));
+        if let Err(err) = spawned {
+            crate::log_eprintln!("warning: failed to follow Hyprland's events: {err}");
+        }
+        Self { current }
+    }
+}
+
+impl ActiveWindowProvider for HyprlandEventProvider {
+    fn probe(&self) -> CaptureOutcome {
+        self.current.outcome()
+    }
+}
+
+/// Reads events into the cell behind `watched` until its provider is dropped.
+fn follow_events(
+    path: &Path,
+    query: Option<&HyprlandSocketProvider>,
+    watched: &Weak<Mutex<Focus>>,
+    retry_delay: Duration,
+) {
+    while let Some(focus) = watched.upgrade() {
+        let current = CurrentWindow { focus };
+        if let Ok(stream) = UnixStream::connect(path) {
+            for line in BufReader::new(stream).lines() {
+                let Ok(line) = line else { break };
+                if current.apply(&line) {
+                    if let Some(details) = query.and_then(HyprlandSocketProvider::capture) {
+                        current.enrich(details);
+                    }
+                }
+            }
+            current.forget();
+            crate::log_eprintln!(
+                "warning: {} closed; reconnecting, and querying Hyprland at each copy meanwhile",
+                path.display()
+            );
+        }
+        drop(current);
+        thread::sleep(retry_delay);
+    }
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Hyprland only runs on Unix. Elsewhere its sockets never exist, so connecting always fails
+/// and nothing else is ever reached.
+#[cfg(not(unix))]
+struct UnixStream(std::convert::Infallible);
+
+#[cfg(not(unix))]
+impl UnixStream {
+    fn connect(_path: impl AsRef<Path>) -> io::Result<Self> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        match self.0 {}
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        match self.0 {}
+    }
+}
+
+#[cfg(not(unix))]
+impl io::Read for UnixStream {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        match self.0 {}
+    }
+}
+
+#[cfg(not(unix))]
+impl io::Read for &UnixStream {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        match self.0 {}
+    }
+}
+
+#[cfg(not(unix))]
+impl Write for UnixStream {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        match self.0 {}
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.0 {}
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::{CurrentWindow, HyprlandEventProvider, HyprlandSocketProvider};
+    use crate::core::active_window::{ActiveWindowContext, ActiveWindowProvider, CaptureOutcome};
+    use crate::testing::ScratchStore;
+
+    /// Answers each request on a connection with the next title, as `j/activewindow` JSON
+    /// (`{}` for no window), and hangs up after `per_connection` answers.
+    fn fake_hyprland(
+        listener: UnixListener,
+        titles: Vec<&'static str>,
+        per_connection: usize,
+    ) -> Arc<AtomicUsize> {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&connections);
+        thread::spawn(move || {
+            let mut titles = titles.into_iter();
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { return };
+                counted.fetch_add(1, Ordering::SeqCst);
+                for _ in 0..per_connection {
+                    let mut request = [0; 64];
+                    let Ok(read) = stream.read(&mut request) else {
+                        break;
+                    };
+                    assert_eq!(&request[..read], b"j/activewindow");
+                    let reply = match titles.next() {
+                        Some("") => "{}".to_string(),
+                        Some(title) => format!(
+                            r#"{{"class":"kitty","title":"{title}","workspace":{{"id":2,"name":"2"}}}}"#
+                        ),
+                        None => return,
+                    };
+                    if stream.write_all(reply.as_bytes()).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        connections
+    }
+
+    fn title(provider: &HyprlandSocketProvider) -> Option<String> {
+        provider.capture().map(|context| {
+            assert_eq!(context.backend, "hyprland");
+            context.title
+        })
+    }
+
+    #[test]
+    fn keeps_the_connection_between_captures_and_reconnects_when_it_closes() {
+        let store = ScratchStore::new("hyprland-socket");
+        let path = store.dir().join(".socket.sock");
+        let listener = UnixListener::bind(&path).expect("bind");
+        let connections = fake_hyprland(listener, vec!["one", "two", "", "three"], 3);
+        let provider = HyprlandSocketProvider::new(path);
+
+        assert_eq!(title(&provider).as_deref(), Some("one"));
+        assert_eq!(title(&provider).as_deref(), Some("two"));
+        assert_eq!(
+            provider.probe(),
+            CaptureOutcome::Failed("Hyprland reports no active window".to_string())
+        );
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+        // The fake hung up after three answers.
+        assert_eq!(title(&provider).as_deref(), Some("three"));
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn reports_a_missing_socket_and_a_silent_compositor() {
+        let store = ScratchStore::new("hyprland-silent");
+        let missing = HyprlandSocketProvider::new(store.dir().join("missing.sock"));
+        assert!(matches!(missing.probe(), CaptureOutcome::Failed(_)));
+
+        let path = store.dir().join(".socket.sock");
+        let _listener = UnixListener::bind(&path).expect("bind");
+        let silent = HyprlandSocketProvider::new(path).with_timeout(Duration::from_millis(50));
+        assert_eq!(silent.probe(), CaptureOutcome::TimedOut);
+    }
+
+    fn focused(current: &CurrentWindow) -> Option<ActiveWindowContext> {
+        current.outcome().into_context()
+    }
+
+    #[test]
+    fn follows_focus_through_event_lines() {
+        let current = CurrentWindow::default();
+        assert_eq!(
+            current.outcome(),
+            CaptureOutcome::Failed("no focus change seen on Hyprland's event socket".to_string())
+        );
+
+        assert!(!current.apply("activewindow>>firefox,Docs, drafts"));
+        assert!(current.apply("activewindowv2>>55d1e2f0"));
+        assert!(!current.apply("workspace>>3"));
+        let window = focused(&current).expect("focused");
+        assert_eq!(window.backend, "hyprland-events");
+        assert_eq!(window.app_id.as_deref(), Some("firefox"));
+        assert_eq!(window.title, "Docs, drafts");
+        assert_eq!(window.window_id.as_deref(), Some("0x55d1e2f0"));
+
+        current.apply("windowtitlev2>>77aa,Another window");
+        current.apply("windowtitlev2>>55d1e2f0,Inbox");
+        current.apply("movewindowv2>>55d1e2f0,4,mail");
+        let window = focused(&current).expect("focused");
+        assert_eq!(window.title, "Inbox");
+        assert_eq!(
+            (window.workspace_id, window.workspace_name.as_deref()),
+            (Some(4), Some("mail"))
+        );
+
+        current.apply("activewindow>>,");
+        assert!(!current.apply("activewindowv2>>"));
+        assert_eq!(
+            current.outcome(),
+            CaptureOutcome::Failed("Hyprland reports no active window".to_string())
+        );
+    }
+
+    #[test]
+    fn takes_query_details_only_for_the_window_still_focused() {
+        let current = CurrentWindow::default();
+        current.apply("activewindow>>kitty,~/src");
+        current.apply("activewindowv2>>abc");
+        let details = |window_id: &str| ActiveWindowContext {
+            window_id: Some(window_id.to_string()),
+            pid: Some(4242),
+            ..crate::testing::window(Some("kitty"), "~/src")
+        };
+
+        current.enrich(details("0xdef"));
+        assert_eq!(focused(&current).and_then(|window| window.pid), None);
+        current.enrich(details("0xabc"));
+        let window = focused(&current).expect("focused");
+        assert_eq!(
+            (window.pid, window.backend.as_str()),
+            (Some(4242), "hyprland-events")
+        );
+    }
+
+    #[test]
+    fn forgets_focus_while_the_event_socket_is_closed_and_reconnects() {
+        let store = ScratchStore::new("hyprland-events");
+        let path = store.dir().join(".socket2.sock");
+        let listener = UnixListener::bind(&path).expect("bind");
+        let (hang_up, hung_up) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            for lines in [
+                "activewindow>>kitty,first\n",
+                "activewindow>>kitty,second\n",
+            ] {
+                let Ok((mut stream, _)) = listener.accept() else {
+                    return;
+                };
+                let _ = stream.write_all(lines.as_bytes());
+                let _ = hung_up.recv();
+            }
+        });
+        let provider = HyprlandEventProvider::watch_retrying(path, None, Duration::from_millis(10));
+        let title_becomes = |expected: Option<&str>| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while provider.capture().map(|window| window.title).as_deref() != expected {
+                assert!(Instant::now() < deadline, "never saw {expected:?}");
+                thread::sleep(Duration::from_millis(5));
+            }
+        };
+
+        title_becomes(Some("first"));
+        hang_up.send(()).unwrap();
+        title_becomes(Some("second"));
+        hang_up.send(()).unwrap();
+        title_becomes(None);
+    }
+}
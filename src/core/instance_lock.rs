@@ -1,74 +1,164 @@
 use std::fs;
+use std::io;
+#[cfg(unix)]
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use anyhow::{bail, Context, Result};
+use crate::core::session::{private_runtime_dir, runtime_dir, session_key};
+use crate::error::{Error, Result};
 
 pub struct InstanceLock {
     path: PathBuf,
-    _listener: UnixListener,
+    _handle: LockHandle,
 }
 
+/// Held open for as long as the lock is: a listening socket on Unix, where a connection
+/// attempt tells whether anyone still listens, and a file nobody else may open on Windows.
+#[cfg(unix)]
+type LockHandle = UnixListener;
+#[cfg(windows)]
+type LockHandle = fs::File;
+
 impl InstanceLock {
     pub fn acquire() -> Result<Self> {
-        let lock_path = default_lock_path();
-        acquire_from_path(lock_path)
+        acquire_from_path(default_lock_path()?)
+    }
+}
+
+/// The daemon holding this session's instance lock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunningInstance {
+    /// `None` when the holder didn't record one (older versions).
+    pub pid: Option<u32>,
+    pub lock_path: PathBuf,
+}
+
+/// The daemon running in this session, if any. A leftover lock socket nobody listens on,
+/// e.g. after the daemon was killed, does not count.
+pub fn running_instance() -> Option<RunningInstance> {
+    running_instance_at(default_lock_path().ok()?)
+}
+
+fn running_instance_at(lock_path: PathBuf) -> Option<RunningInstance> {
+    if !is_held(&lock_path) {
+        return None;
     }
+    Some(RunningInstance {
+        pid: read_pid(&lock_path),
+        lock_path,
+    })
 }
 
 impl Drop for InstanceLock {
     fn drop(&mut self) {
+        let _ = fs::remove_file(pid_path(&self.path));
         let _ = fs::remove_file(&self.path);
     }
 }
 
 fn acquire_from_path(path: PathBuf) -> Result<InstanceLock> {
     if path.exists() {
-        if UnixStream::connect(&path).is_ok() {
-            bail!(
-                "another slyboard instance is already running (lock: {})",
-                path.display()
-            );
+        if let Some(RunningInstance { pid, lock_path }) = running_instance_at(path.clone()) {
+            return Err(Error::LockHeld {
+                pid,
+                path: lock_path,
+            });
         }
 
-        fs::remove_file(&path).with_context(|| {
-            format!(
-                "failed to remove stale slyboard instance lock file: {}",
-                path.display()
-            )
-        })?;
+        if let Err(source) = fs::remove_file(&path) {
+            return Err(Error::LockIo {
+                action: "remove stale slyboard instance lock file",
+                path,
+                source,
+            });
+        }
     }
 
-    let listener = UnixListener::bind(&path).with_context(|| {
-        format!(
-            "failed to create slyboard instance lock socket: {}",
-            path.display()
-        )
-    })?;
+    let handle = match create_handle(&path) {
+        Ok(handle) => handle,
+        Err(source) => {
+            return Err(Error::LockIo {
+                action: "create slyboard instance lock socket",
+                path,
+                source,
+            })
+        }
+    };
+    // Names the holder in `Error::LockHeld` and lets `slyboard stop` signal it; without it
+    // the daemon still runs, so a failed write isn't fatal.
+    let _ = fs::write(pid_path(&path), std::process::id().to_string());
 
     Ok(InstanceLock {
         path,
-        _listener: listener,
+        _handle: handle,
     })
 }
 
-fn default_lock_path() -> PathBuf {
-    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
-        .map(PathBuf::from)
-        .unwrap_or_else(std::env::temp_dir);
-    runtime_dir.join(format!("slyboard-{}.sock", user_hint()))
+#[cfg(unix)]
+fn create_handle(path: &Path) -> io::Result<LockHandle> {
+    UnixListener::bind(path)
+}
+
+#[cfg(unix)]
+fn is_held(lock_path: &Path) -> bool {
+    UnixStream::connect(lock_path).is_ok()
+}
+
+/// Shared for deletion only, so the holder can remove it on drop while [`is_held`] can't
+/// open it.
+#[cfg(windows)]
+fn create_handle(path: &Path) -> io::Result<LockHandle> {
+    use std::os::windows::fs::OpenOptionsExt;
+    const FILE_SHARE_DELETE: u32 = 0x4;
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .share_mode(FILE_SHARE_DELETE)
+        .open(path)
+}
+
+#[cfg(windows)]
+fn is_held(lock_path: &Path) -> bool {
+    use std::os::windows::fs::OpenOptionsExt;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    fs::OpenOptions::new()
+        .read(true)
+        .share_mode(0)
+        .open(lock_path)
+        .is_err_and(|err| err.raw_os_error() == Some(ERROR_SHARING_VIOLATION))
+}
+
+/// One daemon per graphical session, see [`session_key`]. In the private runtime dir, so
+/// another user can neither bind the socket first nor plant a pid for `slyboard stop`.
+fn default_lock_path() -> Result<PathBuf> {
+    let dir = private_runtime_dir().map_err(|source| Error::LockIo {
+        action: "prepare slyboard runtime directory",
+        path: runtime_dir(),
+        source,
+    })?;
+    Ok(lock_path(&dir, &session_key()))
+}
+
+fn lock_path(runtime_dir: &Path, session_key: &str) -> PathBuf {
+    runtime_dir.join(format!("slyboard-{session_key}.sock"))
+}
+
+/// Holds the lock owner's pid next to the socket, e.g. `slyboard-alice-x0.pid`.
+fn pid_path(lock_path: &Path) -> PathBuf {
+    lock_path.with_extension("pid")
 }
 
-fn user_hint() -> String {
-    std::env::var("USER")
+fn read_pid(lock_path: &Path) -> Option<u32> {
+    fs::read_to_string(pid_path(lock_path))
         .ok()
-        .filter(|value| !value.trim().is_empty())
-        .unwrap_or_else(|| "user".to_string())
+        .and_then(|pid| pid.trim().parse().ok())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::acquire_from_path;
+    use super::{acquire_from_path, lock_path, pid_path, running_instance_at, RunningInstance};
+    use crate::core::session::session_key_from;
+    use crate::error::Error;
     use std::path::PathBuf;
 
     fn test_lock_path(name: &str) -> PathBuf {
@@ -85,13 +175,75 @@ mod tests {
         let first = acquire_from_path(path.clone()).expect("first lock should succeed");
         let second = acquire_from_path(path.clone());
 
-        assert!(second.is_err(), "second lock should fail");
+        match second {
+            Err(Error::LockHeld { pid, path: held }) => {
+                assert_eq!(pid, Some(std::process::id()));
+                assert_eq!(held, path);
+            }
+            _ => panic!("second lock should fail with LockHeld"),
+        }
 
         drop(first);
+        assert!(
+            !pid_path(&path).exists(),
+            "pid file is removed with the lock"
+        );
         let _ = std::fs::remove_file(path);
     }
 
     #[test]
+    fn reports_the_running_holder_from_its_pid_file() {
+        let path = test_lock_path("running");
+        assert_eq!(running_instance_at(path.clone()), None);
+
+        let lock = acquire_from_path(path.clone()).expect("lock should succeed");
+        assert_eq!(
+            std::fs::read_to_string(pid_path(&path)).unwrap(),
+            std::process::id().to_string()
+        );
+        assert_eq!(
+            running_instance_at(path.clone()),
+            Some(RunningInstance {
+                pid: Some(std::process::id()),
+                lock_path: path.clone(),
+            })
+        );
+
+        std::fs::write(pid_path(&path), "garbage\n").unwrap();
+        assert_eq!(running_instance_at(path.clone()).unwrap().pid, None);
+
+        drop(lock);
+        assert_eq!(running_instance_at(path), None);
+    }
+
+    #[test]
+    fn sessions_of_the_same_user_hold_separate_locks() {
+        let dir = std::env::temp_dir().join(format!("slyboard-test-locks-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create lock dir");
+        let key = |display: (&'static str, &'static str)| {
+            session_key_from(move |name| match name {
+                "USER" => Some("alice".to_string()),
+                name if name == display.0 => Some(display.1.to_string()),
+                _ => None,
+            })
+        };
+        let x11 = lock_path(&dir, &key(("DISPLAY", ":0")));
+        let wayland = lock_path(&dir, &key(("WAYLAND_DISPLAY", "wayland-1")));
+        assert_eq!(x11, dir.join("slyboard-alice-x0.sock"));
+
+        let x11_lock = acquire_from_path(x11.clone()).expect("x11 session lock");
+        let wayland_lock = acquire_from_path(wayland.clone()).expect("wayland session lock");
+        assert!(
+            acquire_from_path(x11).is_err(),
+            "same session must stay exclusive"
+        );
+
+        drop((x11_lock, wayland_lock));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    #[cfg(unix)]
     fn recovers_from_stale_socket_file() {
         let path = test_lock_path("stale-socket");
         let stale = std::os::unix::net::UnixListener::bind(&path).expect("create stale listener");
@@ -0,0 +1,226 @@
+//! Copies daemon output to a size-rotated log file (`logging.file`), so messages survive
+//! when slyboard is autostarted without a terminal. [`log_println!`] and [`log_eprintln!`]
+//! print as usual and also [`record`] the line once [`init`] has been called; [`log_debug!`]
+//! does the same only after [`set_debug`] turned debug messages on.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use crate::clipboard::age::unix_now;
+
+static LOG_FILE: OnceLock<RotatingFile> = OnceLock::new();
+static DEBUG: AtomicBool = AtomicBool::new(false);
+
+/// Starts copying logged lines to `path`. Only the first call has an effect.
+pub fn init(path: &Path, max_bytes: u64) -> io::Result<()> {
+    let file = RotatingFile::open(path, max_bytes)?;
+    let _ = LOG_FILE.set(file);
+    Ok(())
+}
+
+/// Appends `line` to the log file, if one was set up with [`init`]. Write failures are
+/// dropped: there is nowhere left to report them.
+pub fn record(line: &str) {
+    if let Some(file) = LOG_FILE.get() {
+        let _ = file.write_line(line);
+    }
+}
+
+/// Prints [`log_debug!`] messages from now on (`logging.debug`, `slyboard run --debug`).
+pub fn set_debug(enabled: bool) {
+    DEBUG.store(enabled, Ordering::Relaxed);
+}
+
+pub fn debug_enabled() -> bool {
+    DEBUG.load(Ordering::Relaxed)
+}
+
+/// `println!` that is also written to the log file.
+#[macro_export]
+macro_rules! log_println {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        println!("{line}");
+        $crate::core::logging::record(&line);
+    }};
+}
+
+/// `eprintln!` that is also written to the log file.
+#[macro_export]
+macro_rules! log_eprintln {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        eprintln!("{line}");
+        $crate::core::logging::record(&line);
+    }};
+}
+
+/// [`log_eprintln!`] prefixed with `debug: `, printed only when [`debug_enabled`]. The
+/// message isn't formatted otherwise.
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => {{
+        if $crate::core::logging::debug_enabled() {
+            $crate::log_eprintln!("debug: {}", format_args!($($arg)*));
+        }
+    }};
+}
+
+/// A log file that is moved to `<path>.1` (replacing the previous one) once the next line
+/// would take it past `max_bytes`, so at most two files are kept.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    /// The open file and its size. One lock per line keeps lines from different threads
+    /// whole and makes rotation atomic with respect to writers.
+    state: Mutex<(File, u64)>,
+}
+
+impl RotatingFile {
+    /// Opens `path` for appending, creating it and its parent directories as needed.
+    pub fn open(path: &Path, max_bytes: u64) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = append(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            state: Mutex::new((file, size)),
+        })
+    }
+
+    pub fn rotated_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".1");
+        PathBuf::from(path)
+    }
+
+    /// Writes `line` prefixed with the current unix time.
+    pub fn write_line(&self, line: &str) -> io::Result<()> {
+        self.append_line(&format!("{} {line}", unix_now()))
+    }
+
+    /// Writes `line` as it is, for files whose lines carry their own timestamps.
+    pub fn append_line(&self, line: &str) -> io::Result<()> {
+        let record = format!("{line}\n");
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (file, size) = &mut *state;
+        if *size > 0 && *size + record.len() as u64 > self.max_bytes {
+            fs::rename(&self.path, self.rotated_path())?;
+            *file = append(&self.path)?;
+            *size = 0;
+        }
+        file.write_all(record.as_bytes())?;
+        *size += record.len() as u64;
+        Ok(())
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RotatingFile;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "slyboard-test-logging-{}-{name}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn lines(path: &PathBuf) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(|line| line.split_once(' ').unwrap().1.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn creates_the_file_and_its_directories() {
+        let dir = test_dir("create");
+        let path = dir.join("state/slyboard/slyboard.log");
+        let log = RotatingFile::open(&path, 1024).expect("open log");
+        log.write_line("slyboard v1").unwrap();
+        drop(log);
+
+        let log = RotatingFile::open(&path, 1024).expect("reopen log");
+        log.write_line("second run").unwrap();
+        assert_eq!(lines(&path), ["slyboard v1", "second run"]);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn rotates_into_a_single_backup() {
+        let dir = test_dir("rotate");
+        let path = dir.join("slyboard.log");
+        // Each record is "<10-digit time> line N\n", 18 bytes; two fit under 40.
+        let log = RotatingFile::open(&path, 40).unwrap();
+        for index in 0..5 {
+            log.write_line(&format!("line {index}")).unwrap();
+        }
+
+        assert_eq!(lines(&path), ["line 4"]);
+        assert_eq!(lines(&log.rotated_path()), ["line 2", "line 3"]);
+        assert_eq!(log.rotated_path(), dir.join("slyboard.log.1"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn keeps_lines_whole_across_threads() {
+        // Without rotation every line must arrive; with it, the survivors must still be whole
+        // and in order per thread.
+        for max_bytes in [1 << 20, 2048] {
+            let dir = test_dir(&format!("threads-{max_bytes}"));
+            let path = dir.join("slyboard.log");
+            let log = Arc::new(RotatingFile::open(&path, max_bytes).unwrap());
+            let writers: Vec<_> = (0..8)
+                .map(|thread| {
+                    let log = log.clone();
+                    thread::spawn(move || {
+                        for index in 0..100 {
+                            log.write_line(&format!("thread {thread} line {index}"))
+                                .unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for writer in writers {
+                writer.join().unwrap();
+            }
+
+            let mut all = lines(&log.rotated_path());
+            all.extend(lines(&path));
+            if max_bytes == 1 << 20 {
+                assert_eq!(all.len(), 800);
+            }
+            for thread in 0..8 {
+                let prefix = format!("thread {thread} line ");
+                let indices: Vec<usize> = all
+                    .iter()
+                    .filter_map(|line| line.strip_prefix(&prefix))
+                    .map(|index| index.parse().unwrap())
+                    .collect();
+                assert!(indices.windows(2).all(|pair| pair[1] == pair[0] + 1));
+            }
+            assert!(all.iter().all(|line| line.split(' ').count() == 4));
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
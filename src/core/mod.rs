@@ -1,3 +1,25 @@
 pub mod active_window;
+pub mod append_mode;
 pub mod capture_control;
+pub mod capture_stats;
+pub mod daemon;
+pub mod daemonize;
+pub mod environment;
+pub mod events;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod hyprland;
 pub mod instance_lock;
+pub mod logging;
+pub mod plain_paste;
+pub mod provider_stats;
+pub mod screen_lock;
+pub mod self_write;
+pub mod session;
+pub mod setup;
+pub mod status_bar;
+pub mod systemd;
+pub mod tail;
+#[cfg(unix)]
+pub mod termination;
+pub mod tray_menu;
@@ -0,0 +1,91 @@
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::clipboard::plain::plain_entry;
+use crate::clipboard::ClipboardEntry;
+use crate::core::session::{runtime_dir, session_key};
+
+const PLAIN_PASTE_FILE_SUFFIX: &str = "plain-paste";
+
+pub fn is_plain_paste_enabled() -> bool {
+    default_plain_paste_path().exists()
+}
+
+pub fn set_plain_paste(enabled: bool) -> Result<()> {
+    set_plain_paste_at_path(&default_plain_paste_path(), enabled)
+}
+
+/// What restoring `entry` puts on the clipboard: its plain text while plain-text paste is on,
+/// otherwise the entry itself.
+pub fn entry_for_paste(entry: &ClipboardEntry) -> Cow<'_, ClipboardEntry> {
+    entry_for_paste_at_path(&default_plain_paste_path(), entry)
+}
+
+fn entry_for_paste_at_path<'a>(path: &Path, entry: &'a ClipboardEntry) -> Cow<'a, ClipboardEntry> {
+    if path.exists() {
+        plain_entry(entry)
+    } else {
+        Cow::Borrowed(entry)
+    }
+}
+
+fn set_plain_paste_at_path(path: &Path, enabled: bool) -> Result<()> {
+    if enabled {
+        fs::write(path, b"plain-paste\n").with_context(|| {
+            format!(
+                "failed to write slyboard plain-text paste marker: {}",
+                path.display()
+            )
+        })?;
+        return Ok(());
+    }
+
+    if path.exists() {
+        fs::remove_file(path).with_context(|| {
+            format!(
+                "failed to remove slyboard plain-text paste marker: {}",
+                path.display()
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// Like pausing, plain-text paste applies to the current graphical session only, see
+/// [`session_key`].
+fn default_plain_paste_path() -> PathBuf {
+    runtime_dir().join(format!(
+        "slyboard-{}-{PLAIN_PASTE_FILE_SUFFIX}",
+        session_key()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{entry_for_paste_at_path, set_plain_paste_at_path};
+    use crate::clipboard::ClipboardEntry;
+    use std::borrow::Cow;
+
+    #[test]
+    fn strips_restored_text_only_while_enabled() {
+        let path =
+            std::env::temp_dir().join(format!("slyboard-test-plain-paste-{}", std::process::id()));
+        let entry = ClipboardEntry::text("line  \r\n");
+
+        set_plain_paste_at_path(&path, false).unwrap();
+        assert!(matches!(
+            entry_for_paste_at_path(&path, &entry),
+            Cow::Borrowed(_)
+        ));
+
+        set_plain_paste_at_path(&path, true).unwrap();
+        assert!(path.exists());
+        assert!(entry_for_paste_at_path(&path, &entry).content_eq(&ClipboardEntry::text("line")));
+
+        set_plain_paste_at_path(&path, false).unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,167 @@
+//! Per-provider active-window counters. The daemon records every capture attempt and keeps a
+//! copy in a runtime file next to the pause marker, where `slyboard status --json` reads it.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::core::active_window::CaptureOutcome;
+use crate::core::session::{runtime_dir, session_key, write_atomically};
+
+const PROVIDER_STATS_FILE_SUFFIX: &str = "providers.json";
+
+/// Capture attempts of one provider since the daemon started.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProviderCounters {
+    pub successes: u64,
+    pub failures: u64,
+    pub timeouts: u64,
+    pub last_latency_ms: Option<u64>,
+    /// Why the last failed attempt failed, e.g. "hyprctl exited with exit status: 1".
+    pub last_error: Option<String>,
+}
+
+/// Counters keyed by provider name, shared by every provider of one daemon.
+#[derive(Debug, Clone, Default)]
+pub struct ProviderStats {
+    counters: Arc<Mutex<BTreeMap<String, ProviderCounters>>>,
+    path: Option<PathBuf>,
+}
+
+impl ProviderStats {
+    /// Stats of the daemon in the current session, see [`session_key`]. Starts from zero,
+    /// replacing whatever a previous daemon left behind.
+    pub fn for_session() -> Self {
+        let stats = Self::at(default_provider_stats_path());
+        stats.persist(&BTreeMap::new());
+        stats
+    }
+
+    pub fn at(path: PathBuf) -> Self {
+        Self {
+            counters: Arc::default(),
+            path: Some(path),
+        }
+    }
+
+    pub fn record(&self, provider: &str, outcome: &CaptureOutcome, latency: Duration) {
+        let mut counters = self
+            .counters
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let entry = counters.entry(provider.to_string()).or_default();
+        match outcome {
+            CaptureOutcome::Captured(_) => entry.successes += 1,
+            CaptureOutcome::Failed(reason) => {
+                entry.failures += 1;
+                entry.last_error = Some(reason.clone());
+            }
+            CaptureOutcome::TimedOut => {
+                entry.timeouts += 1;
+                entry.last_error = Some("timed out".to_string());
+            }
+        }
+        entry.last_latency_ms = Some(latency.as_millis().try_into().unwrap_or(u64::MAX));
+        self.persist(&counters);
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, ProviderCounters> {
+        self.counters
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Capture goes on without the file, so a failed write is only logged.
+    fn persist(&self, counters: &BTreeMap<String, ProviderCounters>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let written = serde_json::to_vec(counters)
+            .map_err(io::Error::from)
+            .and_then(|contents| write_atomically(path, &contents));
+        if let Err(err) = written {
+            crate::log_eprintln!(
+                "warning: failed to write provider stats {}: {err}",
+                path.display()
+            );
+        }
+    }
+}
+
+/// The counters of the daemon in this session, or `None` if no daemon has written any.
+pub fn load_provider_stats() -> Result<Option<BTreeMap<String, ProviderCounters>>> {
+    let path = default_provider_stats_path();
+    let contents = match fs::read(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("failed to read provider stats: {}", path.display()))
+        }
+    };
+    let counters = serde_json::from_slice(&contents)
+        .with_context(|| format!("provider stats are not valid JSON: {}", path.display()))?;
+    Ok(Some(counters))
+}
+
+fn default_provider_stats_path() -> PathBuf {
+    runtime_dir().join(format!(
+        "slyboard-{}-{PROVIDER_STATS_FILE_SUFFIX}",
+        session_key()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProviderCounters, ProviderStats};
+    use crate::core::active_window::CaptureOutcome;
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    #[test]
+    fn counts_outcomes_and_keeps_the_file_in_sync() {
+        let path = std::env::temp_dir().join(format!(
+            "slyboard-test-providers-{}.json",
+            std::process::id()
+        ));
+        let stats = ProviderStats::at(path.clone());
+        stats.record(
+            "hyprctl",
+            &CaptureOutcome::Failed("hyprctl exited with exit status: 1".to_string()),
+            Duration::from_millis(3),
+        );
+        stats.record("hyprctl", &CaptureOutcome::TimedOut, Duration::from_secs(1));
+        stats.record(
+            "xdotool",
+            &CaptureOutcome::Failed("no active window".to_string()),
+            Duration::from_millis(7),
+        );
+
+        let snapshot = stats.snapshot();
+        assert_eq!(
+            snapshot["hyprctl"],
+            ProviderCounters {
+                successes: 0,
+                failures: 1,
+                timeouts: 1,
+                last_latency_ms: Some(1000),
+                last_error: Some("timed out".to_string()),
+            }
+        );
+        assert_eq!(snapshot["xdotool"].failures, 1);
+
+        let from_file: BTreeMap<String, ProviderCounters> =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(from_file, snapshot);
+        // Clones share the counters, as every provider of the daemon does.
+        assert_eq!(stats.clone().snapshot(), snapshot);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,125 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use anyhow::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockEvent {
+    Locked,
+    Unlocked,
+}
+
+pub type LockEventHandler = Rc<dyn Fn(LockEvent)>;
+
+/// Something that reports screen lock transitions (D-Bus in the daemon, scripted in tests).
+pub trait LockSignalSource {
+    fn subscribe(&mut self, handler: LockEventHandler) -> Result<()>;
+}
+
+/// Tracks whether the screen is locked, separately from the user's pause marker.
+#[derive(Clone, Default)]
+pub struct ScreenLockMonitor {
+    locked: Rc<Cell<bool>>,
+}
+
+impl ScreenLockMonitor {
+    pub fn start<F>(source: &mut dyn LockSignalSource, on_change: F) -> Result<Self>
+    where
+        F: Fn(bool) + 'static,
+    {
+        let monitor = Self::default();
+        let locked = monitor.locked.clone();
+        source.subscribe(Rc::new(move |event| {
+            let is_locked = event == LockEvent::Locked;
+            if locked.replace(is_locked) != is_locked {
+                on_change(is_locked);
+            }
+        }))?;
+        Ok(monitor)
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.get()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureState {
+    Running,
+    Paused,
+    PausedScreenLocked,
+}
+
+impl CaptureState {
+    /// A manual pause wins over the lock, so unlocking never resumes a user-paused daemon.
+    pub fn from_flags(user_paused: bool, screen_locked: bool) -> Self {
+        if user_paused {
+            CaptureState::Paused
+        } else if screen_locked {
+            CaptureState::PausedScreenLocked
+        } else {
+            CaptureState::Running
+        }
+    }
+
+    pub fn is_capturing(self) -> bool {
+        self == CaptureState::Running
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CaptureState, LockEvent, LockEventHandler, LockSignalSource, ScreenLockMonitor};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct MockLockSource {
+        handlers: Vec<LockEventHandler>,
+    }
+
+    impl MockLockSource {
+        fn emit(&self, event: LockEvent) {
+            for handler in &self.handlers {
+                handler(event);
+            }
+        }
+    }
+
+    impl LockSignalSource for MockLockSource {
+        fn subscribe(&mut self, handler: LockEventHandler) -> anyhow::Result<()> {
+            self.handlers.push(handler);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn tracks_lock_transitions_and_reports_changes_once() {
+        let mut source = MockLockSource::default();
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let changes_for_handler = changes.clone();
+        let monitor = ScreenLockMonitor::start(&mut source, move |locked| {
+            changes_for_handler.borrow_mut().push(locked)
+        })
+        .expect("monitor should start");
+
+        assert!(!monitor.is_locked());
+        source.emit(LockEvent::Locked);
+        source.emit(LockEvent::Locked);
+        assert!(monitor.is_locked());
+        source.emit(LockEvent::Unlocked);
+        assert!(!monitor.is_locked());
+        assert_eq!(*changes.borrow(), vec![true, false]);
+    }
+
+    #[test]
+    fn unlocking_keeps_manual_pause() {
+        assert_eq!(
+            CaptureState::from_flags(false, true),
+            CaptureState::PausedScreenLocked
+        );
+        assert_eq!(CaptureState::from_flags(true, true), CaptureState::Paused);
+        assert_eq!(CaptureState::from_flags(true, false), CaptureState::Paused);
+        assert!(CaptureState::from_flags(false, false).is_capturing());
+    }
+}
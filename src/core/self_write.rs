@@ -0,0 +1,221 @@
+//! Clipboard changes slyboard causes itself. Restoring an entry puts it back on the clipboard,
+//! where the poller would see it as a new copy; announcing the write first lets the poller
+//! skip it. The tray announces in-process, the CLI and `slyboard-rofi` through a short-lived
+//! runtime marker next to the pause marker.
+
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::clipboard::age::unix_now;
+use crate::clipboard::digest::StableHasher;
+use crate::clipboard::ClipboardEntry;
+use crate::core::plain_paste::entry_for_paste;
+use crate::core::session::{runtime_dir, session_key, write_atomically};
+
+/// How long an announced write waits to be seen before it no longer suppresses anything.
+pub const SELF_WRITE_TTL_SECS: u64 = 5;
+const SELF_WRITE_FILE_SUFFIX: &str = "self-write.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct ExpectedWrite {
+    digest: u64,
+    expires_at: u64,
+}
+
+impl ExpectedWrite {
+    fn new(entry: &ClipboardEntry, now: u64) -> Self {
+        Self {
+            digest: write_digest(&entry_for_paste(entry)),
+            expires_at: now + SELF_WRITE_TTL_SECS,
+        }
+    }
+}
+
+/// Writes announced to a poller. Clones share the announcements.
+#[derive(Debug, Clone, Default)]
+pub struct ExpectedWrites {
+    pending: Arc<Mutex<Vec<ExpectedWrite>>>,
+    marker: Option<PathBuf>,
+}
+
+impl ExpectedWrites {
+    /// Also takes writes announced by other processes in the current session, see
+    /// [`session_key`].
+    pub fn for_session() -> Self {
+        Self::with_marker(default_self_write_path())
+    }
+
+    pub fn with_marker(path: PathBuf) -> Self {
+        Self {
+            pending: Arc::default(),
+            marker: Some(path),
+        }
+    }
+
+    /// Announces that this process is about to put `entry` on the clipboard.
+    pub fn expect(&self, entry: &ClipboardEntry) {
+        let mut pending = self
+            .pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        pending.push(ExpectedWrite::new(entry, unix_now()));
+    }
+
+    /// Whether `entry` was announced, in this process or through the marker. A match is used
+    /// up, so the same content copied again afterwards is captured as usual.
+    pub fn take(&self, entry: &ClipboardEntry) -> bool {
+        self.take_at(entry, unix_now())
+    }
+
+    fn take_at(&self, entry: &ClipboardEntry, now: u64) -> bool {
+        let digest = write_digest(entry);
+        let mut pending = self
+            .pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if take_matching(&mut pending, digest, now) {
+            return true;
+        }
+        let Some(path) = &self.marker else {
+            return false;
+        };
+        match take_from_marker(path, digest, now) {
+            Ok(taken) => taken,
+            Err(err) => {
+                crate::log_eprintln!("warning: {err:#}");
+                false
+            }
+        }
+    }
+}
+
+/// Announces, for the daemon of this session, that a short-lived command is about to put
+/// `entry` on the clipboard.
+pub fn expect_self_write(entry: &ClipboardEntry) -> Result<()> {
+    expect_at(&default_self_write_path(), entry, unix_now())
+}
+
+fn expect_at(path: &Path, entry: &ClipboardEntry, now: u64) -> Result<()> {
+    let mut pending = read_marker(path)?;
+    pending.retain(|write| write.expires_at > now);
+    pending.push(ExpectedWrite::new(entry, now));
+    write_marker(path, &pending)
+}
+
+/// Drops expired writes and removes the first one matching `digest`.
+fn take_matching(pending: &mut Vec<ExpectedWrite>, digest: u64, now: u64) -> bool {
+    pending.retain(|write| write.expires_at > now);
+    match pending.iter().position(|write| write.digest == digest) {
+        Some(position) => {
+            pending.remove(position);
+            true
+        }
+        None => false,
+    }
+}
+
+fn take_from_marker(path: &Path, digest: u64, now: u64) -> Result<bool> {
+    let mut pending = read_marker(path)?;
+    if pending.is_empty() {
+        return Ok(false);
+    }
+    let before = pending.len();
+    let taken = take_matching(&mut pending, digest, now);
+    if pending.len() != before {
+        write_marker(path, &pending)?;
+    }
+    Ok(taken)
+}
+
+fn read_marker(path: &Path) -> Result<Vec<ExpectedWrite>> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("failed to read self-write marker: {}", path.display()))
+        }
+    };
+    serde_json::from_slice(&contents)
+        .with_context(|| format!("self-write marker is not valid JSON: {}", path.display()))
+}
+
+/// Removes the marker once nothing is pending.
+fn write_marker(path: &Path, pending: &[ExpectedWrite]) -> Result<()> {
+    let written = if pending.is_empty() {
+        match fs::remove_file(path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    } else {
+        write_atomically(path, &serde_json::to_vec(pending)?)
+    };
+    written.with_context(|| format!("failed to write self-write marker: {}", path.display()))
+}
+
+/// Identifies content the way it reads back from the clipboard: text by its bytes, images by
+/// their size only, since they come back in a different encoding than they were written in.
+/// Stable, so the CLI and the daemon agree.
+fn write_digest(entry: &ClipboardEntry) -> u64 {
+    let mut hasher = StableHasher::default();
+    match entry {
+        ClipboardEntry::Text { value, .. } => ("text", value).hash(&mut hasher),
+        ClipboardEntry::Image { width, height, .. } => ("image", width, height).hash(&mut hasher),
+    }
+    hasher.finish()
+}
+
+fn default_self_write_path() -> PathBuf {
+    runtime_dir().join(format!(
+        "slyboard-{}-{SELF_WRITE_FILE_SUFFIX}",
+        session_key()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expect_at, ExpectedWrites, SELF_WRITE_TTL_SECS};
+    use crate::clipboard::ClipboardEntry;
+    use std::path::PathBuf;
+
+    fn test_marker(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "slyboard-test-self-write-{}-{name}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn in_process_writes_are_taken_once() {
+        let expected = ExpectedWrites::default();
+        expected.expect(&ClipboardEntry::text("restored"));
+        assert!(!expected.take(&ClipboardEntry::text("other")));
+        assert!(expected.clone().take(&ClipboardEntry::text("restored")));
+        assert!(!expected.take(&ClipboardEntry::text("restored")));
+    }
+
+    #[test]
+    fn marker_writes_expire_and_are_removed_when_taken() {
+        let path = test_marker("marker");
+        let expected = ExpectedWrites::with_marker(path.clone());
+        expect_at(&path, &ClipboardEntry::text("from rofi"), 100).unwrap();
+        expect_at(&path, &ClipboardEntry::text("stale"), 90).unwrap();
+
+        assert!(!expected.take_at(&ClipboardEntry::text("stale"), 100));
+        assert!(expected.take_at(&ClipboardEntry::text("from rofi"), 101));
+        assert!(!path.exists(), "an empty marker is removed");
+        assert!(!expected.take_at(&ClipboardEntry::text("from rofi"), 101));
+
+        expect_at(&path, &ClipboardEntry::text("late"), 100).unwrap();
+        assert!(!expected.take_at(&ClipboardEntry::text("late"), 100 + SELF_WRITE_TTL_SECS));
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,215 @@
+use std::env;
+use std::fs;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+/// Names the current user's graphical session, so runtime files (instance lock, pause
+/// marker) are shared by everything in one session but not across sessions of the same user.
+///
+/// Built from `$USER` (`%USERNAME%` on Windows), `$XDG_SESSION_ID`, and `$WAYLAND_DISPLAY` or else `$DISPLAY`, e.g.
+/// `alice-s3-wayland-1` or `alice-x0`. Outside a graphical session it is just the user name.
+pub fn session_key() -> String {
+    session_key_from(|name| env::var(name).ok())
+}
+
+pub fn session_key_from(var: impl Fn(&str) -> Option<String>) -> String {
+    let var = |name: &str| var(name).filter(|value| !value.trim().is_empty());
+
+    let user = var("USER").or_else(|| var("USERNAME"));
+    let mut key = sanitize(&user.unwrap_or_else(|| "user".to_string()));
+    if let Some(session_id) = var("XDG_SESSION_ID") {
+        key.push_str("-s");
+        key.push_str(&sanitize(&session_id));
+    }
+    if let Some(wayland_display) = var("WAYLAND_DISPLAY") {
+        // May be an absolute socket path; its file name is what tells sessions apart.
+        let name = Path::new(&wayland_display)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or(wayland_display);
+        key.push('-');
+        key.push_str(&sanitize(&name));
+    } else if let Some(display) = var("DISPLAY") {
+        key.push_str("-x");
+        key.push_str(&sanitize(display.trim_start_matches(':')));
+    }
+    key
+}
+
+/// `$XDG_RUNTIME_DIR`, or the temp dir when it is unset.
+pub fn runtime_dir() -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir)
+}
+
+/// Where to keep files whose mere presence changes what slyboard does, such as the pause
+/// marker: `$XDG_RUNTIME_DIR`, or else `slyboard-<uid>` in the temp dir, created private to
+/// the user. Unlike [`runtime_dir`], other users cannot plant files there.
+pub fn private_runtime_dir() -> io::Result<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    #[cfg(unix)]
+    {
+        let uid = current_uid();
+        let dir = fallback_runtime_dir(&env::temp_dir(), uid);
+        create_private_dir(&dir, uid)?;
+        Ok(dir)
+    }
+    // Windows keeps each user's temp dir inside their profile, out of other users' reach.
+    #[cfg(not(unix))]
+    Ok(env::temp_dir())
+}
+
+/// Named by uid rather than `$USER`, which anyone can set to someone else's name.
+pub fn fallback_runtime_dir(temp_dir: &Path, uid: u32) -> PathBuf {
+    temp_dir.join(format!("slyboard-{uid}"))
+}
+
+/// Creates `dir` with mode 0700, or checks that the existing one is a directory `uid` owns
+/// and nobody else may write to, refusing a symlink or a directory someone else made first.
+#[cfg(unix)]
+pub fn create_private_dir(dir: &Path, uid: u32) -> io::Result<()> {
+    match fs::DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => return Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(err) => return Err(err),
+    }
+    let metadata = fs::symlink_metadata(dir)?;
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o022 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} is not a directory only uid {uid} can write to",
+                dir.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub fn current_uid() -> u32 {
+    // SAFETY: getuid takes no arguments, touches no memory of ours, and always succeeds.
+    unsafe { libc::getuid() }
+}
+
+/// Replaces a runtime file through a temporary one, so readers never see half of it.
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path)
+}
+
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|ch| match ch {
+            ch if ch.is_ascii_alphanumeric() || ch == '-' || ch == '.' => ch,
+            _ => '_',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{fallback_runtime_dir, session_key_from};
+
+    fn key(vars: &[(&str, &str)]) -> String {
+        session_key_from(|name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    fn scopes_by_session_and_display() {
+        assert_eq!(key(&[("USER", "alice")]), "alice");
+        assert_eq!(key(&[]), "user");
+        assert_eq!(key(&[("USERNAME", "alice")]), "alice");
+        assert_eq!(key(&[("USER", "alice"), ("DISPLAY", ":0")]), "alice-x0");
+        assert_eq!(
+            key(&[("USER", "alice"), ("DISPLAY", "host:10.0")]),
+            "alice-xhost_10.0"
+        );
+        assert_eq!(
+            key(&[
+                ("USER", "alice"),
+                ("XDG_SESSION_ID", "3"),
+                ("WAYLAND_DISPLAY", "wayland-1"),
+                ("DISPLAY", ":1"),
+            ]),
+            "alice-s3-wayland-1"
+        );
+        assert_eq!(
+            key(&[
+                ("USER", "alice"),
+                ("WAYLAND_DISPLAY", "/run/user/1000/wayland-2")
+            ]),
+            "alice-wayland-2"
+        );
+        assert_eq!(
+            key(&[("USER", "a/b"), ("WAYLAND_DISPLAY", " "), ("DISPLAY", ":0")]),
+            "a_b-x0"
+        );
+    }
+
+    #[test]
+    fn separate_sessions_get_separate_keys() {
+        let x11 = key(&[("USER", "alice"), ("DISPLAY", ":0")]);
+        let wayland = key(&[("USER", "alice"), ("WAYLAND_DISPLAY", "wayland-1")]);
+        let other_seat = key(&[("USER", "alice"), ("DISPLAY", ":1")]);
+        assert_ne!(x11, wayland);
+        assert_ne!(x11, other_seat);
+        assert_eq!(x11, key(&[("USER", "alice"), ("DISPLAY", ":0")]));
+    }
+
+    #[test]
+    fn falls_back_to_a_directory_named_by_uid() {
+        assert_eq!(
+            fallback_runtime_dir(Path::new("/tmp"), 1000),
+            Path::new("/tmp/slyboard-1000")
+        );
+        assert_ne!(
+            fallback_runtime_dir(Path::new("/tmp"), 1000),
+            fallback_runtime_dir(Path::new("/tmp"), 1001)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_dirs_are_created_private_and_others_refused() {
+        use super::{create_private_dir, current_uid};
+        use crate::testing::ScratchStore;
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        let store = ScratchStore::new("session-private-dir");
+        let uid = current_uid();
+        let dir = fallback_runtime_dir(store.dir(), uid);
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        create_private_dir(&dir, uid).unwrap();
+        assert_eq!(mode(&dir), 0o700);
+        create_private_dir(&dir, uid).expect("an existing private dir is fine");
+        assert!(create_private_dir(&dir, uid + 1).is_err(), "someone else's");
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(create_private_dir(&dir, uid).is_err(), "writable by others");
+
+        let file = store.dir().join("file");
+        fs::write(&file, "").unwrap();
+        assert!(create_private_dir(&file, uid).is_err());
+        let link = store.dir().join("link");
+        std::os::unix::fs::symlink(&dir, &link).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+        assert!(create_private_dir(&link, uid).is_err(), "a symlink to it");
+    }
+}
@@ -0,0 +1,480 @@
+//! `slyboard setup`: proposes settings for the desktop it runs in, asks about each one the
+//! command line didn't answer, and renders the user config file.
+
+use std::io::{BufRead, Write};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use crate::clipboard::DEFAULT_HISTORY_LIMIT;
+use crate::config::ActiveWindowBackend;
+use crate::core::environment::{Compositor, Environment, SessionType};
+
+/// Offered for the blacklist, matched against the focused window's app id or title.
+pub const PASSWORD_MANAGERS: &[&str] = &["KeePassXC", "1Password", "Bitwarden"];
+
+/// Sway doesn't speak Hyprland's IPC and has no X11 window for `xdotool`, so the focused
+/// window's title comes from its tree.
+const SWAY_FOCUSED_TITLE: &str =
+    "swaymsg -t get_tree | jq -r '.. | select(.focused? == true) | .name'";
+
+/// The `kind: command` backend proposed under Sway.
+pub fn sway_backend() -> ActiveWindowBackend {
+    ActiveWindowBackend::Command {
+        program: "sh".to_string(),
+        args: vec!["-c".to_string(), SWAY_FOCUSED_TITLE.to_string()],
+    }
+}
+
+/// `auto` under Hyprland and X11, the `swaymsg` command under Sway when `swaymsg` and `jq` are
+/// installed, and `disabled` on other Wayland desktops (GNOME among them), which don't tell
+/// clients which window is focused.
+pub fn proposed_backend(environment: &Environment) -> ActiveWindowBackend {
+    match (environment.compositor, environment.session) {
+        (Some(Compositor::Hyprland), _) => ActiveWindowBackend::Auto,
+        (Some(Compositor::Sway), _) if environment.has("swaymsg") && environment.has("jq") => {
+            sway_backend()
+        }
+        (_, Some(SessionType::Wayland)) => ActiveWindowBackend::Disabled,
+        _ => ActiveWindowBackend::Auto,
+    }
+}
+
+/// The name `--active-window` and the wizard use for `backend`: `auto`, `disabled`, `sway`,
+/// or the program of any other command.
+pub fn backend_name(backend: &ActiveWindowBackend) -> &str {
+    match backend {
+        ActiveWindowBackend::Auto => "auto",
+        ActiveWindowBackend::Disabled => "disabled",
+        backend if *backend == sway_backend() => "sway",
+        ActiveWindowBackend::Command { program, .. } => program,
+    }
+}
+
+pub fn parse_backend(name: &str) -> Option<ActiveWindowBackend> {
+    match name.to_ascii_lowercase().as_str() {
+        "auto" => Some(ActiveWindowBackend::Auto),
+        "disabled" => Some(ActiveWindowBackend::Disabled),
+        "sway" => Some(sway_backend()),
+        _ => None,
+    }
+}
+
+/// Where to bind `slyboard-rofi` to a key on this desktop.
+pub fn rofi_binding(environment: &Environment) -> &'static str {
+    match environment.compositor {
+        Some(Compositor::Hyprland) => {
+            "add `bind = SUPER, V, exec, slyboard-rofi` to ~/.config/hypr/hyprland.conf"
+        }
+        Some(Compositor::Sway) => "add `bindsym $mod+v exec slyboard-rofi` to ~/.config/sway/config",
+        Some(Compositor::Gnome) => {
+            "add a custom shortcut running `slyboard-rofi` in Settings > Keyboard > Keyboard Shortcuts"
+        }
+        None => "bind `slyboard-rofi` to a key in your window manager, e.g. Super+V",
+    }
+}
+
+/// Everything the wizard decides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetupChoices {
+    pub active_window: ActiveWindowBackend,
+    pub blacklist: Vec<String>,
+    pub history_limit: usize,
+    /// `clipboard.notifications.on_capture`.
+    pub notifications: bool,
+    /// Whether to write the systemd user unit, as `slyboard install-service` does.
+    pub install_service: bool,
+}
+
+impl SetupChoices {
+    /// What `--accept-defaults` takes and the prompts offer: the backend for `environment`,
+    /// the password managers blacklisted, and the service when `systemctl` is installed.
+    pub fn proposed(environment: &Environment) -> Self {
+        Self {
+            active_window: proposed_backend(environment),
+            blacklist: PASSWORD_MANAGERS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            history_limit: DEFAULT_HISTORY_LIMIT,
+            notifications: true,
+            install_service: environment.has("systemctl"),
+        }
+    }
+}
+
+/// Answers given on the command line, which are not asked about.
+#[derive(Debug, Clone, Default)]
+pub struct SetupOverrides {
+    pub active_window: Option<ActiveWindowBackend>,
+    pub blacklist: Option<Vec<String>>,
+    pub history_limit: Option<usize>,
+    pub notifications: Option<bool>,
+    pub install_service: Option<bool>,
+}
+
+/// Settles each choice from `overrides`, else from `proposed` with `accept_defaults`, else by
+/// asking on `output` and reading `input`, where an empty answer takes the proposal. Without
+/// a terminal to ask on, a question nothing answered is an error naming its flag.
+pub fn choose(
+    proposed: SetupChoices,
+    overrides: SetupOverrides,
+    accept_defaults: bool,
+    interactive: bool,
+    input: impl BufRead,
+    output: impl Write,
+) -> Result<SetupChoices> {
+    let mut prompt = Prompt { input, output };
+    let ask = |flag: &str| -> Result<bool> {
+        if accept_defaults {
+            return Ok(false);
+        }
+        if !interactive {
+            bail!("pass {flag} or --accept-defaults when stdin is not a terminal");
+        }
+        Ok(true)
+    };
+
+    let active_window = match overrides.active_window {
+        Some(backend) => backend,
+        None if ask("--active-window")? => prompt.ask(
+            &format!(
+                "Active-window backend (auto, disabled, sway) [{}]:",
+                backend_name(&proposed.active_window)
+            ),
+            proposed.active_window,
+            parse_backend,
+            "auto, disabled or sway",
+        )?,
+        None => proposed.active_window,
+    };
+    let blacklist = match overrides.blacklist {
+        Some(blacklist) => blacklist,
+        None if ask("--blacklist (or --no-blacklist)")? => {
+            let mut blacklist = Vec::new();
+            let question = format!(
+                "Keep copies from password managers ({}) out of history?",
+                PASSWORD_MANAGERS.join(", ")
+            );
+            if prompt.confirm(&question, true)? {
+                blacklist.extend(PASSWORD_MANAGERS.iter().map(|name| name.to_string()));
+            }
+            let others = prompt
+                .answer("Other apps to keep out of history, comma-separated []:")?
+                .unwrap_or_default();
+            blacklist.extend(
+                others
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string),
+            );
+            blacklist
+        }
+        None => proposed.blacklist,
+    };
+    let history_limit = match overrides.history_limit {
+        Some(limit) => limit,
+        None if ask("--history-limit")? => prompt.ask(
+            &format!("History entries to keep [{}]:", proposed.history_limit),
+            proposed.history_limit,
+            |answer| answer.parse().ok().filter(|limit| *limit > 0),
+            "a positive number",
+        )?,
+        None => proposed.history_limit,
+    };
+    let notifications = match overrides.notifications {
+        Some(notifications) => notifications,
+        None if ask("--notifications")? => {
+            prompt.confirm("Show a notification for each copy?", proposed.notifications)?
+        }
+        None => proposed.notifications,
+    };
+    let install_service = match overrides.install_service {
+        Some(install_service) => install_service,
+        None if ask("--install-service")? => prompt.confirm(
+            "Install the systemd user service?",
+            proposed.install_service,
+        )?,
+        None => proposed.install_service,
+    };
+
+    Ok(SetupChoices {
+        active_window,
+        blacklist,
+        history_limit,
+        notifications,
+        install_service,
+    })
+}
+
+struct Prompt<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Prompt<R, W> {
+    /// The trimmed answer, or `None` when it is empty or input ended.
+    fn answer(&mut self, question: &str) -> Result<Option<String>> {
+        write!(self.output, "{question} ")?;
+        self.output.flush()?;
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            writeln!(self.output)?;
+            return Ok(None);
+        }
+        let answer = line.trim();
+        Ok((!answer.is_empty()).then(|| answer.to_string()))
+    }
+
+    /// Asks again until `parse` accepts the answer; no answer takes `default`.
+    fn ask<T>(
+        &mut self,
+        question: &str,
+        default: T,
+        parse: impl Fn(&str) -> Option<T>,
+        expected: &str,
+    ) -> Result<T> {
+        loop {
+            let Some(answer) = self.answer(question)? else {
+                return Ok(default);
+            };
+            match parse(&answer) {
+                Some(value) => return Ok(value),
+                None => writeln!(self.output, "Expected {expected}.")?,
+            }
+        }
+    }
+
+    fn confirm(&mut self, question: &str, default: bool) -> Result<bool> {
+        let hint = if default { "[Y/n]" } else { "[y/N]" };
+        self.ask(
+            &format!("{question} {hint}"),
+            default,
+            |answer| match answer.to_ascii_lowercase().as_str() {
+                "y" | "yes" => Some(true),
+                "n" | "no" => Some(false),
+                _ => None,
+            },
+            "y or n",
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct ConfigFile<'a> {
+    clipboard: ClipboardSection<'a>,
+    storage: StorageSection,
+}
+
+#[derive(Serialize)]
+struct ClipboardSection<'a> {
+    active_window: ActiveWindowSection<'a>,
+    notifications: NotificationSection,
+}
+
+#[derive(Serialize)]
+struct ActiveWindowSection<'a> {
+    backend: &'a ActiveWindowBackend,
+    blacklist: &'a [String],
+}
+
+#[derive(Serialize)]
+struct NotificationSection {
+    on_capture: bool,
+}
+
+#[derive(Serialize)]
+struct StorageSection {
+    history_limit: usize,
+}
+
+/// The user config for `choices`; everything else keeps its default.
+pub fn config_yaml(choices: &SetupChoices) -> Result<String> {
+    let file = ConfigFile {
+        clipboard: ClipboardSection {
+            active_window: ActiveWindowSection {
+                backend: &choices.active_window,
+                blacklist: &choices.blacklist,
+            },
+            notifications: NotificationSection {
+                on_capture: choices.notifications,
+            },
+        },
+        storage: StorageSection {
+            history_limit: choices.history_limit,
+        },
+    };
+    Ok(format!(
+        "# Written by `slyboard setup`; the README describes every setting.\n{}",
+        serde_yaml::to_string(&file)?
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        backend_name, choose, config_yaml, parse_backend, proposed_backend, sway_backend,
+        SetupChoices, SetupOverrides, PASSWORD_MANAGERS,
+    };
+    use crate::config::{ActiveWindowBackend, AppConfig};
+    use crate::core::environment::{Compositor, Environment, SessionType};
+
+    fn environment(
+        session: Option<SessionType>,
+        compositor: Option<Compositor>,
+        programs: &[&'static str],
+    ) -> Environment {
+        Environment {
+            session,
+            compositor,
+            programs: programs.to_vec(),
+        }
+    }
+
+    fn proposal() -> SetupChoices {
+        SetupChoices::proposed(&environment(Some(SessionType::X11), None, &["systemctl"]))
+    }
+
+    /// The choices made with `answers` typed in, and everything the wizard printed.
+    fn answered(overrides: SetupOverrides, answers: &str) -> (SetupChoices, String) {
+        let mut output = Vec::new();
+        let choices = choose(
+            proposal(),
+            overrides,
+            false,
+            true,
+            answers.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        (choices, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn proposes_the_backend_each_desktop_supports() {
+        let wayland = Some(SessionType::Wayland);
+        let proposed = |compositor, session, programs: &[&'static str]| {
+            proposed_backend(&environment(session, compositor, programs))
+        };
+        assert_eq!(
+            proposed(Some(Compositor::Hyprland), wayland, &[]),
+            ActiveWindowBackend::Auto
+        );
+        assert_eq!(
+            proposed(Some(Compositor::Sway), wayland, &["swaymsg", "jq"]),
+            sway_backend()
+        );
+        assert_eq!(
+            proposed(Some(Compositor::Sway), wayland, &["swaymsg"]),
+            ActiveWindowBackend::Disabled
+        );
+        assert_eq!(
+            proposed(Some(Compositor::Gnome), wayland, &[]),
+            ActiveWindowBackend::Disabled
+        );
+        assert_eq!(
+            proposed(Some(Compositor::Gnome), Some(SessionType::X11), &[]),
+            ActiveWindowBackend::Auto
+        );
+        assert_eq!(proposed(None, None, &[]), ActiveWindowBackend::Auto);
+
+        for name in ["auto", "disabled", "sway"] {
+            assert_eq!(backend_name(&parse_backend(name).unwrap()), name);
+        }
+        assert_eq!(parse_backend("xdotool"), None);
+    }
+
+    #[test]
+    fn accepting_defaults_asks_nothing() {
+        let mut output = Vec::new();
+        let overrides = SetupOverrides {
+            history_limit: Some(200),
+            ..SetupOverrides::default()
+        };
+        let choices = choose(proposal(), overrides, true, false, &b""[..], &mut output).unwrap();
+        assert!(output.is_empty());
+        assert_eq!(
+            choices,
+            SetupChoices {
+                history_limit: 200,
+                ..proposal()
+            }
+        );
+        assert_eq!(choices.blacklist, PASSWORD_MANAGERS);
+        assert!(choices.install_service, "systemctl is installed");
+    }
+
+    #[test]
+    fn asks_about_what_the_flags_left_open() {
+        let (choices, output) = answered(
+            SetupOverrides {
+                notifications: Some(false),
+                ..SetupOverrides::default()
+            },
+            "xdotool\ndisabled\nn\nSignal, Secrets\nlots\n500\n\n",
+        );
+        assert_eq!(
+            choices,
+            SetupChoices {
+                active_window: ActiveWindowBackend::Disabled,
+                blacklist: vec!["Signal".to_string(), "Secrets".to_string()],
+                history_limit: 500,
+                notifications: false,
+                install_service: true,
+            }
+        );
+        assert!(output.contains("Active-window backend (auto, disabled, sway) [auto]:"));
+        assert!(output.contains("Expected auto, disabled or sway."));
+        assert!(output.contains("Expected a positive number."));
+        assert!(!output.contains("notification"), "answered by the flag");
+        assert!(output.contains("Install the systemd user service? [Y/n]"));
+
+        let (choices, _) = answered(SetupOverrides::default(), "");
+        assert_eq!(choices, proposal(), "input ended, so every proposal stands");
+    }
+
+    #[test]
+    fn refuses_to_guess_without_a_terminal() {
+        let overrides = SetupOverrides {
+            active_window: Some(ActiveWindowBackend::Auto),
+            blacklist: Some(Vec::new()),
+            ..SetupOverrides::default()
+        };
+        let err = choose(proposal(), overrides, false, false, &b""[..], Vec::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "pass --history-limit or --accept-defaults when stdin is not a terminal"
+        );
+
+        let overrides = SetupOverrides {
+            active_window: Some(ActiveWindowBackend::Auto),
+            blacklist: Some(Vec::new()),
+            history_limit: Some(10),
+            notifications: Some(true),
+            install_service: Some(false),
+        };
+        assert!(choose(proposal(), overrides, false, false, &b""[..], Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn writes_a_config_that_loads_back() {
+        let choices = SetupChoices {
+            active_window: sway_backend(),
+            blacklist: vec!["KeePassXC".to_string(), "it's: secret".to_string()],
+            history_limit: 120,
+            notifications: false,
+            install_service: false,
+        };
+        let yaml = config_yaml(&choices).unwrap();
+        assert!(yaml.starts_with("# Written by `slyboard setup`"));
+
+        let config: AppConfig = serde_yaml::from_str(&yaml).unwrap();
+        config.validate().unwrap();
+        let active_window = &config.clipboard.active_window;
+        assert_eq!(active_window.backend, sway_backend());
+        assert_eq!(active_window.blacklist, choices.blacklist);
+        assert!(!config.clipboard.notifications.on_capture);
+        assert_eq!(config.storage.history_limit, 120);
+    }
+}
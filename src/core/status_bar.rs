@@ -0,0 +1,127 @@
+use std::borrow::Borrow;
+
+use serde::Serialize;
+
+use crate::clipboard::preview::preview_text;
+use crate::clipboard::ClipboardEntry;
+
+pub const DEFAULT_BAR_PREVIEW_CHARS: usize = 30;
+pub const DEFAULT_BAR_TOOLTIP_ENTRIES: usize = 5;
+const PAUSED_CLASS: &str = "paused";
+const RUNNING_CLASS: &str = "running";
+
+/// One line of Waybar custom-module output (`"return-type": "json"`). Waybar renders `text`
+/// and `tooltip` as Pango markup, so previews are escaped for it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BarStatus {
+    pub text: String,
+    pub tooltip: String,
+    pub class: String,
+}
+
+impl BarStatus {
+    pub fn new<E: Borrow<ClipboardEntry>>(
+        history: &[E],
+        paused: bool,
+        preview_chars: usize,
+        tooltip_entries: usize,
+    ) -> Self {
+        let text = if paused {
+            PAUSED_CLASS.to_string()
+        } else {
+            history
+                .first()
+                .map(|entry| entry_preview(entry.borrow(), preview_chars))
+                .unwrap_or_default()
+        };
+        // Numbered like `slyboard history`, which gives the oldest entry id 0.
+        let tooltip = history
+            .iter()
+            .take(tooltip_entries)
+            .enumerate()
+            .map(|(index, entry)| {
+                let id = history.len() - 1 - index;
+                format!("{id}: {}", entry_preview(entry.borrow(), preview_chars))
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let class = if paused { PAUSED_CLASS } else { RUNNING_CLASS };
+
+        Self {
+            text,
+            tooltip,
+            class: class.to_string(),
+        }
+    }
+
+    pub fn to_json_line(&self) -> String {
+        serde_json::to_string(self).expect("bar status serializes to JSON")
+    }
+}
+
+fn entry_preview(entry: &ClipboardEntry, preview_chars: usize) -> String {
+    match entry {
+        ClipboardEntry::Text { value, .. } => markup_escape(&preview_text(value, preview_chars)),
+        ClipboardEntry::Image { width, height, .. } => format!("[image] {}x{}", width, height),
+    }
+}
+
+/// `value` as Pango markup that shows it literally.
+fn markup_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BarStatus;
+    use crate::clipboard::ClipboardEntry;
+    use crate::testing::text;
+
+    #[test]
+    fn shows_newest_entry_and_recent_tooltip() {
+        let history = vec![
+            text("newest \"quoted\" value"),
+            text("older"),
+            text("oldest"),
+        ];
+        let status = BarStatus::new(&history, false, 10, 2);
+
+        assert_eq!(status.text, "newest &quot;qu...");
+        assert_eq!(status.tooltip, "2: newest &quot;qu...\n1: older");
+        assert_eq!(status.class, "running");
+        assert_eq!(
+            status.to_json_line(),
+            r#"{"text":"newest &quot;qu...","tooltip":"2: newest &quot;qu...\n1: older","class":"running"}"#
+        );
+    }
+
+    #[test]
+    fn escapes_previews_for_pango() {
+        let status = BarStatus::new(&[text("<b>Tom & 'Jerry'</b>")], false, 40, 5);
+        assert_eq!(status.text, "&lt;b&gt;Tom &amp; &#39;Jerry&#39;&lt;/b&gt;");
+        assert_eq!(status.tooltip, format!("0: {}", status.text));
+    }
+
+    #[test]
+    fn reports_paused_state_and_empty_history() {
+        let status = BarStatus::new(&[text("a\nb")], true, 10, 5);
+        assert_eq!(status.text, "paused");
+        assert_eq!(status.tooltip, "0: a\\nb");
+        assert_eq!(status.class, "paused");
+
+        let status = BarStatus::new::<ClipboardEntry>(&[], false, 10, 5);
+        assert_eq!(status.text, "");
+        assert_eq!(status.tooltip, "");
+    }
+}
@@ -0,0 +1,355 @@
+//! Running as a systemd user service: `sd_notify` readiness, status, and watchdog messages for
+//! `Type=notify` units, and the unit `slyboard install-service` writes.
+
+use std::env;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+
+/// How often the daemon checks for pause changes to report when systemd has no watchdog.
+pub const STATUS_POLL_INTERVAL: Duration = Duration::from_secs(5);
+pub const UNIT_NAME: &str = "slyboard.service";
+
+/// Sends `sd_notify` messages to `$NOTIFY_SOCKET`. Without it (not started by systemd, or not
+/// a `Type=notify` unit) every message is dropped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Notifier {
+    socket: Option<String>,
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| env::var(name).ok(), std::process::id())
+    }
+
+    /// Reads `NOTIFY_SOCKET`, `WATCHDOG_USEC`, and `WATCHDOG_PID` through `var`. The watchdog is
+    /// ignored when `WATCHDOG_PID` names another process than `pid`.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>, pid: u32) -> Self {
+        let var = |name: &str| var(name).filter(|value| !value.trim().is_empty());
+        let socket = var("NOTIFY_SOCKET");
+        let for_us = var("WATCHDOG_PID").is_none_or(|watchdog_pid| {
+            watchdog_pid
+                .trim()
+                .parse::<u32>()
+                .is_ok_and(|watchdog_pid| watchdog_pid == pid)
+        });
+        let watchdog = var("WATCHDOG_USEC")
+            .and_then(|usec| usec.trim().parse::<u64>().ok())
+            .filter(|&usec| usec > 0 && for_us && socket.is_some())
+            .map(Duration::from_micros);
+        Self { socket, watchdog }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// How often to send `WATCHDOG=1`: half the interval systemd allows, so one late tick
+    /// doesn't get the daemon restarted.
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog.map(|timeout| timeout / 2)
+    }
+
+    /// Sends one datagram of newline-separated `KEY=value` assignments.
+    pub fn notify(&self, message: &str) -> io::Result<()> {
+        let Some(socket) = &self.socket else {
+            return Ok(());
+        };
+        send_datagram(socket, message)
+    }
+
+    /// Like [`notify`](Self::notify), logging failures instead of returning them: a service
+    /// manager that stops listening is no reason to stop capturing.
+    pub fn notify_or_warn(&self, message: &str) {
+        if let Err(err) = self.notify(message) {
+            crate::log_eprintln!("warning: failed to notify systemd: {err}");
+        }
+    }
+
+    /// Keeps systemd informed until `wait` returns a termination signal, and returns its name.
+    ///
+    /// `wait` blocks for at most the given tick (forever for `None`) and returns `None` when it
+    /// ran out. Every tick pings the watchdog, if there is one, and sends `STATUS=` when
+    /// `paused` changed since the last one.
+    pub fn supervise(
+        &self,
+        mut wait: impl FnMut(Option<Duration>) -> io::Result<Option<&'static str>>,
+        paused: impl Fn() -> bool,
+    ) -> io::Result<&'static str> {
+        if !self.is_enabled() {
+            loop {
+                if let Some(signal) = wait(None)? {
+                    return Ok(signal);
+                }
+            }
+        }
+        let tick = self
+            .watchdog_interval()
+            .map_or(STATUS_POLL_INTERVAL, |interval| {
+                interval.min(STATUS_POLL_INTERVAL)
+            });
+        let mut was_paused = paused();
+        loop {
+            if let Some(signal) = wait(Some(tick))? {
+                return Ok(signal);
+            }
+            if self.watchdog.is_some() {
+                self.notify_or_warn("WATCHDOG=1");
+            }
+            let now_paused = paused();
+            if now_paused != was_paused {
+                self.notify_or_warn(&status_message(now_paused));
+                was_paused = now_paused;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn send_datagram(socket: &str, message: &str) -> io::Result<()> {
+    let datagram = UnixDatagram::unbound()?;
+    match socket.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            datagram.send_to_addr(message.as_bytes(), &addr)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "abstract notify sockets are only supported on Linux",
+            ))
+        }
+        None => {
+            datagram.send_to(message.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send_datagram(_socket: &str, _message: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "notify sockets are only supported on Unix",
+    ))
+}
+
+/// The `STATUS=` line `systemctl status` shows for the daemon.
+pub fn status_message(paused: bool) -> String {
+    if paused {
+        "STATUS=Clipboard capture is paused".to_string()
+    } else {
+        "STATUS=Capturing clipboard".to_string()
+    }
+}
+
+/// `$XDG_CONFIG_HOME/systemd/user/slyboard.service`.
+pub fn default_unit_path() -> Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .ok_or(Error::MissingDirectory { kind: "config" })?
+        .join("systemd")
+        .join("user")
+        .join(UNIT_NAME))
+}
+
+/// A `Type=notify` user unit running `program`, started with the graphical session. `config`
+/// is passed as `--config` when given.
+pub fn unit_file(program: &Path, config: Option<&Path>) -> String {
+    let mut exec_start = quote_arg(&program.to_string_lossy());
+    if let Some(config) = config {
+        exec_start.push_str(" --config ");
+        exec_start.push_str(&quote_arg(&config.to_string_lossy()));
+    }
+    format!(
+        "[Unit]\n\
+         Description=slyboard clipboard manager\n\
+         After=graphical-session.target\n\
+         PartOf=graphical-session.target\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         ExecStart={exec_start} run --foreground\n\
+         Restart=on-failure\n\
+         RestartSec=2\n\
+         WatchdogSec=60\n\
+         \n\
+         [Install]\n\
+         WantedBy=graphical-session.target\n"
+    )
+}
+
+/// Quotes `arg` for `ExecStart=` when it contains spaces, quotes, or backslashes; `%` and `$`
+/// are always escaped since systemd expands them.
+fn quote_arg(arg: &str) -> String {
+    let escaped = arg.replace('%', "%%").replace('$', "$$");
+    if !escaped.contains([' ', '\t', '"', '\'', '\\']) {
+        return escaped;
+    }
+    format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unit_file, Notifier, STATUS_POLL_INTERVAL};
+    use std::collections::HashMap;
+    #[cfg(unix)]
+    use std::os::unix::net::UnixDatagram;
+    use std::path::Path;
+    use std::time::Duration;
+
+    fn notifier(vars: &[(&str, &str)], pid: u32) -> Notifier {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        Notifier::from_vars(|name| vars.get(name).map(|value| value.to_string()), pid)
+    }
+
+    #[cfg(unix)]
+    fn socket_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "slyboard-test-notify-{}-{name}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[cfg(unix)]
+    fn received(socket: &UnixDatagram) -> Vec<String> {
+        let mut messages = Vec::new();
+        let mut buf = [0u8; 256];
+        while let Ok(len) = socket.recv(&mut buf) {
+            messages.push(String::from_utf8_lossy(&buf[..len]).into_owned());
+        }
+        messages
+    }
+
+    #[test]
+    fn reads_the_socket_and_watchdog_from_the_environment() {
+        assert!(!notifier(&[], 7).is_enabled());
+        assert_eq!(
+            notifier(&[("WATCHDOG_USEC", "1000000")], 7).watchdog_interval(),
+            None
+        );
+        assert!(
+            notifier(&[], 7).notify("READY=1").is_ok(),
+            "no-op without a socket"
+        );
+
+        let with_watchdog = notifier(
+            &[
+                ("NOTIFY_SOCKET", "/run/notify"),
+                ("WATCHDOG_USEC", "30000000"),
+            ],
+            7,
+        );
+        assert!(with_watchdog.is_enabled());
+        assert_eq!(
+            with_watchdog.watchdog_interval(),
+            Some(Duration::from_secs(15))
+        );
+
+        let other_pid = notifier(
+            &[
+                ("NOTIFY_SOCKET", "/run/notify"),
+                ("WATCHDOG_USEC", "30000000"),
+                ("WATCHDOG_PID", "8"),
+            ],
+            7,
+        );
+        assert_eq!(other_pid.watchdog_interval(), None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn sends_datagrams_and_reports_pause_changes_each_tick() {
+        use super::status_message;
+        use std::cell::RefCell;
+
+        let path = socket_path("supervise");
+        let socket = UnixDatagram::bind(&path).expect("bind notify socket");
+        socket.set_nonblocking(true).unwrap();
+        let notifier = notifier(
+            &[
+                ("NOTIFY_SOCKET", path.to_str().unwrap()),
+                ("WATCHDOG_USEC", "4000000"),
+            ],
+            7,
+        );
+        notifier.notify("READY=1").unwrap();
+        assert_eq!(received(&socket), ["READY=1"]);
+
+        // Paused from the second tick on; the fourth wait ends with a signal.
+        let ticks = RefCell::new(Vec::new());
+        let signal = notifier
+            .supervise(
+                |tick| {
+                    ticks.borrow_mut().push(tick);
+                    Ok((ticks.borrow().len() == 4).then_some("SIGTERM"))
+                },
+                || ticks.borrow().len() >= 2,
+            )
+            .unwrap();
+        assert_eq!(signal, "SIGTERM");
+        assert_eq!(ticks.into_inner(), vec![Some(Duration::from_secs(2)); 4]);
+        assert_eq!(
+            received(&socket),
+            [
+                "WATCHDOG=1",
+                "WATCHDOG=1",
+                &status_message(true),
+                "WATCHDOG=1"
+            ]
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn blocks_without_a_tick_when_not_under_systemd() {
+        let mut waits = Vec::new();
+        let signal = Notifier::default()
+            .supervise(
+                |tick| {
+                    waits.push(tick);
+                    Ok(Some("SIGINT"))
+                },
+                || unreachable!("pause state is only polled under systemd"),
+            )
+            .unwrap();
+        assert_eq!((signal, waits), ("SIGINT", vec![None]));
+
+        let mut ticks = Vec::new();
+        notifier(&[("NOTIFY_SOCKET", "/nonexistent/notify")], 7)
+            .supervise(
+                |tick| {
+                    ticks.push(tick);
+                    Ok(Some("SIGTERM"))
+                },
+                || false,
+            )
+            .unwrap();
+        assert_eq!(ticks, vec![Some(STATUS_POLL_INTERVAL)]);
+    }
+
+    #[test]
+    fn writes_a_notify_unit_with_escaped_paths() {
+        let unit = unit_file(Path::new("/usr/bin/slyboard"), None);
+        assert!(unit.contains("Type=notify\n"));
+        assert!(unit.contains("ExecStart=/usr/bin/slyboard run --foreground\n"));
+        assert!(unit.contains("WantedBy=graphical-session.target\n"));
+
+        let unit = unit_file(
+            Path::new("/opt/my apps/slyboard"),
+            Some(Path::new("/home/a/100%.yaml")),
+        );
+        assert!(unit.contains(
+            "ExecStart=\"/opt/my apps/slyboard\" --config /home/a/100%%.yaml run --foreground\n"
+        ));
+    }
+}
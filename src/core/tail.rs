@@ -0,0 +1,139 @@
+//! `slyboard tail`: one readable line per new capture. New entries are found by comparing
+//! history snapshots, so captures that land between two polls are all reported, oldest first.
+
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use crate::clipboard::age::format_clock_time;
+use crate::clipboard::digest::StableHasher;
+use crate::clipboard::template::{entry_kind, entry_preview};
+use crate::clipboard::ClipboardEntry;
+
+pub const DEFAULT_TAIL_PREVIEW_CHARS: usize = 80;
+/// Width of the app column, so previews line up.
+const APP_COLUMN_CHARS: usize = 16;
+/// ANSI foreground colors (red through cyan) cycled through by app for `--follow-source`.
+const SOURCE_COLORS: [u8; 6] = [31, 32, 33, 34, 35, 36];
+
+/// Which history entries have been reported. An entry is identified by its content and
+/// capture time, so copying something again is reported again while pinning or labelling it
+/// is not.
+#[derive(Debug, Default)]
+pub struct TailCursor {
+    seen: HashSet<(u64, Option<u64>)>,
+}
+
+impl TailCursor {
+    /// Starts after everything in `history`, so only later captures are reported.
+    pub fn after(history: &[Arc<ClipboardEntry>]) -> Self {
+        let mut cursor = Self::default();
+        cursor.advance(history);
+        cursor
+    }
+
+    /// Entries of `history` (newest first, as snapshots are) not reported yet, oldest first.
+    pub fn advance(&mut self, history: &[Arc<ClipboardEntry>]) -> Vec<Arc<ClipboardEntry>> {
+        let current: HashSet<_> = history.iter().map(|entry| entry_key(entry)).collect();
+        let new = history
+            .iter()
+            .rev()
+            .filter(|entry| !self.seen.contains(&entry_key(entry)))
+            .cloned()
+            .collect();
+        // Entries that left history are forgotten, so the set stays as small as history.
+        self.seen = current;
+        new
+    }
+}
+
+fn entry_key(entry: &ClipboardEntry) -> (u64, Option<u64>) {
+    (entry.content_id(), entry.captured_at())
+}
+
+/// `14:03:22  firefox           text   preview...`. With `colorize`, the app name is colored,
+/// the same color for the same app every time.
+pub fn render_tail_line(entry: &ClipboardEntry, preview_chars: usize, colorize: bool) -> String {
+    let clock = entry
+        .captured_at()
+        .map(format_clock_time)
+        .unwrap_or_else(|| "--:--:--".to_string());
+    let app = entry
+        .source_window()
+        .and_then(|window| window.app_id.as_deref())
+        .unwrap_or("-");
+    let padding = " ".repeat(APP_COLUMN_CHARS.saturating_sub(app.chars().count()));
+    let app_column = if colorize && app != "-" {
+        format!("\x1b[{}m{app}\x1b[0m{padding}", source_color(app))
+    } else {
+        format!("{app}{padding}")
+    };
+    format!(
+        "{clock}  {app_column}  {:<5}  {}",
+        entry_kind(entry),
+        entry_preview(entry, preview_chars)
+    )
+}
+
+fn source_color(app: &str) -> u8 {
+    let mut hasher = StableHasher::default();
+    app.hash(&mut hasher);
+    SOURCE_COLORS[(hasher.finish() % SOURCE_COLORS.len() as u64) as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_tail_line, source_color, TailCursor};
+    use crate::clipboard::ClipboardEntry;
+    use crate::core::active_window::ActiveWindowContext;
+    use std::sync::Arc;
+
+    fn entry(value: &str, captured_at: u64) -> Arc<ClipboardEntry> {
+        Arc::new(ClipboardEntry::text(value).with_captured_at(Some(captured_at)))
+    }
+
+    #[test]
+    fn reports_every_capture_between_polls_oldest_first() {
+        let a = entry("a", 100);
+        let mut cursor = TailCursor::after(std::slice::from_ref(&a));
+        assert!(cursor.advance(std::slice::from_ref(&a)).is_empty());
+
+        let (b, c) = (entry("b", 101), entry("c", 101));
+        let new = cursor.advance(&[c.clone(), b.clone(), a.clone()]);
+        assert_eq!(new, vec![b.clone(), c.clone()]);
+
+        // Copying `a` again moves it to the front with a new capture time.
+        let again = entry("a", 105);
+        let pinned = Arc::new((*b).clone().with_pinned(true));
+        assert_eq!(cursor.advance(&[again.clone(), c, pinned]), vec![again]);
+    }
+
+    #[test]
+    fn renders_aligned_lines_with_optional_source_colors() {
+        let source = ActiveWindowContext {
+            backend: "hyprctl".to_string(),
+            title: "Mozilla Firefox".to_string(),
+            app_id: Some("firefox".to_string()),
+            initial_app_id: None,
+            initial_title: None,
+            window_id: None,
+            pid: None,
+            workspace_id: None,
+            workspace_name: None,
+            is_xwayland: None,
+            monitor: None,
+            geometry: None,
+        };
+        let entry = ClipboardEntry::text("hello\nworld").with_source(source);
+        let plain = render_tail_line(&entry, 80, false);
+        assert_eq!(plain, "--:--:--  firefox           text   hello\\nworld");
+
+        let colored = render_tail_line(&entry, 80, true);
+        let color = source_color("firefox");
+        assert!(colored.contains(&format!("\x1b[{color}mfirefox\x1b[0m         ")));
+        assert_eq!(
+            render_tail_line(&ClipboardEntry::text("x"), 80, true),
+            "--:--:--  -                 text   x"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! SIGTERM (`slyboard stop`) and SIGINT (Ctrl-C) are blocked in every daemon thread and
+//! awaited on the main thread, so they end the daemon through
+//! `SharedClipboardState::shutdown` instead of killing it between two storage writes.
+//! SIGUSR2 is blocked and awaited alongside them, so the daemon can log its capture counters
+//! when asked instead of being killed by its default action.
+
+use std::io;
+use std::mem::MaybeUninit;
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+
+const AWAITED_SIGNALS: [libc::c_int; 3] = [libc::SIGTERM, libc::SIGINT, libc::SIGUSR2];
+
+/// The name [`TerminationSignals::wait`] returns for SIGUSR2, which asks for the counters
+/// rather than for termination.
+pub const DUMP_SIGNAL: &str = "SIGUSR2";
+
+/// The blocked termination signals of this thread and the threads it spawns afterwards.
+pub struct TerminationSignals {
+    set: libc::sigset_t,
+}
+
+impl TerminationSignals {
+    /// Blocks the termination signals for the calling thread. Threads inherit the mask, so
+    /// call this before spawning any, or a signal may be delivered to one that doesn't wait.
+    pub fn block() -> io::Result<Self> {
+        let mut set = MaybeUninit::<libc::sigset_t>::uninit();
+        // SAFETY: `sigemptyset` initializes the set before `sigaddset` and `pthread_sigmask`
+        // read it; all pointers are valid for the duration of each call.
+        let set = unsafe {
+            libc::sigemptyset(set.as_mut_ptr());
+            for signal in AWAITED_SIGNALS {
+                libc::sigaddset(set.as_mut_ptr(), signal);
+            }
+            set.assume_init()
+        };
+        // SAFETY: `set` is initialized and the old mask is not requested.
+        let result = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) };
+        if result != 0 {
+            return Err(io::Error::from_raw_os_error(result));
+        }
+        Ok(Self { set })
+    }
+
+    /// Waits for a termination signal or [`DUMP_SIGNAL`] and returns its name.
+    pub fn wait(&self) -> io::Result<&'static str> {
+        let mut signal = 0;
+        // SAFETY: `self.set` is initialized and `signal` outlives the call.
+        let result = unsafe { libc::sigwait(&self.set, &mut signal) };
+        if result != 0 {
+            return Err(io::Error::from_raw_os_error(result));
+        }
+        Ok(signal_name(signal))
+    }
+
+    /// Like [`wait`](Self::wait), but gives up after `timeout` and returns `None`; waits
+    /// forever when `timeout` is `None`.
+    #[cfg(target_os = "linux")]
+    pub fn wait_timeout(&self, timeout: Option<Duration>) -> io::Result<Option<&'static str>> {
+        let Some(timeout) = timeout else {
+            return self.wait().map(Some);
+        };
+        let timeout = libc::timespec {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_nsec: timeout.subsec_nanos() as libc::c_long,
+        };
+        loop {
+            // SAFETY: `self.set` is initialized, no signal info is requested, and `timeout`
+            // outlives the call.
+            let signal = unsafe { libc::sigtimedwait(&self.set, std::ptr::null_mut(), &timeout) };
+            if signal >= 0 {
+                return Ok(Some(signal_name(signal)));
+            }
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                Some(libc::EAGAIN) => return Ok(None),
+                // Interrupted by a signal we don't wait for; the tick restarts, which only
+                // delays it.
+                Some(libc::EINTR) => continue,
+                _ => return Err(err),
+            }
+        }
+    }
+}
+
+fn signal_name(signal: libc::c_int) -> &'static str {
+    match signal {
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGINT => "SIGINT",
+        libc::SIGUSR2 => DUMP_SIGNAL,
+        _ => "signal",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TerminationSignals;
+
+    #[test]
+    fn waits_for_a_blocked_signal() {
+        // Runs on its own test thread; a thread-directed signal only depends on its mask.
+        let signals = TerminationSignals::block().expect("block");
+        // SAFETY: signals the current thread, which has SIGINT blocked.
+        assert_eq!(
+            unsafe { libc::pthread_kill(libc::pthread_self(), libc::SIGINT) },
+            0
+        );
+        assert_eq!(signals.wait().expect("wait"), "SIGINT");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn times_out_without_a_signal() {
+        let signals = TerminationSignals::block().expect("block");
+        let timeout = Some(std::time::Duration::from_millis(10));
+        assert_eq!(signals.wait_timeout(timeout).expect("wait"), None);
+        // SAFETY: signals the current thread, which has SIGTERM blocked.
+        assert_eq!(
+            unsafe { libc::pthread_kill(libc::pthread_self(), libc::SIGTERM) },
+            0
+        );
+        assert_eq!(
+            signals.wait_timeout(timeout).expect("wait"),
+            Some("SIGTERM")
+        );
+    }
+}
@@ -0,0 +1,809 @@
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::clipboard::classify::ContentHint;
+use crate::clipboard::ocr::record_recognized_text;
+use crate::clipboard::preview::{preview_text, with_hint_tag};
+use crate::clipboard::template::HistoryTemplate;
+use crate::clipboard::{ClipboardEntry, SharedClipboardState};
+use crate::core::append_mode::{format_piece_count, AppendBuffer};
+use crate::core::screen_lock::CaptureState;
+
+pub const MENU_LABEL_CHAR_LIMIT: usize = 70;
+/// History labels when `tray.label_template` is unset: just the preview.
+pub const DEFAULT_MENU_LABEL_TEMPLATE: &str = "{preview:70}";
+pub const EMPTY_HISTORY_LABEL: &str = "No clipboard history yet";
+pub const NO_MATCHES_LABEL: &str = "No matching entries";
+pub const NO_SLOTS_LABEL: &str = "No slots saved";
+/// Characters of the newest entry the tray title shows.
+pub const TITLE_PREVIEW_CHAR_LIMIT: usize = 40;
+
+const RUNNING_LABEL: &str = "Running";
+const PAUSED_LABEL: &str = "Paused";
+const PAUSED_SCREEN_LOCKED_LABEL: &str = "Paused (screen locked)";
+const PAUSE_CAPTURE_LABEL: &str = "Pause Capture";
+const RESUME_CAPTURE_LABEL: &str = "Resume Capture";
+
+/// What activating a tray menu item asks the daemon to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrayAction {
+    TogglePause,
+    /// Acts on the history entry whose [`ClipboardEntry::content_id`] is `id`, wherever it
+    /// has moved since the menu was built.
+    Entry {
+        id: u64,
+        action: EntryAction,
+    },
+    /// Puts the slot entry whose [`ClipboardEntry::content_id`] is `id` on the clipboard.
+    RestoreSlot {
+        id: u64,
+    },
+    ClearHistory,
+    Quit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryAction {
+    /// Put the entry back on the clipboard.
+    Copy,
+    Delete,
+    TogglePin,
+    /// Open a URL entry in the default browser.
+    OpenUrl,
+    /// Copy the text recognized in an image entry, recording it as a new entry.
+    RecognizeText,
+}
+
+/// Desktop side effects of entry actions, kept behind a trait so dispatch runs without GTK.
+pub trait EntryActionTarget {
+    fn copy(&self, entry: &ClipboardEntry);
+    fn open_url(&self, url: &str);
+    /// The text in an image entry; only asked for when the menu offered [`EntryAction::RecognizeText`].
+    fn recognize_text(&self, entry: &ClipboardEntry) -> Result<String>;
+}
+
+/// Toolkit-independent description of the tray menu; each tray backend renders this.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrayMenuItem {
+    /// Insensitive status line.
+    Label(String),
+    Action {
+        label: String,
+        action: TrayAction,
+        /// Shown on hover, e.g. the entry's label.
+        tooltip: Option<String>,
+    },
+    Separator,
+    Submenu {
+        label: String,
+        items: Vec<TrayMenuItem>,
+        /// Run when the submenu's own item is clicked instead of hovered.
+        activate: Option<TrayAction>,
+        tooltip: Option<String>,
+    },
+}
+
+/// How history entries are labelled: a `--format`-style template, with values escaped onto
+/// one line, cut to `char_limit`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MenuLabelFormat {
+    pub template: HistoryTemplate,
+    pub char_limit: usize,
+}
+
+impl MenuLabelFormat {
+    pub fn new(template: Option<&str>, char_limit: usize) -> Result<Self> {
+        Ok(Self {
+            template: HistoryTemplate::parse(template.unwrap_or(DEFAULT_MENU_LABEL_TEMPLATE))?
+                .escaped(true),
+            char_limit,
+        })
+    }
+}
+
+impl Default for MenuLabelFormat {
+    fn default() -> Self {
+        Self::new(None, MENU_LABEL_CHAR_LIMIT).expect("default menu label template is valid")
+    }
+}
+
+/// Status line and pause-toggle labels for a capture state.
+pub fn capture_labels(capture_state: CaptureState) -> (&'static str, &'static str) {
+    match capture_state {
+        CaptureState::Running => (RUNNING_LABEL, PAUSE_CAPTURE_LABEL),
+        CaptureState::Paused => (PAUSED_LABEL, RESUME_CAPTURE_LABEL),
+        CaptureState::PausedScreenLocked => (PAUSED_SCREEN_LOCKED_LABEL, PAUSE_CAPTURE_LABEL),
+    }
+}
+
+/// The status line, which while capturing tells when the last copy was withheld because of
+/// a paused workspace, e.g. "Capture suspended (workspace private)".
+pub fn capture_status_label(
+    capture_state: CaptureState,
+    suppressed_workspace: Option<&str>,
+) -> String {
+    match suppressed_workspace {
+        Some(workspace) if capture_state.is_capturing() => {
+            format!("Capture suspended (workspace {workspace})")
+        }
+        _ => capture_labels(capture_state).0.to_string(),
+    }
+}
+
+/// Status line shown while append mode is on, e.g. "Appending (4 pieces)".
+pub fn append_label(buffer: Option<&AppendBuffer>) -> Option<String> {
+    buffer.map(|buffer| format!("Appending ({})", format_piece_count(buffer.pieces.len())))
+}
+
+/// The tray icon's title, which most hosts show as its tooltip, e.g.
+/// "slyboard — 37 entries — last: https://example.com" while capturing and
+/// "slyboard — paused" while not.
+pub fn tray_title(
+    capture_state: CaptureState,
+    history_len: usize,
+    newest: Option<&ClipboardEntry>,
+) -> String {
+    if !capture_state.is_capturing() {
+        return format!(
+            "slyboard — {}",
+            capture_labels(capture_state).0.to_lowercase()
+        );
+    }
+    let count = match history_len {
+        1 => "1 entry".to_string(),
+        len => format!("{len} entries"),
+    };
+    match newest {
+        Some(entry) => format!(
+            "slyboard — {count} — last: {}",
+            entry_preview(entry, TITLE_PREVIEW_CHAR_LIMIT)
+        ),
+        None => format!("slyboard — {count}"),
+    }
+}
+
+/// The whole tray menu: status, pause toggle, history and slots submenus, clear and quit.
+pub fn tray_menu<E: Borrow<ClipboardEntry>>(
+    capture_state: CaptureState,
+    history: &[E],
+    slots: &BTreeMap<String, Arc<ClipboardEntry>>,
+    label_format: &MenuLabelFormat,
+    entry_submenus: bool,
+    ocr: bool,
+    now: u64,
+) -> Vec<TrayMenuItem> {
+    let (status, toggle) = capture_labels(capture_state);
+    vec![
+        TrayMenuItem::Label(status.to_string()),
+        TrayMenuItem::Action {
+            label: toggle.to_string(),
+            action: TrayAction::TogglePause,
+            tooltip: None,
+        },
+        TrayMenuItem::Separator,
+        TrayMenuItem::Submenu {
+            label: "History".to_string(),
+            items: history_items(history, label_format, entry_submenus, ocr, now),
+            activate: None,
+            tooltip: None,
+        },
+        TrayMenuItem::Submenu {
+            label: "Slots".to_string(),
+            items: slot_items(slots, label_format.char_limit),
+            activate: None,
+            tooltip: None,
+        },
+        TrayMenuItem::Action {
+            label: "Clear History".to_string(),
+            action: TrayAction::ClearHistory,
+            tooltip: None,
+        },
+        TrayMenuItem::Separator,
+        TrayMenuItem::Action {
+            label: "Quit".to_string(),
+            action: TrayAction::Quit,
+            tooltip: None,
+        },
+    ]
+}
+
+/// One item per history entry, or a placeholder when history is empty. With
+/// `entry_submenus` each entry gets its own Copy/Delete/Pin submenu, otherwise clicking it copies.
+/// With `ocr`, image entries' submenus also offer to copy the text in them.
+pub fn history_items<E: Borrow<ClipboardEntry>>(
+    history: &[E],
+    label_format: &MenuLabelFormat,
+    entry_submenus: bool,
+    ocr: bool,
+    now: u64,
+) -> Vec<TrayMenuItem> {
+    if history.is_empty() {
+        return vec![TrayMenuItem::Label(EMPTY_HISTORY_LABEL.to_string())];
+    }
+    let entries: Vec<(usize, &ClipboardEntry)> =
+        history.iter().map(Borrow::borrow).enumerate().collect();
+    matching_history_items(&entries, label_format, entry_submenus, ocr, now)
+}
+
+/// Restore items for a filtered history, as `(index, entry)` pairs from
+/// [`HistoryFilter::apply`](crate::clipboard::filters::HistoryFilter::apply).
+pub fn matching_history_items(
+    entries: &[(usize, &ClipboardEntry)],
+    label_format: &MenuLabelFormat,
+    entry_submenus: bool,
+    ocr: bool,
+    now: u64,
+) -> Vec<TrayMenuItem> {
+    if entries.is_empty() {
+        return vec![TrayMenuItem::Label(NO_MATCHES_LABEL.to_string())];
+    }
+
+    entries
+        .iter()
+        .map(|(index, entry)| {
+            let label = format_menu_label(*index, entry, label_format, now);
+            let id = entry.content_id();
+            let copy = TrayAction::Entry {
+                id,
+                action: EntryAction::Copy,
+            };
+            let tooltip = entry_tooltip(entry);
+            if !entry_submenus {
+                return TrayMenuItem::Action {
+                    label,
+                    action: copy,
+                    tooltip,
+                };
+            }
+            TrayMenuItem::Submenu {
+                label,
+                items: entry_action_items(entry, id, ocr),
+                activate: Some(copy),
+                tooltip,
+            }
+        })
+        .collect()
+}
+
+/// Hover text of a history item: the entry's label, if it has one.
+pub fn entry_tooltip(entry: &ClipboardEntry) -> Option<String> {
+    entry.label().map(|label| format!("Label: {label}"))
+}
+
+fn entry_action_items(entry: &ClipboardEntry, id: u64, ocr: bool) -> Vec<TrayMenuItem> {
+    let item = |label: &str, action| TrayMenuItem::Action {
+        label: label.to_string(),
+        action: TrayAction::Entry { id, action },
+        tooltip: None,
+    };
+    let pin_label = if entry.is_pinned() { "Unpin" } else { "Pin" };
+    let mut items = vec![
+        item("Copy", EntryAction::Copy),
+        item("Delete", EntryAction::Delete),
+        item(pin_label, EntryAction::TogglePin),
+    ];
+    if entry.content_hint() == Some(ContentHint::Url.as_str()) {
+        items.push(item("Open in Browser", EntryAction::OpenUrl));
+    }
+    if ocr && matches!(entry, ClipboardEntry::Image { .. }) {
+        items.push(item("Copy Text (OCR)", EntryAction::RecognizeText));
+    }
+    items
+}
+
+/// One restore item per named slot, labelled `name: preview`, or a placeholder.
+pub fn slot_items(
+    slots: &BTreeMap<String, Arc<ClipboardEntry>>,
+    char_limit: usize,
+) -> Vec<TrayMenuItem> {
+    if slots.is_empty() {
+        return vec![TrayMenuItem::Label(NO_SLOTS_LABEL.to_string())];
+    }
+    slots
+        .iter()
+        .map(|(name, entry)| {
+            let preview_chars = char_limit.saturating_sub(name.chars().count() + 2);
+            TrayMenuItem::Action {
+                label: format!("{name}: {}", entry_preview(entry, preview_chars)),
+                action: TrayAction::RestoreSlot {
+                    id: entry.content_id(),
+                },
+                tooltip: None,
+            }
+        })
+        .collect()
+}
+
+/// Runs `action` on the entry `id` currently refers to. Returns `false` when that entry is no
+/// longer in history, e.g. because it was deleted after the menu was built.
+pub fn dispatch_entry_action(
+    state: &SharedClipboardState,
+    target: &dyn EntryActionTarget,
+    id: u64,
+    action: EntryAction,
+) -> Result<bool> {
+    let history = state.history_snapshot();
+    let Some(index) = history.iter().position(|entry| entry.content_id() == id) else {
+        return Ok(false);
+    };
+    let entry = &history[index];
+    match action {
+        EntryAction::Copy => {
+            target.copy(entry);
+            state.mark_restored(entry)?;
+        }
+        EntryAction::Delete => state.remove_entry(index)?,
+        EntryAction::TogglePin => {
+            state.set_pinned(index, !entry.is_pinned())?;
+        }
+        EntryAction::OpenUrl => {
+            if let ClipboardEntry::Text { value, .. } = entry.as_ref() {
+                target.open_url(value.trim());
+            }
+        }
+        EntryAction::RecognizeText => {
+            // `slyboard history` ids count from the oldest entry.
+            let history_id = history.len() - 1 - index;
+            let text = target.recognize_text(entry)?;
+            target.copy(&record_recognized_text(state, text, history_id, entry)?);
+        }
+    }
+    Ok(true)
+}
+
+/// Renders a history label, keeping any content-hint tag within the character limit.
+pub fn format_menu_label(
+    id: usize,
+    entry: &ClipboardEntry,
+    label_format: &MenuLabelFormat,
+    now: u64,
+) -> String {
+    let tag_chars = with_hint_tag(entry, String::new()).chars().count();
+    let label = label_format.template.render_limited(
+        id,
+        entry,
+        now,
+        label_format.char_limit.saturating_sub(tag_chars),
+    );
+    with_hint_tag(entry, label)
+}
+
+pub fn entry_preview(entry: &ClipboardEntry, char_limit: usize) -> String {
+    match entry {
+        ClipboardEntry::Text { value, .. } => preview_text(value, char_limit),
+        ClipboardEntry::Image { width, height, .. } => {
+            format!("[image] {}x{}", width, height)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clipboard::filters::HistoryFilter;
+    use crate::clipboard::ImageData;
+    use crate::testing::text;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn action(label: &str, action: TrayAction) -> TrayMenuItem {
+        TrayMenuItem::Action {
+            label: label.to_string(),
+            action,
+            tooltip: None,
+        }
+    }
+
+    fn copy(value: &str) -> TrayAction {
+        TrayAction::Entry {
+            id: text(value).content_id(),
+            action: EntryAction::Copy,
+        }
+    }
+
+    #[test]
+    fn menu_lists_history_newest_first() {
+        let history = vec![text("newest"), text("older\nline")];
+        let slots = BTreeMap::from([("url".to_string(), Arc::new(text("https://x.test")))]);
+        let menu = tray_menu(
+            CaptureState::Running,
+            &history,
+            &slots,
+            &MenuLabelFormat::default(),
+            false,
+            false,
+            NOW,
+        );
+
+        assert_eq!(
+            menu,
+            vec![
+                TrayMenuItem::Label("Running".to_string()),
+                action("Pause Capture", TrayAction::TogglePause),
+                TrayMenuItem::Separator,
+                TrayMenuItem::Submenu {
+                    label: "History".to_string(),
+                    items: vec![
+                        action("newest", copy("newest")),
+                        action("older\\nline", copy("older\nline")),
+                    ],
+                    activate: None,
+                    tooltip: None,
+                },
+                TrayMenuItem::Submenu {
+                    label: "Slots".to_string(),
+                    items: vec![action(
+                        "url: https://x.test",
+                        TrayAction::RestoreSlot {
+                            id: text("https://x.test").content_id(),
+                        },
+                    )],
+                    activate: None,
+                    tooltip: None,
+                },
+                action("Clear History", TrayAction::ClearHistory),
+                TrayMenuItem::Separator,
+                action("Quit", TrayAction::Quit),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_history_shows_placeholder() {
+        let history: Vec<ClipboardEntry> = Vec::new();
+        assert_eq!(
+            history_items(&history, &MenuLabelFormat::default(), true, false, NOW),
+            vec![TrayMenuItem::Label(EMPTY_HISTORY_LABEL.to_string())]
+        );
+    }
+
+    #[test]
+    fn slot_items_are_cut_to_the_label_limit() {
+        assert_eq!(
+            slot_items(&BTreeMap::new(), MENU_LABEL_CHAR_LIMIT),
+            vec![TrayMenuItem::Label(NO_SLOTS_LABEL.to_string())]
+        );
+        let slots = BTreeMap::from([("a".to_string(), Arc::new(text(&"x".repeat(20))))]);
+        let [TrayMenuItem::Action { label, .. }] = &slot_items(&slots, 10)[..] else {
+            panic!("expected one slot item");
+        };
+        assert_eq!(label, &format!("a: {}...", "x".repeat(7)));
+    }
+
+    #[test]
+    fn labelled_entries_show_their_label_as_tooltip() {
+        let labelled = text("hunter2").with_label(Some("prod db".to_string()));
+        let items = history_items(
+            &[labelled.clone(), text("plain")],
+            &MenuLabelFormat::default(),
+            false,
+            false,
+            NOW,
+        );
+        let tooltips: Vec<Option<&str>> = items
+            .iter()
+            .map(|item| match item {
+                TrayMenuItem::Action { tooltip, .. } => tooltip.as_deref(),
+                other => panic!("expected an action, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(tooltips, [Some("Label: prod db"), None]);
+
+        let items = history_items(&[labelled], &MenuLabelFormat::default(), true, false, NOW);
+        assert!(matches!(
+            &items[..],
+            [TrayMenuItem::Submenu { tooltip: Some(tooltip), .. }] if tooltip == "Label: prod db"
+        ));
+    }
+
+    #[test]
+    fn filtered_items_keep_history_ids() {
+        let history = vec![text("alpha"), text("beta"), text("alphabet")];
+        let filter = HistoryFilter {
+            search: Some("ALPHA".to_string()),
+            ..HistoryFilter::default()
+        };
+        let format = MenuLabelFormat::default();
+        assert_eq!(
+            matching_history_items(&filter.apply(&history), &format, false, false, NOW),
+            vec![
+                action("alpha", copy("alpha")),
+                action("alphabet", copy("alphabet")),
+            ]
+        );
+
+        let filter = HistoryFilter {
+            search: Some("gamma".to_string()),
+            ..HistoryFilter::default()
+        };
+        assert_eq!(
+            matching_history_items(&filter.apply(&history), &format, false, false, NOW),
+            vec![TrayMenuItem::Label(NO_MATCHES_LABEL.to_string())]
+        );
+    }
+
+    #[test]
+    fn capture_state_sets_status_and_toggle_labels() {
+        assert_eq!(
+            capture_labels(CaptureState::Paused),
+            ("Paused", "Resume Capture")
+        );
+        assert_eq!(
+            capture_labels(CaptureState::PausedScreenLocked),
+            ("Paused (screen locked)", "Pause Capture")
+        );
+        assert_eq!(
+            capture_status_label(CaptureState::Running, Some("private")),
+            "Capture suspended (workspace private)"
+        );
+        assert_eq!(
+            capture_status_label(CaptureState::Paused, Some("private")),
+            "Paused"
+        );
+        assert_eq!(capture_status_label(CaptureState::Running, None), "Running");
+        let menu = tray_menu::<ClipboardEntry>(
+            CaptureState::Paused,
+            &[],
+            &BTreeMap::new(),
+            &MenuLabelFormat::default(),
+            true,
+            false,
+            NOW,
+        );
+        assert_eq!(menu[0], TrayMenuItem::Label("Paused".to_string()));
+        assert_eq!(menu[1], action("Resume Capture", TrayAction::TogglePause));
+    }
+
+    #[test]
+    fn append_label_counts_pieces() {
+        assert_eq!(append_label(None), None);
+        let buffer = AppendBuffer {
+            pieces: vec!["a".to_string(); 4],
+        };
+        assert_eq!(
+            append_label(Some(&buffer)).as_deref(),
+            Some("Appending (4 pieces)")
+        );
+    }
+
+    #[test]
+    fn image_entries_use_dimensions_as_label() {
+        let image = ClipboardEntry::image(ImageData::new(3, 2, 4, 12, vec![0; 24]).unwrap());
+        assert_eq!(
+            format_menu_label(0, &image, &MenuLabelFormat::default(), NOW),
+            "[image] 3x2"
+        );
+    }
+
+    #[test]
+    fn templated_labels_show_age_and_app_within_the_limit() {
+        let entry = ClipboardEntry::text("https://example.com/a/very/long/path")
+            .with_source(crate::core::active_window::ActiveWindowContext {
+                backend: "hyprctl".to_string(),
+                title: "Example".to_string(),
+                app_id: Some("firefox".to_string()),
+                initial_app_id: None,
+                initial_title: None,
+                window_id: None,
+                pid: None,
+                workspace_id: None,
+                workspace_name: None,
+                is_xwayland: None,
+                monitor: None,
+                geometry: None,
+            })
+            .with_classified_content()
+            .with_captured_at(Some(NOW - 120));
+        let format = MenuLabelFormat::new(Some("{time} · {app} · {preview}"), 32).unwrap();
+
+        let label = format_menu_label(0, &entry, &format, NOW);
+        assert_eq!(label, "[url] 2m · firefox · https://...");
+        assert_eq!(label.chars().count(), 32);
+    }
+
+    #[test]
+    fn default_labels_truncate_long_text_to_the_limit() {
+        let entry = text(&"x".repeat(100));
+        let label = format_menu_label(0, &entry, &MenuLabelFormat::default(), NOW);
+        assert_eq!(label.chars().count(), MENU_LABEL_CHAR_LIMIT);
+        assert!(label.ends_with("..."));
+    }
+
+    #[test]
+    fn title_summarizes_history_or_says_why_capture_is_off() {
+        let newest = text(&format!(
+            "https://example.com/{}\nsecond line",
+            "x".repeat(40)
+        ));
+        assert_eq!(
+            tray_title(CaptureState::Running, 37, Some(&newest)),
+            "slyboard — 37 entries — last: https://example.com/xxxxxxxxxxxxxxxxxxxx..."
+        );
+        assert_eq!(
+            tray_title(CaptureState::Running, 1, Some(&text("a\tb\nc"))),
+            "slyboard — 1 entry — last: a b\\nc"
+        );
+        assert_eq!(
+            tray_title(CaptureState::Running, 0, None),
+            "slyboard — 0 entries"
+        );
+        assert_eq!(
+            tray_title(CaptureState::Paused, 37, Some(&newest)),
+            "slyboard — paused"
+        );
+        assert_eq!(
+            tray_title(CaptureState::PausedScreenLocked, 37, Some(&newest)),
+            "slyboard — paused (screen locked)"
+        );
+    }
+
+    #[test]
+    fn entry_submenus_offer_actions_by_entry_kind() {
+        let url = text("https://example.com").with_classified_content();
+        let pinned = text("note").with_pinned(true);
+        let items = history_items(
+            &[url.clone(), pinned.clone()],
+            &MenuLabelFormat::default(),
+            true,
+            false,
+            NOW,
+        );
+        let entry_action = |entry: &ClipboardEntry, action| TrayAction::Entry {
+            id: entry.content_id(),
+            action,
+        };
+
+        assert_eq!(
+            items,
+            vec![
+                TrayMenuItem::Submenu {
+                    label: "[url] https://example.com".to_string(),
+                    items: vec![
+                        action("Copy", entry_action(&url, EntryAction::Copy)),
+                        action("Delete", entry_action(&url, EntryAction::Delete)),
+                        action("Pin", entry_action(&url, EntryAction::TogglePin)),
+                        action("Open in Browser", entry_action(&url, EntryAction::OpenUrl)),
+                    ],
+                    activate: Some(entry_action(&url, EntryAction::Copy)),
+                    tooltip: None,
+                },
+                TrayMenuItem::Submenu {
+                    label: "note".to_string(),
+                    items: vec![
+                        action("Copy", entry_action(&pinned, EntryAction::Copy)),
+                        action("Delete", entry_action(&pinned, EntryAction::Delete)),
+                        action("Unpin", entry_action(&pinned, EntryAction::TogglePin)),
+                    ],
+                    activate: Some(entry_action(&pinned, EntryAction::Copy)),
+                    tooltip: None,
+                },
+            ]
+        );
+    }
+
+    #[derive(Default)]
+    struct RecordingTarget {
+        copied: std::cell::RefCell<Vec<String>>,
+        opened: std::cell::RefCell<Vec<String>>,
+    }
+
+    impl EntryActionTarget for RecordingTarget {
+        fn copy(&self, entry: &ClipboardEntry) {
+            if let ClipboardEntry::Text { value, .. } = entry {
+                self.copied.borrow_mut().push(value.clone());
+            }
+        }
+
+        fn open_url(&self, url: &str) {
+            self.opened.borrow_mut().push(url.to_string());
+        }
+
+        fn recognize_text(&self, _entry: &ClipboardEntry) -> Result<String> {
+            Ok("Error 502".to_string())
+        }
+    }
+
+    fn values(state: &SharedClipboardState) -> Vec<(String, bool)> {
+        state
+            .history_snapshot()
+            .iter()
+            .map(|entry| match entry.as_ref() {
+                ClipboardEntry::Text { value, pinned, .. } => (value.clone(), *pinned),
+                ClipboardEntry::Image { .. } => unreachable!("test history holds text only"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dispatch_follows_entries_after_history_shifts() {
+        let path = std::env::temp_dir()
+            .join(format!("slyboard-test-tray-actions-{}", std::process::id()))
+            .join("history.json");
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+        let state = SharedClipboardState::load(path.clone(), 10).expect("load");
+        let target = RecordingTarget::default();
+        let url = text("https://example.com").with_classified_content();
+        state.record(url.clone()).unwrap();
+        state.record(text("second")).unwrap();
+        let id = |value: &str| text(value).content_id();
+
+        // The menu was built with "second" on top; a newer capture shifts every index.
+        state.record(text("third")).unwrap();
+        assert!(dispatch_entry_action(&state, &target, id("second"), EntryAction::Copy).unwrap());
+        assert_eq!(*target.copied.borrow(), vec!["second"]);
+        assert_eq!(state.history_snapshot()[1].restores().0, 1);
+
+        assert!(
+            dispatch_entry_action(&state, &target, id("second"), EntryAction::TogglePin).unwrap()
+        );
+        assert!(
+            dispatch_entry_action(&state, &target, url.content_id(), EntryAction::OpenUrl).unwrap()
+        );
+        assert_eq!(*target.opened.borrow(), vec!["https://example.com"]);
+        assert!(dispatch_entry_action(&state, &target, id("third"), EntryAction::Delete).unwrap());
+        assert_eq!(
+            values(&state),
+            vec![
+                ("second".to_string(), true),
+                ("https://example.com".to_string(), false),
+            ]
+        );
+
+        assert!(!dispatch_entry_action(&state, &target, id("third"), EntryAction::Copy).unwrap());
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn recognized_text_is_offered_for_images_and_recorded() {
+        let path = std::env::temp_dir()
+            .join(format!("slyboard-test-tray-ocr-{}", std::process::id()))
+            .join("history.json");
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+        let state = SharedClipboardState::load(path.clone(), 10).expect("load");
+        let image = ClipboardEntry::image(ImageData::new(1, 1, 4, 4, vec![0; 4]).unwrap());
+        state.record(image.clone()).unwrap();
+        state.record(text("note")).unwrap();
+
+        let ocr_items = |ocr| -> Vec<usize> {
+            history_items(
+                &state.history_snapshot(),
+                &MenuLabelFormat::default(),
+                true,
+                ocr,
+                NOW,
+            )
+            .iter()
+            .map(|item| match item {
+                TrayMenuItem::Submenu { items, .. } => items
+                    .iter()
+                    .filter(|item| matches!(item, TrayMenuItem::Action { label, .. } if label == "Copy Text (OCR)"))
+                    .count(),
+                other => panic!("expected a submenu, got {other:?}"),
+            })
+            .collect()
+        };
+        assert_eq!(ocr_items(true), vec![0, 1]);
+        assert_eq!(ocr_items(false), vec![0, 0]);
+
+        let target = RecordingTarget::default();
+        let recognize = dispatch_entry_action(
+            &state,
+            &target,
+            image.content_id(),
+            EntryAction::RecognizeText,
+        );
+        assert!(recognize.unwrap());
+        assert_eq!(*target.copied.borrow(), vec!["Error 502"]);
+        let newest = state.history_snapshot()[0].clone();
+        assert!(newest.content_eq(&text("Error 502")));
+        assert_eq!(
+            newest.source_window().map(|source| source.title.as_str()),
+            Some("Text recognized in history entry 0")
+        );
+        assert_eq!(state.history_snapshot().len(), 3);
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
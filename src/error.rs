@@ -0,0 +1,196 @@
+use std::io;
+use std::path::PathBuf;
+
+/// Result type of the library's storage, config, and instance-lock APIs.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Failures library consumers may want to tell apart. The binaries convert these into
+/// `anyhow::Error` with `?`; embedders can match on them, e.g. to move a corrupt history
+/// database aside and start over.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Reading or writing the history database, journal, or cache directory failed.
+    #[error("failed to {action}: {}", path.display())]
+    StorageIo {
+        action: &'static str,
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// The history database or journal exists but can't be parsed.
+    #[error("clipboard history is corrupt: {}: {reason}", path.display())]
+    StorageCorrupt { path: PathBuf, reason: String },
+    /// The history database has a format version this build doesn't know.
+    #[error(
+        "clipboard history was created by a newer slyboard (format version {version}, this \
+         version reads up to {supported}): {}",
+        path.display()
+    )]
+    StorageNewerVersion {
+        path: PathBuf,
+        version: u32,
+        supported: u32,
+    },
+    /// The history database was last saved by a newer major version, which may have stored
+    /// fields this version would drop when it saves.
+    #[error(
+        "clipboard history was last written by slyboard {written_by}, newer than this \
+         {running}; run with --force to use it anyway: {}",
+        path.display()
+    )]
+    StorageNewerWriter {
+        path: PathBuf,
+        written_by: String,
+        running: &'static str,
+    },
+    #[error("failed to serialize clipboard history")]
+    StorageSerialize(#[from] serde_json::Error),
+    /// A file given to `slyboard import` is not in the format it was imported as.
+    #[error("cannot import {}: {reason}", path.display())]
+    ImportCorrupt { path: PathBuf, reason: String },
+    /// A file given to `slyboard diff` or `import` is neither a history export, an export
+    /// bundle, nor a database copy.
+    #[error("cannot read history export {}: {reason}", path.display())]
+    ExportCorrupt { path: PathBuf, reason: String },
+    /// An encrypted export bundle didn't open with the passphrase given.
+    #[error("cannot decrypt {}: wrong passphrase, or the bundle is damaged", path.display())]
+    BundlePassphrase { path: PathBuf },
+    /// `slyboard restore --trash` found no history moved aside by a clear.
+    #[error("the trash is empty: {}", path.display())]
+    TrashEmpty { path: PathBuf },
+    #[error("history entry index out of range: {index}")]
+    HistoryIndex { index: usize },
+    #[error("invalid image: {reason}")]
+    InvalidImage { reason: String },
+    #[error("invalid label: {reason}")]
+    InvalidLabel { reason: String },
+    #[error("invalid slot name {name:?}: use 1 to 32 letters and digits")]
+    InvalidSlotName { name: String },
+    /// `$XDG_CACHE_HOME`/`$HOME` (or the config equivalent) could not be resolved.
+    #[error("unable to resolve {kind} directory from environment")]
+    MissingDirectory { kind: &'static str },
+    #[error("failed to read config: {}", path.display())]
+    ConfigIo {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("no config file found; expected one of:{}", list_paths(candidates))]
+    ConfigNotFound { candidates: Vec<PathBuf> },
+    #[error("failed to parse YAML config: {}", path.display())]
+    ConfigParse {
+        path: PathBuf,
+        #[source]
+        source: serde_yaml::Error,
+    },
+    /// The config parsed but a value is out of range; `reason` names the offending key.
+    #[error("{reason}")]
+    ConfigInvalid { reason: String },
+    /// Another daemon in this session holds the instance lock. `pid` is `None` when the holder
+    /// didn't record one (older versions).
+    #[error(
+        "another slyboard instance is already running ({}lock: {})",
+        pid_prefix(*pid),
+        path.display()
+    )]
+    LockHeld { pid: Option<u32>, path: PathBuf },
+    #[error("failed to {action}: {}", path.display())]
+    LockIo {
+        action: &'static str,
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl Error {
+    pub(crate) fn config_invalid(reason: impl Into<String>) -> Self {
+        Self::ConfigInvalid {
+            reason: reason.into(),
+        }
+    }
+}
+
+fn pid_prefix(pid: Option<u32>) -> String {
+    pid.map(|pid| format!("pid {pid}, ")).unwrap_or_default()
+}
+
+fn list_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| format!("\n- {}", path.display()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+    use std::error::Error as _;
+    use std::io;
+    use std::path::PathBuf;
+
+    #[test]
+    fn formats_messages_with_paths_and_details() {
+        let io_error = Error::StorageIo {
+            action: "read clipboard history database",
+            path: PathBuf::from("/tmp/history.json"),
+            source: io::Error::new(io::ErrorKind::PermissionDenied, "denied"),
+        };
+        assert_eq!(
+            io_error.to_string(),
+            "failed to read clipboard history database: /tmp/history.json"
+        );
+        assert_eq!(io_error.source().unwrap().to_string(), "denied");
+
+        let corrupt = Error::StorageCorrupt {
+            path: PathBuf::from("/tmp/history.jsonl"),
+            reason: "invalid journal record on line 2".to_string(),
+        };
+        assert_eq!(
+            corrupt.to_string(),
+            "clipboard history is corrupt: /tmp/history.jsonl: invalid journal record on line 2"
+        );
+
+        let not_found = Error::ConfigNotFound {
+            candidates: vec![PathBuf::from("./slyboard.yaml"), PathBuf::from("/c.yaml")],
+        };
+        assert_eq!(
+            not_found.to_string(),
+            "no config file found; expected one of:\n- ./slyboard.yaml\n- /c.yaml"
+        );
+
+        let held = |pid| Error::LockHeld {
+            pid,
+            path: PathBuf::from("/run/slyboard.sock"),
+        };
+        assert_eq!(
+            held(Some(42)).to_string(),
+            "another slyboard instance is already running (pid 42, lock: /run/slyboard.sock)"
+        );
+        assert_eq!(
+            held(None).to_string(),
+            "another slyboard instance is already running (lock: /run/slyboard.sock)"
+        );
+        assert_eq!(
+            Error::config_invalid("tray.label_chars must be greater than zero").to_string(),
+            "tray.label_chars must be greater than zero"
+        );
+    }
+
+    #[test]
+    fn converts_from_sources_and_survives_anyhow() {
+        let json = serde_json::from_str::<u32>("nope").unwrap_err();
+        let error = Error::from(json);
+        assert!(matches!(error, Error::StorageSerialize(_)));
+        assert!(error.source().is_some());
+
+        let wrapped = anyhow::Error::from(Error::LockHeld {
+            pid: Some(7),
+            path: PathBuf::from("/run/slyboard.sock"),
+        });
+        assert!(matches!(
+            wrapped.downcast_ref::<Error>(),
+            Some(Error::LockHeld { pid: Some(7), .. })
+        ));
+    }
+}
@@ -1,4 +1,9 @@
 pub mod clipboard;
 pub mod config;
 pub mod core;
+pub mod error;
 pub mod platform;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
+
+pub use error::{Error, Result};
@@ -1,84 +1,822 @@
 mod cli;
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::process::ExitCode;
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use clap::Parser;
-use serde::Serialize;
-use slyboard::clipboard::{ClipboardEntry, SharedClipboardState, DEFAULT_HISTORY_LIMIT};
-use slyboard::config::AppConfig;
-use slyboard::core::active_window::ActiveWindowContext;
-use slyboard::core::capture_control::{is_capture_paused, set_capture_paused};
-use slyboard::core::instance_lock::InstanceLock;
+use slyboard::clipboard::age::unix_now;
+#[cfg(all(target_os = "linux", feature = "gtk"))]
+use slyboard::clipboard::backend::GtkClipboardBackend;
+use slyboard::clipboard::bundle;
+use slyboard::clipboard::details::write_entry_details;
+use slyboard::clipboard::filters::HistoryFilter;
+use slyboard::clipboard::json::{
+    write_json_history, write_jsonl_history, SerializableHistoryEntry,
+};
+use slyboard::clipboard::klipper;
+use slyboard::clipboard::listing::write_history_listing;
+use slyboard::clipboard::migrations;
+use slyboard::clipboard::ocr::{recognize_entry, record_recognized_text, CommandOcr};
+use slyboard::clipboard::slots::SlotStore;
+use slyboard::clipboard::storage::{self, default_database_path, read_database_header};
+use slyboard::clipboard::template::render_default_history_line;
+use slyboard::clipboard::template::{entry_preview, HistoryTemplate};
+use slyboard::clipboard::thumbnails::ThumbnailCache;
+use slyboard::clipboard::transfer;
+#[cfg(target_os = "linux")]
+use slyboard::clipboard::wayland::WaylandBackend;
+use slyboard::clipboard::writer::RestoreTarget;
+use slyboard::clipboard::{CaptureOrigin, ClipboardEntry, PruneOptions, SharedClipboardState};
+use slyboard::config::effective::{EffectiveSettings, SettingOverrides};
+use slyboard::config::layers::MergedConfig;
 #[cfg(target_os = "linux")]
+use slyboard::config::ClipboardBackend as ClipboardBackendKind;
+use slyboard::config::{default_user_config_path, AppConfig};
+use slyboard::core::active_window::{provider_from_config, providers_from_config, CaptureOutcome};
+use slyboard::core::append_mode::{format_piece_count, AppendMode};
+use slyboard::core::capture_control::{is_capture_paused, set_capture_paused};
+use slyboard::core::capture_stats::{load_capture_stats, CaptureStats};
+use slyboard::core::daemon::{capture_single, configured_poller, DaemonStatus};
+use slyboard::core::daemonize::{daemonize, default_log_path, terminate};
+use slyboard::core::environment::Environment;
+use slyboard::core::events;
+use slyboard::core::instance_lock::{running_instance, InstanceLock, RunningInstance};
+use slyboard::core::logging;
+use slyboard::core::plain_paste::{is_plain_paste_enabled, set_plain_paste};
+use slyboard::core::provider_stats::{load_provider_stats, ProviderStats};
+use slyboard::core::setup::{choose, config_yaml, rofi_binding, SetupChoices};
+use slyboard::core::status_bar::BarStatus;
+use slyboard::core::systemd::{default_unit_path, unit_file, UNIT_NAME};
+#[cfg(all(target_os = "linux", feature = "gtk"))]
+use slyboard::core::systemd::{status_message, Notifier};
+use slyboard::core::tail::{render_tail_line, TailCursor};
+#[cfg(all(target_os = "linux", feature = "gtk"))]
+use slyboard::core::termination::{TerminationSignals, DUMP_SIGNAL};
+#[cfg(not(feature = "http"))]
+use slyboard::log_eprintln;
+use slyboard::log_println;
+#[cfg(all(target_os = "linux", feature = "gtk"))]
 use slyboard::platform::tray_indicator;
+use zeroize::Zeroizing;
+
+use crate::cli::{
+    confirm_clear, BarArgs, ClearArgs, Cli, Commands, DiffArgs, ExportArgs, HistoryArgs,
+    HistorySort, ImportArgs, ImportSource, LabelArgs, ModeAction, OcrArgs, PruneArgs, PutArgs,
+    RunArgs, SearchArgs, SetupArgs, ShowArgs, TailArgs, YankArgs,
+};
 
-use crate::cli::{Cli, Commands, HistoryArgs};
+const BAR_FOLLOW_INTERVAL_MS: u64 = 500;
+/// Short, since several captures in a row should show up as they happen.
+const TAIL_POLL_INTERVAL_MS: u64 = 250;
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+const SLOT_LINE_TEMPLATE: &str = "{preview:60} ({approx_size})";
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     let cli = Cli::parse();
+    let overrides = SettingOverrides {
+        history_limit: cli.history_limit,
+    };
+    // Only commands that load history need it, and it may read the config.
+    let settings = || EffectiveSettings::load(cli.config.clone(), overrides);
+    // Any command may capture, restore, clear, pause, or upgrade the database; commands that
+    // need the config report a broken one themselves.
+    if let Ok(Some(loaded)) = AppConfig::load_optional(cli.config.clone()) {
+        migrations::set_legacy_order(loaded.config.storage.legacy_order);
+        if let Err(err) = events::init(&loaded.config.events) {
+            eprintln!("warning: {err:#}");
+        }
+    }
 
-    match cli.command.unwrap_or(Commands::Run) {
-        Commands::Run => run(cli.config),
-        Commands::History(HistoryArgs { json, images }) => print_history(json, images),
-        Commands::ClearHistory => clear_history(),
+    match cli.command.unwrap_or(Commands::Run(RunArgs::default())) {
+        Commands::Run(args) => run(cli.config, overrides, args).inspect_err(|err| {
+            logging::record(&format!("Error: {err:#}"));
+        }),
+        Commands::Stop => stop_daemon(),
+        Commands::CaptureOnce => return capture_clipboard_once(cli.config.clone(), settings()?),
+        Commands::Status(args) => print_daemon_status(args.json, settings()?),
+        Commands::Providers => print_providers(cli.config),
+        Commands::History(args) => print_history(args, settings()?),
+        Commands::Show(args) => show_entry(args, settings()?),
+        Commands::Search(args) => search_history(args, settings()?),
+        Commands::Tail(args) => tail_history(args, settings()?),
+        Commands::Label(args) => label_entry(args, settings()?),
+        Commands::Ocr(args) => {
+            let settings = settings()?;
+            recognize_text(cli.config, args, settings)
+        }
+        Commands::ClearHistory(args) => {
+            let settings = settings()?;
+            clear_history(cli.config, args, settings)
+        }
+        Commands::Restore(_) => restore_trash(settings()?),
         Commands::PauseCapture => pause_capture(),
         Commands::ResumeCapture => resume_capture(),
         Commands::CaptureStatus => print_capture_status(),
-        Commands::ValidateConfig => validate_config(cli.config),
+        Commands::Append(args) => match args.action {
+            ModeAction::On => start_append(),
+            ModeAction::Off => finish_append(cli.config, overrides),
+            ModeAction::Status => print_append_status(),
+        },
+        Commands::PlainPaste(args) => match args.action {
+            ModeAction::On => set_plain_paste_mode(true),
+            ModeAction::Off => set_plain_paste_mode(false),
+            ModeAction::Status => print_plain_paste_status(),
+        },
+        Commands::Bar(args) => print_bar(args, settings()?),
+        Commands::Prune(args) => {
+            let settings = settings()?;
+            prune_history(cli.config, args, settings)
+        }
+        Commands::Yank(args) => yank_slot(args, settings()?),
+        Commands::Put(args) => {
+            let settings = settings()?;
+            put_slot(cli.config, args, settings)
+        }
+        Commands::Slots(args) => match args.remove {
+            Some(slot) => remove_slot(&slot),
+            None => list_slots(),
+        },
+        Commands::ValidateConfig(args) => validate_config(cli.config, args.strict, args.show),
+        Commands::Export(args) => export_history(args, settings()?),
+        Commands::Import(args) => import_history(args, settings()?),
+        Commands::Diff(args) => diff_histories(args),
+        Commands::InstallService(args) => install_service(args.force, cli.config),
+        Commands::Setup(args) => setup(args, cli.config, cli.history_limit),
     }
+    .map(|()| ExitCode::SUCCESS)
 }
 
-fn run(config_path_override: Option<std::path::PathBuf>) -> Result<()> {
-    println!("slyboard v{}", env!("CARGO_PKG_VERSION"));
-    let _instance_lock = InstanceLock::acquire()?;
-
-    let loaded = AppConfig::load(config_path_override)?;
-    let config_path = loaded.path.clone();
+fn run(
+    config_path_override: Option<std::path::PathBuf>,
+    overrides: SettingOverrides,
+    args: RunArgs,
+) -> Result<()> {
+    // Loaded before daemonizing so errors reach the terminal and relative paths still resolve.
+    let loaded = AppConfig::load_layered(config_path_override)?;
+    let config_paths = loaded
+        .layers
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
     let config = loaded.config;
     config.validate()?;
+    let settings = EffectiveSettings::resolve(overrides, Some(&config))?;
 
-    println!("Loaded config from {}", config_path.display());
-    println!("Running clipboard manager...");
+    if args.daemonize {
+        if let Some(instance) = running_instance() {
+            return Err(slyboard::Error::LockHeld {
+                pid: instance.pid,
+                path: instance.lock_path,
+            }
+            .into());
+        }
+        let log_path = match args.log_file {
+            Some(log_path) => log_path,
+            None => default_log_path().context("failed to prepare the runtime directory")?,
+        };
+        println!(
+            "Running in the background; logging to {}",
+            log_path.display()
+        );
+        daemonize(&log_path)
+            .with_context(|| format!("failed to daemonize (log file {})", log_path.display()))?;
+    }
+    if let Some(log_path) = config.logging.resolved_file()? {
+        logging::init(&log_path, config.logging.max_bytes())
+            .with_context(|| format!("failed to open log file {}", log_path.display()))?;
+    }
+    logging::set_debug(args.debug || config.logging.debug);
+    log_println!("slyboard v{}", env!("CARGO_PKG_VERSION"));
+    // Acquired after daemonizing, so the pid file names the process that keeps running.
+    let _instance_lock = InstanceLock::acquire()?;
+
+    log_println!("Loaded config from {config_paths}");
+    log_println!("Clipboard backend: {}", config.clipboard.backend.names());
+    log_println!("Running clipboard manager...");
     if is_capture_paused()? {
-        println!("Clipboard capture is currently paused.");
+        log_println!("Clipboard capture is currently paused.");
+    }
+
+    storage::set_refuse_newer_writer(!args.force);
+    let prune_on_load = if config.storage.prune_on_load {
+        PruneOptions::all()
+    } else {
+        PruneOptions::default()
+    };
+    let shared_state = SharedClipboardState::load_default_with(
+        settings.history_limit,
+        prune_on_load,
+        settings.dedup,
+    )?;
+    if config.clipboard.active_window.prune_on_start {
+        let by_rule =
+            shared_state.prune_blacklisted(&config.clipboard.active_window.blacklist_rules())?;
+        for (rule, count) in by_rule {
+            log_println!("Removed {count} blacklisted entries matching {rule}.");
+        }
+    }
+    shared_state.set_trash_on_clear(config.storage.trash_on_clear);
+    shared_state.set_text_preview_limit(settings.text_preview_limit);
+    let retention = config.clipboard.retention_policy();
+    if !retention.is_empty() {
+        let removed = shared_state.set_retention(retention)?;
+        if removed > 0 {
+            log_println!("Removed {removed} entries past their retention rule.");
+        }
+    }
+    #[cfg(feature = "http")]
+    let _http_server = match config.http.listen_addr()? {
+        Some(addr) => {
+            let endpoint = slyboard::core::http::HttpEndpoint::new(
+                shared_state.clone(),
+                config.http.token.clone(),
+            )
+            .with_remote_hosts(config.http.allow_remote);
+            let server = slyboard::core::http::HttpServer::start(addr, endpoint)?;
+            log_println!("Serving history on http://{}", server.local_addr());
+            Some(server)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "http"))]
+    if config.http.listen.is_some() {
+        log_eprintln!(
+            "warning: slyboard was built without the `http` feature; ignoring http.listen"
+        );
     }
 
-    let shared_state = SharedClipboardState::load_default(DEFAULT_HISTORY_LIMIT)?;
+    #[cfg(all(target_os = "linux", feature = "gtk"))]
+    {
+        // Blocked before the tray spawns its threads, so only this thread receives them.
+        let signals = TerminationSignals::block().context("failed to block termination signals")?;
+        let capture_stats = CaptureStats::for_session();
+        let _app_indicator = tray_indicator::start(
+            shared_state.clone(),
+            config.clipboard.clone(),
+            config.tray.clone(),
+            config.ocr.clone(),
+            capture_stats.clone(),
+        );
 
-    #[cfg(target_os = "linux")]
-    let _app_indicator = tray_indicator::start(shared_state, config.clipboard.clone());
+        let notifier = Notifier::from_env();
+        let paused = || is_capture_paused().unwrap_or(false);
+        notifier.notify_or_warn(&format!("READY=1\n{}", status_message(paused())));
+        let wait = |tick: Option<Duration>| -> std::io::Result<Option<&'static str>> {
+            match signals.wait_timeout(tick)? {
+                Some(DUMP_SIGNAL) => {
+                    for line in capture_stats.snapshot().summary_lines(unix_now()) {
+                        log_println!("{line}");
+                    }
+                    Ok(None)
+                }
+                signal => Ok(signal),
+            }
+        };
+        let signal = notifier
+            .supervise(wait, paused)
+            .context("failed to wait for termination signals")?;
+        notifier.notify_or_warn("STOPPING=1");
+        log_println!("Received {signal}; saving clipboard history and exiting.");
+        shared_state.shutdown()?;
+        Ok(())
+    }
 
-    loop {
-        thread::sleep(Duration::from_secs(60));
+    // No main loop or tray here: the clipboard is polled on a plain thread until the process
+    // is stopped. History is saved as each entry is recorded.
+    #[cfg(any(target_os = "windows", target_os = "macos"))]
+    {
+        use slyboard::clipboard::native::NativeClipboardBackend;
+        use slyboard::core::daemon::{
+            spawn_capture_thread, LimitWarning, SizeWarning, CLIPBOARD_POLL_INTERVAL_MS,
+        };
+        use slyboard::core::self_write::ExpectedWrites;
+
+        let poller = configured_poller(
+            NativeClipboardBackend::new()?,
+            &config.clipboard,
+            &CaptureStats::for_session(),
+        )
+        .with_expected_writes(ExpectedWrites::for_session());
+        let capture = spawn_capture_thread(
+            poller,
+            shared_state.clone(),
+            Duration::from_millis(CLIPBOARD_POLL_INTERVAL_MS),
+            SizeWarning::new(config.clipboard.size_warning_bytes),
+            LimitWarning::new(config.clipboard.notifications.history_limit),
+        );
+        let _ = capture.join();
+        shared_state.shutdown()?;
+        anyhow::bail!("the clipboard capture thread stopped unexpectedly");
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
+    {
+        drop(shared_state);
+        anyhow::bail!(
+            "clipboard capture is not available on this platform; history, clear, and prune \
+             still work"
+        );
+    }
+
+    #[cfg(all(target_os = "linux", not(feature = "gtk")))]
+    {
+        drop(shared_state);
+        anyhow::bail!(
+            "slyboard was built without the `gtk` feature, so it cannot capture the clipboard; \
+             history, clear, and prune still work"
+        );
     }
 }
 
-fn print_history(json: bool, include_images: bool) -> Result<()> {
-    let shared_state = SharedClipboardState::load_default(DEFAULT_HISTORY_LIMIT)?;
-    let history = shared_state.history_snapshot();
-    let entries: Vec<&ClipboardEntry> = history.iter().rev().collect();
+/// History oldest first, so a position is the id `slyboard history` prints.
+fn history_by_id(shared_state: &SharedClipboardState) -> Vec<Arc<ClipboardEntry>> {
+    shared_state.history_snapshot().into_iter().rev().collect()
+}
 
-    if json {
-        let serializable: Vec<SerializableHistoryEntry> = entries
-            .iter()
-            .enumerate()
-            .map(|(id, entry)| SerializableHistoryEntry::new(id, entry, include_images))
-            .collect();
-        println!("{}", serde_json::to_string(&serializable)?);
+/// The history cache with this invocation's settings applied.
+fn load_history(settings: EffectiveSettings) -> Result<SharedClipboardState> {
+    let shared_state = SharedClipboardState::load_default_with(
+        settings.history_limit,
+        PruneOptions::default(),
+        settings.dedup,
+    )?;
+    shared_state.set_text_preview_limit(settings.text_preview_limit);
+    Ok(shared_state)
+}
+
+fn print_history(args: HistoryArgs, settings: EffectiveSettings) -> Result<()> {
+    let shared_state = load_history(settings)?;
+    let history = history_by_id(&shared_state);
+    let filter = HistoryFilter {
+        content_hint: args.hint,
+        workspace_id: args.workspace,
+        include_unknown_workspace: args.include_unknown,
+        search: args.search.clone(),
+        search_labels: args.labels,
+        ..HistoryFilter::default()
+    };
+    let mut entries = filter.apply(&history);
+    if args.sort == HistorySort::Size {
+        // Stable, so equally sized entries stay newest first.
+        entries.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.approx_size_bytes()));
+    }
+
+    if args.json || args.jsonl {
+        let stdout = io::stdout();
+        let mut writer = BufWriter::new(stdout.lock());
+        if args.json {
+            write_json_history(&mut writer, &entries, args.images, args.redact)?;
+        } else {
+            write_jsonl_history(&mut writer, &entries, args.images, args.redact)?;
+        }
+        writer.flush()?;
+        return Ok(());
+    }
+
+    if let Some(template) = args.format.clone() {
+        let template = template.escaped(args.escape);
+        for (id, entry) in entries {
+            println!("{}", template.render(id, entry));
+        }
         return Ok(());
     }
+    let stdout = io::stdout();
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    let style = args.listing_style(stdout.is_terminal(), no_color);
+    let mut writer = BufWriter::new(stdout.lock());
+    write_history_listing(&mut writer, &entries, style, unix_now())?;
+    writer.flush()?;
+    Ok(())
+}
 
-    for (id, entry) in entries.iter().enumerate() {
-        println!("{}", format_history_entry(id, entry));
+fn search_history(args: SearchArgs, settings: EffectiveSettings) -> Result<()> {
+    let shared_state = load_history(settings)?;
+    let history = history_by_id(&shared_state);
+    let filter = HistoryFilter {
+        search: Some(args.query),
+        search_labels: args.labels,
+        fuzzy: args.fuzzy,
+        ..HistoryFilter::default()
+    };
+    let entries = filter.apply(&history);
+
+    if args.json {
+        let stdout = io::stdout();
+        let mut writer = BufWriter::new(stdout.lock());
+        write_json_history(&mut writer, &entries, false, false)?;
+        writer.flush()?;
+        return Ok(());
+    }
+    for (id, entry) in entries {
+        println!("{}", render_default_history_line(id, entry));
     }
     Ok(())
 }
 
-fn clear_history() -> Result<()> {
-    let shared_state = SharedClipboardState::load_default(DEFAULT_HISTORY_LIMIT)?;
+fn tail_history(args: TailArgs, settings: EffectiveSettings) -> Result<()> {
+    let shared_state = load_history(settings)?;
+    let mut cursor = TailCursor::after(&shared_state.history_snapshot());
+    let stdout = io::stdout();
+    loop {
+        thread::sleep(Duration::from_millis(TAIL_POLL_INTERVAL_MS));
+        if !shared_state.reload_if_changed()? {
+            continue;
+        }
+        let mut out = stdout.lock();
+        for entry in cursor.advance(&shared_state.history_snapshot()) {
+            let line = render_tail_line(&entry, args.preview_chars, args.follow_source);
+            // Flushed per line so a pipe sees each capture as it happens.
+            let written = writeln!(out, "{line}").and_then(|()| out.flush());
+            match written {
+                Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+                written => written?,
+            }
+        }
+    }
+}
+
+fn show_entry(args: ShowArgs, settings: EffectiveSettings) -> Result<()> {
+    let shared_state = load_history(settings)?;
+    let (id, entry) = shared_state
+        .find_entry(&args.id)
+        .with_context(|| format!("no history entry with id {}", args.id))?;
+    let entry = shared_state.whole_entry(&entry)?;
+    let stdout = io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    if args.json {
+        let serializable = SerializableHistoryEntry::new(id, &entry, false);
+        if args.redact {
+            serde_json::to_writer(&mut writer, &serializable.redacted())?;
+        } else {
+            serde_json::to_writer(&mut writer, &serializable)?;
+        }
+        writeln!(writer)?;
+    } else {
+        write_entry_details(
+            &mut writer,
+            id,
+            &entry,
+            args.verbose,
+            args.redact,
+            unix_now(),
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn label_entry(args: LabelArgs, settings: EffectiveSettings) -> Result<()> {
+    let shared_state = load_history(settings)?;
+    let id = args.id;
+    // The state indexes from the newest entry, while `slyboard history` ids count from the oldest.
+    let index = shared_state
+        .history_snapshot()
+        .len()
+        .checked_sub(id + 1)
+        .with_context(|| format!("no history entry with id {id}"))?;
+    // `--clear` conflicts with a text, and a blank text clears too.
+    let text = args.text.unwrap_or_default();
+    let cleared = text.trim().is_empty();
+    let changed = if cleared {
+        shared_state.clear_label(index)?
+    } else {
+        shared_state.set_label(index, &text)?
+    };
+    match (cleared, changed) {
+        (false, true) => println!("Labelled entry {id}."),
+        (false, false) => println!("Entry {id} already has that label."),
+        (true, true) => println!("Cleared the label of entry {id}."),
+        (true, false) => println!("Entry {id} has no label."),
+    }
+    Ok(())
+}
+
+fn recognize_text(
+    config_path_override: Option<std::path::PathBuf>,
+    args: OcrArgs,
+    settings: EffectiveSettings,
+) -> Result<()> {
+    let config = AppConfig::load_optional(config_path_override)?
+        .map(|loaded| loaded.config)
+        .unwrap_or_default();
+    let shared_state = load_history(settings)?;
+    let history = history_by_id(&shared_state);
+    let id = args.id;
+    let entry = history
+        .get(id)
+        .with_context(|| format!("no history entry with id {id}"))?;
+    let text = recognize_entry(&CommandOcr::from_config(&config.ocr), entry)
+        .with_context(|| format!("failed to recognize text in entry {id}"))?;
+    if !args.copy {
+        println!("{text}");
+        return Ok(());
+    }
+    let recognized = record_recognized_text(&shared_state, text, id, entry)?;
+    if let ClipboardEntry::Text { value, .. } = &recognized {
+        println!("{value}");
+    }
+    slyboard::clipboard::writer::set_clipboard(&recognized, config.clipboard.restore_target())
+}
+
+fn clear_history(
+    config_path_override: Option<std::path::PathBuf>,
+    args: ClearArgs,
+    settings: EffectiveSettings,
+) -> Result<()> {
+    let stdin = io::stdin();
+    if !confirm_clear(args.yes, stdin.is_terminal(), stdin.lock(), io::stderr())? {
+        println!("Clipboard history not cleared.");
+        return Ok(());
+    }
+    let trash_on_clear = AppConfig::load_optional(config_path_override)?
+        .is_some_and(|loaded| loaded.config.storage.trash_on_clear);
+    let shared_state = load_history(settings)?;
+    shared_state.set_trash_on_clear(trash_on_clear);
     shared_state.clear_history()?;
-    println!("Clipboard history cleared.");
+    clean_thumbnails(&[]);
+    if trash_on_clear {
+        println!("Clipboard history cleared; `slyboard restore --trash` brings it back.");
+    } else {
+        println!("Clipboard history cleared.");
+    }
+    Ok(())
+}
+
+fn restore_trash(settings: EffectiveSettings) -> Result<()> {
+    let shared_state = load_history(settings)?;
+    let restored = shared_state.restore_trash()?;
+    println!("Restored {restored} entries from the trash.");
+    Ok(())
+}
+
+fn prune_history(
+    config_path_override: Option<std::path::PathBuf>,
+    args: PruneArgs,
+    settings: EffectiveSettings,
+) -> Result<()> {
+    let blacklist = if args.blacklist {
+        let rules = AppConfig::load_optional(config_path_override)?
+            .map(|loaded| loaded.config.clipboard.active_window.blacklist_rules())
+            .unwrap_or_default();
+        if rules.is_empty() {
+            bail!("--blacklist given, but clipboard.active_window.blacklist is empty");
+        }
+        rules
+    } else {
+        Vec::new()
+    };
+
+    let shared_state = load_history(settings)?;
+    let mut removed = shared_state.prune(PruneOptions {
+        duplicates: args.duplicates,
+        empty: args.empty,
+        broken_images: args.broken_images,
+    })?;
+    let by_rule = shared_state.prune_blacklisted(&blacklist)?;
+    removed += by_rule.values().sum::<usize>();
+    clean_thumbnails(&shared_state.history_snapshot());
+    match removed {
+        0 => println!("Nothing to prune."),
+        1 => println!("Removed 1 entry."),
+        count => println!("Removed {count} entries."),
+    }
+    for (rule, count) in by_rule {
+        println!("  blacklist {rule}: {count}");
+    }
+    Ok(())
+}
+
+fn import_history(args: ImportArgs, settings: EffectiveSettings) -> Result<()> {
+    let (entries, skipped_images, truncated) = match args.from {
+        ImportSource::Slyboard => return merge_export(args, settings),
+        ImportSource::Klipper => {
+            let history = klipper::read_history(&args.path)?;
+            (history.entries, history.skipped_images, history.truncated)
+        }
+    };
+    if truncated {
+        eprintln!(
+            "warning: {} ends mid-entry; importing the entries before that",
+            args.path.display()
+        );
+    }
+
+    let shared_state = load_history(settings)?;
+    let (mut imported, mut duplicates) = (0, 0);
+    // Both formats list entries newest first; record the oldest first so order is kept.
+    for text in entries.into_iter().rev() {
+        let entry = ClipboardEntry::text(text)
+            .with_classified_content()
+            .with_captured_by(CaptureOrigin::Import);
+        let outcome = shared_state.record(entry)?;
+        if outcome.deduplicated_from_index.is_some() {
+            duplicates += 1;
+        } else if outcome.inserted {
+            imported += 1;
+        }
+    }
+    println!("Imported {imported} entries from {}.", args.path.display());
+    if duplicates > 0 {
+        println!("  already in history: {duplicates}");
+    }
+    if skipped_images > 0 {
+        println!("  images skipped: {skipped_images}");
+    }
+    Ok(())
+}
+
+/// Merges a slyboard export, encrypted or not, into history by capture time.
+fn merge_export(args: ImportArgs, settings: EffectiveSettings) -> Result<()> {
+    let bytes =
+        fs::read(&args.path).with_context(|| format!("failed to read {}", args.path.display()))?;
+    let entries = if bundle::is_bundle(&bytes) {
+        let passphrase = read_passphrase(args.passphrase_file.as_deref(), false)?;
+        bundle::open_export(&bytes, &passphrase, &args.path)?
+    } else {
+        transfer::read_export(&args.path)?
+    };
+    let shared_state = load_history(settings)?;
+    let added = shared_state.merge_history(&entries)?;
+    println!("Imported {added} entries from {}.", args.path.display());
+    let duplicates = transfer::diff(&entries, &[]).only_left.len() - added;
+    if duplicates > 0 {
+        println!("  already in history: {duplicates}");
+    }
+    Ok(())
+}
+
+fn export_history(args: ExportArgs, settings: EffectiveSettings) -> Result<()> {
+    let shared_state = load_history(settings)?;
+    let entries = shared_state
+        .history_snapshot()
+        .iter()
+        .map(|entry| shared_state.whole_entry(entry).map(Cow::into_owned))
+        .collect::<slyboard::Result<Vec<_>>>()?;
+    let mut export = Vec::new();
+    transfer::write_export(&mut export, &entries)?;
+    let contents = if args.encrypt {
+        let passphrase = read_passphrase(args.passphrase_file.as_deref(), true)?;
+        bundle::seal(&export, &passphrase).context("failed to encrypt the export")?
+    } else {
+        export
+    };
+    match &args.output {
+        Some(path) => {
+            fs::write(path, contents)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+            println!("Wrote {} entries to {}.", entries.len(), path.display());
+        }
+        None => io::stdout().lock().write_all(&contents)?,
+    }
+    Ok(())
+}
+
+/// The passphrase of an encrypted bundle: the first line of `file`, or typed without echo,
+/// twice when `confirm` (for a new bundle).
+/// Wiped from memory once dropped, like every copy read along the way.
+fn read_passphrase(file: Option<&std::path::Path>, confirm: bool) -> Result<Zeroizing<String>> {
+    if let Some(path) = file {
+        let contents = Zeroizing::new(
+            fs::read_to_string(path)
+                .with_context(|| format!("failed to read {}", path.display()))?,
+        );
+        return bundle::passphrase_line(&contents)
+            .map(|line| Zeroizing::new(line.to_string()))
+            .with_context(|| format!("{} holds no passphrase", path.display()));
+    }
+    if !io::stdin().is_terminal() {
+        bail!("pass --passphrase-file when stdin is not a terminal");
+    }
+    let passphrase = Zeroizing::new(rpassword::prompt_password("Passphrase: ")?);
+    if passphrase.is_empty() {
+        bail!("the passphrase cannot be empty");
+    }
+    if confirm {
+        let repeated = Zeroizing::new(rpassword::prompt_password("Repeat passphrase: ")?);
+        if repeated != passphrase {
+            bail!("the passphrases don't match");
+        }
+    }
+    Ok(passphrase)
+}
+
+fn diff_histories(args: DiffArgs) -> Result<()> {
+    let left = transfer::read_export(&args.left)?;
+    let right = transfer::read_export(&args.right)?;
+    if args.merge {
+        let merged = transfer::merge(&left, &right);
+        match &args.output {
+            Some(path) => {
+                let file = fs::File::create(path)
+                    .with_context(|| format!("failed to create {}", path.display()))?;
+                let mut writer = BufWriter::new(file);
+                transfer::write_export(&mut writer, &merged)?;
+                writer.flush()?;
+                println!("Wrote {} entries to {}.", merged.len(), path.display());
+            }
+            None => {
+                let stdout = io::stdout();
+                let mut writer = BufWriter::new(stdout.lock());
+                transfer::write_export(&mut writer, &merged)?;
+                writer.flush()?;
+            }
+        }
+        return Ok(());
+    }
+
+    let diff = transfer::diff(&left, &right);
+    for (path, entries) in [
+        (&args.left, &diff.only_left),
+        (&args.right, &diff.only_right),
+    ] {
+        println!("Only in {}: {}", path.display(), entries.len());
+        for entry in entries {
+            println!("  {}", entry_preview(entry, args.preview_chars));
+        }
+    }
+    println!("In both: {}", diff.in_both);
+    Ok(())
+}
+
+/// Drops thumbnails of images no longer in `history`. The history change already happened, so
+/// a failure here is only a warning.
+fn clean_thumbnails(history: &[Arc<ClipboardEntry>]) {
+    let cleaned = ThumbnailCache::open_default().and_then(|cache| cache.cleanup(history));
+    if let Err(err) = cleaned {
+        eprintln!("warning: failed to clean up thumbnails: {err}");
+    }
+}
+
+fn yank_slot(args: YankArgs, settings: EffectiveSettings) -> Result<()> {
+    let store = SlotStore::open_default()?;
+    let shared_state = load_history(settings)?;
+    let history = shared_state.history_snapshot();
+    // The snapshot is newest first, while `slyboard history` ids count from the oldest entry.
+    let entry = match args.id {
+        Some(id) => history
+            .iter()
+            .rev()
+            .nth(id)
+            .with_context(|| format!("no history entry with id {id}"))?,
+        None => history.first().context("clipboard history is empty")?,
+    };
+    // Slots outlive history, so they keep the whole text of entries cut to a preview.
+    if store
+        .set(&args.slot, &*shared_state.whole_entry(entry)?)?
+        .is_some()
+    {
+        println!("Overwrote slot {}.", args.slot);
+    } else {
+        println!("Saved to slot {}.", args.slot);
+    }
+    Ok(())
+}
+
+fn put_slot(
+    config_path_override: Option<std::path::PathBuf>,
+    args: PutArgs,
+    settings: EffectiveSettings,
+) -> Result<()> {
+    let restore_target = if args.primary {
+        RestoreTarget::Both
+    } else {
+        AppConfig::load_optional(config_path_override)?
+            .map_or_else(RestoreTarget::default, |loaded| {
+                loaded.config.clipboard.restore_target()
+            })
+    };
+    let slot = &args.slot;
+    let entry = SlotStore::open_default()?
+        .get(slot)?
+        .with_context(|| format!("slot {slot} is empty"))?;
+    slyboard::clipboard::writer::set_clipboard(&entry, restore_target)?;
+    let marked =
+        load_history(settings).and_then(|shared_state| Ok(shared_state.mark_restored(&entry)?));
+    if let Err(err) = marked {
+        eprintln!("warning: failed to record the restore: {err}");
+    }
+    Ok(())
+}
+
+fn list_slots() -> Result<()> {
+    let template = HistoryTemplate::parse(SLOT_LINE_TEMPLATE)?;
+    for (name, entry) in SlotStore::open_default()?.load()? {
+        println!("{name}: {}", template.render(0, &entry));
+    }
+    Ok(())
+}
+
+fn remove_slot(slot: &str) -> Result<()> {
+    if SlotStore::open_default()?.remove(slot)?.is_none() {
+        anyhow::bail!("slot {slot} is empty");
+    }
+    println!("Removed slot {slot}.");
     Ok(())
 }
 
@@ -104,161 +842,400 @@ fn resume_capture() -> Result<()> {
     Ok(())
 }
 
-fn print_capture_status() -> Result<()> {
-    if is_capture_paused()? {
-        println!("paused");
-    } else {
-        println!("running");
+/// `slyboard capture-once`: reads the clipboard through the first configured backend and
+/// records it with the daemon's capture rules, then says what happened.
+fn capture_clipboard_once(
+    config_path: Option<std::path::PathBuf>,
+    settings: EffectiveSettings,
+) -> Result<ExitCode> {
+    let config = AppConfig::load_layered(config_path)?.config;
+    config.validate()?;
+    let clipboard_config = &config.clipboard;
+    let shared_state = load_history(settings)?;
+    let retention = clipboard_config.retention_policy();
+    if !retention.is_empty() {
+        shared_state.set_retention(retention)?;
     }
-    Ok(())
+    let provider = provider_from_config(
+        &clipboard_config.active_window.backend,
+        &ProviderStats::default(),
+    );
+    let paused = is_capture_paused()?;
+    let append = AppendMode::for_session();
+    // Counted by the running daemon only; a one-off capture leaves its counters alone.
+    let capture_stats = CaptureStats::default();
+    let backend = clipboard_config
+        .backend
+        .iter()
+        .next()
+        .context("no clipboard backend configured")?;
+    let selection = *clipboard_config
+        .selections
+        .first()
+        .context("no clipboard selection configured")?;
+    let captured = match backend {
+        #[cfg(target_os = "linux")]
+        ClipboardBackendKind::Wayland => {
+            let backend = WaylandBackend::new(selection, provider)
+                .with_recorded_targets(clipboard_config.record_targets)
+                .with_max_entry_bytes(clipboard_config.max_entry_bytes);
+            let mut poller = configured_poller(backend, clipboard_config, &capture_stats);
+            capture_single(&mut poller, &shared_state, paused, &append)?
+        }
+        #[cfg(all(target_os = "linux", feature = "gtk"))]
+        ClipboardBackendKind::Gtk => {
+            gtk::init().context("failed to initialize GTK for clipboard access")?;
+            let backend = GtkClipboardBackend::new(selection, provider)
+                .with_recorded_targets(clipboard_config.record_targets);
+            let mut poller = configured_poller(backend, clipboard_config, &capture_stats);
+            capture_single(&mut poller, &shared_state, paused, &append)?
+        }
+        // Windows and macOS have one clipboard, whichever backend and selection are configured,
+        // and no active-window lookup yet.
+        #[cfg(any(target_os = "windows", target_os = "macos"))]
+        _ => {
+            let _ = (provider, selection);
+            let backend = slyboard::clipboard::native::NativeClipboardBackend::new()?;
+            let mut poller = configured_poller(backend, clipboard_config, &capture_stats);
+            capture_single(&mut poller, &shared_state, paused, &append)?
+        }
+        #[cfg(all(target_os = "linux", not(feature = "gtk")))]
+        backend => bail!(
+            "this build can't read the clipboard through the {} backend",
+            backend.as_str()
+        ),
+    };
+    println!("{}", captured.summary());
+    Ok(ExitCode::from(captured.exit_code()))
 }
 
-#[derive(Debug, Clone, Serialize)]
-struct SerializableHistoryEntry {
-    id: usize,
-    #[serde(flatten)]
-    entry: SerializableClipboardEntry,
+fn stop_daemon() -> Result<()> {
+    let Some(instance) = running_instance() else {
+        anyhow::bail!("slyboard is not running");
+    };
+    let pid = instance.pid.with_context(|| {
+        format!(
+            "the running slyboard did not record its pid (lock: {})",
+            instance.lock_path.display()
+        )
+    })?;
+    if !terminate(pid, STOP_TIMEOUT).with_context(|| format!("failed to signal pid {pid}"))? {
+        anyhow::bail!("slyboard (pid {pid}) is still running");
+    }
+    println!("Stopped slyboard (pid {pid})");
+    Ok(())
 }
 
-impl SerializableHistoryEntry {
-    fn new(id: usize, entry: &ClipboardEntry, include_images: bool) -> Self {
-        Self {
-            id,
-            entry: SerializableClipboardEntry::from_entry(entry, include_images),
+fn print_daemon_status(json: bool, settings: EffectiveSettings) -> Result<()> {
+    let instance = running_instance();
+    // Counters a stopped daemon left behind are not reported.
+    let captures = match instance {
+        Some(_) => load_capture_stats()?,
+        None => None,
+    };
+    if json {
+        let history = load_history(settings)?;
+        let providers = match instance {
+            Some(_) => load_provider_stats()?,
+            None => None,
+        };
+        let status = DaemonStatus {
+            running: instance.is_some(),
+            pid: instance.and_then(|instance| instance.pid),
+            paused: is_capture_paused()?,
+            providers,
+            uptime_secs: captures
+                .as_ref()
+                .map(|captures| captures.uptime_secs(unix_now())),
+            captures,
+            history_bytes: history.total_size(),
+            database: history.database_header()?,
+        };
+        println!("{}", serde_json::to_string(&status)?);
+        return Ok(());
+    }
+    let database = read_database_header(&default_database_path()?)?
+        .map(|header| format!("history: {}", header.summary()));
+    match instance {
+        Some(RunningInstance { pid: Some(pid), .. }) => println!("running (pid {pid})"),
+        Some(RunningInstance { pid: None, .. }) => println!("running"),
+        None => {
+            // Still worth reporting when a stopped daemon is being triaged.
+            if let Some(database) = database {
+                println!("{database}");
+            }
+            anyhow::bail!("slyboard is not running")
         }
     }
+    for line in captures.map_or_else(Vec::new, |captures| captures.summary_lines(unix_now())) {
+        println!("{line}");
+    }
+    if let Some(database) = database {
+        println!("{database}");
+    }
+    Ok(())
 }
 
-#[derive(Debug, Clone, Serialize)]
-#[serde(tag = "kind", rename_all = "snake_case")]
-enum SerializableClipboardEntry {
-    Text {
-        value: String,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        source_window: Option<ActiveWindowContext>,
-    },
-    Image {
-        width: i32,
-        height: i32,
-        rowstride: i32,
-        has_alpha: bool,
-        bits_per_sample: i32,
-        channels: i32,
-        pixel_bytes: usize,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        pixels: Option<Vec<u8>>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        source_window: Option<ActiveWindowContext>,
-    },
-}
-
-impl SerializableClipboardEntry {
-    fn from_entry(entry: &ClipboardEntry, include_images: bool) -> Self {
-        match entry {
-            ClipboardEntry::Text {
-                value,
-                source_window,
-            } => Self::Text {
-                value: value.clone(),
-                source_window: source_window.clone(),
-            },
-            ClipboardEntry::Image {
-                width,
-                height,
-                rowstride,
-                has_alpha,
-                bits_per_sample,
-                channels,
-                pixels,
-                source_window,
-            } => Self::Image {
-                width: *width,
-                height: *height,
-                rowstride: *rowstride,
-                has_alpha: *has_alpha,
-                bits_per_sample: *bits_per_sample,
-                channels: *channels,
-                pixel_bytes: pixels.len(),
-                pixels: include_images.then_some(pixels.clone()),
-                source_window: source_window.clone(),
-            },
-        }
+fn install_service(force: bool, config_path_override: Option<std::path::PathBuf>) -> Result<()> {
+    let program = std::env::current_exe().context("failed to locate the slyboard executable")?;
+    // The unit runs from another working directory, so a relative --config must be resolved.
+    let config = config_path_override
+        .map(|path| {
+            fs::canonicalize(&path)
+                .with_context(|| format!("failed to resolve config path {}", path.display()))
+        })
+        .transpose()?;
+    let path = default_unit_path()?;
+    if path.exists() && !force {
+        bail!(
+            "{} already exists; pass --force to overwrite it",
+            path.display()
+        );
     }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    fs::write(&path, unit_file(&program, config.as_deref()))
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    println!("Wrote {}", path.display());
+    println!("Start it with: systemctl --user daemon-reload && systemctl --user enable --now {UNIT_NAME}");
+    Ok(())
 }
 
-fn format_history_entry(id: usize, entry: &ClipboardEntry) -> String {
-    match entry {
-        ClipboardEntry::Text {
-            value,
-            source_window,
-        } => format_entry_with_source(id, value.clone(), source_window.as_ref()),
-        ClipboardEntry::Image {
-            width,
-            height,
-            source_window,
-            ..
-        } => format_entry_with_source(
-            id,
-            format!("[image] {}x{}", width, height),
-            source_window.as_ref(),
-        ),
+fn setup(
+    args: SetupArgs,
+    config_path_override: Option<std::path::PathBuf>,
+    history_limit: Option<usize>,
+) -> Result<()> {
+    let environment = Environment::detect();
+    println!("Detected {environment}.");
+    let path = match &config_path_override {
+        Some(path) => path.clone(),
+        None => default_user_config_path()?,
+    };
+    if path.exists() && !args.force {
+        bail!(
+            "{} already exists; pass --force to overwrite it",
+            path.display()
+        );
     }
-}
+    let stdin = io::stdin();
+    let choices = choose(
+        SetupChoices::proposed(&environment),
+        args.overrides(history_limit),
+        args.accept_defaults,
+        stdin.is_terminal(),
+        stdin.lock(),
+        io::stdout(),
+    )?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    fs::write(&path, config_yaml(&choices)?)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    println!("Wrote {}", path.display());
 
-fn format_entry_with_source(
-    id: usize,
-    value: String,
-    source_window: Option<&ActiveWindowContext>,
-) -> String {
-    match source_window {
-        Some(context) => format!(
-            "{}: {} [source: {} via {}]",
-            id,
-            value,
-            format_window_source(context),
-            context.backend
-        ),
-        None => format!("{}: {}", id, value),
+    if choices.install_service {
+        let unit_path = default_unit_path()?;
+        if unit_path.exists() && !args.force {
+            println!(
+                "{} already exists; left as is (pass --force to replace it)",
+                unit_path.display()
+            );
+        } else {
+            install_service(args.force, config_path_override)?;
+        }
     }
+    println!(
+        "To open history from the keyboard, {}.",
+        rofi_binding(&environment)
+    );
+    if !environment.has("rofi") {
+        println!("slyboard-rofi needs rofi, which was not found on PATH.");
+    }
+    Ok(())
 }
 
-fn format_window_source(context: &ActiveWindowContext) -> String {
-    let mut details = Vec::new();
-    if let Some(app_id) = &context.app_id {
-        details.push(format!("app_id={app_id}"));
+fn print_providers(config_path_override: Option<std::path::PathBuf>) -> Result<()> {
+    let config = AppConfig::load_layered(config_path_override)?.config;
+    let providers = providers_from_config(&config.clipboard.active_window.backend);
+    if providers.is_empty() {
+        println!("Active-window capture is disabled (clipboard.active_window.backend).");
+        return Ok(());
     }
-    if let Some(initial_app_id) = &context.initial_app_id {
-        details.push(format!("initial_app_id={initial_app_id}"));
+    for (name, provider) in providers {
+        let started = Instant::now();
+        let outcome = provider.probe();
+        let latency = started.elapsed().as_millis();
+        let result = match outcome {
+            CaptureOutcome::Captured(context) => format!("ok {}", serde_json::to_string(&context)?),
+            CaptureOutcome::Failed(reason) => format!("failed: {reason}"),
+            CaptureOutcome::TimedOut => "timed out".to_string(),
+        };
+        println!("{name}\t{latency} ms\t{result}");
     }
-    if let Some(initial_title) = &context.initial_title {
-        details.push(format!("initial_title={initial_title}"));
+    Ok(())
+}
+
+fn print_capture_status() -> Result<()> {
+    if is_capture_paused()? {
+        println!("paused");
+    } else {
+        println!("running");
     }
-    if let Some(window_id) = &context.window_id {
-        details.push(format!("window_id={window_id}"));
+    Ok(())
+}
+
+fn start_append() -> Result<()> {
+    let mode = AppendMode::for_session();
+    if mode.start()? {
+        println!("Append mode on; copied text is collected until `slyboard append off`.");
+    } else {
+        let pieces = mode.buffer()?.map_or(0, |buffer| buffer.pieces.len());
+        println!(
+            "Append mode is already on ({}).",
+            format_piece_count(pieces)
+        );
     }
-    if let Some(pid) = context.pid {
-        details.push(format!("pid={pid}"));
+    Ok(())
+}
+
+/// Turns append mode off, records the joined pieces as one history entry and copies them.
+fn finish_append(
+    config_path_override: Option<std::path::PathBuf>,
+    overrides: SettingOverrides,
+) -> Result<()> {
+    let config = AppConfig::load_layered(config_path_override)?.config;
+    let settings = EffectiveSettings::resolve(overrides, Some(&config))?;
+    let Some(buffer) = AppendMode::for_session().finish()? else {
+        println!("Append mode is not on.");
+        return Ok(());
+    };
+    if buffer.pieces.is_empty() {
+        println!("Append mode off; nothing was collected.");
+        return Ok(());
     }
-    if let Some(workspace_id) = context.workspace_id {
-        details.push(format!("workspace_id={workspace_id}"));
+
+    let entry = ClipboardEntry::text(buffer.join(&config.clipboard.append_separator))
+        .with_captured_at(Some(unix_now()))
+        .with_captured_by(CaptureOrigin::CliSet);
+    load_history(settings)?.record(entry.clone())?;
+    slyboard::clipboard::writer::set_clipboard(&entry, config.clipboard.restore_target())?;
+    println!(
+        "Append mode off; copied {}.",
+        format_piece_count(buffer.pieces.len())
+    );
+    Ok(())
+}
+
+fn print_append_status() -> Result<()> {
+    match AppendMode::for_session().buffer()? {
+        Some(buffer) => println!("on ({})", format_piece_count(buffer.pieces.len())),
+        None => println!("off"),
     }
-    if let Some(workspace_name) = &context.workspace_name {
-        details.push(format!("workspace_name={workspace_name}"));
+    Ok(())
+}
+
+fn set_plain_paste_mode(enabled: bool) -> Result<()> {
+    let state = if enabled { "on" } else { "off" };
+    if is_plain_paste_enabled() == enabled {
+        println!("Plain-text paste is already {state}.");
+        return Ok(());
     }
-    if let Some(is_xwayland) = context.is_xwayland {
-        details.push(format!("xwayland={is_xwayland}"));
+
+    set_plain_paste(enabled)?;
+    println!("Plain-text paste {state}.");
+    Ok(())
+}
+
+fn print_plain_paste_status() -> Result<()> {
+    println!(
+        "{}",
+        if is_plain_paste_enabled() {
+            "on"
+        } else {
+            "off"
+        }
+    );
+    Ok(())
+}
+
+fn print_bar(args: BarArgs, settings: EffectiveSettings) -> Result<()> {
+    let shared_state = load_history(settings)?;
+    let render = |paused: bool| {
+        BarStatus::new(
+            &shared_state.history_snapshot(),
+            paused,
+            args.preview_chars,
+            args.tooltip_entries,
+        )
+        .to_json_line()
+    };
+
+    let mut paused = is_capture_paused()?;
+    println!("{}", render(paused));
+    if !args.follow {
+        return Ok(());
     }
 
-    if details.is_empty() {
-        context.title.clone()
-    } else {
-        format!("{} ({})", context.title, details.join(", "))
+    // A read can fail while the daemon is mid-write; the bar keeps its last line and tries
+    // again, logging each distinct error once rather than twice a second.
+    let mut last_error = None;
+    loop {
+        thread::sleep(Duration::from_millis(BAR_FOLLOW_INTERVAL_MS));
+        let update = shared_state
+            .reload_if_changed()
+            .map_err(anyhow::Error::from)
+            .and_then(|history_changed| Ok((history_changed, is_capture_paused()?)));
+        let (history_changed, paused_now) = match update {
+            Ok(update) => {
+                last_error = None;
+                update
+            }
+            Err(err) => {
+                let message = format!("{err:#}");
+                if last_error.as_ref() != Some(&message) {
+                    eprintln!("warning: failed to update the bar: {message}");
+                    last_error = Some(message);
+                }
+                continue;
+            }
+        };
+        if history_changed || paused_now != paused {
+            paused = paused_now;
+            println!("{}", render(paused));
+        }
     }
 }
 
-fn validate_config(config_path_override: Option<std::path::PathBuf>) -> Result<()> {
-    let loaded = AppConfig::load(config_path_override)?;
-    loaded.config.validate()?;
-    println!("Config is valid: {}", loaded.path.display());
+fn validate_config(
+    config_path_override: Option<std::path::PathBuf>,
+    strict: bool,
+    show: bool,
+) -> Result<()> {
+    let merged = MergedConfig::load(config_path_override)?;
+    let config = merged.config()?;
+    let path = merged.layers.last().cloned().unwrap_or_default();
+    if show {
+        for layer in &merged.layers {
+            println!("# {}", layer.display());
+        }
+        for field in merged.fields() {
+            println!("{field}  ({})", merged.layer_path(&field).display());
+        }
+    }
+    config.validate()?;
+    let diagnostics = config.lint();
+    for diagnostic in &diagnostics {
+        eprintln!("warning: {diagnostic}");
+    }
+    if strict && !diagnostics.is_empty() {
+        anyhow::bail!(
+            "config has {} warning(s): {}",
+            diagnostics.len(),
+            path.display()
+        );
+    }
+    println!("Config is valid: {}", path.display());
     Ok(())
 }
@@ -0,0 +1,71 @@
+use gtk::gdk::ScrollDirection;
+use gtk::glib::translate::{from_glib_none, ToGlibPtr};
+use gtk::glib::{self, gobject_ffi};
+use gtk::prelude::*;
+use libappindicator::{
+    _AppIndicator, app_indicator_new, app_indicator_set_icon_theme_path, app_indicator_set_menu,
+    app_indicator_set_secondary_activate_target, app_indicator_set_status, app_indicator_set_title,
+    AppIndicatorCategory_APP_INDICATOR_CATEGORY_APPLICATION_STATUS,
+    AppIndicatorStatus_APP_INDICATOR_STATUS_ACTIVE,
+};
+
+/// Thin wrapper over the libappindicator C API.
+///
+/// The `libappindicator` crate's wrapper hides the raw pointer, which we need for the
+/// secondary-activate target and the `scroll-event` signal.
+pub struct AppIndicator {
+    raw: *mut _AppIndicator,
+}
+
+impl AppIndicator {
+    /// Returns `None` when libappindicator could not create the indicator.
+    pub fn new(id: &str, icon_name: &str) -> Option<Self> {
+        let raw = unsafe {
+            app_indicator_new(
+                id.to_glib_none().0,
+                icon_name.to_glib_none().0,
+                AppIndicatorCategory_APP_INDICATOR_CATEGORY_APPLICATION_STATUS,
+            )
+        };
+        (!raw.is_null()).then_some(Self { raw })
+    }
+
+    pub fn set_title(&self, title: &str) {
+        unsafe { app_indicator_set_title(self.raw, title.to_glib_none().0) }
+    }
+
+    /// Adds a directory searched for the indicator's icon name before the icon theme.
+    pub fn set_icon_theme_path(&self, path: &str) {
+        unsafe { app_indicator_set_icon_theme_path(self.raw, path.to_glib_none().0) }
+    }
+
+    pub fn set_active(&self) {
+        unsafe {
+            app_indicator_set_status(self.raw, AppIndicatorStatus_APP_INDICATOR_STATUS_ACTIVE)
+        }
+    }
+
+    pub fn set_menu(&self, menu: &gtk::Menu) {
+        unsafe { app_indicator_set_menu(self.raw, menu.to_glib_none().0) }
+    }
+
+    /// Activates `item` on a middle click over the tray icon.
+    pub fn set_secondary_activate_target(&self, item: &impl IsA<gtk::Widget>) {
+        unsafe {
+            app_indicator_set_secondary_activate_target(self.raw, item.as_ref().to_glib_none().0)
+        }
+    }
+
+    pub fn connect_scroll<F>(&self, on_scroll: F)
+    where
+        F: Fn(ScrollDirection) + 'static,
+    {
+        let object: glib::Object = unsafe { from_glib_none(self.raw as *mut gobject_ffi::GObject) };
+        object.connect_local("scroll-event", false, move |values| {
+            if let Some(Ok(direction)) = values.get(2).map(|value| value.get::<ScrollDirection>()) {
+                on_scroll(direction);
+            }
+            None
+        });
+    }
+}
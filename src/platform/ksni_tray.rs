@@ -0,0 +1,211 @@
+//! `tray.backend: ksni`: the tray icon as a StatusNotifierItem served over D-Bus by `ksni`
+//! rather than libappindicator, for hosts where the latter is missing or misbehaves. Built
+//! with the `ksni` feature.
+//!
+//! The menu is rendered from the same [`TrayMenuItem`] description as the GTK one. `ksni`
+//! runs on its own thread, so activations come back as [`TrayEvent`]s on a channel for the
+//! main loop to handle.
+
+use std::path::Path;
+use std::sync::mpsc::Sender;
+
+use anyhow::{Context, Result};
+use ksni::blocking::{Handle, TrayMethods};
+use ksni::menu::{StandardItem, SubMenu};
+use ksni::{MenuItem, Orientation, ToolTip};
+
+use crate::core::tray_menu::{TrayAction, TrayMenuItem};
+
+/// What the user did with the tray icon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrayEvent {
+    Action(TrayAction),
+    /// Middle click on the icon.
+    MiddleClick,
+    /// Scrolling over the icon; `step_back` when scrolling down.
+    Scroll {
+        step_back: bool,
+    },
+}
+
+/// The running tray icon.
+pub struct KsniTray {
+    handle: Handle<TrayState>,
+}
+
+struct TrayState {
+    icon_name: String,
+    icon_theme_path: String,
+    title: String,
+    menu: Vec<TrayMenuItem>,
+    events: Sender<TrayEvent>,
+}
+
+impl KsniTray {
+    /// Registers the icon with the session's StatusNotifierWatcher. `icon_theme_path` is
+    /// searched for `icon_name` before the icon theme.
+    pub fn spawn(
+        icon_name: &str,
+        icon_theme_path: Option<&Path>,
+        events: Sender<TrayEvent>,
+    ) -> Result<Self> {
+        let state = TrayState {
+            icon_name: icon_name.to_string(),
+            icon_theme_path: icon_theme_path
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            title: String::new(),
+            menu: Vec::new(),
+            events,
+        };
+        let handle = state
+            .spawn()
+            .context("failed to register the StatusNotifierItem")?;
+        Ok(Self { handle })
+    }
+
+    /// Replaces the icon's title, which most hosts show as its tooltip.
+    pub fn set_title(&self, title: String) {
+        self.handle.update(move |state| state.title = title);
+    }
+
+    pub fn set_menu(&self, menu: Vec<TrayMenuItem>) {
+        self.handle.update(move |state| state.menu = menu);
+    }
+}
+
+impl ksni::Tray for TrayState {
+    fn id(&self) -> String {
+        "slyboard".to_string()
+    }
+
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn icon_name(&self) -> String {
+        self.icon_name.clone()
+    }
+
+    fn icon_theme_path(&self) -> String {
+        self.icon_theme_path.clone()
+    }
+
+    fn tool_tip(&self) -> ToolTip {
+        ToolTip {
+            title: self.title.clone(),
+            ..ToolTip::default()
+        }
+    }
+
+    fn secondary_activate(&mut self, _x: i32, _y: i32) {
+        let _ = self.events.send(TrayEvent::MiddleClick);
+    }
+
+    fn scroll(&mut self, delta: i32, orientation: Orientation) {
+        if orientation == Orientation::Vertical && delta != 0 {
+            let _ = self.events.send(TrayEvent::Scroll {
+                step_back: delta < 0,
+            });
+        }
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        menu_items(&self.menu)
+    }
+}
+
+/// `ksni` items for `items`. Submenus can't be clicked themselves over D-Bus, so their
+/// `activate` action is left out; history entries offer Copy inside theirs. Tooltips are
+/// left out too, having no counterpart in the protocol's menus.
+fn menu_items(items: &[TrayMenuItem]) -> Vec<MenuItem<TrayState>> {
+    items
+        .iter()
+        .map(|item| match item {
+            TrayMenuItem::Label(label) => StandardItem {
+                label: plain_label(label),
+                enabled: false,
+                ..StandardItem::default()
+            }
+            .into(),
+            TrayMenuItem::Action { label, action, .. } => {
+                let action = *action;
+                StandardItem {
+                    label: plain_label(label),
+                    activate: Box::new(move |state: &mut TrayState| {
+                        let _ = state.events.send(TrayEvent::Action(action));
+                    }),
+                    ..StandardItem::default()
+                }
+                .into()
+            }
+            TrayMenuItem::Separator => MenuItem::Separator,
+            TrayMenuItem::Submenu { label, items, .. } => SubMenu {
+                label: plain_label(label),
+                submenu: menu_items(items),
+                ..SubMenu::default()
+            }
+            .into(),
+        })
+        .collect()
+}
+
+/// Doubles underscores, which menu labels would otherwise take as access keys.
+fn plain_label(label: &str) -> String {
+    label.replace('_', "__")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{menu_items, TrayEvent, TrayState};
+    use crate::core::tray_menu::{EntryAction, TrayAction, TrayMenuItem};
+    use ksni::MenuItem;
+    use std::sync::mpsc;
+
+    #[test]
+    fn renders_the_menu_model_and_reports_activations() {
+        let copy = TrayAction::Entry {
+            id: 7,
+            action: EntryAction::Copy,
+        };
+        let model = vec![
+            TrayMenuItem::Label("Running".to_string()),
+            TrayMenuItem::Separator,
+            TrayMenuItem::Submenu {
+                label: "History".to_string(),
+                items: vec![TrayMenuItem::Action {
+                    label: "snake_case_name".to_string(),
+                    action: copy,
+                    tooltip: Some("Label: x".to_string()),
+                }],
+                activate: None,
+                tooltip: None,
+            },
+        ];
+        let (events, received) = mpsc::channel();
+        let mut state = TrayState {
+            icon_name: "slyboard".to_string(),
+            icon_theme_path: String::new(),
+            title: String::new(),
+            menu: model.clone(),
+            events,
+        };
+
+        let items = menu_items(&model);
+        let [MenuItem::Standard(status), MenuItem::Separator, MenuItem::SubMenu(history)] =
+            &items[..]
+        else {
+            panic!("unexpected menu layout");
+        };
+        assert_eq!(status.label, "Running");
+        assert!(!status.enabled);
+        assert_eq!(history.label, "History");
+        let [MenuItem::Standard(entry)] = &history.submenu[..] else {
+            panic!("expected one history item");
+        };
+        assert_eq!(entry.label, "snake__case__name");
+
+        (entry.activate)(&mut state);
+        assert_eq!(received.try_recv(), Ok(TrayEvent::Action(copy)));
+    }
+}
@@ -0,0 +1,47 @@
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use anyhow::{Context, Result};
+
+/// Delivers everything sent on `receiver` to `handler` on the GTK main thread. A forwarding
+/// thread blocks on `receiver` and wakes the main loop through a socket pair, the same way
+/// `clipboard::wayland::spawn_selection_listener` does.
+pub fn attach_receiver<T, F>(receiver: Receiver<T>, mut handler: F) -> Result<()>
+where
+    T: Send + 'static,
+    F: FnMut(T) + 'static,
+{
+    let (forward_tx, forward_rx) = mpsc::channel();
+    let (mut wake_tx, mut wake_rx) = UnixStream::pair().context("failed to create wake socket")?;
+
+    thread::Builder::new()
+        .name("slyboard-main-loop-bridge".to_string())
+        .spawn(move || {
+            for item in receiver {
+                if forward_tx.send(item).is_err() || wake_tx.write_all(&[1]).is_err() {
+                    return;
+                }
+            }
+        })
+        .context("failed to spawn main loop bridge")?;
+
+    let fd = wake_rx.as_raw_fd();
+    gtk::glib::source::unix_fd_add_local(fd, gtk::glib::IOCondition::IN, move |_, condition| {
+        if condition.intersects(gtk::glib::IOCondition::HUP | gtk::glib::IOCondition::ERR) {
+            return gtk::glib::ControlFlow::Break;
+        }
+
+        let mut pending = [0u8; 64];
+        if matches!(wake_rx.read(&mut pending), Ok(0) | Err(_)) {
+            return gtk::glib::ControlFlow::Break;
+        }
+        for item in forward_rx.try_iter() {
+            handler(item);
+        }
+        gtk::glib::ControlFlow::Continue
+    });
+    Ok(())
+}
@@ -1,2 +1,10 @@
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", feature = "gtk"))]
+pub mod app_indicator;
+#[cfg(all(target_os = "linux", feature = "ksni"))]
+pub mod ksni_tray;
+#[cfg(all(target_os = "linux", feature = "gtk"))]
+pub mod main_loop;
+#[cfg(all(target_os = "linux", feature = "gtk"))]
+pub mod screen_lock_dbus;
+#[cfg(all(target_os = "linux", feature = "gtk"))]
 pub mod tray_indicator;
@@ -0,0 +1,106 @@
+use anyhow::{bail, Result};
+use gtk::gio::{self, BusType, DBusConnection, DBusSignalFlags};
+
+use crate::core::screen_lock::{LockEvent, LockEventHandler, LockSignalSource};
+
+const SCREENSAVER_INTERFACES: [&str; 2] = ["org.freedesktop.ScreenSaver", "org.gnome.ScreenSaver"];
+const LOGIND_SERVICE: &str = "org.freedesktop.login1";
+const LOGIND_SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+const LOGIND_SESSION_PATH_PREFIX: &str = "/org/freedesktop/login1/session/";
+
+/// Listens for screensaver `ActiveChanged` signals on the session bus and logind
+/// `Lock`/`Unlock` on the system bus. Keep it alive for as long as events are wanted.
+#[derive(Default)]
+pub struct DbusLockSignalSource {
+    connections: Vec<DBusConnection>,
+}
+
+impl LockSignalSource for DbusLockSignalSource {
+    fn subscribe(&mut self, handler: LockEventHandler) -> Result<()> {
+        match gio::bus_get_sync(BusType::Session, None::<&gio::Cancellable>) {
+            Ok(connection) => {
+                for interface in SCREENSAVER_INTERFACES {
+                    let handler = handler.clone();
+                    connection.signal_subscribe(
+                        None,
+                        Some(interface),
+                        Some("ActiveChanged"),
+                        None,
+                        None,
+                        DBusSignalFlags::NONE,
+                        move |_, _, _, _, _, parameters| {
+                            if let Some((active,)) = parameters.get::<(bool,)>() {
+                                handler(if active {
+                                    LockEvent::Locked
+                                } else {
+                                    LockEvent::Unlocked
+                                });
+                            }
+                        },
+                    );
+                }
+                self.connections.push(connection);
+            }
+            Err(err) => crate::log_eprintln!("warning: failed to connect to session bus: {err}"),
+        }
+
+        match gio::bus_get_sync(BusType::System, None::<&gio::Cancellable>) {
+            Ok(connection) => {
+                let session_path = std::env::var("XDG_SESSION_ID")
+                    .ok()
+                    .map(|id| logind_session_path(&id));
+                for (member, event) in
+                    [("Lock", LockEvent::Locked), ("Unlock", LockEvent::Unlocked)]
+                {
+                    let handler = handler.clone();
+                    connection.signal_subscribe(
+                        Some(LOGIND_SERVICE),
+                        Some(LOGIND_SESSION_INTERFACE),
+                        Some(member),
+                        session_path.as_deref(),
+                        None,
+                        DBusSignalFlags::NONE,
+                        move |_, _, _, _, _, _| handler(event),
+                    );
+                }
+                self.connections.push(connection);
+            }
+            Err(err) => crate::log_eprintln!("warning: failed to connect to system bus: {err}"),
+        }
+
+        if self.connections.is_empty() {
+            bail!("no D-Bus connection available for screen lock signals");
+        }
+        Ok(())
+    }
+}
+
+/// Object path logind uses for a session id, escaped like `sd_bus_path_encode`.
+fn logind_session_path(session_id: &str) -> String {
+    let mut path = LOGIND_SESSION_PATH_PREFIX.to_string();
+    for (index, byte) in session_id.bytes().enumerate() {
+        if byte.is_ascii_alphabetic() || (byte.is_ascii_digit() && index > 0) {
+            path.push(byte as char);
+        } else {
+            path.push_str(&format!("_{byte:02x}"));
+        }
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::logind_session_path;
+
+    #[test]
+    fn escapes_logind_session_ids() {
+        assert_eq!(
+            logind_session_path("3"),
+            "/org/freedesktop/login1/session/_33"
+        );
+        assert_eq!(
+            logind_session_path("c2"),
+            "/org/freedesktop/login1/session/c2"
+        );
+    }
+}
@@ -1,23 +1,62 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
 use std::env;
+use std::ffi::OsStr;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::process::Command;
 use std::rc::Rc;
 use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
+use anyhow::Context;
+use gtk::gdk::keys::constants as keys;
+use gtk::gdk_pixbuf::Pixbuf;
 use gtk::prelude::*;
-use libappindicator::{AppIndicator as LibAppIndicator, AppIndicatorStatus};
 
+use crate::clipboard::age::unix_now;
 use crate::clipboard::backend::GtkClipboardBackend;
-use crate::clipboard::poller::{start_gtk_polling, ClipboardPoller};
-use crate::clipboard::{ClipboardEntry, SharedClipboardState};
-use crate::config::{ClipboardBackend, ClipboardConfig};
-use crate::core::active_window::provider_from_config;
+use crate::clipboard::filters::HistoryFilter;
+use crate::clipboard::long_text::LongTextStore;
+use crate::clipboard::ocr::{recognize_entry, CommandOcr};
+use crate::clipboard::poller::{
+    start_gtk_polling, start_wake_polling, ClipboardPoller, SharedLastSeen,
+};
+use crate::clipboard::preview::preview_text;
+use crate::clipboard::slots::SlotStore;
+use crate::clipboard::thumbnails::ThumbnailCache;
+use crate::clipboard::wayland::{spawn_wayland_listener, ChannelBackend};
+use crate::clipboard::writer::{restore_selections, set_gtk_selections, RestoreTarget};
+use crate::clipboard::{ClipboardEntry, HistoryEvent, SharedClipboardState};
+use crate::config::{
+    ClipboardBackend as ClipboardBackendKind, ClipboardConfig, OcrConfig, TrayBackend, TrayConfig,
+};
+use crate::core::active_window::watching_provider_from_config;
+use crate::core::append_mode::AppendMode;
 use crate::core::capture_control::{is_capture_paused, set_capture_paused};
+use crate::core::capture_stats::{CaptureStats, SkipReason};
+use crate::core::daemon::{
+    configured_poller, LimitWarning, SizeWarning, CLIPBOARD_POLL_INTERVAL_MS,
+};
+use crate::core::plain_paste::{is_plain_paste_enabled, set_plain_paste};
+use crate::core::provider_stats::ProviderStats;
+use crate::core::screen_lock::{CaptureState, ScreenLockMonitor};
+use crate::core::self_write::ExpectedWrites;
+use crate::core::tray_menu::{
+    append_label, capture_labels, capture_status_label, dispatch_entry_action, entry_preview,
+    history_items, matching_history_items, slot_items, tray_menu, tray_title, EntryAction,
+    EntryActionTarget, MenuLabelFormat, TrayAction, TrayMenuItem,
+};
+use crate::platform::app_indicator::AppIndicator;
+#[cfg(feature = "ksni")]
+use crate::platform::ksni_tray::{KsniTray, TrayEvent};
+use crate::platform::main_loop::attach_receiver;
+use crate::platform::screen_lock_dbus::DbusLockSignalSource;
 
 pub struct TrayIndicator {
     _gtk_thread: JoinHandle<()>,
@@ -25,32 +64,44 @@ pub struct TrayIndicator {
 
 const BUNDLED_TRAY_ICON_NAME: &str = "slyboard";
 const BUNDLED_TRAY_ICON_SVG: &[u8] = include_bytes!("slyboard.svg");
-const CLIPBOARD_POLL_INTERVAL_MS: u64 = 750;
-const MENU_LABEL_CHAR_LIMIT: usize = 70;
+const HISTORY_RELOAD_INTERVAL_MS: u64 = 2000;
 const CLIPBOARD_NOTIFICATION_TITLE: &str = "slyboard";
-const CLIPBOARD_TEXT_NOTIFICATION_BODY: &str = "text copied to clipboard";
-const CLIPBOARD_IMAGE_NOTIFICATION_BODY: &str = "image copied to clipboard";
-const RUNNING_LABEL: &str = "Running";
-const PAUSED_LABEL: &str = "Paused";
-const PAUSE_CAPTURE_LABEL: &str = "Pause Capture";
-const RESUME_CAPTURE_LABEL: &str = "Resume Capture";
+/// Longest side of the thumbnail in front of a history item for an image.
+const MENU_ICON_SIZE: i32 = 32;
 
 pub fn start(
     shared_state: SharedClipboardState,
     clipboard_config: ClipboardConfig,
+    tray_config: TrayConfig,
+    ocr_config: OcrConfig,
+    capture_stats: CaptureStats,
 ) -> Option<TrayIndicator> {
-    if env::var_os("DISPLAY").is_none() {
-        eprintln!("warning: DISPLAY is not set; cannot create tray icon");
-        return None;
+    if !display_available(
+        env::var_os("DISPLAY").as_deref(),
+        env::var_os("WAYLAND_DISPLAY").as_deref(),
+    ) {
+        crate::log_eprintln!(
+            "warning: neither DISPLAY nor WAYLAND_DISPLAY is set; GTK will likely fail to start"
+        );
     }
     if env::var_os("DBUS_SESSION_BUS_ADDRESS").is_none() {
-        eprintln!("warning: DBus session is not set; appindicator may not be visible");
+        crate::log_eprintln!("warning: DBus session is not set; appindicator may not be visible");
     }
 
     let (ready_tx, ready_rx) = mpsc::channel();
     let gtk_thread = std::thread::spawn(move || {
-        if let Err(err) = run_indicator(ready_tx, shared_state, clipboard_config) {
-            eprintln!("tray thread exited: {err}");
+        let ocr = ocr_config
+            .enabled
+            .then(|| CommandOcr::from_config(&ocr_config));
+        if let Err(err) = run_indicator(
+            ready_tx,
+            shared_state,
+            clipboard_config,
+            tray_config,
+            ocr,
+            capture_stats,
+        ) {
+            crate::log_eprintln!("tray thread exited: {err}");
         }
     });
 
@@ -59,12 +110,12 @@ pub fn start(
             _gtk_thread: gtk_thread,
         }),
         Ok(Err(err)) => {
-            eprintln!("failed to start tray icon: {err}");
+            crate::log_eprintln!("failed to start tray icon: {err}");
             let _ = gtk_thread.join();
             None
         }
         Err(_) => {
-            eprintln!("warning: tray startup timed out; keeping tray thread running");
+            crate::log_eprintln!("warning: tray startup timed out; keeping tray thread running");
             Some(TrayIndicator {
                 _gtk_thread: gtk_thread,
             })
@@ -72,10 +123,21 @@ pub fn start(
     }
 }
 
+/// GTK3 can start on either X11 or Wayland, and StatusNotifier hosts don't need X11.
+fn display_available(display: Option<&OsStr>, wayland_display: Option<&OsStr>) -> bool {
+    [display, wayland_display]
+        .into_iter()
+        .flatten()
+        .any(|value| !value.is_empty())
+}
+
 fn run_indicator(
     ready_tx: Sender<Result<(), String>>,
     shared_state: SharedClipboardState,
     clipboard_config: ClipboardConfig,
+    tray_config: TrayConfig,
+    ocr: Option<CommandOcr>,
+    capture_stats: CaptureStats,
 ) -> Result<(), String> {
     if let Err(err) = gtk::init() {
         let msg = err.to_string();
@@ -83,84 +145,304 @@ fn run_indicator(
         return Err(msg);
     }
 
-    let tray_icon_name = install_bundled_icon().unwrap_or("input-keyboard");
-    let mut indicator = LibAppIndicator::new("slyboard", tray_icon_name);
-    indicator.set_title("slyboard");
-    indicator.set_status(AppIndicatorStatus::Active);
+    let tray_icon = TrayIcon::from_config(tray_config.icon.as_deref());
+    #[cfg(feature = "ksni")]
+    let (tray_event_tx, tray_events) = mpsc::channel();
+    // Capture keeps running without a tray icon, e.g. when no StatusNotifier host is around.
+    // Shared with the callbacks that keep its title in step with history and the pause state.
+    let indicator = Rc::new(match tray_config.backend {
+        TrayBackend::AppIndicator => {
+            AppIndicator::new("slyboard", tray_icon.name()).map(TrayHost::AppIndicator)
+        }
+        #[cfg(feature = "ksni")]
+        TrayBackend::Ksni => {
+            let theme_path = match &tray_icon {
+                TrayIcon::File { theme_path, .. } => Some(theme_path.as_path()),
+                TrayIcon::Named(_) => None,
+            };
+            match KsniTray::spawn(tray_icon.name(), theme_path, tray_event_tx) {
+                Ok(tray) => Some(TrayHost::Ksni(tray)),
+                Err(err) => {
+                    crate::log_eprintln!("warning: {err:#}");
+                    None
+                }
+            }
+        }
+        #[cfg(not(feature = "ksni"))]
+        TrayBackend::Ksni => {
+            crate::log_eprintln!(
+                "warning: tray.backend: ksni needs slyboard built with the `ksni` feature"
+            );
+            None
+        }
+        TrayBackend::None => None,
+    });
+    if let Some(indicator) = (*indicator).as_ref().and_then(TrayHost::app_indicator) {
+        if let TrayIcon::File { theme_path, .. } = &tray_icon {
+            indicator.set_icon_theme_path(&theme_path.to_string_lossy());
+        }
+        indicator.set_active();
+    }
+    if indicator.is_none() && tray_config.backend != TrayBackend::None {
+        crate::log_eprintln!(
+            "warning: failed to create tray icon; clipboard capture continues without it"
+        )
+    }
 
-    let clipboard = gtk::Clipboard::get(&gtk::gdk::SELECTION_CLIPBOARD);
-    let poller = match clipboard_config.backend {
-        ClipboardBackend::Gtk => Rc::new(RefCell::new(ClipboardPoller::new(
-            GtkClipboardBackend::new(
-                &clipboard,
-                provider_from_config(&clipboard_config.active_window.backend),
-            ),
-            clipboard_config.active_window.blacklist.clone(),
-        ))),
+    let provider_stats = ProviderStats::for_session();
+    let expected_writes = ExpectedWrites::for_session();
+    let tray_clipboard = TrayClipboard {
+        expected_writes: expected_writes.clone(),
+        restore_target: clipboard_config.restore_target(),
+        long_texts: shared_state.long_texts(),
     };
-    if let Some(entry) = poller.borrow_mut().poll_once() {
-        if let Err(err) = shared_state.record_entry(entry) {
-            eprintln!("failed to seed clipboard history: {err}");
+    // With several backends or selections, each change is recorded by whichever sees it first.
+    let shared_last_seen = SharedLastSeen::default();
+    let mut capture_sources = Vec::new();
+    for backend in clipboard_config.backend.iter() {
+        for &selection in &clipboard_config.selections {
+            let capture_source = match backend {
+                ClipboardBackendKind::Gtk => {
+                    let poller = Rc::new(RefCell::new(
+                        configured_poller(
+                            GtkClipboardBackend::new(
+                                selection,
+                                watching_provider_from_config(
+                                    &clipboard_config.active_window.backend,
+                                    &provider_stats,
+                                ),
+                            )
+                            .with_recorded_targets(clipboard_config.record_targets),
+                            &clipboard_config,
+                            &capture_stats,
+                        )
+                        .with_expected_writes(expected_writes.clone())
+                        .with_shared_last_seen(shared_last_seen.clone()),
+                    ));
+                    let seed = poller
+                        .borrow_mut()
+                        .seed(clipboard_config.capture_existing_on_start);
+                    // After a restart the clipboard usually still holds the newest entry, which
+                    // would be recorded again with deduplication off.
+                    let newest = shared_state.history_snapshot().into_iter().next();
+                    let dedup = shared_state.dedup_settings();
+                    let seed = seed.filter(|entry| {
+                        !newest.is_some_and(|newest| dedup.same_content(&newest, entry))
+                    });
+                    if let Some(entry) = seed {
+                        if let Err(err) = shared_state.record(entry) {
+                            crate::log_eprintln!("failed to seed clipboard history: {err}");
+                        }
+                    }
+                    CaptureSource::Gtk(poller)
+                }
+                ClipboardBackendKind::Wayland => {
+                    let listener = spawn_wayland_listener(
+                        selection,
+                        clipboard_config.record_targets,
+                        clipboard_config.max_entry_bytes,
+                        watching_provider_from_config(
+                            &clipboard_config.active_window.backend,
+                            &provider_stats,
+                        ),
+                    )
+                    .map_err(|err| format!("failed to start Wayland clipboard backend: {err:#}"));
+                    let (captures, wake) = match listener {
+                        Ok(listener) => listener,
+                        Err(msg) => {
+                            let _ = ready_tx.send(Err(msg.clone()));
+                            return Err(msg);
+                        }
+                    };
+                    let poller = Rc::new(RefCell::new(
+                        configured_poller(
+                            ChannelBackend::new(captures, selection),
+                            &clipboard_config,
+                            &capture_stats,
+                        )
+                        .with_expected_writes(expected_writes.clone())
+                        .with_shared_last_seen(shared_last_seen.clone()),
+                    ));
+                    CaptureSource::Wayland(poller, wake)
+                }
+            };
+            capture_sources.push(capture_source);
         }
     }
 
-    let mut menu = gtk::Menu::new();
-    let running_item = gtk::MenuItem::with_label(RUNNING_LABEL);
+    let menu = gtk::Menu::new();
+    let running_item = gtk::MenuItem::new();
     running_item.set_sensitive(false);
     menu.append(&running_item);
     running_item.show();
 
+    let append_mode = AppendMode::for_session();
+    let append_item = gtk::MenuItem::new();
+    append_item.set_sensitive(false);
+    append_item.set_no_show_all(true);
+    menu.append(&append_item);
+    update_append_menu_state(&append_item, &append_mode);
+
     let capture_paused = Rc::new(RefCell::new(match is_capture_paused() {
         Ok(value) => value,
         Err(err) => {
-            eprintln!("warning: failed to read capture pause state: {err}");
+            crate::log_eprintln!("warning: failed to read capture pause state: {err}");
             false
         }
     }));
 
-    let pause_item = gtk::MenuItem::with_label(PAUSE_CAPTURE_LABEL);
-    update_capture_menu_state(&running_item, &pause_item, *capture_paused.borrow());
+    let pause_item = gtk::MenuItem::new();
+
+    // Kept alive for the lifetime of the GTK main loop so D-Bus subscriptions stay active.
+    let mut lock_source = DbusLockSignalSource::default();
+    let screen_lock = if clipboard_config.pause_on_lock {
+        let capture_paused_for_lock = capture_paused.clone();
+        let running_item_for_lock = running_item.clone();
+        let pause_item_for_lock = pause_item.clone();
+        let indicator_for_lock = indicator.clone();
+        let shared_state_for_lock = shared_state.clone();
+        match ScreenLockMonitor::start(&mut lock_source, move |locked| {
+            let capture_state = CaptureState::from_flags(*capture_paused_for_lock.borrow(), locked);
+            update_capture_menu_state(&running_item_for_lock, &pause_item_for_lock, capture_state);
+            update_tray_title(
+                &indicator_for_lock,
+                capture_state,
+                &shared_state_for_lock.history_snapshot(),
+            );
+        }) {
+            Ok(monitor) => monitor,
+            Err(err) => {
+                crate::log_eprintln!("warning: failed to watch screen lock state: {err}");
+                ScreenLockMonitor::default()
+            }
+        }
+    } else {
+        ScreenLockMonitor::default()
+    };
+
+    let capture_state = CaptureState::from_flags(*capture_paused.borrow(), screen_lock.is_locked());
+    update_capture_menu_state(&running_item, &pause_item, capture_state);
+    update_tray_title(&indicator, capture_state, &shared_state.history_snapshot());
     let capture_paused_for_toggle = capture_paused.clone();
     let running_item_for_toggle = running_item.clone();
     let pause_item_for_toggle = pause_item.clone();
+    let screen_lock_for_toggle = screen_lock.clone();
+    let indicator_for_toggle = indicator.clone();
+    let shared_state_for_toggle = shared_state.clone();
     pause_item.connect_activate(move |_| {
         let next_state = !*capture_paused_for_toggle.borrow();
         if let Err(err) = set_capture_paused(next_state) {
-            eprintln!("failed to update capture pause state: {err}");
+            crate::log_eprintln!("failed to update capture pause state: {err}");
             return;
         }
 
         *capture_paused_for_toggle.borrow_mut() = next_state;
-        update_capture_menu_state(&running_item_for_toggle, &pause_item_for_toggle, next_state);
+        let capture_state =
+            CaptureState::from_flags(next_state, screen_lock_for_toggle.is_locked());
+        update_capture_menu_state(
+            &running_item_for_toggle,
+            &pause_item_for_toggle,
+            capture_state,
+        );
+        update_tray_title(
+            &indicator_for_toggle,
+            capture_state,
+            &shared_state_for_toggle.history_snapshot(),
+        );
     });
     menu.append(&pause_item);
     pause_item.show();
 
+    let plain_paste_item = gtk::CheckMenuItem::with_label("Plain-Text Paste");
+    plain_paste_item.set_active(is_plain_paste_enabled());
+    plain_paste_item.connect_toggled(|item| {
+        if let Err(err) = set_plain_paste(item.is_active()) {
+            crate::log_eprintln!("failed to update plain-text paste state: {err:#}");
+        }
+    });
+    menu.append(&plain_paste_item);
+    plain_paste_item.show();
+
     let separator = gtk::SeparatorMenuItem::new();
     menu.append(&separator);
     separator.show();
 
     let history_root_item = gtk::MenuItem::with_label("History");
-    let history_menu = gtk::Menu::new();
-    history_root_item.set_submenu(Some(&history_menu));
+    let label_format = MenuLabelFormat::new(
+        tray_config.label_template.as_deref(),
+        tray_config.label_chars,
+    )
+    .unwrap_or_else(|err| {
+        crate::log_eprintln!("warning: invalid tray.label_template, using the default: {err:#}");
+        MenuLabelFormat::default()
+    });
+    let thumbnails = match ThumbnailCache::open_default() {
+        Ok(thumbnails) => {
+            clean_thumbnails(&thumbnails, &shared_state.history_snapshot());
+            Some(thumbnails)
+        }
+        Err(err) => {
+            crate::log_eprintln!("warning: image thumbnails are disabled: {err}");
+            None
+        }
+    };
+    let history_menu = HistoryMenu::new(
+        &tray_clipboard,
+        &shared_state,
+        thumbnails.clone(),
+        label_format,
+        tray_config.search,
+        tray_config.entry_submenus,
+        ocr,
+    );
+    history_root_item.set_submenu(Some(&history_menu.menu));
     menu.append(&history_root_item);
     history_root_item.show();
-    refresh_history_menu(&history_menu, &clipboard, &shared_state.history_snapshot());
+    history_menu.refresh(shared_state.history_snapshot());
+
+    let slots_menu = match SlotStore::open_default() {
+        Ok(store) => {
+            let slots_menu = SlotsMenu::new(
+                store,
+                &tray_clipboard,
+                &shared_state,
+                tray_config.label_chars,
+            );
+            let slots_root_item = gtk::MenuItem::with_label("Slots");
+            slots_root_item.set_submenu(Some(&slots_menu.menu));
+            menu.append(&slots_root_item);
+            slots_root_item.show();
+            slots_menu.reload_if_changed();
+            Some(slots_menu)
+        }
+        Err(err) => {
+            crate::log_eprintln!("warning: failed to locate clipboard slots: {err}");
+            None
+        }
+    };
 
     let clear_history_item = gtk::MenuItem::with_label("Clear History");
     let shared_state_for_clear = shared_state.clone();
-    let history_menu_for_clear = history_menu.clone();
-    let clipboard_for_clear = clipboard.clone();
     clear_history_item.connect_activate(move |_| {
-        if let Err(err) = shared_state_for_clear.clear_history() {
-            eprintln!("failed to clear clipboard history: {err}");
-            return;
-        }
-        refresh_history_menu(
-            &history_menu_for_clear,
-            &clipboard_for_clear,
-            &shared_state_for_clear.history_snapshot(),
+        let dialog = gtk::MessageDialog::new(
+            None::<&gtk::Window>,
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Warning,
+            gtk::ButtonsType::Cancel,
+            "Clear clipboard history?",
         );
+        dialog.set_secondary_text(Some("Pinned entries are removed too."));
+        dialog.add_button("Clear History", gtk::ResponseType::Accept);
+        let shared_state = shared_state_for_clear.clone();
+        dialog.connect_response(move |dialog, response| {
+            if response == gtk::ResponseType::Accept {
+                if let Err(err) = shared_state.clear_history() {
+                    crate::log_eprintln!("failed to clear clipboard history: {err}");
+                }
+            }
+            dialog.close();
+        });
+        dialog.show_all();
     });
     menu.append(&clear_history_item);
     clear_history_item.show();
@@ -170,56 +452,329 @@ fn run_indicator(
     separator.show();
 
     let quit_item = gtk::MenuItem::with_label("Quit");
-    quit_item.connect_activate(|_| process::exit(0));
+    let shared_state_for_quit = shared_state.clone();
+    quit_item.connect_activate(move |_| {
+        if let Err(err) = shared_state_for_quit.shutdown() {
+            crate::log_eprintln!("failed to save clipboard history: {err}");
+        }
+        process::exit(0)
+    });
     menu.append(&quit_item);
     quit_item.show();
 
+    // Index into history of the entry a middle click restores; scrolling over the icon moves it.
+    let armed_index = Rc::new(Cell::new(0usize));
+    let notification_preview_chars = clipboard_config.notifications.preview_chars;
+    let notify_on_capture = clipboard_config.notifications.on_capture;
+    let restore_item = tray_config.middle_click_restore.then(|| {
+        let restore_item = gtk::MenuItem::with_label("Restore Armed Entry");
+        restore_item.set_no_show_all(true);
+        let shared_state_for_restore = shared_state.clone();
+        let clipboard_for_restore = tray_clipboard.clone();
+        let armed_index_for_restore = armed_index.clone();
+        restore_item.connect_activate(move |_| {
+            let history = shared_state_for_restore.history_snapshot();
+            if let Some(entry) = history.get(armed_index_for_restore.get()) {
+                clipboard_for_restore.restore(entry, &shared_state_for_restore);
+            }
+        });
+        menu.append(&restore_item);
+        restore_item
+    });
+    let app_indicator = (*indicator).as_ref().and_then(TrayHost::app_indicator);
+    if let (Some(restore_item), Some(indicator)) = (&restore_item, app_indicator) {
+        indicator.set_secondary_activate_target(restore_item);
+    }
+
+    // Moves the armed entry one step through history, `step_back` towards older entries.
+    let scroll_to_arm = restore_item.is_some() && tray_config.scroll_to_arm;
+    let shared_state_for_scroll = shared_state.clone();
+    let armed_index_for_scroll = armed_index.clone();
+    let capture_stats_for_scroll = capture_stats.clone();
+    let step_armed_entry = Rc::new(move |step_back: bool| {
+        let history = shared_state_for_scroll.history_snapshot();
+        let next = cycle_armed_index(armed_index_for_scroll.get(), history.len(), step_back);
+        armed_index_for_scroll.set(next);
+        if let Some(entry) = history.get(next) {
+            send_clipboard_notification(
+                &capture_stats_for_scroll,
+                &format!(
+                    "Middle click restores {next}: {}",
+                    entry_preview(entry, notification_preview_chars)
+                ),
+            );
+        }
+    });
+    if let (true, Some(indicator)) = (scroll_to_arm, app_indicator) {
+        let step_armed_entry = step_armed_entry.clone();
+        indicator.connect_scroll(move |direction| match direction {
+            gtk::gdk::ScrollDirection::Down => step_armed_entry(true),
+            gtk::gdk::ScrollDirection::Up => step_armed_entry(false),
+            _ => {}
+        });
+    }
+
     menu.show_all();
-    indicator.set_menu(&mut menu);
+    if let Some(indicator) = app_indicator {
+        indicator.set_menu(&menu);
+    }
+
+    let indicator_for_menu = indicator.clone();
+    let shared_state_for_menu = shared_state.clone();
+    let history_menu_for_menu = history_menu.clone();
+    let slots_menu_for_menu = slots_menu.clone();
+    let capture_paused_for_menu = capture_paused.clone();
+    let screen_lock_for_menu = screen_lock.clone();
+    let refresh_tray_menu = Rc::new(move || {
+        let Some(indicator) = indicator_for_menu.as_ref() else {
+            return;
+        };
+        indicator.refresh_menu(|| {
+            let slots = slots_menu_for_menu
+                .as_ref()
+                .map(|slots_menu| slots_menu.slots.borrow().clone())
+                .unwrap_or_default();
+            tray_menu(
+                CaptureState::from_flags(
+                    *capture_paused_for_menu.borrow(),
+                    screen_lock_for_menu.is_locked(),
+                ),
+                &shared_state_for_menu.history_snapshot(),
+                &slots,
+                &history_menu_for_menu.label_format,
+                history_menu_for_menu.entry_submenus,
+                history_menu_for_menu.actions.ocr.is_some(),
+                unix_now(),
+            )
+        });
+    });
+    refresh_tray_menu();
+
+    // Items of a ksni menu are activated on its D-Bus thread and handled here, mostly by
+    // activating the GTK item that does the same.
+    #[cfg(feature = "ksni")]
+    {
+        let history_menu_for_tray = history_menu.clone();
+        let slots_menu_for_tray = slots_menu.clone();
+        let pause_item_for_tray = pause_item.clone();
+        let clear_history_item_for_tray = clear_history_item.clone();
+        let quit_item_for_tray = quit_item.clone();
+        let restore_item_for_tray = restore_item.clone();
+        let step_armed_entry_for_tray = step_armed_entry.clone();
+        let refresh_tray_menu_for_tray = refresh_tray_menu.clone();
+        let on_tray_event = move |event: TrayEvent| {
+            match event {
+                TrayEvent::Action(TrayAction::TogglePause) => pause_item_for_tray.emit_activate(),
+                TrayEvent::Action(TrayAction::Entry { id, action }) => {
+                    history_menu_for_tray.actions.dispatch(id, action)
+                }
+                TrayEvent::Action(TrayAction::RestoreSlot { id }) => {
+                    if let Some(slots_menu) = &slots_menu_for_tray {
+                        slots_menu.restore(id);
+                    }
+                }
+                TrayEvent::Action(TrayAction::ClearHistory) => {
+                    clear_history_item_for_tray.emit_activate()
+                }
+                TrayEvent::Action(TrayAction::Quit) => quit_item_for_tray.emit_activate(),
+                TrayEvent::MiddleClick => {
+                    if let Some(restore_item) = &restore_item_for_tray {
+                        restore_item.emit_activate();
+                    }
+                }
+                TrayEvent::Scroll { step_back } => {
+                    if scroll_to_arm {
+                        step_armed_entry_for_tray(step_back);
+                    }
+                }
+            }
+            refresh_tray_menu_for_tray();
+        };
+        if let Err(err) = attach_receiver(tray_events, on_tray_event) {
+            let msg = format!("failed to watch the tray icon: {err:#}");
+            let _ = ready_tx.send(Err(msg.clone()));
+            return Err(msg);
+        }
+    }
+
+    let history_menu_for_events = history_menu.clone();
+    let shared_state_for_events = shared_state.clone();
+    let armed_index_for_events = armed_index.clone();
+    let thumbnails_for_events = thumbnails.clone();
+    let capture_stats_for_events = capture_stats.clone();
+    let indicator_for_events = indicator.clone();
+    let capture_paused_for_events = capture_paused.clone();
+    let screen_lock_for_events = screen_lock.clone();
+    let refresh_tray_menu_for_events = refresh_tray_menu.clone();
+    let mut size_warning = SizeWarning::new(clipboard_config.size_warning_bytes);
+    let on_history_event = move |event| {
+        let history = shared_state_for_events.history_snapshot();
+        match &event {
+            HistoryEvent::Recorded(entry) => {
+                armed_index_for_events.set(0);
+                let notification_body =
+                    notification_body_for_entry(entry, notification_preview_chars);
+                println!("clipboard event: {notification_body}");
+                if notify_on_capture {
+                    send_clipboard_notification(&capture_stats_for_events, &notification_body);
+                }
+                if let Some(warning) = size_warning.check(shared_state_for_events.total_size()) {
+                    crate::log_eprintln!("warning: {warning}");
+                    send_clipboard_notification(&capture_stats_for_events, &warning);
+                }
+            }
+            HistoryEvent::Cleared | HistoryEvent::Removed(_) | HistoryEvent::Reloaded => {
+                if let Some(thumbnails) = &thumbnails_for_events {
+                    clean_thumbnails(thumbnails, &history);
+                }
+            }
+            _ => {}
+        }
+        update_tray_title(
+            &indicator_for_events,
+            CaptureState::from_flags(
+                *capture_paused_for_events.borrow(),
+                screen_lock_for_events.is_locked(),
+            ),
+            &history,
+        );
+        history_menu_for_events.refresh(history);
+        refresh_tray_menu_for_events();
+    };
+    if let Err(err) = attach_receiver(shared_state.subscribe(), on_history_event) {
+        let msg = format!("failed to watch clipboard history: {err:#}");
+        let _ = ready_tx.send(Err(msg.clone()));
+        return Err(msg);
+    }
 
     let shared_state_for_poll = shared_state.clone();
-    let history_menu_for_poll = history_menu.clone();
-    let clipboard_for_menu = clipboard.clone();
     let capture_paused_for_poll = capture_paused.clone();
     let running_item_for_poll = running_item.clone();
     let pause_item_for_poll = pause_item.clone();
-    start_gtk_polling(
-        poller,
-        Duration::from_millis(CLIPBOARD_POLL_INTERVAL_MS),
-        move |entry| {
-            let paused = match is_capture_paused() {
-                Ok(value) => value,
-                Err(err) => {
-                    eprintln!("warning: failed to read capture pause state: {err}");
-                    *capture_paused_for_poll.borrow()
-                }
-            };
-            {
-                let mut pause_state = capture_paused_for_poll.borrow_mut();
-                if *pause_state != paused {
-                    *pause_state = paused;
-                    update_capture_menu_state(&running_item_for_poll, &pause_item_for_poll, paused);
-                }
+    let screen_lock_for_poll = screen_lock.clone();
+    let append_mode_for_poll = append_mode.clone();
+    let append_item_for_poll = append_item.clone();
+    let capture_stats_for_poll = capture_stats.clone();
+    let indicator_for_poll = indicator.clone();
+    let limit_warning = RefCell::new(LimitWarning::new(
+        clipboard_config.notifications.history_limit,
+    ));
+    let on_change = Rc::new(move |entry: ClipboardEntry| {
+        let paused = match is_capture_paused() {
+            Ok(value) => value,
+            Err(err) => {
+                crate::log_eprintln!("warning: failed to read capture pause state: {err}");
+                *capture_paused_for_poll.borrow()
+            }
+        };
+        let capture_state = CaptureState::from_flags(paused, screen_lock_for_poll.is_locked());
+        {
+            let mut pause_state = capture_paused_for_poll.borrow_mut();
+            if *pause_state != paused {
+                *pause_state = paused;
+                update_capture_menu_state(
+                    &running_item_for_poll,
+                    &pause_item_for_poll,
+                    capture_state,
+                );
+                update_tray_title(
+                    &indicator_for_poll,
+                    capture_state,
+                    &shared_state_for_poll.history_snapshot(),
+                );
             }
-            if paused {
+        }
+        match capture_state {
+            CaptureState::Running => {}
+            CaptureState::Paused => return capture_stats_for_poll.skipped(SkipReason::Paused),
+            CaptureState::PausedScreenLocked => {
+                return capture_stats_for_poll.skipped(SkipReason::ScreenLocked)
+            }
+        }
+        match append_mode_for_poll.collect(&entry) {
+            Ok(Some(_)) => {
+                update_append_menu_state(&append_item_for_poll, &append_mode_for_poll);
+                capture_stats_for_poll.skipped(SkipReason::Appended);
                 return;
             }
+            Ok(None) => {}
+            Err(err) => crate::log_eprintln!("failed to update append buffer: {err:#}"),
+        }
 
-            let notification_body = notification_body_for_entry(&entry);
-            let changed = match shared_state_for_poll.record_entry(entry) {
-                Ok(changed) => changed,
-                Err(err) => {
-                    eprintln!("failed to record clipboard history: {err}");
-                    false
+        // Menu refreshes and notifications follow from the history events this emits.
+        match shared_state_for_poll.record(entry) {
+            Ok(outcome) => {
+                capture_stats_for_poll.captured();
+                if let Some(warning) = limit_warning.borrow_mut().check(&outcome) {
+                    crate::log_eprintln!("warning: {warning}");
+                    send_clipboard_notification(&capture_stats_for_poll, &warning);
                 }
-            };
+                if let Some(thumbnails) = &thumbnails {
+                    if let Err(err) = thumbnails.remove_for(&outcome.evicted) {
+                        crate::log_eprintln!("warning: failed to remove thumbnails: {err}");
+                    }
+                }
+            }
+            Err(err) => crate::log_eprintln!("failed to record clipboard history: {err}"),
+        }
+    });
+    let suppressed_workspaces: Vec<Box<dyn Fn() -> Option<String>>> = capture_sources
+        .iter()
+        .map(|capture_source| -> Box<dyn Fn() -> Option<String>> {
+            match capture_source {
+                CaptureSource::Gtk(poller) => {
+                    let poller = Rc::clone(poller);
+                    Box::new(move || poller.borrow().suppressed_workspace().map(str::to_string))
+                }
+                CaptureSource::Wayland(poller, _) => {
+                    let poller = Rc::clone(poller);
+                    Box::new(move || poller.borrow().suppressed_workspace().map(str::to_string))
+                }
+            }
+        })
+        .collect();
+    let suppressed_workspace = move || suppressed_workspaces.iter().find_map(|lookup| lookup());
+    for capture_source in capture_sources {
+        let on_change = Rc::clone(&on_change);
+        match capture_source {
+            CaptureSource::Gtk(poller) => start_gtk_polling(
+                poller,
+                Duration::from_millis(CLIPBOARD_POLL_INTERVAL_MS),
+                move |entry| on_change(entry),
+            ),
+            CaptureSource::Wayland(poller, wake) => {
+                start_wake_polling(poller, wake, move |entry| on_change(entry))
+            }
+        }
+    }
 
-            if changed {
-                println!("clipboard event: {notification_body}");
-                send_clipboard_notification(notification_body);
-                let history = shared_state_for_poll.history_snapshot();
-                refresh_history_menu(&history_menu_for_poll, &clipboard_for_menu, &history);
+    let shared_state_for_reload = shared_state.clone();
+    gtk::glib::timeout_add_local(
+        Duration::from_millis(HISTORY_RELOAD_INTERVAL_MS),
+        move || {
+            if let Err(err) = shared_state_for_reload.reload_if_changed() {
+                crate::log_eprintln!("failed to reload clipboard history: {err}");
+            }
+            shared_state_for_reload.expire_transient_entries();
+            if let Err(err) = shared_state_for_reload.expire_entries() {
+                crate::log_eprintln!("failed to remove expired clipboard entries: {err}");
             }
+            let capture_state =
+                CaptureState::from_flags(*capture_paused.borrow(), screen_lock.is_locked());
+            running_item.set_label(&capture_status_label(
+                capture_state,
+                suppressed_workspace().as_deref(),
+            ));
+            // `slyboard append on|off` only touches the marker, so pick changes up here.
+            update_append_menu_state(&append_item, &append_mode);
+            // Likewise for `slyboard plain-paste on|off`; setting the same state is a no-op.
+            plain_paste_item.set_active(is_plain_paste_enabled());
+            if let Some(slots_menu) = &slots_menu {
+                slots_menu.reload_if_changed();
+            }
+            // Picks up pause, screen lock and slot changes made outside the menu.
+            refresh_tray_menu();
+            gtk::glib::ControlFlow::Continue
         },
     );
 
@@ -228,116 +783,588 @@ fn run_indicator(
     Ok(())
 }
 
+enum CaptureSource {
+    Gtk(Rc<RefCell<ClipboardPoller<GtkClipboardBackend>>>),
+    Wayland(Rc<RefCell<ClipboardPoller<ChannelBackend>>>, UnixStream),
+}
+
 fn update_capture_menu_state(
     running_item: &gtk::MenuItem,
     pause_item: &gtk::MenuItem,
-    paused: bool,
+    capture_state: CaptureState,
 ) {
-    if paused {
-        running_item.set_label(PAUSED_LABEL);
-        pause_item.set_label(RESUME_CAPTURE_LABEL);
-    } else {
-        running_item.set_label(RUNNING_LABEL);
-        pause_item.set_label(PAUSE_CAPTURE_LABEL);
+    let (status, toggle) = capture_labels(capture_state);
+    running_item.set_label(status);
+    pause_item.set_label(toggle);
+}
+
+/// The tray icon, as whichever `tray.backend` shows it.
+enum TrayHost {
+    AppIndicator(AppIndicator),
+    #[cfg(feature = "ksni")]
+    Ksni(KsniTray),
+}
+
+impl TrayHost {
+    fn app_indicator(&self) -> Option<&AppIndicator> {
+        match self {
+            TrayHost::AppIndicator(indicator) => Some(indicator),
+            #[cfg(feature = "ksni")]
+            TrayHost::Ksni(_) => None,
+        }
+    }
+
+    fn set_title(&self, title: String) {
+        match self {
+            TrayHost::AppIndicator(indicator) => indicator.set_title(&title),
+            #[cfg(feature = "ksni")]
+            TrayHost::Ksni(tray) => tray.set_title(title),
+        }
+    }
+
+    /// Hands a ksni icon the menu `menu` builds. An AppIndicator shows the GTK menu, which is
+    /// kept up to date in place.
+    #[cfg_attr(not(feature = "ksni"), allow(unused_variables))]
+    fn refresh_menu(&self, menu: impl FnOnce() -> Vec<TrayMenuItem>) {
+        match self {
+            TrayHost::AppIndicator(_) => {}
+            #[cfg(feature = "ksni")]
+            TrayHost::Ksni(tray) => tray.set_menu(menu()),
+        }
     }
 }
 
-fn send_clipboard_notification(body: &str) {
-    if let Err(err) = Command::new("notify-send")
+/// Summarizes history in the tray icon's title, or says why capture is off.
+fn update_tray_title(
+    indicator: &Option<TrayHost>,
+    capture_state: CaptureState,
+    history: &[Arc<ClipboardEntry>],
+) {
+    if let Some(indicator) = indicator {
+        indicator.set_title(tray_title(
+            capture_state,
+            history.len(),
+            history.first().map(Arc::as_ref),
+        ));
+    }
+}
+
+fn update_append_menu_state(append_item: &gtk::MenuItem, append_mode: &AppendMode) {
+    let buffer = match append_mode.buffer() {
+        Ok(buffer) => buffer,
+        Err(err) => {
+            crate::log_eprintln!("warning: failed to read append mode state: {err:#}");
+            return;
+        }
+    };
+    match append_label(buffer.as_ref()) {
+        Some(label) => {
+            append_item.set_label(&label);
+            append_item.show();
+        }
+        None => append_item.hide(),
+    }
+}
+
+fn send_clipboard_notification(capture_stats: &CaptureStats, body: &str) {
+    match Command::new("notify-send")
         .arg("-t")
         .arg("1000")
         .arg(CLIPBOARD_NOTIFICATION_TITLE)
         .arg(body)
         .status()
     {
-        eprintln!("warning: failed to send clipboard notification: {err}");
+        Ok(_) => capture_stats.notified(),
+        Err(err) => {
+            crate::log_eprintln!("warning: failed to send clipboard notification: {err}")
+        }
     }
 }
 
-fn refresh_history_menu(
-    history_menu: &gtk::Menu,
-    clipboard: &gtk::Clipboard,
-    history: &[ClipboardEntry],
-) {
-    for child in history_menu.children() {
-        history_menu.remove(&child);
+/// The History submenu, optionally topped by a search entry that filters it while typing.
+struct HistoryMenu {
+    menu: gtk::Menu,
+    actions: Rc<HistoryActions>,
+    label_format: MenuLabelFormat,
+    entry_submenus: bool,
+    search: Option<gtk::SearchEntry>,
+    history: RefCell<Vec<Arc<ClipboardEntry>>>,
+    thumbnails: Option<ThumbnailCache>,
+}
+
+impl HistoryMenu {
+    fn new(
+        clipboard: &TrayClipboard,
+        shared_state: &SharedClipboardState,
+        thumbnails: Option<ThumbnailCache>,
+        label_format: MenuLabelFormat,
+        search: bool,
+        entry_submenus: bool,
+        ocr: Option<CommandOcr>,
+    ) -> Rc<Self> {
+        let menu = gtk::Menu::new();
+        let search = search.then(|| {
+            let entry = gtk::SearchEntry::new();
+            let item = gtk::MenuItem::new();
+            item.add(&entry);
+            menu.append(&item);
+            item.show_all();
+            entry
+        });
+        let history_menu = Rc::new(Self {
+            menu,
+            actions: Rc::new(HistoryActions {
+                clipboard: clipboard.clone(),
+                shared_state: shared_state.clone(),
+                ocr,
+            }),
+            label_format,
+            entry_submenus,
+            search,
+            history: RefCell::new(Vec::new()),
+            thumbnails,
+        });
+
+        if let Some(entry) = &history_menu.search {
+            let weak_menu = Rc::downgrade(&history_menu);
+            entry.connect_search_changed(move |_| {
+                if let Some(history_menu) = weak_menu.upgrade() {
+                    history_menu.rebuild();
+                }
+            });
+
+            let entry_for_show = entry.clone();
+            history_menu
+                .menu
+                .connect_show(move |_| entry_for_show.grab_focus());
+
+            // The open menu holds the keyboard grab for navigation, so typing is handed to the
+            // entry explicitly. Escape clears a non-empty filter before it may close the menu.
+            let entry_for_keys = entry.clone();
+            history_menu.menu.connect_key_press_event(move |_, event| {
+                let key = event.keyval();
+                if key == keys::Escape {
+                    if entry_for_keys.text().is_empty() {
+                        return gtk::glib::Propagation::Proceed;
+                    }
+                    entry_for_keys.set_text("");
+                    return gtk::glib::Propagation::Stop;
+                }
+                let navigation = [
+                    keys::Up,
+                    keys::Down,
+                    keys::Page_Up,
+                    keys::Page_Down,
+                    keys::Return,
+                    keys::KP_Enter,
+                    keys::Left,
+                    keys::Right,
+                ];
+                if navigation.contains(&key) || !entry_for_keys.event(event) {
+                    return gtk::glib::Propagation::Proceed;
+                }
+                gtk::glib::Propagation::Stop
+            });
+        }
+        history_menu
     }
 
-    if history.is_empty() {
-        let empty_item = gtk::MenuItem::with_label("No clipboard history yet");
-        empty_item.set_sensitive(false);
-        history_menu.append(&empty_item);
-        empty_item.show();
-        return;
+    fn refresh(&self, history: Vec<Arc<ClipboardEntry>>) {
+        *self.history.borrow_mut() = history;
+        self.rebuild();
     }
 
-    for entry in history.iter().cloned() {
-        let label = format_menu_label(&entry);
-        let item = gtk::MenuItem::with_label(&label);
-        let clipboard = clipboard.clone();
-        item.connect_activate(move |_| {
-            set_clipboard_value(&clipboard, &entry);
+    fn rebuild(&self) {
+        let search_items = usize::from(self.search.is_some());
+        for child in self.menu.children().into_iter().skip(search_items) {
+            self.menu.remove(&child);
+        }
+
+        let history = self.history.borrow();
+        let query = self
+            .search
+            .as_ref()
+            .map(|entry| entry.text().to_string())
+            .filter(|query| !query.trim().is_empty());
+        let items = match query {
+            Some(query) if !history.is_empty() => {
+                let filter = HistoryFilter {
+                    search: Some(query),
+                    fuzzy: true,
+                    ..HistoryFilter::default()
+                };
+                matching_history_items(
+                    &filter.apply(&history),
+                    &self.label_format,
+                    self.entry_submenus,
+                    self.actions.ocr.is_some(),
+                    unix_now(),
+                )
+            }
+            _ => history_items(
+                &history,
+                &self.label_format,
+                self.entry_submenus,
+                self.actions.ocr.is_some(),
+                unix_now(),
+            ),
+        };
+        let actions = self.actions.clone();
+        let on_action: Rc<dyn Fn(TrayAction)> = Rc::new(move |action| {
+            if let TrayAction::Entry { id, action } = action {
+                actions.dispatch(id, action);
+            }
         });
-        history_menu.append(&item);
-        item.show();
+        render_menu_items(&self.menu, &items, &on_action, &|action| {
+            self.thumbnail_for(&history, action)
+        });
+    }
+
+    /// The thumbnail shown in front of the item copying an image entry.
+    fn thumbnail_for(
+        &self,
+        history: &[Arc<ClipboardEntry>],
+        action: TrayAction,
+    ) -> Option<PathBuf> {
+        let TrayAction::Entry {
+            id,
+            action: EntryAction::Copy,
+        } = action
+        else {
+            return None;
+        };
+        let thumbnails = self.thumbnails.as_ref()?;
+        let entry = history.iter().find(|entry| entry.content_id() == id)?;
+        if !matches!(**entry, ClipboardEntry::Image { .. }) {
+            return None;
+        }
+        thumbnails
+            .get_or_create(entry)
+            .map_err(|err| crate::log_eprintln!("warning: failed to create thumbnail: {err}"))
+            .ok()
     }
 }
 
-fn format_menu_label(entry: &ClipboardEntry) -> String {
-    match entry {
-        ClipboardEntry::Text { value, .. } => format_text_menu_label(value),
-        ClipboardEntry::Image { width, height, .. } => {
-            format!("[image] {}x{}", width, height)
+/// The "Slots" submenu. `slyboard yank` writes the slots file from another process, so it is
+/// re-read whenever its modification time or size changes.
+struct SlotsMenu {
+    menu: gtk::Menu,
+    store: SlotStore,
+    clipboard: TrayClipboard,
+    shared_state: SharedClipboardState,
+    char_limit: usize,
+    stamp: Cell<Option<(SystemTime, u64)>>,
+    loaded: Cell<bool>,
+    /// The slots as last read, for menus built elsewhere from the same description.
+    slots: RefCell<BTreeMap<String, Arc<ClipboardEntry>>>,
+}
+
+impl SlotsMenu {
+    fn new(
+        store: SlotStore,
+        clipboard: &TrayClipboard,
+        shared_state: &SharedClipboardState,
+        char_limit: usize,
+    ) -> Rc<Self> {
+        Rc::new(Self {
+            menu: gtk::Menu::new(),
+            store,
+            clipboard: clipboard.clone(),
+            shared_state: shared_state.clone(),
+            char_limit,
+            stamp: Cell::new(None),
+            loaded: Cell::new(false),
+            slots: RefCell::new(BTreeMap::new()),
+        })
+    }
+
+    fn reload_if_changed(self: &Rc<Self>) {
+        let stamp = fs::metadata(self.store.path())
+            .and_then(|metadata| Ok((metadata.modified()?, metadata.len())))
+            .ok();
+        if self.loaded.get() && stamp == self.stamp.get() {
+            return;
+        }
+        let slots = match self.store.load() {
+            Ok(slots) => slots,
+            Err(err) => {
+                crate::log_eprintln!("failed to load clipboard slots: {err}");
+                return;
+            }
+        };
+        self.stamp.set(stamp);
+        self.loaded.set(true);
+
+        for child in self.menu.children() {
+            self.menu.remove(&child);
+        }
+        let items = slot_items(&slots, self.char_limit);
+        *self.slots.borrow_mut() = slots;
+        let slots_menu = Rc::downgrade(self);
+        let on_action: Rc<dyn Fn(TrayAction)> = Rc::new(move |action| {
+            if let (TrayAction::RestoreSlot { id }, Some(slots_menu)) =
+                (action, slots_menu.upgrade())
+            {
+                slots_menu.restore(id);
+            }
+        });
+        render_menu_items(&self.menu, &items, &on_action, &|_| None);
+    }
+
+    /// Puts the slot entry whose content id is `id` on the clipboard. The file is read again
+    /// so the restored entry is the one saved now, not when the menu was built.
+    fn restore(&self, id: u64) {
+        match self.store.load() {
+            Ok(slots) => match slots.values().find(|entry| entry.content_id() == id) {
+                Some(entry) => self.clipboard.restore(entry, &self.shared_state),
+                None => crate::log_eprintln!("clipboard slot no longer exists"),
+            },
+            Err(err) => crate::log_eprintln!("failed to load clipboard slots: {err}"),
         }
     }
 }
 
-fn format_text_menu_label(value: &str) -> String {
-    let sanitized = value.replace('\n', "\\n").replace('\r', "\\r");
-    let char_count = sanitized.chars().count();
-    if char_count <= MENU_LABEL_CHAR_LIMIT {
-        return sanitized;
+struct HistoryActions {
+    clipboard: TrayClipboard,
+    shared_state: SharedClipboardState,
+    /// Set when `ocr.enabled` is, adding text recognition to image entries' submenus.
+    ocr: Option<CommandOcr>,
+}
+
+impl HistoryActions {
+    /// Menu items carry entry ids rather than entries, so actions see the history as it is
+    /// when they run.
+    fn dispatch(&self, id: u64, action: EntryAction) {
+        match dispatch_entry_action(&self.shared_state, self, id, action) {
+            Ok(true) => {}
+            Ok(false) => crate::log_eprintln!("clipboard entry is no longer in history"),
+            Err(err) => crate::log_eprintln!("clipboard entry action failed: {err:#}"),
+        }
     }
+}
 
-    let truncated: String = sanitized.chars().take(MENU_LABEL_CHAR_LIMIT).collect();
-    format!("{truncated}...")
+impl EntryActionTarget for HistoryActions {
+    fn copy(&self, entry: &ClipboardEntry) {
+        self.clipboard.set(entry);
+    }
+
+    fn open_url(&self, url: &str) {
+        if let Err(err) = Command::new("xdg-open").arg(url).spawn() {
+            crate::log_eprintln!("failed to open {url}: {err}");
+        }
+    }
+
+    /// Runs on the GTK thread, so the menu waits for the OCR command.
+    fn recognize_text(&self, entry: &ClipboardEntry) -> anyhow::Result<String> {
+        let ocr = self.ocr.as_ref().context("text recognition is disabled")?;
+        recognize_entry(ocr, entry)
+    }
 }
 
-fn set_clipboard_value(clipboard: &gtk::Clipboard, entry: &ClipboardEntry) {
-    match entry {
-        ClipboardEntry::Text { value, .. } => {
-            clipboard.set_text(value);
-            clipboard.store();
-        }
-        ClipboardEntry::Image {
-            width,
-            height,
-            rowstride,
-            has_alpha,
-            bits_per_sample,
-            pixels,
-            ..
-        } => {
-            let bytes = gtk::glib::Bytes::from(pixels.as_slice());
-            let image = gtk::gdk_pixbuf::Pixbuf::from_bytes(
-                &bytes,
-                gtk::gdk_pixbuf::Colorspace::Rgb,
-                *has_alpha,
-                *bits_per_sample,
-                *width,
-                *height,
-                *rowstride,
-            );
-            clipboard.set_image(&image);
-            clipboard.store();
+/// Appends GTK widgets for a tray menu description, routing activations to `on_action`.
+/// `icon_for` may put an image in front of the top-level items.
+fn render_menu_items(
+    menu: &gtk::Menu,
+    items: &[TrayMenuItem],
+    on_action: &Rc<dyn Fn(TrayAction)>,
+    icon_for: &dyn Fn(TrayAction) -> Option<PathBuf>,
+) {
+    for item in items {
+        let widget: gtk::MenuItem = match item {
+            TrayMenuItem::Label(label) => {
+                let widget = gtk::MenuItem::with_label(label);
+                widget.set_sensitive(false);
+                widget
+            }
+            TrayMenuItem::Action {
+                label,
+                action,
+                tooltip,
+            } => {
+                let widget = menu_item(label, icon_for(*action).as_deref());
+                widget.set_tooltip_text(tooltip.as_deref());
+                let on_action = on_action.clone();
+                let action = *action;
+                widget.connect_activate(move |_| on_action(action));
+                widget
+            }
+            TrayMenuItem::Separator => gtk::SeparatorMenuItem::new().upcast(),
+            TrayMenuItem::Submenu {
+                label,
+                items,
+                activate,
+                tooltip,
+            } => {
+                let icon = activate.and_then(icon_for);
+                let widget = menu_item(label, icon.as_deref());
+                widget.set_tooltip_text(tooltip.as_deref());
+                let submenu = gtk::Menu::new();
+                render_menu_items(&submenu, items, on_action, &|_| None);
+                widget.set_submenu(Some(&submenu));
+                // GTK opens submenus on hover and never emits `activate` for a click on them.
+                if let Some(action) = *activate {
+                    let on_action = on_action.clone();
+                    widget.connect_button_release_event(move |widget, _| {
+                        on_action(action);
+                        close_menu_tree(widget);
+                        gtk::glib::Propagation::Stop
+                    });
+                }
+                widget
+            }
+        };
+        menu.append(&widget);
+        widget.show();
+    }
+}
+
+/// Closes every menu from `item` up to the tray menu, as activating a plain item would.
+/// A menu item showing `label`, with the image at `icon` in front of it if that loads.
+fn menu_item(label: &str, icon: Option<&Path>) -> gtk::MenuItem {
+    let pixbuf = icon.and_then(|icon| {
+        Pixbuf::from_file_at_size(icon, MENU_ICON_SIZE, MENU_ICON_SIZE)
+            .map_err(|err| {
+                crate::log_eprintln!("warning: failed to load {}: {err}", icon.display());
+            })
+            .ok()
+    });
+    let Some(pixbuf) = pixbuf else {
+        return gtk::MenuItem::with_label(label);
+    };
+    let row = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    row.pack_start(&gtk::Image::from_pixbuf(Some(&pixbuf)), false, false, 0);
+    row.pack_start(&gtk::Label::new(Some(label)), false, false, 0);
+    let item = gtk::MenuItem::new();
+    item.add(&row);
+    item
+}
+
+/// Thumbnails only speed up the menu, so failing to clean them up is logged and ignored.
+fn clean_thumbnails(thumbnails: &ThumbnailCache, history: &[Arc<ClipboardEntry>]) {
+    if let Err(err) = thumbnails.cleanup(history) {
+        crate::log_eprintln!("warning: failed to clean up thumbnails: {err}");
+    }
+}
+
+fn close_menu_tree(item: &gtk::MenuItem) {
+    let mut shell = item.parent().and_downcast::<gtk::MenuShell>();
+    while let Some(current) = shell {
+        let parent = current
+            .downcast_ref::<gtk::Menu>()
+            .and_then(|menu| menu.attach_widget())
+            .and_then(|attached| attached.parent())
+            .and_downcast::<gtk::MenuShell>();
+        if parent.is_none() {
+            current.deactivate();
         }
+        shell = parent;
+    }
+}
+
+/// Moves the armed index one entry older (`step_back`) or newer, wrapping around.
+fn cycle_armed_index(current: usize, len: usize, step_back: bool) -> usize {
+    if len == 0 {
+        return 0;
     }
+    let current = current.min(len - 1);
+    if step_back {
+        (current + 1) % len
+    } else {
+        (current + len - 1) % len
+    }
+}
+
+/// The selections `clipboard.restore_target` names, as the tray restores to them. Each write
+/// is announced to the poller first, so the restore isn't captured as a new copy.
+#[derive(Clone)]
+struct TrayClipboard {
+    expected_writes: ExpectedWrites,
+    restore_target: RestoreTarget,
+    /// Where history keeps the whole text of entries cut to a preview.
+    long_texts: LongTextStore,
 }
 
-fn notification_body_for_entry(entry: &ClipboardEntry) -> &'static str {
-    match entry {
-        ClipboardEntry::Text { .. } => CLIPBOARD_TEXT_NOTIFICATION_BODY,
-        ClipboardEntry::Image { .. } => CLIPBOARD_IMAGE_NOTIFICATION_BODY,
+impl TrayClipboard {
+    fn set(&self, entry: &ClipboardEntry) {
+        let entry = match self.long_texts.restore(entry) {
+            Ok(entry) => entry,
+            Err(err) => {
+                crate::log_eprintln!("failed to restore clipboard entry: {err:#}");
+                return;
+            }
+        };
+        self.expected_writes.expect(&entry);
+        let selections = restore_selections(&entry, self.restore_target);
+        if let Err(err) = set_gtk_selections(&entry, &selections) {
+            crate::log_eprintln!("failed to restore clipboard entry: {err:#}");
+        }
+    }
+
+    /// Puts `entry` back and counts the restore on the history entry with its content.
+    fn restore(&self, entry: &ClipboardEntry, shared_state: &SharedClipboardState) {
+        self.set(entry);
+        if let Err(err) = shared_state.mark_restored(entry) {
+            crate::log_eprintln!("warning: failed to record the restore: {err}");
+        }
+    }
+}
+
+/// Transient entries (password managers, private windows) say nothing about their content or
+/// where it came from: they are kept out of history so that it never leaves memory.
+fn notification_body_for_entry(entry: &ClipboardEntry, preview_chars: usize) -> String {
+    if entry.is_transient() {
+        return "Copied: contents hidden".to_string();
+    }
+    let preview = entry_preview(entry, preview_chars);
+    let (ClipboardEntry::Text { source_window, .. } | ClipboardEntry::Image { source_window, .. }) =
+        entry;
+
+    let source = source_window.as_ref().and_then(|context| {
+        let name = context.app_id.as_deref().unwrap_or(&context.title);
+        (!name.trim().is_empty()).then(|| preview_text(name.trim(), preview_chars))
+    });
+    match source {
+        Some(source) => format!("Copied from {source}: {preview}"),
+        None => format!("Copied: {preview}"),
+    }
+}
+
+/// Icon the indicator is created with.
+#[derive(Debug, PartialEq, Eq)]
+enum TrayIcon {
+    /// Looked up in the icon theme.
+    Named(String),
+    /// A file named `{name}.svg`/`{name}.png` inside `theme_path`.
+    File { theme_path: PathBuf, name: String },
+}
+
+impl TrayIcon {
+    fn from_config(icon: Option<&str>) -> Self {
+        match icon.map(str::trim).filter(|icon| !icon.is_empty()) {
+            Some(icon) if icon.contains('/') => {
+                let path = Path::new(icon);
+                match (path.parent(), path.file_stem().and_then(OsStr::to_str)) {
+                    (Some(parent), Some(name)) => TrayIcon::File {
+                        theme_path: parent.to_path_buf(),
+                        name: name.to_string(),
+                    },
+                    _ => Self::bundled(),
+                }
+            }
+            Some(icon) => TrayIcon::Named(icon.to_string()),
+            None => Self::bundled(),
+        }
+    }
+
+    fn bundled() -> Self {
+        let name = install_bundled_icon().unwrap_or("input-keyboard");
+        TrayIcon::Named(name.to_string())
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            TrayIcon::Named(name) | TrayIcon::File { name, .. } => name,
+        }
     }
 }
 
@@ -346,24 +1373,162 @@ fn install_bundled_icon() -> Option<&'static str> {
         .map(PathBuf::from)
         .or_else(|| dirs::home_dir().map(|home| home.join(".local/share")))?;
 
+    match install_icon_if_changed(&data_home, BUNDLED_TRAY_ICON_NAME, BUNDLED_TRAY_ICON_SVG) {
+        Ok(_) => Some(BUNDLED_TRAY_ICON_NAME),
+        Err(err) => {
+            crate::log_eprintln!("warning: failed to install bundled tray icon: {err}");
+            None
+        }
+    }
+}
+
+/// Writes `{name}.svg` into the hicolor theme under `data_home` unless an identical copy is
+/// already there, so a read-only icon directory with the current icon is fine.
+/// Returns whether the file was written.
+fn install_icon_if_changed(data_home: &Path, name: &str, svg: &[u8]) -> io::Result<bool> {
     let icon_path = data_home
         .join("icons")
         .join("hicolor")
         .join("scalable")
         .join("apps")
-        .join(format!("{BUNDLED_TRAY_ICON_NAME}.svg"));
+        .join(format!("{name}.svg"));
 
+    if fs::read(&icon_path).is_ok_and(|existing| existing == svg) {
+        return Ok(false);
+    }
     if let Some(parent) = icon_path.parent() {
-        if let Err(err) = fs::create_dir_all(parent) {
-            eprintln!("warning: failed to create icon directory: {err}");
-            return None;
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&icon_path, svg)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        cycle_armed_index, display_available, install_icon_if_changed, notification_body_for_entry,
+        TrayIcon,
+    };
+    use crate::clipboard::ClipboardEntry;
+    use crate::core::active_window::ActiveWindowContext;
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn text_from(value: &str, source_window: Option<ActiveWindowContext>) -> ClipboardEntry {
+        ClipboardEntry::text(value).with_source_window(source_window)
+    }
+
+    fn window(title: &str, app_id: Option<&str>) -> ActiveWindowContext {
+        ActiveWindowContext {
+            backend: "hyprctl".to_string(),
+            title: title.to_string(),
+            app_id: app_id.map(str::to_string),
+            initial_app_id: None,
+            initial_title: None,
+            window_id: None,
+            pid: None,
+            workspace_id: None,
+            workspace_name: None,
+            is_xwayland: None,
+            monitor: None,
+            geometry: None,
         }
     }
 
-    if let Err(err) = fs::write(&icon_path, BUNDLED_TRAY_ICON_SVG) {
-        eprintln!("warning: failed to write bundled tray icon: {err}");
-        return None;
+    #[test]
+    fn either_display_variable_allows_startup() {
+        let set = Some(OsStr::new(":0"));
+        let wayland = Some(OsStr::new("wayland-1"));
+        assert!(display_available(set, None));
+        assert!(display_available(None, wayland));
+        assert!(display_available(Some(OsStr::new("")), wayland));
+        assert!(!display_available(None, None));
+        assert!(!display_available(
+            Some(OsStr::new("")),
+            Some(OsStr::new(""))
+        ));
+    }
+
+    #[test]
+    fn notification_names_source_app() {
+        let entry = text_from(
+            "https://example.com/a/long/path",
+            Some(window("Example - Mozilla Firefox", Some("firefox"))),
+        );
+        assert_eq!(
+            notification_body_for_entry(&entry, 15),
+            "Copied from firefox: https://example..."
+        );
     }
 
-    Some(BUNDLED_TRAY_ICON_NAME)
+    #[test]
+    fn notification_falls_back_to_title_and_plain_body() {
+        let entry = text_from("line one\nline two", Some(window("Terminal", None)));
+        assert_eq!(
+            notification_body_for_entry(&entry, 40),
+            "Copied from Terminal: line one\\nline two"
+        );
+
+        let entry = text_from("hello", None);
+        assert_eq!(notification_body_for_entry(&entry, 40), "Copied: hello");
+    }
+
+    #[test]
+    fn notification_hides_transient_entries() {
+        let entry = text_from("hunter2", Some(window("Private Browsing", Some("firefox"))))
+            .with_transient_ttl(Duration::from_secs(30));
+        assert_eq!(
+            notification_body_for_entry(&entry, 40),
+            "Copied: contents hidden"
+        );
+    }
+
+    #[test]
+    fn bundled_icon_is_only_rewritten_when_changed() {
+        let data_home =
+            std::env::temp_dir().join(format!("slyboard-test-icons-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&data_home);
+        let icon_path = data_home.join("icons/hicolor/scalable/apps/slyboard.svg");
+
+        assert!(install_icon_if_changed(&data_home, "slyboard", b"<svg>v1</svg>").unwrap());
+        assert!(!install_icon_if_changed(&data_home, "slyboard", b"<svg>v1</svg>").unwrap());
+        assert!(install_icon_if_changed(&data_home, "slyboard", b"<svg>v2</svg>").unwrap());
+        assert_eq!(fs::read(&icon_path).unwrap(), b"<svg>v2</svg>");
+
+        // An up-to-date icon in a read-only directory is not an error.
+        let mut permissions = fs::metadata(&icon_path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&icon_path, permissions.clone()).unwrap();
+        assert!(!install_icon_if_changed(&data_home, "slyboard", b"<svg>v2</svg>").unwrap());
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(&icon_path, permissions).unwrap();
+        let _ = fs::remove_dir_all(&data_home);
+    }
+
+    #[test]
+    fn configured_icon_is_a_theme_name_or_file() {
+        assert_eq!(
+            TrayIcon::from_config(Some("edit-paste")),
+            TrayIcon::Named("edit-paste".to_string())
+        );
+        assert_eq!(
+            TrayIcon::from_config(Some("/opt/icons/clip.png")),
+            TrayIcon::File {
+                theme_path: PathBuf::from("/opt/icons"),
+                name: "clip".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn scrolling_cycles_armed_index() {
+        assert_eq!(cycle_armed_index(0, 3, true), 1);
+        assert_eq!(cycle_armed_index(2, 3, true), 0);
+        assert_eq!(cycle_armed_index(0, 3, false), 2);
+        assert_eq!(cycle_armed_index(5, 3, false), 1);
+        assert_eq!(cycle_armed_index(4, 0, true), 0);
+    }
 }
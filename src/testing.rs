@@ -0,0 +1,356 @@
+//! Building blocks for tests of code built on slyboard: a scriptable clipboard backend, a
+//! throwaway history database, and terse constructors for entries and source windows.
+//! Enabled by the `test-util` feature.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::clipboard::backend::ClipboardBackend;
+use crate::clipboard::dedup::DedupSettings;
+use crate::clipboard::writer::Selection;
+use crate::clipboard::{
+    CaptureOrigin, ClipboardEntry, ImageEncoding, PruneOptions, SharedClipboardState,
+};
+use crate::core::active_window::ActiveWindowContext;
+use crate::error::Result;
+
+/// A read made on a [`FakeClipboardBackend`], in the order [`FakeClipboardHandle::calls`]
+/// lists them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendCall {
+    ReadEntry,
+    ReadActiveWindow,
+}
+
+#[derive(Debug, Default)]
+struct Script {
+    entries: VecDeque<ClipboardEntry>,
+    active_windows: VecDeque<Option<ActiveWindowContext>>,
+    calls: Vec<BackendCall>,
+}
+
+/// A [`ClipboardBackend`] that replays queued clipboard reads and focused windows, one per
+/// call, and reads nothing once a queue runs dry. More can be queued while a poller owns the
+/// backend through a [`FakeClipboardHandle`].
+#[derive(Debug, Clone)]
+pub struct FakeClipboardBackend {
+    script: Arc<Mutex<Script>>,
+    each_copy: bool,
+    read_delay: Duration,
+    selection: Selection,
+}
+
+/// Queues reads on a [`FakeClipboardBackend`] from outside, e.g. from another thread.
+#[derive(Debug, Clone)]
+pub struct FakeClipboardHandle {
+    script: Arc<Mutex<Script>>,
+}
+
+impl FakeClipboardBackend {
+    /// A backend polling a clipboard, like GTK's.
+    pub fn new() -> Self {
+        Self {
+            script: Arc::new(Mutex::new(Script::default())),
+            each_copy: false,
+            read_delay: Duration::ZERO,
+            selection: Selection::Clipboard,
+        }
+    }
+
+    /// Reads `selection` instead of the clipboard, e.g. to script a primary selection whose
+    /// contents differ from the clipboard's.
+    pub fn watching(mut self, selection: Selection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Behaves like a selection-event backend (`wl-paste --watch`) instead: every read is a
+    /// separate copy.
+    pub fn reporting_each_copy(mut self) -> Self {
+        self.each_copy = true;
+        self
+    }
+
+    /// Makes every clipboard read that finds an entry take `delay`, like transferring a large
+    /// image.
+    pub fn with_read_delay(mut self, delay: Duration) -> Self {
+        self.read_delay = delay;
+        self
+    }
+
+    /// Queues what the next [`read_entry`](ClipboardBackend::read_entry) calls return.
+    pub fn with_entries(self, entries: impl IntoIterator<Item = ClipboardEntry>) -> Self {
+        self.handle().lock().entries.extend(entries);
+        self
+    }
+
+    /// Queues what the next [`read_active_window`](ClipboardBackend::read_active_window)
+    /// calls return; `None` is a read that found no focused window.
+    pub fn with_active_windows(
+        self,
+        windows: impl IntoIterator<Item = Option<ActiveWindowContext>>,
+    ) -> Self {
+        self.handle().lock().active_windows.extend(windows);
+        self
+    }
+
+    pub fn handle(&self) -> FakeClipboardHandle {
+        FakeClipboardHandle {
+            script: Arc::clone(&self.script),
+        }
+    }
+}
+
+impl Default for FakeClipboardBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeClipboardHandle {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Script> {
+        self.script.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn push_entry(&self, entry: ClipboardEntry) {
+        self.lock().entries.push_back(entry);
+    }
+
+    pub fn push_active_window(&self, window: Option<ActiveWindowContext>) {
+        self.lock().active_windows.push_back(window);
+    }
+
+    /// Queues `entry` as copied in `window`.
+    pub fn copy(&self, entry: ClipboardEntry, window: Option<ActiveWindowContext>) {
+        let mut script = self.lock();
+        script.entries.push_back(entry);
+        script.active_windows.push_back(window);
+    }
+
+    /// Entries queued but not read yet.
+    pub fn pending_entries(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Focused windows queued but not read yet.
+    pub fn pending_active_windows(&self) -> usize {
+        self.lock().active_windows.len()
+    }
+
+    /// Every read made on the backend so far, oldest first.
+    pub fn calls(&self) -> Vec<BackendCall> {
+        self.lock().calls.clone()
+    }
+}
+
+impl ClipboardBackend for FakeClipboardBackend {
+    fn read_entry(&self) -> Option<ClipboardEntry> {
+        let entry = {
+            let handle = self.handle();
+            let mut script = handle.lock();
+            script.calls.push(BackendCall::ReadEntry);
+            script.entries.pop_front()
+        };
+        if entry.is_some() {
+            std::thread::sleep(self.read_delay);
+        }
+        entry
+    }
+
+    fn origin(&self) -> CaptureOrigin {
+        if self.each_copy {
+            CaptureOrigin::WaylandEvent
+        } else {
+            CaptureOrigin::GtkPoll
+        }
+    }
+
+    fn selection(&self) -> Selection {
+        self.selection
+    }
+
+    fn read_active_window(&self) -> Option<ActiveWindowContext> {
+        let handle = self.handle();
+        let mut script = handle.lock();
+        script.calls.push(BackendCall::ReadActiveWindow);
+        script.active_windows.pop_front().flatten()
+    }
+
+    fn reports_each_copy(&self) -> bool {
+        self.each_copy
+    }
+}
+
+/// A history database in a fresh temp directory, deleted with everything in it on drop.
+#[derive(Debug)]
+pub struct ScratchStore {
+    dir: PathBuf,
+}
+
+impl ScratchStore {
+    /// `name` only makes the directory recognizable; each store gets its own.
+    pub fn new(name: &str) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "slyboard-test-{name}-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("create scratch store directory");
+        Self { dir }
+    }
+
+    /// Where other files of the test (pause markers, append buffers) can go.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn database_path(&self) -> PathBuf {
+        self.dir.join("history.json")
+    }
+
+    /// Loads the database, as the daemon or a CLI process would.
+    pub fn load(&self, history_limit: usize) -> Result<SharedClipboardState> {
+        SharedClipboardState::load(self.database_path(), history_limit)
+    }
+
+    /// [`load`](Self::load) with `clipboard.dedup` set to `dedup`.
+    pub fn load_with_dedup(
+        &self,
+        history_limit: usize,
+        dedup: DedupSettings,
+    ) -> Result<SharedClipboardState> {
+        SharedClipboardState::load_pruned(
+            self.database_path(),
+            history_limit,
+            PruneOptions::default(),
+            dedup,
+        )
+    }
+}
+
+impl Drop for ScratchStore {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub fn text(value: &str) -> ClipboardEntry {
+    ClipboardEntry::text(value)
+}
+
+/// A `width`x`height` RGBA image filled with `rgba`, stored as raw rows like entries saved
+/// before PNG support.
+pub fn raw_image(width: i32, height: i32, rgba: [u8; 4]) -> ClipboardEntry {
+    ClipboardEntry::Image {
+        width,
+        height,
+        rowstride: width * 4,
+        has_alpha: true,
+        bits_per_sample: 8,
+        channels: 4,
+        encoding: ImageEncoding::Raw,
+        pixels: rgba.repeat((width * height) as usize),
+        source_window: None,
+        pinned: false,
+        label: None,
+        transient_until: None,
+        captured_at: None,
+        captured_by: None,
+        selection: None,
+        restore_count: 0,
+        last_restored_at: None,
+        expires_at: None,
+        offered_targets: Vec::new(),
+    }
+}
+
+/// Like [`raw_image`], but PNG-encoded as captured images are.
+pub fn png_image(width: i32, height: i32, rgba: [u8; 4]) -> ClipboardEntry {
+    raw_image(width, height, rgba).into_png_encoded()
+}
+
+/// A `width`x`height` PNG whose pixels vary with `seed` and position, so images from
+/// different seeds differ and don't compress to nothing.
+pub fn synthetic_image(width: i32, height: i32, seed: u8) -> ClipboardEntry {
+    let mut image = raw_image(width, height, [0; 4]);
+    if let ClipboardEntry::Image { pixels, .. } = &mut image {
+        for (index, pixel) in pixels.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (index % width as usize, index / width as usize);
+            pixel.copy_from_slice(&[(x ^ y) as u8, (x / 8) as u8 ^ seed, (y / 8) as u8, 255]);
+        }
+    }
+    image.into_png_encoded()
+}
+
+/// A 1920x1080 [`synthetic_image`], the size of a typical screenshot.
+pub fn screenshot(seed: u8) -> ClipboardEntry {
+    synthetic_image(1920, 1080, seed)
+}
+
+/// `count` distinct text entries of a few hundred bytes each, newest first.
+pub fn text_history(count: usize) -> VecDeque<Arc<ClipboardEntry>> {
+    (0..count)
+        .rev()
+        .map(|index| {
+            Arc::new(text(&format!(
+                "clipboard entry {index}: {}",
+                "lorem ipsum ".repeat(20)
+            )))
+        })
+        .collect()
+}
+
+/// `count` distinct [`screenshot`]s, newest first.
+pub fn screenshot_history(count: usize) -> VecDeque<Arc<ClipboardEntry>> {
+    (0..count)
+        .rev()
+        .map(|index| Arc::new(screenshot(index as u8)))
+        .collect()
+}
+
+/// A window focused when copying; `app_id` is optional, as with X11 title-only backends.
+pub fn window(app_id: Option<&str>, title: &str) -> ActiveWindowContext {
+    ActiveWindowContext {
+        backend: "hyprctl".to_string(),
+        title: title.to_string(),
+        app_id: app_id.map(str::to_string),
+        initial_app_id: None,
+        initial_title: None,
+        window_id: None,
+        pid: None,
+        workspace_id: None,
+        workspace_name: None,
+        is_xwayland: None,
+        monitor: None,
+        geometry: None,
+    }
+}
+
+/// `value` as copied in `window`.
+pub fn text_from(value: &str, window: ActiveWindowContext) -> ClipboardEntry {
+    text(value).with_source(window)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{synthetic_image, text_history};
+
+    #[test]
+    fn generated_entries_are_distinct() {
+        let history = text_history(50);
+        assert!(matches!(
+            history[0].as_ref(),
+            crate::clipboard::ClipboardEntry::Text { value, .. } if value.starts_with("clipboard entry 49:")
+        ));
+        assert!(history
+            .iter()
+            .skip(1)
+            .all(|entry| !entry.content_eq(&history[0])));
+        assert!(!synthetic_image(16, 16, 1).content_eq(&synthetic_image(16, 16, 2)));
+    }
+}
@@ -0,0 +1,220 @@
+//! Copies scripted on a fake backend, captured by the poller into history on disk.
+
+use std::collections::BTreeMap;
+
+use slyboard::clipboard::blacklist::BlacklistRule;
+use slyboard::clipboard::dedup::{DedupScope, DedupSettings};
+use slyboard::clipboard::poller::{ClipboardPoller, SharedLastSeen};
+use slyboard::clipboard::writer::Selection;
+use slyboard::clipboard::{CaptureOrigin, ClipboardEntry, SharedClipboardState};
+use slyboard::config::WindowMatchMode;
+use slyboard::core::append_mode::AppendMode;
+use slyboard::core::capture_stats::{CaptureStats, SkipReason};
+use slyboard::core::daemon::capture_once;
+use slyboard::core::self_write::ExpectedWrites;
+use slyboard::testing::{png_image, text, window, FakeClipboardBackend, ScratchStore};
+
+fn history(state: &SharedClipboardState) -> Vec<(String, Option<String>)> {
+    state
+        .history_snapshot()
+        .iter()
+        .map(|entry| {
+            let content = match entry.as_ref() {
+                ClipboardEntry::Text { value, .. } => value.clone(),
+                ClipboardEntry::Image { width, height, .. } => format!("{width}x{height} image"),
+            };
+            let app_id = entry
+                .source_window()
+                .and_then(|window| window.app_id.clone());
+            (content, app_id)
+        })
+        .collect()
+}
+
+const DEDUP_OFF: DedupSettings = DedupSettings {
+    enabled: false,
+    scope: DedupScope::All,
+    ignore_trailing_whitespace: true,
+};
+
+#[test]
+fn records_copies_from_allowed_windows_and_persists_them() {
+    let store = ScratchStore::new("capture-pipeline");
+    let state = store.load(10).expect("load");
+    let append = AppendMode::at(store.dir().join("append.json"));
+    let backend = FakeClipboardBackend::new();
+    let clipboard = backend.handle();
+    let blacklist = vec![BlacklistRule::parse("keepassxc", WindowMatchMode::Exact).unwrap()];
+    let mut poller = ClipboardPoller::new(backend, blacklist);
+    let mut capture = || capture_once(&mut poller, &state, || Ok(false), &append).unwrap();
+
+    assert!(capture().is_none(), "nothing copied yet");
+
+    clipboard.copy(text("cargo test"), Some(window(Some("kitty"), "~/crate")));
+    assert!(capture().is_some_and(|outcome| outcome.inserted));
+    assert!(capture().is_none(), "the clipboard didn't change");
+
+    clipboard.copy(
+        text("hunter2"),
+        Some(window(Some("keepassxc"), "Passwords")),
+    );
+    assert!(capture().is_none(), "copied in a blacklisted window");
+
+    clipboard.copy(png_image(2, 2, [0, 0, 255, 255]), None);
+    clipboard.copy(
+        text("https://example.com"),
+        Some(window(Some("firefox"), "Example")),
+    );
+    assert!(capture().is_some());
+    assert!(capture().is_some());
+    assert_eq!(clipboard.pending_entries(), 0);
+
+    let expected = vec![
+        (
+            "https://example.com".to_string(),
+            Some("firefox".to_string()),
+        ),
+        ("2x2 image".to_string(), None),
+        ("cargo test".to_string(), Some("kitty".to_string())),
+    ];
+    assert_eq!(history(&state), expected);
+
+    let reloaded = store.load(10).expect("reload");
+    assert_eq!(history(&reloaded), expected);
+}
+
+#[test]
+fn records_each_change_once_when_two_backends_watch_the_clipboard() {
+    let store = ScratchStore::new("capture-pipeline-two-backends");
+    // Off, so a change recorded by both backends would show up twice.
+    let state = store.load_with_dedup(10, DEDUP_OFF).expect("load");
+    let append = AppendMode::at(store.dir().join("append.json"));
+    let stats = CaptureStats::default();
+    let shared_last_seen = SharedLastSeen::default();
+    let expected_writes = ExpectedWrites::default();
+    let poller = |backend: FakeClipboardBackend| {
+        ClipboardPoller::new(backend, Vec::new())
+            .with_capture_stats(stats.clone())
+            .with_expected_writes(expected_writes.clone())
+            .with_shared_last_seen(shared_last_seen.clone())
+    };
+    let gtk_backend = FakeClipboardBackend::new();
+    let gtk = gtk_backend.handle();
+    let mut gtk_poller = poller(gtk_backend);
+    let wayland_backend = FakeClipboardBackend::new().reporting_each_copy();
+    let wayland = wayland_backend.handle();
+    let mut wayland_poller = poller(wayland_backend);
+    let kitty = || Some(window(Some("kitty"), "~"));
+
+    let mut captured = Vec::new();
+    let mut see = |seen_by: CaptureOrigin, value: &str| {
+        let (clipboard, poller) = match seen_by {
+            CaptureOrigin::GtkPoll => (&gtk, &mut gtk_poller),
+            _ => (&wayland, &mut wayland_poller),
+        };
+        clipboard.copy(text(value), kitty());
+        let outcome = capture_once(poller, &state, || Ok(false), &append).unwrap();
+        if outcome.is_some() {
+            captured.push((seen_by, value.to_string()));
+        }
+    };
+    let (gtk_poll, wayland_event) = (CaptureOrigin::GtkPoll, CaptureOrigin::WaylandEvent);
+    see(wayland_event, "a");
+    see(gtk_poll, "a");
+    see(gtk_poll, "b");
+    see(wayland_event, "b");
+    see(wayland_event, "c");
+    see(gtk_poll, "c");
+    expected_writes.expect(&text("restored"));
+    see(gtk_poll, "restored");
+    see(wayland_event, "restored");
+    see(gtk_poll, "a");
+    see(wayland_event, "a");
+
+    let expected = [
+        (wayland_event, "a"),
+        (gtk_poll, "b"),
+        (wayland_event, "c"),
+        (gtk_poll, "a"),
+    ];
+    assert_eq!(
+        captured,
+        expected.map(|(origin, value)| (origin, value.to_string()))
+    );
+    let recorded: Vec<_> = state
+        .history_snapshot()
+        .iter()
+        .map(|entry| entry.captured_by())
+        .zip(history(&state).into_iter().map(|(value, _)| value))
+        .rev()
+        .collect();
+    assert_eq!(
+        recorded,
+        expected.map(|(origin, value)| (Some(origin), value.to_string()))
+    );
+    assert_eq!(
+        stats.snapshot().skipped,
+        BTreeMap::from([(SkipReason::OwnRestore, 1)])
+    );
+}
+
+#[test]
+fn records_text_once_when_clipboard_and_primary_both_hold_it() {
+    let store = ScratchStore::new("capture-pipeline-selections");
+    // Off, so text recorded from both selections would show up twice.
+    let state = store.load_with_dedup(10, DEDUP_OFF).expect("load");
+    let append = AppendMode::at(store.dir().join("append.json"));
+    let stats = CaptureStats::default();
+    let shared_last_seen = SharedLastSeen::default();
+    let expected_writes = ExpectedWrites::default();
+    let poller = |selection: Selection| {
+        let backend = FakeClipboardBackend::new().watching(selection);
+        let handle = backend.handle();
+        let poller = ClipboardPoller::new(backend, Vec::new())
+            .with_capture_stats(stats.clone())
+            .with_expected_writes(expected_writes.clone())
+            .with_shared_last_seen(shared_last_seen.clone());
+        (handle, poller)
+    };
+    let (clipboard, mut clipboard_poller) = poller(Selection::Clipboard);
+    let (primary, mut primary_poller) = poller(Selection::Primary);
+
+    let mut see = |selection: Selection, value: &str| {
+        let (handle, poller) = match selection {
+            Selection::Clipboard => (&clipboard, &mut clipboard_poller),
+            Selection::Primary => (&primary, &mut primary_poller),
+        };
+        handle.copy(text(value), Some(window(Some("firefox"), "Docs")));
+        capture_once(poller, &state, || Ok(false), &append).unwrap();
+    };
+    // Selected, then copied with Ctrl+C.
+    see(Selection::Primary, "https://example.com");
+    see(Selection::Clipboard, "https://example.com");
+    // The selections now differ, and the clipboard still holds the link.
+    see(Selection::Primary, "a word");
+    see(Selection::Clipboard, "https://example.com");
+    see(Selection::Clipboard, "notes");
+    expected_writes.expect(&text("a word"));
+    see(Selection::Clipboard, "a word");
+    see(Selection::Primary, "a word");
+
+    let recorded: Vec<_> = state
+        .history_snapshot()
+        .iter()
+        .map(|entry| entry.selection())
+        .zip(history(&state).into_iter().map(|(value, _)| value))
+        .rev()
+        .collect();
+    assert_eq!(
+        recorded,
+        [
+            (Some(Selection::Primary), "https://example.com".to_string()),
+            (Some(Selection::Primary), "a word".to_string()),
+            (Some(Selection::Clipboard), "notes".to_string()),
+        ]
+    );
+    assert_eq!(
+        stats.snapshot().skipped,
+        BTreeMap::from([(SkipReason::OwnRestore, 1)])
+    );
+}